@@ -0,0 +1,74 @@
+//! Compares [Diff::new] against a byte by byte scan on a large rom pair.
+//!
+//! Run with `cargo bench --bench diff`. Build with `--features parallel` to include comparing on
+//! all cores.
+
+use std::hint::black_box;
+use std::ops::Range;
+use std::time::{Duration, Instant};
+
+use rom_patcher::create::Diff;
+
+const ROM_LEN: usize = 64 << 20;
+const RUNS: u32 = 5;
+
+/// the comparison [Diff::new] replaced, one byte at a time.
+fn byte_by_byte(source: &[u8], target: &[u8]) -> Vec<Range<u64>> {
+    let mut regions = Vec::new();
+    let mut start = None;
+    for (i, byte) in target.iter().enumerate() {
+        match (source.get(i) != Some(byte), start) {
+            (true, None) => start = Some(i as u64),
+            (false, Some(x)) => {
+                regions.push(x..i as u64);
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(x) = start {
+        regions.push(x..target.len() as u64);
+    }
+    regions
+}
+
+fn fastest(mut run: impl FnMut() -> usize) -> (Duration, usize) {
+    let mut best = Duration::MAX;
+    let mut regions = 0;
+    for _ in 0..RUNS {
+        let started = Instant::now();
+        regions = black_box(run());
+        best = best.min(started.elapsed());
+    }
+    (best, regions)
+}
+
+fn report(name: &str, (time, regions): (Duration, usize)) {
+    let throughput = ROM_LEN as f64 / time.as_secs_f64() / (1 << 20) as f64;
+    println!("{:<14} {:>10.2?} {:>10.0} MiB/s {:>8} regions", name, time, throughput, regions);
+}
+
+fn main() {
+    let mut state = 0x9E3779B97F4A7C15u64;
+    let source: Vec<u8> = (0..ROM_LEN)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        })
+        .collect();
+    // a typical hack: a few thousand scattered edits and some larger rewritten blocks
+    let mut target = source.clone();
+    for i in 0..4096 {
+        let at = (i * 16381) % ROM_LEN;
+        target[at] = !target[at];
+    }
+    for i in 0..16 {
+        let at = i * (ROM_LEN / 16);
+        target[at..at + 0x8000].iter_mut().for_each(|x| *x = x.wrapping_add(1));
+    }
+
+    report("byte by byte", fastest(|| byte_by_byte(&source, &target).len()));
+    report("Diff::new", fastest(|| Diff::new(&source, &target).regions.len()));
+}
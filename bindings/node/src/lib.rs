@@ -0,0 +1,59 @@
+//! Node bindings of rom-patcher for Electron based patcher frontends.
+//!
+//! Everything works on buffers, leaving file handling to the JavaScript side.
+
+use napi::bindgen_prelude::Buffer;
+use napi::{Error, Result};
+use napi_derive::napi;
+use rom_patcher::create::{create_all, CreateFormat};
+use rom_patcher::registry::{detect_format as detect, formats as registered_formats, read_any_from_slice};
+
+fn to_napi(e: rom_patcher::Error) -> Error {
+    Error::from_reason(e.to_string())
+}
+
+/// returns the names of all known patch formats.
+#[napi]
+pub fn formats() -> Vec<String> {
+    registered_formats().iter().map(|x| x.name.to_string()).collect()
+}
+
+/// returns the name of the format of `patch`, or `null` if it is of no known format.
+#[napi]
+pub fn detect_format(patch: Buffer) -> Option<String> {
+    detect(&patch).map(|x| x.name.to_string())
+}
+
+/// applies `patch` to `source` and returns the patched file.
+#[napi]
+pub fn apply(source: Buffer, patch: Buffer) -> Result<Buffer> {
+    let patch = read_any_from_slice(&patch).map_err(to_napi)?;
+    let patched = patch.apply_to_vec(&source).map_err(to_napi)?;
+    Ok(patched.into())
+}
+
+/// creates a patch of `format`, `"ips"`, `"pmsr"`, `"ups"` or `"bps"`, turning `source` into `target`.
+#[napi]
+pub fn create(source: Buffer, target: Buffer, format: String) -> Result<Buffer> {
+    let format = match format.as_str() {
+        "ips" => CreateFormat::Ips,
+        "pmsr" => CreateFormat::Pmsr,
+        "ups" => CreateFormat::Ups,
+        "bps" => CreateFormat::Bps,
+        _ => return Err(Error::from_reason(format!("Unsupported format \"{}\".", format))),
+    };
+    let mut created = create_all(&source, &target, &[format]);
+    if let Some((_, e)) = created.skipped.pop() {
+        return Err(to_napi(e));
+    }
+    let mut data = Vec::new();
+    created.patches[0].write_to(&mut data).map_err(|e| Error::from_reason(e.to_string()))?;
+    Ok(data.into())
+}
+
+/// checks the native module against embedded golden vectors, returning a description of each
+/// failed check.
+#[napi]
+pub fn self_test() -> Vec<String> {
+    rom_patcher::self_test().failures.iter().map(|x| format!("{}: {}", x.check, x.reason)).collect()
+}
@@ -0,0 +1,394 @@
+//! Options controlling how patches are applied.
+
+use crate::console::Console;
+use crate::Error;
+use crate::detect::{confidence, Confidence};
+use crate::ErrorKind::{AlreadyApplied, ChecksumMismatch, ValidationError, WrongSource};
+use crate::hash::{Sha1, to_hex};
+use crate::fingerprint::fingerprint;
+use crate::header::split_header;
+use crate::messages::Message;
+use crate::patch::Patch;
+
+/// What to do about hunks writing data that the patch then truncates away.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum TruncateCheck {
+    /// apply the patch as is.
+    Ignore,
+    /// apply the patch and report the affected hunks.
+    Warn,
+    /// refuse to apply the patch.
+    #[default]
+    Deny,
+}
+
+/// How a copier header in front of the target is treated, see [crate::header].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum HeaderHandling {
+    /// the patch addresses the target as is, header included.
+    #[default]
+    AsIs,
+    /// the patch addresses the rom body, so it is shifted past the header if the target has one.
+    SkipCopierHeader,
+}
+
+/// What to do about hunks writing different data to the same offset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum DuplicateWrites {
+    /// the data of the later hunk is kept, like hunks are usually applied.
+    #[default]
+    LastWins,
+    /// the data of the earlier hunk is kept.
+    FirstWins,
+    /// refuse to apply the patch.
+    Error,
+}
+
+/// Options for applying a patch.
+///
+/// # Examples
+///
+/// ```
+/// use rom_patcher::apply::{ApplyOptions, TruncateCheck};
+/// let options = ApplyOptions::new().with_truncate_check(TruncateCheck::Warn);
+/// assert_eq!(options.truncate_check(), TruncateCheck::Warn);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize), serde(default))]
+pub struct ApplyOptions {
+    truncate_check: TruncateCheck,
+    header: HeaderHandling,
+    gap_fill: u8,
+    force: bool,
+    max_offset: Option<u64>,
+    suggested_output_name: bool,
+    fix_checksum: bool,
+    keep_going: bool,
+    duplicate_writes: DuplicateWrites,
+}
+
+impl ApplyOptions {
+    /// constructs the default options.
+    pub fn new() -> ApplyOptions {
+        ApplyOptions::default()
+    }
+
+    /// constructs the options that suit patches for roms of `console`.
+    ///
+    /// - SNES patches are applied past a copier header, since they are made for headerless roms,
+    ///   and the internal checksum is fixed.
+    /// - GBA patches fill gaps with `0xFF` like erased flash and fix the header complement.
+    /// - Genesis patches fix the checksum.
+    /// - NES, Nintendo 64 and unknown consoles keep the defaults.
+    ///
+    /// Hunks lost to truncation are refused for every console, see [TruncateCheck::Deny].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rom_patcher::apply::{ApplyOptions, HeaderHandling};
+    /// use rom_patcher::console::Console;
+    /// let options = ApplyOptions::preset(Console::SnesLoRom);
+    /// assert_eq!(options.header(), HeaderHandling::SkipCopierHeader);
+    /// assert!(options.fix_checksum());
+    /// ```
+    pub fn preset(console: Console) -> ApplyOptions {
+        let options = ApplyOptions::new().with_truncate_check(TruncateCheck::Deny);
+        match console {
+            Console::SnesLoRom | Console::SnesHiRom => options
+                .with_header(HeaderHandling::SkipCopierHeader)
+                .with_fix_checksum(true),
+            Console::GameBoyAdvance => options.with_gap_fill(0xFF).with_fix_checksum(true),
+            Console::Genesis => options.with_fix_checksum(true),
+            Console::Flat | Console::Nintendo64 | Console::Nes => options,
+        }
+    }
+
+    /// returns what is done about hunks that are truncated away.
+    pub fn truncate_check(&self) -> TruncateCheck {
+        self.truncate_check
+    }
+
+    /// returns how a copier header on the target is treated.
+    pub fn header(&self) -> HeaderHandling {
+        self.header
+    }
+
+    /// returns the byte written to the gap between the end of the target and a hunk past it.
+    pub fn gap_fill(&self) -> u8 {
+        self.gap_fill
+    }
+
+    /// returns whether patches are applied even to roms that already have them applied.
+    pub fn force(&self) -> bool {
+        self.force
+    }
+
+    /// returns the largest offset patches may write to, if they are limited.
+    pub fn max_offset(&self) -> Option<u64> {
+        self.max_offset
+    }
+
+    /// returns whether outputs are named as the patch suggests, see [Self::with_suggested_output_name].
+    pub fn suggested_output_name(&self) -> bool {
+        self.suggested_output_name
+    }
+
+    /// returns whether the checksum of patched roms is fixed, see [Self::with_fix_checksum].
+    pub fn fix_checksum(&self) -> bool {
+        self.fix_checksum
+    }
+
+    /// returns whether applying continues past failed hunks, see [Self::with_keep_going].
+    pub fn keep_going(&self) -> bool {
+        self.keep_going
+    }
+
+    /// returns what is done about hunks writing different data to the same offset.
+    pub fn duplicate_writes(&self) -> DuplicateWrites {
+        self.duplicate_writes
+    }
+
+    /// modifies the options with the given `header` handling.
+    pub fn with_header(mut self, header: HeaderHandling) -> ApplyOptions {
+        self.header = header;
+        return self;
+    }
+
+    /// modifies the options with the given `truncate_check`.
+    pub fn with_truncate_check(mut self, truncate_check: TruncateCheck) -> ApplyOptions {
+        self.truncate_check = truncate_check;
+        return self;
+    }
+
+    /// modifies the options to fill gaps the patch leaves when extending the target with
+    /// `gap_fill`, `0x00` by default.
+    ///
+    /// Flash carts and emulators often expect unused rom space to be `0xFF`, like erased flash.
+    pub fn with_gap_fill(mut self, gap_fill: u8) -> ApplyOptions {
+        self.gap_fill = gap_fill;
+        return self;
+    }
+
+    /// modifies the options to apply patches even to roms that [already have them
+    /// applied](crate::detect::already_applied) if `force` is `true`.
+    ///
+    /// By default [Patch::apply_to_vec_with_options] refuses that with an [AlreadyApplied] error,
+    /// since applying a patch twice can corrupt the rom.
+    pub fn with_force(mut self, force: bool) -> ApplyOptions {
+        self.force = force;
+        return self;
+    }
+
+    /// modifies the options to refuse patches writing past `max_offset` with a [ValidationError],
+    /// `None` to only limit them to what their format can address.
+    ///
+    /// This sandboxes how far into a target a patch may write, e.g. to keep patches from growing a
+    /// rom or touching a save area behind it. Offsets are those of the patch, so with
+    /// [HeaderHandling::SkipCopierHeader] they are counted from the end of the header.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rom_patcher::apply::ApplyOptions;
+    /// use rom_patcher::ips::{IPSHunk, IPSPatch, IPSRLEHunkData};
+    /// use rom_patcher::patch::Patch;
+    ///
+    /// let patch = IPSPatch::new()
+    ///     .with_hunk(IPSHunk::RLE(IPSRLEHunkData { offset: 6, run_length: 4, payload: 0xFF }));
+    /// let options = ApplyOptions::new().with_max_offset(Some(7));
+    /// assert!(patch.apply_to_vec_with_options(&[0; 8], &options).is_err());
+    /// ```
+    pub fn with_max_offset(mut self, max_offset: Option<u64>) -> ApplyOptions {
+        self.max_offset = max_offset;
+        return self;
+    }
+
+    /// modifies the options to name outputs written to a path as the patch suggests if
+    /// `suggested_output_name` is `true`, see [Patch::suggested_output_name].
+    ///
+    /// The output is then written next to the requested output path under the
+    /// [sanitized](crate::naming::sanitize_file_name) suggested name, and only where the patch
+    /// suggests none at the requested path itself. Applying to memory ignores this option.
+    pub fn with_suggested_output_name(mut self, suggested_output_name: bool) -> ApplyOptions {
+        self.suggested_output_name = suggested_output_name;
+        return self;
+    }
+
+    /// modifies the options to recompute the checksum the header of the patched rom records if
+    /// `fix_checksum` is `true`, for the console [detected](Console::detect) from the patched rom.
+    ///
+    /// See [Console::fix_checksum] for the consoles this covers. Roms of other consoles are left
+    /// as they are.
+    pub fn with_fix_checksum(mut self, fix_checksum: bool) -> ApplyOptions {
+        self.fix_checksum = fix_checksum;
+        return self;
+    }
+
+    /// modifies the options to keep applying the remaining hunks if `keep_going` is `true` and a
+    /// hunk fails, recording every failed hunk as a [DiagnosticCode::FailedHunk] error instead of
+    /// returning the first failure.
+    ///
+    /// Hunks writing past [Self::max_offset] and hunks [denied](TruncateCheck::Deny) for being
+    /// truncated are skipped and reported as failed. This shows forensic users everything that fails
+    /// at once. Formats that are applied as a whole, like UPS and BPS, have no hunks to skip
+    /// and fail as before.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rom_patcher::apply::ApplyOptions;
+    /// use rom_patcher::ips::{IPSHunk, IPSPatch, IPSRLEHunkData};
+    /// use rom_patcher::patch::Patch;
+    ///
+    /// let patch = IPSPatch::new()
+    ///     .with_hunk(IPSHunk::RLE(IPSRLEHunkData { offset: 6, run_length: 4, payload: 0xFF }))
+    ///     .with_hunk(IPSHunk::RLE(IPSRLEHunkData { offset: 0, run_length: 2, payload: 0xEE }));
+    /// let options = ApplyOptions::new().with_max_offset(Some(7)).with_keep_going(true);
+    /// let (patched, diagnostics) = patch.apply_to_vec_with_options(&[0; 8], &options).unwrap();
+    /// assert_eq!(patched, [0xEE, 0xEE, 0, 0, 0, 0, 0, 0]);
+    /// assert_eq!(diagnostics[0].hunk, Some(0));
+    /// ```
+    ///
+    /// [DiagnosticCode::FailedHunk]: crate::diagnostics::DiagnosticCode::FailedHunk
+    pub fn with_keep_going(mut self, keep_going: bool) -> ApplyOptions {
+        self.keep_going = keep_going;
+        return self;
+    }
+
+    /// modifies the options with the given `duplicate_writes` policy.
+    ///
+    /// Every offset hunks write different data to is reported as a
+    /// [DiagnosticCode::DuplicateWrite], whichever hunk wins. [DuplicateWrites::Error] refuses the
+    /// patch with a [ValidationError] instead, or with [Self::keep_going] skips the later hunk and
+    /// reports it as failed. Only IPS patches are checked, other formats apply as before.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rom_patcher::apply::{ApplyOptions, DuplicateWrites};
+    /// use rom_patcher::ips::{IPSHunk, IPSPatch, IPSRLEHunkData};
+    /// use rom_patcher::patch::Patch;
+    ///
+    /// let patch = IPSPatch::new()
+    ///     .with_hunk(IPSHunk::RLE(IPSRLEHunkData { offset: 0, run_length: 2, payload: 0xAA }))
+    ///     .with_hunk(IPSHunk::RLE(IPSRLEHunkData { offset: 1, run_length: 2, payload: 0xBB }));
+    /// let options = ApplyOptions::new().with_duplicate_writes(DuplicateWrites::FirstWins);
+    /// let (patched, diagnostics) = patch.apply_to_vec_with_options(&[0; 4], &options).unwrap();
+    /// assert_eq!(patched, [0xAA, 0xAA, 0xBB, 0]);
+    /// assert_eq!(diagnostics[0].hunk, Some(1));
+    /// ```
+    ///
+    /// [DiagnosticCode::DuplicateWrite]: crate::diagnostics::DiagnosticCode::DuplicateWrite
+    pub fn with_duplicate_writes(mut self, duplicate_writes: DuplicateWrites) -> ApplyOptions {
+        self.duplicate_writes = duplicate_writes;
+        return self;
+    }
+}
+
+/// returns a [ValidationError] if the write of `length` bytes at `offset` goes past the
+/// [maximum offset](ApplyOptions::max_offset) of `options`.
+pub(crate) fn check_max_offset(offset: u64, length: u64, options: &ApplyOptions) -> Result<(), Error> {
+    match options.max_offset() {
+        Some(max_offset) if length > 0 && offset.saturating_add(length - 1) > max_offset => {
+            let offset = offset.max(max_offset.saturating_add(1));
+            Err(Error::new(ValidationError).with_message(Message::BeyondMaxOffset { offset, max_offset }))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// returns a [ValidationError] if `patched`, the result of applying a patch to `source`, differs
+/// from it past the [maximum offset](ApplyOptions::max_offset) of `options`.
+///
+/// This is how formats that don't expose where they write are sandboxed.
+pub(crate) fn check_max_offset_written(source: &[u8], patched: &[u8], options: &ApplyOptions) -> Result<(), Error> {
+    let Some(max_offset) = options.max_offset() else {
+        return Ok(());
+    };
+    let Ok(start) = usize::try_from(max_offset.saturating_add(1)) else {
+        return Ok(());
+    };
+    let source_tail = source.get(start..).unwrap_or_default();
+    let patched_tail = patched.get(start..).unwrap_or_default();
+    match patched_tail.iter().zip(source_tail.iter()).position(|(x, y)| x != y) {
+        Some(i) => check_max_offset((start + i) as u64, 1, options),
+        // everything past the source is written by the patch, truncating isn't writing
+        None if patched_tail.len() > source_tail.len() => check_max_offset((start + source_tail.len()) as u64, 1, options),
+        None => Ok(()),
+    }
+}
+
+/// fixes the checksum of the patched `rom` if `options` ask for it.
+pub(crate) fn fix_checksum(rom: &mut [u8], options: &ApplyOptions) {
+    if options.fix_checksum() {
+        if let Some(console) = Console::detect(rom) {
+            console.fix_checksum(rom);
+        }
+    }
+}
+
+/// returns an [AlreadyApplied] error if `source` likely has `patch` applied already, unless
+/// `options` force applying it.
+pub(crate) fn check_not_applied<P: Patch + ?Sized>(patch: &P, source: &[u8], options: &ApplyOptions) -> Result<(), Error> {
+    if options.force() {
+        return Ok(());
+    }
+    let rom = match options.header() {
+        HeaderHandling::AsIs => source,
+        HeaderHandling::SkipCopierHeader => split_header(source).1,
+    };
+    let confidence = confidence(patch, rom);
+    if confidence >= Confidence::Likely {
+        return Err(Error::new(AlreadyApplied)
+            .with_message(Message::AlreadyApplied { format: patch.format().to_string(), confidence }));
+    }
+    Ok(())
+}
+
+/// applies `patch` to `rom`, checking the SHA-1 of `rom` before and of the output after applying.
+///
+/// This gives formats without checksums of their own, like IPS, the safety of formats with
+/// checksums. Returns a [WrongSource] or [ChecksumMismatch] error naming the expected and actual
+/// hash if the source or output doesn't match.
+///
+/// # Examples
+///
+/// ```
+/// use rom_patcher::apply::apply_verified;
+/// use rom_patcher::hash::Sha1;
+/// use rom_patcher::ips::{IPSHunk, IPSPatch, IPSRLEHunkData};
+///
+/// let patch = IPSPatch::new()
+///     .with_hunk(IPSHunk::RLE(IPSRLEHunkData { offset: 0, run_length: 2, payload: 0xFF }));
+/// let rom = [0; 4];
+/// let expected = Sha1::hash(&[0xFF, 0xFF, 0, 0]);
+/// let output = apply_verified(&patch, &rom, &Sha1::hash(&rom), &expected).unwrap();
+/// assert_eq!(output, [0xFF, 0xFF, 0, 0]);
+/// assert!(apply_verified(&patch, &[1; 4], &Sha1::hash(&rom), &expected).is_err());
+/// ```
+pub fn apply_verified(patch: &dyn Patch, rom: &[u8], expected_source_sha1: &[u8; 20], expected_output_sha1: &[u8; 20]) -> Result<Vec<u8>, Error> {
+    verify_sha1(rom, expected_source_sha1).map_err(|(actual, expected)| {
+        let (header, body) = split_header(rom);
+        let copier_header = !header.is_empty() && Sha1::hash(body) == *expected_source_sha1;
+        Error::new(WrongSource).with_message(Message::SourceChecksumMismatch {
+            actual, expected, copier_header, fingerprint: fingerprint(rom),
+        })
+    })?;
+    let output = patch.apply_to_vec(rom)?;
+    verify_sha1(&output, expected_output_sha1)
+        .map_err(|(actual, expected)| Error::new(ChecksumMismatch).with_message(Message::OutputChecksumMismatch { actual, expected }))?;
+    Ok(output)
+}
+
+/// returns the actual and expected SHA-1 as hex if `data` doesn't have the `expected` one.
+fn verify_sha1(data: &[u8], expected: &[u8; 20]) -> Result<(), (String, String)> {
+    let actual = Sha1::hash(data);
+    if actual != *expected {
+        return Err((to_hex(&actual), to_hex(expected)));
+    }
+    Ok(())
+}
@@ -0,0 +1,328 @@
+//! Auditing a directory of roms before batch patching them.
+//!
+//! [scan] reports every rom it finds with its console, copier header, checksums and what its
+//! headers say it is. An [Audit] additionally matches the roms against a [Dat] of known good dumps
+//! and a [Catalog] of patches, answering which dump each rom is and which patches apply to it.
+
+use std::fs;
+use std::path::Path;
+
+use crate::batch::find_files;
+use crate::catalog::Catalog;
+use crate::console::Console;
+use crate::Error;
+use crate::ErrorKind::{ParsingError, PatchingError};
+use crate::fingerprint::{fingerprint, Fingerprint};
+use crate::hash::{Crc32, Sha1, to_hex};
+use crate::header::split_header;
+use crate::registry::detect_format;
+use crate::report::Paths;
+
+/// extensions of files that are audited even if their console isn't recognized.
+pub const ROM_EXTENSIONS: &[&str] = &["sfc", "smc", "nes", "gba", "gb", "gbc", "n64", "z64", "v64", "md", "gen", "smd", "bin", "rom"];
+
+/// A known good dump listed in a [Dat].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize))]
+pub struct DatEntry {
+    /// the name of the game.
+    pub name: String,
+    /// length of the dump.
+    pub size: u64,
+    /// CRC-32 of the dump.
+    pub crc32: u32,
+    /// SHA-1 of the dump as lowercase hex, if the DAT lists it.
+    pub sha1: Option<String>,
+}
+
+/// A list of known good dumps, e.g. a No-Intro DAT.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Dat {
+    entries: Vec<DatEntry>,
+}
+
+impl Dat {
+    /// constructs an empty DAT.
+    pub fn new() -> Dat {
+        Dat::default()
+    }
+
+    /// modifies the DAT to list `entry`.
+    pub fn with_entry(mut self, entry: DatEntry) -> Dat {
+        self.entries.push(entry);
+        return self;
+    }
+
+    /// returns the listed dumps.
+    pub fn entries(&self) -> &[DatEntry] {
+        &self.entries
+    }
+
+    /// parses a DAT in the Logiqx XML format, e.g. as published by No-Intro.
+    ///
+    /// Only the `rom` elements and the names of the `game` or `machine` elements containing them
+    /// are read, everything else is ignored.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rom_patcher::audit::Dat;
+    /// let dat = Dat::parse(r#"<datafile><game name="Hack &amp; Slash"><rom name="a.sfc" size="4" crc="2144DF1C"/></game></datafile>"#).unwrap();
+    /// assert_eq!(dat.entries()[0].name, "Hack & Slash");
+    /// assert_eq!(dat.find(&[0; 4]).unwrap().crc32, 0x2144DF1C);
+    /// ```
+    pub fn parse(xml: &str) -> Result<Dat, Error> {
+        let invalid = |what: &str| Error::new(ParsingError).with_description(format!("Invalid DAT, {}.", what));
+        let mut result = Dat::new();
+        let mut game = None;
+        let mut rest = xml;
+        while let Some(start) = rest.find('<') {
+            let end = rest[start..].find('>').ok_or_else(|| invalid("unterminated element"))? + start;
+            let tag = &rest[start + 1..end];
+            rest = &rest[end + 1..];
+            let name = tag.split(|x: char| x.is_whitespace() || x == '/').next().unwrap_or("");
+            match name {
+                "game" | "machine" => game = attribute(tag, "name"),
+                "rom" => {
+                    let size = attribute(tag, "size").and_then(|x| x.parse().ok()).ok_or_else(|| invalid("rom without size"))?;
+                    let crc32 = attribute(tag, "crc").and_then(|x| u32::from_str_radix(&x, 16).ok()).ok_or_else(|| invalid("rom without crc"))?;
+                    let name = game.clone().or_else(|| attribute(tag, "name")).unwrap_or_default();
+                    let sha1 = attribute(tag, "sha1").map(|x| x.to_lowercase());
+                    result.entries.push(DatEntry { name, size, crc32, sha1 });
+                }
+                _ => {}
+            }
+        }
+        return Ok(result);
+    }
+
+    /// returns the dump `rom` is, matched by SHA-1 where listed and by length and CRC-32 otherwise.
+    pub fn find(&self, rom: &[u8]) -> Option<&DatEntry> {
+        let crc32 = Crc32::checksum(rom);
+        let mut sha1 = None;
+        self.entries.iter()
+            .filter(|x| x.size == rom.len() as u64 && x.crc32 == crc32)
+            .find(|x| match &x.sha1 {
+                Some(expected) => *expected == *sha1.get_or_insert_with(|| to_hex(&Sha1::hash(rom))),
+                None => true,
+            })
+    }
+}
+
+/// returns the unescaped value of the attribute `name` of the element `tag`.
+fn attribute(tag: &str, name: &str) -> Option<String> {
+    let mut rest = tag;
+    while let Some(position) = rest.find(name) {
+        let preceded = rest[..position].ends_with(char::is_whitespace);
+        rest = &rest[position + name.len()..];
+        let value = rest.trim_start().strip_prefix('=').map(str::trim_start);
+        if let (true, Some(value)) = (preceded, value) {
+            let quote = value.chars().next().filter(|x| *x == '"' || *x == '\'')?;
+            let end = value[1..].find(quote)? + 1;
+            return Some(unescape(&value[1..end]));
+        }
+    }
+    return None;
+}
+
+fn unescape(value: &str) -> String {
+    value.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// A rom found by an audit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize))]
+pub struct AuditEntry {
+    /// path of the rom relative to the audited directory, see [Paths].
+    pub path: String,
+    /// length of the rom.
+    pub size: u64,
+    /// the console the rom was detected to be for.
+    pub console: Option<Console>,
+    /// whether the rom has a copier header, see [crate::header].
+    pub copier_header: bool,
+    /// CRC-32 of the rom.
+    pub crc32: u32,
+    /// SHA-1 of the rom as lowercase hex.
+    pub sha1: String,
+    /// what the headers of the rom say it is.
+    pub fingerprint: Option<Fingerprint>,
+    /// the name of the dump of the [Dat] the rom, or its body without copier header, is.
+    pub dat_name: Option<String>,
+    /// paths of the patches of the [Catalog] known to apply to the rom or its body.
+    pub patches: Vec<String>,
+}
+
+/// The roms found by an audit.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize))]
+pub struct AuditReport {
+    /// audited roms, ordered by path.
+    pub entries: Vec<AuditEntry>,
+}
+
+impl AuditReport {
+    /// serializes the report as JSON.
+    #[cfg(feature = "json")]
+    pub fn to_json(&self) -> String {
+        // the report only contains plain data
+        serde_json::to_string_pretty(self).unwrap()
+    }
+}
+
+/// An audit of roms against known dumps and patches.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::path::Path;
+/// use rom_patcher::audit::{Audit, Dat};
+/// use rom_patcher::catalog::Catalog;
+///
+/// let dat = Dat::parse(&std::fs::read_to_string("snes.dat").unwrap()).unwrap();
+/// let catalog = Catalog::index(Path::new("patches")).unwrap();
+/// let report = Audit::new().with_dat(&dat).with_catalog(&catalog).scan(Path::new("roms")).unwrap();
+/// for entry in report.entries {
+///     println!("{}: {:?}, {} patches", entry.path, entry.dat_name, entry.patches.len());
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Audit<'a> {
+    dat: Option<&'a Dat>,
+    catalog: Option<&'a Catalog>,
+}
+
+impl<'a> Audit<'a> {
+    /// constructs an audit matching against nothing.
+    pub fn new() -> Audit<'a> {
+        Audit::default()
+    }
+
+    /// modifies the audit to match roms against the dumps of `dat`.
+    pub fn with_dat(mut self, dat: &'a Dat) -> Audit<'a> {
+        self.dat = Some(dat);
+        return self;
+    }
+
+    /// modifies the audit to match roms against the patches of `catalog`.
+    pub fn with_catalog(mut self, catalog: &'a Catalog) -> Audit<'a> {
+        self.catalog = Some(catalog);
+        return self;
+    }
+
+    /// audits every rom in `dir` and its subdirectories.
+    ///
+    /// Files are roms if their console is recognized or they have one of the [ROM_EXTENSIONS].
+    /// Patches are never roms.
+    pub fn scan(&self, dir: &Path) -> Result<AuditReport, Error> {
+        let paths = Paths::new(dir);
+        let mut found = Vec::new();
+        find_files(dir, &mut found, &|_| true)?;
+        found.sort_by_key(|x| paths.relative(x));
+
+        let mut result = AuditReport::default();
+        for path in found {
+            let data = fs::read(&path).map_err(|e| Error::new(PatchingError)
+                .with_description(format!("Unable to read rom {}.", path.display()))
+                .with_source(Box::new(e)))?;
+            let console = Console::detect(&data);
+            let rom_extension = path.extension().and_then(|x| x.to_str())
+                .is_some_and(|x| ROM_EXTENSIONS.iter().any(|e| e.eq_ignore_ascii_case(x)));
+            if detect_format(&data).is_some() || (console.is_none() && !rom_extension) {
+                continue;
+            }
+            result.entries.push(self.entry(paths.relative(&path), &data, console));
+        }
+        return Ok(result);
+    }
+
+    fn entry(&self, path: String, rom: &[u8], console: Option<Console>) -> AuditEntry {
+        let (header, body) = split_header(rom);
+        let candidates = if header.is_empty() { vec![rom] } else { vec![rom, body] };
+        let dat_name = self.dat
+            .and_then(|dat| candidates.iter().find_map(|x| dat.find(x)))
+            .map(|x| x.name.clone());
+        let mut patches: Vec<String> = Vec::new();
+        if let Some(catalog) = self.catalog {
+            for candidate in &candidates {
+                patches.extend(catalog.applicable_to(candidate).iter().map(|x| x.path.clone()));
+            }
+        }
+        patches.sort();
+        patches.dedup();
+        AuditEntry {
+            path,
+            size: rom.len() as u64,
+            console,
+            copier_header: !header.is_empty(),
+            crc32: Crc32::checksum(rom),
+            sha1: to_hex(&Sha1::hash(rom)),
+            fingerprint: fingerprint(rom),
+            dat_name,
+            patches,
+        }
+    }
+}
+
+/// audits every rom in `dir` without matching them against known dumps or patches, see
+/// [Audit::scan].
+pub fn scan(dir: &Path) -> Result<AuditReport, Error> {
+    Audit::new().scan(dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use spectral::prelude::*;
+
+    use crate::pmsr::{PMSRPatch, PMSRRecord};
+
+    use super::*;
+
+    #[test]
+    fn dat_attributes_are_parsed() {
+        let dat = Dat::parse(r#"<?xml version="1.0"?>
+            <datafile>
+                <header><name>Test</name></header>
+                <game name="Game (USA)">
+                    <description>Game (USA)</description>
+                    <rom name="Game (USA).sfc" size="16" crc="0000ABCD" sha1="ABCDEF"/>
+                </game>
+            </datafile>"#).unwrap();
+        assert_that!(dat.entries().to_vec()).is_equal_to(vec![DatEntry {
+            name: "Game (USA)".to_string(), size: 16, crc32: 0xABCD, sha1: Some("abcdef".to_string()),
+        }]);
+        assert_that!(Dat::parse(r#"<rom name="x"/>"#)).is_err();
+    }
+
+    #[test]
+    fn roms_are_matched_against_dat_and_catalog() {
+        let dir = env::temp_dir().join(format!("rom-patcher-audit-scan-{}", std::process::id()));
+        fs::create_dir_all(dir.join("patches")).unwrap();
+        let rom: Vec<u8> = (0..1024).map(|x| x as u8).collect();
+        let mut headered = vec![0; 512];
+        headered.extend_from_slice(&rom);
+        fs::write(dir.join("clean.sfc"), &rom).unwrap();
+        fs::write(dir.join("headered.smc"), &headered).unwrap();
+        fs::write(dir.join("notes.txt"), b"not a rom").unwrap();
+        let mut pmsr = Vec::new();
+        PMSRPatch::new().with_record(PMSRRecord { offset: 0, data: Box::new([1]) }).write(&mut pmsr).unwrap();
+        fs::write(dir.join("patches/star.mod"), pmsr).unwrap();
+
+        let dat = Dat::new().with_entry(DatEntry { name: "Game".to_string(), size: 1024, crc32: Crc32::checksum(&rom), sha1: None });
+        let report = Audit::new().with_dat(&dat).scan(&dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let paths: Vec<&str> = report.entries.iter().map(|x| x.path.as_str()).collect();
+        assert_that!(paths).is_equal_to(vec!["clean.sfc", "headered.smc"]);
+        assert_that!(report.entries[0].copier_header).is_false();
+        assert_that!(report.entries[1].copier_header).is_true();
+        assert_that!(report.entries[1].dat_name).is_equal_to(Some("Game".to_string()));
+        assert_that!(report.entries[1].patches).is_empty();
+    }
+}
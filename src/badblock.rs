@@ -0,0 +1,329 @@
+//! Applying patches to flash dumps with bad blocks.
+//!
+//! Cartridges written back from imperfect dumps can have regions that don't hold data, like worn
+//! out flash blocks. A [BadBlockMap] lists them, and [apply_to_dump] keeps the patch from writing
+//! there according to the [policy](BadBlockPolicy) of the map.
+
+use std::fmt::{Display, Formatter};
+use std::ops::Range;
+
+use crate::apply::ApplyOptions;
+use crate::create::Diff;
+use crate::diagnostics::{Diagnostic, DiagnosticCode};
+use crate::Error;
+use crate::ErrorKind::{ParsingError, ValidationError};
+use crate::io_util::U64Extensions;
+use crate::patch::Patch;
+
+/// What is done about patches writing to bad blocks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum BadBlockPolicy {
+    /// refuse to apply the patch.
+    #[default]
+    Deny,
+    /// apply the patch, but leave bad blocks as they are and report the writes that were dropped.
+    Skip,
+    /// treat the dump like flash that skips bad blocks: the patch addresses the dump without its
+    /// bad blocks, so everything at or past a bad block moves past it.
+    Relocate,
+}
+
+/// The regions of a dump that don't hold data, together with what to do about patches writing
+/// there.
+///
+/// # Examples
+///
+/// ```
+/// use rom_patcher::badblock::{BadBlockMap, BadBlockPolicy};
+/// let map = BadBlockMap::new().with_range(0x4000..0x8000).with_policy(BadBlockPolicy::Skip);
+/// assert!(map.is_bad(0x4000));
+/// assert!(!map.is_bad(0x8000));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BadBlockMap {
+    /// sorted, non-overlapping and non-empty.
+    ranges: Vec<Range<u64>>,
+    policy: BadBlockPolicy,
+}
+
+impl BadBlockMap {
+    /// constructs an empty map denying writes to bad blocks.
+    pub fn new() -> BadBlockMap {
+        BadBlockMap::default()
+    }
+
+    /// parses a map listing a bad region per line as hexadecimal offsets `start-end`, with `end`
+    /// exclusive and an optional `0x` prefix. Empty lines and lines starting with `#` are ignored.
+    ///
+    /// Returns a [ParsingError] naming the first line that isn't a valid region.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rom_patcher::badblock::BadBlockMap;
+    /// let map = BadBlockMap::parse("# bad blocks\n0x20000-0x40000\n80000-A0000\n").unwrap();
+    /// assert_eq!(map.ranges(), &[0x20000..0x40000, 0x80000..0xA0000]);
+    /// ```
+    pub fn parse(text: &str) -> Result<BadBlockMap, Error> {
+        let mut result = BadBlockMap::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let offset = |x: &str| {
+                let x = x.trim();
+                u64::from_str_radix(x.strip_prefix("0x").or_else(|| x.strip_prefix("0X")).unwrap_or(x), 16).ok()
+            };
+            let range = line.split_once('-')
+                .and_then(|(start, end)| Some(offset(start)?..offset(end)?))
+                .filter(|x| x.start < x.end)
+                .ok_or_else(|| Error::new(ParsingError)
+                    .with_description(format!("Invalid bad block \"{}\" on line {}.", line, i + 1)))?;
+            result.add_range(range);
+        }
+        return Ok(result);
+    }
+
+    /// returns the bad regions, sorted and merged where they overlap or touch.
+    pub fn ranges(&self) -> &[Range<u64>] {
+        &self.ranges
+    }
+
+    /// returns what is done about patches writing to bad blocks.
+    pub fn policy(&self) -> BadBlockPolicy {
+        self.policy
+    }
+
+    /// marks `range` as bad. Empty ranges are ignored.
+    pub fn add_range(&mut self, range: Range<u64>) {
+        if range.is_empty() {
+            return;
+        }
+        self.ranges.push(range);
+        self.ranges.sort_by_key(|x| x.start);
+        let mut merged: Vec<Range<u64>> = Vec::with_capacity(self.ranges.len());
+        for range in self.ranges.drain(..) {
+            match merged.last_mut() {
+                Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+                _ => merged.push(range),
+            }
+        }
+        self.ranges = merged;
+    }
+
+    /// returns a new map with `range` marked as bad.
+    pub fn with_range(mut self, range: Range<u64>) -> Self {
+        self.add_range(range);
+        return self;
+    }
+
+    /// returns a new map handling writes to bad blocks according to `policy`.
+    pub fn with_policy(mut self, policy: BadBlockPolicy) -> Self {
+        self.policy = policy;
+        return self;
+    }
+
+    /// returns `true` if `offset` lies in a bad block.
+    pub fn is_bad(&self, offset: u64) -> bool {
+        self.ranges.iter().any(|x| x.contains(&offset))
+    }
+
+    /// returns the parts of `range` that lie in bad blocks.
+    fn bad_parts(&self, range: &Range<u64>) -> Vec<Range<u64>> {
+        self.ranges.iter()
+            .map(|x| x.start.max(range.start)..x.end.min(range.end))
+            .filter(|x| !x.is_empty())
+            .collect()
+    }
+
+    /// returns the offset of the dump the byte at `offset` of the dump without its bad blocks is
+    /// stored at.
+    pub fn physical_offset(&self, offset: u64) -> u64 {
+        let mut result = offset;
+        for range in &self.ranges {
+            if range.start > result {
+                break;
+            }
+            result += range.end - range.start;
+        }
+        return result;
+    }
+
+    /// returns `dump` without its bad blocks.
+    fn logical(&self, dump: &[u8]) -> Vec<u8> {
+        let mut result = Vec::with_capacity(dump.len());
+        let mut start = 0;
+        for range in &self.ranges {
+            let end = range.start.to_index().min(dump.len());
+            result.extend_from_slice(&dump[start.min(end)..end]);
+            start = range.end.to_index();
+        }
+        result.extend_from_slice(dump.get(start..).unwrap_or_default());
+        return result;
+    }
+
+    /// returns `logical` spread over the good blocks of `dump`, keeping the bad blocks of `dump`.
+    /// Bad blocks past the end of `dump` read as erased flash.
+    fn physical(&self, dump: &[u8], logical: &[u8]) -> Vec<u8> {
+        let mut result = Vec::with_capacity(dump.len().max(logical.len()));
+        let mut rest = logical;
+        for range in &self.ranges {
+            let (start, end) = (range.start.to_index(), range.end.to_index());
+            let take = start.saturating_sub(result.len()).min(rest.len());
+            result.extend_from_slice(&rest[..take]);
+            rest = &rest[take..];
+            if result.len() < start || (rest.is_empty() && start >= dump.len()) {
+                break;
+            }
+            let end = if rest.is_empty() { end.min(dump.len()) } else { end };
+            result.extend((start..end).map(|i| dump.get(i).copied().unwrap_or(0xFF)));
+        }
+        result.extend_from_slice(rest);
+        return result;
+    }
+}
+
+/// Formats the map as [parsed](BadBlockMap::parse), a region per line.
+impl Display for BadBlockMap {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for range in &self.ranges {
+            writeln!(f, "{:#X}-{:#X}", range.start, range.end)?;
+        }
+        Ok(())
+    }
+}
+
+/// applies `patch` to `dump` according to `options`, keeping it from writing to the bad blocks of
+/// `map`, and returns the patched dump together with the diagnostics found.
+///
+/// Depending on the [policy](BadBlockMap::policy), writes to bad blocks are refused with a
+/// [ValidationError], dropped, or the patch is applied to the dump without its bad blocks and the
+/// result spread over the good ones. Dropped and relocated writes are reported as
+/// [DiagnosticCode::BadBlock] warnings.
+///
+/// # Examples
+///
+/// ```
+/// use rom_patcher::apply::ApplyOptions;
+/// use rom_patcher::badblock::{apply_to_dump, BadBlockMap, BadBlockPolicy};
+/// use rom_patcher::ips::{IPSHunk, IPSPatch, IPSRegularHunkData};
+/// let patch = IPSPatch::new().with_hunk(IPSHunk::Regular(IPSRegularHunkData {
+///     offset: 2,
+///     payload: Box::new([0xAA]),
+/// }));
+/// let map = BadBlockMap::new().with_range(1..2).with_policy(BadBlockPolicy::Relocate);
+/// let (patched, diagnostics) = apply_to_dump(&patch, &[0, 1, 2, 3], &map, &ApplyOptions::default()).unwrap();
+/// assert_eq!(patched, [0, 1, 2, 0xAA]);
+/// assert_eq!(diagnostics.len(), 2);
+/// ```
+pub fn apply_to_dump(patch: &dyn Patch, dump: &[u8], map: &BadBlockMap, options: &ApplyOptions) -> Result<(Vec<u8>, Vec<Diagnostic>), Error> {
+    if map.policy() == BadBlockPolicy::Relocate {
+        let logical = map.logical(dump);
+        let (patched, mut diagnostics) = patch.apply_to_vec_with_options(&logical, options)?;
+        for region in Diff::new(&logical, &patched).regions {
+            let start = map.physical_offset(region.start);
+            if start != region.start {
+                diagnostics.push(Diagnostic::warning(None, format!(
+                    "Relocated {} bytes at {:#X} past bad blocks to {:#X}.", region.end - region.start, region.start, start,
+                )).with_code(DiagnosticCode::BadBlock));
+            }
+        }
+        return Ok((map.physical(dump, &patched), diagnostics));
+    }
+
+    let (mut patched, mut diagnostics) = patch.apply_to_vec_with_options(dump, options)?;
+    for region in Diff::new(dump, &patched).regions {
+        for bad in map.bad_parts(&region) {
+            if map.policy() == BadBlockPolicy::Deny {
+                return Err(Error::new(ValidationError)
+                    .with_description(format!("The patch writes to the bad block at {:#X}.", bad.start)));
+            }
+            for i in bad.start.to_index()..bad.end.to_index().min(patched.len()) {
+                patched[i] = dump.get(i).copied().unwrap_or(0xFF);
+            }
+            diagnostics.push(Diagnostic::warning(None, format!(
+                "Skipped writing {} bytes at {:#X} in a bad block.", bad.end - bad.start, bad.start,
+            )).with_code(DiagnosticCode::BadBlock));
+        }
+    }
+    Ok((patched, diagnostics))
+}
+
+#[cfg(test)]
+mod tests {
+    use spectral::prelude::*;
+
+    use crate::ups::UPSPatch;
+
+    use super::*;
+
+    fn dump() -> Vec<u8> {
+        (0..32).collect()
+    }
+
+    fn map(policy: BadBlockPolicy) -> BadBlockMap {
+        BadBlockMap::new().with_range(8..12).with_range(20..24).with_policy(policy)
+    }
+
+    #[test]
+    fn ranges_are_merged() {
+        let map = BadBlockMap::new().with_range(10..20).with_range(0..4).with_range(15..30).with_range(4..6).with_range(7..7);
+        assert_that!(map.ranges().to_vec()).is_equal_to(vec![0..6, 10..30]);
+        assert_that!(BadBlockMap::parse(&map.to_string()).unwrap().ranges().to_vec()).is_equal_to(vec![0..6, 10..30]);
+        assert_that!(BadBlockMap::parse("10-8").unwrap_err().to_string()).contains("line 1");
+    }
+
+    #[test]
+    fn writes_to_bad_blocks_are_denied() {
+        let mut target = dump();
+        target[9] = 0xFF;
+        let patch = UPSPatch::create(&dump(), &target);
+        let err = apply_to_dump(&patch, &dump(), &map(BadBlockPolicy::Deny), &ApplyOptions::default()).unwrap_err();
+        assert_that!(err.to_string()).contains("bad block at 0x9");
+        target[9] = 9;
+        target[0] = 0xFF;
+        let patch = UPSPatch::create(&dump(), &target);
+        assert_that!(apply_to_dump(&patch, &dump(), &map(BadBlockPolicy::Deny), &ApplyOptions::default()).unwrap().0).is_equal_to(target);
+    }
+
+    #[test]
+    fn writes_to_bad_blocks_are_skipped() {
+        let mut target = dump();
+        target[6..14].fill(0xEE);
+        let patch = UPSPatch::create(&dump(), &target);
+        let (patched, diagnostics) = apply_to_dump(&patch, &dump(), &map(BadBlockPolicy::Skip), &ApplyOptions::default()).unwrap();
+        assert_that!(patched[6..14].to_vec()).is_equal_to(vec![0xEE, 0xEE, 8, 9, 10, 11, 0xEE, 0xEE]);
+        let codes: Vec<Option<DiagnosticCode>> = diagnostics.iter().map(|x| x.code).collect();
+        assert_that!(codes).contains(Some(DiagnosticCode::BadBlock));
+    }
+
+    #[test]
+    fn writes_are_relocated_past_bad_blocks() {
+        let map = map(BadBlockPolicy::Relocate);
+        let logical = map.logical(&dump());
+        assert_that!(logical.len()).is_equal_to(24);
+        assert_that!(map.physical(&dump(), &logical)).is_equal_to(dump());
+
+        let mut target = logical.clone();
+        target[10] = 0xEE;
+        target.push(0xDD);
+        let patch = UPSPatch::create(&logical, &target);
+        let (patched, diagnostics) = apply_to_dump(&patch, &dump(), &map, &ApplyOptions::default()).unwrap();
+        assert_that!(patched[14]).is_equal_to(0xEE);
+        assert_that!(patched[8..12].to_vec()).is_equal_to(vec![8, 9, 10, 11]);
+        assert_that!(patched.len()).is_equal_to(33);
+        assert_that!(patched[32]).is_equal_to(0xDD);
+        assert_that!(diagnostics.iter().filter(|x| x.code == Some(DiagnosticCode::BadBlock)).count()).is_equal_to(2);
+    }
+
+    #[test]
+    fn dumps_ending_in_bad_blocks_keep_their_length() {
+        let map = BadBlockMap::new().with_range(28..40).with_policy(BadBlockPolicy::Relocate);
+        assert_that!(map.physical(&dump(), &map.logical(&dump()))).is_equal_to(dump());
+        let extended = map.physical(&dump(), &[7; 30]);
+        assert_that!(extended.len()).is_equal_to(42);
+        assert_that!(extended[28..40].to_vec()).is_equal_to(vec![28, 29, 30, 31, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]);
+    }
+}
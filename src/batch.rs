@@ -0,0 +1,385 @@
+//! Applying a whole directory of patches to one rom.
+
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::apply::ApplyOptions;
+use crate::console::Console;
+use crate::Error;
+use crate::ErrorKind::PatchingError;
+use crate::facade::{JobReport, PatchJob};
+use crate::hash::{Crc32, HashAlgorithm, to_hex};
+use crate::naming::{NamingContext, NamingTemplate};
+use crate::outcome::OperationResult;
+use crate::registry::format_for_extension;
+use crate::report::{Paths, resolve};
+
+/// The outcome of applying a single patch of a batch.
+#[derive(Debug)]
+pub struct BatchEntry {
+    /// path of the patch relative to the patch directory, see [Paths].
+    pub patch: String,
+    /// path of the output relative to the output directory, see [Paths].
+    pub output: String,
+    /// the report of the applied patch, or why it couldn't be applied.
+    pub result: Result<JobReport, Error>,
+    /// `true` if the patch was skipped since a previous run already applied it, see
+    /// [apply_dir_resumable]. The report then only holds the output length and CRC-32.
+    pub resumed: bool,
+}
+
+/// The outcome of [apply_dir], ordered by patch path.
+#[derive(Debug)]
+pub struct BatchReport {
+    /// one entry per patch found.
+    pub entries: Vec<BatchEntry>,
+}
+
+impl BatchReport {
+    /// returns `true` if every patch was applied.
+    pub fn is_success(&self) -> bool {
+        self.entries.iter().all(|x| x.result.is_ok())
+    }
+
+    /// returns the result of the first patch that failed, or how the batch succeeded.
+    pub fn result(&self) -> OperationResult {
+        let results: Vec<OperationResult> = self.entries.iter().map(|x| OperationResult::from(&x.result)).collect();
+        if let Some(failed) = results.iter().find(|x| !x.is_success()) {
+            return *failed;
+        }
+        if results.contains(&OperationResult::AppliedWithWarnings) {
+            return OperationResult::AppliedWithWarnings;
+        }
+        return OperationResult::Success;
+    }
+
+    /// returns a line based summary without anything specific to the machine it was created on,
+    /// meant to be checked in and diffed.
+    ///
+    /// Each line holds the patch path, the output path and either the CRC-32 of the output or the
+    /// error, separated by tabs.
+    pub fn manifest(&self) -> String {
+        let mut result = String::new();
+        for entry in &self.entries {
+            let outcome = match &entry.result {
+                Ok(report) => format!("{:08x}", report.output_crc32),
+                Err(e) => e.to_string(),
+            };
+            result.push_str(&format!("{}\t{}\t{}\n", entry.patch, entry.output, outcome));
+        }
+        return result;
+    }
+
+    /// returns a manifest like [BatchReport::manifest], holding hashes of `algorithm` instead of
+    /// CRC-32s, e.g. for deduplicating outputs with a stronger hash.
+    ///
+    /// The outputs are read back from `output_dir`, the directory the batch wrote them to.
+    pub fn manifest_with(&self, output_dir: &Path, algorithm: &HashAlgorithm) -> Result<String, Error> {
+        let mut result = String::new();
+        for entry in &self.entries {
+            let outcome = match &entry.result {
+                Ok(_) => {
+                    let path = resolve(output_dir, &entry.output);
+                    let data = fs::read(&path).map_err(|e| Error::new(PatchingError)
+                        .with_description(format!("Unable to read output {}.", path.display()))
+                        .with_source(Box::new(e)))?;
+                    to_hex(&algorithm.hash(&data))
+                }
+                Err(e) => e.to_string(),
+            };
+            result.push_str(&format!("{}\t{}\t{}\n", entry.patch, entry.output, outcome));
+        }
+        return Ok(result);
+    }
+}
+
+/// The arguments of [apply_dir], for keeping batch runs in config files, see [crate::config].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize))]
+pub struct BatchConfig {
+    /// path of the rom every patch is applied to.
+    pub source: PathBuf,
+    /// directory searched for patches.
+    pub patch_dir: PathBuf,
+    /// directory the outputs are written to.
+    pub output_dir: PathBuf,
+    /// options every patch is applied with.
+    #[cfg_attr(feature = "json", serde(default))]
+    pub options: ApplyOptions,
+    /// path of the file progress is recorded in, see [apply_dir_resumable].
+    #[cfg_attr(feature = "json", serde(default))]
+    pub state_file: Option<PathBuf>,
+    /// `true` to skip the patches already applied according to [BatchConfig::state_file].
+    #[cfg_attr(feature = "json", serde(default))]
+    pub resume: bool,
+    /// how outputs are named, [None] to name them after their patch, see [apply_dir_named].
+    #[cfg_attr(feature = "json", serde(default))]
+    pub naming: Option<NamingTemplate>,
+}
+
+impl BatchConfig {
+    /// runs the batch with [apply_dir], [apply_dir_resumable] if it has a state file, and naming
+    /// the outputs like [apply_dir_named] if it has a naming template.
+    pub fn run(&self) -> Result<BatchReport, Error> {
+        let state = match &self.state_file {
+            Some(state) => Some(State::open(state, self.resume)?),
+            None => None,
+        };
+        run(&self.source, &self.patch_dir, &self.output_dir, &self.options, self.naming.as_ref(), state)
+    }
+}
+
+/// applies every patch in `patch_dir` and its subdirectories to `source`, writing each result to
+/// the same relative path in `output_dir`, with the extension of `source`.
+///
+/// Files are considered patches if their extension belongs to a [registered
+/// format](crate::registry). A patch that fails to apply doesn't stop the batch.
+pub fn apply_dir(source: &Path, patch_dir: &Path, output_dir: &Path, options: &ApplyOptions) -> Result<BatchReport, Error> {
+    run(source, patch_dir, output_dir, options, None, None)
+}
+
+/// applies every patch like [apply_dir], naming each output with `naming` in the directory of its
+/// patch instead.
+///
+/// The console of `source` is [detected](Console::detect) once for the `{ext}` of the template,
+/// and patches are titled by their file name. Patches whose name renders to nothing usable keep
+/// the name [apply_dir] gives them.
+pub fn apply_dir_named(source: &Path, patch_dir: &Path, output_dir: &Path, options: &ApplyOptions, naming: &NamingTemplate) -> Result<BatchReport, Error> {
+    run(source, patch_dir, output_dir, options, Some(naming), None)
+}
+
+/// applies every patch like [apply_dir], recording each applied patch and the CRC-32 of its output
+/// in the file at `state` as soon as it is written, so an interrupted run can be picked up later.
+///
+/// With `resume` set, patches recorded by a previous run are skipped if their output still has the
+/// recorded CRC-32, and reported as [resumed](BatchEntry::resumed). Otherwise the state file is
+/// started over.
+pub fn apply_dir_resumable(source: &Path, patch_dir: &Path, output_dir: &Path, options: &ApplyOptions, state: &Path, resume: bool) -> Result<BatchReport, Error> {
+    return run(source, patch_dir, output_dir, options, None, Some(State::open(state, resume)?));
+}
+
+/// a state file of [apply_dir_resumable] and the patches it recorded with their output and CRC-32.
+struct State<'a> {
+    path: &'a Path,
+    file: File,
+    completed: HashMap<String, (String, u32)>,
+}
+
+impl State<'_> {
+    /// opens the state file at `path`, reading the patches it recorded if `resume` is set and
+    /// starting it over otherwise.
+    fn open(path: &Path, resume: bool) -> Result<State<'_>, Error> {
+        let completed = if resume && path.exists() { read_state(path)? } else { HashMap::new() };
+        let file = OpenOptions::new().create(true).append(resume).write(true).truncate(!resume).open(path)
+            .map_err(|e| state_error(path, e))?;
+        return Ok(State { path, file, completed });
+    }
+
+    /// returns the report of `patch` if a previous run applied it to `output` and the output is
+    /// still there.
+    fn resumed(&self, patch: &str, output: &str, patch_path: &Path, output_path: &Path) -> Option<JobReport> {
+        let (previous, crc32) = self.completed.get(patch)?;
+        if previous != output {
+            return None;
+        }
+        let data = fs::read(output_path).ok()?;
+        if Crc32::checksum(&data) != *crc32 {
+            return None;
+        }
+        let format = patch_path.extension().and_then(|x| x.to_str()).and_then(format_for_extension)?;
+        return Some(JobReport { format: format.name, diagnostics: Vec::new(), output_len: data.len() as u64, output_crc32: *crc32, source: None, output: output_path.to_path_buf() });
+    }
+
+    /// appends `entry` to the state file if its patch was applied in this run.
+    fn record(&mut self, entry: &BatchEntry) -> Result<(), Error> {
+        match &entry.result {
+            Ok(report) if !entry.resumed => writeln!(self.file, "{}\t{}\t{:08x}", entry.patch, entry.output, report.output_crc32)
+                .and_then(|_| self.file.flush())
+                .map_err(|e| state_error(self.path, e)),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn run(source: &Path, patch_dir: &Path, output_dir: &Path, options: &ApplyOptions, naming: Option<&NamingTemplate>, mut state: Option<State>) -> Result<BatchReport, Error> {
+    let paths = Paths::new(patch_dir);
+    let mut found = Vec::new();
+    find_patches(patch_dir, &mut found)?;
+    let extension = source.extension().map(|x| x.to_string_lossy().into_owned());
+    // an unreadable source fails every patch on its own
+    let console = naming.and_then(|_| fs::read(source).ok()).and_then(|x| Console::detect(&x));
+
+    let mut entries = Vec::new();
+    for patch in paths.sorted(&found) {
+        let mut output = match (patch.rsplit_once('.'), &extension) {
+            (Some((stem, _)), Some(extension)) => format!("{}.{}", stem, extension),
+            (Some((stem, _)), None) => stem.to_string(),
+            (None, _) => patch.clone(),
+        };
+        let context = NamingContext { rom: source, patch: Path::new(&patch), title: None, console };
+        if let Some(name) = naming.and_then(|x| x.render(&context)) {
+            output = match patch.rsplit_once('/') {
+                Some((dir, _)) => format!("{}/{}", dir, name),
+                None => name,
+            };
+        }
+        let output_path = resolve(output_dir, &output);
+        let patch_path = resolve(patch_dir, &patch);
+        let previous = state.as_ref().and_then(|x| x.resumed(&patch, &output, &patch_path, &output_path));
+        let entry = match previous {
+            Some(report) => BatchEntry { patch, output, result: Ok(report), resumed: true },
+            None => {
+                let result = create_parent(&output_path).and_then(|_| PatchJob::new(source, patch_path, output_path)
+                    .with_options(options.clone())
+                    .run());
+                BatchEntry { patch, output, result, resumed: false }
+            }
+        };
+        if let Some(state) = &mut state {
+            state.record(&entry)?;
+        }
+        entries.push(entry);
+    }
+    return Ok(BatchReport { entries });
+}
+
+/// collects every file in `dir` and its subdirectories with the extension of a registered format.
+pub(crate) fn find_patches(dir: &Path, result: &mut Vec<PathBuf>) -> Result<(), Error> {
+    find_files(dir, result, &|path| path.extension().and_then(|x| x.to_str()).is_some_and(|x| format_for_extension(x).is_some()))
+}
+
+/// collects every file in `dir` and its subdirectories `filter` returns `true` for.
+pub(crate) fn find_files(dir: &Path, result: &mut Vec<PathBuf>, filter: &dyn Fn(&Path) -> bool) -> Result<(), Error> {
+    let read_error = |e: std::io::Error| Error::new(PatchingError)
+        .with_description(format!("Unable to read directory {}.", dir.display()))
+        .with_source(Box::new(e));
+    for entry in fs::read_dir(dir).map_err(read_error)? {
+        let path = entry.map_err(read_error)?.path();
+        if path.is_dir() {
+            find_files(&path, result, filter)?;
+        } else if filter(&path) {
+            result.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// reads the patches recorded in the state file at `path` with the output and CRC-32 they produced.
+fn read_state(path: &Path) -> Result<HashMap<String, (String, u32)>, Error> {
+    let text = fs::read_to_string(path).map_err(|e| state_error(path, e))?;
+    let mut result = HashMap::new();
+    // a line cut off by an interruption is ignored, its patch is just applied again
+    for line in text.lines() {
+        let fields: Vec<&str> = line.split('\t').collect();
+        if let [patch, output, crc32] = fields[..] {
+            if let Ok(crc32) = u32::from_str_radix(crc32, 16) {
+                result.insert(patch.to_string(), (output.to_string(), crc32));
+            }
+        }
+    }
+    return Ok(result);
+}
+
+fn state_error(path: &Path, e: std::io::Error) -> Error {
+    Error::new(PatchingError)
+        .with_description(format!("Unable to access state file {}.", path.display()))
+        .with_source(Box::new(e))
+}
+
+fn create_parent(path: &Path) -> Result<(), Error> {
+    match path.parent() {
+        Some(parent) => fs::create_dir_all(parent).map_err(|e| Error::new(PatchingError)
+            .with_description(format!("Unable to create directory {}.", parent.display()))
+            .with_source(Box::new(e))),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use spectral::prelude::*;
+
+    use crate::testkit::Fixture;
+
+    use super::*;
+
+    #[test]
+    fn applies_every_patch_in_stable_order() {
+        let dir = env::temp_dir().join(format!("rom-patcher-batch-{}", std::process::id()));
+        let patches = dir.join("patches");
+        fs::create_dir_all(patches.join("sub")).unwrap();
+        let fixture = Fixture::generate(11);
+        fs::write(dir.join("game.sfc"), &fixture.source).unwrap();
+        let mut patch = Vec::new();
+        fixture.ips.write(&mut patch).unwrap();
+        fs::write(patches.join("sub/b.ips"), &patch).unwrap();
+        fs::write(patches.join("a.ips"), &patch).unwrap();
+        fs::write(patches.join("broken.ips"), b"PATCH").unwrap();
+        fs::write(patches.join("notes.txt"), b"not a patch").unwrap();
+
+        let report = apply_dir(&dir.join("game.sfc"), &patches, &dir.join("out"), &ApplyOptions::new()).unwrap();
+        let names: Vec<(&str, &str)> = report.entries.iter().map(|x| (x.patch.as_str(), x.output.as_str())).collect();
+        assert_that!(names).is_equal_to(vec![("a.ips", "a.sfc"), ("broken.ips", "broken.sfc"), ("sub/b.ips", "sub/b.sfc")]);
+        assert_that!(report.is_success()).is_false();
+        assert_that!(report.result()).is_equal_to(OperationResult::InvalidPatch);
+        assert_that!(fs::read(dir.join("out/sub/b.sfc")).unwrap()).is_equal_to(&fixture.target);
+
+        let manifest = report.manifest();
+        assert_that!(manifest.lines().next().unwrap().to_string())
+            .is_equal_to(format!("a.ips\ta.sfc\t{:08x}", crate::hash::Crc32::checksum(&fixture.target)));
+        let sha1 = crate::hash::hash_algorithm("sha1").unwrap();
+        let manifest = report.manifest_with(&dir.join("out"), &sha1).unwrap();
+        assert_that!(manifest.lines().next().unwrap().to_string())
+            .is_equal_to(format!("a.ips\ta.sfc\t{}", to_hex(&crate::hash::Sha1::hash(&fixture.target))));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn resumed_runs_skip_applied_patches() {
+        let dir = env::temp_dir().join(format!("rom-patcher-batch-resume-{}", std::process::id()));
+        let patches = dir.join("patches");
+        fs::create_dir_all(&patches).unwrap();
+        let fixture = Fixture::generate(12);
+        fs::write(dir.join("game.sfc"), &fixture.source).unwrap();
+        let mut patch = Vec::new();
+        fixture.ips.write(&mut patch).unwrap();
+        fs::write(patches.join("a.ips"), &patch).unwrap();
+        fs::write(patches.join("b.ips"), &patch).unwrap();
+        fs::write(patches.join("broken.ips"), b"PATCH").unwrap();
+        let (source, out, state) = (dir.join("game.sfc"), dir.join("out"), dir.join("state.tsv"));
+
+        let report = apply_dir_resumable(&source, &patches, &out, &ApplyOptions::new(), &state, false).unwrap();
+        assert_that!(report.entries.iter().any(|x| x.resumed)).is_false();
+        fs::write(out.join("b.sfc"), b"damaged").unwrap();
+
+        let report = apply_dir_resumable(&source, &patches, &out, &ApplyOptions::new(), &state, true).unwrap();
+        let resumed: Vec<bool> = report.entries.iter().map(|x| x.resumed).collect();
+        assert_that!(resumed).is_equal_to(vec![true, false, false]);
+        assert_that!(report.manifest()).is_equal_to(apply_dir(&source, &patches, &out, &ApplyOptions::new()).unwrap().manifest());
+        assert_that!(fs::read(out.join("b.sfc")).unwrap()).is_equal_to(&fixture.target);
+        assert_that!(fs::read_to_string(&state).unwrap().lines().count()).is_equal_to(3);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn outputs_are_named_by_the_template() {
+        let dir = env::temp_dir().join(format!("rom-patcher-batch-named-{}", std::process::id()));
+        let patches = dir.join("patches");
+        fs::create_dir_all(patches.join("sub")).unwrap();
+        let fixture = Fixture::generate(13);
+        fs::write(dir.join("game.smc"), &fixture.source).unwrap();
+        let mut patch = Vec::new();
+        fixture.ips.write(&mut patch).unwrap();
+        fs::write(patches.join("sub/hard mode.ips"), &patch).unwrap();
+
+        let naming = NamingTemplate::default();
+        let report = apply_dir_named(&dir.join("game.smc"), &patches, &dir.join("out"), &ApplyOptions::new(), &naming).unwrap();
+        assert_that!(report.entries[0].output.as_str()).is_equal_to("sub/game (hard mode).smc");
+        assert_that!(fs::read(dir.join("out/sub/game (hard mode).smc")).unwrap()).is_equal_to(&fixture.target);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,669 @@
+//! BPS patches (`.bps`), as designed by byuu for beat.
+//!
+//! BPS patches consist of the magic `BPS1`, the sizes of the source and target and the length of
+//! the manifest as variable-length integers, the manifest and the actions building the target from
+//! start to end, followed by the CRC-32 of the source, the target and the patch itself, each
+//! little-endian. An action is a variable-length integer holding its kind in the lowest two bits
+//! and its length minus one in the others:
+//!
+//! - source read copies the bytes of the source at the current position of the target,
+//! - target read copies the bytes that follow the action in the patch,
+//! - source copy copies bytes from anywhere in the source,
+//! - target copy copies bytes written to the target before, byte by byte, so runs can repeat.
+//!
+//! Both copies store their offset relative to the end of the previous copy of their kind, as a
+//! variable-length integer with the sign in its lowest bit.
+
+use std::fmt::{Display, Formatter};
+use std::io::{Cursor, Read, Result as IOResult, Seek, SeekFrom, Write};
+use std::ops::Range;
+
+use crate::create::Diff;
+use crate::Error;
+use crate::ErrorKind::{ChecksumMismatch, ParsingError, PatchingError, WrongSource};
+use crate::hash::Crc32;
+use crate::io_util::{write_varint, AssertRead, HashingWriter, ReaderExtensions, Truncate, U64Extensions};
+
+/// An action of a BPS patch, writing the next bytes of the target.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum BPSAction {
+    /// copies `length` bytes of the source at the current position of the target.
+    SourceRead {
+        /// amount of bytes to copy.
+        length: u64,
+    },
+    /// writes `data`.
+    TargetRead {
+        /// the bytes to write.
+        data: Box<[u8]>,
+    },
+    /// copies `length` bytes of the source starting at `offset`.
+    SourceCopy {
+        /// offset in the source of the first byte to copy. Actions store it relative to the end of
+        /// the previous source copy when written.
+        offset: u64,
+        /// amount of bytes to copy.
+        length: u64,
+    },
+    /// copies `length` bytes of the target starting at `offset`, which may overlap the bytes
+    /// written by the copy itself.
+    TargetCopy {
+        /// offset in the target of the first byte to copy. Actions store it relative to the end of
+        /// the previous target copy when written.
+        offset: u64,
+        /// amount of bytes to copy.
+        length: u64,
+    },
+}
+
+impl BPSAction {
+    /// returns the amount of bytes the action writes to the target.
+    pub fn length(&self) -> u64 {
+        match self {
+            BPSAction::SourceRead { length } => *length,
+            BPSAction::TargetRead { data } => data.len() as u64,
+            BPSAction::SourceCopy { length, .. } => *length,
+            BPSAction::TargetCopy { length, .. } => *length,
+        }
+    }
+
+    /// returns the kind of the action as stored in the lowest two bits of its header.
+    fn kind(&self) -> u64 {
+        match self {
+            BPSAction::SourceRead { .. } => 0,
+            BPSAction::TargetRead { .. } => 1,
+            BPSAction::SourceCopy { .. } => 2,
+            BPSAction::TargetCopy { .. } => 3,
+        }
+    }
+}
+
+/// Formats the action as a one-line summary, e.g. `source copy of 4 bytes from 0x00000010`.
+impl Display for BPSAction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BPSAction::SourceRead { length } => write!(f, "source read of {} bytes", length),
+            BPSAction::TargetRead { data } => write!(f, "target read of {} bytes", data.len()),
+            BPSAction::SourceCopy { offset, length } => write!(f, "source copy of {} bytes from {:#010X}", length, offset),
+            BPSAction::TargetCopy { offset, length } => write!(f, "target copy of {} bytes from {:#010X}", length, offset),
+        }
+    }
+}
+
+/// Represents a BPS patch.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct BPSPatch {
+    source_len: u64,
+    target_len: u64,
+    manifest: String,
+    /// actions in the order they write the target.
+    actions: Vec<BPSAction>,
+    source_crc32: u32,
+    target_crc32: u32,
+}
+
+impl BPSPatch {
+    /// Patch header for BPS patches.
+    pub const HEADER: &'static [u8] = "BPS1".as_bytes();
+
+    /// Length of the footer holding the checksums.
+    const FOOTER_LEN: usize = 12;
+
+    /// Amount of bytes target copies read back from the target at once.
+    const COPY_BUF_LEN: usize = 0x10000;
+
+    /// constructs a patch without actions or manifest turning a rom of `source_len` bytes with the
+    /// CRC-32 `source_crc32` into one of `target_len` bytes with the CRC-32 `target_crc32`.
+    pub fn new(source_len: u64, source_crc32: u32, target_len: u64, target_crc32: u32) -> BPSPatch {
+        BPSPatch { source_len, target_len, manifest: String::new(), actions: Vec::new(), source_crc32, target_crc32 }
+    }
+
+    /// creates a patch turning `source` into `target`.
+    ///
+    /// Unchanged bytes are read from the source and changed ones stored in the patch, like the
+    /// linear mode of other BPS creators. Data moved around the rom is stored again.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rom_patcher::bps::BPSPatch;
+    /// let patch = BPSPatch::create(&[0, 1, 2, 3], &[0, 9, 2]);
+    /// assert_eq!(patch.actions().len(), 3);
+    /// assert_eq!(patch.apply_to_vec(&[0, 1, 2, 3]).unwrap(), vec![0, 9, 2]);
+    /// ```
+    pub fn create(source: &[u8], target: &[u8]) -> BPSPatch {
+        Self::from_diff(&Diff::new(source, target), source, target)
+    }
+
+    /// encodes `diff` of `source` and `target` like [BPSPatch::create].
+    pub(crate) fn from_diff(diff: &Diff, source: &[u8], target: &[u8]) -> BPSPatch {
+        let mut result = BPSPatch::new(source.len() as u64, Crc32::checksum(source), target.len() as u64, Crc32::checksum(target));
+        let mut position = 0;
+        for region in diff.regions.iter().chain([&(diff.target_len..diff.target_len)]) {
+            if region.start > position {
+                result.push_unchanged(position..region.start, diff.source_len, target);
+            }
+            if !region.is_empty() {
+                result.actions.push(BPSAction::TargetRead { data: target[region.start.to_index()..region.end.to_index()].into() });
+            }
+            position = region.end;
+        }
+        return result;
+    }
+
+    /// adds actions writing the bytes of `target` in `range`, which the diff found unchanged. Those
+    /// past the end of a source of `source_len` bytes can't be read from it, so they are stored.
+    fn push_unchanged(&mut self, range: Range<u64>, source_len: u64, target: &[u8]) {
+        let split = range.end.min(source_len.max(range.start));
+        if split > range.start {
+            self.actions.push(BPSAction::SourceRead { length: split - range.start });
+        }
+        if range.end > split {
+            self.actions.push(BPSAction::TargetRead { data: target[split.to_index()..range.end.to_index()].into() });
+        }
+    }
+
+    /// returns the actions of the patch in the order they write the target.
+    pub fn actions(&self) -> &[BPSAction] {
+        &self.actions
+    }
+
+    /// adds `action` to the patch, writing the bytes after those of the previous actions.
+    pub fn add_action(&mut self, action: BPSAction) {
+        self.actions.push(action);
+    }
+
+    /// returns a new patch with a given `action`.
+    pub fn with_action(mut self, action: BPSAction) -> Self {
+        self.add_action(action);
+        return self;
+    }
+
+    /// returns the manifest of the patch, empty if it has none.
+    ///
+    /// The format doesn't prescribe its content, beat writes XML and other tools often JSON.
+    pub fn manifest(&self) -> &str {
+        &self.manifest
+    }
+
+    /// returns a new patch carrying `manifest`.
+    pub fn with_manifest(mut self, manifest: impl Into<String>) -> Self {
+        self.manifest = manifest.into();
+        return self;
+    }
+
+    /// returns the length of the rom the patch expects.
+    pub fn source_len(&self) -> u64 {
+        self.source_len
+    }
+
+    /// returns the length of the patched rom.
+    pub fn target_len(&self) -> u64 {
+        self.target_len
+    }
+
+    /// returns the CRC-32 of the rom the patch expects.
+    pub fn source_crc32(&self) -> u32 {
+        self.source_crc32
+    }
+
+    /// returns the CRC-32 of the patched rom.
+    pub fn target_crc32(&self) -> u32 {
+        self.target_crc32
+    }
+
+    /// writes `self` to `writer`, ending with the CRC-32 of everything written before it.
+    ///
+    /// Returns an error of kind [InvalidInput](std::io::ErrorKind::InvalidInput) if an action
+    /// writes no bytes, which the format can't represent.
+    pub fn write(&self, writer: &mut impl Write) -> IOResult<()> {
+        writer.write_all(&self.to_bytes()?)
+    }
+
+    /// returns `self` as written by [BPSPatch::write].
+    pub fn to_bytes(&self) -> IOResult<Vec<u8>> {
+        let mut result = Vec::new();
+        result.extend_from_slice(Self::HEADER);
+        write_varint(&mut result, self.source_len)?;
+        write_varint(&mut result, self.target_len)?;
+        write_varint(&mut result, self.manifest.len() as u64)?;
+        result.extend_from_slice(self.manifest.as_bytes());
+        let (mut source_relative, mut target_relative) = (0, 0);
+        for (i, action) in self.actions.iter().enumerate() {
+            let length = action.length();
+            if length == 0 || length - 1 > u64::MAX >> 2 {
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput,
+                    format!("BPS action {} writes {} bytes, which can't be stored.", i, length)));
+            }
+            write_varint(&mut result, (length - 1) << 2 | action.kind())?;
+            match action {
+                BPSAction::SourceRead { .. } => {}
+                BPSAction::TargetRead { data } => result.extend_from_slice(data),
+                BPSAction::SourceCopy { offset, .. } => {
+                    write_varint(&mut result, relative(*offset, source_relative, i)?)?;
+                    source_relative = offset.saturating_add(length);
+                }
+                BPSAction::TargetCopy { offset, .. } => {
+                    write_varint(&mut result, relative(*offset, target_relative, i)?)?;
+                    target_relative = offset.saturating_add(length);
+                }
+            }
+        }
+        result.extend_from_slice(&self.source_crc32.to_le_bytes());
+        result.extend_from_slice(&self.target_crc32.to_le_bytes());
+        result.extend_from_slice(&Crc32::checksum(&result).to_le_bytes());
+        Ok(result)
+    }
+
+    /// reads a [BPSPatch] from `data`, see [BPSPatch::read_from].
+    pub fn from_bytes(data: &[u8]) -> Result<BPSPatch, Error> {
+        Self::read_from(&mut &data[..])
+    }
+
+    /// Reads a [BPSPatch] from `reader`.
+    ///
+    /// Returns a [ChecksumMismatch] error if the patch doesn't have the CRC-32 it ends with. A
+    /// manifest that isn't UTF-8 is read with invalid sequences replaced.
+    pub fn read_from(reader: &mut impl Read) -> Result<BPSPatch, Error> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)
+            .map_err(|e| Error::new(ParsingError)
+                .with_description("Unable to read patch.".to_string())
+                .with_source(Box::new(e)))?;
+        let mut rest = &data[..];
+        rest.assert_read(
+            Self::HEADER,
+            "Unable to parse header.".to_string(),
+            "Invalid header.".to_string(),
+        )?;
+        if rest.len() < Self::FOOTER_LEN {
+            return Err(Error::new(ParsingError).with_description("Unable to read checksums.".to_string()));
+        }
+        let (body, footer) = rest.split_at(rest.len() - Self::FOOTER_LEN);
+        let crc = |i: usize| u32::from_le_bytes([footer[i], footer[i + 1], footer[i + 2], footer[i + 3]]);
+        let actual = Crc32::checksum(&data[..data.len() - 4]);
+        if actual != crc(8) {
+            return Err(Error::new(ChecksumMismatch)
+                .with_description(format!("CRC-32 of the patch is {:08x}, expected {:08x}.", actual, crc(8))));
+        }
+
+        let mut body = body;
+        let source_len = body.read_varint("Unable to read source size.".to_string())?;
+        let target_len = body.read_varint("Unable to read target size.".to_string())?;
+        let mut result = BPSPatch::new(source_len, crc(0), target_len, crc(4));
+        let manifest_len = body.read_varint("Unable to read manifest size.".to_string())?;
+        let manifest = take(&mut body, manifest_len, "Unable to read manifest.")?;
+        result.manifest = String::from_utf8_lossy(manifest).into_owned();
+
+        let (mut source_relative, mut target_relative) = (0, 0);
+        while !body.is_empty() {
+            let header = body.read_varint("Unable to read action.".to_string())?;
+            let length = (header >> 2) + 1;
+            let action = match header & 3 {
+                0 => BPSAction::SourceRead { length },
+                1 => BPSAction::TargetRead { data: take(&mut body, length, "Unable to read action data.")?.into() },
+                2 => {
+                    let offset = read_relative(&mut body, source_relative)?;
+                    source_relative = offset.saturating_add(length);
+                    BPSAction::SourceCopy { offset, length }
+                }
+                _ => {
+                    let offset = read_relative(&mut body, target_relative)?;
+                    target_relative = offset.saturating_add(length);
+                    BPSAction::TargetCopy { offset, length }
+                }
+            };
+            result.actions.push(action);
+        }
+        Ok(result)
+    }
+
+    /// returns `source` patched.
+    ///
+    /// Returns a [WrongSource] error if `source` isn't the rom the patch expects, a
+    /// [PatchingError] if an action reads or writes past the end of a rom, and a
+    /// [ChecksumMismatch] error if the output isn't what the patch records.
+    pub fn apply_to_vec(&self, source: &[u8]) -> Result<Vec<u8>, Error> {
+        self.check_source(source.len() as u64, Crc32::checksum(source))?;
+        let mut result = Vec::new();
+        result.try_reserve(self.target_len.saturating_usize())
+            .map_err(|_| Error::new(PatchingError)
+                .with_description(format!("The patched rom of {} bytes doesn't fit into memory.", self.target_len)))?;
+        let mut target = Cursor::new(result);
+        self.write_actions(&mut Cursor::new(source), &mut target)?;
+        Ok(target.into_inner())
+    }
+
+    /// Writes `source` patched to `target`, streaming both instead of holding them in memory.
+    ///
+    /// The CRC-32 of the output is computed while it is written, so it is checked without reading
+    /// `target` again, which is only read back for target copies. Returns the errors of
+    /// [BPSPatch::apply_to_vec], in which case `target` may be partly written.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::io::Cursor;
+    /// use rom_patcher::bps::BPSPatch;
+    /// let patch = BPSPatch::create(&[0, 1, 2, 3], &[0, 9, 2]);
+    /// let mut target = Cursor::new(Vec::new());
+    /// patch.apply_to_copy(&mut Cursor::new(vec![0, 1, 2, 3]), &mut target).unwrap();
+    /// assert_eq!(target.into_inner(), vec![0, 9, 2]);
+    /// ```
+    pub fn apply_to_copy<R, W>(&self, source: &mut R, target: &mut W) -> Result<(), Error>
+        where R: Read + Seek, W: Read + Write + Seek + Truncate {
+        let mut crc = HashingWriter::new(std::io::sink(), Crc32::new());
+        let source_len = source.seek(SeekFrom::Start(0))
+            .and_then(|_| std::io::copy(source, &mut crc))
+            .map_err(|e| Error::new(PatchingError)
+                .with_description("Unable to read source.".to_string())
+                .with_source(Box::new(e)))?;
+        self.check_source(source_len, crc.hasher().value())?;
+        self.write_actions(source, target)?;
+        target.truncate(self.target_len)
+            .map_err(|_| Error::new(PatchingError).with_description("Unable to truncate target.".to_string()))
+    }
+
+    /// returns a [WrongSource] error if a source of `len` bytes with the CRC-32 `crc` isn't the
+    /// rom the patch expects.
+    fn check_source(&self, len: u64, crc: u32) -> Result<(), Error> {
+        if len != self.source_len || crc != self.source_crc32 {
+            return Err(Error::new(WrongSource)
+                .with_description(format!("CRC-32 of the source is {:08x}, expected {:08x}.", crc, self.source_crc32)));
+        }
+        Ok(())
+    }
+
+    /// writes the output of the actions to `target` from its start, checking its CRC-32 as it is
+    /// written.
+    fn write_actions<R, W>(&self, source: &mut R, target: &mut W) -> Result<(), Error>
+        where R: Read + Seek, W: Read + Write + Seek {
+        let io_error = |e: std::io::Error| Error::new(PatchingError)
+            .with_description("Unable to apply BPS patch.".to_string())
+            .with_source(Box::new(e));
+        target.seek(SeekFrom::Start(0)).map_err(io_error)?;
+        let mut output = HashingWriter::new(target, Crc32::new());
+        let mut buf = vec![0; Self::COPY_BUF_LEN];
+        let mut position: u64 = 0;
+        for (i, action) in self.actions.iter().enumerate() {
+            if position.saturating_add(action.length()) > self.target_len {
+                return Err(Error::new(PatchingError)
+                    .with_description(format!("BPS action {} writes past the end of the target.", i)));
+            }
+            let read_error = || Error::new(PatchingError)
+                .with_description(format!("BPS action {} reads past the end of the rom.", i));
+            match action {
+                BPSAction::SourceRead { length } => {
+                    if !copy_range(source, position, *length, &mut output).map_err(io_error)? {
+                        return Err(read_error());
+                    }
+                }
+                BPSAction::TargetRead { data } => output.write_all(data).map_err(io_error)?,
+                BPSAction::SourceCopy { offset, length } => {
+                    if !copy_range(source, *offset, *length, &mut output).map_err(io_error)? {
+                        return Err(read_error());
+                    }
+                }
+                BPSAction::TargetCopy { offset, length } => {
+                    if *offset >= position {
+                        return Err(read_error());
+                    }
+                    // the copy may read the bytes it writes itself, repeating them, so it only
+                    // reads as far as the target is written
+                    let (mut from, mut end, mut remaining) = (*offset, position, *length);
+                    while remaining > 0 {
+                        let n = remaining.min(end - from).min(buf.len() as u64) as usize;
+                        let written = output.get_mut();
+                        written.seek(SeekFrom::Start(from))
+                            .and_then(|_| written.read_exact(&mut buf[..n]))
+                            .and_then(|_| written.seek(SeekFrom::Start(end)))
+                            .and_then(|_| output.write_all(&buf[..n]))
+                            .map_err(io_error)?;
+                        from += n as u64;
+                        end += n as u64;
+                        remaining -= n as u64;
+                    }
+                }
+            }
+            position += action.length();
+        }
+        if position != self.target_len {
+            return Err(Error::new(PatchingError)
+                .with_description(format!("The patch writes {} bytes, expected {}.", position, self.target_len)));
+        }
+        let actual = output.hasher().value();
+        if actual != self.target_crc32 {
+            return Err(Error::new(ChecksumMismatch)
+                .with_description(format!("CRC-32 of the output is {:08x}, expected {:08x}.", actual, self.target_crc32)));
+        }
+        Ok(())
+    }
+
+    /// Applies the patch to `target` like [BPSPatch::apply_to_vec], leaving it untouched if that
+    /// fails.
+    pub fn apply<T>(&self, target: &mut T) -> Result<(), Error> where T: Read + Write + Seek + Truncate {
+        let io_error = |e: std::io::Error| Error::new(PatchingError)
+            .with_description("Unable to apply BPS patch.".to_string())
+            .with_source(Box::new(e));
+        let mut rom = Vec::new();
+        target.seek(SeekFrom::Start(0)).and_then(|_| target.read_to_end(&mut rom)).map_err(io_error)?;
+        let patched = self.apply_to_vec(&rom)?;
+        target.seek(SeekFrom::Start(0))
+            .and_then(|_| target.write_all(&patched))
+            .and_then(|_| target.truncate(patched.len() as u64))
+            .map_err(io_error)
+    }
+}
+
+/// copies `length` bytes of `source` starting at `offset` to `output`, returning `false` if they
+/// lie past its end.
+fn copy_range<R: Read + Seek>(source: &mut R, offset: u64, length: u64, output: &mut impl Write) -> IOResult<bool> {
+    source.seek(SeekFrom::Start(offset))?;
+    let copied = std::io::copy(&mut source.take(length), output)?;
+    Ok(copied == length)
+}
+
+/// returns the next `len` bytes of `data` and advances past them.
+fn take<'a>(data: &mut &'a [u8], len: u64, err_message: &str) -> Result<&'a [u8], Error> {
+    if len > data.len() as u64 {
+        return Err(Error::new(ParsingError).with_description(err_message.to_string()));
+    }
+    let (result, rest) = data.split_at(len.to_index());
+    *data = rest;
+    Ok(result)
+}
+
+/// reads the offset of a copy stored relative to `previous`.
+fn read_relative(data: &mut &[u8], previous: u64) -> Result<u64, Error> {
+    let err_message = "Unable to read copy offset.";
+    let value = data.read_varint(err_message.to_string())?;
+    let delta = value >> 1;
+    let offset = if value & 1 == 1 { previous.checked_sub(delta) } else { previous.checked_add(delta) };
+    offset.ok_or_else(|| Error::new(ParsingError).with_description(err_message.to_string()))
+}
+
+/// returns `offset` relative to `previous` as stored by copy `action`.
+fn relative(offset: u64, previous: u64, action: usize) -> IOResult<u64> {
+    let (delta, sign) = if offset >= previous { (offset - previous, 0) } else { (previous - offset, 1) };
+    if delta > u64::MAX >> 1 {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput,
+            format!("BPS action {} copies from {}, which can't be stored.", action, offset)));
+    }
+    Ok(delta << 1 | sign)
+}
+
+/// Formats the patch as a one-line summary, e.g. `BPS patch with 3 actions turning 16 bytes into
+/// 32 bytes`.
+///
+/// The alternate form `{:#}` lists every action on a line of its own below the summary.
+impl Display for BPSPatch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let plural = if self.actions.len() == 1 { "" } else { "s" };
+        write!(f, "BPS patch with {} action{} turning {} bytes into {} bytes", self.actions.len(), plural, self.source_len, self.target_len)?;
+        if f.alternate() {
+            for (i, action) in self.actions.iter().enumerate() {
+                write!(f, "\n  {}: {}", i, action)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use spectral::prelude::*;
+
+    use crate::test_util::BuildVec;
+
+    use super::*;
+
+    const SOURCE: &[u8] = &[0, 1, 2, 3, 4, 5];
+    const TARGET: &[u8] = &[0, 9, 9, 3, 4, 5, 6];
+
+    fn patch_data() -> Vec<u8> {
+        let mut data = Vec::new()
+            .build_with_slice(BPSPatch::HEADER)
+            .build_with_slice(&[0x86, 0x87, 0x84]) // source, target and manifest size
+            .build_with_slice(b"test")
+            .build_with_slice(&[0x80]) // source read of 1 byte
+            .build_with_slice(&[0x85, 9, 9]) // target read of 2 bytes
+            .build_with_slice(&[0x88]) // source read of 3 bytes
+            .build_with_slice(&[0x81, 6]) // target read of 1 byte
+            .build_with_slice(&Crc32::checksum(SOURCE).to_le_bytes())
+            .build_with_slice(&Crc32::checksum(TARGET).to_le_bytes());
+        let crc = Crc32::checksum(&data);
+        data.extend_from_slice(&crc.to_le_bytes());
+        return data;
+    }
+
+    #[test]
+    fn create_and_write_actions() {
+        let patch = BPSPatch::create(SOURCE, TARGET).with_manifest("test");
+        assert_that!(patch.to_bytes().unwrap()).is_equal_to(patch_data());
+        assert_that!(BPSPatch::from_bytes(&patch_data()).unwrap()).is_equal_to(patch);
+    }
+
+    #[test]
+    fn apply_patches_the_source() {
+        let patch = BPSPatch::from_bytes(&patch_data()).unwrap();
+        assert_that!(patch.manifest()).is_equal_to("test");
+        assert_that!(patch.apply_to_vec(SOURCE).unwrap()).is_equal_to(TARGET.to_vec());
+        let mut target = Cursor::new(SOURCE.to_vec());
+        patch.apply(&mut target).unwrap();
+        assert_that!(target.into_inner()).is_equal_to(TARGET.to_vec());
+    }
+
+    #[test]
+    fn copies_round_trip_and_repeat() {
+        let source = [1, 2, 3, 4];
+        let target = [3, 4, 1, 1, 1, 1, 1, 2];
+        let patch = BPSPatch::new(4, Crc32::checksum(&source), 8, Crc32::checksum(&target))
+            .with_action(BPSAction::SourceCopy { offset: 2, length: 2 })
+            .with_action(BPSAction::SourceCopy { offset: 0, length: 1 })
+            .with_action(BPSAction::TargetCopy { offset: 2, length: 4 })
+            .with_action(BPSAction::SourceCopy { offset: 1, length: 1 });
+        let read = BPSPatch::from_bytes(&patch.to_bytes().unwrap()).unwrap();
+        assert_that!(read).is_equal_to(&patch);
+        assert_that!(read.apply_to_vec(&source).unwrap()).is_equal_to(target.to_vec());
+        let mut copy = Cursor::new(vec![0xEE; 12]);
+        read.apply_to_copy(&mut Cursor::new(source), &mut copy).unwrap();
+        assert_that!(copy.into_inner()).is_equal_to(target.to_vec());
+    }
+
+    /// A target that fails the test if it is read.
+    struct WriteOnly(Cursor<Vec<u8>>);
+
+    impl Read for WriteOnly {
+        fn read(&mut self, _: &mut [u8]) -> IOResult<usize> {
+            panic!("the output was read again");
+        }
+    }
+
+    impl Write for WriteOnly {
+        fn write(&mut self, buf: &[u8]) -> IOResult<usize> {
+            self.0.write(buf)
+        }
+
+        fn flush(&mut self) -> IOResult<()> {
+            self.0.flush()
+        }
+    }
+
+    impl Seek for WriteOnly {
+        fn seek(&mut self, pos: SeekFrom) -> IOResult<u64> {
+            self.0.seek(pos)
+        }
+    }
+
+    impl Truncate for WriteOnly {
+        fn truncate(&mut self, amount: u64) -> IOResult<()> {
+            self.0.truncate(amount)
+        }
+    }
+
+    #[test]
+    fn corrupt_target_checksums_are_refused_while_streaming() {
+        let mut data = patch_data();
+        let footer = data.len() - 8;
+        data[footer] ^= 1;
+        let crc = Crc32::checksum(&data[..data.len() - 4]);
+        data[footer + 4..].copy_from_slice(&crc.to_le_bytes());
+        let patch = BPSPatch::from_bytes(&data).unwrap();
+        let mut target = WriteOnly(Cursor::new(Vec::new()));
+        let err = patch.apply_to_copy(&mut Cursor::new(SOURCE), &mut target).unwrap_err();
+        assert_that!(matches!(err.kind(), ChecksumMismatch)).is_true();
+        assert_that!(target.0.into_inner()).is_equal_to(TARGET.to_vec());
+        let err = patch.apply_to_vec(SOURCE).unwrap_err();
+        assert_that!(matches!(err.kind(), ChecksumMismatch)).is_true();
+    }
+
+    #[test]
+    fn wrong_roms_are_refused() {
+        let patch = BPSPatch::create(SOURCE, TARGET);
+        let err = patch.apply_to_vec(TARGET).unwrap_err();
+        assert_that!(matches!(err.kind(), WrongSource)).is_true();
+        let mut target = Cursor::new(vec![0; 6]);
+        assert_that!(patch.apply(&mut target)).is_err();
+        assert_that!(target.into_inner()).is_equal_to(vec![0; 6]);
+    }
+
+    #[test]
+    fn corrupt_patches_are_refused() {
+        let mut data = patch_data();
+        data[7] ^= 1;
+        let err = BPSPatch::from_bytes(&data).unwrap_err();
+        assert_that!(matches!(err.kind(), ChecksumMismatch)).is_true();
+        let err = BPSPatch::from_bytes(b"BPS1\x80").unwrap_err();
+        assert_that!(err.to_string()).is_equal_to("ParsingError: Unable to read checksums.".to_string());
+    }
+
+    #[test]
+    fn actions_past_the_roms_are_refused() {
+        let crc = Crc32::checksum(&[0; 4]);
+        let copy = BPSPatch::new(4, crc, 4, 0).with_action(BPSAction::SourceCopy { offset: 2, length: 4 });
+        assert_that!(copy.apply_to_vec(&[0; 4]).unwrap_err().to_string()).contains("reads past the end");
+        let forward = BPSPatch::new(4, crc, 4, 0).with_action(BPSAction::TargetCopy { offset: 0, length: 1 });
+        assert_that!(forward.apply_to_vec(&[0; 4]).unwrap_err().to_string()).contains("reads past the end");
+        let long = BPSPatch::new(4, crc, 2, 0).with_action(BPSAction::SourceRead { length: 4 });
+        assert_that!(long.apply_to_vec(&[0; 4]).unwrap_err().to_string()).contains("writes past the end");
+        let empty = BPSPatch::new(4, crc, 4, 0).with_action(BPSAction::SourceRead { length: 0 });
+        assert_that!(empty.to_bytes()).is_err();
+    }
+
+    #[test]
+    fn shrinking_roms_are_created() {
+        let source: Vec<u8> = (0..32).collect();
+        let target = vec![0, 1, 2, 9];
+        let patch = BPSPatch::from_bytes(&BPSPatch::create(&source, &target).to_bytes().unwrap()).unwrap();
+        assert_that!(patch.apply_to_vec(&source).unwrap()).is_equal_to(target);
+    }
+
+    #[test]
+    fn display_summarizes_actions() {
+        let patch = BPSPatch::create(SOURCE, TARGET);
+        assert_that!(patch.to_string()).is_equal_to("BPS patch with 4 actions turning 6 bytes into 7 bytes".to_string());
+        assert_that!(format!("{:#}", patch)).contains("\n  1: target read of 2 bytes");
+    }
+}
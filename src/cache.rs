@@ -0,0 +1,208 @@
+//! A content-addressed cache of applied patches, letting build pipelines skip repeated work.
+//!
+//! Entries are keyed by the SHA-1 of the source and the SHA-1 of the patch, or the hashes of
+//! another [HashAlgorithm] set with [Cache::with_key_hash]. Each entry records the
+//! SHA-1 of the output, and optionally the output itself, stored under its own hash so identical
+//! outputs are stored only once.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::Error;
+use crate::ErrorKind::PatchingError;
+use crate::hash::{HashAlgorithm, Sha1, to_hex};
+use crate::registry::read_any_from_slice;
+
+/// The outcome of [Cache::get_or_apply].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheOutcome {
+    /// `true` if the result came from the cache.
+    pub hit: bool,
+    /// SHA-1 of the patched file.
+    pub output_sha1: [u8; 20],
+    /// the patched file, unless it came from a cache that doesn't store outputs.
+    pub output: Option<Vec<u8>>,
+}
+
+/// A cache of applied patches in a directory.
+///
+/// # Examples
+///
+/// ```no_run
+/// use rom_patcher::cache::Cache;
+///
+/// let cache = Cache::new("target/patch-cache").with_store_outputs(true);
+/// let source = std::fs::read("game.sfc").expect("Unable to read source.");
+/// let patch = std::fs::read("hack.ips").expect("Unable to read patch.");
+/// let outcome = cache.get_or_apply(&source, &patch).expect("Unable to apply patch.");
+/// println!("cache hit: {}", outcome.hit);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cache {
+    dir: PathBuf,
+    store_outputs: bool,
+    key_hash: Option<HashAlgorithm>,
+}
+
+impl Cache {
+    /// constructs a cache in `dir`, which is created when first written to.
+    pub fn new(dir: impl Into<PathBuf>) -> Cache {
+        Cache { dir: dir.into(), store_outputs: false, key_hash: None }
+    }
+
+    /// modifies the cache to also store the patched files if `store_outputs` is `true`.
+    pub fn with_store_outputs(mut self, store_outputs: bool) -> Cache {
+        self.store_outputs = store_outputs;
+        return self;
+    }
+
+    /// modifies the cache to key entries by hashes of `key_hash` instead of SHA-1, e.g. a faster
+    /// one from [hash_algorithm](crate::hash::hash_algorithm). Entries keyed by different hash
+    /// functions don't see each other.
+    pub fn with_key_hash(mut self, key_hash: HashAlgorithm) -> Cache {
+        self.key_hash = Some(key_hash).filter(|x| x.name != "sha1");
+        return self;
+    }
+
+    /// returns the SHA-1 of the output cached for `source` and `patch`, if any.
+    pub fn get(&self, source: &[u8], patch: &[u8]) -> Option<[u8; 20]> {
+        let entry = fs::read_to_string(self.entry_path(source, patch)).ok()?;
+        parse_sha1(entry.trim())
+    }
+
+    /// returns the result of applying `patch` to `source`, applying it only if it isn't cached yet.
+    ///
+    /// If the cache holds the output it is returned as well. A hit on a cache not storing outputs
+    /// only returns the hash, which callers can compare against an output they already have.
+    pub fn get_or_apply(&self, source: &[u8], patch: &[u8]) -> Result<CacheOutcome, Error> {
+        if let Some(output_sha1) = self.get(source, patch) {
+            let output = fs::read(self.output_path(&output_sha1)).ok()
+                .filter(|x| Sha1::hash(x) == output_sha1);
+            // a missing output of a cache storing outputs is recreated below
+            if output.is_some() || !self.store_outputs {
+                return Ok(CacheOutcome { hit: true, output_sha1, output });
+            }
+        }
+
+        let output = read_any_from_slice(patch)?.apply_to_vec(source)?;
+        let output_sha1 = Sha1::hash(&output);
+        if self.store_outputs {
+            self.write(&self.output_path(&output_sha1), &output)?;
+        }
+        self.write(&self.entry_path(source, patch), to_hex(&output_sha1).as_bytes())?;
+        Ok(CacheOutcome { hit: false, output_sha1, output: Some(output) })
+    }
+
+    fn entry_path(&self, source: &[u8], patch: &[u8]) -> PathBuf {
+        // entries keyed by SHA-1 keep the names of caches written before keys were configurable
+        match &self.key_hash {
+            Some(key_hash) => self.dir.join(format!("{}-{}-{}.entry",
+                key_hash.name, to_hex(&key_hash.hash(source)), to_hex(&key_hash.hash(patch)))),
+            None => self.dir.join(format!("{}-{}.entry", to_hex(&Sha1::hash(source)), to_hex(&Sha1::hash(patch)))),
+        }
+    }
+
+    fn output_path(&self, sha1: &[u8; 20]) -> PathBuf {
+        self.dir.join(format!("{}.bin", to_hex(sha1)))
+    }
+
+    /// writes `data` to `path` through a temporary file, so readers never see a partial file.
+    fn write(&self, path: &Path, data: &[u8]) -> Result<(), Error> {
+        let temporary = path.with_extension(format!("tmp{}", std::process::id()));
+        fs::create_dir_all(&self.dir)
+            .and_then(|_| fs::write(&temporary, data))
+            .and_then(|_| fs::rename(&temporary, path))
+            .map_err(|e| Error::new(PatchingError)
+                .with_description(format!("Unable to write cache file {}.", path.display()))
+                .with_source(Box::new(e)))
+    }
+}
+
+/// returns the result of applying `patch` to `source` using a [Cache] in `dir` that doesn't store
+/// outputs, see [Cache::get_or_apply].
+pub fn get_or_apply(dir: impl Into<PathBuf>, source: &[u8], patch: &[u8]) -> Result<CacheOutcome, Error> {
+    Cache::new(dir).get_or_apply(source, patch)
+}
+
+fn parse_sha1(hex: &str) -> Option<[u8; 20]> {
+    if hex.len() != 40 {
+        return None;
+    }
+    let mut result = [0; 20];
+    for (i, byte) in result.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(hex.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+    return Some(result);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use spectral::prelude::*;
+
+    use crate::hash::hash_algorithm;
+    use crate::testkit::Fixture;
+
+    use super::*;
+
+    fn setup(name: &str) -> (PathBuf, Fixture, Vec<u8>) {
+        let dir = env::temp_dir().join(format!("rom-patcher-cache-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let fixture = Fixture::generate(9);
+        let mut patch = Vec::new();
+        fixture.ips.write(&mut patch).unwrap();
+        return (dir, fixture, patch);
+    }
+
+    #[test]
+    fn second_application_is_a_hit() {
+        let (dir, fixture, patch) = setup("hit");
+        let cache = Cache::new(&dir).with_store_outputs(true);
+        let first = cache.get_or_apply(&fixture.source, &patch).unwrap();
+        let second = cache.get_or_apply(&fixture.source, &patch).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_that!(first.hit).is_false();
+        assert_that!(second.hit).is_true();
+        assert_that!(second.output).is_equal_to(Some(fixture.target.clone()));
+        assert_that!(second.output_sha1).is_equal_to(Sha1::hash(&fixture.target));
+    }
+
+    #[test]
+    fn hit_without_stored_output_returns_hash_only() {
+        let (dir, fixture, patch) = setup("hash");
+        let cache = Cache::new(&dir);
+        cache.get_or_apply(&fixture.source, &patch).unwrap();
+        let outcome = cache.get_or_apply(&fixture.source, &patch).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_that!(outcome.hit).is_true();
+        assert_that!(outcome.output).is_none();
+        assert_that!(outcome.output_sha1).is_equal_to(Sha1::hash(&fixture.target));
+    }
+
+    #[test]
+    fn entries_are_keyed_by_the_configured_hash() {
+        let (dir, fixture, patch) = setup("key");
+        let crc32 = Cache::new(&dir).with_key_hash(hash_algorithm("crc32").unwrap());
+        crc32.get_or_apply(&fixture.source, &patch).unwrap();
+        let hit = crc32.get(&fixture.source, &patch);
+        let other = Cache::new(&dir).get(&fixture.source, &patch);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_that!(hit).is_equal_to(Some(Sha1::hash(&fixture.target)));
+        assert_that!(other).is_none();
+    }
+
+    #[test]
+    fn different_sources_miss() {
+        let (dir, fixture, patch) = setup("miss");
+        let cache = Cache::new(&dir);
+        cache.get_or_apply(&fixture.source, &patch).unwrap();
+        let mut other = fixture.source.clone();
+        other[0] ^= 0xFF;
+        assert_that!(cache.get(&other, &patch)).is_none();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,89 @@
+//! Capturing the parts of a rom a patch is going to change, e.g. to undo, verify or preview it.
+
+use crate::hunk::coverage;
+use crate::io_util::U64Extensions;
+use crate::ips::IPSPatch;
+
+/// returns the bytes of `rom` that `patch` overwrites, as ordered, non-overlapping ranges.
+///
+/// Overlapping and adjacent hunks share a single range. Bytes a hunk writes past the end of `rom`
+/// have no original and are left out, as are the bytes cut off by [IPSPatch::truncate], which are
+/// simply `rom[truncate..]`.
+///
+/// # Examples
+///
+/// ```
+/// use rom_patcher::capture::original_bytes;
+/// use rom_patcher::ips::{IPSHunk, IPSPatch, IPSRLEHunkData};
+///
+/// let patch = IPSPatch::new()
+///     .with_hunk(IPSHunk::RLE(IPSRLEHunkData { offset: 1, run_length: 2, payload: 0xFF }));
+/// let captured = original_bytes(&patch, &[0, 1, 2, 3]);
+/// assert_eq!(captured, vec![(1, vec![1, 2].into_boxed_slice())]);
+/// ```
+pub fn original_bytes(patch: &IPSPatch, rom: &[u8]) -> Vec<(u64, Box<[u8]>)> {
+    coverage(patch.hunks())
+        .into_iter()
+        .filter(|x| x.start < rom.len() as u64)
+        .map(|x| (x.start, rom[x.start.to_index()..x.end.min(rom.len() as u64).to_index()].into()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use spectral::prelude::*;
+
+    use crate::ips::{IPSHunk, IPSRegularHunkData, IPSRLEHunkData};
+
+    use super::*;
+
+    fn regular(offset: u64, length: u16) -> IPSHunk {
+        IPSHunk::Regular(IPSRegularHunkData {
+            offset,
+            payload: vec![0xFF; length as usize].into_boxed_slice(),
+        })
+    }
+
+    fn rom() -> Vec<u8> {
+        (0..16).collect()
+    }
+
+    #[test]
+    fn empty_patch_captures_nothing() {
+        assert_that!(original_bytes(&IPSPatch::new(), &rom())).is_empty();
+    }
+
+    #[test]
+    fn overlapping_and_adjacent_hunks_share_a_range() {
+        let patch = IPSPatch::new()
+            .with_hunk(regular(8, 2))
+            .with_hunk(regular(2, 3))
+            .with_hunk(regular(4, 2))
+            .with_hunk(regular(6, 1));
+        assert_that!(original_bytes(&patch, &rom())).is_equal_to(vec![
+            (2, vec![2, 3, 4, 5, 6].into_boxed_slice()),
+            (8, vec![8, 9].into_boxed_slice()),
+        ]);
+    }
+
+    #[test]
+    fn bytes_past_the_rom_are_left_out() {
+        let patch = IPSPatch::new()
+            .with_hunk(IPSHunk::RLE(IPSRLEHunkData { offset: 14, run_length: 4, payload: 0 }))
+            .with_hunk(regular(20, 2));
+        assert_that!(original_bytes(&patch, &rom())).is_equal_to(vec![(14, vec![14, 15].into_boxed_slice())]);
+    }
+
+    #[test]
+    fn captured_bytes_undo_the_patch() {
+        let patch = IPSPatch::new().with_hunk(regular(3, 4)).with_hunk(regular(10, 2));
+        let original = rom();
+        let mut patched = std::io::Cursor::new(original.clone());
+        patch.apply(&mut patched).unwrap();
+        let mut patched = patched.into_inner();
+        for (offset, data) in original_bytes(&patch, &original) {
+            patched[offset as usize..offset as usize + data.len()].copy_from_slice(&data);
+        }
+        assert_that!(patched).is_equal_to(original);
+    }
+}
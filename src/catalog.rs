@@ -0,0 +1,199 @@
+//! An index over a directory of patches, answering which of them apply to a given rom.
+
+use std::fs;
+use std::path::Path;
+
+use crate::batch::find_patches;
+use crate::Error;
+#[cfg(feature = "json")]
+use crate::ErrorKind::ParsingError;
+use crate::ErrorKind::PatchingError;
+use crate::hash::Crc32;
+use crate::patch::PatchInfo;
+use crate::registry::read_any_from_slice;
+use crate::report::Paths;
+
+/// A patch found while indexing a directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize))]
+pub struct CatalogEntry {
+    /// path of the patch relative to the indexed directory, see [Paths].
+    pub path: String,
+    /// name of the format of the patch.
+    pub format: String,
+    /// length of the patch file.
+    pub size: u64,
+    /// CRC-32 of the patch file.
+    pub crc32: u32,
+    /// what the patch records about its source and target.
+    pub info: PatchInfo,
+}
+
+/// A filter for [Catalog::search]. Unset fields match everything.
+///
+/// # Examples
+///
+/// ```
+/// use rom_patcher::catalog::Query;
+/// let query = Query::new().with_format("ips").with_path_containing("hack");
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Query {
+    format: Option<String>,
+    path_containing: Option<String>,
+    source_crc32: Option<u32>,
+}
+
+impl Query {
+    /// constructs a query matching every entry.
+    pub fn new() -> Query {
+        Query::default()
+    }
+
+    /// modifies the query to only match patches of `format`.
+    pub fn with_format(mut self, format: &str) -> Query {
+        self.format = Some(format.to_string());
+        return self;
+    }
+
+    /// modifies the query to only match patches whose path contains `text`, ignoring case.
+    pub fn with_path_containing(mut self, text: &str) -> Query {
+        self.path_containing = Some(text.to_lowercase());
+        return self;
+    }
+
+    /// modifies the query to only match patches known to expect a source with `crc32`.
+    pub fn with_source_crc32(mut self, crc32: u32) -> Query {
+        self.source_crc32 = Some(crc32);
+        return self;
+    }
+
+    /// returns `true` if `entry` matches the query.
+    pub fn matches(&self, entry: &CatalogEntry) -> bool {
+        self.format.as_ref().is_none_or(|x| *x == entry.format)
+            && self.path_containing.as_ref().is_none_or(|x| entry.path.to_lowercase().contains(x))
+            && self.source_crc32.is_none_or(|x| entry.info.source_crc32 == Some(x))
+    }
+}
+
+/// An index over a directory of patches.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize))]
+pub struct Catalog {
+    /// indexed patches, ordered by path.
+    pub entries: Vec<CatalogEntry>,
+    /// paths of files that looked like patches but couldn't be read, with the reason why.
+    pub skipped: Vec<(String, String)>,
+}
+
+impl Catalog {
+    /// indexes every patch in `dir` and its subdirectories.
+    pub fn index(dir: &Path) -> Result<Catalog, Error> {
+        let paths = Paths::new(dir);
+        let mut found = Vec::new();
+        find_patches(dir, &mut found)?;
+        found.sort_by_key(|x| paths.relative(x));
+
+        let mut result = Catalog::default();
+        for path in found {
+            let relative = paths.relative(&path);
+            let data = fs::read(&path).map_err(|e| Error::new(PatchingError)
+                .with_description(format!("Unable to read patch {}.", path.display()))
+                .with_source(Box::new(e)))?;
+            match read_any_from_slice(&data) {
+                Ok(patch) => result.entries.push(CatalogEntry {
+                    path: relative,
+                    format: patch.format().to_string(),
+                    size: data.len() as u64,
+                    crc32: Crc32::checksum(&data),
+                    info: patch.info(),
+                }),
+                Err(e) => result.skipped.push((relative, e.to_string())),
+            }
+        }
+        return Ok(result);
+    }
+
+    /// returns every entry matching `query`, ordered by path.
+    pub fn search(&self, query: &Query) -> Vec<&CatalogEntry> {
+        self.entries.iter().filter(|x| query.matches(x)).collect()
+    }
+
+    /// returns every entry known to expect `rom` as its source.
+    ///
+    /// Patches that don't record their source, e.g. IPS, are never returned.
+    pub fn applicable_to(&self, rom: &[u8]) -> Vec<&CatalogEntry> {
+        let crc32 = Crc32::checksum(rom);
+        self.entries.iter()
+            .filter(|x| x.info.source_crc32 == Some(crc32))
+            .filter(|x| x.info.source_len.is_none_or(|len| len == rom.len() as u64))
+            .collect()
+    }
+
+    /// serializes the catalog as JSON.
+    #[cfg(feature = "json")]
+    pub fn to_json(&self) -> String {
+        // the catalog only contains plain data
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    /// deserializes a catalog serialized with [Catalog::to_json].
+    #[cfg(feature = "json")]
+    pub fn from_json(json: &str) -> Result<Catalog, Error> {
+        serde_json::from_str(json).map_err(|e| Error::new(ParsingError)
+            .with_description("Invalid catalog.".to_string())
+            .with_source(Box::new(e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::path::PathBuf;
+
+    use spectral::prelude::*;
+
+    use crate::pmsr::{PMSRPatch, PMSRRecord};
+    use crate::testkit::Fixture;
+
+    use super::*;
+
+    fn catalog_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("rom-patcher-catalog-{}-{}", name, std::process::id()));
+        fs::create_dir_all(dir.join("mods")).unwrap();
+        let mut ips = Vec::new();
+        Fixture::generate(5).ips.write(&mut ips).unwrap();
+        fs::write(dir.join("Hack.ips"), ips).unwrap();
+        let mut pmsr = Vec::new();
+        PMSRPatch::new().with_record(PMSRRecord { offset: 0, data: Box::new([1]) }).write(&mut pmsr).unwrap();
+        fs::write(dir.join("mods/star.mod"), pmsr).unwrap();
+        fs::write(dir.join("bad.ips"), b"NOPE").unwrap();
+        return dir;
+    }
+
+    #[test]
+    fn index_search_and_applicable() {
+        let dir = catalog_dir("search");
+        let catalog = Catalog::index(&dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let paths: Vec<&str> = catalog.entries.iter().map(|x| x.path.as_str()).collect();
+        assert_that!(paths).is_equal_to(vec!["Hack.ips", "mods/star.mod"]);
+        assert_that!(catalog.skipped.len()).is_equal_to(1);
+
+        assert_that!(catalog.search(&Query::new().with_format("pmsr")).len()).is_equal_to(1);
+        assert_that!(catalog.search(&Query::new().with_path_containing("hack")).len()).is_equal_to(1);
+        let star_rod = catalog.search(&Query::new().with_source_crc32(PMSRPatch::SOURCE_CRC32));
+        assert_that!(star_rod[0].path.as_str()).is_equal_to("mods/star.mod");
+        assert_that!(catalog.applicable_to(&[0; 16])).is_empty();
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn json_roundtrip() {
+        let dir = catalog_dir("json");
+        let catalog = Catalog::index(&dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_that!(Catalog::from_json(&catalog.to_json()).unwrap()).is_equal_to(catalog);
+    }
+}
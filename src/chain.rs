@@ -0,0 +1,293 @@
+//! Applying patches that build on each other in order of their dependencies.
+//!
+//! A patch can declare that it [requires](PatchMetadata::requires) the output of another patch,
+//! like an addon made for a translation, by the SHA-1 of that output. [PatchChain] orders its
+//! patches so every patch comes after the patches it requires, and checks the outputs along the
+//! way.
+//!
+//! The metadata is stored next to the patch in a JSON sidecar, `<patch>.deps.json`, with the
+//! `json` feature enabled.
+
+#[cfg(feature = "json")]
+use std::fs;
+#[cfg(feature = "json")]
+use std::path::{Path, PathBuf};
+
+use crate::Error;
+#[cfg(feature = "json")]
+use crate::ErrorKind::{ParsingError, PatchingError};
+use crate::ErrorKind::{ChecksumMismatch, ValidationError};
+use crate::hash::{Sha1, to_hex};
+#[cfg(feature = "json")]
+use crate::messages::Message;
+use crate::patch::Patch;
+
+/// What a patch declares about the patches it builds on.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize), serde(default))]
+pub struct PatchMetadata {
+    /// SHA-1 of the rom after applying the patch, as lowercase hex.
+    pub provides: Option<String>,
+    /// SHA-1s of the roms that have to be produced before the patch is applied, as lowercase hex.
+    ///
+    /// A requirement is met by the source of the chain or by a patch [providing](Self::provides) it.
+    pub requires: Vec<String>,
+    /// free-form metadata the patch carries itself, like the manifest of a BPS patch, which higan
+    /// writes as XML and other tools often as JSON. Read with [Patch::metadata].
+    pub manifest: Option<String>,
+    /// file name the author intends for the patched rom, read with
+    /// [Patch::suggested_output_name].
+    pub output_name: Option<String>,
+}
+
+impl PatchMetadata {
+    /// constructs metadata without dependencies.
+    pub fn new() -> PatchMetadata {
+        PatchMetadata::default()
+    }
+
+    /// modifies the metadata to declare that the patch produces a rom with the SHA-1 `provides`.
+    pub fn with_provides(mut self, provides: &[u8; 20]) -> PatchMetadata {
+        self.provides = Some(to_hex(provides));
+        return self;
+    }
+
+    /// modifies the metadata to declare that the patch needs a rom with the SHA-1 `requires`
+    /// produced first.
+    pub fn with_requires(mut self, requires: &[u8; 20]) -> PatchMetadata {
+        self.requires.push(to_hex(requires));
+        return self;
+    }
+
+    /// modifies the metadata to carry `manifest`.
+    pub fn with_manifest(mut self, manifest: impl Into<String>) -> PatchMetadata {
+        self.manifest = Some(manifest.into());
+        return self;
+    }
+
+    /// modifies the metadata to suggest naming the patched rom `output_name`.
+    pub fn with_output_name(mut self, output_name: impl Into<String>) -> PatchMetadata {
+        self.output_name = Some(output_name.into());
+        return self;
+    }
+
+    /// serializes the metadata as JSON.
+    #[cfg(feature = "json")]
+    pub fn to_json(&self) -> String {
+        // metadata only contains plain data
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    /// deserializes metadata serialized with [PatchMetadata::to_json].
+    #[cfg(feature = "json")]
+    pub fn from_json(json: &str) -> Result<PatchMetadata, Error> {
+        serde_json::from_str(json).map_err(|e| Error::new(ParsingError)
+            .with_description("Invalid patch metadata.".to_string())
+            .with_source(Box::new(e)))
+    }
+
+    /// returns the path of the sidecar storing the metadata of the patch at `patch`.
+    #[cfg(feature = "json")]
+    pub fn sidecar_path(patch: &Path) -> PathBuf {
+        let mut name = patch.as_os_str().to_os_string();
+        name.push(".deps.json");
+        return PathBuf::from(name);
+    }
+
+    /// reads the metadata of the patch at `patch` from its sidecar, [None] if it has none.
+    #[cfg(feature = "json")]
+    pub fn read_sidecar(patch: &Path) -> Result<Option<PatchMetadata>, Error> {
+        let path = Self::sidecar_path(patch);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let json = fs::read_to_string(&path).map_err(|e| Error::new(ParsingError)
+            .with_message(Message::UnableToRead { path: path.to_path_buf() })
+            .with_source(Box::new(e)))?;
+        return PatchMetadata::from_json(&json).map(Some);
+    }
+
+    /// writes the metadata to the sidecar of the patch at `patch`.
+    #[cfg(feature = "json")]
+    pub fn write_sidecar(&self, patch: &Path) -> Result<(), Error> {
+        let path = Self::sidecar_path(patch);
+        fs::write(&path, self.to_json()).map_err(|e| Error::new(PatchingError)
+            .with_message(Message::UnableToWrite { path: path.to_path_buf() })
+            .with_source(Box::new(e)))
+    }
+}
+
+/// A named patch of a [PatchChain] with its metadata.
+#[derive(Debug)]
+pub struct ChainLink {
+    /// name of the patch, e.g. its file name, used in errors.
+    pub name: String,
+    /// the patch.
+    pub patch: Box<dyn Patch>,
+    /// what the patch declares about its dependencies.
+    pub metadata: PatchMetadata,
+}
+
+/// Patches applied one after another in order of their dependencies.
+///
+/// # Examples
+///
+/// ```
+/// use rom_patcher::chain::{PatchChain, PatchMetadata};
+/// use rom_patcher::hash::Sha1;
+/// use rom_patcher::ips::{IPSHunk, IPSPatch, IPSRLEHunkData};
+///
+/// let base = IPSPatch::new().with_hunk(IPSHunk::RLE(IPSRLEHunkData { offset: 0, run_length: 1, payload: 0xAA }));
+/// let addon = IPSPatch::new().with_hunk(IPSHunk::RLE(IPSRLEHunkData { offset: 1, run_length: 1, payload: 0xBB }));
+/// let translated = Sha1::hash(&[0xAA, 0]);
+///
+/// let chain = PatchChain::new()
+///     .with_patch("addon.ips", Box::new(addon), PatchMetadata::new().with_requires(&translated))
+///     .with_patch("translation.ips", Box::new(base), PatchMetadata::new().with_provides(&translated));
+/// assert_eq!(chain.apply(&[0, 0]).unwrap(), vec![0xAA, 0xBB]);
+/// ```
+#[derive(Debug, Default)]
+pub struct PatchChain {
+    links: Vec<ChainLink>,
+}
+
+impl PatchChain {
+    /// constructs an empty chain.
+    pub fn new() -> PatchChain {
+        PatchChain::default()
+    }
+
+    /// modifies the chain to contain `patch` called `name` with `metadata`.
+    pub fn with_patch(mut self, name: &str, patch: Box<dyn Patch>, metadata: PatchMetadata) -> PatchChain {
+        self.links.push(ChainLink { name: name.to_string(), patch, metadata });
+        return self;
+    }
+
+    /// returns the patches in the order they were added.
+    pub fn links(&self) -> &[ChainLink] {
+        &self.links
+    }
+
+    /// returns the indices of the patches in the order they are applied to a source with the
+    /// SHA-1 `source_sha1`.
+    ///
+    /// Every patch comes after the patches it requires, otherwise patches keep the order they were
+    /// added in. Returns a [ValidationError] naming the patch if a requirement is met by nothing
+    /// or the patches require each other.
+    pub fn ordered(&self, source_sha1: &[u8; 20]) -> Result<Vec<usize>, Error> {
+        let mut available = vec![to_hex(source_sha1)];
+        let mut result: Vec<usize> = Vec::new();
+        while result.len() < self.links.len() {
+            let next = (0..self.links.len())
+                .filter(|x| !result.contains(x))
+                .find(|x| self.links[*x].metadata.requires.iter().all(|r| available.contains(r)));
+            let Some(next) = next else {
+                return Err(self.unmet(&result, &available));
+            };
+            available.extend(self.links[next].metadata.provides.clone());
+            result.push(next);
+        }
+        return Ok(result);
+    }
+
+    /// applies the patches to `source` in [order](PatchChain::ordered).
+    ///
+    /// Returns a [ChecksumMismatch] error if a patch doesn't produce the rom it
+    /// [provides](PatchMetadata::provides), so patches requiring it never see the wrong rom.
+    pub fn apply(&self, source: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut rom = source.to_vec();
+        for i in self.ordered(&Sha1::hash(source))? {
+            let link = &self.links[i];
+            rom = link.patch.apply_to_vec(&rom)?;
+            if let Some(provides) = &link.metadata.provides {
+                let actual = to_hex(&Sha1::hash(&rom));
+                if actual != *provides {
+                    return Err(Error::new(ChecksumMismatch).with_description(format!(
+                        "SHA-1 of the output of {} is {}, expected {}.", link.name, actual, provides,
+                    )));
+                }
+            }
+        }
+        return Ok(rom);
+    }
+
+    /// describes why none of the patches not in `ordered` can be applied with `available` roms.
+    fn unmet(&self, ordered: &[usize], available: &[String]) -> Error {
+        let remaining: Vec<&ChainLink> = (0..self.links.len())
+            .filter(|x| !ordered.contains(x))
+            .map(|x| &self.links[x])
+            .collect();
+        let provided = |hash: &String| remaining.iter().any(|x| x.metadata.provides.as_ref() == Some(hash));
+        for link in &remaining {
+            if let Some(missing) = link.metadata.requires.iter().find(|x| !available.contains(x) && !provided(x)) {
+                return Error::new(ValidationError)
+                    .with_description(format!("{} requires a rom with SHA-1 {}, which no patch provides.", link.name, missing));
+            }
+        }
+        let names: Vec<&str> = remaining.iter().map(|x| x.name.as_str()).collect();
+        return Error::new(ValidationError)
+            .with_description(format!("The patches {} require each other.", names.join(", ")));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use spectral::prelude::*;
+
+    use crate::ips::{IPSHunk, IPSPatch, IPSRLEHunkData};
+
+    use super::*;
+
+    fn rle(offset: u64, payload: u8) -> Box<dyn Patch> {
+        Box::new(IPSPatch::new().with_hunk(IPSHunk::RLE(IPSRLEHunkData { offset, run_length: 1, payload })))
+    }
+
+    #[test]
+    fn patches_are_ordered_by_dependencies() {
+        let source = [0; 3];
+        let (first, second) = (Sha1::hash(&[1, 0, 0]), Sha1::hash(&[1, 2, 0]));
+        let chain = PatchChain::new()
+            .with_patch("c", rle(2, 3), PatchMetadata::new().with_requires(&second))
+            .with_patch("b", rle(1, 2), PatchMetadata::new().with_requires(&first).with_provides(&second))
+            .with_patch("a", rle(0, 1), PatchMetadata::new().with_requires(&Sha1::hash(&source)).with_provides(&first));
+        assert_that!(chain.ordered(&Sha1::hash(&source)).unwrap()).is_equal_to(vec![2, 1, 0]);
+        assert_that!(chain.apply(&source).unwrap()).is_equal_to(vec![1, 2, 3]);
+        assert_that!(chain.apply(&[9, 9, 9])).is_err();
+    }
+
+    #[test]
+    fn unmet_and_cyclic_dependencies_fail() {
+        let (a, b) = (Sha1::hash(b"a"), Sha1::hash(b"b"));
+        let missing = PatchChain::new().with_patch("a", rle(0, 1), PatchMetadata::new().with_requires(&a));
+        assert_that!(missing.ordered(&b).unwrap_err().to_string()).contains("no patch provides");
+        let cyclic = PatchChain::new()
+            .with_patch("a", rle(0, 1), PatchMetadata::new().with_requires(&b).with_provides(&a))
+            .with_patch("b", rle(0, 2), PatchMetadata::new().with_requires(&a).with_provides(&b));
+        assert_that!(cyclic.ordered(&Sha1::hash(b"source")).unwrap_err().to_string()).contains("require each other");
+    }
+
+    #[test]
+    fn wrong_outputs_fail() {
+        let chain = PatchChain::new().with_patch("a", rle(0, 1), PatchMetadata::new().with_provides(&Sha1::hash(b"other")));
+        assert_that!(matches!(chain.apply(&[0]).unwrap_err().kind(), ChecksumMismatch)).is_true();
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn manifests_round_trip_through_json() {
+        let metadata = PatchMetadata::new().with_manifest("<title>Hack</title>");
+        let read = PatchMetadata::from_json(&metadata.to_json()).unwrap();
+        assert_that!(read.manifest).is_equal_to(Some("<title>Hack</title>".to_string()));
+        assert_that!(PatchMetadata::from_json("{}").unwrap().manifest).is_none();
+        assert_that!(rle(0, 1).metadata()).is_equal_to(PatchMetadata::new());
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn output_names_round_trip_through_json() {
+        let metadata = PatchMetadata::new().with_output_name("Hack (v1.1).sfc");
+        let read = PatchMetadata::from_json(&metadata.to_json()).unwrap();
+        assert_that!(read.output_name).is_equal_to(Some("Hack (v1.1).sfc".to_string()));
+        assert_that!(rle(0, 1).suggested_output_name()).is_none();
+    }
+}
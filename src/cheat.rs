@@ -0,0 +1,253 @@
+//! Importing emulator cheat files as patches.
+//!
+//! Cheats that write to rom can be made permanent by turning them into hunks. Addresses in cheat
+//! files are CPU addresses, so they are translated to rom offsets with a [Console] profile first;
+//! cheats touching anything but rom, e.g. RAM, are skipped.
+
+use std::collections::BTreeMap;
+
+use crate::console::Console;
+use crate::Error;
+use crate::ErrorKind::ParsingError;
+use crate::ips::{IPSHunk, IPSPatch, IPSRegularHunkData};
+
+/// A cheat as found in a cheat file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cheat {
+    /// the description of the cheat.
+    pub description: String,
+    /// whether the cheat is enabled.
+    pub enabled: bool,
+    /// the raw code, one or more `address:value` pairs in hexadecimal joined with `+`.
+    pub code: String,
+}
+
+/// A single write of a [Cheat].
+#[derive(Debug, Clone, PartialEq)]
+pub struct CheatEntry {
+    /// CPU address written to.
+    pub address: u32,
+    /// value written, in the order its bytes are written.
+    pub value: Box<[u8]>,
+}
+
+impl Cheat {
+    /// parses the code of the cheat.
+    ///
+    /// Returns a [ParsingError] for codes that are not plain `address:value` pairs, e.g. encrypted
+    /// Game Genie codes.
+    pub fn entries(&self) -> Result<Vec<CheatEntry>, Error> {
+        self.code.split('+')
+            .map(|x| x.trim())
+            .filter(|x| !x.is_empty())
+            .map(parse_entry)
+            .collect()
+    }
+}
+
+fn parse_entry(code: &str) -> Result<CheatEntry, Error> {
+    let invalid = || Error::new(ParsingError)
+        .with_description(format!("Unsupported cheat code \"{}\".", code));
+    let (address, value) = code.split_once([':', ' ']).ok_or_else(invalid)?;
+    let value = value.trim();
+    if value.is_empty() || value.len() % 2 != 0 {
+        return Err(invalid());
+    }
+    let address = u32::from_str_radix(address.trim(), 16).map_err(|_| invalid())?;
+    let value = (0..value.len())
+        .step_by(2)
+        .map(|i| value.get(i..i + 2).and_then(|x| u8::from_str_radix(x, 16).ok()))
+        .collect::<Option<Box<[u8]>>>()
+        .ok_or_else(invalid)?;
+    Ok(CheatEntry { address, value })
+}
+
+/// parses a RetroArch `.cht` file.
+///
+/// # Examples
+///
+/// ```
+/// use rom_patcher::cheat::parse_cht;
+/// let cheats = parse_cht("cheats = 1\ncheat0_desc = \"Lives\"\ncheat0_code = \"7E0DBF:09\"\ncheat0_enable = true\n").unwrap();
+/// assert_eq!(cheats[0].description, "Lives");
+/// ```
+pub fn parse_cht(text: &str) -> Result<Vec<Cheat>, Error> {
+    let mut count: Option<usize> = None;
+    let mut fields: BTreeMap<(usize, String), String> = BTreeMap::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (key, value) = line.split_once('=')
+            .ok_or_else(|| Error::new(ParsingError)
+                .with_description(format!("Expected \"key = value\" on line {}.", number + 1)))?;
+        let key = key.trim();
+        let value = value.trim().trim_matches('"').to_string();
+        if key == "cheats" {
+            count = Some(value.parse().map_err(|_| Error::new(ParsingError)
+                .with_description(format!("Invalid cheat count \"{}\".", value)))?);
+            continue;
+        }
+        // keys look like cheat<index>_<field>
+        if let Some((index, field)) = key.strip_prefix("cheat").and_then(|x| x.split_once('_')) {
+            if let Ok(index) = index.parse::<usize>() {
+                fields.insert((index, field.to_string()), value);
+            }
+        }
+    }
+
+    let count = count.ok_or_else(|| Error::new(ParsingError)
+        .with_description("Missing cheat count.".to_string()))?;
+    let codes = fields.keys().filter(|(_, field)| field == "code").count();
+    if count > codes {
+        return Err(Error::new(ParsingError)
+            .with_description(format!("The file counts {} cheats, but only has codes for {}.", count, codes)));
+    }
+    let mut result = Vec::with_capacity(count);
+    for index in 0..count {
+        let mut take = |field: &str| fields.remove(&(index, field.to_string()));
+        let code = take("code").ok_or_else(|| Error::new(ParsingError)
+            .with_description(format!("Missing code of cheat {}.", index)))?;
+        result.push(Cheat {
+            description: take("desc").unwrap_or_default(),
+            enabled: take("enable").is_some_and(|x| x == "true"),
+            code,
+        });
+    }
+    return Ok(result);
+}
+
+/// The result of [import].
+#[derive(Debug)]
+pub struct ImportedCheats {
+    /// patch writing every imported cheat into the rom.
+    pub patch: IPSPatch,
+    /// descriptions of cheats that couldn't be imported, with the reason why.
+    pub skipped: Vec<(String, Error)>,
+}
+
+/// converts `cheats` into a patch, translating their addresses with `console`.
+///
+/// A cheat is imported only if all of its entries can be parsed and land in rom, since applying
+/// part of a cheat rarely does anything useful.
+pub fn import<'a>(cheats: impl IntoIterator<Item=&'a Cheat>, console: Console) -> ImportedCheats {
+    let mut result = ImportedCheats {
+        patch: IPSPatch::new(),
+        skipped: Vec::new(),
+    };
+    for cheat in cheats {
+        match to_hunks(cheat, console) {
+            Ok(hunks) => hunks.into_iter().for_each(|x| result.patch.add_hunk(x)),
+            Err(e) => result.skipped.push((cheat.description.clone(), e)),
+        }
+    }
+    return result;
+}
+
+fn to_hunks(cheat: &Cheat, console: Console) -> Result<Vec<IPSHunk>, Error> {
+    let mut result = Vec::new();
+    for entry in cheat.entries()? {
+        let offset = console.rom_offset(entry.address)
+            .map(u64::from)
+            .filter(|x| *x <= IPSPatch::MAX_OFFSET)
+            .ok_or_else(|| Error::new(ParsingError)
+                .with_description(format!("Address {:X} is not in rom.", entry.address)))?;
+        result.push(IPSHunk::Regular(IPSRegularHunkData {
+            offset,
+            payload: entry.value,
+        }));
+    }
+    return Ok(result);
+}
+
+#[cfg(test)]
+mod tests {
+    use spectral::prelude::*;
+
+    use super::*;
+
+    const CHT: &str = r#"cheats = 3
+
+cheat0_desc = "Infinite Lives"
+cheat0_code = "7E0DBF:09"
+cheat0_enable = false
+
+cheat1_desc = "Skip Intro"
+cheat1_code = "00896A:EAEA+00896C:80"
+cheat1_enable = true
+
+cheat2_desc = "Moon Jump"
+cheat2_code = "DD62-6DAD"
+cheat2_enable = false
+"#;
+
+    mod parse_tests {
+        use super::*;
+
+        #[test]
+        fn parses_all_cheats() {
+            let cheats = parse_cht(CHT).unwrap();
+            assert_that!(cheats).has_length(3);
+            assert_that!(cheats[1]).is_equal_to(Cheat {
+                description: "Skip Intro".to_string(),
+                enabled: true,
+                code: "00896A:EAEA+00896C:80".to_string(),
+            });
+        }
+
+        #[test]
+        fn parses_entries_of_a_code() {
+            let entries = parse_cht(CHT).unwrap()[1].entries().unwrap();
+            assert_that!(entries).is_equal_to(vec![
+                CheatEntry { address: 0x896A, value: Box::new([0xEA, 0xEA]) },
+                CheatEntry { address: 0x896C, value: Box::new([0x80]) },
+            ]);
+        }
+
+        #[test]
+        fn missing_count_fails() {
+            assert_that!(parse_cht("cheat0_code = \"00:00\"")).is_err();
+        }
+
+        #[test]
+        fn missing_code_fails() {
+            assert_that!(parse_cht("cheats = 1\ncheat0_desc = \"a\"")).is_err();
+        }
+
+        #[test]
+        fn counts_past_the_codes_fail() {
+            let err = parse_cht("cheats = 100000000000000000\ncheat0_code = \"00:00\"\n").unwrap_err();
+            assert_that!(matches!(err.kind(), ParsingError)).is_true();
+            assert_that!(err.to_string()).contains("only has codes for 1");
+        }
+    }
+
+    mod import_tests {
+        use super::*;
+
+        #[test]
+        fn rom_cheats_become_hunks() {
+            let cheats = parse_cht(CHT).unwrap();
+            let imported = import(&cheats, Console::SnesLoRom);
+            let hunks: Vec<(u64, u64)> = imported.patch.hunks().iter().map(|x| (x.offset(), x.length())).collect();
+            assert_that!(hunks).is_equal_to(vec![(0x096A, 2), (0x096C, 1)]);
+        }
+
+        #[test]
+        fn ram_and_encrypted_cheats_are_skipped() {
+            let cheats = parse_cht(CHT).unwrap();
+            let imported = import(&cheats, Console::SnesLoRom);
+            let skipped: Vec<&str> = imported.skipped.iter().map(|(x, _)| x.as_str()).collect();
+            assert_that!(skipped).is_equal_to(vec!["Infinite Lives", "Moon Jump"]);
+        }
+
+        #[test]
+        fn only_given_cheats_are_imported() {
+            let cheats = parse_cht(CHT).unwrap();
+            let imported = import(cheats.iter().filter(|x| x.enabled), Console::SnesLoRom);
+            assert_that!(imported.patch.hunks().len()).is_equal_to(2);
+            assert_that!(imported.skipped).is_empty();
+        }
+    }
+}
@@ -0,0 +1,252 @@
+//! Patching compressed assets inside roms through pluggable [codecs](Codec).
+//!
+//! An [AssetPatch] is expressed against the decompressed asset. Applying it decompresses the asset,
+//! patches it, recompresses it and writes it back, moving it to the end of the rom and rewriting
+//! the pointers to it if it no longer fits into its old place.
+
+use std::fmt::Debug;
+use std::io::Cursor;
+
+use crate::compression::{huffman, lz77, yay0, yaz0};
+use crate::Error;
+use crate::ErrorKind::PatchingError;
+use crate::io_util::U64Extensions;
+use crate::ips::IPSPatch;
+
+/// A compression format assets can be stored in.
+///
+/// Implement this to patch assets in formats the crate doesn't know.
+pub trait Codec: Debug + Send + Sync {
+    /// returns the name of the format.
+    fn name(&self) -> &'static str;
+
+    /// decompresses the asset at the start of `data`, returning it and the amount of bytes the
+    /// compressed asset spans.
+    fn decompress(&self, data: &[u8]) -> Result<(Vec<u8>, usize), Error>;
+
+    /// compresses `data`.
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>, Error>;
+}
+
+/// The LZ77 format of the GBA and DS BIOS, see [lz77].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Lz77;
+
+impl Codec for Lz77 {
+    fn name(&self) -> &'static str {
+        "lz77"
+    }
+
+    fn decompress(&self, data: &[u8]) -> Result<(Vec<u8>, usize), Error> {
+        lz77::decompress_with_len(data)
+    }
+
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        lz77::compress(data)
+    }
+}
+
+/// The Huffman format of the GBA and DS BIOS, see [huffman].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Huffman {
+    /// size of the symbols recompressed assets are coded in, 4 or 8.
+    pub symbol_bits: u8,
+}
+
+impl Codec for Huffman {
+    fn name(&self) -> &'static str {
+        "huffman"
+    }
+
+    fn decompress(&self, data: &[u8]) -> Result<(Vec<u8>, usize), Error> {
+        huffman::decompress_with_len(data)
+    }
+
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        huffman::compress(data, self.symbol_bits)
+    }
+}
+
+/// The Yay0 format of N64 assets, see [yay0].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Yay0;
+
+impl Codec for Yay0 {
+    fn name(&self) -> &'static str {
+        "yay0"
+    }
+
+    fn decompress(&self, data: &[u8]) -> Result<(Vec<u8>, usize), Error> {
+        yay0::decompress_with_len(data)
+    }
+
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        yay0::compress(data)
+    }
+}
+
+/// The Yaz0 format of GameCube and Wii assets, see [yaz0].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Yaz0;
+
+impl Codec for Yaz0 {
+    fn name(&self) -> &'static str {
+        "yaz0"
+    }
+
+    fn decompress(&self, data: &[u8]) -> Result<(Vec<u8>, usize), Error> {
+        yaz0::decompress_with_len(data)
+    }
+
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        yaz0::compress(data)
+    }
+}
+
+/// A patch of a compressed asset.
+///
+/// # Examples
+///
+/// ```
+/// use rom_patcher::codec::{AssetPatch, Lz77};
+/// use rom_patcher::compression::lz77;
+/// use rom_patcher::ips::{IPSHunk, IPSPatch, IPSRLEHunkData};
+///
+/// let mut rom = lz77::compress(b"HELLO WORLD").unwrap();
+/// let patch = IPSPatch::new()
+///     .with_hunk(IPSHunk::RLE(IPSRLEHunkData { offset: 0, run_length: 1, payload: b'J' }));
+/// AssetPatch::new(Box::new(Lz77), 0, patch).apply(&mut rom).unwrap();
+/// assert_eq!(lz77::decompress(&rom).unwrap(), b"JELLO WORLD");
+/// ```
+#[derive(Debug)]
+pub struct AssetPatch {
+    codec: Box<dyn Codec>,
+    offset: u64,
+    patch: IPSPatch,
+    pointers: Vec<u64>,
+    alignment: u64,
+}
+
+impl AssetPatch {
+    /// constructs a patch applying `patch` to the decompressed asset stored with `codec` at rom
+    /// offset `offset`.
+    pub fn new(codec: Box<dyn Codec>, offset: u64, patch: IPSPatch) -> AssetPatch {
+        AssetPatch { codec, offset, patch, pointers: Vec::new(), alignment: 4 }
+    }
+
+    /// modifies the patch to rewrite the little-endian 32-bit pointer at rom offset `offset` when the
+    /// asset moves.
+    ///
+    /// Pointers keep their distance to the asset, so CPU addresses like `0x08xxxxxx` stay CPU
+    /// addresses.
+    pub fn with_pointer(mut self, offset: u64) -> AssetPatch {
+        self.pointers.push(offset);
+        return self;
+    }
+
+    /// modifies the patch to align assets to `alignment` bytes, four by default.
+    pub fn with_alignment(mut self, alignment: u64) -> AssetPatch {
+        self.alignment = alignment.max(1);
+        return self;
+    }
+
+    /// returns the codec the asset is stored with.
+    pub fn codec(&self) -> &dyn Codec {
+        self.codec.as_ref()
+    }
+
+    /// returns the rom offset of the compressed asset.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// returns the patch applied to the decompressed asset.
+    pub fn patch(&self) -> &IPSPatch {
+        &self.patch
+    }
+
+    /// applies the patch to `rom`, returning the rom offset the asset ends up at.
+    ///
+    /// If the recompressed asset fits into the space of the old one, including its padding to the
+    /// alignment, it is written in place and the rest of the old asset is left as it is. Otherwise it is appended to the rom and every
+    /// [pointer](AssetPatch::with_pointer) is rewritten.
+    pub fn apply(&self, rom: &mut Vec<u8>) -> Result<u64, Error> {
+        let start = self.offset.saturating_usize();
+        let compressed = rom.get(start..).ok_or_else(|| Error::new(PatchingError)
+            .with_description(format!("Asset offset {:#X} is past the end of the rom.", self.offset)))?;
+        let (asset, old_len) = self.codec.decompress(compressed)?;
+        let mut asset = Cursor::new(asset);
+        self.patch.apply(&mut asset)?;
+        let recompressed = self.codec.compress(asset.get_ref())?;
+
+        // assets are padded to the alignment, so the padding is free to use as well
+        let old_len = (old_len as u64).div_ceil(self.alignment) * self.alignment;
+        if recompressed.len() as u64 <= old_len.min((rom.len() - start) as u64) {
+            rom[start..start + recompressed.len()].copy_from_slice(&recompressed);
+            return Ok(self.offset);
+        }
+
+        for pointer in &self.pointers {
+            if (rom.len() as u64) < pointer.saturating_add(4) {
+                return Err(Error::new(PatchingError)
+                    .with_description(format!("Pointer offset {:#X} is past the end of the rom.", pointer)));
+            }
+        }
+        let new_offset = (rom.len() as u64).div_ceil(self.alignment) * self.alignment;
+        rom.resize(new_offset as usize, 0);
+        rom.extend_from_slice(&recompressed);
+        for pointer in &self.pointers {
+            let at = pointer.to_index();
+            let value = u32::from_le_bytes([rom[at], rom[at + 1], rom[at + 2], rom[at + 3]]);
+            let moved = value.wrapping_sub(self.offset as u32).wrapping_add(new_offset as u32);
+            rom[at..at + 4].copy_from_slice(&moved.to_le_bytes());
+        }
+        Ok(new_offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use spectral::prelude::*;
+
+    use crate::ips::{IPSHunk, IPSRegularHunkData};
+
+    use super::*;
+
+    #[test]
+    fn growing_assets_move_and_pointers_follow() {
+        // a pointer to the asset at 8 in GBA address space, then the asset
+        let mut rom = vec![0x08, 0x00, 0x00, 0x08, 0, 0, 0, 0];
+        rom.extend(lz77::compress(&[0; 32]).unwrap());
+        let payload: Vec<u8> = (0..32).collect();
+        let patch = IPSPatch::new().with_hunk(IPSHunk::Regular(IPSRegularHunkData {
+            offset: 0,
+            payload: payload.clone().into_boxed_slice(),
+        }));
+
+        let offset = AssetPatch::new(Box::new(Lz77), 8, patch).with_pointer(0).apply(&mut rom).unwrap();
+        assert_that!(offset).is_equal_to(20);
+        assert_that!(rom[..4].to_vec()).is_equal_to(vec![20, 0, 0, 8]);
+        assert_that!(lz77::decompress(&rom[20..]).unwrap()).is_equal_to(payload);
+    }
+
+    #[test]
+    fn fitting_assets_stay_in_place() {
+        let mut rom = lz77::compress(b"0123456789").unwrap();
+        let patch = IPSPatch::new().with_hunk(IPSHunk::Regular(IPSRegularHunkData {
+            offset: 9,
+            payload: Box::new([b'0']),
+        }));
+        let len = rom.len();
+        let offset = AssetPatch::new(Box::new(Lz77), 0, patch).apply(&mut rom).unwrap();
+        assert_that!(offset).is_equal_to(0);
+        assert_that!(rom.len()).is_equal_to(len);
+        assert_that!(lz77::decompress(&rom).unwrap()).is_equal_to(b"0123456780".to_vec());
+    }
+
+    #[test]
+    fn assets_past_the_end_fail() {
+        let result = AssetPatch::new(Box::new(Lz77), 100, IPSPatch::new()).apply(&mut vec![0; 8]);
+        assert_that!(result).is_err();
+    }
+}
@@ -0,0 +1,250 @@
+//! A JSON command API for GUI shells.
+//!
+//! Every operation is a [Request] that can be deserialized from JSON and answers with a
+//! [Response] that serializes back to JSON, so frontends like Tauri or Flutter apps can drive the
+//! patcher over a thin IPC bridge with [handle_json] instead of bespoke bindings.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::apply::ApplyOptions;
+use crate::create::{create_all_with_progress, CreateFormat, CreateOptions};
+use crate::Error;
+use crate::ErrorKind::{CreatingError, ParsingError, PatchingError};
+use crate::facade::PatchJob;
+use crate::messages::Message;
+use crate::outcome::OperationResult;
+use crate::progress::{CancellationToken, Progress};
+use crate::registry::{formats, read_any_from_slice};
+
+/// An operation requested by a frontend.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Request {
+    /// lists the known patch formats.
+    Formats,
+    /// detects the format of the patch at `patch`.
+    Detect {
+        patch: PathBuf,
+    },
+    /// validates the patch at `patch`.
+    Validate {
+        patch: PathBuf,
+    },
+    /// applies the patch at `patch` to `source`, writing the result to `output`.
+    Apply {
+        source: PathBuf,
+        patch: PathBuf,
+        output: PathBuf,
+        #[serde(default)]
+        options: ApplyOptions,
+    },
+    /// creates a patch of `format` turning `source` into `target`, writing it to `output`.
+    Create {
+        source: PathBuf,
+        target: PathBuf,
+        output: PathBuf,
+        format: CreateFormat,
+        #[serde(default)]
+        options: CreateOptions,
+    },
+}
+
+/// The answer to a [Request].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum Response {
+    /// the request succeeded with `result`.
+    Ok {
+        result: Value,
+    },
+    /// the request failed.
+    Error {
+        /// the [crate::ErrorKind] of the failure.
+        kind: String,
+        /// a human readable description of the failure.
+        message: String,
+        /// the outcome of the request, for deciding what to do next.
+        result: OperationResult,
+        /// advice for users on how to resolve the failure, see [crate::Error::suggestion].
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        suggestion: Option<String>,
+    },
+}
+
+impl From<Result<Value, Error>> for Response {
+    fn from(value: Result<Value, Error>) -> Self {
+        match value {
+            Ok(result) => Response::Ok { result },
+            Err(e) => Response::Error {
+                kind: format!("{:?}", e.kind()),
+                message: e.to_string(),
+                result: OperationResult::from_error(&e),
+                suggestion: e.suggestion().map(|x| x.to_string()),
+            },
+        }
+    }
+}
+
+/// executes `request`.
+pub fn handle(request: &Request) -> Response {
+    Response::from(execute(request, None, &CancellationToken::new()))
+}
+
+/// executes `request`, sending the progress of long running commands to `progress` and stopping
+/// them once `cancellation` is cancelled.
+pub fn handle_with_progress(request: &Request, progress: Option<Sender<Progress>>, cancellation: &CancellationToken) -> Response {
+    Response::from(execute(request, progress, cancellation))
+}
+
+/// executes the request serialized in `request` and returns the serialized response.
+///
+/// # Examples
+///
+/// ```
+/// use rom_patcher::commands::handle_json;
+/// let response = handle_json(r#"{"command": "formats"}"#);
+/// assert!(response.contains(r#""status":"ok""#));
+/// ```
+pub fn handle_json(request: &str) -> String {
+    let response = match serde_json::from_str::<Request>(request) {
+        Ok(request) => handle(&request),
+        Err(e) => Response::from(Err(Error::new(ParsingError)
+            .with_description(format!("Invalid request: {}.", e)))),
+    };
+    // serializing a Value-based enum can't fail
+    return serde_json::to_string(&response).unwrap();
+}
+
+fn execute(request: &Request, progress: Option<Sender<Progress>>, cancellation: &CancellationToken) -> Result<Value, Error> {
+    cancellation.check()?;
+    match request {
+        Request::Formats => Ok(formats()
+            .iter()
+            .map(|x| json!({ "name": x.name, "extensions": x.extensions }))
+            .collect()),
+        Request::Detect { patch } => {
+            let patch = read_any_from_slice(&read_file(patch)?)?;
+            Ok(json!({ "format": patch.format() }))
+        }
+        Request::Validate { patch } => {
+            let patch = read_any_from_slice(&read_file(patch)?)?;
+            Ok(json!({ "format": patch.format(), "diagnostics": patch.validate() }))
+        }
+        Request::Apply { source, patch, output, options } => {
+            let mut job = PatchJob::new(source, patch, output)
+                .with_options(options.clone())
+                .with_cancellation(cancellation.clone());
+            if let Some(progress) = progress {
+                job = job.with_progress(progress);
+            }
+            let report = job.run()?;
+            Ok(to_value(&report))
+        }
+        Request::Create { source, target, output, format, options } => {
+            let source = read_file(source)?;
+            let target = read_file(target)?;
+            let mut created = create_all_with_progress(&source, &target, &[*format], options, progress, cancellation)?;
+            if let Some((_, e)) = created.skipped.pop() {
+                return Err(e);
+            }
+            let patch = created.patches.remove(0);
+            let data = patch.to_bytes()
+                .and_then(|data| fs::write(output, &data).map(|_| data))
+                .map_err(|e| Error::new(CreatingError)
+                    .with_description(format!("Unable to write patch {}.", output.display()))
+                    .with_source(Box::new(e)))?;
+            Ok(json!({
+                "format": patch.format(),
+                "patch_len": data.len(),
+                "header": created.header.is_some(),
+                "strategy": created.strategy,
+            }))
+        }
+    }
+}
+
+fn to_value(value: &impl Serialize) -> Value {
+    // the crate's report types only contain plain data
+    serde_json::to_value(value).unwrap()
+}
+
+fn read_file(path: &Path) -> Result<Vec<u8>, Error> {
+    fs::read(path).map_err(|e| Error::new(PatchingError)
+        .with_message(Message::UnableToRead { path: path.to_path_buf() })
+        .with_source(Box::new(e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use spectral::prelude::*;
+
+    use crate::testkit::Fixture;
+
+    use super::*;
+
+    fn command_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("rom-patcher-commands-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        return dir;
+    }
+
+    fn request(json: Value) -> Value {
+        serde_json::from_str(&handle_json(&json.to_string())).unwrap()
+    }
+
+    #[test]
+    fn invalid_requests_fail() {
+        let response = request(json!({ "command": "explode" }));
+        assert_that!(response["status"].as_str()).is_equal_to(Some("error"));
+        assert_that!(response["kind"].as_str()).is_equal_to(Some("ParsingError"));
+        assert_that!(response["result"].as_str()).is_equal_to(Some("invalid_patch"));
+    }
+
+    #[test]
+    fn create_then_apply_reproduces_target() {
+        let dir = command_dir("roundtrip");
+        let fixture = Fixture::generate(3);
+        fs::write(dir.join("source.bin"), &fixture.source).unwrap();
+        fs::write(dir.join("target.bin"), &fixture.target).unwrap();
+
+        let created = request(json!({
+            "command": "create",
+            "source": dir.join("source.bin"),
+            "target": dir.join("target.bin"),
+            "output": dir.join("patch.ips"),
+            "format": "ips",
+        }));
+        assert_that!(created["status"].as_str()).is_equal_to(Some("ok"));
+
+        let detected = request(json!({ "command": "detect", "patch": dir.join("patch.ips") }));
+        assert_that!(detected["result"]["format"].as_str()).is_equal_to(Some("ips"));
+
+        let applied = request(json!({
+            "command": "apply",
+            "source": dir.join("source.bin"),
+            "patch": dir.join("patch.ips"),
+            "output": dir.join("out.bin"),
+            "options": { "truncate_check": "warn" },
+        }));
+        assert_that!(applied["status"].as_str()).is_equal_to(Some("ok"));
+        assert_that!(applied["result"]["output_len"].as_u64()).is_equal_to(Some(fixture.target.len() as u64));
+        assert_that!(fs::read(dir.join("out.bin")).unwrap()).is_equal_to(&fixture.target);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn failures_report_their_kind() {
+        let response = handle(&Request::Detect { patch: PathBuf::from("/nonexistent/patch.ips") });
+        match response {
+            Response::Error { kind, .. } => assert_that!(kind).is_equal_to("PatchingError".to_string()),
+            Response::Ok { .. } => panic!("expected an error"),
+        }
+    }
+}
@@ -0,0 +1,290 @@
+//! Huffman, the format decompressed by the `HuffUnComp` function of the GBA and DS BIOS.
+//!
+//! The data starts with a little-endian word holding the symbol size of 4 or 8 bits in its lowest
+//! nibble, the type `2` in the next one and the decompressed size in the upper three bytes. The
+//! tree table follows: a byte holding half its length minus one, then the nodes, the root first.
+//! Each node holds the offset of its pair of children and flags telling which of them are leaves
+//! holding a symbol. The codes follow as little-endian words read from their highest bit.
+//! Symbols fill the output from the lowest bit of each byte up.
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+use crate::Error;
+use crate::ErrorKind::{CreatingError, ParsingError};
+
+/// Type nibble identifying Huffman data.
+pub const TYPE: u8 = 0x20;
+
+/// the largest size that fits into the header.
+pub const MAX_SIZE: usize = 0xFF_FFFF;
+
+/// the largest offset from a node to its children.
+const MAX_NODE_OFFSET: usize = 0x3F;
+
+/// returns `true` if `data` starts with a Huffman header.
+pub fn is_huffman(data: &[u8]) -> bool {
+    data.len() >= 5 && data[0] & 0xF0 == TYPE && matches!(data[0] & 0xF, 4 | 8)
+}
+
+/// returns the decompressed size stored in the header of `data`.
+pub fn decompressed_size(data: &[u8]) -> Result<usize, Error> {
+    if !is_huffman(data) {
+        return Err(invalid("Invalid Huffman header."));
+    }
+    Ok(u32::from_le_bytes([data[0], data[1], data[2], data[3]]) as usize >> 8)
+}
+
+/// decompresses Huffman `data`.
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>, Error> {
+    decompress_with_len(data).map(|(result, _)| result)
+}
+
+/// decompresses Huffman `data`, also returning the amount of bytes the compressed data spans.
+pub fn decompress_with_len(data: &[u8]) -> Result<(Vec<u8>, usize), Error> {
+    let size = decompressed_size(data)?;
+    let symbol_bits = (data[0] & 0xF) as usize;
+    let table = &data[4..];
+    let node = |address: usize| table.get(address).copied().ok_or_else(|| invalid("Huffman node is out of bounds."));
+    let mut position = 4 + (table[0] as usize + 1) * 2;
+
+    let mut result = Vec::with_capacity(size);
+    let mut current = 0u8;
+    let mut current_bits = 0;
+    let mut address = 1;
+    while result.len() < size {
+        let word = data.get(position..position + 4).ok_or_else(|| invalid("Unexpected end of Huffman data."))?;
+        position += 4;
+        let word = u32::from_le_bytes([word[0], word[1], word[2], word[3]]);
+        for bit in (0..32).rev() {
+            let direction = (word >> bit & 1) as usize;
+            let value = node(address)?;
+            let child = (address & !1) + (value as usize & MAX_NODE_OFFSET) * 2 + 2 + direction;
+            let is_leaf = value & (0x80 >> direction) != 0;
+            if !is_leaf {
+                address = child;
+                continue;
+            }
+            current |= node(child)? << current_bits;
+            current_bits += symbol_bits;
+            address = 1;
+            if current_bits == 8 {
+                result.push(current);
+                current = 0;
+                current_bits = 0;
+                if result.len() >= size {
+                    break;
+                }
+            }
+        }
+    }
+    Ok((result, position))
+}
+
+#[derive(Debug)]
+enum Node {
+    Leaf(u8),
+    Internal(Box<Node>, Box<Node>),
+}
+
+/// compresses `data` into symbols of `symbol_bits`, which must be 4 or 8.
+///
+/// # Examples
+///
+/// ```
+/// use rom_patcher::compression::huffman;
+/// let data = b"aaaaaaaabbbbcc".to_vec();
+/// let compressed = huffman::compress(&data, 8).unwrap();
+/// assert_eq!(huffman::decompress(&compressed).unwrap(), data);
+/// ```
+pub fn compress(data: &[u8], symbol_bits: u8) -> Result<Vec<u8>, Error> {
+    if symbol_bits != 4 && symbol_bits != 8 {
+        return Err(Error::new(CreatingError).with_description("Huffman symbols must be 4 or 8 bits.".to_string()));
+    }
+    if data.len() > MAX_SIZE {
+        return Err(Error::new(CreatingError).with_description("Data is too large for Huffman.".to_string()));
+    }
+    let symbols: Vec<u8> = match symbol_bits {
+        4 => data.iter().flat_map(|x| [x & 0xF, x >> 4]).collect(),
+        _ => data.to_vec(),
+    };
+
+    let tree = build_tree(&symbols);
+    let mut codes = vec![Vec::new(); 256];
+    assign_codes(&tree, &mut Vec::new(), &mut codes);
+
+    let mut result = ((data.len() as u32) << 8 | (TYPE | symbol_bits) as u32).to_le_bytes().to_vec();
+    let table = layout_tree(tree)?;
+    result.extend_from_slice(&table);
+
+    let mut word = 0u32;
+    let mut word_bits = 0;
+    for symbol in symbols {
+        for bit in &codes[symbol as usize] {
+            word |= (*bit as u32) << (31 - word_bits);
+            word_bits += 1;
+            if word_bits == 32 {
+                result.extend_from_slice(&word.to_le_bytes());
+                word = 0;
+                word_bits = 0;
+            }
+        }
+    }
+    if word_bits > 0 {
+        result.extend_from_slice(&word.to_le_bytes());
+    }
+    Ok(result)
+}
+
+/// builds the Huffman tree of `symbols`, whose root is always an internal node.
+fn build_tree(symbols: &[u8]) -> Node {
+    let mut counts = [0usize; 256];
+    for symbol in symbols {
+        counts[*symbol as usize] += 1;
+    }
+    let mut nodes: Vec<Option<Node>> = Vec::new();
+    // the index breaks ties between equal counts, which keeps the output deterministic
+    let mut heap = BinaryHeap::new();
+    for (symbol, count) in counts.iter().enumerate().filter(|(_, x)| **x > 0) {
+        heap.push(Reverse((*count, nodes.len())));
+        nodes.push(Some(Node::Leaf(symbol as u8)));
+    }
+    if nodes.len() < 2 {
+        // a tree needs two leaves, so a single symbol is paired with itself
+        let symbol = symbols.first().copied().unwrap_or_default();
+        return Node::Internal(Box::new(Node::Leaf(symbol)), Box::new(Node::Leaf(symbol)));
+    }
+    while heap.len() > 1 {
+        let Reverse((a_count, a)) = heap.pop().unwrap();
+        let Reverse((b_count, b)) = heap.pop().unwrap();
+        let node = Node::Internal(Box::new(nodes[a].take().unwrap()), Box::new(nodes[b].take().unwrap()));
+        heap.push(Reverse((a_count + b_count, nodes.len())));
+        nodes.push(Some(node));
+    }
+    let Reverse((_, root)) = heap.pop().unwrap();
+    return nodes[root].take().unwrap();
+}
+
+fn assign_codes(node: &Node, prefix: &mut Vec<u8>, codes: &mut [Vec<u8>]) {
+    match node {
+        Node::Leaf(symbol) => {
+            if codes[*symbol as usize].is_empty() {
+                codes[*symbol as usize] = prefix.clone();
+            }
+        }
+        Node::Internal(zero, one) => {
+            prefix.push(0);
+            assign_codes(zero, prefix, codes);
+            prefix.pop();
+            prefix.push(1);
+            assign_codes(one, prefix, codes);
+            prefix.pop();
+        }
+    }
+}
+
+fn leaves(node: &Node) -> usize {
+    match node {
+        Node::Leaf(_) => 1,
+        Node::Internal(zero, one) => leaves(zero) + leaves(one),
+    }
+}
+
+/// lays out `root` as a tree table.
+///
+/// Every node must have its children within [MAX_NODE_OFFSET] pairs after its own, which breadth
+/// first layouts of wide trees miss. So the children of the smallest pending subtree are placed
+/// next, unless that would leave another pending node out of reach.
+fn layout_tree(root: Node) -> Result<Vec<u8>, Error> {
+    let mut table = vec![0, 0];
+    // nodes whose children aren't placed yet, ordered by address
+    let mut pending = vec![(1, leaves(&root), root)];
+    // the last pair the children of the node at `address` can be placed at
+    let deadline = |address: usize| address / 2 + MAX_NODE_OFFSET + 1;
+    while !pending.is_empty() {
+        let pair = table.len() / 2;
+        let feasible = |chosen: usize| pending.iter()
+            .enumerate()
+            .filter(|(i, _)| *i != chosen)
+            .enumerate()
+            .all(|(k, (_, (address, _, _)))| deadline(*address) > pair + k);
+        let choice = (0..pending.len())
+            .filter(|i| feasible(*i))
+            .min_by_key(|i| pending[*i].1)
+            .unwrap_or(0);
+        let (address, _, node) = pending.remove(choice);
+        if deadline(address) < pair {
+            return Err(Error::new(CreatingError).with_description("Huffman tree is too wide for the tree table.".to_string()));
+        }
+        let Node::Internal(zero, one) = node else {
+            continue;
+        };
+        let children = table.len();
+        table[address] = (pair - address / 2 - 1) as u8;
+        table.extend_from_slice(&[0, 0]);
+        for (direction, child) in [(0, *zero), (1, *one)] {
+            match child {
+                Node::Leaf(symbol) => {
+                    table[address] |= 0x80 >> direction;
+                    table[children + direction] = symbol;
+                }
+                internal => pending.push((children + direction, leaves(&internal), internal)),
+            }
+        }
+    }
+    while !table.len().is_multiple_of(4) {
+        table.push(0);
+    }
+    table[0] = (table.len() / 2 - 1) as u8;
+    return Ok(table);
+}
+
+fn invalid(description: &str) -> Error {
+    Error::new(ParsingError).with_description(description.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use spectral::prelude::*;
+
+    use crate::test_util::BuildVec;
+
+    use super::*;
+
+    #[test]
+    fn decompresses_bios_layout() {
+        let data = Vec::new()
+            .build_with_slice(&[0x28, 4, 0, 0]) // 8 bit symbols, 4 bytes
+            .build_with_slice(&[0x01, 0xC0, b'A', b'B']) // root with two leaves
+            .build_with_slice(&0x5000_0000u32.to_le_bytes()); // A B A B
+        assert_that!(decompress_with_len(&data).unwrap()).is_equal_to((b"ABAB".to_vec(), 12));
+    }
+
+    #[test]
+    fn compression_roundtrips() {
+        let data: Vec<u8> = (0..3000u32).map(|i| (i * 7 % 13 + i % 3) as u8).collect();
+        for symbol_bits in [4, 8] {
+            let compressed = compress(&data, symbol_bits).unwrap();
+            assert_that!(compressed.len()).is_less_than(data.len());
+            assert_that!(decompress(&compressed).unwrap()).is_equal_to(&data);
+        }
+    }
+
+    #[test]
+    fn every_byte_value_fits_the_tree_table() {
+        let data: Vec<u8> = (0..4096u32).map(|i| (i.wrapping_mul(2654435761) >> 13) as u8).collect();
+        let compressed = compress(&data, 8).unwrap();
+        assert_that!(decompress(&compressed).unwrap()).is_equal_to(&data);
+    }
+
+    #[test]
+    fn single_symbols_roundtrip() {
+        let compressed = compress(&[7; 9], 8).unwrap();
+        assert_that!(decompress(&compressed).unwrap()).is_equal_to(vec![7; 9]);
+    }
+
+    #[test]
+    fn rejects_other_symbol_sizes() {
+        assert_that!(compress(&[1, 2, 3], 2)).is_err();
+    }
+}
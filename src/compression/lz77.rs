@@ -0,0 +1,165 @@
+//! LZ77, the LZSS variant decompressed by the `LZ77UnComp` functions of the GBA and DS BIOS.
+//!
+//! The data starts with a little-endian word holding the type `0x10` in its low byte and the
+//! decompressed size in the upper three bytes. Blocks of eight items follow, each preceded by a
+//! flag byte whose bits, starting with the highest, tell if the item is a literal byte or a two
+//! byte reference to 3 to 18 earlier bytes up to 4096 bytes back.
+
+use crate::compression::Matcher;
+use crate::Error;
+use crate::ErrorKind::{CreatingError, ParsingError};
+
+/// Type byte identifying LZ77 data.
+pub const TYPE: u8 = 0x10;
+
+/// the largest size that fits into the header.
+pub const MAX_SIZE: usize = 0xFF_FFFF;
+
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 18;
+const WINDOW: usize = 0x1000;
+
+/// returns `true` if `data` starts with an LZ77 header.
+pub fn is_lz77(data: &[u8]) -> bool {
+    data.len() >= 4 && data[0] == TYPE
+}
+
+/// returns the decompressed size stored in the header of `data`.
+pub fn decompressed_size(data: &[u8]) -> Result<usize, Error> {
+    if !is_lz77(data) {
+        return Err(invalid("Invalid LZ77 header."));
+    }
+    Ok(u32::from_le_bytes([data[0], data[1], data[2], data[3]]) as usize >> 8)
+}
+
+/// decompresses LZ77 `data`.
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>, Error> {
+    decompress_with_len(data).map(|(result, _)| result)
+}
+
+/// decompresses LZ77 `data`, also returning the amount of bytes the compressed data spans.
+///
+/// The length doesn't include the padding to a multiple of four bytes most roms store the data
+/// with.
+pub fn decompress_with_len(data: &[u8]) -> Result<(Vec<u8>, usize), Error> {
+    let size = decompressed_size(data)?;
+    let mut position = 4;
+    let mut result = Vec::with_capacity(size);
+    let read = |position: &mut usize| {
+        let value = data.get(*position).copied().ok_or_else(|| invalid("Unexpected end of LZ77 data."));
+        *position += 1;
+        value
+    };
+    while result.len() < size {
+        let flags = read(&mut position)?;
+        for bit in (0..8).rev() {
+            if result.len() >= size {
+                break;
+            }
+            if flags >> bit & 1 == 0 {
+                result.push(read(&mut position)?);
+                continue;
+            }
+            let high = read(&mut position)? as usize;
+            let low = read(&mut position)? as usize;
+            let count = (high >> 4) + MIN_MATCH;
+            let distance = ((high & 0xF) << 8 | low) + 1;
+            if distance > result.len() {
+                return Err(invalid("LZ77 reference points before start of data."));
+            }
+            let start = result.len() - distance;
+            // references may overlap themselves, so copy byte by byte
+            for i in 0..count.min(size - result.len()) {
+                result.push(result[start + i]);
+            }
+        }
+    }
+    Ok((result, position))
+}
+
+/// compresses `data`, padding the result to a multiple of four bytes.
+///
+/// References never point to the previous byte, so the result can also be decompressed straight
+/// into VRAM, which only accepts 16-bit writes.
+///
+/// # Examples
+///
+/// ```
+/// use rom_patcher::compression::lz77;
+/// let data = b"abcabcabcabcabcabc".to_vec();
+/// let compressed = lz77::compress(&data).unwrap();
+/// assert!(compressed.len() < data.len());
+/// assert_eq!(lz77::decompress(&compressed).unwrap(), data);
+/// ```
+pub fn compress(data: &[u8]) -> Result<Vec<u8>, Error> {
+    if data.len() > MAX_SIZE {
+        return Err(Error::new(CreatingError).with_description("Data is too large for LZ77.".to_string()));
+    }
+    let mut result = ((data.len() as u32) << 8 | TYPE as u32).to_le_bytes().to_vec();
+    // VRAM only accepts 16-bit writes, so a byte can't be copied from the one before it
+    let mut matcher = Matcher::new(data, WINDOW, MAX_MATCH).with_min_distance(2);
+    let mut position = 0;
+    while position < data.len() {
+        let flags_index = result.len();
+        result.push(0);
+        for bit in (0..8).rev() {
+            if position >= data.len() {
+                break;
+            }
+            let (distance, count) = matcher.find(position);
+            if count >= MIN_MATCH {
+                result[flags_index] |= 1 << bit;
+                let encoded = (count - MIN_MATCH) << 12 | (distance - 1);
+                result.extend_from_slice(&(encoded as u16).to_be_bytes());
+                position += count;
+            } else {
+                result.push(data[position]);
+                position += 1;
+            }
+        }
+    }
+    while !result.len().is_multiple_of(4) {
+        result.push(0);
+    }
+    Ok(result)
+}
+
+fn invalid(description: &str) -> Error {
+    Error::new(ParsingError).with_description(description.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use spectral::prelude::*;
+
+    use crate::test_util::BuildVec;
+
+    use super::*;
+
+    #[test]
+    fn decompresses_references() {
+        let data = Vec::new()
+            .build_with_slice(&[TYPE, 9, 0, 0]) // decompressed size
+            .build_with_slice(&[0x10]) // flags: 3 literals, 1 reference
+            .build_with_slice(b"ABC")
+            .build_with_slice(&[0x30, 0x02]); // copy 6 bytes from 3 bytes back
+        assert_that!(decompress_with_len(&data).unwrap()).is_equal_to((b"ABCABCABC".to_vec(), 10));
+    }
+
+    #[test]
+    fn compression_roundtrips() {
+        let mut data = Vec::new();
+        for i in 0..5000u32 {
+            data.push((i * i % 251) as u8 & 0xF0);
+        }
+        let compressed = compress(&data).unwrap();
+        assert_that!(compressed.len() % 4).is_equal_to(0);
+        assert_that!(decompress(&compressed).unwrap()).is_equal_to(data);
+    }
+
+    #[test]
+    fn rejects_truncated_data() {
+        let err = decompress(&[TYPE, 9, 0, 0, 0x00, b'A']).unwrap_err();
+        assert_that!(err.to_string()).is_equal_to("ParsingError: Unexpected end of LZ77 data.".to_string());
+    }
+}
@@ -0,0 +1,90 @@
+//! Codecs for compressed data found inside roms.
+
+pub mod huffman;
+pub mod lz77;
+pub mod yay0;
+pub mod yaz0;
+
+/// the most earlier positions [Matcher] compares against per position.
+const MAX_CHAIN: usize = 512;
+
+/// Finds the longest earlier run matching the data at a position, for LZ style encoders.
+///
+/// Earlier positions are chained by a hash of their first three bytes, so only positions that can
+/// match at all are compared.
+pub(crate) struct Matcher<'a> {
+    data: &'a [u8],
+    window: usize,
+    max_len: usize,
+    min_distance: usize,
+    head: Vec<usize>,
+    previous: Vec<usize>,
+    inserted: usize,
+}
+
+impl<'a> Matcher<'a> {
+    /// constructs a matcher over `data` finding runs of up to `max_len` bytes at most `window`
+    /// bytes back.
+    pub(crate) fn new(data: &'a [u8], window: usize, max_len: usize) -> Matcher<'a> {
+        Matcher {
+            data,
+            window,
+            max_len,
+            min_distance: 1,
+            head: vec![usize::MAX; 1 << 16],
+            previous: vec![usize::MAX; data.len()],
+            inserted: 0,
+        }
+    }
+
+    /// modifies the matcher to only find runs at least `min_distance` bytes back.
+    pub(crate) fn with_min_distance(mut self, min_distance: usize) -> Matcher<'a> {
+        self.min_distance = min_distance;
+        return self;
+    }
+
+    fn hash(&self, position: usize) -> usize {
+        let bytes = &self.data[position..position + 3];
+        (bytes[0] as usize) << 8 ^ (bytes[1] as usize) << 4 ^ bytes[2] as usize
+    }
+
+    /// returns the distance and length of the longest run matching `data[position..]`, a length
+    /// below three if there is none.
+    ///
+    /// Positions must be passed in increasing order.
+    pub(crate) fn find(&mut self, position: usize) -> (usize, usize) {
+        while self.inserted < position {
+            if self.inserted + 3 <= self.data.len() {
+                let hash = self.hash(self.inserted);
+                self.previous[self.inserted] = self.head[hash];
+                self.head[hash] = self.inserted;
+            }
+            self.inserted += 1;
+        }
+        let max_len = self.max_len.min(self.data.len() - position);
+        if max_len < 3 {
+            return (0, 0);
+        }
+        let mut best = (0, 0);
+        let mut candidate = self.head[self.hash(position)];
+        for _ in 0..MAX_CHAIN {
+            if candidate == usize::MAX || position - candidate > self.window {
+                break;
+            }
+            let distance = position - candidate;
+            if distance >= self.min_distance {
+                let len = (0..max_len)
+                    .take_while(|i| self.data[candidate + i] == self.data[position + i])
+                    .count();
+                if len > best.1 {
+                    best = (distance, len);
+                    if len == max_len {
+                        break;
+                    }
+                }
+            }
+            candidate = self.previous[candidate];
+        }
+        return best;
+    }
+}
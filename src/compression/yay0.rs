@@ -0,0 +1,216 @@
+//! Yay0, the LZ-style compression used by Nintendo for N64 assets.
+//!
+//! A Yay0 file starts with a 16 byte header: the magic `Yay0`, the decompressed size, the offset of
+//! the link table and the offset of the literal bytes, all big-endian. The mask bits follow the
+//! header. A set mask bit copies one literal byte, a cleared one copies a run from the output
+//! described by the next link.
+
+use crate::compression::Matcher;
+use crate::Error;
+use crate::ErrorKind::{CreatingError, ParsingError};
+
+/// Magic identifying Yay0 data.
+pub const MAGIC: &[u8] = "Yay0".as_bytes();
+
+/// returns `true` if `data` starts with a Yay0 header.
+pub fn is_yay0(data: &[u8]) -> bool {
+    data.len() >= 16 && data.starts_with(MAGIC)
+}
+
+/// returns the decompressed size stored in the header of `data`.
+pub fn decompressed_size(data: &[u8]) -> Result<usize, Error> {
+    if !is_yay0(data) {
+        return Err(invalid("Invalid Yay0 header."));
+    }
+    Ok(read_u32(data, 4)? as usize)
+}
+
+/// the longest run a link can copy.
+const MAX_LINK: usize = 0xFF + 0x12;
+const WINDOW: usize = 0x1000;
+
+/// decompresses Yay0 `data`.
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>, Error> {
+    decompress_with_len(data).map(|(result, _)| result)
+}
+
+/// decompresses Yay0 `data`, also returning the amount of bytes the compressed data spans.
+pub fn decompress_with_len(data: &[u8]) -> Result<(Vec<u8>, usize), Error> {
+    let size = decompressed_size(data)?;
+    let mut link_ptr = read_u32(data, 8)? as usize;
+    let mut chunk_ptr = read_u32(data, 12)? as usize;
+    let mut mask_ptr = 16;
+    let mut mask = 0u32;
+    let mut mask_bits = 0;
+
+    // the size comes from the header, so only part of it is reserved up front
+    let mut result = Vec::with_capacity(size.min(1 << 24));
+    while result.len() < size {
+        if mask_bits == 0 {
+            mask = read_u32(data, mask_ptr)?;
+            mask_ptr += 4;
+            mask_bits = 32;
+        }
+        if mask & 0x8000_0000 != 0 {
+            result.push(read_u8(data, chunk_ptr)?);
+            chunk_ptr += 1;
+        } else {
+            let link = read_u16(data, link_ptr)? as usize;
+            link_ptr += 2;
+            let distance = (link & 0xFFF) + 1;
+            let count = match link >> 12 {
+                // long runs store their length in the literal bytes
+                0 => {
+                    chunk_ptr += 1;
+                    read_u8(data, chunk_ptr - 1)? as usize + 0x12
+                }
+                x => x + 2,
+            };
+            if distance > result.len() {
+                return Err(invalid("Yay0 link points before start of data."));
+            }
+            let start = result.len() - distance;
+            // runs may overlap themselves, so copy byte by byte
+            for i in 0..count.min(size - result.len()) {
+                result.push(result[start + i]);
+            }
+        }
+        mask <<= 1;
+        mask_bits -= 1;
+    }
+    Ok((result, mask_ptr.max(link_ptr).max(chunk_ptr)))
+}
+
+/// compresses `data`.
+///
+/// # Examples
+///
+/// ```
+/// use rom_patcher::compression::yay0;
+/// let data = b"abcabcabcabcabcabc".to_vec();
+/// let compressed = yay0::compress(&data).unwrap();
+/// assert_eq!(yay0::decompress(&compressed).unwrap(), data);
+/// ```
+pub fn compress(data: &[u8]) -> Result<Vec<u8>, Error> {
+    if data.len() > u32::MAX as usize {
+        return Err(Error::new(CreatingError).with_description("Data is too large for Yay0.".to_string()));
+    }
+    let mut masks: Vec<u32> = Vec::new();
+    let mut links: Vec<u8> = Vec::new();
+    let mut chunks: Vec<u8> = Vec::new();
+    let mut matcher = Matcher::new(data, WINDOW, MAX_LINK);
+    let mut position = 0;
+    let mut bit = 0;
+    while position < data.len() {
+        if bit == 0 {
+            masks.push(0);
+            bit = 32;
+        }
+        bit -= 1;
+        let (distance, count) = matcher.find(position);
+        if count < 3 {
+            *masks.last_mut().unwrap() |= 1 << bit;
+            chunks.push(data[position]);
+            position += 1;
+            continue;
+        }
+        if count < 0x12 {
+            links.extend_from_slice(&(((count - 2) << 12 | (distance - 1)) as u16).to_be_bytes());
+        } else {
+            // long runs store their length in the literal bytes
+            links.extend_from_slice(&((distance - 1) as u16).to_be_bytes());
+            chunks.push((count - 0x12) as u8);
+        }
+        position += count;
+    }
+
+    let link_offset = 16 + masks.len() * 4;
+    let chunk_offset = link_offset + links.len();
+    let mut result = MAGIC.to_vec();
+    result.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    result.extend_from_slice(&(link_offset as u32).to_be_bytes());
+    result.extend_from_slice(&(chunk_offset as u32).to_be_bytes());
+    for mask in masks {
+        result.extend_from_slice(&mask.to_be_bytes());
+    }
+    result.extend(links);
+    result.extend(chunks);
+    Ok(result)
+}
+
+fn invalid(description: &str) -> Error {
+    Error::new(ParsingError).with_description(description.to_string())
+}
+
+fn read_u8(data: &[u8], offset: usize) -> Result<u8, Error> {
+    data.get(offset).copied().ok_or_else(|| invalid("Unexpected end of Yay0 data."))
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Result<u16, Error> {
+    Ok(u16::from_be_bytes([read_u8(data, offset)?, read_u8(data, offset + 1)?]))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32, Error> {
+    Ok((read_u16(data, offset)? as u32) << 16 | read_u16(data, offset + 2)? as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use spectral::prelude::*;
+
+    use crate::test_util::BuildVec;
+
+    use super::*;
+
+    #[test]
+    fn decompresses_short_links() {
+        let data = Vec::new()
+            .build_with_slice(MAGIC)
+            .build_with_slice(&[0, 0, 0, 9]) // decompressed size
+            .build_with_slice(&[0, 0, 0, 0x14]) // link table
+            .build_with_slice(&[0, 0, 0, 0x16]) // literals
+            .build_with_slice(&[0xE0, 0, 0, 0]) // mask: 3 literals, 1 link
+            .build_with_slice(&[0x40, 0x02]) // copy 6 bytes from 3 bytes back
+            .build_with_slice(b"ABC");
+        assert_that!(decompress(&data).unwrap()).is_equal_to(b"ABCABCABC".to_vec());
+    }
+
+    #[test]
+    fn decompresses_long_links() {
+        let data = Vec::new()
+            .build_with_slice(MAGIC)
+            .build_with_slice(&[0, 0, 0, 20]) // decompressed size
+            .build_with_slice(&[0, 0, 0, 0x14]) // link table
+            .build_with_slice(&[0, 0, 0, 0x16]) // literals
+            .build_with_slice(&[0x80, 0, 0, 0]) // mask: 1 literal, 1 link
+            .build_with_slice(&[0x00, 0x00]) // long copy from 1 byte back
+            .build_with_slice(&[b'A', 1]); // literal, run length - 0x12
+        assert_that!(decompress(&data).unwrap()).is_equal_to(vec![b'A'; 20]);
+    }
+
+    #[test]
+    fn compression_roundtrips() {
+        let mut data = vec![7; 600];
+        data.extend((0..2000u32).map(|i| (i % 37 + i / 200) as u8));
+        let compressed = compress(&data).unwrap();
+        assert_that!(compressed.len()).is_less_than(data.len());
+        assert_that!(decompress_with_len(&compressed).unwrap()).is_equal_to((data, compressed.len()));
+    }
+
+    #[test]
+    fn rejects_invalid_header() {
+        assert_that!(decompress(b"Yaz0............")).is_err();
+    }
+
+    #[test]
+    fn rejects_truncated_data() {
+        let data = Vec::new()
+            .build_with_slice(MAGIC)
+            .build_with_slice(&[0, 0, 0, 9])
+            .build_with_slice(&[0, 0, 0, 0x14])
+            .build_with_slice(&[0, 0, 0, 0x16])
+            .build_with_slice(&[0xE0, 0, 0, 0]);
+        let err = decompress(&data).unwrap_err();
+        assert_that!(err.to_string()).is_equal_to("ParsingError: Unexpected end of Yay0 data.".to_string());
+    }
+}
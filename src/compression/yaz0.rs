@@ -0,0 +1,168 @@
+//! Yaz0, the LZ-style compression used by Nintendo for GameCube and Wii assets.
+//!
+//! A Yaz0 file starts with a 16 byte header: the magic `Yaz0`, the big-endian decompressed size and
+//! eight reserved bytes. Groups of eight items follow, each preceded by a code byte whose bits,
+//! starting with the highest, tell if the item is a literal byte or a reference of two or three
+//! bytes to earlier output.
+
+use crate::compression::Matcher;
+use crate::Error;
+use crate::ErrorKind::{CreatingError, ParsingError};
+
+/// Magic identifying Yaz0 data.
+pub const MAGIC: &[u8] = "Yaz0".as_bytes();
+
+/// the longest run a reference can copy.
+const MAX_REFERENCE: usize = 0xFF + 0x12;
+const WINDOW: usize = 0x1000;
+
+/// returns `true` if `data` starts with a Yaz0 header.
+pub fn is_yaz0(data: &[u8]) -> bool {
+    data.len() >= 16 && data.starts_with(MAGIC)
+}
+
+/// returns the decompressed size stored in the header of `data`.
+pub fn decompressed_size(data: &[u8]) -> Result<usize, Error> {
+    if !is_yaz0(data) {
+        return Err(invalid("Invalid Yaz0 header."));
+    }
+    Ok(u32::from_be_bytes([data[4], data[5], data[6], data[7]]) as usize)
+}
+
+/// decompresses Yaz0 `data`.
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>, Error> {
+    decompress_with_len(data).map(|(result, _)| result)
+}
+
+/// decompresses Yaz0 `data`, also returning the amount of bytes the compressed data spans.
+pub fn decompress_with_len(data: &[u8]) -> Result<(Vec<u8>, usize), Error> {
+    let size = decompressed_size(data)?;
+    let mut position = 16;
+    let read = |position: &mut usize| {
+        let value = data.get(*position).copied().ok_or_else(|| invalid("Unexpected end of Yaz0 data."));
+        *position += 1;
+        value
+    };
+    // the size comes from the header, so only part of it is reserved up front
+    let mut result = Vec::with_capacity(size.min(1 << 24));
+    while result.len() < size {
+        let code = read(&mut position)?;
+        for bit in (0..8).rev() {
+            if result.len() >= size {
+                break;
+            }
+            if code >> bit & 1 != 0 {
+                result.push(read(&mut position)?);
+                continue;
+            }
+            let high = read(&mut position)? as usize;
+            let low = read(&mut position)? as usize;
+            let distance = ((high & 0xF) << 8 | low) + 1;
+            let count = match high >> 4 {
+                // long runs store their length in a third byte
+                0 => read(&mut position)? as usize + 0x12,
+                x => x + 2,
+            };
+            if distance > result.len() {
+                return Err(invalid("Yaz0 reference points before start of data."));
+            }
+            let start = result.len() - distance;
+            // references may overlap themselves, so copy byte by byte
+            for i in 0..count.min(size - result.len()) {
+                result.push(result[start + i]);
+            }
+        }
+    }
+    Ok((result, position))
+}
+
+/// compresses `data`.
+///
+/// # Examples
+///
+/// ```
+/// use rom_patcher::compression::yaz0;
+/// let data = b"abcabcabcabcabcabc".to_vec();
+/// let compressed = yaz0::compress(&data).unwrap();
+/// assert_eq!(yaz0::decompress(&compressed).unwrap(), data);
+/// ```
+pub fn compress(data: &[u8]) -> Result<Vec<u8>, Error> {
+    if data.len() > u32::MAX as usize {
+        return Err(Error::new(CreatingError).with_description("Data is too large for Yaz0.".to_string()));
+    }
+    let mut result = MAGIC.to_vec();
+    result.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    result.extend_from_slice(&[0; 8]);
+    let mut matcher = Matcher::new(data, WINDOW, MAX_REFERENCE);
+    let mut position = 0;
+    while position < data.len() {
+        let code_index = result.len();
+        result.push(0);
+        for bit in (0..8).rev() {
+            if position >= data.len() {
+                break;
+            }
+            let (distance, count) = matcher.find(position);
+            if count < 3 {
+                result[code_index] |= 1 << bit;
+                result.push(data[position]);
+                position += 1;
+                continue;
+            }
+            let distance = distance - 1;
+            if count < 0x12 {
+                result.extend_from_slice(&[((count - 2) << 4 | distance >> 8) as u8, distance as u8]);
+            } else {
+                result.extend_from_slice(&[(distance >> 8) as u8, distance as u8, (count - 0x12) as u8]);
+            }
+            position += count;
+        }
+    }
+    Ok(result)
+}
+
+fn invalid(description: &str) -> Error {
+    Error::new(ParsingError).with_description(description.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use spectral::prelude::*;
+
+    use crate::test_util::BuildVec;
+
+    use super::*;
+
+    #[test]
+    fn decompresses_short_and_long_references() {
+        let data = Vec::new()
+            .build_with_slice(MAGIC)
+            .build_with_slice(&[0, 0, 0, 27]) // decompressed size
+            .build_with_slice(&[0; 8])
+            .build_with_slice(&[0xC0]) // code: 2 literals, 2 references
+            .build_with_slice(b"AB")
+            .build_with_slice(&[0x40, 0x01]) // copy 6 bytes from 2 bytes back
+            .build_with_slice(&[0x00, 0x01, 0x01]); // copy 19 bytes from 2 bytes back
+        assert_that!(decompress(&data).unwrap()).is_equal_to(b"ABABABABABABABABABABABABABA".to_vec());
+    }
+
+    #[test]
+    fn compression_roundtrips() {
+        let mut data = vec![0; 1000];
+        data.extend((0..3000u32).map(|i| ((i % 41) ^ (i / 300)) as u8));
+        let compressed = compress(&data).unwrap();
+        assert_that!(compressed.len()).is_less_than(data.len());
+        assert_that!(decompress_with_len(&compressed).unwrap()).is_equal_to((data, compressed.len()));
+    }
+
+    #[test]
+    fn rejects_truncated_data() {
+        let data = Vec::new()
+            .build_with_slice(MAGIC)
+            .build_with_slice(&[0, 0, 0, 9])
+            .build_with_slice(&[0; 8])
+            .build_with_slice(&[0xFF, b'A']);
+        let err = decompress(&data).unwrap_err();
+        assert_that!(err.to_string()).is_equal_to("ParsingError: Unexpected end of Yaz0 data.".to_string());
+    }
+}
@@ -0,0 +1,152 @@
+//! Loading options from TOML and YAML config files, so patching settings can be kept in version
+//! control next to the pipeline using them.
+//!
+//! Keys are checked against the options they configure: a key that doesn't exist or holds a
+//! value of the wrong type fails with an error naming the key, e.g. `options.truncate_check`.
+
+use std::fs;
+use std::path::Path;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::apply::ApplyOptions;
+use crate::batch::BatchConfig;
+use crate::create::CreateOptions;
+use crate::Error;
+use crate::ErrorKind::ParsingError;
+use crate::messages::Message;
+
+/// Settings that can be loaded from config files.
+///
+/// # Examples
+///
+/// ```
+/// use rom_patcher::apply::ApplyOptions;
+/// use rom_patcher::config::ConfigFile;
+///
+/// let options = ApplyOptions::from_toml_str("gap_fill = 255").unwrap();
+/// assert_eq!(options.gap_fill(), 0xFF);
+/// assert!(ApplyOptions::from_toml_str("gap_fil = 255").is_err());
+/// ```
+pub trait ConfigFile: Serialize + DeserializeOwned {
+    /// parses the TOML config file at `path`.
+    fn from_toml(path: &Path) -> Result<Self, Error> {
+        let (text, name) = (read(path)?, path.display().to_string());
+        return parse(toml::from_str(&text).map_err(|e| invalid(&name, e)), &name);
+    }
+
+    /// parses the YAML config file at `path`.
+    fn from_yaml(path: &Path) -> Result<Self, Error> {
+        let (text, name) = (read(path)?, path.display().to_string());
+        return parse(serde_yaml::from_str(&text).map_err(|e| invalid(&name, e)), &name);
+    }
+
+    /// parses `text` as a TOML config.
+    fn from_toml_str(text: &str) -> Result<Self, Error> {
+        parse(toml::from_str(text).map_err(|e| invalid("config", e)), "config")
+    }
+
+    /// parses `text` as a YAML config.
+    fn from_yaml_str(text: &str) -> Result<Self, Error> {
+        parse(serde_yaml::from_str(text).map_err(|e| invalid("config", e)), "config")
+    }
+}
+
+impl ConfigFile for ApplyOptions {}
+
+impl ConfigFile for CreateOptions {}
+
+impl ConfigFile for BatchConfig {}
+
+fn read(path: &Path) -> Result<String, Error> {
+    fs::read_to_string(path).map_err(|e| Error::new(ParsingError)
+        .with_message(Message::UnableToRead { path: path.to_path_buf() })
+        .with_source(Box::new(e)))
+}
+
+fn invalid<E: std::error::Error + Send + Sync + 'static>(name: &str, e: E) -> Error {
+    Error::new(ParsingError)
+        .with_description(format!("Invalid {}.", name))
+        .with_source(Box::new(e))
+}
+
+/// deserializes the parsed config `value`, read from `name`, and checks it for unknown keys.
+fn parse<T: ConfigFile>(value: Result<Value, Error>, name: &str) -> Result<T, Error> {
+    let value = value?;
+    let result: T = serde_path_to_error::deserialize(&value).map_err(|e| {
+        let key = e.path().to_string();
+        Error::new(ParsingError)
+            .with_description(format!("Invalid value for key {} in {}.", key, name))
+            .with_source(Box::new(e.into_inner()))
+    })?;
+    // values that are known round trip, so every key missing after serializing is unknown
+    let known = serde_json::to_value(&result).map_err(|e| invalid(name, e))?;
+    if let Some(key) = unknown_key(&value, &known, "") {
+        return Err(Error::new(ParsingError).with_description(format!("Unknown key {} in {}.", key, name)));
+    }
+    return Ok(result);
+}
+
+/// returns the path of the first key of the object `value` that isn't in `known`.
+fn unknown_key(value: &Value, known: &Value, prefix: &str) -> Option<String> {
+    let (Value::Object(value), Value::Object(known)) = (value, known) else {
+        return None;
+    };
+    for (key, value) in value {
+        let path = if prefix.is_empty() { key.clone() } else { format!("{}.{}", prefix, key) };
+        match known.get(key) {
+            None => return Some(path),
+            Some(known) => {
+                if let Some(path) = unknown_key(value, known, &path) {
+                    return Some(path);
+                }
+            }
+        }
+    }
+    return None;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use spectral::prelude::*;
+
+    use crate::apply::TruncateCheck;
+
+    use super::*;
+
+    #[test]
+    fn loads_toml_and_yaml() {
+        let toml = BatchConfig::from_toml_str(
+            "source = \"game.sfc\"\npatch_dir = \"patches\"\noutput_dir = \"out\"\n[options]\ngap_fill = 255\n").unwrap();
+        let yaml = BatchConfig::from_yaml_str(
+            "source: game.sfc\npatch_dir: patches\noutput_dir: out\noptions:\n  gap_fill: 255\n").unwrap();
+        assert_that!(toml.source).is_equal_to(PathBuf::from("game.sfc"));
+        assert_that!(toml.options.gap_fill()).is_equal_to(0xFF);
+        assert_that!(toml).is_equal_to(yaml);
+        assert_that!(ApplyOptions::from_yaml_str("truncate_check: warn").unwrap().truncate_check())
+            .is_equal_to(TruncateCheck::Warn);
+    }
+
+    #[test]
+    fn naming_templates_are_checked_when_loaded() {
+        let batch = "source: game.sfc\npatch_dir: patches\noutput_dir: out\n";
+        let config = BatchConfig::from_yaml_str(&format!("{}naming: \"{{patch_stem}}.{{ext}}\"\n", batch)).unwrap();
+        assert_that!(config.naming.map(|x| x.to_string())).is_equal_to(Some("{patch_stem}.{ext}".to_string()));
+        let error = BatchConfig::from_yaml_str(&format!("{}naming: \"{{title}}\"\n", batch)).unwrap_err();
+        assert_that!(error.to_string()).contains("Invalid value for key naming");
+    }
+
+    #[test]
+    fn errors_name_the_key() {
+        let batch = "source: game.sfc\npatch_dir: patches\noutput_dir: out\n";
+        let error = BatchConfig::from_yaml_str(&format!("{}options:\n  truncate_check: sometimes\n", batch)).unwrap_err();
+        assert_that!(error.to_string()).contains("options.truncate_check");
+        let error = BatchConfig::from_yaml_str(&format!("{}options:\n  gap_fil: 255\n", batch)).unwrap_err();
+        assert_that!(error.to_string()).contains("options.gap_fil");
+        assert_that!(CreateOptions::from_toml_str("exclude_header = 1")).is_err();
+    }
+}
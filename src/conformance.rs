@@ -0,0 +1,183 @@
+//! Golden output conformance checks of the format implementations.
+//!
+//! A corpus is a directory with one subdirectory per case, laid out like
+//! [Fixture::write_to_dir]: the rom `source.bin`, the expected output `target.bin` and one or more
+//! patches named `patch.<extension>`. Every patch is detected, applied and compared against the
+//! expected output, then written back and applied again to check that writing preserves it.
+//!
+//! Downstream packagers can run [run_builtin] to check the crate on their platform, or [run_dir]
+//! on a corpus of real patches they may not redistribute.
+//!
+//! This module is only compiled for tests or with the `testkit` feature enabled.
+
+use std::fs;
+use std::path::Path;
+
+use crate::Error;
+use crate::ErrorKind::ParsingError;
+use crate::messages::Message;
+use crate::registry::{detect_format, read_any_from_slice};
+use crate::testkit::{corpus, Fixture, FixtureOptions};
+
+/// The outcome of checking one patch of a case.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaseResult {
+    /// name of the case, the name of its directory for corpora read from disk.
+    pub case: String,
+    /// file name of the patch.
+    pub patch: String,
+    /// the detected format, [None] if it wasn't detected.
+    pub format: Option<&'static str>,
+    /// why the patch failed, [None] if it passed.
+    pub failure: Option<String>,
+}
+
+/// The outcomes of a conformance run.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConformanceReport {
+    /// one result per checked patch, ordered by case and patch.
+    pub results: Vec<CaseResult>,
+}
+
+impl ConformanceReport {
+    /// returns `true` if every patch passed.
+    pub fn is_success(&self) -> bool {
+        self.results.iter().all(|x| x.failure.is_none())
+    }
+
+    /// returns the results of the patches that failed.
+    pub fn failures(&self) -> Vec<&CaseResult> {
+        self.results.iter().filter(|x| x.failure.is_some()).collect()
+    }
+}
+
+/// checks every patch in `patches`, pairs of file name and content, against turning `source` into
+/// `target`.
+pub fn run_case(case: &str, source: &[u8], target: &[u8], patches: &[(String, Vec<u8>)]) -> Vec<CaseResult> {
+    patches.iter()
+        .map(|(name, data)| CaseResult {
+            case: case.to_string(),
+            patch: name.clone(),
+            format: detect_format(data).map(|x| x.name),
+            failure: check(name, data, source, target).err(),
+        })
+        .collect()
+}
+
+fn check(name: &str, data: &[u8], source: &[u8], target: &[u8]) -> Result<(), String> {
+    let format = detect_format(data).ok_or("Format was not detected.")?;
+    let extension = name.rsplit_once('.').map(|(_, x)| x.to_ascii_lowercase()).unwrap_or_default();
+    if !format.extensions.contains(&extension.as_str()) {
+        return Err(format!("Detected {}, which doesn't use the extension {}.", format.name, extension));
+    }
+    let patch = read_any_from_slice(data).map_err(|e| e.to_string())?;
+    let output = patch.apply_to_vec(source).map_err(|e| e.to_string())?;
+    compare("Output", &output, target)?;
+
+    let mut written = Vec::new();
+    patch.write_to(&mut written).map_err(|e| format!("Unable to write patch: {}", e))?;
+    let rewritten = read_any_from_slice(&written).map_err(|e| format!("Written patch is unreadable: {}", e))?;
+    let output = rewritten.apply_to_vec(source).map_err(|e| e.to_string())?;
+    return compare("Output of the written patch", &output, target);
+}
+
+fn compare(name: &str, actual: &[u8], expected: &[u8]) -> Result<(), String> {
+    if actual.len() != expected.len() {
+        return Err(format!("{} is {} bytes long, expected {}.", name, actual.len(), expected.len()));
+    }
+    match actual.iter().zip(expected).position(|(a, b)| a != b) {
+        Some(offset) => Err(format!("{} differs first at offset {:#X}.", name, offset)),
+        None => Ok(()),
+    }
+}
+
+/// checks the corpus in `dir`, see the [module documentation](self).
+pub fn run_dir(dir: &Path) -> Result<ConformanceReport, Error> {
+    let read_error = |path: &Path, e: std::io::Error| Error::new(ParsingError)
+        .with_message(Message::UnableToRead { path: path.to_path_buf() })
+        .with_source(Box::new(e));
+    let mut cases: Vec<_> = fs::read_dir(dir)
+        .map_err(|e| read_error(dir, e))?
+        .filter_map(|x| x.ok())
+        .map(|x| x.path())
+        .filter(|x| x.is_dir())
+        .collect();
+    cases.sort();
+
+    let mut report = ConformanceReport::default();
+    for case in cases {
+        let name = case.file_name().unwrap_or_default().to_string_lossy().to_string();
+        let source = fs::read(case.join("source.bin")).map_err(|e| read_error(&case.join("source.bin"), e))?;
+        let target = fs::read(case.join("target.bin")).map_err(|e| read_error(&case.join("target.bin"), e))?;
+        let mut patches = Vec::new();
+        for entry in fs::read_dir(&case).map_err(|e| read_error(&case, e))?.filter_map(|x| x.ok()) {
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            if file_name.starts_with("patch.") {
+                patches.push((file_name, fs::read(entry.path()).map_err(|e| read_error(&entry.path(), e))?));
+            }
+        }
+        patches.sort();
+        report.results.extend(run_case(&name, &source, &target, &patches));
+    }
+    return Ok(report);
+}
+
+/// checks every format against fixtures generated from `seeds`, both with and without truncation.
+pub fn run_builtin(seeds: impl IntoIterator<Item=u64> + Clone) -> ConformanceReport {
+    let mut report = ConformanceReport::default();
+    for truncate in [false, true] {
+        let options = FixtureOptions { truncate, ..FixtureOptions::default() };
+        for fixture in corpus(seeds.clone(), &options) {
+            let name = format!("seed-{}{}", fixture.seed, if truncate { "-truncated" } else { "" });
+            report.results.extend(run_fixture(&name, &fixture));
+        }
+    }
+    return report;
+}
+
+fn run_fixture(name: &str, fixture: &Fixture) -> Vec<CaseResult> {
+    let patches: Vec<(String, Vec<u8>)> = fixture.encoded_patches()
+        .into_iter()
+        .map(|(extension, data)| (format!("patch.{}", extension), data))
+        .collect();
+    run_case(name, &fixture.source, &fixture.target, &patches)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use spectral::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn builtin_corpus_conforms() {
+        let report = run_builtin(0..4);
+        assert_that!(report.failures()).is_equal_to(Vec::<&CaseResult>::new());
+        let formats: Vec<Option<&str>> = report.results.iter().map(|x| x.format).collect();
+        assert_that!(formats).contains(Some("ips"));
+        assert_that!(formats).contains(Some("pmsr"));
+        assert_that!(formats).contains(Some("ups"));
+        assert_that!(formats).contains(Some("bps"));
+        assert_that!(formats).contains(Some("ppf"));
+    }
+
+    #[test]
+    fn corpus_directories_are_checked() {
+        let dir = env::temp_dir().join(format!("rom-patcher-conformance-{}", std::process::id()));
+        Fixture::generate(3).write_to_dir(&dir.join("good")).unwrap();
+        let broken = Fixture::generate(4);
+        broken.write_to_dir(&dir.join("broken")).unwrap();
+        let mut target = broken.target.clone();
+        target[0x10] ^= 0xFF;
+        fs::write(dir.join("broken").join("target.bin"), target).unwrap();
+
+        let report = run_dir(&dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        let failed: Vec<&str> = report.failures().iter().map(|x| x.case.as_str()).collect();
+        assert_that!(failed.iter().all(|x| *x == "broken")).is_true();
+        assert_that!(failed.is_empty()).is_false();
+        assert_that!(report.results.iter().any(|x| x.case == "good")).is_true();
+    }
+}
@@ -0,0 +1,457 @@
+//! Console profiles describing how a console maps its rom into the address space of its CPU and
+//! which named regions its rom consists of.
+
+use std::ops::Range;
+
+use crate::header::copier_header_len;
+
+/// A named, contiguous part of a rom, e.g. its header or a bank.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Region {
+    /// human readable name of the region, e.g. `PRG bank 3`.
+    pub name: String,
+    /// rom offsets the region covers.
+    pub range: Range<u64>,
+}
+
+impl Region {
+    fn new(name: impl Into<String>, range: Range<u64>) -> Region {
+        Region { name: name.into(), range }
+    }
+}
+
+/// The rom mapping of a console.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum Console {
+    /// address space and rom are the same, e.g. for addresses that are already rom offsets.
+    Flat,
+    /// Super Nintendo with a LoROM cartridge.
+    SnesLoRom,
+    /// Super Nintendo with a HiROM cartridge.
+    SnesHiRom,
+    /// Game Boy Advance with the cartridge at `0x08000000` and its mirrors.
+    GameBoyAdvance,
+    /// Nintendo 64 with the cartridge domain at `0x10000000`, also reachable through KSEG1.
+    Nintendo64,
+    /// Sega Genesis / Mega Drive with the cartridge at `0x000000`.
+    Genesis,
+    /// Nintendo Entertainment System rom in the iNES format.
+    Nes,
+}
+
+impl Console {
+    /// Every console, in the order they are declared.
+    pub const ALL: [Console; 7] = [
+        Console::Flat,
+        Console::SnesLoRom,
+        Console::SnesHiRom,
+        Console::GameBoyAdvance,
+        Console::Nintendo64,
+        Console::Genesis,
+        Console::Nes,
+    ];
+
+    /// returns the file extensions roms of the console are stored with, the canonical one first,
+    /// without a leading dot.
+    ///
+    /// Nintendo 64 roms are canonically stored big-endian as `.z64`, other byte orders use `.v64`
+    /// and `.n64`. [Console::Flat] has no extensions.
+    pub fn extensions(&self) -> &'static [&'static str] {
+        match self {
+            Console::Flat => &[],
+            Console::SnesLoRom | Console::SnesHiRom => &["sfc", "smc"],
+            Console::GameBoyAdvance => &["gba", "agb"],
+            Console::Nintendo64 => &["z64", "v64", "n64"],
+            Console::Genesis => &["md", "gen", "smd"],
+            Console::Nes => &["nes"],
+        }
+    }
+
+    /// returns the canonical file extension of roms of the console, see [Console::extensions].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rom_patcher::console::Console;
+    /// assert_eq!(Console::SnesHiRom.extension(), Some("sfc"));
+    /// assert_eq!(Console::Flat.extension(), None);
+    /// ```
+    pub fn extension(&self) -> Option<&'static str> {
+        self.extensions().first().copied()
+    }
+
+    /// returns the consoles whose roms are stored with `extension`, ignoring case and a leading
+    /// dot.
+    ///
+    /// The extension doesn't tell LoROM and HiROM cartridges apart, so SNES extensions return both,
+    /// [Console::detect] decides between them.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rom_patcher::console::Console;
+    /// assert_eq!(Console::for_extension(".GBA"), vec![Console::GameBoyAdvance]);
+    /// assert_eq!(Console::for_extension("smc"), vec![Console::SnesLoRom, Console::SnesHiRom]);
+    /// ```
+    pub fn for_extension(extension: &str) -> Vec<Console> {
+        let extension = extension.trim_start_matches('.');
+        Console::ALL.into_iter()
+            .filter(|x| x.extensions().iter().any(|e| e.eq_ignore_ascii_case(extension)))
+            .collect()
+    }
+
+    /// detects the console `rom` was dumped from by its headers, or [None] if it isn't recognized.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rom_patcher::console::Console;
+    /// let mut rom = vec![0; 0x6010];
+    /// rom[..4].copy_from_slice(b"NES\x1A");
+    /// assert_eq!(Console::detect(&rom), Some(Console::Nes));
+    /// ```
+    pub fn detect(rom: &[u8]) -> Option<Console> {
+        if rom.starts_with(b"NES\x1A") {
+            return Some(Console::Nes);
+        }
+        if rom.starts_with(&[0x80, 0x37, 0x12, 0x40]) {
+            return Some(Console::Nintendo64);
+        }
+        if rom.get(0x100..0x104) == Some(b"SEGA") {
+            return Some(Console::Genesis);
+        }
+        // the fixed value every GBA header carries
+        if rom.len() >= 0xC0 && rom[0xB2] == 0x96 {
+            return Some(Console::GameBoyAdvance);
+        }
+        let body = &rom[copier_header_len(rom.len() as u64) as usize..];
+        if has_snes_header(body, 0x7FC0) {
+            return Some(Console::SnesLoRom);
+        }
+        if has_snes_header(body, 0xFFC0) {
+            return Some(Console::SnesHiRom);
+        }
+        return None;
+    }
+
+    /// recomputes the checksum the header of `rom` records for its content, returning `false` if
+    /// the console has none or `rom` is too short to hold it.
+    ///
+    /// Patches that change code or data without updating the checksum leave roms that some
+    /// emulators and flash carts warn about or refuse. SNES roms get their checksum and its
+    /// complement, GBA roms the complement of their header and Genesis roms the checksum of
+    /// everything past the header. Nintendo 64 checksums depend on the boot code of the
+    /// cartridge and aren't fixed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rom_patcher::console::Console;
+    /// let mut rom = vec![0; 0xC0];
+    /// rom[0xB2] = 0x96;
+    /// assert!(Console::GameBoyAdvance.fix_checksum(&mut rom));
+    /// assert_eq!(rom[0xBD], 0x51);
+    /// ```
+    pub fn fix_checksum(&self, rom: &mut [u8]) -> bool {
+        match self {
+            Console::SnesLoRom | Console::SnesHiRom => {
+                let header_len = copier_header_len(rom.len() as u64) as usize;
+                let body = &mut rom[header_len..];
+                let offset = if *self == Console::SnesLoRom { 0x7FC0 } else { 0xFFC0 };
+                if body.len() < offset + 0x20 {
+                    return false;
+                }
+                // the pair always sums to 0x1FE, so it counts the same before and after fixing
+                body[offset + 0x1C..offset + 0x20].copy_from_slice(&[0xFF, 0xFF, 0, 0]);
+                let checksum = snes_checksum(body);
+                body[offset + 0x1C..offset + 0x1E].copy_from_slice(&(!checksum).to_le_bytes());
+                body[offset + 0x1E..offset + 0x20].copy_from_slice(&checksum.to_le_bytes());
+                return true;
+            }
+            Console::GameBoyAdvance => {
+                if rom.len() < 0xC0 {
+                    return false;
+                }
+                let sum = rom[0xA0..0xBD].iter().fold(0u8, |a, b| a.wrapping_add(*b));
+                rom[0xBD] = 0u8.wrapping_sub(sum).wrapping_sub(0x19);
+                return true;
+            }
+            Console::Genesis => {
+                if rom.len() < 0x200 {
+                    return false;
+                }
+                let checksum = rom[0x200..].chunks(2)
+                    .map(|x| u16::from_be_bytes([x[0], *x.get(1).unwrap_or(&0)]))
+                    .fold(0u16, |a, b| a.wrapping_add(b));
+                rom[0x18E..0x190].copy_from_slice(&checksum.to_be_bytes());
+                return true;
+            }
+            Console::Flat | Console::Nintendo64 | Console::Nes => false,
+        }
+    }
+
+    /// returns the named regions of `rom`, ordered by offset.
+    ///
+    /// Regions never overlap, but they don't need to cover the whole rom. [Console::Flat] has no
+    /// regions at all.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rom_patcher::console::Console;
+    /// let mut rom = vec![0; 16 + 0x8000 + 0x2000];
+    /// rom[..6].copy_from_slice(&[b'N', b'E', b'S', 0x1A, 2, 1]);
+    /// let regions = Console::Nes.regions(&rom);
+    /// assert_eq!(regions.last().unwrap().name, "CHR bank 0");
+    /// ```
+    pub fn regions(&self, rom: &[u8]) -> Vec<Region> {
+        let len = rom.len() as u64;
+        let mut result = Vec::new();
+        match self {
+            Console::Flat => {}
+            Console::SnesLoRom | Console::SnesHiRom => {
+                let header_len = copier_header_len(len);
+                if header_len > 0 {
+                    result.push(Region::new("copier header", 0..header_len));
+                }
+                let bank_len: u64 = if *self == Console::SnesLoRom { 0x8000 } else { 0x10000 };
+                let internal_header = header_len + bank_len - 0x40;
+                result.push(Region::new("bank 0", header_len..internal_header));
+                result.push(Region::new("internal header", internal_header..internal_header + 0x20));
+                result.push(Region::new("vectors", internal_header + 0x20..header_len + bank_len));
+                push_banks(&mut result, "bank", header_len + bank_len..len, bank_len, 1);
+            }
+            Console::GameBoyAdvance => {
+                result.push(Region::new("entry point", 0..4));
+                result.push(Region::new("header", 4..0xC0));
+                result.push(Region::new("program", 0xC0..len));
+            }
+            Console::Nintendo64 => {
+                result.push(Region::new("header", 0..0x40));
+                result.push(Region::new("boot code", 0x40..0x1000));
+                result.push(Region::new("program", 0x1000..len));
+            }
+            Console::Genesis => {
+                result.push(Region::new("vector table", 0..0x100));
+                result.push(Region::new("header", 0x100..0x1B0));
+                result.push(Region::new("header save RAM info", 0x1B0..0x1BC));
+                result.push(Region::new("header", 0x1BC..0x200));
+                result.push(Region::new("program", 0x200..len));
+            }
+            Console::Nes => {
+                result.push(Region::new("header magic", 0..4));
+                result.push(Region::new("header PRG size", 4..5));
+                result.push(Region::new("header CHR size", 5..6));
+                result.push(Region::new("header mapper byte", 6..8));
+                result.push(Region::new("header", 8..16));
+                let mut offset = 16;
+                // a trainer is flagged by bit 2 of byte 6
+                if rom.get(6).is_some_and(|x| x & 0x04 != 0) {
+                    result.push(Region::new("trainer", 16..16 + 512));
+                    offset += 512;
+                }
+                let prg_len = rom.get(4).map_or(0, |x| *x as u64) * 0x4000;
+                let chr_len = rom.get(5).map_or(0, |x| *x as u64) * 0x2000;
+                push_banks(&mut result, "PRG bank", offset..offset + prg_len, 0x4000, 0);
+                push_banks(&mut result, "CHR bank", offset + prg_len..offset + prg_len + chr_len, 0x2000, 0);
+            }
+        }
+        result.retain(|x| x.range.start < x.range.end.min(len));
+        for region in &mut result {
+            region.range.end = region.range.end.min(len);
+        }
+        return result;
+    }
+
+    /// returns the rom offset `address` maps to, or [None] if `address` is not rom, e.g. RAM or
+    /// IO registers.
+    ///
+    /// [Console::Nes] maps its rom through mapper specific bank switching, so no address maps to a
+    /// fixed rom offset.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rom_patcher::console::Console;
+    /// assert_eq!(Console::SnesLoRom.rom_offset(0x01_8000), Some(0x8000));
+    /// assert_eq!(Console::SnesLoRom.rom_offset(0x7E_0DBF), None);
+    /// ```
+    pub fn rom_offset(&self, address: u32) -> Option<u32> {
+        let bank = address >> 16;
+        let low = address & 0xFFFF;
+        match self {
+            Console::Flat => Some(address),
+            Console::SnesLoRom => match bank {
+                0x7E | 0x7F => None,
+                0x00..=0xFF if low >= 0x8000 && address <= 0xFF_FFFF => {
+                    Some((bank & 0x7F) * 0x8000 + (low - 0x8000))
+                }
+                _ => None,
+            },
+            Console::SnesHiRom => match bank {
+                0x7E | 0x7F => None,
+                0x40..=0x7D | 0xC0..=0xFF => Some(address & 0x3F_FFFF),
+                0x00..=0x3F | 0x80..=0xBF if low >= 0x8000 => Some(address & 0x3F_FFFF),
+                _ => None,
+            },
+            Console::GameBoyAdvance => match address {
+                0x0800_0000..=0x0DFF_FFFF => Some(address & 0x01FF_FFFF),
+                _ => None,
+            },
+            Console::Nintendo64 => match address {
+                0x1000_0000..=0x1FBF_FFFF => Some(address - 0x1000_0000),
+                0xB000_0000..=0xBFBF_FFFF => Some(address - 0xB000_0000),
+                _ => None,
+            },
+            Console::Genesis => match address {
+                0x00_0000..=0x3F_FFFF => Some(address),
+                _ => None,
+            },
+            Console::Nes => None,
+        }
+    }
+}
+
+/// returns the checksum of a SNES rom `body`, the sum of its bytes with the part past the largest
+/// power of two mirrored until it fills another one, as the console maps it.
+fn snes_checksum(body: &[u8]) -> u16 {
+    let sum = |x: &[u8]| x.iter().fold(0u16, |a, b| a.wrapping_add(*b as u16));
+    if body.is_empty() || body.len().is_power_of_two() {
+        return sum(body);
+    }
+    let base = 1 << body.len().ilog2();
+    let rest = &body[base..];
+    let repeats = (base / rest.len()) as u16;
+    return sum(&body[..base]).wrapping_add(sum(rest).wrapping_mul(repeats));
+}
+
+/// returns `true` if `body` has a SNES internal header at `offset`, whose checksum and its
+/// complement add up to `0xFFFF`.
+fn has_snes_header(body: &[u8], offset: usize) -> bool {
+    let Some(header) = body.get(offset + 0x1C..offset + 0x20) else {
+        return false;
+    };
+    let complement = u16::from_le_bytes([header[0], header[1]]);
+    let checksum = u16::from_le_bytes([header[2], header[3]]);
+    return complement ^ checksum == 0xFFFF;
+}
+
+/// pushes a region named `name` followed by its number for every `bank_len` bytes of `range`.
+fn push_banks(regions: &mut Vec<Region>, name: &str, range: Range<u64>, bank_len: u64, first: u64) {
+    let mut offset = range.start;
+    let mut bank = first;
+    while offset < range.end {
+        let end = (offset + bank_len).min(range.end);
+        regions.push(Region::new(format!("{} {}", name, bank), offset..end));
+        offset = end;
+        bank += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use spectral::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn lorom_maps_upper_half_of_each_bank() {
+        assert_that!(Console::SnesLoRom.rom_offset(0x00_8000)).is_equal_to(Some(0));
+        assert_that!(Console::SnesLoRom.rom_offset(0x80_FFFF)).is_equal_to(Some(0x7FFF));
+        assert_that!(Console::SnesLoRom.rom_offset(0x02_9234)).is_equal_to(Some(0x1_1234));
+        assert_that!(Console::SnesLoRom.rom_offset(0x00_2000)).is_none();
+        assert_that!(Console::SnesLoRom.rom_offset(0x7F_8000)).is_none();
+    }
+
+    #[test]
+    fn hirom_maps_whole_banks() {
+        assert_that!(Console::SnesHiRom.rom_offset(0xC1_2345)).is_equal_to(Some(0x1_2345));
+        assert_that!(Console::SnesHiRom.rom_offset(0x01_8000)).is_equal_to(Some(0x1_8000));
+        assert_that!(Console::SnesHiRom.rom_offset(0x01_6000)).is_none();
+        assert_that!(Console::SnesHiRom.rom_offset(0x7E_0000)).is_none();
+    }
+
+    #[test]
+    fn gba_maps_cartridge_and_mirrors() {
+        assert_that!(Console::GameBoyAdvance.rom_offset(0x0800_00C0)).is_equal_to(Some(0xC0));
+        assert_that!(Console::GameBoyAdvance.rom_offset(0x0A00_00C0)).is_equal_to(Some(0xC0));
+        assert_that!(Console::GameBoyAdvance.rom_offset(0x0200_0000)).is_none();
+    }
+
+    #[test]
+    fn detects_consoles_by_their_headers() {
+        let mut genesis = vec![0; 0x400];
+        genesis[0x100..0x104].copy_from_slice(b"SEGA");
+        assert_that!(Console::detect(&genesis)).is_equal_to(Some(Console::Genesis));
+
+        let mut lorom = vec![0; 0x8200];
+        lorom[0x200 + 0x7FDC..0x200 + 0x7FE0].copy_from_slice(&[0x34, 0x12, 0xCB, 0xED]);
+        assert_that!(Console::detect(&lorom)).is_equal_to(Some(Console::SnesLoRom));
+
+        assert_that!(Console::detect(&[1, 2, 3])).is_none();
+    }
+
+    #[test]
+    fn nes_regions_follow_the_ines_header() {
+        let mut rom = vec![0; 16 + 512 + 2 * 0x4000 + 0x2000];
+        rom[..7].copy_from_slice(&[b'N', b'E', b'S', 0x1A, 2, 1, 0x04]);
+        let regions = Console::Nes.regions(&rom);
+        let names: Vec<&str> = regions.iter().map(|x| x.name.as_str()).collect();
+        assert_that!(names).is_equal_to(vec![
+            "header magic", "header PRG size", "header CHR size", "header mapper byte", "header",
+            "trainer", "PRG bank 0", "PRG bank 1", "CHR bank 0",
+        ]);
+        assert_that!(regions[7].range.clone()).is_equal_to(16 + 512 + 0x4000..16 + 512 + 0x8000);
+    }
+
+    #[test]
+    fn regions_are_clamped_to_the_rom() {
+        let regions = Console::GameBoyAdvance.regions(&[0; 0x10]);
+        assert_that!(regions.len()).is_equal_to(2);
+        assert_that!(regions[1].range.clone()).is_equal_to(4..0x10);
+    }
+
+    #[test]
+    fn extensions_map_to_consoles_and_back() {
+        for console in Console::ALL {
+            for extension in console.extensions() {
+                assert_that!(Console::for_extension(extension)).contains(console);
+            }
+        }
+        assert_that!(Console::Nintendo64.extension()).is_equal_to(Some("z64"));
+        assert_that!(Console::for_extension("bin")).is_equal_to(Vec::new());
+    }
+
+    #[test]
+    fn snes_checksums_cover_mirrored_roms() {
+        let mut rom = vec![1; 0x30000];
+        assert_that!(Console::SnesLoRom.fix_checksum(&mut rom)).is_true();
+        // 0x20000 bytes plus twice 0x10000 bytes, with the header pair summing to 0x1FE
+        let expected = ((0x20000 - 4 + 2 * 0x10000) as u16).wrapping_add(0x1FE);
+        assert_that!(u16::from_le_bytes([rom[0x7FDE], rom[0x7FDF]])).is_equal_to(expected);
+        assert_that!(Console::detect(&rom)).is_equal_to(Some(Console::SnesLoRom));
+
+        let mut headered = vec![0; 0x200];
+        headered.extend(vec![1; 0x30000]);
+        Console::SnesLoRom.fix_checksum(&mut headered);
+        assert_that!(headered[0x200..].to_vec()).is_equal_to(rom);
+    }
+
+    #[test]
+    fn genesis_checksums_skip_the_header() {
+        let mut rom = vec![0; 0x204];
+        rom[0x100..0x104].copy_from_slice(b"SEGA");
+        rom[0x200..].copy_from_slice(&[0x12, 0x34, 0x00, 0x01]);
+        assert_that!(Console::Genesis.fix_checksum(&mut rom)).is_true();
+        assert_that!(rom[0x18E..0x190].to_vec()).is_equal_to(vec![0x12, 0x35]);
+        assert_that!(Console::Nintendo64.fix_checksum(&mut rom)).is_false();
+    }
+
+    #[test]
+    fn n64_maps_cartridge_domain() {
+        assert_that!(Console::Nintendo64.rom_offset(0x1000_1000)).is_equal_to(Some(0x1000));
+        assert_that!(Console::Nintendo64.rom_offset(0xB000_1000)).is_equal_to(Some(0x1000));
+        assert_that!(Console::Nintendo64.rom_offset(0x8033_B21E)).is_none();
+    }
+}
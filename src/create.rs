@@ -0,0 +1,1319 @@
+//! Creating patches from a source and a target rom.
+//!
+//! The comparison of source and target is done once into a [Diff], which every format then encodes
+//! on its own. [create_all] uses this to emit patches of several formats from a single pass over
+//! the roms.
+
+use std::borrow::Cow;
+use std::io::{ErrorKind, Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::sync::mpsc::Sender;
+use std::time::{Duration, Instant};
+
+use crate::bps::BPSPatch;
+use crate::diagnostics::Diagnostic;
+use crate::Error;
+use crate::ErrorKind::{Cancelled, CreatingError};
+use crate::header::{copier_header_len, split_header};
+use crate::io_util::U64Extensions;
+use crate::ips::{IPSOptimization, IPSPatch};
+use crate::messages::Message;
+use crate::metrics::measure;
+use crate::patch::Patch;
+use crate::pmsr::PMSRPatch;
+use crate::ups::UPSPatch;
+use crate::progress::{CancellationToken, Progress, Stage};
+use crate::verbatim::find_verbatim;
+
+/// How a [Diff] was computed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum DiffStrategy {
+    /// byte by byte, so the regions contain only differing bytes.
+    Exact,
+    /// chunk by chunk after a limit was exceeded, so regions are made of whole chunks of
+    /// `chunk_len` bytes that contain differing bytes.
+    Chunked {
+        /// length of the compared chunks.
+        chunk_len: u64,
+    },
+}
+
+/// The differences between a source and a target rom.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diff {
+    /// length of the source rom.
+    pub source_len: u64,
+    /// length of the target rom.
+    pub target_len: u64,
+    /// ranges of the target that differ from the source, ordered and non-adjacent. Bytes of the
+    /// target past the end of the source always differ. With [DiffStrategy::Chunked] the ranges
+    /// also cover unchanged bytes of changed chunks.
+    pub regions: Vec<Range<u64>>,
+    /// how the regions were found.
+    pub strategy: DiffStrategy,
+}
+
+impl Diff {
+    /// Smallest chunk the [DiffStrategy::Chunked] fallback compares.
+    pub const MIN_CHUNK_LEN: u64 = 0x1000;
+
+    /// Bytes compared between checks of the time limit.
+    const TIME_CHECK_INTERVAL: usize = 0x100000;
+
+    /// compares `source` and `target`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rom_patcher::create::Diff;
+    /// let diff = Diff::new(&[0, 1, 2, 3], &[0, 9, 9, 3, 4]);
+    /// assert_eq!(diff.regions, vec![1..3, 4..5]);
+    /// ```
+    pub fn new(source: &[u8], target: &[u8]) -> Diff {
+        Diff::with_limits(source, target, None, None)
+    }
+
+    /// compares `source` and `target` byte by byte, falling back to comparing the rest chunk by
+    /// chunk once the regions would take more than `max_memory` bytes or the comparison takes
+    /// longer than `max_time`.
+    ///
+    /// The fallback is a plain linear scan comparing whole chunks at once and keeps at most about
+    /// `max_memory` bytes of regions, so huge or entirely different images can't exhaust memory.
+    /// Patches created from a chunked diff are larger, but apply just the same.
+    ///
+    /// Without limits and with the `parallel` feature, the roms are compared on all cores. The
+    /// result is the same as on a single core regardless of the amount of threads, so patches stay
+    /// reproducible. Only the comparison is parallel: formats are encoded from the diff on a single
+    /// core, and none searches for moved data. A `max_time` makes the result depend on the speed of
+    /// the machine.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rom_patcher::create::{Diff, DiffStrategy};
+    /// let target: Vec<u8> = (0..0x10000u32).map(|x| (x % 2) as u8).collect();
+    /// let diff = Diff::with_limits(&vec![0; 0x10000], &target, Some(0x1000), None);
+    /// assert_eq!(diff.strategy, DiffStrategy::Chunked { chunk_len: 0x1000 });
+    /// assert_eq!(diff.regions, vec![0..0x10000]);
+    /// ```
+    pub fn with_limits(source: &[u8], target: &[u8], max_memory: Option<u64>, max_time: Option<Duration>) -> Diff {
+        #[cfg(feature = "parallel")]
+        if max_memory.is_none() && max_time.is_none() {
+            return Diff {
+                source_len: source.len() as u64,
+                target_len: target.len() as u64,
+                regions: Self::par_regions(source, target, Self::PARALLEL_CHUNK_LEN),
+                strategy: DiffStrategy::Exact,
+            };
+        }
+
+        // without an observer the comparison is never stopped
+        return Self::with_limits_observed(source, target, max_memory, max_time, &mut |_| true).unwrap();
+    }
+
+    /// compares like [Diff::with_limits] on a single core, passing the amount of compared bytes of
+    /// the target to `observe` about every [Diff::TIME_CHECK_INTERVAL] bytes. Returns `None` once
+    /// `observe` returns `false`.
+    pub(crate) fn with_limits_observed(
+        source: &[u8],
+        target: &[u8],
+        max_memory: Option<u64>,
+        max_time: Option<Duration>,
+        observe: &mut dyn FnMut(u64) -> bool,
+    ) -> Option<Diff> {
+        let started = Instant::now();
+        let max_regions = max_memory.map_or(u64::MAX, |x| x / std::mem::size_of::<Range<u64>>() as u64);
+        let mut regions: Vec<Range<u64>> = Vec::new();
+        let mut strategy = DiffStrategy::Exact;
+        let common = source.len().min(target.len());
+        let mut position = 0;
+        let mut next_time_check = Self::TIME_CHECK_INTERVAL;
+        while position < common {
+            position += equal_prefix(&source[position..common], &target[position..common]);
+            if position == common {
+                break;
+            }
+            let end = position + differing_prefix(&source[position..common], &target[position..common]);
+            regions.push(position as u64..end as u64);
+            position = end;
+            if regions.len() as u64 > max_regions {
+                // start over, chunks of the whole target fit as a whole
+                regions = Vec::new();
+                let chunk_len = Self::chunk_len(target.len() as u64, max_regions);
+                Self::diff_chunks(source, target, 0, chunk_len, &mut regions);
+                strategy = DiffStrategy::Chunked { chunk_len };
+                break;
+            }
+            if position >= next_time_check {
+                next_time_check = position + Self::TIME_CHECK_INTERVAL;
+                if !observe(position as u64) {
+                    return None;
+                }
+                if max_time.is_some_and(|x| started.elapsed() > x) {
+                    let budget = max_regions.saturating_sub(regions.len() as u64);
+                    let chunk_len = Self::chunk_len((target.len() - position) as u64, budget);
+                    Self::diff_chunks(source, target, position as u64, chunk_len, &mut regions);
+                    strategy = DiffStrategy::Chunked { chunk_len };
+                    break;
+                }
+            }
+        }
+        // bytes past the end of the source always differ
+        if strategy == DiffStrategy::Exact && target.len() > common {
+            match regions.last_mut() {
+                Some(last) if last.end == common as u64 => last.end = target.len() as u64,
+                _ => regions.push(common as u64..target.len() as u64),
+            }
+        }
+        Some(Diff {
+            source_len: source.len() as u64,
+            target_len: target.len() as u64,
+            regions,
+            strategy,
+        })
+    }
+
+    /// Bytes of the target compared per task by [Diff::par_regions].
+    #[cfg(feature = "parallel")]
+    const PARALLEL_CHUNK_LEN: usize = 0x400000;
+
+    /// returns the exact regions like a comparison without limits, comparing chunks of
+    /// `chunk_len` bytes of the target on all cores.
+    ///
+    /// The chunks don't depend on the amount of threads, and their regions are collected in chunk
+    /// order and merged across chunk boundaries, so the result is the same as comparing byte by
+    /// byte on a single core, no matter how the chunks were scheduled.
+    #[cfg(feature = "parallel")]
+    fn par_regions(source: &[u8], target: &[u8], chunk_len: usize) -> Vec<Range<u64>> {
+        use rayon::prelude::*;
+
+        // collecting an indexed parallel iterator keeps the order of the chunks
+        let chunks: Vec<Vec<Range<u64>>> = target.par_chunks(chunk_len)
+            .enumerate()
+            .map(|(i, chunk)| {
+                let base = i * chunk_len;
+                Diff::new_serial(source.get(base..).unwrap_or_default(), chunk).regions.into_iter()
+                    .map(|x| x.start + base as u64..x.end + base as u64)
+                    .collect()
+            })
+            .collect();
+        let mut regions: Vec<Range<u64>> = Vec::new();
+        for region in chunks.into_iter().flatten() {
+            match regions.last_mut() {
+                Some(last) if last.end == region.start => last.end = region.end,
+                _ => regions.push(region),
+            }
+        }
+        return regions;
+    }
+
+    /// compares like [Diff::new] on a single core.
+    #[cfg(feature = "parallel")]
+    fn new_serial(source: &[u8], target: &[u8]) -> Diff {
+        Diff::with_limits(source, target, Some(u64::MAX), None)
+    }
+
+    /// returns the smallest power of two chunk length, at least [Diff::MIN_CHUNK_LEN], that splits
+    /// `len` bytes into at most `max_chunks` chunks.
+    fn chunk_len(len: u64, max_chunks: u64) -> u64 {
+        len.div_ceil(max_chunks.max(1)).next_power_of_two().max(Self::MIN_CHUNK_LEN)
+    }
+
+    /// adds the chunks of `chunk_len` bytes starting at `from` that differ to `regions`.
+    fn diff_chunks(source: &[u8], target: &[u8], from: u64, chunk_len: u64, regions: &mut Vec<Range<u64>>) {
+        let mut position = from.to_index();
+        while position < target.len() {
+            let end = position.saturating_add(chunk_len.saturating_usize()).min(target.len());
+            if source.get(position..end) != Some(&target[position..end]) {
+                match regions.last_mut() {
+                    Some(last) if last.end == position as u64 => last.end = end as u64,
+                    _ => regions.push(position as u64..end as u64),
+                }
+            }
+            position = end;
+        }
+    }
+
+    /// compares `source` and `target` like [Diff::with_limits], reading both a window of
+    /// `window_len` bytes at a time.
+    ///
+    /// Only the two windows and the regions are held in memory, so roms larger than the available
+    /// memory can be compared. Regions are merged across window boundaries, so without limits the
+    /// result is the same as comparing the whole roms at once. `max_memory` and `max_time` apply
+    /// to the whole comparison, windows after one of them was exceeded are compared chunk by chunk.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rom_patcher::create::Diff;
+    /// let (source, target) = ([0u8, 1, 2, 3, 4], [0u8, 9, 9, 3, 4, 5]);
+    /// let diff = Diff::from_readers(&mut &source[..], &mut &target[..], 2, None, None).unwrap();
+    /// assert_eq!(diff, Diff::new(&source, &target));
+    /// ```
+    pub fn from_readers(source: &mut impl Read, target: &mut impl Read, window_len: usize, max_memory: Option<u64>, max_time: Option<Duration>) -> Result<Diff, Error> {
+        let started = Instant::now();
+        let mut source_window = vec![0; window_len.max(1)];
+        let mut target_window = vec![0; window_len.max(1)];
+        let mut result = Diff {
+            source_len: 0,
+            target_len: 0,
+            regions: Vec::new(),
+            strategy: DiffStrategy::Exact,
+        };
+        loop {
+            let source_read = read_window(source, &mut source_window, "source")?;
+            let target_read = read_window(target, &mut target_window, "target")?;
+            if source_read == 0 && target_read == 0 {
+                break;
+            }
+            let base = result.target_len;
+            let used = (result.regions.len() * std::mem::size_of::<Range<u64>>()) as u64;
+            let window = Diff::with_limits(
+                &source_window[..source_read],
+                &target_window[..target_read],
+                max_memory.map(|x| x.saturating_sub(used)),
+                max_time.map(|x| x.saturating_sub(started.elapsed())),
+            );
+            for region in window.regions {
+                let region = region.start + base..region.end + base;
+                match result.regions.last_mut() {
+                    Some(last) if last.end == region.start => last.end = region.end,
+                    _ => result.regions.push(region),
+                }
+            }
+            if let DiffStrategy::Chunked { chunk_len } = window.strategy {
+                if !matches!(result.strategy, DiffStrategy::Chunked { chunk_len: x } if x >= chunk_len) {
+                    result.strategy = DiffStrategy::Chunked { chunk_len };
+                }
+            }
+            result.source_len += source_read as u64;
+            result.target_len += target_read as u64;
+        }
+        return Ok(result);
+    }
+
+    /// returns `true` if source and target are equal.
+    pub fn is_empty(&self) -> bool {
+        self.regions.is_empty() && self.source_len == self.target_len
+    }
+
+    /// returns the amount of differing bytes.
+    pub fn changed_bytes(&self) -> u64 {
+        self.regions.iter().map(|x| x.end - x.start).sum()
+    }
+
+    /// Shortest run of `0x00` or `0xFF` at the end of a target that
+    /// [Diff::ignore_trailing_padding] treats as padding.
+    pub const MIN_PADDING_LEN: u64 = 0x100;
+
+    /// returns the rom that patches built from the diff produce from `source`: `target` where the
+    /// diff differs, and `source` extended with zeros everywhere else.
+    fn produced(&self, source: &[u8], target: &[u8]) -> Vec<u8> {
+        let mut result = source.to_vec();
+        result.resize(self.target_len.to_index(), 0);
+        for region in &self.regions {
+            let region = region.start.to_index()..region.end.to_index();
+            result[region.clone()].copy_from_slice(&target[region]);
+        }
+        return result;
+    }
+
+    /// drops the differences inside the trailing padding of `target`, the target the diff was
+    /// created for, and returns the range of the padding if there is any.
+    ///
+    /// Padding is a run of at least [Diff::MIN_PADDING_LEN] `0x00` or `0xFF` bytes ending the
+    /// target, like the one trimmed GBA roms lack. Patches created from the diff still truncate or
+    /// extend the rom to the length of the target, writing only its last byte, so bytes of the
+    /// padding keep whatever the source or the extension by zeros left there.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rom_patcher::create::Diff;
+    /// let mut target = vec![1; 0x10];
+    /// target.resize(0x1000, 0xFF);
+    /// let mut diff = Diff::new(&[1; 0x10], &target);
+    /// assert_eq!(diff.ignore_trailing_padding(&target), Some(0x10..0x1000));
+    /// assert_eq!(diff.regions, vec![0xFFF..0x1000]);
+    /// ```
+    pub fn ignore_trailing_padding(&mut self, target: &[u8]) -> Option<Range<u64>> {
+        let fill = *target.last().filter(|x| **x == 0x00 || **x == 0xFF)?;
+        let len = target.iter().rev().take_while(|x| **x == fill).count() as u64;
+        if len < Self::MIN_PADDING_LEN {
+            return None;
+        }
+        let padding = self.target_len - len..self.target_len;
+        self.regions.retain(|x| x.start < padding.start);
+        if let Some(last) = self.regions.last_mut() {
+            last.end = last.end.min(padding.start);
+        }
+        // the last byte extends the rom to the length of the target
+        if self.target_len > self.source_len {
+            self.regions.push(self.target_len - 1..self.target_len);
+        }
+        return Some(padding);
+    }
+}
+
+/// fills `buf` from `reader` as far as possible and returns the amount of bytes read.
+fn read_window(reader: &mut impl Read, buf: &mut [u8], name: &str) -> Result<usize, Error> {
+    let mut read = 0;
+    while read < buf.len() {
+        match reader.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(Error::new(CreatingError)
+                .with_description(format!("Unable to read {}.", name))
+                .with_source(Box::new(e))),
+        }
+    }
+    Ok(read)
+}
+
+/// The target a [Diff] was created for, which formats read the changed bytes from.
+pub(crate) trait DiffTarget {
+    /// returns the bytes of the target in `range`, which lies inside it.
+    fn read_range(&mut self, range: Range<u64>) -> Result<Cow<'_, [u8]>, Error>;
+}
+
+impl DiffTarget for &[u8] {
+    fn read_range(&mut self, range: Range<u64>) -> Result<Cow<'_, [u8]>, Error> {
+        Ok(Cow::Borrowed(&self[range.start.to_index()..range.end.to_index()]))
+    }
+}
+
+/// A target read on demand, its body starting `offset` bytes into `reader`.
+struct ReaderTarget<'a, R> {
+    reader: &'a mut R,
+    offset: u64,
+}
+
+impl<R> DiffTarget for ReaderTarget<'_, R> where R: Read + Seek {
+    fn read_range(&mut self, range: Range<u64>) -> Result<Cow<'_, [u8]>, Error> {
+        let mut result = vec![0; (range.end - range.start).to_index()];
+        self.reader.seek(SeekFrom::Start(self.offset + range.start))
+            .and_then(|_| self.reader.read_exact(&mut result))
+            .map_err(|e| Error::new(CreatingError)
+                .with_description("Unable to read target.".to_string())
+                .with_source(Box::new(e)))?;
+        Ok(Cow::Owned(result))
+    }
+}
+
+/// Bytes compared at once by [equal_prefix] and [differing_prefix].
+const WORD: usize = std::mem::size_of::<u64>();
+
+/// returns the xor of the words of `a` and `b` at `at`, which has a zero byte where they are equal.
+fn word_xor(a: &[u8], b: &[u8], at: usize) -> u64 {
+    let x = u64::from_le_bytes(a[at..at + WORD].try_into().unwrap());
+    let y = u64::from_le_bytes(b[at..at + WORD].try_into().unwrap());
+    return x ^ y;
+}
+
+/// returns the amount of equal bytes `a` and `b` start with.
+///
+/// Bytes are compared a word at a time, four words per step, which compilers turn into vector
+/// compares.
+pub(crate) fn equal_prefix(a: &[u8], b: &[u8]) -> usize {
+    let len = a.len().min(b.len());
+    let mut i = 0;
+    while i + 4 * WORD <= len
+        && (word_xor(a, b, i) | word_xor(a, b, i + WORD) | word_xor(a, b, i + 2 * WORD) | word_xor(a, b, i + 3 * WORD)) == 0 {
+        i += 4 * WORD;
+    }
+    while i + WORD <= len {
+        let x = word_xor(a, b, i);
+        if x != 0 {
+            return i + (x.trailing_zeros() / 8) as usize;
+        }
+        i += WORD;
+    }
+    return i + a[i..len].iter().zip(&b[i..len]).take_while(|(x, y)| x == y).count();
+}
+
+/// returns the amount of differing bytes `a` and `b` start with.
+pub(crate) fn differing_prefix(a: &[u8], b: &[u8]) -> usize {
+    const LOW: u64 = 0x0101010101010101;
+    const HIGH: u64 = 0x8080808080808080;
+    let len = a.len().min(b.len());
+    let mut i = 0;
+    while i + WORD <= len {
+        let x = word_xor(a, b, i);
+        // flags the zero bytes of x, and possibly bytes above the lowest zero byte
+        let zero = x.wrapping_sub(LOW) & !x & HIGH;
+        if zero != 0 {
+            return i + (zero.trailing_zeros() / 8) as usize;
+        }
+        i += WORD;
+    }
+    return i + a[i..len].iter().zip(&b[i..len]).take_while(|(x, y)| x != y).count();
+}
+
+/// A format [create_all] can emit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum CreateFormat {
+    /// [IPSPatch].
+    Ips,
+    /// [PMSRPatch].
+    Pmsr,
+    /// [BPSPatch], carrying the [manifest](CreateOptions::manifest).
+    Bps,
+    /// [UPSPatch].
+    Ups,
+}
+
+impl CreateFormat {
+    /// encodes `diff` of `target` in this format according to `options`.
+    ///
+    /// `roms` are the whole source and target if they are in memory, which formats recording
+    /// checksums of them need.
+    fn create(&self, diff: &Diff, target: &mut dyn DiffTarget, roms: Option<(&[u8], &[u8])>, options: &CreateOptions) -> Result<Box<dyn Patch>, Error> {
+        match self {
+            CreateFormat::Ips => Ok(Box::new(IPSPatch::from_diff_target(diff, target, options.ips_optimization())?)),
+            CreateFormat::Pmsr => Ok(Box::new(PMSRPatch::from_diff_target(diff, target)?)),
+            CreateFormat::Bps => {
+                let (source, target) = roms.ok_or_else(|| Error::new(CreatingError)
+                    .with_description("BPS patches need the whole source and target in memory.".to_string()))?;
+                let patch = BPSPatch::from_diff(diff, source, target);
+                Ok(Box::new(match options.manifest() {
+                    Some(manifest) => patch.with_manifest(manifest),
+                    None => patch,
+                }))
+            }
+            CreateFormat::Ups => {
+                let (source, target) = roms.ok_or_else(|| Error::new(CreatingError)
+                    .with_description("UPS patches need the whole source and target in memory.".to_string()))?;
+                Ok(Box::new(UPSPatch::from_diff(diff, source, target)))
+            }
+        }
+    }
+}
+
+/// Options for creating patches.
+///
+/// # Examples
+///
+/// ```
+/// use rom_patcher::create::CreateOptions;
+/// let options = CreateOptions::new().with_exclude_header(true);
+/// assert!(options.exclude_header());
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize), serde(default))]
+pub struct CreateOptions {
+    exclude_header: bool,
+    verbatim_check: Option<usize>,
+    max_memory: Option<u64>,
+    max_time: Option<Duration>,
+    ips_optimization: IPSOptimization,
+    ignore_trailing_padding: bool,
+    manifest: Option<String>,
+}
+
+impl CreateOptions {
+    /// constructs the default options.
+    pub fn new() -> CreateOptions {
+        CreateOptions::default()
+    }
+
+    /// returns whether copier headers are left out of the comparison.
+    pub fn exclude_header(&self) -> bool {
+        self.exclude_header
+    }
+
+    /// modifies the options to compare only the rom bodies if `exclude_header` is `true`.
+    ///
+    /// The created patches then address the body, and serve headered and headerless roms alike
+    /// when applied with [crate::apply::HeaderHandling::SkipCopierHeader].
+    pub fn with_exclude_header(mut self, exclude_header: bool) -> CreateOptions {
+        self.exclude_header = exclude_header;
+        return self;
+    }
+
+    /// returns the length from which data copied verbatim from the source is reported, if checked.
+    pub fn verbatim_check(&self) -> Option<usize> {
+        self.verbatim_check
+    }
+
+    /// modifies the options to report changed data of at least `min_len` bytes that is copied
+    /// verbatim from the source in [CreatedPatches::diagnostics], see [crate::verbatim].
+    /// `None`, the default, disables the check.
+    pub fn with_verbatim_check(mut self, min_len: Option<usize>) -> CreateOptions {
+        self.verbatim_check = min_len;
+        return self;
+    }
+
+    /// returns the amount of memory the differences may take before they are found chunk by chunk.
+    pub fn max_memory(&self) -> Option<u64> {
+        self.max_memory
+    }
+
+    /// returns how long the byte by byte comparison may take before the rest is compared chunk by
+    /// chunk.
+    pub fn max_time(&self) -> Option<Duration> {
+        self.max_time
+    }
+
+    /// modifies the options to fall back to [DiffStrategy::Chunked] once the differences would take
+    /// more than `max_memory` bytes, see [Diff::with_limits]. `None`, the default, sets no limit.
+    pub fn with_max_memory(mut self, max_memory: Option<u64>) -> CreateOptions {
+        self.max_memory = max_memory;
+        return self;
+    }
+
+    /// modifies the options to fall back to [DiffStrategy::Chunked] once comparing takes longer than
+    /// `max_time`, see [Diff::with_limits]. `None`, the default, sets no limit.
+    ///
+    /// Patches created with a time limit may differ between runs, so reproducible builds shouldn't
+    /// set one.
+    pub fn with_max_time(mut self, max_time: Option<Duration>) -> CreateOptions {
+        self.max_time = max_time;
+        return self;
+    }
+
+    /// returns how hard IPS patches are made small.
+    pub fn ips_optimization(&self) -> IPSOptimization {
+        self.ips_optimization
+    }
+
+    /// modifies the options to choose the hunks of IPS patches according to `ips_optimization`.
+    /// [IPSOptimization::Basic] is the default.
+    pub fn with_ips_optimization(mut self, ips_optimization: IPSOptimization) -> CreateOptions {
+        self.ips_optimization = ips_optimization;
+        return self;
+    }
+
+    /// returns whether differences in the trailing padding of the target are left out.
+    pub fn ignore_trailing_padding(&self) -> bool {
+        self.ignore_trailing_padding
+    }
+
+    /// modifies the options to leave differences in the trailing `0x00` or `0xFF` padding of the
+    /// target out of the patches if `ignore_trailing_padding` is `true`, see
+    /// [Diff::ignore_trailing_padding].
+    ///
+    /// This keeps patches for trimmed GBA roms small, at the cost of the patched rom matching the
+    /// target only up to its padding. BPS and UPS patches record the checksum of that patched rom.
+    pub fn with_ignore_trailing_padding(mut self, ignore_trailing_padding: bool) -> CreateOptions {
+        self.ignore_trailing_padding = ignore_trailing_padding;
+        return self;
+    }
+
+    /// returns the manifest stored in created patches, if any.
+    pub fn manifest(&self) -> Option<&str> {
+        self.manifest.as_deref()
+    }
+
+    /// modifies the options to store `manifest` in created patches of formats with a metadata
+    /// field, see [PatchMetadata::manifest](crate::chain::PatchMetadata::manifest). Other formats
+    /// leave it out.
+    pub fn with_manifest(mut self, manifest: Option<String>) -> CreateOptions {
+        self.manifest = manifest;
+        return self;
+    }
+}
+
+/// The result of [create_all].
+#[derive(Debug)]
+pub struct CreatedPatches {
+    /// created patches, in the order their formats were requested.
+    pub patches: Vec<Box<dyn Patch>>,
+    /// formats that can't represent the difference, with the reason why.
+    pub skipped: Vec<(CreateFormat, Error)>,
+    /// the copier header of the target if it was left out of the patches, to be distributed or
+    /// applied separately.
+    pub header: Option<Box<[u8]>>,
+    /// findings about the changes, the same for every format. Their hunk is the index of the
+    /// changed region of the [Diff].
+    pub diagnostics: Vec<Diagnostic>,
+    /// how source and target were compared.
+    pub strategy: DiffStrategy,
+}
+
+/// creates a patch turning `source` into `target` for every format in `formats`.
+///
+/// Source and target are compared only once. Formats that can't represent the difference, e.g. IPS
+/// for targets larger than 16 MiB, are skipped instead of failing the whole operation.
+///
+/// # Examples
+///
+/// ```
+/// use rom_patcher::create::{create_all, CreateFormat};
+/// let created = create_all(&[0; 16], &[1; 16], &[CreateFormat::Ips, CreateFormat::Pmsr]);
+/// assert_eq!(created.patches.len(), 2);
+/// ```
+pub fn create_all(source: &[u8], target: &[u8], formats: &[CreateFormat]) -> CreatedPatches {
+    create_all_with_options(source, target, formats, &CreateOptions::new())
+}
+
+/// creates a patch turning `source` into `target` for every format in `formats` according to
+/// `options`, like [create_all].
+pub fn create_all_with_options(source: &[u8], target: &[u8], formats: &[CreateFormat], options: &CreateOptions) -> CreatedPatches {
+    // without a token nothing cancels the operation
+    return create_observed(source, target, formats, options, &Reporter { progress: None, cancellation: None }).unwrap();
+}
+
+/// creates a patch turning `source` into `target` for every format in `formats` according to
+/// `options` like [create_all_with_options], sending its progress to `progress` and stopping once
+/// `cancellation` is cancelled.
+///
+/// [Stage::Matching] counts the compared bytes of the target, [Stage::Hashing] is only entered for
+/// [CreateOptions::with_verbatim_check] and [Stage::Encoding] counts the encoded formats. The
+/// comparison runs on a single core, even with the `parallel` feature, so it can be cancelled.
+///
+/// Returns a [Cancelled] error once cancelled.
+///
+/// # Examples
+///
+/// ```
+/// use std::sync::mpsc::channel;
+/// use rom_patcher::create::{create_all_with_progress, CreateFormat, CreateOptions};
+/// use rom_patcher::progress::{CancellationToken, Stage};
+///
+/// let (sender, receiver) = channel();
+/// let options = CreateOptions::new();
+/// let created = create_all_with_progress(&[0; 16], &[1; 16], &[CreateFormat::Ips], &options, Some(sender), &CancellationToken::new()).unwrap();
+/// assert_eq!(created.patches.len(), 1);
+/// assert_eq!(receiver.iter().last().unwrap().stage, Stage::Done);
+/// ```
+pub fn create_all_with_progress(
+    source: &[u8],
+    target: &[u8],
+    formats: &[CreateFormat],
+    options: &CreateOptions,
+    progress: Option<Sender<Progress>>,
+    cancellation: &CancellationToken,
+) -> Result<CreatedPatches, Error> {
+    create_observed(source, target, formats, options, &Reporter { progress, cancellation: Some(cancellation) })
+}
+
+fn create_observed(source: &[u8], target: &[u8], formats: &[CreateFormat], options: &CreateOptions, reporter: &Reporter) -> Result<CreatedPatches, Error> {
+    measure("create", || create_unmeasured(source, target, formats, options, reporter), |_| target.len() as u64)
+}
+
+fn create_unmeasured(source: &[u8], target: &[u8], formats: &[CreateFormat], options: &CreateOptions, reporter: &Reporter) -> Result<CreatedPatches, Error> {
+    let mut header = None;
+    let (source, target) = if options.exclude_header() {
+        let (_, source_body) = split_header(source);
+        let (target_header, target_body) = split_header(target);
+        if !target_header.is_empty() {
+            header = Some(target_header.into());
+        }
+        (source_body, target_body)
+    } else {
+        (source, target)
+    };
+
+    let total = target.len() as u64;
+    reporter.enter(Stage::Matching, 0, total)?;
+    let mut diff = match reporter.cancellation {
+        None => Diff::with_limits(source, target, options.max_memory(), options.max_time()),
+        Some(cancellation) => {
+            let mut observe = |done| {
+                reporter.report(Stage::Matching, done, total);
+                !cancellation.is_cancelled()
+            };
+            Diff::with_limits_observed(source, target, options.max_memory(), options.max_time(), &mut observe)
+                .ok_or_else(|| Error::new(Cancelled).with_message(Message::Cancelled))?
+        }
+    };
+    reporter.enter(Stage::Matching, total, total)?;
+    // checksummed formats record the target the patch produces, which keeps the old padding
+    let produced = match options.ignore_trailing_padding() {
+        true => diff.ignore_trailing_padding(target).map(|_| diff.produced(source, target)),
+        false => None,
+    };
+    let target = produced.as_deref().unwrap_or(target);
+    let mut result = CreatedPatches {
+        patches: Vec::new(),
+        skipped: Vec::new(),
+        header,
+        diagnostics: Vec::new(),
+        strategy: diff.strategy,
+    };
+    if let Some(min_len) = options.verbatim_check() {
+        reporter.enter(Stage::Hashing, 0, 1)?;
+        let writes = diff.regions.iter().map(|x| (x.start, &target[x.start.to_index()..x.end.to_index()]));
+        result.diagnostics = find_verbatim(source, writes, min_len).iter().map(|x| x.to_diagnostic()).collect();
+        reporter.report(Stage::Hashing, 1, 1);
+    }
+    for (i, format) in formats.iter().enumerate() {
+        reporter.enter(Stage::Encoding, i as u64, formats.len() as u64)?;
+        match format.create(&diff, &mut &target[..], Some((source, target)), options) {
+            Ok(patch) => result.patches.push(patch),
+            Err(e) => result.skipped.push((*format, e)),
+        }
+    }
+    reporter.enter(Stage::Encoding, formats.len() as u64, formats.len() as u64)?;
+    reporter.report(Stage::Done, 1, 1);
+    return Ok(result);
+}
+
+/// Sends the progress of [create_all_with_progress] and checks for its cancellation. Without a
+/// token the operation can't be cancelled, and compares on all cores with the `parallel` feature.
+struct Reporter<'a> {
+    progress: Option<Sender<Progress>>,
+    cancellation: Option<&'a CancellationToken>,
+}
+
+impl Reporter<'_> {
+    /// reports `done` of `total` steps of `stage` unless the operation was cancelled.
+    fn enter(&self, stage: Stage, done: u64, total: u64) -> Result<(), Error> {
+        if let Some(cancellation) = self.cancellation {
+            cancellation.check()?;
+        }
+        self.report(stage, done, total);
+        Ok(())
+    }
+
+    fn report(&self, stage: Stage, done: u64, total: u64) {
+        if let Some(progress) = &self.progress {
+            // a frontend that stopped listening doesn't stop the operation
+            let _ = progress.send(Progress { stage, done, total });
+        }
+    }
+}
+
+/// Length of the windows [create_all_windowed] reads at once by default.
+pub const DEFAULT_WINDOW_LEN: usize = 0x1000000;
+
+/// creates a patch turning `source` into `target` for every format in `formats` according to
+/// `options` like [create_all_with_options], reading the roms a window of `window_len` bytes at a
+/// time, see [Diff::from_readers].
+///
+/// This keeps memory use at about two windows plus the patches, so patches for roms that don't fit
+/// into memory twice can be created. The changed bytes are read back from `target` while encoding.
+/// [CreateOptions::with_verbatim_check] and [CreateOptions::with_ignore_trailing_padding] need the
+/// whole roms in memory and are not applied.
+///
+/// Returns a [CreatingError] if the roms can't be read for comparing them. Formats whose changed
+/// bytes can't be read back are skipped with the error, as is BPS, which needs the whole roms for
+/// their checksums.
+///
+/// # Examples
+///
+/// ```
+/// use std::io::Cursor;
+/// use rom_patcher::create::{create_all_windowed, CreateFormat, CreateOptions};
+///
+/// let mut source = Cursor::new(vec![0; 0x100]);
+/// let mut target = Cursor::new(vec![1; 0x100]);
+/// let options = CreateOptions::new();
+/// let created = create_all_windowed(&mut source, &mut target, &[CreateFormat::Ips], &options, 0x40).unwrap();
+/// assert_eq!(created.patches[0].apply_to_vec(&[0; 0x100]).unwrap(), vec![1; 0x100]);
+/// ```
+pub fn create_all_windowed<S, T>(source: &mut S, target: &mut T, formats: &[CreateFormat], options: &CreateOptions, window_len: usize) -> Result<CreatedPatches, Error>
+    where S: Read + Seek, T: Read + Seek {
+    let seek_error = |name: &str, e: std::io::Error| Error::new(CreatingError)
+        .with_description(format!("Unable to read {}.", name))
+        .with_source(Box::new(e));
+    let mut header = None;
+    let (mut source_start, mut target_start) = (0, 0);
+    if options.exclude_header() {
+        source_start = source.seek(SeekFrom::End(0)).map(copier_header_len).map_err(|e| seek_error("source", e))?;
+        target_start = target.seek(SeekFrom::End(0)).map(copier_header_len).map_err(|e| seek_error("target", e))?;
+        if target_start > 0 {
+            let mut target_header = vec![0; target_start.to_index()];
+            target.seek(SeekFrom::Start(0))
+                .and_then(|_| target.read_exact(&mut target_header))
+                .map_err(|e| seek_error("target", e))?;
+            header = Some(target_header.into());
+        }
+    }
+    source.seek(SeekFrom::Start(source_start)).map_err(|e| seek_error("source", e))?;
+    target.seek(SeekFrom::Start(target_start)).map_err(|e| seek_error("target", e))?;
+
+    let diff = Diff::from_readers(source, target, window_len, options.max_memory(), options.max_time())?;
+    let mut result = CreatedPatches {
+        patches: Vec::new(),
+        skipped: Vec::new(),
+        header,
+        diagnostics: Vec::new(),
+        strategy: diff.strategy,
+    };
+    let mut target = ReaderTarget { reader: target, offset: target_start };
+    for format in formats {
+        match format.create(&diff, &mut target, None, options) {
+            Ok(patch) => result.patches.push(patch),
+            Err(e) => result.skipped.push((*format, e)),
+        }
+    }
+    return Ok(result);
+}
+
+/// A candidate base rom for [create_best].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Source<'a> {
+    /// name of the rom, e.g. its region, to tell the candidates apart.
+    pub name: &'a str,
+    /// the rom.
+    pub data: &'a [u8],
+}
+
+/// The result of [create_best].
+#[derive(Debug)]
+pub struct BestPatch {
+    /// index of the source the patch was created against.
+    pub source: usize,
+    /// the smallest patch.
+    pub patch: Box<dyn Patch>,
+    /// size of the written patch in bytes.
+    pub len: usize,
+    /// the copier header of the target if it was left out of the patch.
+    pub header: Option<Box<[u8]>>,
+    /// sources no patch could be created against, by index, with the reason why.
+    pub skipped: Vec<(usize, Error)>,
+}
+
+/// creates a patch of `format` turning each of `sources` into `target` and returns the smallest.
+///
+/// Useful when several dumps, e.g. of different regions, could serve as the base of a patch. On a
+/// tie the earlier source wins.
+///
+/// # Examples
+///
+/// ```
+/// use rom_patcher::create::{create_best, CreateFormat, Source};
+///
+/// let sources = [
+///     Source { name: "USA", data: &[0, 0, 0, 0] },
+///     Source { name: "Europe", data: &[1, 2, 3, 0] },
+/// ];
+/// let best = create_best(&sources, &[1, 2, 3, 4], CreateFormat::Ips).unwrap();
+/// assert_eq!(sources[best.source].name, "Europe");
+/// ```
+pub fn create_best(sources: &[Source], target: &[u8], format: CreateFormat) -> Result<BestPatch, Error> {
+    create_best_with_options(sources, target, format, &CreateOptions::new())
+}
+
+/// creates a patch of `format` turning each of `sources` into `target` according to `options` and
+/// returns the smallest, like [create_best].
+///
+/// Returns a [CreatingError] if `sources` is empty, or the error of the last source if no patch
+/// could be created against any of them.
+pub fn create_best_with_options(sources: &[Source], target: &[u8], format: CreateFormat, options: &CreateOptions) -> Result<BestPatch, Error> {
+    let mut best: Option<BestPatch> = None;
+    let mut skipped = Vec::new();
+    for (i, source) in sources.iter().enumerate() {
+        let mut created = create_all_with_options(source.data, target, &[format], options);
+        let patch = match created.skipped.pop() {
+            Some((_, e)) => {
+                skipped.push((i, e));
+                continue;
+            }
+            None => created.patches.remove(0),
+        };
+        let len = match patch.to_bytes() {
+            Ok(x) => x.len(),
+            Err(e) => {
+                skipped.push((i, Error::new(CreatingError)
+                    .with_description(format!("Unable to write the patch against {}.", source.name))
+                    .with_source(Box::new(e))));
+                continue;
+            }
+        };
+        if best.as_ref().is_none_or(|x| len < x.len) {
+            best = Some(BestPatch { source: i, patch, len, header: created.header, skipped: Vec::new() });
+        }
+    }
+    match best {
+        Some(mut best) => {
+            best.skipped = skipped;
+            Ok(best)
+        }
+        None => Err(skipped.pop().map(|(_, e)| e).unwrap_or_else(|| Error::new(CreatingError)
+            .with_description("No source to create the patch against.".to_string()))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use spectral::prelude::*;
+
+    use std::sync::mpsc::channel;
+
+    use crate::apply::{ApplyOptions, HeaderHandling};
+    use crate::registry::read_any_from_slice;
+    use crate::testkit::{corpus, Fixture, FixtureOptions};
+
+    use super::*;
+
+    mod diff_tests {
+        use super::*;
+
+        #[test]
+        fn equal_roms_have_no_differences() {
+            let diff = Diff::new(&[1, 2, 3], &[1, 2, 3]);
+            assert_that!(diff.is_empty()).is_true();
+            assert_that!(diff.changed_bytes()).is_equal_to(0);
+        }
+
+        #[test]
+        fn differing_bytes_are_grouped_into_regions() {
+            let diff = Diff::new(&[0, 0, 0, 0, 0, 0], &[1, 1, 0, 1, 0, 1]);
+            assert_that!(diff.regions).is_equal_to(vec![0..2, 3..4, 5..6]);
+            assert_that!(diff.changed_bytes()).is_equal_to(4);
+        }
+
+        #[test]
+        fn bytes_past_the_source_differ() {
+            let diff = Diff::new(&[0, 0], &[0, 0, 0, 0]);
+            assert_that!(diff.regions.len()).is_equal_to(1);
+            assert_that!(diff.regions[0].clone()).is_equal_to(2..4);
+        }
+
+        #[test]
+        fn exceeding_max_memory_falls_back_to_chunks() {
+            let source = vec![0; 0x4000];
+            let mut target = source.clone();
+            for i in (0x2000..0x3000).step_by(2) {
+                target[i] = 1;
+            }
+            let diff = Diff::with_limits(&source, &target, Some(0x100), None);
+            assert_that!(diff.strategy).is_equal_to(DiffStrategy::Chunked { chunk_len: Diff::MIN_CHUNK_LEN });
+            assert_that!(diff.regions).is_equal_to(std::iter::once(0x2000..0x3000).collect::<Vec<_>>());
+            assert_that!(Diff::new(&source, &target).strategy).is_equal_to(DiffStrategy::Exact);
+        }
+
+        #[test]
+        fn exceeding_max_time_keeps_exact_regions_so_far() {
+            let source = vec![0; 0x300000];
+            let mut target = source.clone();
+            target[5] = 1;
+            target[0x100001] = 1;
+            target[0x2FFFFF] = 1;
+            let diff = Diff::with_limits(&source, &target, None, Some(Duration::ZERO));
+            assert_that!(diff.strategy).is_equal_to(DiffStrategy::Chunked { chunk_len: Diff::MIN_CHUNK_LEN });
+            assert_that!(diff.regions).is_equal_to(vec![5..6, 0x100001..0x100002, 0x2FF002..0x300000]);
+        }
+
+        #[cfg(feature = "parallel")]
+        #[test]
+        fn parallel_comparison_matches_serial_comparison() {
+            let source: Vec<u8> = (0..0x00A00000u32).map(|x| (x.wrapping_mul(2654435761) >> 24) as u8).collect();
+            let mut target = source.clone();
+            // changes straddling the chunk boundaries
+            target[Diff::PARALLEL_CHUNK_LEN - 3..Diff::PARALLEL_CHUNK_LEN + 3].fill(!0);
+            target[2 * Diff::PARALLEL_CHUNK_LEN - 1] ^= 1;
+            target[2 * Diff::PARALLEL_CHUNK_LEN] ^= 1;
+            target.extend_from_slice(&[0; 100]);
+            target[12345..12400].fill(0x55);
+            assert_that!(Diff::new(&source, &target)).is_equal_to(Diff::new_serial(&source, &target));
+        }
+
+        #[cfg(feature = "parallel")]
+        #[test]
+        fn parallel_regions_are_independent_of_threads_and_chunks() {
+            let options = FixtureOptions { source_size: 0x3000, hunk_count: 64, allow_extension: true, ..FixtureOptions::default() };
+            for fixture in corpus(0..6, &options) {
+                let serial = Diff::new_serial(&fixture.source, &fixture.target).regions;
+                for threads in [1, 2, 3, 8] {
+                    let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+                    for chunk_len in [1, 7, 0x100, 0x1000, 0x10000] {
+                        let parallel = pool.install(|| Diff::par_regions(&fixture.source, &fixture.target, chunk_len));
+                        assert_that!(parallel).named(&format!("seed {} threads {} chunk {:#x}", fixture.seed, threads, chunk_len))
+                            .is_equal_to(&serial);
+                    }
+                }
+            }
+        }
+
+        #[test]
+        fn windowed_comparison_merges_regions_across_windows() {
+            let source = [0u8; 10];
+            let target = [0u8, 1, 1, 1, 1, 0, 0, 1, 1, 1, 1, 1];
+            let diff = Diff::from_readers(&mut &source[..], &mut &target[..], 3, None, None).unwrap();
+            assert_that!(diff).is_equal_to(Diff::new(&source, &target));
+            assert_that!(diff.regions).is_equal_to(vec![1..5, 7..12]);
+        }
+
+        #[test]
+        fn windowed_comparison_keeps_memory_limit() {
+            let target: Vec<u8> = (0..0x10000u32).map(|x| (x % 2) as u8).collect();
+            let diff = Diff::from_readers(&mut &vec![0; 0x10000][..], &mut &target[..], 0x4000, Some(0x1000), None).unwrap();
+            assert_that!(matches!(diff.strategy, DiffStrategy::Chunked { .. })).is_true();
+            assert_that!(diff.regions.len() as u64 * 16).is_less_than_or_equal_to(0x1000);
+        }
+
+        #[test]
+        fn word_comparisons_find_every_boundary() {
+            let a: Vec<u8> = (0..100).collect();
+            for i in 0..100 {
+                let mut changed_tail = a.clone();
+                changed_tail[i..].iter_mut().for_each(|x| *x ^= 0x01);
+                assert_that!(equal_prefix(&a, &changed_tail)).is_equal_to(i);
+                let mut changed_head = a.clone();
+                changed_head[..i].iter_mut().for_each(|x| *x ^= 0x01);
+                assert_that!(differing_prefix(&a, &changed_head)).is_equal_to(i);
+            }
+        }
+
+        #[test]
+        fn shorter_targets_only_differ_in_length() {
+            let diff = Diff::new(&[0, 0, 0, 0], &[0, 0]);
+            assert_that!(diff.regions).is_empty();
+            assert_that!(diff.is_empty()).is_false();
+        }
+    }
+
+    mod create_all_tests {
+        use super::*;
+
+        #[test]
+        fn created_patches_turn_source_into_target() {
+            let formats = [CreateFormat::Ips, CreateFormat::Pmsr, CreateFormat::Ups, CreateFormat::Bps];
+            for fixture in corpus(0..8, &FixtureOptions::default()) {
+                let created = create_all(&fixture.source, &fixture.target, &formats);
+                assert_that!(created.skipped).is_empty();
+                let names: Vec<&str> = created.patches.iter().map(|x| x.format()).collect();
+                assert_that!(names).is_equal_to(vec!["ips", "pmsr", "ups", "bps"]);
+                for patch in created.patches {
+                    assert_that!(patch.apply_to_vec(&fixture.source).unwrap()).is_equal_to(&fixture.target);
+                }
+            }
+        }
+
+        #[test]
+        fn patches_without_header_serve_headered_and_headerless_roms() {
+            let body: Vec<u8> = (0..2048u32).map(|x| x as u8).collect();
+            let mut patched_body = body.clone();
+            patched_body[100..110].fill(0xEE);
+            let header = vec![0x11; 512];
+            let headered_source = [header.clone(), body.clone()].concat();
+            let headered_target = [header.clone(), patched_body.clone()].concat();
+
+            let options = CreateOptions::new().with_exclude_header(true);
+            let created = create_all_with_options(&headered_source, &headered_target, &[CreateFormat::Ips], &options);
+            assert_that!(created.header.as_deref()).is_equal_to(Some(&header[..]));
+            let patch = created.patches[0].as_any().downcast_ref::<IPSPatch>().unwrap();
+
+            let apply_options = ApplyOptions::new().with_header(HeaderHandling::SkipCopierHeader);
+            let mut headered = std::io::Cursor::new(headered_source);
+            patch.apply_with_options(&mut headered, &apply_options).unwrap();
+            assert_that!(headered.into_inner()).is_equal_to(headered_target);
+            let mut headerless = std::io::Cursor::new(body);
+            patch.apply_with_options(&mut headerless, &apply_options).unwrap();
+            assert_that!(headerless.into_inner()).is_equal_to(patched_body);
+        }
+
+        #[test]
+        fn chunked_patches_turn_source_into_target() {
+            let options = CreateOptions::new().with_max_memory(Some(0));
+            for fixture in corpus(0..4, &FixtureOptions::default()) {
+                let created = create_all_with_options(&fixture.source, &fixture.target, &[CreateFormat::Ips], &options);
+                assert_that!(created.strategy).is_not_equal_to(DiffStrategy::Exact);
+                assert_that!(created.patches[0].apply_to_vec(&fixture.source).unwrap()).is_equal_to(&fixture.target);
+            }
+        }
+
+        #[test]
+        fn unrepresentable_formats_are_skipped() {
+            let created = create_all(&[0; 8], &[0; 4], &[CreateFormat::Pmsr, CreateFormat::Ips]);
+            assert_that!(created.patches.len()).is_equal_to(1);
+            assert_that!(created.skipped.len()).is_equal_to(1);
+            assert_that!(created.skipped[0].0).is_equal_to(CreateFormat::Pmsr);
+        }
+
+        #[test]
+        fn verbatim_copies_are_reported_when_checked() {
+            let source: Vec<u8> = (0..4096u32).map(|x| (x.wrapping_mul(2654435761) >> 13) as u8).collect();
+            let mut target = source.clone();
+            target.extend_from_slice(&source[1000..1200]);
+
+            let created = create_all(&source, &target, &[CreateFormat::Ips]);
+            assert_that!(created.diagnostics).is_empty();
+            let options = CreateOptions::new().with_verbatim_check(Some(64));
+            let created = create_all_with_options(&source, &target, &[CreateFormat::Ips], &options);
+            assert_that!(created.diagnostics.len()).is_equal_to(1);
+            assert_that!(created.diagnostics[0].code).is_equal_to(Some(crate::diagnostics::DiagnosticCode::VerbatimSource));
+        }
+
+        #[test]
+        fn trailing_padding_is_left_out_when_ignored() {
+            let mut source = vec![7; 0x100];
+            source.resize(0x800, 0x00);
+            let mut target = vec![7; 0x100];
+            target[0x10] = 8;
+            target.resize(0x1000, 0xFF);
+
+            let options = CreateOptions::new().with_ignore_trailing_padding(true);
+            let formats = [CreateFormat::Ips, CreateFormat::Pmsr, CreateFormat::Ups, CreateFormat::Bps];
+            let created = create_all_with_options(&source, &target, &formats, &options);
+            assert_that!(created.skipped).is_empty();
+            for patch in &created.patches {
+                let patch = read_any_from_slice(&patch.to_bytes().unwrap()).unwrap();
+                let patched = patch.apply_to_vec(&source).unwrap();
+                assert_that!(patched.len()).is_equal_to(target.len());
+                assert_that!(patched[..0x100].to_vec()).is_equal_to(target[..0x100].to_vec());
+                assert_that!(patched[0xFFF]).is_equal_to(0xFF);
+            }
+            let pmsr = created.patches[1].to_bytes().unwrap();
+            assert_that!(pmsr.len()).is_less_than(0x20);
+            let ups = created.patches[2].to_bytes().unwrap();
+            assert_that!(ups.len()).is_less_than(0x40);
+        }
+
+        #[test]
+        fn short_trailing_runs_are_not_padding() {
+            let target = [1, 2, 0, 0];
+            let mut diff = Diff::new(&[1, 2, 3, 4], &target);
+            assert_that!(diff.ignore_trailing_padding(&target)).is_none();
+            assert_that!(diff.regions).is_equal_to(std::iter::once(2..4).collect::<Vec<_>>());
+        }
+
+        #[test]
+        fn padding_of_shorter_targets_is_truncated() {
+            let source = vec![0xFF; 0x2000];
+            let mut target = vec![0x00; 0x1000];
+            target[0] = 1;
+            let mut diff = Diff::new(&source, &target);
+            assert_that!(diff.ignore_trailing_padding(&target)).is_equal_to(Some(1..0x1000));
+            assert_that!(diff.regions).is_equal_to(std::iter::once(0..1).collect::<Vec<_>>());
+            let patch = IPSPatch::from_diff(&diff, &target).unwrap();
+            assert_that!(patch.truncate()).is_equal_to(Some(0x1000));
+        }
+
+        #[test]
+        fn windowed_creation_matches_creation_in_memory() {
+            for fixture in corpus(0..6, &FixtureOptions::default()) {
+                let in_memory = create_all(&fixture.source, &fixture.target, &[CreateFormat::Ips, CreateFormat::Pmsr]);
+                let windowed = create_all_windowed(
+                    &mut std::io::Cursor::new(&fixture.source),
+                    &mut std::io::Cursor::new(&fixture.target),
+                    &[CreateFormat::Ips, CreateFormat::Pmsr],
+                    &CreateOptions::new(),
+                    0x333,
+                ).unwrap();
+                let encode = |x: &CreatedPatches| x.patches.iter().map(|p| p.to_bytes().unwrap()).collect::<Vec<_>>();
+                assert_that!(encode(&windowed)).is_equal_to(encode(&in_memory));
+                assert_that!(windowed.skipped.len()).is_equal_to(in_memory.skipped.len());
+            }
+        }
+
+        #[test]
+        fn windowed_creation_excludes_headers() {
+            let mut target = vec![0xAA; 512];
+            target.extend_from_slice(&[1; 0x400]);
+            let options = CreateOptions::new().with_exclude_header(true);
+            let created = create_all_windowed(
+                &mut std::io::Cursor::new(vec![0; 0x400]),
+                &mut std::io::Cursor::new(target),
+                &[CreateFormat::Ips],
+                &options,
+                0x100,
+            ).unwrap();
+            assert_that!(created.header).is_equal_to(Some(vec![0xAA; 512].into_boxed_slice()));
+            assert_that!(created.patches[0].apply_to_vec(&[0; 0x400]).unwrap()).is_equal_to(vec![1; 0x400]);
+        }
+
+        #[test]
+        fn best_patch_uses_closest_source() {
+            let target = [1, 2, 3, 4, 5, 6, 7, 8];
+            let sources = [
+                Source { name: "far", data: &[0; 8] },
+                Source { name: "short", data: &[1, 2, 3, 4, 5, 6, 7, 8, 9] },
+                Source { name: "close", data: &[1, 2, 3, 4, 5, 6, 7, 0] },
+            ];
+            let best = create_best(&sources, &target, CreateFormat::Pmsr).unwrap();
+            assert_that!(best.source).is_equal_to(2);
+            assert_that!(best.len).is_equal_to(best.patch.to_bytes().unwrap().len());
+            assert_that!(best.patch.apply_to_vec(sources[2].data).unwrap()).is_equal_to(target.to_vec());
+            let skipped: Vec<usize> = best.skipped.iter().map(|(i, _)| *i).collect();
+            assert_that!(skipped).is_equal_to(vec![1]);
+        }
+
+        #[test]
+        fn best_patch_needs_a_source() {
+            assert_that!(create_best(&[], &[1], CreateFormat::Ips)).is_err();
+            let sources = [Source { name: "long", data: &[0; 4] }];
+            let err = create_best(&sources, &[1], CreateFormat::Pmsr).unwrap_err();
+            assert_that!(err.to_string()).is_equal_to("CreatingError: Star Rod mods can't truncate the rom.".to_string());
+        }
+
+        #[test]
+        fn ips_optimization_is_passed_to_ips() {
+            let target = [3, 3, 3, 3, 3, 3, 3, 3, 3, 1, 2];
+            let options = CreateOptions::new().with_ips_optimization(IPSOptimization::Size);
+            let created = create_all_with_options(&[0; 11], &target, &[CreateFormat::Ips], &options);
+            let patch = created.patches[0].as_any().downcast_ref::<IPSPatch>().unwrap();
+            assert_that!(patch.hunks().len()).is_equal_to(2);
+            assert_that!(created.patches[0].apply_to_vec(&[0; 11]).unwrap()).is_equal_to(target.to_vec());
+        }
+
+        #[test]
+        fn bps_patches_carry_the_manifest() {
+            let options = CreateOptions::new().with_manifest(Some("<patch/>".to_string()));
+            let created = create_all_with_options(&[0; 8], &[1; 12], &[CreateFormat::Bps, CreateFormat::Ips], &options);
+            assert_that!(created.patches[0].metadata().manifest).is_equal_to(Some("<patch/>".to_string()));
+            assert_that!(created.patches[1].metadata().manifest).is_none();
+            assert_that!(created.patches[0].apply_to_vec(&[0; 8]).unwrap()).is_equal_to(vec![1; 12]);
+
+            let windowed = create_all_windowed(
+                &mut std::io::Cursor::new([0; 8]),
+                &mut std::io::Cursor::new([1; 12]),
+                &[CreateFormat::Bps],
+                &options,
+                4,
+            ).unwrap();
+            assert_that!(windowed.skipped[0].0).is_equal_to(CreateFormat::Bps);
+        }
+
+        #[cfg(feature = "parallel")]
+        #[test]
+        fn parallel_creation_matches_serial_creation() {
+            let formats = [CreateFormat::Ips, CreateFormat::Pmsr];
+            let options = FixtureOptions { source_size: 0x900000, hunk_count: 200, ..FixtureOptions::default() };
+            let fixtures = [Fixture::generate_with(1, &options), Fixture::generate_with(2, &FixtureOptions::default())];
+            for fixture in &fixtures {
+                let encode = |created: CreatedPatches| -> Vec<Vec<u8>> {
+                    created.patches.iter().map(|x| x.to_bytes().unwrap()).collect()
+                };
+                // creation observing a token always compares on a single core
+                let serial = encode(create_all_with_progress(&fixture.source, &fixture.target, &formats, &CreateOptions::new(), None, &CancellationToken::new()).unwrap());
+                for threads in [1, 2, 4, 8] {
+                    let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+                    let parallel = pool.install(|| encode(create_all(&fixture.source, &fixture.target, &formats)));
+                    assert_that!(parallel).named(&format!("seed {} threads {}", fixture.seed, threads)).is_equal_to(&serial);
+                }
+            }
+        }
+
+        #[test]
+        fn progress_reports_every_phase() {
+            let fixture = Fixture::generate(4);
+            let (sender, receiver) = channel();
+            let options = CreateOptions::new().with_verbatim_check(Some(64));
+            let formats = [CreateFormat::Ips, CreateFormat::Pmsr];
+            let created = create_all_with_progress(&fixture.source, &fixture.target, &formats, &options, Some(sender), &CancellationToken::new()).unwrap();
+            assert_that!(created.patches.len()).is_equal_to(2);
+
+            let mut stages: Vec<Stage> = receiver.iter().map(|x| x.stage).collect();
+            stages.dedup();
+            assert_that!(stages).is_equal_to(vec![Stage::Matching, Stage::Hashing, Stage::Encoding, Stage::Done]);
+        }
+
+        #[test]
+        fn cancelled_creation_stops() {
+            let token = CancellationToken::new();
+            token.cancel();
+            let options = CreateOptions::new();
+            let err = create_all_with_progress(&[0; 16], &[1; 16], &[CreateFormat::Ips], &options, None, &token).unwrap_err();
+            assert_that!(err.to_string()).is_equal_to("Cancelled: Operation was cancelled.".to_string());
+        }
+
+        #[test]
+        fn observed_comparison_stops_when_asked() {
+            let target: Vec<u8> = (0..0x300000u32).map(|x| (x % 3 == 0) as u8).collect();
+            let mut observed = Vec::new();
+            let diff = Diff::with_limits_observed(&vec![0; target.len()], &target, None, None, &mut |done| {
+                observed.push(done);
+                false
+            });
+            assert_that!(diff).is_none();
+            assert_that!(observed.len()).is_equal_to(1);
+        }
+    }
+}
@@ -0,0 +1,119 @@
+//! Telling whether a rom already has a patch applied, since applying a patch twice can corrupt
+//! the rom.
+
+use crate::hash::Crc32;
+use crate::io_util::U64Extensions;
+use crate::ips::IPSPatch;
+use crate::patch::Patch;
+
+/// How sure [already_applied] is that a patch was already applied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum Confidence {
+    /// the rom doesn't have the patch applied.
+    NotApplied,
+    /// most of the data the patch writes is already there, e.g. since an older version of the
+    /// patch was applied.
+    Partial,
+    /// all of the data the patch writes is already there.
+    Likely,
+    /// the rom has the checksum the patch records for its output.
+    Certain,
+}
+
+/// returns how sure it is that `rom` already has `patch` applied.
+///
+/// Patches recording the checksum of their output are compared against it. Otherwise the data the
+/// patch writes is compared against `rom`, where a patch that writes nothing is never considered
+/// applied.
+///
+/// # Examples
+///
+/// ```
+/// use rom_patcher::detect::{already_applied, Confidence};
+/// use rom_patcher::ips::{IPSHunk, IPSPatch, IPSRLEHunkData};
+///
+/// let patch = IPSPatch::new()
+///     .with_hunk(IPSHunk::RLE(IPSRLEHunkData { offset: 0, run_length: 2, payload: 0xFF }));
+/// assert_eq!(already_applied(&patch, &[0; 4]), Confidence::NotApplied);
+/// assert_eq!(already_applied(&patch, &[0xFF, 0xFF, 0, 0]), Confidence::Likely);
+/// ```
+pub fn already_applied(patch: &dyn Patch, rom: &[u8]) -> Confidence {
+    confidence(patch, rom)
+}
+
+/// [already_applied] for patches that might not be trait objects yet.
+pub(crate) fn confidence<P: Patch + ?Sized>(patch: &P, rom: &[u8]) -> Confidence {
+    let info = patch.info();
+    if let Some(target_crc32) = info.target_crc32 {
+        let len_matches = info.target_len.is_none_or(|x| x == rom.len() as u64);
+        if len_matches && Crc32::checksum(rom) == target_crc32 {
+            return Confidence::Certain;
+        }
+        return Confidence::NotApplied;
+    }
+    if info.source_crc32.is_some_and(|x| x == Crc32::checksum(rom)) {
+        return Confidence::NotApplied;
+    }
+    let Ok(patched) = patch.apply_to_vec(rom) else {
+        return Confidence::NotApplied;
+    };
+    let Some(ips) = patch.as_any().downcast_ref::<IPSPatch>() else {
+        return if patched == rom { Confidence::Likely } else { Confidence::NotApplied };
+    };
+    return ips_confidence(ips, rom, &patched);
+}
+
+/// compares the bytes `patch` wrote to `patched` against the same bytes of `rom`.
+fn ips_confidence(patch: &IPSPatch, rom: &[u8], patched: &[u8]) -> Confidence {
+    let mut written = 0u64;
+    let mut matching = 0u64;
+    for hunk in patch.hunks() {
+        // bytes past a truncation aren't part of the output
+        let range = hunk.offset().saturating_usize()..hunk.end().saturating_usize().min(patched.len());
+        for (i, byte) in patched.get(range.clone()).unwrap_or_default().iter().enumerate() {
+            written += 1;
+            if rom.get(range.start + i) == Some(byte) {
+                matching += 1;
+            }
+        }
+    }
+    let truncated = patch.truncate().is_none_or(|x| x == rom.len() as u64);
+    if written == 0 {
+        return Confidence::NotApplied;
+    }
+    if matching == written && truncated {
+        return Confidence::Likely;
+    }
+    if matching * 2 > written {
+        return Confidence::Partial;
+    }
+    return Confidence::NotApplied;
+}
+
+#[cfg(test)]
+mod tests {
+    use spectral::prelude::*;
+
+    use crate::ips::{IPSHunk, IPSRLEHunkData};
+    use crate::testkit::Fixture;
+
+    use super::*;
+
+    #[test]
+    fn patched_roms_are_detected() {
+        let fixture = Fixture::generate(21);
+        assert_that!(already_applied(&fixture.ips, &fixture.source)).is_equal_to(Confidence::NotApplied);
+        assert_that!(already_applied(&fixture.ips, &fixture.target)).is_equal_to(Confidence::Likely);
+    }
+
+    #[test]
+    fn partially_patched_roms_are_detected() {
+        let patch = IPSPatch::new()
+            .with_hunk(IPSHunk::RLE(IPSRLEHunkData { offset: 0, run_length: 3, payload: 0xFF }))
+            .with_hunk(IPSHunk::RLE(IPSRLEHunkData { offset: 4, run_length: 1, payload: 0xEE }));
+        assert_that!(already_applied(&patch, &[0xFF, 0xFF, 0xFF, 0, 0])).is_equal_to(Confidence::Partial);
+        assert_that!(already_applied(&patch, &[0xFF, 0xFF, 0xFF, 0, 0xEE])).is_equal_to(Confidence::Likely);
+        assert_that!(already_applied(&IPSPatch::new(), &[0; 4])).is_equal_to(Confidence::NotApplied);
+    }
+}
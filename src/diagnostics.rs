@@ -0,0 +1,92 @@
+//! Findings about a patch that don't necessarily prevent it from being used.
+
+use std::fmt::{Display, Formatter};
+
+/// How severe a [Diagnostic] is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum Severity {
+    /// the patch can be used, but likely doesn't do what its author intended.
+    Warning,
+    /// the patch would corrupt its target.
+    Error,
+}
+
+/// A machine readable category of a [Diagnostic], for frontends reacting to specific findings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum DiagnosticCode {
+    /// a hunk writes data the patch then truncates away.
+    TruncatedHunk,
+    /// the format has no checksums, so a wrong source rom goes unnoticed.
+    Unverified,
+    /// written data is copied verbatim from the source rom, see [crate::verbatim].
+    VerbatimSource,
+    /// a write to a bad block of a dump was dropped or relocated, see [crate::badblock].
+    BadBlock,
+    /// a hunk failed and was skipped, see [ApplyOptions::with_keep_going](crate::apply::ApplyOptions::with_keep_going).
+    FailedHunk,
+    /// a hunk writes different data to an offset than an earlier one, see
+    /// [ApplyOptions::with_duplicate_writes](crate::apply::ApplyOptions::with_duplicate_writes).
+    DuplicateWrite,
+}
+
+/// A single finding about a patch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize))]
+pub struct Diagnostic {
+    /// how severe the finding is.
+    pub severity: Severity,
+    /// the index of the hunk the finding is about, if any.
+    pub hunk: Option<usize>,
+    /// a human readable description of the finding.
+    pub message: String,
+    /// the category of the finding, if it has one.
+    #[cfg_attr(feature = "json", serde(default))]
+    pub code: Option<DiagnosticCode>,
+}
+
+impl Diagnostic {
+    /// constructs a [Severity::Warning] about `hunk`.
+    pub fn warning(hunk: Option<usize>, message: String) -> Diagnostic {
+        Diagnostic { severity: Severity::Warning, hunk, message, code: None }
+    }
+
+    /// constructs a [Severity::Error] about `hunk`.
+    pub fn error(hunk: Option<usize>, message: String) -> Diagnostic {
+        Diagnostic { severity: Severity::Error, hunk, message, code: None }
+    }
+
+    /// constructs the [DiagnosticCode::Unverified] warning for a patch of `format`.
+    pub fn unverified(format: &str) -> Diagnostic {
+        Diagnostic::warning(None, format!(
+            "{} patches carry no checksums, so applying to the wrong rom goes unnoticed. Verify the source and output against known hashes, e.g. with apply_verified.",
+            format.to_uppercase(),
+        )).with_code(DiagnosticCode::Unverified)
+    }
+
+    /// constructs the [DiagnosticCode::FailedHunk] error for `hunk`, which failed with `error`.
+    pub fn failed_hunk(hunk: usize, error: &crate::Error) -> Diagnostic {
+        Diagnostic::error(Some(hunk), error.to_string()).with_code(DiagnosticCode::FailedHunk)
+    }
+
+    /// returns the diagnostic rendered by `localizer`, see [crate::messages].
+    pub fn localized(&self, localizer: &dyn crate::messages::Localizer) -> String {
+        localizer.diagnostic(self)
+    }
+
+    /// modifies the diagnostic to have `code`.
+    pub fn with_code(mut self, code: DiagnosticCode) -> Diagnostic {
+        self.code = Some(code);
+        return self;
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.hunk {
+            Some(hunk) => write!(f, "{:?} in hunk {}: {}", self.severity, hunk, self.message),
+            None => write!(f, "{:?}: {}", self.severity, self.message),
+        }
+    }
+}
@@ -0,0 +1,193 @@
+//! Decoding 32-bit ARM instructions of the ARMv4T architecture, as run by the Game Boy Advance and
+//! the Nintendo DS.
+
+use super::relative;
+
+const CONDITIONS: [&str; 16] = ["EQ", "NE", "CS", "CC", "MI", "PL", "VS", "VC", "HI", "LS", "GE", "LT", "GT", "LE", "", "NV"];
+const DATA_PROCESSING: [&str; 16] = ["AND", "EOR", "SUB", "RSB", "ADD", "ADC", "SBC", "RSC", "TST", "TEQ", "CMP", "CMN", "ORR", "MOV", "BIC", "MVN"];
+const SHIFTS: [&str; 4] = ["LSL", "LSR", "ASR", "ROR"];
+
+fn register(i: u32) -> String {
+    match i & 0xF {
+        13 => "SP".to_string(),
+        14 => "LR".to_string(),
+        15 => "PC".to_string(),
+        i => format!("R{}", i),
+    }
+}
+
+fn bit(word: u32, i: u32) -> bool {
+    word >> i & 1 != 0
+}
+
+/// formats the shifted register operand in the low 12 bits of `word`.
+fn shifted_register(word: u32) -> String {
+    let rm = register(word);
+    let shift = SHIFTS[(word >> 5 & 3) as usize];
+    if bit(word, 4) {
+        return format!("{},{} {}", rm, shift, register(word >> 8));
+    }
+    match (shift, word >> 7 & 0x1F) {
+        ("LSL", 0) => rm,
+        ("ROR", 0) => format!("{},RRX", rm),
+        (_, 0) => format!("{},{} #32", rm, shift),
+        (_, amount) => format!("{},{} #{}", rm, shift, amount),
+    }
+}
+
+/// formats the register list of a block transfer.
+fn register_list(word: u32) -> String {
+    let registers: Vec<String> = (0..16).filter(|i| bit(word, *i)).map(register).collect();
+    return format!("{{{}}}", registers.join(","));
+}
+
+/// decodes the instruction at the start of `data`, returning its length and text.
+pub(crate) fn decode(data: &[u8]) -> Option<(usize, String)> {
+    let bytes = data.get(..4)?;
+    let word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    let cond = CONDITIONS[(word >> 28) as usize];
+    let rn = register(word >> 16);
+    let rd = register(word >> 12);
+    let s = if bit(word, 20) { "S" } else { "" };
+
+    let text = if word & 0x0FFF_FFF0 == 0x012F_FF10 {
+        format!("BX{} {}", cond, register(word))
+    } else if word & 0x0FC0_00F0 == 0x0000_0090 {
+        // the destination is in the bits data processing uses for the first operand
+        let (rd, rn) = (register(word >> 16), register(word >> 12));
+        if bit(word, 21) {
+            format!("MLA{}{} {},{},{},{}", cond, s, rd, register(word), register(word >> 8), rn)
+        } else {
+            format!("MUL{}{} {},{},{}", cond, s, rd, register(word), register(word >> 8))
+        }
+    } else if word & 0x0F80_00F0 == 0x0080_0090 {
+        let name = match (bit(word, 22), bit(word, 21)) {
+            (false, false) => "UMULL",
+            (false, true) => "UMLAL",
+            (true, false) => "SMULL",
+            (true, true) => "SMLAL",
+        };
+        format!("{}{}{} {},{},{},{}", name, cond, s, rd, rn, register(word), register(word >> 8))
+    } else if word & 0x0FB0_0FF0 == 0x0100_0090 {
+        let b = if bit(word, 22) { "B" } else { "" };
+        format!("SWP{}{} {},{},[{}]", cond, b, rd, register(word), rn)
+    } else if word & 0x0E00_0090 == 0x0000_0090 && word & 0x60 != 0 {
+        let name = match (bit(word, 20), word >> 5 & 3) {
+            (false, 1) => "STRH",
+            (false, _) => return None,
+            (true, 1) => "LDRH",
+            (true, 2) => "LDRSB",
+            _ => "LDRSH",
+        };
+        let offset = if bit(word, 22) {
+            format!("#{}0x{:X}", if bit(word, 23) { "" } else { "-" }, (word >> 4 & 0xF0) | (word & 0xF))
+        } else {
+            format!("{}{}", if bit(word, 23) { "" } else { "-" }, register(word))
+        };
+        format!("{}{} {},{}", name, cond, rd, address(word, &rn, &offset))
+    } else if word & 0x0FBF_0FFF == 0x010F_0000 {
+        format!("MRS{} {},{}", cond, rd, if bit(word, 22) { "SPSR" } else { "CPSR" })
+    } else if word & 0x0DB0_F000 == 0x0120_F000 {
+        let psr = if bit(word, 22) { "SPSR" } else { "CPSR" };
+        let fields: String = [(16, 'c'), (17, 'x'), (18, 's'), (19, 'f')]
+            .iter()
+            .filter(|(i, _)| bit(word, *i))
+            .map(|(_, x)| *x)
+            .collect();
+        format!("MSR{} {}_{},{}", cond, psr, fields, operand2(word))
+    } else if word & 0x0C00_0000 == 0x0000_0000 {
+        let opcode = (word >> 21 & 0xF) as usize;
+        let name = DATA_PROCESSING[opcode];
+        match opcode {
+            8..=11 => format!("{}{} {},{}", name, cond, rn, operand2(word)),
+            13 | 15 => format!("{}{}{} {},{}", name, cond, s, rd, operand2(word)),
+            _ => format!("{}{}{} {},{},{}", name, cond, s, rd, rn, operand2(word)),
+        }
+    } else if word & 0x0C00_0000 == 0x0400_0000 {
+        if bit(word, 25) && bit(word, 4) {
+            return None;
+        }
+        let name = if bit(word, 20) { "LDR" } else { "STR" };
+        let b = if bit(word, 22) { "B" } else { "" };
+        let sign = if bit(word, 23) { "" } else { "-" };
+        let offset = if bit(word, 25) {
+            format!("{}{}", sign, shifted_register(word))
+        } else {
+            format!("#{}0x{:X}", sign, word & 0xFFF)
+        };
+        format!("{}{}{} {},{}", name, cond, b, rd, address(word, &rn, &offset))
+    } else if word & 0x0E00_0000 == 0x0800_0000 {
+        let name = if bit(word, 20) { "LDM" } else { "STM" };
+        let mode = match (bit(word, 24), bit(word, 23)) {
+            (false, true) => "IA",
+            (true, true) => "IB",
+            (false, false) => "DA",
+            (true, false) => "DB",
+        };
+        let writeback = if bit(word, 21) { "!" } else { "" };
+        let user = if bit(word, 22) { "^" } else { "" };
+        format!("{}{}{} {}{},{}{}", name, cond, mode, rn, writeback, register_list(word), user)
+    } else if word & 0x0E00_0000 == 0x0A00_0000 {
+        // the offset is in words, relative to the instruction after the next one
+        let offset = ((word << 8) as i32 >> 6) as i64;
+        format!("{}{} {}", if bit(word, 24) { "BL" } else { "B" }, cond, relative(8 + offset))
+    } else if word & 0x0F00_0000 == 0x0F00_0000 {
+        format!("SWI{} 0x{:X}", cond, word & 0xFF_FFFF)
+    } else {
+        return None;
+    };
+    return Some((4, text));
+}
+
+/// formats the second operand of a data processing instruction.
+fn operand2(word: u32) -> String {
+    if bit(word, 25) {
+        let value = (word & 0xFF).rotate_right((word >> 8 & 0xF) * 2);
+        return format!("#0x{:X}", value);
+    }
+    return shifted_register(word);
+}
+
+/// formats the address of a single transfer with `offset` from `rn`.
+fn address(word: u32, rn: &str, offset: &str) -> String {
+    let pre = bit(word, 24);
+    let writeback = if bit(word, 21) { "!" } else { "" };
+    match (pre, offset) {
+        (true, "#0x0") => format!("[{}]", rn),
+        (true, _) => format!("[{},{}]{}", rn, offset, writeback),
+        (false, _) => format!("[{}],{}", rn, offset),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use spectral::prelude::*;
+
+    use super::*;
+
+    fn text(word: u32) -> Option<String> {
+        decode(&word.to_le_bytes()).map(|(_, x)| x)
+    }
+
+    #[test]
+    fn decodes_data_processing() {
+        assert_that!(text(0xE3A0_0001)).is_equal_to(Some("MOV R0,#0x1".to_string()));
+        assert_that!(text(0xE081_0002)).is_equal_to(Some("ADD R0,R1,R2".to_string()));
+        assert_that!(text(0xE350_0C01)).is_equal_to(Some("CMP R0,#0x100".to_string()));
+        assert_that!(text(0x11A0_1102)).is_equal_to(Some("MOVNE R1,R2,LSL #2".to_string()));
+    }
+
+    #[test]
+    fn decodes_transfers_and_branches() {
+        assert_that!(text(0xE591_0004)).is_equal_to(Some("LDR R0,[R1,#0x4]".to_string()));
+        assert_that!(text(0xE1D0_00B2)).is_equal_to(Some("LDRH R0,[R0,#0x2]".to_string()));
+        assert_that!(text(0xE92D_4010)).is_equal_to(Some("STMDB SP!,{R4,LR}".to_string()));
+        assert_that!(text(0xEAFF_FFFE)).is_equal_to(Some("B *+0".to_string()));
+        assert_that!(text(0xE12F_FF1E)).is_equal_to(Some("BX LR".to_string()));
+    }
+
+    #[test]
+    fn partial_words_are_not_decoded() {
+        assert_that!(decode(&[0x01, 0x00, 0xA0])).is_none();
+    }
+}
@@ -0,0 +1,275 @@
+//! Instruction level diffs of the code a patch overwrites, for reviewing what a patch changes.
+//!
+//! Every hunk is disassembled twice, once as the original bytes of the rom and once as the bytes
+//! the hunk writes, and both listings are diffed line by line. Disassembly starts at the offset of
+//! the hunk, so hunks starting in the middle of an instruction or in data decode as garbage, just
+//! like in any disassembler pointed at the wrong offset.
+
+use std::fmt::{Display, Formatter};
+
+use crate::io_util::U64Extensions;
+use crate::ips::IPSPatch;
+
+mod arm;
+mod w65816;
+mod z80;
+
+/// A CPU whose instructions can be decoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Cpu {
+    /// MOS 6502 as in the NES, documented opcodes only.
+    Mos6502,
+    /// WDC 65816 as in the SNES, starting with the given register widths.
+    ///
+    /// `REP` and `SEP` instructions in the disassembled bytes change the widths for the
+    /// instructions following them.
+    W65816 {
+        /// `true` if the accumulator starts out as 16 bits wide.
+        wide_accumulator: bool,
+        /// `true` if the index registers start out as 16 bits wide.
+        wide_index: bool,
+    },
+    /// Zilog Z80 as in the Master System, also covering the Game Boy subset.
+    Z80,
+    /// 32-bit ARM instructions as in the Game Boy Advance. Thumb code is not decoded.
+    Arm,
+}
+
+/// A decoded instruction.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Instruction {
+    /// rom offset of the instruction.
+    pub offset: u64,
+    /// encoding of the instruction.
+    pub bytes: Vec<u8>,
+    /// assembly of the instruction, a data directive for bytes that don't decode.
+    pub text: String,
+}
+
+/// A line of an instruction level diff.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Change {
+    /// an instruction found in both listings.
+    Same(Instruction),
+    /// an original instruction the patch removes.
+    Removed(Instruction),
+    /// an instruction the patch adds.
+    Added(Instruction),
+}
+
+/// The instruction level diff of a single hunk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HunkDiff {
+    /// index of the hunk.
+    pub hunk: usize,
+    /// rom offset the hunk is written to.
+    pub offset: u64,
+    /// the diff of the original and the patched listing.
+    pub changes: Vec<Change>,
+}
+
+/// disassembles `data`, which starts at rom offset `offset`.
+///
+/// # Examples
+///
+/// ```
+/// use rom_patcher::disasm::{disassemble, Cpu};
+/// let listing = disassemble(Cpu::Mos6502, &[0xA9, 0x01, 0x60], 0x8000);
+/// assert_eq!(listing[1].text, "RTS");
+/// assert_eq!(listing[1].offset, 0x8002);
+/// ```
+pub fn disassemble(cpu: Cpu, data: &[u8], offset: u64) -> Vec<Instruction> {
+    let mut widths = match cpu {
+        Cpu::W65816 { wide_accumulator, wide_index } => Some(w65816::Widths { wide_accumulator, wide_index }),
+        _ => None,
+    };
+    let mut result = Vec::new();
+    let mut position = 0;
+    while position < data.len() {
+        let rest = &data[position..];
+        let decoded = match cpu {
+            Cpu::Mos6502 | Cpu::W65816 { .. } => w65816::decode(rest, &mut widths),
+            Cpu::Z80 => z80::decode(rest),
+            Cpu::Arm => arm::decode(rest),
+        };
+        let (len, text) = decoded.unwrap_or_else(|| {
+            // ARM instructions are never split, the other CPUs resynchronize after a byte
+            let len = if cpu == Cpu::Arm { rest.len().min(4) } else { 1 };
+            let bytes: Vec<String> = rest[..len].iter().map(|x| format!("${:02X}", x)).collect();
+            (len, format!(".byte {}", bytes.join(",")))
+        });
+        result.push(Instruction {
+            offset: offset + position as u64,
+            bytes: rest[..len].to_vec(),
+            text,
+        });
+        position += len;
+    }
+    return result;
+}
+
+/// returns the instruction level diff of every hunk of `patch` applied to `rom`, in the order the
+/// hunks are applied.
+///
+/// Each hunk is compared against `rom` as it is, not against the output of earlier hunks.
+pub fn diff_hunks(cpu: Cpu, patch: &IPSPatch, rom: &[u8]) -> Vec<HunkDiff> {
+    patch.hunks()
+        .iter()
+        .enumerate()
+        .map(|(hunk, x)| {
+            let offset = x.offset();
+            let start = x.offset().saturating_usize().min(rom.len());
+            let original = &rom[start..x.end().saturating_usize().min(rom.len())];
+            let mut patched = vec![0; x.length().to_index()];
+            patched[..original.len()].copy_from_slice(original);
+            x.overlay(offset, &mut patched);
+            HunkDiff {
+                hunk,
+                offset,
+                changes: diff(disassemble(cpu, original, offset), disassemble(cpu, &patched, offset)),
+            }
+        })
+        .collect()
+}
+
+/// the largest amount of instruction pairs compared to find the longest common subsequence, larger
+/// differences are shown as removing all original and adding all patched instructions.
+const MAX_DIFF_CELLS: usize = 1 << 22;
+
+/// returns the changes turning `original` into `patched`, keeping the longest common subsequence of
+/// instructions.
+fn diff(mut original: Vec<Instruction>, mut patched: Vec<Instruction>) -> Vec<Change> {
+    let same = |a: &Instruction, b: &Instruction| a.offset == b.offset && a.bytes == b.bytes;
+    let prefix = original.iter().zip(&patched).take_while(|(a, b)| same(a, b)).count();
+    let suffix = original[prefix..].iter().rev()
+        .zip(patched[prefix..].iter().rev())
+        .take_while(|(a, b)| same(a, b))
+        .count();
+    let tail: Vec<Instruction> = original.drain(original.len() - suffix..).collect();
+    patched.truncate(patched.len() - suffix);
+    let mut result: Vec<Change> = original.drain(..prefix).map(Change::Same).collect();
+    patched.drain(..prefix);
+
+    if (original.len() + 1).saturating_mul(patched.len() + 1) > MAX_DIFF_CELLS {
+        result.extend(original.into_iter().map(Change::Removed));
+        result.extend(patched.into_iter().map(Change::Added));
+        result.extend(tail.into_iter().map(Change::Same));
+        return result;
+    }
+
+    // lengths[i][j] is the length of the longest common subsequence of original[i..] and patched[j..]
+    let mut lengths = vec![vec![0usize; patched.len() + 1]; original.len() + 1];
+    for i in (0..original.len()).rev() {
+        for j in (0..patched.len()).rev() {
+            lengths[i][j] = if same(&original[i], &patched[j]) {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+
+    let mut original = original.into_iter().peekable();
+    let mut patched = patched.into_iter().peekable();
+    let (mut i, mut j) = (0, 0);
+    loop {
+        match (original.peek(), patched.peek()) {
+            (Some(a), Some(b)) if same(a, b) => {
+                result.push(Change::Same(original.next().unwrap()));
+                patched.next();
+                i += 1;
+                j += 1;
+            }
+            (Some(_), Some(_)) if lengths[i + 1][j] >= lengths[i][j + 1] => {
+                result.push(Change::Removed(original.next().unwrap()));
+                i += 1;
+            }
+            (_, Some(_)) => {
+                result.push(Change::Added(patched.next().unwrap()));
+                j += 1;
+            }
+            (Some(_), None) => {
+                result.push(Change::Removed(original.next().unwrap()));
+                i += 1;
+            }
+            (None, None) => break,
+        }
+    }
+    result.extend(tail.into_iter().map(Change::Same));
+    return result;
+}
+
+/// formats a relative branch target as an offset from the branch instruction, e.g. `*+4`.
+fn relative(delta: i64) -> String {
+    format!("*{:+}", delta)
+}
+
+impl Display for Instruction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let bytes: Vec<String> = self.bytes.iter().map(|x| format!("{:02X}", x)).collect();
+        write!(f, "0x{:06X}  {:<12} {}", self.offset, bytes.join(" "), self.text)
+    }
+}
+
+impl Display for HunkDiff {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "hunk {} at 0x{:06X}", self.hunk, self.offset)?;
+        for change in &self.changes {
+            match change {
+                Change::Same(x) => writeln!(f, "  {}", x)?,
+                Change::Removed(x) => writeln!(f, "- {}", x)?,
+                Change::Added(x) => writeln!(f, "+ {}", x)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use spectral::prelude::*;
+
+    use crate::ips::{IPSHunk, IPSRegularHunkData};
+
+    use super::*;
+
+    #[test]
+    fn undecodable_bytes_become_data() {
+        let listing = disassemble(Cpu::Mos6502, &[0x02, 0xEA], 0);
+        assert_that!(listing[0].text.as_str()).is_equal_to(".byte $02");
+        assert_that!(listing[1].text.as_str()).is_equal_to("NOP");
+    }
+
+    #[test]
+    fn hunks_are_diffed_by_instruction() {
+        // LDA #$03; STA $0700; RTS becomes LDA #$09; STA $0700; RTS
+        let rom = [0xA9, 0x03, 0x8D, 0x00, 0x07, 0x60];
+        let patch = IPSPatch::new().with_hunk(IPSHunk::Regular(IPSRegularHunkData {
+            offset: 0,
+            payload: Box::new([0xA9, 0x09, 0x8D, 0x00, 0x07, 0x60]),
+        }));
+        let diffs = diff_hunks(Cpu::Mos6502, &patch, &rom);
+        let lines: Vec<String> = diffs[0].to_string().lines().map(|x| x.to_string()).collect();
+        assert_that!(lines).is_equal_to(vec![
+            "hunk 0 at 0x000000".to_string(),
+            "- 0x000000  A9 03        LDA #$03".to_string(),
+            "+ 0x000000  A9 09        LDA #$09".to_string(),
+            "  0x000002  8D 00 07     STA $0700".to_string(),
+            "  0x000005  60           RTS".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn hunks_past_the_end_only_add() {
+        let patch = IPSPatch::new().with_hunk(IPSHunk::Regular(IPSRegularHunkData {
+            offset: 2,
+            payload: Box::new([0x60]),
+        }));
+        let diffs = diff_hunks(Cpu::Mos6502, &patch, &[0xEA, 0xEA]);
+        assert_that!(diffs[0].changes.clone()).is_equal_to(vec![Change::Added(Instruction {
+            offset: 2,
+            bytes: vec![0x60],
+            text: "RTS".to_string(),
+        })]);
+    }
+}
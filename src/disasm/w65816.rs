@@ -0,0 +1,183 @@
+//! Decoding 6502 and 65816 instructions.
+
+use super::relative;
+
+/// an addressing mode, deciding the operand length and syntax.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Imp,
+    Acc,
+    /// immediate sized by the accumulator width.
+    ImmM,
+    /// immediate sized by the index width.
+    ImmX,
+    Imm8,
+    Dp,
+    DpX,
+    DpY,
+    DpInd,
+    DpIndX,
+    DpIndY,
+    DpIndLong,
+    DpIndLongY,
+    Abs,
+    AbsX,
+    AbsY,
+    Long,
+    LongX,
+    AbsInd,
+    AbsIndX,
+    AbsIndLong,
+    Rel8,
+    Rel16,
+    Sr,
+    SrIndY,
+    Move,
+}
+
+use Mode::*;
+
+/// mnemonic and addressing mode of every 65816 opcode.
+const OPCODES: [(&str, Mode); 256] = [
+    ("BRK", Imm8), ("ORA", DpIndX), ("COP", Imm8), ("ORA", Sr), ("TSB", Dp), ("ORA", Dp), ("ASL", Dp), ("ORA", DpIndLong),
+    ("PHP", Imp), ("ORA", ImmM), ("ASL", Acc), ("PHD", Imp), ("TSB", Abs), ("ORA", Abs), ("ASL", Abs), ("ORA", Long),
+    ("BPL", Rel8), ("ORA", DpIndY), ("ORA", DpInd), ("ORA", SrIndY), ("TRB", Dp), ("ORA", DpX), ("ASL", DpX), ("ORA", DpIndLongY),
+    ("CLC", Imp), ("ORA", AbsY), ("INC", Acc), ("TCS", Imp), ("TRB", Abs), ("ORA", AbsX), ("ASL", AbsX), ("ORA", LongX),
+    ("JSR", Abs), ("AND", DpIndX), ("JSL", Long), ("AND", Sr), ("BIT", Dp), ("AND", Dp), ("ROL", Dp), ("AND", DpIndLong),
+    ("PLP", Imp), ("AND", ImmM), ("ROL", Acc), ("PLD", Imp), ("BIT", Abs), ("AND", Abs), ("ROL", Abs), ("AND", Long),
+    ("BMI", Rel8), ("AND", DpIndY), ("AND", DpInd), ("AND", SrIndY), ("BIT", DpX), ("AND", DpX), ("ROL", DpX), ("AND", DpIndLongY),
+    ("SEC", Imp), ("AND", AbsY), ("DEC", Acc), ("TSC", Imp), ("BIT", AbsX), ("AND", AbsX), ("ROL", AbsX), ("AND", LongX),
+    ("RTI", Imp), ("EOR", DpIndX), ("WDM", Imm8), ("EOR", Sr), ("MVP", Move), ("EOR", Dp), ("LSR", Dp), ("EOR", DpIndLong),
+    ("PHA", Imp), ("EOR", ImmM), ("LSR", Acc), ("PHK", Imp), ("JMP", Abs), ("EOR", Abs), ("LSR", Abs), ("EOR", Long),
+    ("BVC", Rel8), ("EOR", DpIndY), ("EOR", DpInd), ("EOR", SrIndY), ("MVN", Move), ("EOR", DpX), ("LSR", DpX), ("EOR", DpIndLongY),
+    ("CLI", Imp), ("EOR", AbsY), ("PHY", Imp), ("TCD", Imp), ("JML", Long), ("EOR", AbsX), ("LSR", AbsX), ("EOR", LongX),
+    ("RTS", Imp), ("ADC", DpIndX), ("PER", Rel16), ("ADC", Sr), ("STZ", Dp), ("ADC", Dp), ("ROR", Dp), ("ADC", DpIndLong),
+    ("PLA", Imp), ("ADC", ImmM), ("ROR", Acc), ("RTL", Imp), ("JMP", AbsInd), ("ADC", Abs), ("ROR", Abs), ("ADC", Long),
+    ("BVS", Rel8), ("ADC", DpIndY), ("ADC", DpInd), ("ADC", SrIndY), ("STZ", DpX), ("ADC", DpX), ("ROR", DpX), ("ADC", DpIndLongY),
+    ("SEI", Imp), ("ADC", AbsY), ("PLY", Imp), ("TDC", Imp), ("JMP", AbsIndX), ("ADC", AbsX), ("ROR", AbsX), ("ADC", LongX),
+    ("BRA", Rel8), ("STA", DpIndX), ("BRL", Rel16), ("STA", Sr), ("STY", Dp), ("STA", Dp), ("STX", Dp), ("STA", DpIndLong),
+    ("DEY", Imp), ("BIT", ImmM), ("TXA", Imp), ("PHB", Imp), ("STY", Abs), ("STA", Abs), ("STX", Abs), ("STA", Long),
+    ("BCC", Rel8), ("STA", DpIndY), ("STA", DpInd), ("STA", SrIndY), ("STY", DpX), ("STA", DpX), ("STX", DpY), ("STA", DpIndLongY),
+    ("TYA", Imp), ("STA", AbsY), ("TXS", Imp), ("TXY", Imp), ("STZ", Abs), ("STA", AbsX), ("STZ", AbsX), ("STA", LongX),
+    ("LDY", ImmX), ("LDA", DpIndX), ("LDX", ImmX), ("LDA", Sr), ("LDY", Dp), ("LDA", Dp), ("LDX", Dp), ("LDA", DpIndLong),
+    ("TAY", Imp), ("LDA", ImmM), ("TAX", Imp), ("PLB", Imp), ("LDY", Abs), ("LDA", Abs), ("LDX", Abs), ("LDA", Long),
+    ("BCS", Rel8), ("LDA", DpIndY), ("LDA", DpInd), ("LDA", SrIndY), ("LDY", DpX), ("LDA", DpX), ("LDX", DpY), ("LDA", DpIndLongY),
+    ("CLV", Imp), ("LDA", AbsY), ("TSX", Imp), ("TYX", Imp), ("LDY", AbsX), ("LDA", AbsX), ("LDX", AbsY), ("LDA", LongX),
+    ("CPY", ImmX), ("CMP", DpIndX), ("REP", Imm8), ("CMP", Sr), ("CPY", Dp), ("CMP", Dp), ("DEC", Dp), ("CMP", DpIndLong),
+    ("INY", Imp), ("CMP", ImmM), ("DEX", Imp), ("WAI", Imp), ("CPY", Abs), ("CMP", Abs), ("DEC", Abs), ("CMP", Long),
+    ("BNE", Rel8), ("CMP", DpIndY), ("CMP", DpInd), ("CMP", SrIndY), ("PEI", DpInd), ("CMP", DpX), ("DEC", DpX), ("CMP", DpIndLongY),
+    ("CLD", Imp), ("CMP", AbsY), ("PHX", Imp), ("STP", Imp), ("JML", AbsIndLong), ("CMP", AbsX), ("DEC", AbsX), ("CMP", LongX),
+    ("CPX", ImmX), ("SBC", DpIndX), ("SEP", Imm8), ("SBC", Sr), ("CPX", Dp), ("SBC", Dp), ("INC", Dp), ("SBC", DpIndLong),
+    ("INX", Imp), ("SBC", ImmM), ("NOP", Imp), ("XBA", Imp), ("CPX", Abs), ("SBC", Abs), ("INC", Abs), ("SBC", Long),
+    ("BEQ", Rel8), ("SBC", DpIndY), ("SBC", DpInd), ("SBC", SrIndY), ("PEA", Abs), ("SBC", DpX), ("INC", DpX), ("SBC", DpIndLongY),
+    ("SED", Imp), ("SBC", AbsY), ("PLX", Imp), ("XCE", Imp), ("JSR", AbsIndX), ("SBC", AbsX), ("INC", AbsX), ("SBC", LongX),
+];
+
+/// per row of [OPCODES], the columns that are documented 6502 opcodes.
+const NMOS_OPCODES: [u16; 16] = [
+    0x6763, 0x6363, 0x7773, 0x6363, 0x7763, 0x6363, 0x7763, 0x6363,
+    0x7572, 0x2773, 0x7777, 0x7773, 0x7773, 0x6363, 0x7773, 0x6363,
+];
+
+/// The register widths a 65816 decodes immediates with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Widths {
+    pub(crate) wide_accumulator: bool,
+    pub(crate) wide_index: bool,
+}
+
+/// decodes the instruction at the start of `data`, returning its length and text.
+///
+/// `widths` is [None] for a 6502 and is updated by `REP` and `SEP` otherwise.
+pub(crate) fn decode(data: &[u8], widths: &mut Option<Widths>) -> Option<(usize, String)> {
+    let opcode = *data.first()?;
+    let (mnemonic, mode) = OPCODES[opcode as usize];
+    let nmos = widths.is_none();
+    if nmos && NMOS_OPCODES[(opcode >> 4) as usize] & (1 << (opcode & 0xF)) == 0 {
+        return None;
+    }
+    let wide = |x: fn(&Widths) -> bool| widths.as_ref().is_some_and(x);
+    let operand_len = match mode {
+        Imp | Acc => 0,
+        // the 6502 BRK has no signature byte
+        Imm8 if nmos => 0,
+        ImmM => if wide(|x| x.wide_accumulator) { 2 } else { 1 },
+        ImmX => if wide(|x| x.wide_index) { 2 } else { 1 },
+        Imm8 | Dp | DpX | DpY | DpInd | DpIndX | DpIndY | DpIndLong | DpIndLongY | Rel8 | Sr | SrIndY => 1,
+        Abs | AbsX | AbsY | AbsInd | AbsIndX | AbsIndLong | Rel16 | Move => 2,
+        Long | LongX => 3,
+    };
+    let operand = data.get(1..1 + operand_len)?;
+    let value = operand.iter().rev().fold(0u32, |acc, x| acc << 8 | *x as u32);
+    let len = 1 + operand_len;
+
+    if let (Some(widths), "REP" | "SEP") = (widths.as_mut(), mnemonic) {
+        let set = mnemonic == "SEP";
+        if value & 0x20 != 0 {
+            widths.wide_accumulator = !set;
+        }
+        if value & 0x10 != 0 {
+            widths.wide_index = !set;
+        }
+    }
+
+    let hex = |x: u32| format!("${:0width$X}", x, width = operand_len * 2);
+    let text = match mode {
+        Imp => return Some((len, mnemonic.to_string())),
+        Imm8 if nmos => return Some((len, mnemonic.to_string())),
+        Acc => "A".to_string(),
+        ImmM | ImmX | Imm8 => format!("#{}", hex(value)),
+        Dp | Abs | Long => hex(value),
+        DpX | AbsX | LongX => format!("{},X", hex(value)),
+        DpY | AbsY => format!("{},Y", hex(value)),
+        DpInd | AbsInd => format!("({})", hex(value)),
+        DpIndX | AbsIndX => format!("({},X)", hex(value)),
+        DpIndY => format!("({}),Y", hex(value)),
+        DpIndLong | AbsIndLong => format!("[{}]", hex(value)),
+        DpIndLongY => format!("[{}],Y", hex(value)),
+        Rel8 => relative(len as i64 + value as u8 as i8 as i64),
+        Rel16 => relative(len as i64 + value as u16 as i16 as i64),
+        Sr => format!("{},S", hex(value)),
+        SrIndY => format!("({},S),Y", hex(value)),
+        // the destination bank comes first in the encoding, but last in the syntax
+        Move => format!("${:02X},${:02X}", operand[1], operand[0]),
+    };
+    return Some((len, format!("{} {}", mnemonic, text)));
+}
+
+#[cfg(test)]
+mod tests {
+    use spectral::prelude::*;
+
+    use super::*;
+
+    fn decode_text(data: &[u8], widths: &mut Option<Widths>) -> Option<String> {
+        decode(data, widths).map(|(_, text)| text)
+    }
+
+    #[test]
+    fn decodes_6502() {
+        let mut widths = None;
+        assert_that!(decode_text(&[0xA9, 0x01], &mut widths)).is_equal_to(Some("LDA #$01".to_string()));
+        assert_that!(decode_text(&[0x8D, 0x00, 0x21], &mut widths)).is_equal_to(Some("STA $2100".to_string()));
+        assert_that!(decode_text(&[0xD0, 0xFC], &mut widths)).is_equal_to(Some("BNE *-2".to_string()));
+        // STZ is 65C02 and later only
+        assert_that!(decode_text(&[0x64, 0x00], &mut widths)).is_none();
+    }
+
+    #[test]
+    fn rep_and_sep_change_immediate_widths() {
+        let mut widths = Some(Widths { wide_accumulator: false, wide_index: false });
+        assert_that!(decode(&[0xC2, 0x30], &mut widths)).is_equal_to(Some((2, "REP #$30".to_string())));
+        assert_that!(decode(&[0xA9, 0x34, 0x12], &mut widths)).is_equal_to(Some((3, "LDA #$1234".to_string())));
+        decode(&[0xE2, 0x20], &mut widths);
+        assert_that!(decode(&[0xA9, 0x34, 0x12], &mut widths)).is_equal_to(Some((2, "LDA #$34".to_string())));
+        assert_that!(decode(&[0xA2, 0x34, 0x12], &mut widths)).is_equal_to(Some((3, "LDX #$1234".to_string())));
+    }
+
+    #[test]
+    fn truncated_instructions_are_not_decoded() {
+        let mut widths = Some(Widths { wide_accumulator: false, wide_index: false });
+        assert_that!(decode(&[0x22, 0x00, 0x80], &mut widths)).is_none();
+    }
+}
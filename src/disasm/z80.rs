@@ -0,0 +1,224 @@
+//! Decoding Z80 instructions, following the opcode structure described at
+//! <http://www.z80.info/decoding.htm>.
+
+use super::relative;
+
+const R: [&str; 8] = ["B", "C", "D", "E", "H", "L", "(HL)", "A"];
+const RP: [&str; 4] = ["BC", "DE", "HL", "SP"];
+const RP2: [&str; 4] = ["BC", "DE", "HL", "AF"];
+const CC: [&str; 8] = ["NZ", "Z", "NC", "C", "PO", "PE", "P", "M"];
+const ALU: [&str; 8] = ["ADD A,", "ADC A,", "SUB ", "SBC A,", "AND ", "XOR ", "OR ", "CP "];
+const ROT: [&str; 8] = ["RLC", "RRC", "RL", "RR", "SLA", "SRA", "SLL", "SRL"];
+const IM: [&str; 8] = ["0", "0/1", "1", "2", "0", "0/1", "1", "2"];
+const BLOCK: [[&str; 4]; 4] = [
+    ["LDI", "CPI", "INI", "OUTI"],
+    ["LDD", "CPD", "IND", "OUTD"],
+    ["LDIR", "CPIR", "INIR", "OTIR"],
+    ["LDDR", "CPDR", "INDR", "OTDR"],
+];
+
+/// the bytes of an instruction being decoded, with `HL` replaced by `IX` or `IY` after a `DD` or
+/// `FD` prefix.
+struct Decoder<'a> {
+    data: &'a [u8],
+    position: usize,
+    index: Option<&'static str>,
+    displacement: Option<i8>,
+}
+
+impl<'a> Decoder<'a> {
+    fn byte(&mut self) -> Option<u8> {
+        let result = *self.data.get(self.position)?;
+        self.position += 1;
+        return Some(result);
+    }
+
+    fn word(&mut self) -> Option<u16> {
+        let low = self.byte()?;
+        let high = self.byte()?;
+        return Some(u16::from_le_bytes([low, high]));
+    }
+
+    fn hl(&self) -> &'static str {
+        self.index.unwrap_or("HL")
+    }
+
+    /// returns the 8-bit register `i`, reading the displacement of `(IX+d)` if needed.
+    ///
+    /// `H` and `L` only become the halves of the index register if no `(IX+d)` is involved.
+    fn r(&mut self, i: u8, memory: bool) -> Option<String> {
+        match (i, self.index) {
+            (6, Some(index)) => {
+                let displacement = match self.displacement {
+                    Some(x) => x,
+                    None => self.byte()? as i8,
+                };
+                self.displacement = Some(displacement);
+                Some(format!("({}{:+})", index, displacement))
+            }
+            (4 | 5, Some(index)) if !memory => Some(format!("{}{}", index, R[i as usize])),
+            _ => Some(R[i as usize].to_string()),
+        }
+    }
+
+    fn rp(&self, p: u8) -> &'static str {
+        if p == 2 { self.hl() } else { RP[p as usize] }
+    }
+
+    fn rp2(&self, p: u8) -> &'static str {
+        if p == 2 { self.hl() } else { RP2[p as usize] }
+    }
+}
+
+/// decodes the instruction at the start of `data`, returning its length and text.
+pub(crate) fn decode(data: &[u8]) -> Option<(usize, String)> {
+    let mut decoder = Decoder { data, position: 0, index: None, displacement: None };
+    let mut opcode = decoder.byte()?;
+    if opcode == 0xDD || opcode == 0xFD {
+        decoder.index = Some(if opcode == 0xDD { "IX" } else { "IY" });
+        opcode = decoder.byte()?;
+    }
+    let text = match opcode {
+        0xCB => decode_cb(&mut decoder)?,
+        0xED if decoder.index.is_none() => decode_ed(&mut decoder)?,
+        // a prefix followed by another prefix has no effect of its own
+        0xDD | 0xED | 0xFD => return Some((1, "NOP".to_string())),
+        _ => decode_unprefixed(&mut decoder, opcode)?,
+    };
+    return Some((decoder.position, text));
+}
+
+fn decode_unprefixed(d: &mut Decoder, opcode: u8) -> Option<String> {
+    let (x, y, z) = (opcode >> 6, (opcode >> 3) & 7, opcode & 7);
+    let (p, q) = (y >> 1, y & 1);
+    let text = match (x, z) {
+        (0, 0) => match y {
+            0 => "NOP".to_string(),
+            1 => "EX AF,AF'".to_string(),
+            2 => format!("DJNZ {}", relative(2 + d.byte()? as i8 as i64)),
+            3 => format!("JR {}", relative(2 + d.byte()? as i8 as i64)),
+            _ => format!("JR {},{}", CC[y as usize - 4], relative(2 + d.byte()? as i8 as i64)),
+        },
+        (0, 1) if q == 0 => format!("LD {},${:04X}", d.rp(p), d.word()?),
+        (0, 1) => format!("ADD {},{}", d.hl(), d.rp(p)),
+        (0, 2) => match (q, p) {
+            (0, 0) => "LD (BC),A".to_string(),
+            (0, 1) => "LD (DE),A".to_string(),
+            (0, 2) => format!("LD (${:04X}),{}", d.word()?, d.hl()),
+            (0, _) => format!("LD (${:04X}),A", d.word()?),
+            (_, 0) => "LD A,(BC)".to_string(),
+            (_, 1) => "LD A,(DE)".to_string(),
+            (_, 2) => format!("LD {},(${:04X})", d.hl(), d.word()?),
+            _ => format!("LD A,(${:04X})", d.word()?),
+        },
+        (0, 3) => format!("{} {}", if q == 0 { "INC" } else { "DEC" }, d.rp(p)),
+        (0, 4) => format!("INC {}", d.r(y, y == 6)?),
+        (0, 5) => format!("DEC {}", d.r(y, y == 6)?),
+        (0, 6) => {
+            let register = d.r(y, y == 6)?;
+            format!("LD {},${:02X}", register, d.byte()?)
+        }
+        (0, _) => ["RLCA", "RRCA", "RLA", "RRA", "DAA", "CPL", "SCF", "CCF"][y as usize].to_string(),
+        (1, 6) if y == 6 => "HALT".to_string(),
+        (1, _) => {
+            let memory = y == 6 || z == 6;
+            let target = d.r(y, memory)?;
+            format!("LD {},{}", target, d.r(z, memory)?)
+        }
+        (2, _) => format!("{}{}", ALU[y as usize], d.r(z, z == 6)?),
+        (_, 0) => format!("RET {}", CC[y as usize]),
+        (_, 1) => match (q, p) {
+            (0, _) => format!("POP {}", d.rp2(p)),
+            (_, 0) => "RET".to_string(),
+            (_, 1) => "EXX".to_string(),
+            (_, 2) => format!("JP ({})", d.hl()),
+            _ => format!("LD SP,{}", d.hl()),
+        },
+        (_, 2) => format!("JP {},${:04X}", CC[y as usize], d.word()?),
+        (_, 3) => match y {
+            0 => format!("JP ${:04X}", d.word()?),
+            2 => format!("OUT (${:02X}),A", d.byte()?),
+            3 => format!("IN A,(${:02X})", d.byte()?),
+            4 => format!("EX (SP),{}", d.hl()),
+            5 => "EX DE,HL".to_string(),
+            6 => "DI".to_string(),
+            // 1 is the CB prefix, which is handled before
+            _ => "EI".to_string(),
+        },
+        (_, 4) => format!("CALL {},${:04X}", CC[y as usize], d.word()?),
+        (_, 5) if q == 0 => format!("PUSH {}", d.rp2(p)),
+        // the other prefixes are handled before
+        (_, 5) => format!("CALL ${:04X}", d.word()?),
+        (_, 6) => format!("{}${:02X}", ALU[y as usize], d.byte()?),
+        _ => format!("RST ${:02X}", y * 8),
+    };
+    return Some(text);
+}
+
+fn decode_cb(d: &mut Decoder) -> Option<String> {
+    // with an index prefix the displacement comes before the opcode
+    if d.index.is_some() {
+        d.displacement = Some(d.byte()? as i8);
+    }
+    let opcode = d.byte()?;
+    let (x, y, z) = (opcode >> 6, (opcode >> 3) & 7, opcode & 7);
+    let operand = if d.index.is_some() { d.r(6, true)? } else { d.r(z, false)? };
+    let text = match x {
+        0 => format!("{} {}", ROT[y as usize], operand),
+        1 => format!("BIT {},{}", y, operand),
+        2 => format!("RES {},{}", y, operand),
+        _ => format!("SET {},{}", y, operand),
+    };
+    return Some(text);
+}
+
+fn decode_ed(d: &mut Decoder) -> Option<String> {
+    let opcode = d.byte()?;
+    let (x, y, z) = (opcode >> 6, (opcode >> 3) & 7, opcode & 7);
+    let (p, q) = (y >> 1, y & 1);
+    let text = match (x, z) {
+        (1, 0) if y == 6 => "IN (C)".to_string(),
+        (1, 0) => format!("IN {},(C)", R[y as usize]),
+        (1, 1) if y == 6 => "OUT (C),0".to_string(),
+        (1, 1) => format!("OUT (C),{}", R[y as usize]),
+        (1, 2) => format!("{} HL,{}", if q == 0 { "SBC" } else { "ADC" }, RP[p as usize]),
+        (1, 3) if q == 0 => format!("LD (${:04X}),{}", d.word()?, RP[p as usize]),
+        (1, 3) => format!("LD {},(${:04X})", RP[p as usize], d.word()?),
+        (1, 4) => "NEG".to_string(),
+        (1, 5) => if y == 1 { "RETI".to_string() } else { "RETN".to_string() },
+        (1, 6) => format!("IM {}", IM[y as usize]),
+        (1, _) => ["LD I,A", "LD R,A", "LD A,I", "LD A,R", "RRD", "RLD", "NOP", "NOP"][y as usize].to_string(),
+        (2, 0..=3) if y >= 4 => BLOCK[y as usize - 4][z as usize].to_string(),
+        _ => return None,
+    };
+    return Some(text);
+}
+
+#[cfg(test)]
+mod tests {
+    use spectral::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn decodes_unprefixed_instructions() {
+        assert_that!(decode(&[0x3E, 0x05])).is_equal_to(Some((2, "LD A,$05".to_string())));
+        assert_that!(decode(&[0xC3, 0x50, 0x01])).is_equal_to(Some((3, "JP $0150".to_string())));
+        assert_that!(decode(&[0x20, 0xFE])).is_equal_to(Some((2, "JR NZ,*+0".to_string())));
+        assert_that!(decode(&[0x76])).is_equal_to(Some((1, "HALT".to_string())));
+    }
+
+    #[test]
+    fn decodes_prefixed_instructions() {
+        assert_that!(decode(&[0xCB, 0x7F])).is_equal_to(Some((2, "BIT 7,A".to_string())));
+        assert_that!(decode(&[0xED, 0xB0])).is_equal_to(Some((2, "LDIR".to_string())));
+        assert_that!(decode(&[0xDD, 0x7E, 0x05])).is_equal_to(Some((3, "LD A,(IX+5)".to_string())));
+        assert_that!(decode(&[0xFD, 0x66, 0xFE])).is_equal_to(Some((3, "LD H,(IY-2)".to_string())));
+        assert_that!(decode(&[0xDD, 0xCB, 0x02, 0xC6])).is_equal_to(Some((4, "SET 0,(IX+2)".to_string())));
+    }
+
+    #[test]
+    fn undefined_ed_opcodes_are_not_decoded() {
+        assert_that!(decode(&[0xED, 0x00])).is_none();
+    }
+}
@@ -0,0 +1,298 @@
+//! DLDI driver patching for Nintendo DS homebrew.
+//!
+//! Homebrew linked against libfat reserves space for a DLDI ("dynamically linked device interface")
+//! driver behind a magic string. Patching replaces that stub with the driver for a specific flash
+//! cart, relocating the driver's pointers to where the stub is loaded in memory. This follows the
+//! behaviour of the reference `dlditool`.
+
+use crate::Error;
+use crate::ErrorKind::{ParsingError, PatchingError};
+
+/// Magic string every DLDI section starts with.
+pub const MAGIC: [u8; 12] = [0xED, 0xA5, 0x8D, 0xBF, b' ', b'C', b'h', b'i', b's', b'h', b'm', 0];
+
+/// Size of the DLDI header. Driver code follows it.
+pub const HEADER_SIZE: usize = 0x80;
+
+const VERSION: usize = 0x0C;
+const DRIVER_SIZE: usize = 0x0D;
+const FIX_SECTIONS: usize = 0x0E;
+const ALLOCATED_SPACE: usize = 0x0F;
+const FRIENDLY_NAME: usize = 0x10;
+const TEXT_START: usize = 0x40;
+const DATA_END: usize = 0x44;
+const GLUE_START: usize = 0x48;
+const GLUE_END: usize = 0x4C;
+const GOT_START: usize = 0x50;
+const GOT_END: usize = 0x54;
+const BSS_START: usize = 0x58;
+const BSS_END: usize = 0x5C;
+const IO_TYPE: usize = 0x60;
+const FEATURES: usize = 0x64;
+const STARTUP: usize = 0x68;
+const SHUTDOWN: usize = 0x7C;
+
+/// Relocate every pointer into the driver found in its text and data sections.
+pub const FIX_ALL: u8 = 0x01;
+/// Relocate pointers into the driver found in the glue section.
+pub const FIX_GLUE: u8 = 0x02;
+/// Relocate pointers into the driver found in the global offset table.
+pub const FIX_GOT: u8 = 0x04;
+/// Zero the bss section.
+pub const FIX_BSS: u8 = 0x08;
+
+/// The header of a DLDI section or driver.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DldiHeader {
+    /// version of the DLDI interface.
+    pub version: u8,
+    /// size the driver needs in memory, as a power of two.
+    pub driver_size_log2: u8,
+    /// [FIX_ALL], [FIX_GLUE], [FIX_GOT] and [FIX_BSS] flags.
+    pub fix_sections: u8,
+    /// space reserved for a driver, as a power of two.
+    pub allocated_space_log2: u8,
+    /// human readable name of the driver.
+    pub friendly_name: String,
+    /// four character code of the device the driver is for.
+    pub io_type: [u8; 4],
+    /// feature flags of the driver.
+    pub features: u32,
+    /// address the driver is linked to run at.
+    pub text_start: u32,
+}
+
+impl DldiHeader {
+    /// reads the header at the start of `section`.
+    pub fn read(section: &[u8]) -> Result<DldiHeader, Error> {
+        if section.len() < HEADER_SIZE || section[..MAGIC.len()] != MAGIC {
+            return Err(Error::new(ParsingError).with_description("Invalid DLDI header.".to_string()));
+        }
+        let name = &section[FRIENDLY_NAME..TEXT_START];
+        let name_len = name.iter().position(|x| *x == 0).unwrap_or(name.len());
+        Ok(DldiHeader {
+            version: section[VERSION],
+            driver_size_log2: section[DRIVER_SIZE],
+            fix_sections: section[FIX_SECTIONS],
+            allocated_space_log2: section[ALLOCATED_SPACE],
+            friendly_name: String::from_utf8_lossy(&name[..name_len]).into_owned(),
+            io_type: [section[IO_TYPE], section[IO_TYPE + 1], section[IO_TYPE + 2], section[IO_TYPE + 3]],
+            features: read_addr(section, FEATURES),
+            text_start: read_addr(section, TEXT_START),
+        })
+    }
+}
+
+/// returns the offset of the DLDI section in `app`, if it has one.
+pub fn find_section(app: &[u8]) -> Option<usize> {
+    app.windows(MAGIC.len()).position(|x| x == MAGIC)
+}
+
+/// patches the DLDI section of `app` with `driver`.
+///
+/// Returns the header of the section that was replaced. `app` is left unchanged if patching fails.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::fs;
+/// use rom_patcher::dldi;
+///
+/// let mut app = fs::read("homebrew.nds").expect("Unable to read app.");
+/// let driver = fs::read("r4tf.dldi").expect("Unable to read driver.");
+/// dldi::patch(&mut app, &driver).expect("Unable to patch app.");
+/// fs::write("homebrew.nds", app).expect("Unable to write app.");
+/// ```
+pub fn patch(app: &mut [u8], driver: &[u8]) -> Result<DldiHeader, Error> {
+    let driver_header = DldiHeader::read(driver)
+        .map_err(|_| Error::new(ParsingError).with_description("Invalid DLDI driver.".to_string()))?;
+    let offset = find_section(app)
+        .ok_or_else(|| Error::new(PatchingError).with_description("No DLDI section found.".to_string()))?;
+    let stub = DldiHeader::read(&app[offset..])?;
+
+    let allocated = 1u64 << stub.allocated_space_log2.min(32);
+    if driver_header.driver_size_log2 > stub.allocated_space_log2 || driver.len() as u64 > allocated {
+        return Err(Error::new(PatchingError).with_description(format!(
+            "DLDI driver needs {} bytes but only {} bytes are allocated.",
+            (1u64 << driver_header.driver_size_log2.min(32)).max(driver.len() as u64),
+            allocated
+        )));
+    }
+    let end = (offset as u64 + allocated).min(app.len() as u64) as usize;
+    if offset + driver.len() > end {
+        return Err(Error::new(PatchingError).with_description("DLDI section exceeds the app.".to_string()));
+    }
+
+    // where the stub is loaded in memory decides where the driver has to be relocated to
+    let mut memory_offset = read_addr(&app[offset..], TEXT_START);
+    if memory_offset == 0 {
+        memory_offset = read_addr(&app[offset..], STARTUP).wrapping_sub(HEADER_SIZE as u32);
+    }
+    let driver_start = driver_header.text_start;
+    let driver_end = driver_start.wrapping_add(1 << driver_header.driver_size_log2.min(31));
+    let relocation = memory_offset.wrapping_sub(driver_start);
+
+    let mut section = app[offset..end].to_vec();
+    let section = section.as_mut_slice();
+    section[..driver.len()].copy_from_slice(driver);
+    // the app keeps its reserved space, whatever the driver was built for
+    section[ALLOCATED_SPACE] = stub.allocated_space_log2;
+
+    for field in (TEXT_START..=BSS_END).step_by(4).chain((STARTUP..=SHUTDOWN).step_by(4)) {
+        write_addr(section, field, read_addr(section, field).wrapping_add(relocation));
+    }
+
+    let relocate = |section: &mut [u8], start: usize, end: usize| -> Result<(), Error> {
+        let from = (read_addr(driver, start).wrapping_sub(driver_start)) as usize;
+        let to = (read_addr(driver, end).wrapping_sub(driver_start)) as usize;
+        if from > to || to > driver.len() {
+            return Err(Error::new(PatchingError).with_description("Invalid DLDI driver section.".to_string()));
+        }
+        for address in (from..to - (to - from) % 4).step_by(4) {
+            let value = read_addr(section, address);
+            if driver_start <= value && value < driver_end {
+                write_addr(section, address, value.wrapping_add(relocation));
+            }
+        }
+        Ok(())
+    };
+    let fix = driver_header.fix_sections;
+    if fix & FIX_ALL != 0 {
+        relocate(section, TEXT_START, DATA_END)?;
+    }
+    if fix & FIX_GLUE != 0 {
+        relocate(section, GLUE_START, GLUE_END)?;
+    }
+    if fix & FIX_GOT != 0 {
+        relocate(section, GOT_START, GOT_END)?;
+    }
+    if fix & FIX_BSS != 0 {
+        let from = (read_addr(driver, BSS_START).wrapping_sub(driver_start)) as usize;
+        let to = (read_addr(driver, BSS_END).wrapping_sub(driver_start)) as usize;
+        if from > to || to as u64 > allocated || to > section.len() {
+            return Err(Error::new(PatchingError).with_description("Invalid DLDI driver section.".to_string()));
+        }
+        section[from..to].fill(0);
+    }
+    app[offset..end].copy_from_slice(section);
+    Ok(stub)
+}
+
+fn read_addr(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]])
+}
+
+fn write_addr(data: &mut [u8], offset: usize, value: u32) {
+    data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use spectral::prelude::*;
+
+    use super::*;
+
+    const APP_OFFSET: usize = 0x100;
+    const STUB_ADDRESS: u32 = 0x0200_1000;
+    const DRIVER_ADDRESS: u32 = 0xBF80_0000;
+
+    fn header(driver_size: u8, fix: u8, allocated: u8, name: &str, text_start: u32) -> Vec<u8> {
+        let mut result = vec![0; HEADER_SIZE];
+        result[..MAGIC.len()].copy_from_slice(&MAGIC);
+        result[VERSION] = 1;
+        result[DRIVER_SIZE] = driver_size;
+        result[FIX_SECTIONS] = fix;
+        result[ALLOCATED_SPACE] = allocated;
+        result[FRIENDLY_NAME..FRIENDLY_NAME + name.len()].copy_from_slice(name.as_bytes());
+        write_addr(&mut result, TEXT_START, text_start);
+        return result;
+    }
+
+    fn app() -> Vec<u8> {
+        let mut result = vec![0xEE; APP_OFFSET + 0x400];
+        let stub = header(10, 0, 10, "Default (No interface)", STUB_ADDRESS);
+        result[APP_OFFSET..APP_OFFSET + HEADER_SIZE].copy_from_slice(&stub);
+        return result;
+    }
+
+    fn driver() -> Vec<u8> {
+        let mut result = header(9, FIX_ALL | FIX_BSS, 9, "Test driver", DRIVER_ADDRESS);
+        result.resize(0x100, 0);
+        write_addr(&mut result, DATA_END, DRIVER_ADDRESS + 0x100);
+        write_addr(&mut result, GLUE_START, DRIVER_ADDRESS + 0x100);
+        write_addr(&mut result, GLUE_END, DRIVER_ADDRESS + 0x100);
+        write_addr(&mut result, GOT_START, DRIVER_ADDRESS + 0x100);
+        write_addr(&mut result, GOT_END, DRIVER_ADDRESS + 0x100);
+        write_addr(&mut result, BSS_START, DRIVER_ADDRESS + 0x100);
+        write_addr(&mut result, BSS_END, DRIVER_ADDRESS + 0x110);
+        result[IO_TYPE..IO_TYPE + 4].copy_from_slice(b"TEST");
+        write_addr(&mut result, STARTUP, DRIVER_ADDRESS + 0x80);
+        // code referencing the driver and something outside of it
+        write_addr(&mut result, 0x80, DRIVER_ADDRESS + 0x84);
+        write_addr(&mut result, 0x84, 0x1234_5678);
+        return result;
+    }
+
+    #[test]
+    fn finds_section() {
+        assert_that!(find_section(&app())).is_equal_to(Some(APP_OFFSET));
+        assert_that!(find_section(&[0; 64])).is_none();
+    }
+
+    #[test]
+    fn reads_header() {
+        let header = DldiHeader::read(&driver()).unwrap();
+        assert_that!(header.friendly_name).is_equal_to("Test driver".to_string());
+        assert_that!(header.io_type).is_equal_to(*b"TEST");
+        assert_that!(header.driver_size_log2).is_equal_to(9);
+    }
+
+    #[test]
+    fn patch_copies_and_relocates_driver() {
+        let mut app = app();
+        let stub = patch(&mut app, &driver()).unwrap();
+        assert_that!(stub.friendly_name).is_equal_to("Default (No interface)".to_string());
+
+        let section = &app[APP_OFFSET..];
+        let patched = DldiHeader::read(section).unwrap();
+        assert_that!(patched.friendly_name).is_equal_to("Test driver".to_string());
+        assert_that!(patched.allocated_space_log2).is_equal_to(10);
+        assert_that!(patched.text_start).is_equal_to(STUB_ADDRESS);
+        assert_that!(read_addr(section, STARTUP)).is_equal_to(STUB_ADDRESS + 0x80);
+        assert_that!(read_addr(section, BSS_END)).is_equal_to(STUB_ADDRESS + 0x110);
+        assert_that!(read_addr(section, 0x80)).is_equal_to(STUB_ADDRESS + 0x84);
+        assert_that!(read_addr(section, 0x84)).is_equal_to(0x1234_5678);
+        assert_that!(section[0x100..0x110].to_vec()).is_equal_to(vec![0; 16]);
+        assert_that!(section[0x110]).is_equal_to(0xEE);
+    }
+
+    #[test]
+    fn patch_rejects_drivers_larger_than_allocated_space() {
+        let mut driver = driver();
+        driver[DRIVER_SIZE] = 11;
+        let err = patch(&mut app(), &driver).unwrap_err();
+        assert_that!(err.to_string())
+            .is_equal_to("PatchingError: DLDI driver needs 2048 bytes but only 1024 bytes are allocated.".to_string());
+    }
+
+    #[test]
+    fn failed_patch_leaves_app_unchanged() {
+        let mut driver = driver();
+        write_addr(&mut driver, BSS_END, DRIVER_ADDRESS + 0x1000);
+        let mut app = app();
+        assert_that!(patch(&mut app, &driver)).is_err();
+        assert_that!(app).is_equal_to(self::app());
+    }
+
+    #[test]
+    fn patch_requires_a_section() {
+        let err = patch(&mut [0; 0x200], &driver()).unwrap_err();
+        assert_that!(err.to_string()).is_equal_to("PatchingError: No DLDI section found.".to_string());
+    }
+
+    #[test]
+    fn patch_requires_a_valid_driver() {
+        let err = patch(&mut app(), &[0; 0x80]).unwrap_err();
+        assert_that!(err.to_string()).is_equal_to("ParsingError: Invalid DLDI driver.".to_string());
+    }
+}
@@ -1,6 +1,8 @@
 use std::error;
 use std::fmt::{Display, Formatter};
 
+use crate::messages::{English, Localizer, Message, Suggestion};
+
 /// represents the kind of error that occurred.
 #[derive(Debug, Clone)]
 pub enum ErrorKind {
@@ -8,6 +10,20 @@ pub enum ErrorKind {
     PatchingError,
     /// An error that occurs when trying to parse a patch file.
     ParsingError,
+    /// An error that occurs when creating a patch.
+    CreatingError,
+    /// An error that occurs when a patch would violate one of its invariants.
+    ValidationError,
+    /// An error that occurs when patch data is not of any known format.
+    UnsupportedFormat,
+    /// An error that occurs when an operation was cancelled before it finished.
+    Cancelled,
+    /// An error that occurs when the source rom isn't the one a patch was made for.
+    WrongSource,
+    /// An error that occurs when an output doesn't have its expected checksum.
+    ChecksumMismatch,
+    /// An error that occurs when the target already has a patch applied.
+    AlreadyApplied,
 }
 
 /// Represents an error specific to patching roms.
@@ -15,7 +31,8 @@ pub enum ErrorKind {
 pub struct Error {
     kind: ErrorKind,
     description: Option<String>,
-    source: Option<Box<dyn error::Error>>,
+    message: Option<Box<Message>>,
+    source: Option<Box<dyn error::Error + Send + Sync>>,
 }
 
 impl Error {
@@ -24,24 +41,65 @@ impl Error {
         return Error {
             kind,
             description: None,
+            message: None,
             source: None,
         };
     }
 
+    /// returns the kind of the error.
+    pub fn kind(&self) -> &ErrorKind {
+        &self.kind
+    }
+
+    /// returns the description of the error.
+    pub fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+
+    /// returns the message of the error, if it was constructed with [Error::with_message].
+    pub fn message(&self) -> Option<&Message> {
+        self.message.as_deref()
+    }
+
     /// Modifies the error with a given `description`.
     pub fn with_description(self, description: String) -> Error {
         return Error {
             kind: self.kind,
             description: Some(description),
+            message: None,
             source: self.source,
         };
     }
 
+    /// Modifies the error with a given `message`, which also becomes its English description.
+    pub fn with_message(self, message: Message) -> Error {
+        return Error {
+            kind: self.kind,
+            description: Some(English.message(&message)),
+            message: Some(Box::new(message)),
+            source: self.source,
+        };
+    }
+
+    /// returns advice for users on how to resolve the error, if there is any.
+    ///
+    /// Suggestions are derived from the [Message] of the error, so errors constructed without one
+    /// have none.
+    pub fn suggestion(&self) -> Option<Suggestion> {
+        self.message.as_deref().and_then(Suggestion::for_message)
+    }
+
+    /// returns the error rendered by `localizer`, see [crate::messages].
+    pub fn localized(&self, localizer: &dyn Localizer) -> String {
+        localizer.error(self)
+    }
+
     /// Modifies the error with a given `source`.
-    pub fn with_source(self, source: Box<dyn error::Error>) -> Error {
+    pub fn with_source(self, source: Box<dyn error::Error + Send + Sync>) -> Error {
         return Error {
             kind: self.kind,
             description: self.description,
+            message: self.message,
             source: Some(source),
         };
     }
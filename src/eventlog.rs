@@ -0,0 +1,211 @@
+//! Structured event logs of operations, one JSON object per line, for server deployments
+//! aggregating patching telemetry without parsing human readable output.
+//!
+//! Every [Event] records the operation, the file it worked on, how long it took, its
+//! [OperationResult] and the warnings it raised. [EventLog::handle] runs [commands](crate::commands)
+//! and logs each, [crate::server::Server::with_event_log] does so for every request of a server.
+//!
+//! # Examples
+//!
+//! ```
+//! use rom_patcher::commands::Request;
+//! use rom_patcher::eventlog::EventLog;
+//! use rom_patcher::progress::CancellationToken;
+//!
+//! let log = EventLog::new(Vec::new());
+//! log.handle(&Request::Formats, None, &CancellationToken::new());
+//! let line = String::from_utf8(log.into_inner::<Vec<u8>>().unwrap()).unwrap();
+//! assert!(line.starts_with(r#"{"timestamp_ms":"#));
+//! assert!(line.contains(r#""operation":"formats""#));
+//! ```
+
+use std::any::Any;
+use std::fmt::{Debug, Formatter};
+use std::io::{Result as IOResult, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::sync::mpsc::Sender;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::commands::{handle_with_progress, Request, Response};
+use crate::outcome::OperationResult;
+use crate::progress::{CancellationToken, Progress};
+
+/// A finished operation.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Event {
+    /// milliseconds since the Unix epoch at which the operation finished.
+    pub timestamp_ms: u64,
+    /// name of the operation, e.g. `"apply"`.
+    pub operation: String,
+    /// the file the operation worked on, the patch for most operations and the target for
+    /// creating one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<PathBuf>,
+    /// how long the operation took in milliseconds.
+    pub duration_ms: u64,
+    /// the outcome of the operation.
+    pub result: OperationResult,
+    /// the warnings the operation raised.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+    /// why the operation failed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Event {
+    /// returns the event of `request` answered with `response` after `duration`.
+    pub fn from_response(request: &Request, response: &Response, duration: Duration) -> Event {
+        let (operation, file) = match request {
+            Request::Formats => ("formats", None),
+            Request::Detect { patch } => ("detect", Some(patch)),
+            Request::Validate { patch } => ("validate", Some(patch)),
+            Request::Apply { patch, .. } => ("apply", Some(patch)),
+            Request::Create { target, .. } => ("create", Some(target)),
+        };
+        let (result, warnings, error) = match response {
+            Response::Ok { result } => {
+                let warnings = warnings(result);
+                let outcome = if warnings.is_empty() { OperationResult::Success } else { OperationResult::AppliedWithWarnings };
+                (outcome, warnings, None)
+            }
+            Response::Error { message, result, .. } => (*result, Vec::new(), Some(message.clone())),
+        };
+        Event {
+            timestamp_ms: now_ms(),
+            operation: operation.to_string(),
+            file: file.cloned(),
+            duration_ms: duration.as_millis().try_into().unwrap_or(u64::MAX),
+            result,
+            warnings,
+            error,
+        }
+    }
+}
+
+/// returns the messages of the diagnostics in the result of a command.
+fn warnings(result: &Value) -> Vec<String> {
+    result.get("diagnostics")
+        .and_then(|x| x.as_array())
+        .map(|x| x.iter().filter_map(|x| x.get("message")?.as_str().map(|x| x.to_string())).collect())
+        .unwrap_or_default()
+}
+
+fn now_ms() -> u64 {
+    // a clock set before 1970 logs the epoch
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |x| x.as_millis().try_into().unwrap_or(u64::MAX))
+}
+
+/// A writer an [EventLog] can hand back with [EventLog::into_inner].
+trait LogWriter: Write + Send {
+    fn into_any(self: Box<Self>) -> Box<dyn Any>;
+}
+
+impl<W: Write + Send + 'static> LogWriter for W {
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+/// A writer [Event]s are logged to as JSON lines, shareable between threads.
+pub struct EventLog {
+    writer: Mutex<Box<dyn LogWriter>>,
+}
+
+impl Debug for EventLog {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EventLog").finish_non_exhaustive()
+    }
+}
+
+impl EventLog {
+    /// constructs a log writing to `writer`, e.g. a file opened for appending or stderr.
+    pub fn new(writer: impl Write + Send + 'static) -> EventLog {
+        EventLog { writer: Mutex::new(Box::new(writer)) }
+    }
+
+    /// writes `event` as a single line and flushes the writer, so lines of concurrent operations
+    /// never interleave.
+    pub fn log(&self, event: &Event) -> IOResult<()> {
+        // events only contain plain data
+        let mut line = serde_json::to_vec(event).unwrap();
+        line.push(b'\n');
+        let mut writer = self.writer.lock().unwrap_or_else(|e| e.into_inner());
+        writer.write_all(&line)?;
+        writer.flush()
+    }
+
+    /// executes `request` like [handle_with_progress] and logs its event.
+    ///
+    /// A failure to write the log doesn't fail the request.
+    pub fn handle(&self, request: &Request, progress: Option<Sender<Progress>>, cancellation: &CancellationToken) -> Response {
+        let started = Instant::now();
+        let response = handle_with_progress(request, progress, cancellation);
+        let _ = self.log(&Event::from_response(request, &response, started.elapsed()));
+        return response;
+    }
+
+    /// returns the writer the log was constructed with, or `None` if it isn't a `W`.
+    pub fn into_inner<W: 'static>(self) -> Option<W> {
+        let writer = self.writer.into_inner().unwrap_or_else(|e| e.into_inner());
+        writer.into_any().downcast::<W>().ok().map(|x| *x)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+
+    use spectral::prelude::*;
+
+    use crate::testkit::Fixture;
+
+    use super::*;
+
+    fn events(log: EventLog) -> Vec<Event> {
+        let data = log.into_inner::<Vec<u8>>().unwrap();
+        String::from_utf8(data).unwrap().lines().map(|x| serde_json::from_str(x).unwrap()).collect()
+    }
+
+    #[test]
+    fn operations_are_logged_with_their_warnings() {
+        let dir = env::temp_dir().join(format!("rom-patcher-eventlog-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let fixture = Fixture::generate(2);
+        fs::write(dir.join("source.bin"), &fixture.source).unwrap();
+        fs::write(dir.join("patch.ips"), fixture.ips.to_bytes().unwrap()).unwrap();
+        let request = Request::Apply {
+            source: dir.join("source.bin"),
+            patch: dir.join("patch.ips"),
+            output: dir.join("out.bin"),
+            options: Default::default(),
+        };
+
+        let log = EventLog::new(Vec::new());
+        let response = log.handle(&request, None, &CancellationToken::new());
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_that!(matches!(response, Response::Ok { .. })).is_true();
+        let events = events(log);
+        assert_that!(events.len()).is_equal_to(1);
+        assert_that!(events[0].operation).is_equal_to("apply".to_string());
+        assert_that!(events[0].file).is_equal_to(Some(dir.join("patch.ips")));
+        assert_that!(events[0].result).is_equal_to(OperationResult::AppliedWithWarnings);
+        assert_that!(events[0].warnings.is_empty()).is_false();
+        assert_that!(events[0].error).is_none();
+    }
+
+    #[test]
+    fn failures_are_logged_with_their_error() {
+        let log = EventLog::new(Vec::new());
+        log.handle(&Request::Detect { patch: PathBuf::from("/nonexistent/patch.ips") }, None, &CancellationToken::new());
+        let events = events(log);
+        assert_that!(events[0].result).is_equal_to(OperationResult::Failed);
+        assert_that!(events[0].error.is_some()).is_true();
+    }
+}
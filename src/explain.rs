@@ -0,0 +1,153 @@
+//! Human explanations of what a patch touches, in terms of the [regions](Region) of a rom.
+
+use std::fmt::{Display, Formatter};
+use std::ops::Range;
+
+use crate::console::{Console, Region};
+use crate::index::IntervalIndex;
+use crate::ips::IPSPatch;
+
+/// The bytes a patch writes to one named region.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Touched {
+    /// name of the region.
+    pub region: String,
+    /// amount of bytes written to the region.
+    pub bytes: u64,
+}
+
+/// What a patch touches, see [explain].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Explanation {
+    /// the console the regions were taken from, [None] if it wasn't detected.
+    pub console: Option<Console>,
+    /// the touched regions, ordered by the offset they are first written at.
+    pub touched: Vec<Touched>,
+    /// amount of bytes written inside the rom but outside of any named region.
+    pub unnamed: u64,
+    /// amount of bytes written past the end of the rom.
+    pub past_end: u64,
+}
+
+/// explains which regions of `rom` the given written ranges touch, using the regions of the
+/// [detected](Console::detect) console.
+///
+/// Overlapping ranges are counted once.
+pub fn explain(rom: &[u8], writes: impl IntoIterator<Item=Range<u64>>) -> Explanation {
+    let console = Console::detect(rom);
+    let regions = console.map(|x| x.regions(rom)).unwrap_or_default();
+    let mut result = explain_regions(&regions, rom.len() as u64, writes);
+    result.console = console;
+    return result;
+}
+
+/// explains which regions of `rom` the hunks of `patch` touch, see [explain].
+///
+/// # Examples
+///
+/// ```
+/// use rom_patcher::explain::explain_ips;
+/// use rom_patcher::ips::{IPSHunk, IPSPatch, IPSRLEHunkData};
+///
+/// let mut rom = vec![0; 16 + 4 * 0x4000];
+/// rom[..6].copy_from_slice(&[b'N', b'E', b'S', 0x1A, 4, 0]);
+/// let patch = IPSPatch::new()
+///     .with_hunk(IPSHunk::RLE(IPSRLEHunkData { offset: 6, run_length: 1, payload: 0x10 }))
+///     .with_hunk(IPSHunk::RLE(IPSRLEHunkData { offset: 16 + 3 * 0x4000, run_length: 2, payload: 0xEA }));
+/// assert_eq!(explain_ips(&patch, &rom).to_string(), "modifies header mapper byte and PRG bank 3");
+/// ```
+pub fn explain_ips(patch: &IPSPatch, rom: &[u8]) -> Explanation {
+    explain(rom, patch.hunks().iter().map(|x| x.offset()..x.end()))
+}
+
+/// explains which of `regions` the given written ranges touch in a rom of `rom_len` bytes.
+pub fn explain_regions(regions: &[Region], rom_len: u64, writes: impl IntoIterator<Item=Range<u64>>) -> Explanation {
+    let index = IntervalIndex::new(regions.iter().map(|x| (x.range.clone(), x.name.as_str())));
+    let mut result = Explanation { console: None, touched: Vec::new(), unnamed: 0, past_end: 0 };
+    for write in merged(writes) {
+        let inside = write.start.min(rom_len)..write.end.min(rom_len);
+        result.past_end += (write.end - write.start) - (inside.end - inside.start);
+        let mut named = 0;
+        for (range, name) in index.overlapping(inside.clone()) {
+            let bytes = range.end.min(inside.end) - range.start.max(inside.start);
+            named += bytes;
+            match result.touched.iter_mut().find(|x| x.region == *name) {
+                Some(touched) => touched.bytes += bytes,
+                None => result.touched.push(Touched { region: name.to_string(), bytes }),
+            }
+        }
+        result.unnamed += (inside.end - inside.start) - named;
+    }
+    return result;
+}
+
+/// returns `ranges` sorted with overlapping and adjacent ranges merged.
+fn merged(ranges: impl IntoIterator<Item=Range<u64>>) -> Vec<Range<u64>> {
+    let mut ranges: Vec<Range<u64>> = ranges.into_iter().filter(|x| x.start < x.end).collect();
+    ranges.sort_by_key(|x| x.start);
+    let mut result: Vec<Range<u64>> = Vec::new();
+    for range in ranges {
+        match result.last_mut() {
+            Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+            _ => result.push(range),
+        }
+    }
+    return result;
+}
+
+impl Display for Explanation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let mut parts: Vec<String> = self.touched.iter().map(|x| x.region.clone()).collect();
+        if self.unnamed > 0 {
+            parts.push(format!("{} other bytes", self.unnamed));
+        }
+        if self.past_end > 0 {
+            parts.push(format!("{} bytes past the end of the rom", self.past_end));
+        }
+        match parts.split_last() {
+            None => write!(f, "modifies nothing"),
+            Some((last, [])) => write!(f, "modifies {}", last),
+            Some((last, rest)) => write!(f, "modifies {} and {}", rest.join(", "), last),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use spectral::prelude::*;
+
+    use super::*;
+
+    fn regions() -> Vec<Region> {
+        vec![
+            Region { name: "header".to_string(), range: 0..16 },
+            Region { name: "bank 0".to_string(), range: 16..32 },
+        ]
+    }
+
+    #[test]
+    fn counts_bytes_per_region() {
+        let explanation = explain_regions(&regions(), 32, vec![10..20, 12..14]);
+        assert_that!(explanation.touched).is_equal_to(vec![
+            Touched { region: "header".to_string(), bytes: 6 },
+            Touched { region: "bank 0".to_string(), bytes: 4 },
+        ]);
+        assert_that!(explanation.to_string()).is_equal_to("modifies header and bank 0".to_string());
+    }
+
+    #[test]
+    fn counts_unnamed_and_appended_bytes() {
+        let explanation = explain_regions(&regions()[..1], 32, std::iter::once(14..40));
+        assert_that!(explanation.unnamed).is_equal_to(16);
+        assert_that!(explanation.past_end).is_equal_to(8);
+        assert_that!(explanation.to_string())
+            .is_equal_to("modifies header, 16 other bytes and 8 bytes past the end of the rom".to_string());
+    }
+
+    #[test]
+    fn empty_patches_modify_nothing() {
+        let explanation = explain(&[0; 8], Vec::new());
+        assert_that!(explanation.console).is_none();
+        assert_that!(explanation.to_string()).is_equal_to("modifies nothing".to_string());
+    }
+}
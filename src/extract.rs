@@ -0,0 +1,193 @@
+//! Patching roms embedded in emulator packages, like Virtual Console titles, through pluggable
+//! [extractors](RomExtractor).
+//!
+//! [apply_embedded] takes the rom out of its container, applies the patch to it like to any other
+//! rom and hands the patched rom back to the extractor that found it, which puts it back into the
+//! container, e.g. recompressing it or updating the size and checksum fields of the container.
+
+use std::fmt::Debug;
+use std::ops::Range;
+
+use crate::apply::ApplyOptions;
+use crate::diagnostics::Diagnostic;
+use crate::Error;
+use crate::ErrorKind::{PatchingError, UnsupportedFormat, WrongSource};
+use crate::io_util::U64Extensions;
+use crate::patch::Patch;
+
+/// A container format roms can be embedded in.
+///
+/// Implement this to patch roms inside packages the crate doesn't know.
+pub trait RomExtractor: Debug + Send + Sync {
+    /// returns the name of the container format.
+    fn name(&self) -> &'static str;
+
+    /// returns `true` if `container` is of this format.
+    fn matches(&self, container: &[u8]) -> bool;
+
+    /// returns the rom embedded in `container`.
+    fn extract(&self, container: &[u8]) -> Result<Vec<u8>, Error>;
+
+    /// returns `container` with its embedded rom replaced by `rom`.
+    fn repack(&self, container: &[u8], rom: &[u8]) -> Result<Vec<u8>, Error>;
+}
+
+/// A container starting with a magic that stores its rom uncompressed at a fixed offset, like
+/// emulator executables with the rom appended.
+///
+/// # Examples
+///
+/// ```
+/// use rom_patcher::extract::{RomExtractor, SimpleContainer};
+/// let container = SimpleContainer::new("demo", b"PACK", 8);
+/// let data = b"PACK\0\0\0\0rom data";
+/// assert!(container.matches(data));
+/// assert_eq!(container.extract(data).unwrap(), b"rom data");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SimpleContainer {
+    name: &'static str,
+    magic: Vec<u8>,
+    offset: u64,
+    len: Option<u64>,
+}
+
+impl SimpleContainer {
+    /// constructs a container format called `name` starting with `magic` whose rom starts at
+    /// `offset` and spans the rest of the container.
+    pub fn new(name: &'static str, magic: &[u8], offset: u64) -> SimpleContainer {
+        SimpleContainer { name, magic: magic.to_vec(), offset, len: None }
+    }
+
+    /// returns a new container format whose rom spans `len` bytes, followed by other data.
+    ///
+    /// Patched roms have to keep that length to be repacked.
+    pub fn with_len(mut self, len: u64) -> Self {
+        self.len = Some(len);
+        return self;
+    }
+
+    /// returns the range of `container` the rom spans, or [None] if `container` is too short.
+    fn range(&self, container: &[u8]) -> Option<Range<usize>> {
+        let start = self.offset.to_index();
+        let end = match self.len {
+            Some(len) => start.checked_add(len.to_index())?,
+            None => container.len(),
+        };
+        if start > end || end > container.len() {
+            return None;
+        }
+        return Some(start..end);
+    }
+}
+
+impl RomExtractor for SimpleContainer {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn matches(&self, container: &[u8]) -> bool {
+        container.starts_with(&self.magic) && self.range(container).is_some()
+    }
+
+    fn extract(&self, container: &[u8]) -> Result<Vec<u8>, Error> {
+        let range = self.range(container).ok_or_else(|| Error::new(WrongSource)
+            .with_description(format!("The {} container is too short to hold its rom.", self.name)))?;
+        Ok(container[range].to_vec())
+    }
+
+    fn repack(&self, container: &[u8], rom: &[u8]) -> Result<Vec<u8>, Error> {
+        let range = self.range(container).ok_or_else(|| Error::new(WrongSource)
+            .with_description(format!("The {} container is too short to hold its rom.", self.name)))?;
+        if self.len.is_some() && rom.len() != range.len() {
+            return Err(Error::new(PatchingError)
+                .with_description(format!("The {} container only holds roms of {} bytes, but the patched rom has {}.", self.name, range.len(), rom.len())));
+        }
+        let mut result = container[..range.start].to_vec();
+        result.extend_from_slice(rom);
+        result.extend_from_slice(&container[range.end..]);
+        return Ok(result);
+    }
+}
+
+/// returns the first of `extractors` that matches `container`.
+pub fn find_extractor<'a>(container: &[u8], extractors: &[&'a dyn RomExtractor]) -> Option<&'a dyn RomExtractor> {
+    extractors.iter().copied().find(|x| x.matches(container))
+}
+
+/// applies `patch` to the rom embedded in `container` according to `options`, returning the
+/// repacked container together with the diagnostics found.
+///
+/// The first of `extractors` matching `container` extracts and repacks the rom. Returns an
+/// [UnsupportedFormat] error if none matches, and errors of the extractor or of applying the patch
+/// otherwise.
+///
+/// # Examples
+///
+/// ```
+/// use rom_patcher::apply::ApplyOptions;
+/// use rom_patcher::extract::{apply_embedded, SimpleContainer};
+/// use rom_patcher::ips::{IPSHunk, IPSPatch, IPSRegularHunkData};
+/// let patch = IPSPatch::new().with_hunk(IPSHunk::Regular(IPSRegularHunkData {
+///     offset: 1,
+///     payload: Box::new([0xFF]),
+/// }));
+/// let container = SimpleContainer::new("demo", b"PACK", 4).with_len(3);
+/// let (patched, _) = apply_embedded(&patch, b"PACK\x01\x02\x03END", &[&container], &ApplyOptions::default()).unwrap();
+/// assert_eq!(patched, b"PACK\x01\xFF\x03END");
+/// ```
+pub fn apply_embedded(patch: &dyn Patch, container: &[u8], extractors: &[&dyn RomExtractor], options: &ApplyOptions) -> Result<(Vec<u8>, Vec<Diagnostic>), Error> {
+    let extractor = find_extractor(container, extractors).ok_or_else(|| Error::new(UnsupportedFormat)
+        .with_description("The container isn't of any known format.".to_string()))?;
+    let rom = extractor.extract(container)?;
+    let (patched, diagnostics) = patch.apply_to_vec_with_options(&rom, options)?;
+    let repacked = extractor.repack(container, &patched)?;
+    Ok((repacked, diagnostics))
+}
+
+#[cfg(test)]
+mod tests {
+    use spectral::prelude::*;
+
+    use crate::ups::UPSPatch;
+
+    use super::*;
+
+    fn container() -> Vec<u8> {
+        let mut result = b"EMU1".to_vec();
+        result.extend_from_slice(&[0, 1, 2, 3, 4, 5, 6, 7]);
+        result.extend_from_slice(b"TRAILER");
+        return result;
+    }
+
+    #[test]
+    fn roms_are_extracted_and_repacked() {
+        let extractor = SimpleContainer::new("emu", b"EMU1", 4).with_len(8);
+        let patch = UPSPatch::create(&[0, 1, 2, 3, 4, 5, 6, 7], &[0, 1, 9, 3, 4, 5, 6, 7]);
+        let (patched, _) = apply_embedded(&patch, &container(), &[&extractor], &ApplyOptions::default()).unwrap();
+        assert_that!(patched[..4].to_vec()).is_equal_to(b"EMU1".to_vec());
+        assert_that!(patched[6]).is_equal_to(9);
+        assert_that!(patched.ends_with(b"TRAILER")).is_true();
+    }
+
+    #[test]
+    fn fixed_length_roms_keep_their_size() {
+        let extractor = SimpleContainer::new("emu", b"EMU1", 4).with_len(8);
+        let patch = UPSPatch::create(&[0, 1, 2, 3, 4, 5, 6, 7], &[0; 9]);
+        let err = apply_embedded(&patch, &container(), &[&extractor], &ApplyOptions::default()).unwrap_err();
+        assert_that!(err.to_string()).contains("only holds roms of 8 bytes");
+        let appended = SimpleContainer::new("emu", b"EMU1", 4);
+        assert_that!(appended.repack(&container(), &[1, 2]).unwrap()).is_equal_to(b"EMU1\x01\x02".to_vec());
+    }
+
+    #[test]
+    fn the_first_matching_extractor_is_used() {
+        let other = SimpleContainer::new("other", b"OTHR", 0);
+        let short = SimpleContainer::new("short", b"EMU1", 4).with_len(100);
+        let emu = SimpleContainer::new("emu", b"EMU1", 4);
+        assert_that!(find_extractor(&container(), &[&other, &short, &emu]).map(|x| x.name())).is_equal_to(Some("emu"));
+        let patch = UPSPatch::create(&[0], &[1]);
+        let err = apply_embedded(&patch, &container(), &[&other], &ApplyOptions::default()).unwrap_err();
+        assert_that!(matches!(err.kind(), UnsupportedFormat)).is_true();
+    }
+}
@@ -0,0 +1,342 @@
+//! A single entry point for frontends applying patches to files.
+//!
+//! [PatchJob] bundles detection, validation, applying and reporting behind one [PatchJob::run],
+//! reporting progress over a channel and honoring a [CancellationToken], so GUI frontends can run
+//! it on a worker thread without knowing about the individual patch formats.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::mpsc::Sender;
+
+use crate::apply::{ApplyOptions, TruncateCheck};
+#[cfg(feature = "json")]
+use crate::chain::PatchMetadata;
+use crate::diagnostics::{Diagnostic, Severity};
+use crate::Error;
+use crate::ErrorKind::{PatchingError, ValidationError};
+use crate::fingerprint::{fingerprint, Fingerprint};
+use crate::hash::Crc32;
+use crate::outcome::OperationResult;
+use crate::metrics::measure;
+use crate::naming::sanitize_file_name;
+use crate::patch::Patch;
+use crate::progress::{CancellationToken, Progress, Stage};
+use crate::registry::read_any_from_slice;
+use crate::vfs::{StdFs, Vfs};
+
+/// The outcome of a successful [PatchJob::run].
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize))]
+pub struct JobReport {
+    /// the format of the applied patch, e.g. `"ips"`.
+    pub format: &'static str,
+    /// everything found wrong with the patch that didn't stop it from being applied.
+    pub diagnostics: Vec<Diagnostic>,
+    /// length of the written output.
+    pub output_len: u64,
+    /// CRC-32 of the written output.
+    pub output_crc32: u32,
+    /// what the headers of the source rom say it is.
+    pub source: Option<Fingerprint>,
+    /// path the output was written to, which differs from the requested one if the patch
+    /// suggested a name, see [ApplyOptions::with_suggested_output_name].
+    pub output: PathBuf,
+}
+
+impl JobReport {
+    /// returns how the job succeeded, see [OperationResult::from_diagnostics].
+    pub fn result(&self) -> OperationResult {
+        OperationResult::from_diagnostics(&self.diagnostics)
+    }
+}
+
+/// A request to apply the patch at one path to the rom at another, writing the result to a third.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::sync::mpsc::channel;
+/// use rom_patcher::facade::PatchJob;
+/// use rom_patcher::progress::CancellationToken;
+///
+/// let (sender, receiver) = channel();
+/// let token = CancellationToken::new();
+/// let job = PatchJob::new("game.sfc", "hack.ips", "hacked.sfc")
+///     .with_progress(sender)
+///     .with_cancellation(token.clone());
+/// let handle = std::thread::spawn(move || job.run());
+/// for progress in receiver {
+///     println!("{:?} {}/{}", progress.stage, progress.done, progress.total);
+/// }
+/// let report = handle.join().unwrap().expect("Unable to apply patch.");
+/// println!("wrote {} bytes", report.output_len);
+/// ```
+#[derive(Debug, Clone)]
+pub struct PatchJob {
+    source: PathBuf,
+    patch: PathBuf,
+    output: PathBuf,
+    options: ApplyOptions,
+    progress: Option<Sender<Progress>>,
+    cancellation: CancellationToken,
+    vfs: Arc<dyn Vfs>,
+}
+
+impl PatchJob {
+    /// amount of stages reported before [Stage::Done].
+    const STAGES: u64 = 5;
+
+    /// constructs a job applying the patch at `patch` to the rom at `source`, writing to `output`.
+    pub fn new(source: impl Into<PathBuf>, patch: impl Into<PathBuf>, output: impl Into<PathBuf>) -> PatchJob {
+        PatchJob {
+            source: source.into(),
+            patch: patch.into(),
+            output: output.into(),
+            options: ApplyOptions::new(),
+            progress: None,
+            cancellation: CancellationToken::new(),
+            vfs: Arc::new(StdFs),
+        }
+    }
+
+    /// modifies the job to apply the patch according to `options`.
+    pub fn with_options(mut self, options: ApplyOptions) -> PatchJob {
+        self.options = options;
+        return self;
+    }
+
+    /// modifies the job to send its progress to `progress`.
+    pub fn with_progress(mut self, progress: Sender<Progress>) -> PatchJob {
+        self.progress = Some(progress);
+        return self;
+    }
+
+    /// modifies the job to stop once `cancellation` is cancelled.
+    pub fn with_cancellation(mut self, cancellation: CancellationToken) -> PatchJob {
+        self.cancellation = cancellation;
+        return self;
+    }
+
+    /// modifies the job to read and write its files through `vfs` instead of the real file system.
+    pub fn with_vfs(mut self, vfs: Arc<dyn Vfs>) -> PatchJob {
+        self.vfs = vfs;
+        return self;
+    }
+
+    /// runs the job.
+    ///
+    /// The output is only written once the patch applied successfully, so a failed or cancelled
+    /// job never leaves a partial output behind.
+    pub fn run(&self) -> Result<JobReport, Error> {
+        measure("apply", || self.run_unmeasured(), |x| x.output_len)
+    }
+
+    fn run_unmeasured(&self) -> Result<JobReport, Error> {
+        self.enter(Stage::Reading, 0)?;
+        let source = self.read_file(&self.source, "source")?;
+        let patch_data = self.read_file(&self.patch, "patch")?;
+
+        self.enter(Stage::Detecting, 1)?;
+        let patch = read_any_from_slice(&patch_data)?;
+
+        self.enter(Stage::Validating, 2)?;
+        let mut diagnostics = patch.validate();
+        if self.options.truncate_check() == TruncateCheck::Deny {
+            if let Some(diagnostic) = diagnostics.iter().find(|x| x.severity == Severity::Error) {
+                return Err(Error::new(ValidationError).with_description(diagnostic.message.clone()));
+            }
+        }
+
+        self.enter(Stage::Applying, 3)?;
+        let (patched, applied) = patch.apply_to_vec_with_options(&source, &self.options)?;
+        for diagnostic in applied {
+            if !diagnostics.contains(&diagnostic) {
+                diagnostics.push(diagnostic);
+            }
+        }
+
+        self.enter(Stage::Writing, 4)?;
+        let output = self.output_path(patch.as_ref());
+        self.vfs.write(&output, &patched)
+            .map_err(|e| Error::new(PatchingError)
+                .with_description(format!("Unable to write output {}.", output.display()))
+                .with_source(Box::new(e)))?;
+
+        self.report(Stage::Done, Self::STAGES);
+        Ok(JobReport {
+            format: patch.format(),
+            diagnostics,
+            output_len: patched.len() as u64,
+            output_crc32: Crc32::checksum(&patched),
+            source: fingerprint(&source),
+            output,
+        })
+    }
+
+    /// returns the path the output is written to, next to the requested one under the name the
+    /// sidecar or the patch suggest if the options ask for it.
+    fn output_path(&self, patch: &dyn Patch) -> PathBuf {
+        if !self.options.suggested_output_name() {
+            return self.output.clone();
+        }
+        let suggested = self.sidecar_output_name()
+            .or_else(|| patch.suggested_output_name())
+            .and_then(|x| sanitize_file_name(&x));
+        return match suggested {
+            Some(name) => self.output.with_file_name(name),
+            None => self.output.clone(),
+        };
+    }
+
+    /// returns the output name suggested by the sidecar of the patch, see [PatchMetadata].
+    ///
+    /// A missing or unreadable sidecar suggests nothing, since the name is only a convenience.
+    #[cfg(feature = "json")]
+    fn sidecar_output_name(&self) -> Option<String> {
+        let json = self.vfs.read(&PatchMetadata::sidecar_path(&self.patch)).ok()?;
+        PatchMetadata::from_json(&String::from_utf8_lossy(&json)).ok()?.output_name
+    }
+
+    #[cfg(not(feature = "json"))]
+    fn sidecar_output_name(&self) -> Option<String> {
+        None
+    }
+
+    /// reports entering `stage` unless the job was cancelled.
+    fn enter(&self, stage: Stage, done: u64) -> Result<(), Error> {
+        self.cancellation.check()?;
+        self.report(stage, done);
+        Ok(())
+    }
+
+    fn report(&self, stage: Stage, done: u64) {
+        if let Some(progress) = &self.progress {
+            // a frontend that stopped listening doesn't stop the job
+            let _ = progress.send(Progress { stage, done, total: Self::STAGES });
+        }
+    }
+
+    fn read_file(&self, path: &Path, name: &str) -> Result<Vec<u8>, Error> {
+        self.vfs.read(path).map_err(|e| Error::new(PatchingError)
+            .with_description(format!("Unable to read {} {}.", name, path.display()))
+            .with_source(Box::new(e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::sync::mpsc::channel;
+
+    use spectral::prelude::*;
+
+    use crate::diagnostics::DiagnosticCode;
+    use crate::testkit::Fixture;
+    use crate::vfs::MemoryFs;
+
+    use super::*;
+
+    fn job_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("rom-patcher-facade-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        return dir;
+    }
+
+    fn write_fixture(dir: &Path) -> Fixture {
+        let fixture = Fixture::generate(7);
+        fs::write(dir.join("source.bin"), &fixture.source).unwrap();
+        let mut patch = Vec::new();
+        fixture.ips.write(&mut patch).unwrap();
+        fs::write(dir.join("patch.ips"), patch).unwrap();
+        return fixture;
+    }
+
+    #[test]
+    fn run_writes_patched_output_and_reports_progress() {
+        let dir = job_dir("run");
+        let fixture = write_fixture(&dir);
+        let (sender, receiver) = channel();
+        let report = PatchJob::new(dir.join("source.bin"), dir.join("patch.ips"), dir.join("out.bin"))
+            .with_progress(sender)
+            .run()
+            .unwrap();
+
+        assert_that!(fs::read(dir.join("out.bin")).unwrap()).is_equal_to(&fixture.target);
+        assert_that!(report.format).is_equal_to("ips");
+        assert_that!(report.output_crc32).is_equal_to(Crc32::checksum(&fixture.target));
+        let codes: Vec<Option<DiagnosticCode>> = report.diagnostics.iter().map(|x| x.code).collect();
+        assert_that!(codes).contains(Some(DiagnosticCode::Unverified));
+        let stages: Vec<Stage> = receiver.iter().map(|x| x.stage).collect();
+        assert_that!(stages).is_equal_to(vec![
+            Stage::Reading, Stage::Detecting, Stage::Validating, Stage::Applying, Stage::Writing, Stage::Done,
+        ]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn cancelled_job_writes_nothing() {
+        let dir = job_dir("cancel");
+        write_fixture(&dir);
+        let token = CancellationToken::new();
+        token.cancel();
+        let result = PatchJob::new(dir.join("source.bin"), dir.join("patch.ips"), dir.join("out.bin"))
+            .with_cancellation(token)
+            .run();
+        assert_that!(result).is_err();
+        assert_that!(dir.join("out.bin").exists()).is_false();
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn reapplying_needs_force() {
+        let dir = job_dir("reapply");
+        write_fixture(&dir);
+        PatchJob::new(dir.join("source.bin"), dir.join("patch.ips"), dir.join("out.bin")).run().unwrap();
+        let again = PatchJob::new(dir.join("out.bin"), dir.join("patch.ips"), dir.join("again.bin")).run();
+        assert_that!(matches!(again.unwrap_err().kind(), crate::ErrorKind::AlreadyApplied)).is_true();
+        let forced = PatchJob::new(dir.join("out.bin"), dir.join("patch.ips"), dir.join("again.bin"))
+            .with_options(ApplyOptions::new().with_force(true))
+            .run();
+        assert_that!(forced).is_ok();
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn runs_in_memory() {
+        let fixture = Fixture::generate(8);
+        let mut patch = Vec::new();
+        fixture.ips.write(&mut patch).unwrap();
+        let vfs = Arc::new(MemoryFs::new().with_file("source.bin", fixture.source.clone()).with_file("patch.ips", patch));
+        PatchJob::new("source.bin", "patch.ips", "out.bin").with_vfs(vfs.clone()).run().unwrap();
+        assert_that!(vfs.file("out.bin")).is_equal_to(Some(fixture.target));
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn suggested_output_names_are_honored() {
+        let fixture = Fixture::generate(9);
+        let mut patch = Vec::new();
+        fixture.ips.write(&mut patch).unwrap();
+        let sidecar = PatchMetadata::new().with_output_name("../Hack: Deluxe.bin").to_json();
+        let vfs = Arc::new(MemoryFs::new()
+            .with_file("roms/source.bin", fixture.source.clone())
+            .with_file("roms/patch.ips", patch)
+            .with_file("roms/patch.ips.deps.json", sidecar.into_bytes()));
+        let job = PatchJob::new("roms/source.bin", "roms/patch.ips", "roms/out.bin").with_vfs(vfs.clone());
+
+        let report = job.clone().run().unwrap();
+        assert_that!(report.output).is_equal_to(PathBuf::from("roms/out.bin"));
+        let report = job.with_options(ApplyOptions::new().with_suggested_output_name(true)).run().unwrap();
+        assert_that!(report.output).is_equal_to(PathBuf::from("roms/Hack Deluxe.bin"));
+        assert_that!(vfs.file("roms/Hack Deluxe.bin")).is_equal_to(Some(fixture.target));
+    }
+
+    #[test]
+    fn missing_source_fails() {
+        let dir = job_dir("missing");
+        let result = PatchJob::new(dir.join("none.bin"), dir.join("none.ips"), dir.join("out.bin")).run();
+        assert_that!(result).is_err();
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,291 @@
+//! Update feeds of hack distributions, so launchers can tell whether a newer version of a patch
+//! exists for a rom and install it.
+//!
+//! A feed lists every released version of a patch with where to download it and the hashes
+//! identifying it. In JSON, with the `json` feature enabled:
+//!
+//! ```json
+//! {
+//!   "name": "Example Hack",
+//!   "versions": [
+//!     {
+//!       "version": "1.1.0",
+//!       "url": "https://example.com/example-hack-1.1.0.ips",
+//!       "sha256": "<SHA-256 of the patch>",
+//!       "source_sha1": "<SHA-1 of the rom the patch applies to>",
+//!       "target_sha1": "<SHA-1 of the patched rom>"
+//!     }
+//!   ]
+//! }
+//! ```
+//!
+//! Feeds are downloaded and updates installed with the `http` feature enabled.
+
+use std::cmp::Ordering;
+
+#[cfg(feature = "json")]
+use crate::Error;
+#[cfg(feature = "json")]
+use crate::ErrorKind::ParsingError;
+use crate::hash::{Sha1, to_hex};
+
+/// A released version of a patch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize))]
+pub struct FeedVersion {
+    /// the version, compared by its dot separated numbers, e.g. `1.10.0` is newer than `1.9.2`.
+    pub version: String,
+    /// where the patch is downloaded from.
+    pub url: String,
+    /// SHA-256 of the patch, as lowercase hex.
+    pub sha256: String,
+    /// SHA-1 of the rom the patch applies to, as lowercase hex.
+    #[cfg_attr(feature = "json", serde(default))]
+    pub source_sha1: Option<String>,
+    /// SHA-1 of the patched rom, as lowercase hex.
+    #[cfg_attr(feature = "json", serde(default))]
+    pub target_sha1: Option<String>,
+    /// what changed in the version.
+    #[cfg_attr(feature = "json", serde(default))]
+    pub notes: Option<String>,
+}
+
+/// Whether a rom has the latest version of a feed installed, see [PatchFeed::check].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UpdateStatus<'a> {
+    /// the rom has the latest version installed.
+    UpToDate(&'a FeedVersion),
+    /// `latest` can be installed on the rom, which has the older `installed` version or, if
+    /// [None], no version installed.
+    Available {
+        installed: Option<&'a FeedVersion>,
+        latest: &'a FeedVersion,
+    },
+    /// the rom is neither a source nor a target of any version.
+    Unknown,
+}
+
+/// The released versions of a patch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize))]
+pub struct PatchFeed {
+    /// the name of the patched hack.
+    pub name: String,
+    /// the released versions, in any order.
+    pub versions: Vec<FeedVersion>,
+}
+
+impl PatchFeed {
+    /// returns the newest version.
+    pub fn latest(&self) -> Option<&FeedVersion> {
+        self.versions.iter().max_by(|a, b| compare_versions(&a.version, &b.version))
+    }
+
+    /// returns the version `rom` is the patched rom of.
+    pub fn installed(&self, rom: &[u8]) -> Option<&FeedVersion> {
+        let sha1 = to_hex(&Sha1::hash(rom));
+        self.versions.iter()
+            .filter(|x| x.target_sha1.as_ref() == Some(&sha1))
+            .max_by(|a, b| compare_versions(&a.version, &b.version))
+    }
+
+    /// returns whether `rom` has the latest version installed or can be updated.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rom_patcher::feed::{FeedVersion, PatchFeed, UpdateStatus};
+    /// use rom_patcher::hash::{Sha1, to_hex};
+    ///
+    /// let version = |version: &str, target: &[u8]| FeedVersion {
+    ///     version: version.to_string(),
+    ///     url: format!("https://example.com/{}.ips", version),
+    ///     sha256: String::new(),
+    ///     source_sha1: Some(to_hex(&Sha1::hash(&[0]))),
+    ///     target_sha1: Some(to_hex(&Sha1::hash(target))),
+    ///     notes: None,
+    /// };
+    /// let feed = PatchFeed { name: "hack".to_string(), versions: vec![version("1.0", &[1]), version("1.1", &[2])] };
+    /// assert!(matches!(feed.check(&[1]), UpdateStatus::Available { latest, .. } if latest.version == "1.1"));
+    /// assert!(matches!(feed.check(&[2]), UpdateStatus::UpToDate(_)));
+    /// assert_eq!(feed.check(&[3]), UpdateStatus::Unknown);
+    /// ```
+    pub fn check(&self, rom: &[u8]) -> UpdateStatus<'_> {
+        let Some(latest) = self.latest() else {
+            return UpdateStatus::Unknown;
+        };
+        if let Some(installed) = self.installed(rom) {
+            if compare_versions(&installed.version, &latest.version) == Ordering::Less {
+                return UpdateStatus::Available { installed: Some(installed), latest };
+            }
+            return UpdateStatus::UpToDate(installed);
+        }
+        let sha1 = to_hex(&Sha1::hash(rom));
+        if latest.source_sha1.as_ref().is_none_or(|x| *x == sha1) && self.versions.iter().any(|x| x.source_sha1.as_ref() == Some(&sha1)) {
+            return UpdateStatus::Available { installed: None, latest };
+        }
+        return UpdateStatus::Unknown;
+    }
+
+    /// serializes the feed as JSON.
+    #[cfg(feature = "json")]
+    pub fn to_json(&self) -> String {
+        // the feed only contains plain data
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    /// deserializes a feed serialized with [PatchFeed::to_json].
+    #[cfg(feature = "json")]
+    pub fn from_json(json: &str) -> Result<PatchFeed, Error> {
+        serde_json::from_str(json).map_err(|e| Error::new(ParsingError)
+            .with_description("Invalid patch feed.".to_string())
+            .with_source(Box::new(e)))
+    }
+
+    /// downloads the feed at `url`.
+    #[cfg(all(feature = "json", feature = "http"))]
+    pub fn fetch(url: &str) -> Result<PatchFeed, Error> {
+        let data = crate::fetch::download(url)?;
+        let json = String::from_utf8(data).map_err(|e| Error::new(ParsingError)
+            .with_description(format!("Patch feed {} is not UTF-8.", url))
+            .with_source(Box::new(e)))?;
+        return PatchFeed::from_json(&json);
+    }
+
+    /// downloads the latest version and returns `rom` updated to it, see [PatchFeed::check].
+    ///
+    /// A rom with an older version installed is upgraded with [crate::versions::upgrade], which
+    /// needs both versions to be IPS patches. Downloads are verified and cached with
+    /// [crate::fetch::verified].
+    #[cfg(all(feature = "json", feature = "http"))]
+    pub fn update(&self, rom: &[u8]) -> Result<Vec<u8>, Error> {
+        use crate::ErrorKind::{UnsupportedFormat, ValidationError};
+        use crate::ips::IPSPatch;
+
+        let (installed, latest) = match self.check(rom) {
+            UpdateStatus::Available { installed, latest } => (installed, latest),
+            UpdateStatus::UpToDate(x) => return Err(Error::new(ValidationError)
+                .with_description(format!("The rom already has the latest version {} installed.", x.version))),
+            UpdateStatus::Unknown => return Err(Error::new(crate::ErrorKind::WrongSource)
+                .with_description(format!("The rom is not a version of {}.", self.name))),
+        };
+        let latest_patch = download(latest)?;
+        let Some(installed) = installed else {
+            return latest_patch.apply_to_vec(rom);
+        };
+        let installed_patch = download(installed)?;
+        let from = installed_patch.as_any().downcast_ref::<IPSPatch>();
+        let to = latest_patch.as_any().downcast_ref::<IPSPatch>();
+        let (Some(from), Some(to)) = (from, to) else {
+            return Err(Error::new(UnsupportedFormat)
+                .with_description("Only IPS patches can be upgraded without the original rom.".to_string()));
+        };
+        return crate::versions::upgrade(from, to, rom);
+    }
+}
+
+/// downloads the patch of `version`.
+#[cfg(all(feature = "json", feature = "http"))]
+fn download(version: &FeedVersion) -> Result<Box<dyn crate::patch::Patch>, Error> {
+    let sha256 = parse_sha256(&version.sha256).ok_or_else(|| Error::new(crate::ErrorKind::ParsingError)
+        .with_description(format!("Invalid SHA-256 {} of version {}.", version.sha256, version.version)))?;
+    return crate::fetch::verified(&version.url, &sha256);
+}
+
+#[cfg(all(feature = "json", feature = "http"))]
+fn parse_sha256(hex: &str) -> Option<[u8; 32]> {
+    if hex.len() != 64 || !hex.is_ascii() {
+        return None;
+    }
+    let mut result = [0; 32];
+    for (i, byte) in result.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    return Some(result);
+}
+
+/// compares versions by their dot separated numbers, then as text.
+fn compare_versions(a: &str, b: &str) -> Ordering {
+    let numbers = |x: &str| -> Vec<u64> {
+        x.trim_start_matches('v').split(['.', '-', '+']).map_while(|x| x.parse().ok()).collect()
+    };
+    numbers(a).cmp(&numbers(b)).then_with(|| a.cmp(b))
+}
+
+#[cfg(test)]
+mod tests {
+    use spectral::prelude::*;
+
+    use super::*;
+
+    fn version_of(version: &str, source: &[u8], target: &[u8]) -> FeedVersion {
+        FeedVersion {
+            version: version.to_string(),
+            url: format!("https://example.com/{}.ips", version),
+            sha256: String::new(),
+            source_sha1: Some(to_hex(&Sha1::hash(source))),
+            target_sha1: Some(to_hex(&Sha1::hash(target))),
+            notes: None,
+        }
+    }
+
+    #[test]
+    fn versions_compare_by_numbers() {
+        assert_that!(compare_versions("1.10.0", "1.9.2")).is_equal_to(Ordering::Greater);
+        assert_that!(compare_versions("v2", "1.9")).is_equal_to(Ordering::Greater);
+        assert_that!(compare_versions("1.0", "1.0")).is_equal_to(Ordering::Equal);
+    }
+
+    #[test]
+    fn update_status_of_roms() {
+        let feed = PatchFeed {
+            name: "hack".to_string(),
+            versions: vec![version_of("1.10", &[0], &[3]), version_of("1.2", &[0], &[1]), version_of("1.9", &[0], &[2])],
+        };
+        assert_that!(feed.latest().unwrap().version.as_str()).is_equal_to("1.10");
+        match feed.check(&[2]) {
+            UpdateStatus::Available { installed, latest } => {
+                assert_that!(installed.unwrap().version.as_str()).is_equal_to("1.9");
+                assert_that!(latest.version.as_str()).is_equal_to("1.10");
+            }
+            status => panic!("unexpected {:?}", status),
+        }
+        assert_that!(feed.check(&[0])).matches(|x| matches!(x, UpdateStatus::Available { installed: None, .. }));
+        assert_that!(feed.check(&[3])).matches(|x| matches!(x, UpdateStatus::UpToDate(_)));
+        assert_that!(feed.check(&[9])).is_equal_to(UpdateStatus::Unknown);
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn json_roundtrip() {
+        let feed = PatchFeed { name: "hack".to_string(), versions: vec![version_of("1.0", &[0], &[1])] };
+        assert_that!(PatchFeed::from_json(&feed.to_json()).unwrap()).is_equal_to(feed);
+    }
+
+    #[cfg(all(feature = "json", feature = "http"))]
+    #[test]
+    fn updates_installed_versions() {
+        use crate::fetch::tests::serve_once;
+        use crate::hash::Sha256;
+        use crate::ips::IPSPatch;
+
+        let source = vec![0; 64];
+        let mut v1 = source.clone();
+        v1[4] = 1;
+        let mut v2 = v1.clone();
+        v2[8] = 2;
+        let release = |version: &str, target: &[u8]| {
+            let mut data = Vec::new();
+            IPSPatch::create(&source, target).unwrap().write(&mut data).unwrap();
+            FeedVersion {
+                version: version.to_string(),
+                sha256: to_hex(&Sha256::hash(&data)),
+                url: serve_once(data),
+                ..version_of(version, &source, target)
+            }
+        };
+        let feed = PatchFeed { name: "hack".to_string(), versions: vec![release("1.0", &v1), release("1.1", &v2)] };
+        assert_that!(feed.update(&v1).unwrap()).is_equal_to(&v2);
+        assert_that!(feed.update(&v2).unwrap_err().kind()).matches(|x| matches!(x, crate::ErrorKind::ValidationError));
+    }
+}
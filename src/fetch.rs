@@ -0,0 +1,123 @@
+//! Downloading patches with verification, with the `http` feature.
+//!
+//! Launchers usually download a patch, check it against a published hash and apply it. [verified]
+//! covers the first two steps and keeps verified downloads in a cache keyed by their SHA-256, so
+//! a patch is only downloaded once.
+
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::Error;
+use crate::ErrorKind::{ChecksumMismatch, PatchingError};
+use crate::hash::{Sha256, to_hex};
+use crate::patch::Patch;
+use crate::registry::read_any_from_slice;
+use crate::temp::TempTarget;
+
+/// the largest patch downloaded.
+pub const MAX_PATCH_LEN: u64 = 256 << 20;
+
+/// returns the directory [verified] caches downloads in.
+pub fn default_cache_dir() -> PathBuf {
+    std::env::temp_dir().join("rom-patcher-downloads")
+}
+
+/// downloads the patch at `url`, checks that its SHA-256 is `expected_sha256` and parses it,
+/// caching it in [default_cache_dir].
+///
+/// # Examples
+///
+/// ```no_run
+/// use rom_patcher::fetch::verified;
+///
+/// let expected = [0; 32];
+/// let patch = verified("https://example.com/hack.ips", &expected).expect("Unable to download patch.");
+/// let output = patch.apply_to_vec(&std::fs::read("game.sfc").unwrap()).unwrap();
+/// ```
+pub fn verified(url: &str, expected_sha256: &[u8; 32]) -> Result<Box<dyn Patch>, Error> {
+    verified_in(url, expected_sha256, &default_cache_dir())
+}
+
+/// [verified] with a cache in `cache_dir`, which is created when first written to.
+///
+/// A cached patch is used without downloading if it still has the expected hash. Returns a
+/// [ChecksumMismatch] error without caching anything if the download has another hash.
+pub fn verified_in(url: &str, expected_sha256: &[u8; 32], cache_dir: &Path) -> Result<Box<dyn Patch>, Error> {
+    let cached = cache_dir.join(to_hex(expected_sha256));
+    if let Ok(data) = fs::read(&cached) {
+        if Sha256::hash(&data) == *expected_sha256 {
+            return read_any_from_slice(&data);
+        }
+    }
+
+    let data = download(url)?;
+    let actual = Sha256::hash(&data);
+    if actual != *expected_sha256 {
+        return Err(Error::new(ChecksumMismatch).with_description(format!(
+            "SHA-256 of {} is {}, expected {}.", url, to_hex(&actual), to_hex(expected_sha256),
+        )));
+    }
+    let patch = read_any_from_slice(&data)?;
+    // a failing cache only costs another download next time
+    let _ = fs::create_dir_all(cache_dir)
+        .and_then(|_| TempTarget::new(&cached))
+        .and_then(|mut x| x.file().write_all(&data).and_then(|_| x.persist()));
+    return Ok(patch);
+}
+
+pub(crate) fn download(url: &str) -> Result<Vec<u8>, Error> {
+    let error = |e: Box<dyn std::error::Error + Send + Sync>| Error::new(PatchingError)
+        .with_description(format!("Unable to download {}.", url))
+        .with_source(e);
+    let response = ureq::get(url).call().map_err(|e| error(Box::new(e)))?;
+    let mut data = Vec::new();
+    response.into_reader().take(MAX_PATCH_LEN + 1).read_to_end(&mut data).map_err(|e| error(Box::new(e)))?;
+    if data.len() as u64 > MAX_PATCH_LEN {
+        return Err(Error::new(PatchingError)
+            .with_description(format!("{} is larger than {} bytes.", url, MAX_PATCH_LEN)));
+    }
+    return Ok(data);
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::net::TcpListener;
+    use std::thread;
+
+    use spectral::prelude::*;
+
+    use crate::testkit::Fixture;
+
+    use super::*;
+
+    /// serves `body` to a single request and returns its URL.
+    pub(crate) fn serve_once(body: Vec<u8>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hack.ips", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 1024];
+            let _ = stream.read(&mut request);
+            let _ = write!(stream, "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", body.len());
+            let _ = stream.write_all(&body);
+        });
+        return url;
+    }
+
+    #[test]
+    fn downloads_are_verified_and_cached() {
+        let cache = std::env::temp_dir().join(format!("rom-patcher-fetch-{}", std::process::id()));
+        let mut data = Vec::new();
+        Fixture::generate(41).ips.write(&mut data).unwrap();
+        let expected = Sha256::hash(&data);
+
+        assert_that!(verified_in(&serve_once(data.clone()), &[0; 32], &cache).unwrap_err().kind())
+            .matches(|x| matches!(x, ChecksumMismatch));
+        let patch = verified_in(&serve_once(data.clone()), &expected, &cache).unwrap();
+        assert_that!(patch.format()).is_equal_to("ips");
+        // nothing listens anymore, so this is served from the cache
+        assert_that!(verified_in("http://127.0.0.1:9/hack.ips", &expected, &cache)).is_ok();
+        fs::remove_dir_all(cache).unwrap();
+    }
+}
@@ -0,0 +1,228 @@
+//! Region and revision information from the headers of roms, so users can tell which dump of a
+//! game they actually have.
+
+use std::fmt::{Display, Formatter};
+
+use crate::console::Console;
+use crate::header::split_header;
+
+/// What the headers of a rom say about the game it is.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize))]
+pub struct Fingerprint {
+    /// the console the rom was dumped from.
+    pub console: Console,
+    /// the internal title of the game.
+    pub title: Option<String>,
+    /// the product code of the game, e.g. `AXVE` for a GBA game.
+    pub game_code: Option<String>,
+    /// the region the game was released in, e.g. `USA`.
+    pub region: Option<String>,
+    /// the revision of the game, `0` for its first release.
+    pub revision: Option<u8>,
+    /// the iNES mapper number of a NES rom.
+    pub mapper: Option<u16>,
+}
+
+impl Fingerprint {
+    fn new(console: Console) -> Fingerprint {
+        Fingerprint { console, title: None, game_code: None, region: None, revision: None, mapper: None }
+    }
+}
+
+impl Display for Fingerprint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if let Some(title) = &self.title {
+            write!(f, "\"{}\" ", title)?;
+        }
+        write!(f, "({}", console_name(self.console))?;
+        if let Some(game_code) = &self.game_code {
+            write!(f, ", {}", game_code)?;
+        }
+        if let Some(region) = &self.region {
+            write!(f, ", {}", region)?;
+        }
+        if let Some(revision) = self.revision {
+            write!(f, ", revision {}", revision)?;
+        }
+        if let Some(mapper) = self.mapper {
+            write!(f, ", mapper {}", mapper)?;
+        }
+        write!(f, ")")
+    }
+}
+
+/// returns the fingerprint of `rom`, or [None] if its console isn't recognized, see
+/// [Console::detect].
+///
+/// # Examples
+///
+/// ```
+/// use rom_patcher::fingerprint::fingerprint;
+/// let mut rom = vec![0; 0x6010];
+/// rom[..8].copy_from_slice(&[b'N', b'E', b'S', 0x1A, 1, 1, 0x40, 0]);
+/// let fingerprint = fingerprint(&rom).unwrap();
+/// assert_eq!(fingerprint.mapper, Some(4));
+/// assert_eq!(fingerprint.to_string(), "(NES, NTSC, mapper 4)");
+/// ```
+pub fn fingerprint(rom: &[u8]) -> Option<Fingerprint> {
+    let console = Console::detect(rom)?;
+    let mut result = Fingerprint::new(console);
+    match console {
+        Console::Flat => {}
+        Console::SnesLoRom | Console::SnesHiRom => {
+            let body = split_header(rom).1;
+            let offset = if console == Console::SnesLoRom { 0x7FC0 } else { 0xFFC0 };
+            let header = &body[offset..offset + 0x20];
+            result.title = text(&header[..0x15]);
+            result.region = snes_region(header[0x19]).map(str::to_string);
+            result.revision = Some(header[0x1B]);
+        }
+        Console::GameBoyAdvance => {
+            result.title = text(&rom[0xA0..0xAC]);
+            result.game_code = text(&rom[0xAC..0xB0]);
+            result.region = region_of_code(rom[0xAF]).map(str::to_string);
+            result.revision = Some(rom[0xBC]);
+        }
+        Console::Nintendo64 => {
+            result.title = rom.get(0x20..0x34).and_then(text);
+            result.game_code = rom.get(0x3B..0x3F).and_then(text);
+            result.region = rom.get(0x3E).and_then(|x| region_of_code(*x)).map(str::to_string);
+            result.revision = rom.get(0x3F).copied();
+        }
+        Console::Genesis => {
+            result.title = rom.get(0x150..0x180).and_then(text);
+            // the serial reads e.g. `GM 00001009-00`, the last two digits being the revision
+            let serial = rom.get(0x180..0x18E).and_then(text);
+            result.revision = serial.as_ref()
+                .and_then(|x| x.rsplit_once('-'))
+                .and_then(|(_, x)| x.trim().parse().ok());
+            result.game_code = serial;
+            result.region = rom.get(0x1F0..0x1F3).and_then(text);
+        }
+        Console::Nes => {
+            let flags = |i: usize| rom.get(i).copied().unwrap_or(0);
+            let mut mapper = (flags(6) >> 4) as u16 | (flags(7) & 0xF0) as u16;
+            let nes2 = flags(7) & 0x0C == 0x08;
+            if nes2 {
+                mapper |= ((flags(8) & 0x0F) as u16) << 8;
+            }
+            result.mapper = Some(mapper);
+            let timing = if nes2 { flags(12) & 0x03 } else { flags(9) & 0x01 };
+            result.region = Some(match timing {
+                0 => "NTSC",
+                1 => "PAL",
+                2 => "NTSC/PAL",
+                _ => "Dendy",
+            }.to_string());
+        }
+    }
+    return Some(result);
+}
+
+/// returns the human readable name of `console`.
+fn console_name(console: Console) -> &'static str {
+    match console {
+        Console::Flat => "unknown console",
+        Console::SnesLoRom => "SNES LoROM",
+        Console::SnesHiRom => "SNES HiROM",
+        Console::GameBoyAdvance => "GBA",
+        Console::Nintendo64 => "N64",
+        Console::Genesis => "Genesis",
+        Console::Nes => "NES",
+    }
+}
+
+/// returns `data` as trimmed text, or [None] if it is empty or not printable ASCII.
+fn text(data: &[u8]) -> Option<String> {
+    let data = data.split(|x| *x == 0).next().unwrap_or(&[]);
+    if !data.iter().all(|x| (0x20..0x7F).contains(x)) {
+        return None;
+    }
+    let result = String::from_utf8_lossy(data).trim().to_string();
+    if result.is_empty() {
+        return None;
+    }
+    return Some(result);
+}
+
+/// returns the region of a SNES country code.
+fn snes_region(code: u8) -> Option<&'static str> {
+    Some(match code {
+        0x00 => "Japan",
+        0x01 => "USA",
+        0x02 => "Europe",
+        0x03 => "Sweden",
+        0x04 => "Finland",
+        0x05 => "Denmark",
+        0x06 => "France",
+        0x07 => "Netherlands",
+        0x08 => "Spain",
+        0x09 => "Germany",
+        0x0A => "Italy",
+        0x0B => "China",
+        0x0C => "Indonesia",
+        0x0D => "Korea",
+        0x0F => "Canada",
+        0x10 => "Brazil",
+        0x11 => "Australia",
+        _ => return None,
+    })
+}
+
+/// returns the region of the last character of a GBA or N64 game code.
+fn region_of_code(code: u8) -> Option<&'static str> {
+    Some(match code {
+        b'J' => "Japan",
+        b'E' | b'N' => "USA",
+        b'P' | b'X' | b'Y' => "Europe",
+        b'D' => "Germany",
+        b'F' => "France",
+        b'I' => "Italy",
+        b'S' => "Spain",
+        b'U' => "Australia",
+        b'K' => "Korea",
+        b'C' => "China",
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use spectral::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn snes_headers_name_title_region_and_revision() {
+        let mut rom = vec![0; 0x200 + 0x8000];
+        let header = 0x200 + 0x7FC0;
+        rom[header..header + 0x15].copy_from_slice(b"SUPER MARIOWORLD     ");
+        rom[header + 0x19] = 0x01;
+        rom[header + 0x1B] = 0x01;
+        rom[header + 0x1C..header + 0x20].copy_from_slice(&[0x34, 0x12, 0xCB, 0xED]);
+        let fingerprint = fingerprint(&rom).unwrap();
+        assert_that!(fingerprint.to_string()).is_equal_to("\"SUPER MARIOWORLD\" (SNES LoROM, USA, revision 1)".to_string());
+    }
+
+    #[test]
+    fn gba_headers_name_game_code_and_region() {
+        let mut rom = vec![0; 0xC0];
+        rom[0xA0..0xB0].copy_from_slice(b"POKEMON EMERBPEE");
+        rom[0xB2] = 0x96;
+        let fingerprint = fingerprint(&rom).unwrap();
+        assert_that!(fingerprint.title).is_equal_to(Some("POKEMON EMER".to_string()));
+        assert_that!(fingerprint.game_code).is_equal_to(Some("BPEE".to_string()));
+        assert_that!(fingerprint.region).is_equal_to(Some("USA".to_string()));
+        assert_that!(fingerprint.revision).is_equal_to(Some(0));
+    }
+
+    #[test]
+    fn nes2_headers_extend_the_mapper() {
+        let mut rom = vec![0; 16];
+        rom[..13].copy_from_slice(&[b'N', b'E', b'S', 0x1A, 1, 1, 0x10, 0x28, 0x01, 0, 0, 0, 0x01]);
+        let fingerprint = fingerprint(&rom).unwrap();
+        assert_that!(fingerprint.mapper).is_equal_to(Some(0x121));
+        assert_that!(fingerprint.region).is_equal_to(Some("PAL".to_string()));
+    }
+}
@@ -0,0 +1,246 @@
+//! Optional features of a patch, made of named groups of its hunks.
+//!
+//! One patch file can ship optional changes like "easy mode" or "no intro" by tagging their hunks
+//! into [groups](HunkGroup). Hunks outside of every group are always applied, hunks inside of
+//! groups only when one of their groups is enabled.
+//!
+//! Groups are stored next to the patch in a JSON sidecar, `<patch>.groups.json`, with the `json`
+//! feature enabled.
+
+#[cfg(feature = "json")]
+use std::fs;
+use std::io::{Seek, Write};
+#[cfg(feature = "json")]
+use std::path::{Path, PathBuf};
+
+use crate::Error;
+#[cfg(feature = "json")]
+use crate::ErrorKind::{ParsingError, PatchingError};
+use crate::ErrorKind::ValidationError;
+use crate::io_util::Truncate;
+use crate::ips::IPSPatch;
+#[cfg(feature = "json")]
+use crate::messages::Message;
+
+/// A named group of hunks that is applied or left out as a whole.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize))]
+pub struct HunkGroup {
+    /// name of the group, unique within the patch.
+    pub name: String,
+    /// a human readable description of what the group changes.
+    #[cfg_attr(feature = "json", serde(default))]
+    pub description: Option<String>,
+    /// indices of the hunks of the group.
+    pub hunks: Vec<usize>,
+    /// `true` if the group is applied unless disabled.
+    #[cfg_attr(feature = "json", serde(default))]
+    pub enabled_by_default: bool,
+}
+
+impl HunkGroup {
+    /// constructs a group `name` of the hunks at `hunks` that is disabled by default.
+    pub fn new(name: &str, hunks: Vec<usize>) -> HunkGroup {
+        HunkGroup { name: name.to_string(), description: None, hunks, enabled_by_default: false }
+    }
+
+    /// modifies the group to be described by `description`.
+    pub fn with_description(mut self, description: &str) -> HunkGroup {
+        self.description = Some(description.to_string());
+        return self;
+    }
+
+    /// modifies the group to be applied unless disabled if `enabled_by_default` is `true`.
+    pub fn with_enabled_by_default(mut self, enabled_by_default: bool) -> HunkGroup {
+        self.enabled_by_default = enabled_by_default;
+        return self;
+    }
+}
+
+/// The groups of hunks of a patch.
+///
+/// # Examples
+///
+/// ```
+/// use std::io::Cursor;
+/// use rom_patcher::groups::{HunkGroup, HunkGroups};
+/// use rom_patcher::ips::{IPSHunk, IPSPatch, IPSRLEHunkData};
+///
+/// let patch = IPSPatch::new()
+///     .with_hunk(IPSHunk::RLE(IPSRLEHunkData { offset: 0, run_length: 1, payload: 0xAA }))
+///     .with_hunk(IPSHunk::RLE(IPSRLEHunkData { offset: 1, run_length: 1, payload: 0xBB }));
+/// let groups = HunkGroups::new().with_group(HunkGroup::new("easy mode", vec![1]));
+///
+/// let mut target = Cursor::new(vec![0; 2]);
+/// groups.apply(&patch, &mut target, &[]).unwrap();
+/// assert_eq!(target.into_inner(), vec![0xAA, 0]);
+/// let mut target = Cursor::new(vec![0; 2]);
+/// groups.apply(&patch, &mut target, &["easy mode"]).unwrap();
+/// assert_eq!(target.into_inner(), vec![0xAA, 0xBB]);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize))]
+pub struct HunkGroups {
+    groups: Vec<HunkGroup>,
+}
+
+impl HunkGroups {
+    /// constructs an empty set of groups.
+    pub fn new() -> HunkGroups {
+        HunkGroups::default()
+    }
+
+    /// modifies the groups to contain `group`.
+    pub fn with_group(mut self, group: HunkGroup) -> HunkGroups {
+        self.groups.push(group);
+        return self;
+    }
+
+    /// returns the groups in the order they were added.
+    pub fn groups(&self) -> &[HunkGroup] {
+        &self.groups
+    }
+
+    /// returns the group called `name`.
+    pub fn group(&self, name: &str) -> Option<&HunkGroup> {
+        self.groups.iter().find(|x| x.name == name)
+    }
+
+    /// returns the names of the groups that are applied unless disabled.
+    pub fn enabled_by_default(&self) -> Vec<&str> {
+        self.groups.iter().filter(|x| x.enabled_by_default).map(|x| x.name.as_str()).collect()
+    }
+
+    /// returns a [ValidationError] if two groups share a name or a group refers to a hunk past
+    /// the `hunk_count` hunks of the patch.
+    pub fn validate(&self, hunk_count: usize) -> Result<(), Error> {
+        for (i, group) in self.groups.iter().enumerate() {
+            if self.groups[..i].iter().any(|x| x.name == group.name) {
+                return Err(Error::new(ValidationError)
+                    .with_description(format!("Group {} is defined twice.", group.name)));
+            }
+            if let Some(hunk) = group.hunks.iter().find(|x| **x >= hunk_count) {
+                return Err(Error::new(ValidationError)
+                    .with_description(format!("Group {} refers to hunk {}, the patch has {} hunks.", group.name, hunk, hunk_count)));
+            }
+        }
+        Ok(())
+    }
+
+    /// returns the indices of the hunks applied with the groups in `enabled` enabled, out of
+    /// `hunk_count` hunks.
+    ///
+    /// Returns a [ValidationError] if a group in `enabled` doesn't exist or the groups are
+    /// [invalid](HunkGroups::validate).
+    pub fn selected_hunks(&self, hunk_count: usize, enabled: &[&str]) -> Result<Vec<usize>, Error> {
+        self.validate(hunk_count)?;
+        if let Some(name) = enabled.iter().find(|x| self.group(x).is_none()) {
+            return Err(Error::new(ValidationError).with_description(format!("There is no group {}.", name)));
+        }
+        let mut grouped = vec![false; hunk_count];
+        let mut selected = vec![false; hunk_count];
+        for group in &self.groups {
+            let is_enabled = enabled.contains(&group.name.as_str());
+            for hunk in &group.hunks {
+                grouped[*hunk] = true;
+                selected[*hunk] |= is_enabled;
+            }
+        }
+        return Ok((0..hunk_count).filter(|x| !grouped[*x] || selected[*x]).collect());
+    }
+
+    /// applies `patch` to `target` with the groups in `enabled` enabled, see
+    /// [HunkGroups::selected_hunks] and [IPSPatch::apply_selected].
+    pub fn apply<T>(&self, patch: &IPSPatch, target: &mut T, enabled: &[&str]) -> Result<(), Error>
+        where T: Write + Seek + Truncate {
+        let selected = self.selected_hunks(patch.hunks().len(), enabled)?;
+        patch.apply_selected(target, &selected)
+    }
+
+    /// serializes the groups as JSON.
+    #[cfg(feature = "json")]
+    pub fn to_json(&self) -> String {
+        // groups only contain plain data
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    /// deserializes groups serialized with [HunkGroups::to_json].
+    #[cfg(feature = "json")]
+    pub fn from_json(json: &str) -> Result<HunkGroups, Error> {
+        serde_json::from_str(json).map_err(|e| Error::new(ParsingError)
+            .with_description("Invalid hunk groups.".to_string())
+            .with_source(Box::new(e)))
+    }
+
+    /// returns the path of the sidecar storing the groups of the patch at `patch`.
+    #[cfg(feature = "json")]
+    pub fn sidecar_path(patch: &Path) -> PathBuf {
+        let mut name = patch.as_os_str().to_os_string();
+        name.push(".groups.json");
+        return PathBuf::from(name);
+    }
+
+    /// reads the groups of the patch at `patch` from its sidecar, [None] if it has none.
+    #[cfg(feature = "json")]
+    pub fn read_sidecar(patch: &Path) -> Result<Option<HunkGroups>, Error> {
+        let path = Self::sidecar_path(patch);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let json = fs::read_to_string(&path).map_err(|e| Error::new(ParsingError)
+            .with_message(Message::UnableToRead { path: path.to_path_buf() })
+            .with_source(Box::new(e)))?;
+        return HunkGroups::from_json(&json).map(Some);
+    }
+
+    /// writes the groups to the sidecar of the patch at `patch`.
+    #[cfg(feature = "json")]
+    pub fn write_sidecar(&self, patch: &Path) -> Result<(), Error> {
+        let path = Self::sidecar_path(patch);
+        fs::write(&path, self.to_json()).map_err(|e| Error::new(PatchingError)
+            .with_message(Message::UnableToWrite { path: path.to_path_buf() })
+            .with_source(Box::new(e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use spectral::prelude::*;
+
+    use super::*;
+
+    fn groups() -> HunkGroups {
+        HunkGroups::new()
+            .with_group(HunkGroup::new("no intro", vec![1, 2]).with_enabled_by_default(true))
+            .with_group(HunkGroup::new("easy mode", vec![2, 3]).with_description("Halves damage taken."))
+    }
+
+    #[test]
+    fn hunks_of_enabled_groups_and_ungrouped_hunks_are_selected() {
+        let groups = groups();
+        assert_that!(groups.selected_hunks(5, &[]).unwrap()).is_equal_to(vec![0, 4]);
+        assert_that!(groups.selected_hunks(5, &["easy mode"]).unwrap()).is_equal_to(vec![0, 2, 3, 4]);
+        assert_that!(groups.selected_hunks(5, &groups.enabled_by_default()).unwrap()).is_equal_to(vec![0, 1, 2, 4]);
+    }
+
+    #[test]
+    fn unknown_groups_and_hunks_fail() {
+        assert_that!(groups().selected_hunks(5, &["hard mode"])).is_err();
+        assert_that!(groups().selected_hunks(3, &[])).is_err();
+        let duplicate = groups().with_group(HunkGroup::new("no intro", vec![]));
+        assert_that!(duplicate.validate(5)).is_err();
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn sidecar_roundtrip() {
+        let dir = std::env::temp_dir().join(format!("rom-patcher-groups-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let patch = dir.join("hack.ips");
+        assert_that!(HunkGroups::read_sidecar(&patch).unwrap()).is_none();
+        groups().write_sidecar(&patch).unwrap();
+        let read = HunkGroups::read_sidecar(&patch).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_that!(read).is_equal_to(Some(groups()));
+    }
+}
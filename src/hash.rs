@@ -0,0 +1,755 @@
+//! Checksums and hashes used to identify and verify roms and patches.
+//!
+//! Besides the built-in hash functions, others can be added with [register_hash] and looked up by
+//! name with [hash_algorithm].
+
+use std::sync::RwLock;
+
+use crate::Error;
+use crate::ErrorKind::ValidationError;
+
+/// An incremental hash function.
+pub trait Digest {
+    /// feeds `data` to the hash function.
+    fn update(&mut self, data: &[u8]);
+
+    /// returns the hash of all data fed so far as big-endian bytes, without resetting the state.
+    fn finish(&self) -> Vec<u8>;
+}
+
+/// Lookup table for the reflected CRC-32 (IEEE 802.3) polynomial.
+const CRC32_TABLE: [u32; 256] = crc32_table();
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut value = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            value = if value & 1 == 1 { (value >> 1) ^ 0xEDB8_8320 } else { value >> 1 };
+            bit += 1;
+        }
+        table[i] = value;
+        i += 1;
+    }
+    return table;
+}
+
+/// Incremental CRC-32 as used by zip, UPS and BPS.
+#[derive(Debug, Clone)]
+pub struct Crc32 {
+    state: u32,
+}
+
+impl Crc32 {
+    /// constructs a [Crc32] with no data fed.
+    pub const fn new() -> Crc32 {
+        Crc32 { state: 0xFFFF_FFFF }
+    }
+
+    /// returns the CRC-32 of `data`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rom_patcher::hash::Crc32;
+    /// assert_eq!(Crc32::checksum(b"123456789"), 0xCBF43926);
+    /// ```
+    pub fn checksum(data: &[u8]) -> u32 {
+        let mut crc = Crc32::new();
+        crc.update(data);
+        return crc.value();
+    }
+
+    /// returns the CRC-32 of all data fed so far.
+    pub fn value(&self) -> u32 {
+        !self.state
+    }
+}
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Crc32::new()
+    }
+}
+
+impl Digest for Crc32 {
+    fn update(&mut self, data: &[u8]) {
+        for byte in data {
+            self.state = CRC32_TABLE[((self.state ^ *byte as u32) & 0xFF) as usize] ^ (self.state >> 8);
+        }
+    }
+
+    fn finish(&self) -> Vec<u8> {
+        self.value().to_be_bytes().to_vec()
+    }
+}
+
+/// Incremental SHA-1 as used by rom databases.
+#[derive(Debug, Clone)]
+pub struct Sha1 {
+    state: [u32; 5],
+    buffer: [u8; 64],
+    buffered: usize,
+    length: u64,
+}
+
+impl Sha1 {
+    /// constructs a [Sha1] with no data fed.
+    pub const fn new() -> Sha1 {
+        Sha1 {
+            state: [0x6745_2301, 0xEFCD_AB89, 0x98BA_DCFE, 0x1032_5476, 0xC3D2_E1F0],
+            buffer: [0; 64],
+            buffered: 0,
+            length: 0,
+        }
+    }
+
+    /// returns the SHA-1 of `data`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rom_patcher::hash::{to_hex, Sha1};
+    /// assert_eq!(to_hex(&Sha1::hash(b"abc")), "a9993e364706816aba3e25717850c26c9cd0d89d");
+    /// ```
+    pub fn hash(data: &[u8]) -> [u8; 20] {
+        let mut sha1 = Sha1::new();
+        sha1.update(data);
+        return sha1.digest();
+    }
+
+    /// returns the SHA-1 of all data fed so far.
+    pub fn digest(&self) -> [u8; 20] {
+        let mut state = self.clone();
+        let bit_length = state.length.wrapping_mul(8);
+        state.update(&[0x80]);
+        while state.buffered != 56 {
+            state.update(&[0]);
+        }
+        state.update(&bit_length.to_be_bytes());
+
+        let mut result = [0; 20];
+        for (chunk, word) in result.chunks_mut(4).zip(state.state) {
+            chunk.copy_from_slice(&word.to_be_bytes());
+        }
+        return result;
+    }
+
+    fn compress(state: &mut [u32; 5], block: &[u8]) {
+        let mut w = [0u32; 80];
+        for (i, chunk) in block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = *state;
+        for (i, word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A82_7999),
+                20..=39 => (b ^ c ^ d, 0x6ED9_EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1B_BCDC),
+                _ => (b ^ c ^ d, 0xCA62_C1D6),
+            };
+            let temp = a.rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (value, new) in state.iter_mut().zip([a, b, c, d, e]) {
+            *value = value.wrapping_add(new);
+        }
+    }
+}
+
+impl Default for Sha1 {
+    fn default() -> Self {
+        Sha1::new()
+    }
+}
+
+impl Digest for Sha1 {
+    fn update(&mut self, mut data: &[u8]) {
+        self.length = self.length.wrapping_add(data.len() as u64);
+        // top up a partially filled block first
+        if self.buffered > 0 {
+            let taken = (64 - self.buffered).min(data.len());
+            self.buffer[self.buffered..self.buffered + taken].copy_from_slice(&data[..taken]);
+            self.buffered += taken;
+            data = &data[taken..];
+            if self.buffered < 64 {
+                return;
+            }
+            let block = self.buffer;
+            Self::compress(&mut self.state, &block);
+            self.buffered = 0;
+        }
+        let mut blocks = data.chunks_exact(64);
+        for block in &mut blocks {
+            Self::compress(&mut self.state, block);
+        }
+        let rest = blocks.remainder();
+        self.buffer[..rest.len()].copy_from_slice(rest);
+        self.buffered = rest.len();
+    }
+
+    fn finish(&self) -> Vec<u8> {
+        self.digest().to_vec()
+    }
+}
+
+/// Round constants of SHA-256.
+const SHA256_K: [u32; 64] = [
+    0x428A_2F98, 0x7137_4491, 0xB5C0_FBCF, 0xE9B5_DBA5, 0x3956_C25B, 0x59F1_11F1, 0x923F_82A4, 0xAB1C_5ED5,
+    0xD807_AA98, 0x1283_5B01, 0x2431_85BE, 0x550C_7DC3, 0x72BE_5D74, 0x80DE_B1FE, 0x9BDC_06A7, 0xC19B_F174,
+    0xE49B_69C1, 0xEFBE_4786, 0x0FC1_9DC6, 0x240C_A1CC, 0x2DE9_2C6F, 0x4A74_84AA, 0x5CB0_A9DC, 0x76F9_88DA,
+    0x983E_5152, 0xA831_C66D, 0xB003_27C8, 0xBF59_7FC7, 0xC6E0_0BF3, 0xD5A7_9147, 0x06CA_6351, 0x1429_2967,
+    0x27B7_0A85, 0x2E1B_2138, 0x4D2C_6DFC, 0x5338_0D13, 0x650A_7354, 0x766A_0ABB, 0x81C2_C92E, 0x9272_2C85,
+    0xA2BF_E8A1, 0xA81A_664B, 0xC24B_8B70, 0xC76C_51A3, 0xD192_E819, 0xD699_0624, 0xF40E_3585, 0x106A_A070,
+    0x19A4_C116, 0x1E37_6C08, 0x2748_774C, 0x34B0_BCB5, 0x391C_0CB3, 0x4ED8_AA4A, 0x5B9C_CA4F, 0x682E_6FF3,
+    0x748F_82EE, 0x78A5_636F, 0x84C8_7814, 0x8CC7_0208, 0x90BE_FFFA, 0xA450_6CEB, 0xBEF9_A3F7, 0xC671_78F2,
+];
+
+/// Incremental SHA-256 as used by newer rom databases and request signing.
+#[derive(Debug, Clone)]
+pub struct Sha256 {
+    state: [u32; 8],
+    buffer: [u8; 64],
+    buffered: usize,
+    length: u64,
+}
+
+impl Sha256 {
+    /// constructs a [Sha256] with no data fed.
+    pub const fn new() -> Sha256 {
+        Sha256 {
+            state: [
+                0x6A09_E667, 0xBB67_AE85, 0x3C6E_F372, 0xA54F_F53A, 0x510E_527F, 0x9B05_688C, 0x1F83_D9AB, 0x5BE0_CD19,
+            ],
+            buffer: [0; 64],
+            buffered: 0,
+            length: 0,
+        }
+    }
+
+    /// returns the SHA-256 of `data`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rom_patcher::hash::{to_hex, Sha256};
+    /// assert_eq!(to_hex(&Sha256::hash(b"abc")), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+    /// ```
+    pub fn hash(data: &[u8]) -> [u8; 32] {
+        let mut sha256 = Sha256::new();
+        sha256.update(data);
+        return sha256.digest();
+    }
+
+    /// returns the SHA-256 of all data fed so far.
+    pub fn digest(&self) -> [u8; 32] {
+        let mut state = self.clone();
+        let bit_length = state.length.wrapping_mul(8);
+        state.update(&[0x80]);
+        while state.buffered != 56 {
+            state.update(&[0]);
+        }
+        state.update(&bit_length.to_be_bytes());
+
+        let mut result = [0; 32];
+        for (chunk, word) in result.chunks_mut(4).zip(state.state) {
+            chunk.copy_from_slice(&word.to_be_bytes());
+        }
+        return result;
+    }
+
+    fn compress(state: &mut [u32; 8], block: &[u8]) {
+        let mut w = [0u32; 64];
+        for (i, chunk) in block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16].wrapping_add(s0).wrapping_add(w[i - 7]).wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = *state;
+        for (k, word) in SHA256_K.iter().zip(w) {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let choice = (e & f) ^ (!e & g);
+            let temp1 = h.wrapping_add(s1).wrapping_add(choice).wrapping_add(*k).wrapping_add(word);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let majority = (a & b) ^ (a & c) ^ (b & c);
+            let temp2 = s0.wrapping_add(majority);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(temp1);
+            d = c;
+            c = b;
+            b = a;
+            a = temp1.wrapping_add(temp2);
+        }
+        for (value, new) in state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *value = value.wrapping_add(new);
+        }
+    }
+}
+
+impl Default for Sha256 {
+    fn default() -> Self {
+        Sha256::new()
+    }
+}
+
+impl Digest for Sha256 {
+    fn update(&mut self, mut data: &[u8]) {
+        self.length = self.length.wrapping_add(data.len() as u64);
+        // top up a partially filled block first
+        if self.buffered > 0 {
+            let taken = (64 - self.buffered).min(data.len());
+            self.buffer[self.buffered..self.buffered + taken].copy_from_slice(&data[..taken]);
+            self.buffered += taken;
+            data = &data[taken..];
+            if self.buffered < 64 {
+                return;
+            }
+            let block = self.buffer;
+            Self::compress(&mut self.state, &block);
+            self.buffered = 0;
+        }
+        let mut blocks = data.chunks_exact(64);
+        for block in &mut blocks {
+            Self::compress(&mut self.state, block);
+        }
+        let rest = blocks.remainder();
+        self.buffer[..rest.len()].copy_from_slice(rest);
+        self.buffered = rest.len();
+    }
+
+    fn finish(&self) -> Vec<u8> {
+        self.digest().to_vec()
+    }
+}
+
+#[cfg(feature = "xxhash")]
+const XXH_PRIME_1: u64 = 0x9E37_79B1_85EB_CA87;
+#[cfg(feature = "xxhash")]
+const XXH_PRIME_2: u64 = 0xC2B2_AE3D_27D4_EB4F;
+#[cfg(feature = "xxhash")]
+const XXH_PRIME_3: u64 = 0x1656_67B1_9E37_79F9;
+#[cfg(feature = "xxhash")]
+const XXH_PRIME_4: u64 = 0x85EB_CA77_C2B2_AE63;
+#[cfg(feature = "xxhash")]
+const XXH_PRIME_5: u64 = 0x27D4_EB2F_1656_67C5;
+
+/// Incremental xxHash64 with a seed of 0, far faster than the cryptographic hashes for
+/// deduplicating large sets of roms.
+#[cfg(feature = "xxhash")]
+#[derive(Debug, Clone)]
+pub struct Xxh64 {
+    lanes: [u64; 4],
+    buffer: [u8; 32],
+    buffer_len: usize,
+    total_len: u64,
+}
+
+#[cfg(feature = "xxhash")]
+impl Xxh64 {
+    /// constructs a [Xxh64] with no data fed.
+    pub const fn new() -> Xxh64 {
+        Xxh64 {
+            lanes: [
+                XXH_PRIME_1.wrapping_add(XXH_PRIME_2),
+                XXH_PRIME_2,
+                0,
+                0u64.wrapping_sub(XXH_PRIME_1),
+            ],
+            buffer: [0; 32],
+            buffer_len: 0,
+            total_len: 0,
+        }
+    }
+
+    /// returns the xxHash64 of `data`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rom_patcher::hash::Xxh64;
+    /// assert_eq!(Xxh64::hash(b"abc"), 0x44BC2CF5AD770999);
+    /// ```
+    pub fn hash(data: &[u8]) -> u64 {
+        let mut xxh = Xxh64::new();
+        xxh.update(data);
+        return xxh.value();
+    }
+
+    /// returns the xxHash64 of all data fed so far.
+    pub fn value(&self) -> u64 {
+        let mut result = if self.total_len >= 32 {
+            let [v1, v2, v3, v4] = self.lanes;
+            let mut result = v1.rotate_left(1)
+                .wrapping_add(v2.rotate_left(7))
+                .wrapping_add(v3.rotate_left(12))
+                .wrapping_add(v4.rotate_left(18));
+            for lane in self.lanes {
+                result = (result ^ xxh_round(0, lane)).wrapping_mul(XXH_PRIME_1).wrapping_add(XXH_PRIME_4);
+            }
+            result
+        } else {
+            XXH_PRIME_5
+        };
+        result = result.wrapping_add(self.total_len);
+
+        let mut rest = &self.buffer[..self.buffer_len];
+        while let Some((word, tail)) = rest.split_first_chunk::<8>() {
+            result ^= xxh_round(0, u64::from_le_bytes(*word));
+            result = result.rotate_left(27).wrapping_mul(XXH_PRIME_1).wrapping_add(XXH_PRIME_4);
+            rest = tail;
+        }
+        if let Some((word, tail)) = rest.split_first_chunk::<4>() {
+            result ^= (u32::from_le_bytes(*word) as u64).wrapping_mul(XXH_PRIME_1);
+            result = result.rotate_left(23).wrapping_mul(XXH_PRIME_2).wrapping_add(XXH_PRIME_3);
+            rest = tail;
+        }
+        for byte in rest {
+            result ^= (*byte as u64).wrapping_mul(XXH_PRIME_5);
+            result = result.rotate_left(11).wrapping_mul(XXH_PRIME_1);
+        }
+
+        result ^= result >> 33;
+        result = result.wrapping_mul(XXH_PRIME_2);
+        result ^= result >> 29;
+        result = result.wrapping_mul(XXH_PRIME_3);
+        result ^= result >> 32;
+        return result;
+    }
+
+    fn consume_stripe(&mut self, stripe: &[u8; 32]) {
+        for (lane, word) in self.lanes.iter_mut().zip(stripe.chunks_exact(8)) {
+            *lane = xxh_round(*lane, u64::from_le_bytes(word.try_into().unwrap()));
+        }
+    }
+}
+
+#[cfg(feature = "xxhash")]
+fn xxh_round(lane: u64, input: u64) -> u64 {
+    lane.wrapping_add(input.wrapping_mul(XXH_PRIME_2)).rotate_left(31).wrapping_mul(XXH_PRIME_1)
+}
+
+#[cfg(feature = "xxhash")]
+impl Default for Xxh64 {
+    fn default() -> Self {
+        Xxh64::new()
+    }
+}
+
+#[cfg(feature = "xxhash")]
+impl Digest for Xxh64 {
+    fn update(&mut self, mut data: &[u8]) {
+        self.total_len += data.len() as u64;
+        if self.buffer_len > 0 {
+            let len = data.len().min(32 - self.buffer_len);
+            self.buffer[self.buffer_len..self.buffer_len + len].copy_from_slice(&data[..len]);
+            self.buffer_len += len;
+            data = &data[len..];
+            if self.buffer_len < 32 {
+                return;
+            }
+            let stripe = self.buffer;
+            self.consume_stripe(&stripe);
+            self.buffer_len = 0;
+        }
+        while let Some((stripe, rest)) = data.split_first_chunk::<32>() {
+            self.consume_stripe(stripe);
+            data = rest;
+        }
+        self.buffer[..data.len()].copy_from_slice(data);
+        self.buffer_len = data.len();
+    }
+
+    fn finish(&self) -> Vec<u8> {
+        self.value().to_be_bytes().to_vec()
+    }
+}
+
+/// returns the HMAC-SHA-256 of `data` with `key`.
+pub fn hmac_sha256(key: &[u8], data: &[u8]) -> [u8; 32] {
+    let mut block = [0u8; 64];
+    if key.len() > 64 {
+        block[..32].copy_from_slice(&Sha256::hash(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+    let mut inner = Sha256::new();
+    inner.update(&block.map(|x| x ^ 0x36));
+    inner.update(data);
+    let mut outer = Sha256::new();
+    outer.update(&block.map(|x| x ^ 0x5C));
+    outer.update(&inner.digest());
+    return outer.digest();
+}
+
+/// formats `bytes` as lower case hexadecimal.
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|x| format!("{:02x}", x)).collect()
+}
+
+/// Describes a hash function known to [hash_algorithm], see [register_hash].
+///
+/// Hash functions are compared by name, which is unique among known ones.
+#[derive(Debug, Clone, Copy)]
+pub struct HashAlgorithm {
+    /// unique name of the hash function, e.g. `"sha1"`.
+    pub name: &'static str,
+    /// constructs the hash function with no data fed.
+    pub new: fn() -> Box<dyn Digest>,
+}
+
+impl HashAlgorithm {
+    /// returns the hash of `data`.
+    pub fn hash(&self, data: &[u8]) -> Vec<u8> {
+        let mut digest = (self.new)();
+        digest.update(data);
+        return digest.finish();
+    }
+
+    /// returns `true` if `data` has the hash `expected`, given as hexadecimal of any case.
+    pub fn verify(&self, data: &[u8], expected: &str) -> bool {
+        to_hex(&self.hash(data)).eq_ignore_ascii_case(expected.trim())
+    }
+}
+
+impl PartialEq for HashAlgorithm {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
+
+impl Eq for HashAlgorithm {}
+
+/// Hash functions implemented by this crate, checked before registered ones.
+const BUILTIN_HASHES: &[HashAlgorithm] = &[
+    HashAlgorithm { name: "crc32", new: || Box::new(Crc32::new()) },
+    HashAlgorithm { name: "sha1", new: || Box::new(Sha1::new()) },
+    HashAlgorithm { name: "sha256", new: || Box::new(Sha256::new()) },
+    #[cfg(feature = "xxhash")]
+    HashAlgorithm { name: "xxh64", new: || Box::new(Xxh64::new()) },
+];
+
+/// Hash functions added through [register_hash].
+static REGISTERED_HASHES: RwLock<Vec<HashAlgorithm>> = RwLock::new(Vec::new());
+
+/// adds `algorithm` to the hash functions usable by name, e.g. for the keys of a
+/// [Cache](crate::cache::Cache) or the hashes of a [manifest](crate::batch::BatchReport::manifest_with).
+///
+/// Returns a [ValidationError] if a hash function with the same name is already known.
+///
+/// # Examples
+///
+/// ```
+/// use rom_patcher::hash::{self, Digest, HashAlgorithm};
+///
+/// #[derive(Default)]
+/// struct Sum(u8);
+///
+/// impl Digest for Sum {
+///     fn update(&mut self, data: &[u8]) {
+///         self.0 = data.iter().fold(self.0, |a, b| a.wrapping_add(*b));
+///     }
+///
+///     fn finish(&self) -> Vec<u8> {
+///         vec![self.0]
+///     }
+/// }
+///
+/// hash::register_hash(HashAlgorithm { name: "sum8", new: || Box::new(Sum::default()) }).unwrap();
+/// assert_eq!(hash::hash_algorithm("sum8").unwrap().hash(&[1, 2, 3]), vec![6]);
+/// ```
+pub fn register_hash(algorithm: HashAlgorithm) -> Result<(), Error> {
+    let mut registered = REGISTERED_HASHES.write().unwrap_or_else(|e| e.into_inner());
+    if BUILTIN_HASHES.iter().chain(registered.iter()).any(|x| x.name.eq_ignore_ascii_case(algorithm.name)) {
+        return Err(Error::new(ValidationError)
+            .with_description(format!("Hash function {} is already registered.", algorithm.name)));
+    }
+    registered.push(algorithm);
+    Ok(())
+}
+
+/// returns all known hash functions, built-in ones first.
+pub fn hash_algorithms() -> Vec<HashAlgorithm> {
+    let registered = REGISTERED_HASHES.read().unwrap_or_else(|e| e.into_inner());
+    BUILTIN_HASHES.iter()
+        .chain(registered.iter())
+        .copied()
+        .collect()
+}
+
+/// returns the known hash function named `name`, ignoring case.
+pub fn hash_algorithm(name: &str) -> Option<HashAlgorithm> {
+    hash_algorithms().into_iter().find(|x| x.name.eq_ignore_ascii_case(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use spectral::prelude::*;
+
+    use super::*;
+
+    mod crc32_tests {
+        use super::*;
+
+        #[test]
+        fn checksum_of_check_value() {
+            assert_that!(Crc32::checksum(b"123456789")).is_equal_to(0xCBF43926);
+        }
+
+        #[test]
+        fn checksum_of_nothing_is_zero() {
+            assert_that!(Crc32::checksum(&[])).is_equal_to(0);
+        }
+
+        #[test]
+        fn incremental_updates_match_single_update() {
+            let mut crc = Crc32::new();
+            crc.update(b"1234");
+            crc.update(b"56789");
+            assert_that!(crc.value()).is_equal_to(0xCBF43926);
+            assert_that!(crc.finish()).is_equal_to(vec![0xCB, 0xF4, 0x39, 0x26]);
+        }
+    }
+
+    mod sha1_tests {
+        use super::*;
+
+        #[test]
+        fn hash_of_nothing() {
+            assert_that!(to_hex(&Sha1::hash(&[])))
+                .is_equal_to("da39a3ee5e6b4b0d3255bfef95601890afd80709".to_string());
+        }
+
+        #[test]
+        fn hash_of_multi_block_message() {
+            let actual = Sha1::hash(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq");
+            assert_that!(to_hex(&actual))
+                .is_equal_to("84983e441c3bd26ebaae4aa1f95129e5e54670f1".to_string());
+        }
+
+        #[test]
+        fn incremental_updates_match_single_update() {
+            let data: Vec<u8> = (0..1000u32).map(|x| x as u8).collect();
+            let mut sha1 = Sha1::new();
+            for chunk in data.chunks(37) {
+                sha1.update(chunk);
+            }
+            assert_that!(sha1.digest()).is_equal_to(Sha1::hash(&data));
+        }
+
+        #[test]
+        fn digest_does_not_reset_state() {
+            let mut sha1 = Sha1::new();
+            sha1.update(b"ab");
+            sha1.digest();
+            sha1.update(b"c");
+            assert_that!(sha1.digest()).is_equal_to(Sha1::hash(b"abc"));
+        }
+    }
+
+    mod sha256_tests {
+        use super::*;
+
+        #[test]
+        fn hash_of_multi_block_message() {
+            let actual = Sha256::hash(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq");
+            assert_that!(to_hex(&actual))
+                .is_equal_to("248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1".to_string());
+        }
+
+        #[test]
+        fn incremental_updates_match_single_update() {
+            let data: Vec<u8> = (0..1000u32).map(|x| x as u8).collect();
+            let mut sha256 = Sha256::new();
+            for chunk in data.chunks(37) {
+                sha256.update(chunk);
+            }
+            assert_that!(sha256.digest()).is_equal_to(Sha256::hash(&data));
+        }
+
+        #[test]
+        fn hmac_of_rfc_4231_case_2() {
+            assert_that!(to_hex(&hmac_sha256(b"Jefe", b"what do ya want for nothing?")))
+                .is_equal_to("5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843".to_string());
+        }
+    }
+
+    #[cfg(feature = "xxhash")]
+    mod xxh64_tests {
+        use super::*;
+
+        #[test]
+        fn hash_of_short_messages() {
+            assert_that!(Xxh64::hash(b"")).is_equal_to(0xEF46DB3751D8E999);
+            assert_that!(Xxh64::hash(b"abc")).is_equal_to(0x44BC2CF5AD770999);
+            assert_that!(Xxh64::hash(b"aaaaaaaa")).is_equal_to(0xD5462E501DE970F3);
+        }
+
+        #[test]
+        fn hash_of_multi_stripe_message() {
+            assert_that!(Xxh64::hash(b"Nobody inspects the spammish repetition")).is_equal_to(0xFBCEA83C8A378BF1);
+        }
+
+        #[test]
+        fn incremental_updates_match_single_update() {
+            let data: Vec<u8> = (0..1000u32).map(|x| x as u8).collect();
+            let mut xxh = Xxh64::new();
+            for chunk in data.chunks(7) {
+                xxh.update(chunk);
+            }
+            assert_that!(xxh.value()).is_equal_to(Xxh64::hash(&data));
+        }
+    }
+
+    mod registry_tests {
+        use super::*;
+
+        #[derive(Default)]
+        struct Xor(u8);
+
+        impl Digest for Xor {
+            fn update(&mut self, data: &[u8]) {
+                self.0 = data.iter().fold(self.0, |a, b| a ^ b);
+            }
+
+            fn finish(&self) -> Vec<u8> {
+                vec![self.0]
+            }
+        }
+
+        #[test]
+        fn builtin_hashes_are_known() {
+            let sha1 = hash_algorithm("SHA1").unwrap();
+            assert_that!(sha1.hash(b"abc")).is_equal_to(Sha1::hash(b"abc").to_vec());
+            assert_that!(sha1.verify(b"abc", "A9993E364706816ABA3E25717850C26C9CD0D89D")).is_true();
+            assert_that!(hash_algorithm("crc32").unwrap().hash(b"123456789")).is_equal_to(vec![0xCB, 0xF4, 0x39, 0x26]);
+            assert_that!(hash_algorithm("md5").is_none()).is_true();
+        }
+
+        #[test]
+        fn registered_hashes_are_known() {
+            register_hash(HashAlgorithm { name: "xor-test", new: || Box::new(Xor::default()) }).unwrap();
+            assert_that!(hash_algorithm("xor-test").unwrap().hash(&[1, 3])).is_equal_to(vec![2]);
+            assert_that!(register_hash(HashAlgorithm { name: "Sha256", new: || Box::new(Xor::default()) })).is_err();
+        }
+    }
+}
@@ -0,0 +1,121 @@
+//! Copier headers, the 512 bytes old SNES copiers put in front of dumped roms.
+//!
+//! The same game circulates both with and without such a header. Patches created from the rom body
+//! alone serve both, as long as they are shifted past the header when applied to a headered rom.
+
+use std::io::{Error as IOError, ErrorKind as IOErrorKind, Result as IOResult, Seek, SeekFrom, Write};
+
+use crate::io_util::Truncate;
+
+/// length of a copier header.
+pub const COPIER_HEADER_LEN: u64 = 512;
+
+/// returns the length of the copier header of a rom of `rom_len` bytes, `0` if it has none.
+///
+/// Roms are dumped in multiples of 1 KiB, so a remainder of exactly 512 bytes is a header.
+///
+/// # Examples
+///
+/// ```
+/// use rom_patcher::header::copier_header_len;
+/// assert_eq!(copier_header_len(0x80200), 512);
+/// assert_eq!(copier_header_len(0x80000), 0);
+/// ```
+pub fn copier_header_len(rom_len: u64) -> u64 {
+    if rom_len % 1024 == COPIER_HEADER_LEN {
+        COPIER_HEADER_LEN
+    } else {
+        0
+    }
+}
+
+/// splits `rom` into its copier header, empty if it has none, and its body.
+pub fn split_header(rom: &[u8]) -> (&[u8], &[u8]) {
+    rom.split_at(copier_header_len(rom.len() as u64) as usize)
+}
+
+/// A target adapter hiding the first `offset` bytes of `inner`, so position `0` is the rom body.
+#[derive(Debug)]
+pub(crate) struct Offset<'a, T> {
+    inner: &'a mut T,
+    offset: u64,
+}
+
+impl<'a, T> Offset<'a, T> where T: Seek {
+    pub(crate) fn new(inner: &'a mut T, offset: u64) -> Offset<'a, T> {
+        Offset { inner, offset }
+    }
+}
+
+impl<'a, T> Write for Offset<'a, T> where T: Write {
+    fn write(&mut self, buf: &[u8]) -> IOResult<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> IOResult<()> {
+        self.inner.flush()
+    }
+}
+
+impl<'a, T> Seek for Offset<'a, T> where T: Seek {
+    fn seek(&mut self, pos: SeekFrom) -> IOResult<u64> {
+        let pos = match pos {
+            SeekFrom::Start(x) => SeekFrom::Start(x.checked_add(self.offset).ok_or_else(|| IOError::new(
+                IOErrorKind::InvalidInput, format!("Offset {} is out of range.", x)))?),
+            x => x,
+        };
+        let position = self.inner.seek(pos)?;
+        Ok(position.saturating_sub(self.offset))
+    }
+}
+
+impl<'a, T> Truncate for Offset<'a, T> where T: Truncate {
+    fn truncate(&mut self, amount: u64) -> IOResult<()> {
+        self.inner.truncate(amount.saturating_add(self.offset))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use spectral::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn split_header_of_headered_rom() {
+        let rom = vec![1; 1024 + 512];
+        let (header, body) = split_header(&rom);
+        assert_that!(header.len()).is_equal_to(512);
+        assert_that!(body.len()).is_equal_to(1024);
+    }
+
+    #[test]
+    fn split_header_of_headerless_rom() {
+        let rom = vec![1; 2048];
+        let (header, body) = split_header(&rom);
+        assert_that!(header.len()).is_equal_to(0);
+        assert_that!(body.len()).is_equal_to(2048);
+    }
+
+    #[test]
+    fn offset_hides_the_header() {
+        let mut target = Cursor::new(vec![0; 8]);
+        let mut offset = Offset::new(&mut target, 4);
+        offset.seek(SeekFrom::Start(1)).unwrap();
+        offset.write_all(&[1]).unwrap();
+        assert_that!(offset.stream_position().unwrap()).is_equal_to(2);
+        offset.truncate(3).unwrap();
+        assert_that!(target.into_inner()).is_equal_to(vec![0, 0, 0, 0, 0, 1, 0]);
+    }
+
+    #[test]
+    fn offset_rejects_seeks_past_the_address_space() {
+        let mut target = Cursor::new(vec![0; 8]);
+        let mut offset = Offset::new(&mut target, 4);
+        assert_that!(offset.seek(SeekFrom::Start(u64::MAX - 1))).is_err();
+        offset.truncate(u64::MAX).unwrap();
+        assert_that!(target.into_inner().len()).is_equal_to(8);
+    }
+}
@@ -0,0 +1,240 @@
+//! Hunks of any format, so analyses of where patches write are written once for all of them.
+//!
+//! A [Hunk] is a single write of a patch, like an [IPSHunk], a [PMSRRecord] or a [PPFRecord]. The
+//! functions of this module work on slices of any of them. Hunks of every format address the target
+//! with 64-bit offsets in memory, they are only narrowed to what a format can store when they are
+//! written.
+
+use std::ops::Range;
+
+use crate::index::IntervalIndex;
+use crate::io_util::U64Extensions;
+use crate::ips::IPSHunk;
+use crate::pmsr::PMSRRecord;
+use crate::ppf::PPFRecord;
+
+/// A single write of a patch.
+pub trait Hunk {
+    /// returns the offsets of the target the hunk writes to.
+    fn target_range(&self) -> Range<u64>;
+
+    /// returns the amount of bytes the patch stores for the data of the hunk, which is less than
+    /// it writes for run-length encoded hunks.
+    fn payload_len(&self) -> u64;
+
+    /// writes the part of the hunk that falls into `buf` to it, where `buf` holds the bytes of the
+    /// target starting at `buf_offset`.
+    fn overlay(&self, buf_offset: u64, buf: &mut [u8]);
+
+    /// applies the hunk to `target`, extending it with zeros if the hunk writes past its end.
+    fn apply(&self, target: &mut Vec<u8>) {
+        let end = self.target_range().end.saturating_usize();
+        if target.len() < end {
+            target.resize(end, 0);
+        }
+        self.overlay(0, target);
+    }
+}
+
+impl Hunk for IPSHunk {
+    fn target_range(&self) -> Range<u64> {
+        self.offset()..self.end()
+    }
+
+    fn payload_len(&self) -> u64 {
+        match self {
+            IPSHunk::Regular(x) => x.payload.len() as u64,
+            IPSHunk::RLE(_) => 1,
+        }
+    }
+
+    fn overlay(&self, buf_offset: u64, buf: &mut [u8]) {
+        IPSHunk::overlay(self, buf_offset, buf)
+    }
+}
+
+impl Hunk for PMSRRecord {
+    fn target_range(&self) -> Range<u64> {
+        self.offset..self.offset.saturating_add(self.data.len() as u64)
+    }
+
+    fn payload_len(&self) -> u64 {
+        self.data.len() as u64
+    }
+
+    fn overlay(&self, buf_offset: u64, buf: &mut [u8]) {
+        overlay_slice(self.offset, &self.data, buf_offset, buf)
+    }
+}
+
+impl Hunk for PPFRecord {
+    fn target_range(&self) -> Range<u64> {
+        self.offset..self.end()
+    }
+
+    fn payload_len(&self) -> u64 {
+        self.data.len() as u64
+    }
+
+    fn overlay(&self, buf_offset: u64, buf: &mut [u8]) {
+        overlay_slice(self.offset, &self.data, buf_offset, buf)
+    }
+}
+
+/// returns the part of `buf` that `range` covers, where `buf` holds the bytes starting at
+/// `buf_offset`, or [None] if they don't overlap.
+pub(crate) fn overlapping_part(range: Range<u64>, buf_offset: u64, buf: &mut [u8]) -> Option<&mut [u8]> {
+    let start = range.start.max(buf_offset);
+    let end = range.end.min(buf_offset.saturating_add(buf.len() as u64));
+    if start >= end {
+        return None;
+    }
+    Some(&mut buf[(start - buf_offset).to_index()..(end - buf_offset).to_index()])
+}
+
+/// writes the part of `data`, written at `offset`, that falls into `buf` to it, where `buf` holds
+/// the bytes starting at `buf_offset`.
+pub(crate) fn overlay_slice(offset: u64, data: &[u8], buf_offset: u64, buf: &mut [u8]) {
+    let range = offset..offset.saturating_add(data.len() as u64);
+    // the part starts at the later of both offsets
+    let skip = buf_offset.saturating_sub(offset).saturating_usize();
+    if let Some(target) = overlapping_part(range, buf_offset, buf) {
+        let len = target.len();
+        target.copy_from_slice(&data[skip..skip + len]);
+    }
+}
+
+/// returns the offsets `hunks` write to as ordered ranges, merging overlapping and adjacent ones.
+///
+/// # Examples
+///
+/// ```
+/// use rom_patcher::hunk::coverage;
+/// use rom_patcher::ips::{IPSHunk, IPSRLEHunkData};
+///
+/// let rle = |offset, run_length| IPSHunk::RLE(IPSRLEHunkData { offset, run_length, payload: 0 });
+/// assert_eq!(coverage(&[rle(8, 2), rle(0, 4), rle(2, 4), rle(10, 1)]), vec![0..6, 8..11]);
+/// ```
+pub fn coverage<H: Hunk>(hunks: &[H]) -> Vec<Range<u64>> {
+    let mut ranges: Vec<Range<u64>> = hunks.iter()
+        .map(|x| x.target_range())
+        .filter(|x| !x.is_empty())
+        .collect();
+    ranges.sort_unstable_by_key(|x| x.start);
+
+    let mut result: Vec<Range<u64>> = Vec::new();
+    for range in ranges {
+        match result.last_mut() {
+            Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+            _ => result.push(range),
+        }
+    }
+    return result;
+}
+
+/// returns every pair of hunk indices `(earlier, later)` whose hunks write to a common offset.
+pub fn conflicts<H: Hunk>(hunks: &[H]) -> Vec<(usize, usize)> {
+    let index = IntervalIndex::new(hunks.iter().enumerate().map(|(i, x)| (x.target_range(), i)));
+    let mut result = Vec::new();
+    for (i, hunk) in hunks.iter().enumerate() {
+        for (_, j) in index.overlapping(hunk.target_range()) {
+            if *j > i {
+                result.push((i, *j));
+            }
+        }
+    }
+    result.sort_unstable();
+    return result;
+}
+
+/// returns every pair of hunk indices `(earlier, later)` whose hunks write different data to a
+/// common offset, together with the first such offset.
+///
+/// Unlike [conflicts], hunks writing the same data to the offsets they share are left out, since
+/// the order they are applied in doesn't matter for them.
+pub fn duplicate_writes<H: Hunk>(hunks: &[H]) -> Vec<(usize, usize, u64)> {
+    let mut result = Vec::new();
+    for (i, j) in conflicts(hunks) {
+        let (first, second) = (hunks[i].target_range(), hunks[j].target_range());
+        let start = first.start.max(second.start);
+        let len = (first.end.min(second.end) - start).saturating_usize();
+        let (mut earlier, mut later) = (vec![0; len], vec![0; len]);
+        hunks[i].overlay(start, &mut earlier);
+        hunks[j].overlay(start, &mut later);
+        if let Some(position) = earlier.iter().zip(later.iter()).position(|(x, y)| x != y) {
+            result.push((i, j, start + position as u64));
+        }
+    }
+    return result;
+}
+
+/// returns the amount of bytes `hunks` write, counting offsets written more than once only once.
+pub fn written_len<H: Hunk>(hunks: &[H]) -> u64 {
+    coverage(hunks).iter().map(|x| x.end - x.start).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use spectral::prelude::*;
+
+    use crate::ips::{IPSRegularHunkData, IPSRLEHunkData};
+
+    use super::*;
+
+    fn record(offset: u64, data: &[u8]) -> PMSRRecord {
+        PMSRRecord { offset, data: data.into() }
+    }
+
+    fn ppf_record(offset: u64, data: &[u8]) -> PPFRecord {
+        PPFRecord { offset, data: data.into(), undo: None }
+    }
+
+    #[test]
+    fn analyses_work_across_formats() {
+        let ips = [
+            IPSHunk::Regular(IPSRegularHunkData::new(0, vec![1, 2, 3])),
+            IPSHunk::RLE(IPSRLEHunkData { offset: 2, run_length: 4, payload: 9 }),
+        ];
+        let pmsr = [record(0, &[1, 2, 3]), record(2, &[9; 4])];
+        let ppf = [ppf_record(0, &[1, 2, 3]), ppf_record(2, &[9; 4])];
+        assert_that!(coverage(&ips)).is_equal_to(coverage(&pmsr));
+        assert_that!(coverage(&ppf)).is_equal_to(coverage(&pmsr));
+        assert_that!(conflicts(&ips)).is_equal_to(vec![(0, 1)]);
+        assert_that!(conflicts(&pmsr)).is_equal_to(vec![(0, 1)]);
+        assert_that!(conflicts(&ppf)).is_equal_to(vec![(0, 1)]);
+        assert_that!(duplicate_writes(&ips)).is_equal_to(vec![(0, 1, 2)]);
+        assert_that!(duplicate_writes(&pmsr)).is_equal_to(vec![(0, 1, 2)]);
+        assert_that!(duplicate_writes(&ppf)).is_equal_to(vec![(0, 1, 2)]);
+        assert_that!(duplicate_writes(&[record(0, &[1, 2]), record(1, &[2])])).is_empty();
+        assert_that!(written_len(&ips)).is_equal_to(6);
+        assert_that!(written_len(&ppf)).is_equal_to(6);
+        assert_that!(ips[1].payload_len()).is_equal_to(1);
+    }
+
+    #[test]
+    fn apply_extends_and_overwrites() {
+        let mut ips_target = vec![0; 2];
+        let mut pmsr_target = vec![0; 2];
+        let mut ppf_target = vec![0; 2];
+        for (ips, pmsr, ppf) in [
+            (IPSHunk::RLE(IPSRLEHunkData { offset: 1, run_length: 3, payload: 7 }), record(1, &[7; 3]), ppf_record(1, &[7; 3])),
+            (IPSHunk::Regular(IPSRegularHunkData::new(3, vec![8])), record(3, &[8]), ppf_record(3, &[8])),
+        ] {
+            ips.apply(&mut ips_target);
+            pmsr.apply(&mut pmsr_target);
+            ppf.apply(&mut ppf_target);
+        }
+        assert_that!(ips_target).is_equal_to(vec![0, 7, 7, 8]);
+        assert_that!(pmsr_target).is_equal_to(vec![0, 7, 7, 8]);
+        assert_that!(ppf_target).is_equal_to(vec![0, 7, 7, 8]);
+    }
+
+    #[test]
+    fn overlay_writes_only_inside_the_buffer() {
+        let mut buf = [0; 2];
+        record(1, &[1, 2, 3]).overlay(2, &mut buf);
+        assert_that!(buf).is_equal_to([2, 3]);
+        ppf_record(3, &[4, 5]).overlay(2, &mut buf);
+        assert_that!(buf).is_equal_to([2, 4]);
+    }
+}
@@ -0,0 +1,133 @@
+use std::ops::Range;
+
+/// A static interval tree over half-open `u64` ranges.
+///
+/// Entries are sorted by their start and the tree is laid out implicitly over the sorted entries,
+/// each node storing the largest end found in its subtree. Queries prune every subtree that ends
+/// before the queried range or starts after it, which makes them `O(log n + k)`.
+#[derive(Debug)]
+pub(crate) struct IntervalIndex<T> {
+    entries: Vec<(Range<u64>, T)>,
+    max_end: Vec<u64>,
+}
+
+impl<T> IntervalIndex<T> {
+    /// builds an index over `entries`. Empty ranges are never returned by queries.
+    pub(crate) fn new(entries: impl IntoIterator<Item=(Range<u64>, T)>) -> IntervalIndex<T> {
+        let mut entries: Vec<(Range<u64>, T)> = entries.into_iter().collect();
+        entries.sort_by_key(|(range, _)| range.start);
+        let mut result = IntervalIndex {
+            max_end: vec![0; entries.len()],
+            entries,
+        };
+        result.build(0, result.entries.len());
+        return result;
+    }
+
+    /// computes [IntervalIndex::max_end] for the subtree over `lo..hi` and returns it.
+    fn build(&mut self, lo: usize, hi: usize) -> u64 {
+        if lo >= hi {
+            return 0;
+        }
+        let mid = lo + (hi - lo) / 2;
+        let left = self.build(lo, mid);
+        let right = self.build(mid + 1, hi);
+        self.max_end[mid] = self.entries[mid].0.end.max(left).max(right);
+        return self.max_end[mid];
+    }
+
+    /// returns all entries overlapping `range`, ordered by their start.
+    pub(crate) fn overlapping(&self, range: Range<u64>) -> Vec<(&Range<u64>, &T)> {
+        let mut result = Vec::new();
+        if range.start < range.end {
+            self.query(0, self.entries.len(), &range, &mut result);
+        }
+        return result;
+    }
+
+    /// returns all entries containing `offset`, ordered by their start.
+    pub(crate) fn covering(&self, offset: u64) -> Vec<(&Range<u64>, &T)> {
+        self.overlapping(offset..offset.saturating_add(1))
+    }
+
+    fn query<'a>(&'a self, lo: usize, hi: usize, range: &Range<u64>, result: &mut Vec<(&'a Range<u64>, &'a T)>) {
+        if lo >= hi {
+            return;
+        }
+        let mid = lo + (hi - lo) / 2;
+        // nothing in this subtree reaches into range
+        if self.max_end[mid] <= range.start {
+            return;
+        }
+        self.query(lo, mid, range, result);
+        let (entry_range, value) = &self.entries[mid];
+        // entries right of mid start at or after mid
+        if entry_range.start >= range.end {
+            return;
+        }
+        if entry_range.end > range.start && entry_range.start < entry_range.end {
+            result.push((entry_range, value));
+        }
+        self.query(mid + 1, hi, range, result);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use spectral::prelude::*;
+
+    use super::*;
+
+    fn values(result: Vec<(&Range<u64>, &usize)>) -> Vec<usize> {
+        result.into_iter().map(|(_, value)| *value).collect()
+    }
+
+    #[test]
+    fn empty_index_returns_nothing() {
+        let index: IntervalIndex<usize> = IntervalIndex::new(Vec::new());
+        assert_that!(index.covering(0)).is_empty();
+    }
+
+    #[test]
+    fn covering_returns_entries_containing_offset() {
+        let index = IntervalIndex::new(vec![(0..4, 0), (2..6, 1), (6..8, 2), (10..12, 3)]);
+        assert_that!(values(index.covering(3))).is_equal_to(vec![0, 1]);
+        assert_that!(values(index.covering(6))).is_equal_to(vec![2]);
+        assert_that!(values(index.covering(9))).is_empty();
+    }
+
+    #[test]
+    fn overlapping_returns_entries_ordered_by_start() {
+        let index = IntervalIndex::new(vec![(10..12, 0), (0..100, 1), (5..11, 2)]);
+        assert_that!(values(index.overlapping(9..11))).is_equal_to(vec![1, 2, 0]);
+    }
+
+    #[test]
+    fn empty_ranges_are_ignored() {
+        let index = IntervalIndex::new(vec![(4..4, 0), (0..8, 1)]);
+        assert_that!(values(index.covering(4))).is_equal_to(vec![1]);
+        assert_that!(values(index.overlapping(4..4))).is_empty();
+    }
+
+    #[test]
+    fn matches_linear_search() {
+        let ranges: Vec<Range<u64>> = (0..500u64)
+            .map(|i| {
+                let start = (i * 7919) % 1000;
+                start..start + (i * 31) % 50 + 1
+            })
+            .collect();
+        let index = IntervalIndex::new(ranges.iter().cloned().zip(0..));
+        for offset in 0..1100 {
+            let mut expected: Vec<usize> = ranges.iter()
+                .enumerate()
+                .filter(|(_, range)| range.contains(&offset))
+                .map(|(i, _)| i)
+                .collect();
+            let mut actual = values(index.covering(offset));
+            expected.sort();
+            actual.sort();
+            assert_that!(actual).is_equal_to(expected);
+        }
+    }
+}
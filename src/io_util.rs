@@ -1,9 +1,12 @@
 use std::fs::File;
-use std::io::{Cursor, Read, Result as IOResult};
+use std::io::{Cursor, ErrorKind, Read, Result as IOResult, Seek, SeekFrom, Write};
+use std::thread::sleep;
+use std::time::Duration;
 use crate::Error;
 use crate::ErrorKind::ParsingError;
+use crate::hash::{Crc32, Digest};
 
-pub trait U32Extensions {
+pub(crate) trait U32Extensions {
     fn to_u24_be_bytes(&self) -> [u8; 3];
     fn from_u24_be_bytes(bytes: &[u8]) -> Self;
 }
@@ -22,14 +25,55 @@ impl U32Extensions for u32 {
     }
 }
 
-pub trait ReaderExtensions {
+// offsets and lengths of files in memory are assumed to fit into 32 bits at least
+const _: () = assert!(usize::BITS >= 32);
+
+/// Conversions of 64-bit offsets and lengths to indices into buffers, which are only 32-bit on
+/// some platforms.
+pub(crate) trait U64Extensions {
+    /// converts an offset or length inside a buffer in memory, which always fits `usize`.
+    ///
+    /// Debug builds assert that it does, so truncation on 32-bit platforms fails loudly there
+    /// instead of indexing the wrong byte.
+    fn to_index(self) -> usize;
+
+    /// converts an offset or length that may lie past any buffer in memory, saturating at
+    /// [usize::MAX] so comparisons against buffer lengths stay correct.
+    fn saturating_usize(self) -> usize;
+}
+
+impl U64Extensions for u64 {
+    fn to_index(self) -> usize {
+        debug_assert!(usize::try_from(self).is_ok(), "{} doesn't fit into usize", self);
+        self as usize
+    }
+
+    fn saturating_usize(self) -> usize {
+        usize::try_from(self).unwrap_or(usize::MAX)
+    }
+}
+
+pub(crate) trait ReaderExtensions {
+    fn read_u32_be(&mut self, err_message: String) -> Result<u32,Error>;
     fn read_u24_be(&mut self, err_message: String) -> Result<u32,Error>;
     fn read_u16_be(&mut self, err_message: String) -> Result<u16,Error>;
 
     fn read_u8(&mut self, err_message: String) -> Result<u8, Error>;
+
+    /// reads a variable-length integer as used by UPS and BPS, see [write_varint].
+    fn read_varint(&mut self, err_message: String) -> Result<u64, Error>;
 }
 
 impl<T> ReaderExtensions for T where T : Read {
+    fn read_u32_be(&mut self, err_message: String) -> Result<u32, Error> {
+        let mut buf: [u8;4] = [0;4];
+        self.read_exact(&mut buf).map_err(|e|Error::new(ParsingError)
+            .with_description(err_message)
+            .with_source(Box::new(e))
+        )?;
+        return Ok(u32::from_be_bytes(buf));
+    }
+
     fn read_u24_be(&mut self, err_message: String) -> Result<u32,Error> {
         {
             let mut buf: [u8;3] = [0;3];
@@ -58,9 +102,41 @@ impl<T> ReaderExtensions for T where T : Read {
         )?;
         return Ok(buf[0]);
     }
+
+    fn read_varint(&mut self, err_message: String) -> Result<u64, Error> {
+        let overflow = || Error::new(ParsingError).with_description(err_message.clone());
+        let mut result: u64 = 0;
+        let mut shift: u64 = 1;
+        loop {
+            let x = self.read_u8(err_message.clone())?;
+            let value = ((x & 0x7F) as u64).checked_mul(shift).ok_or_else(overflow)?;
+            result = result.checked_add(value).ok_or_else(overflow)?;
+            if x & 0x80 != 0 {
+                return Ok(result);
+            }
+            shift = shift.checked_mul(0x80).ok_or_else(overflow)?;
+            result = result.checked_add(shift).ok_or_else(overflow)?;
+        }
+    }
+}
+
+/// writes `value` as a variable-length integer as used by UPS and BPS.
+///
+/// Each byte holds seven bits, least significant first, and the last byte has its high bit set.
+/// Every byte but the last also adds one to the next, so each value has exactly one encoding.
+pub(crate) fn write_varint(writer: &mut impl Write, mut value: u64) -> IOResult<()> {
+    loop {
+        let x = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            return writer.write_all(&[0x80 | x]);
+        }
+        writer.write_all(&[x])?;
+        value -= 1;
+    }
 }
 
-pub trait AssertRead {
+pub(crate) trait AssertRead {
     fn assert_read(&mut self, expected: &[u8], read_error_message: String, parse_error_message: String) -> Result<(), Error>;
 }
 
@@ -77,27 +153,354 @@ impl<T> AssertRead for T where T: Read {
     }
 }
 
-
+/// Targets that can be shortened to a given length.
+///
+/// Lengths are 64-bit regardless of the format a patch is in, targets longer than `amount` are
+/// shortened and shorter ones left as they are.
 pub trait Truncate {
-    fn truncate(&mut self, amount: u32) -> IOResult<()>;
+    fn truncate(&mut self, amount: u64) -> IOResult<()>;
 }
 
 impl Truncate for Vec<u8> {
-    fn truncate(&mut self, amount: u32) -> IOResult<()> {
-        self.truncate(amount as usize);
+    fn truncate(&mut self, amount: u64) -> IOResult<()> {
+        // a vec can't be longer than usize::MAX, so there is nothing to cut off otherwise
+        if let Ok(amount) = usize::try_from(amount) {
+            self.truncate(amount);
+        }
         Ok(())
     }
 }
 
 impl Truncate for File {
-    fn truncate(&mut self, amount: u32) -> IOResult<()> {
-        self.set_len(self.metadata().unwrap().len().min(amount as u64))
+    fn truncate(&mut self, amount: u64) -> IOResult<()> {
+        self.set_len(self.metadata()?.len().min(amount))
     }
 }
 
 impl <T> Truncate for Cursor<T> where T : Truncate {
-    fn truncate(&mut self, amount: u32) -> IOResult<()> {
+    fn truncate(&mut self, amount: u64) -> IOResult<()> {
         self.get_mut().truncate(amount)?;
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+/// A writer adapter feeding everything written through it to a [Digest].
+///
+/// # Examples
+///
+/// ```
+/// use std::io::Write;
+/// use rom_patcher::hash::Crc32;
+/// use rom_patcher::io_util::HashingWriter;
+///
+/// let mut writer = HashingWriter::new(Vec::new(), Crc32::new());
+/// writer.write_all(b"123456789").unwrap();
+/// assert_eq!(writer.hasher().value(), 0xCBF43926);
+/// ```
+#[derive(Debug)]
+pub struct HashingWriter<W, H = Crc32> {
+    inner: W,
+    hasher: H,
+}
+
+impl<W, H> HashingWriter<W, H> where W: Write, H: Digest {
+    /// constructs a [HashingWriter] writing to `inner` and feeding `hasher`.
+    pub fn new(inner: W, hasher: H) -> HashingWriter<W, H> {
+        HashingWriter { inner, hasher }
+    }
+
+    /// returns the hasher fed with everything written so far.
+    pub fn hasher(&self) -> &H {
+        &self.hasher
+    }
+
+    /// returns a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// returns a mutable reference to the underlying writer. Bytes written to it directly aren't
+    /// hashed.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// consumes the adapter and returns the underlying writer and the hasher.
+    pub fn into_parts(self) -> (W, H) {
+        (self.inner, self.hasher)
+    }
+}
+
+impl<W, H> Write for HashingWriter<W, H> where W: Write, H: Digest {
+    fn write(&mut self, buf: &[u8]) -> IOResult<usize> {
+        let written = self.inner.write(buf)?;
+        // only hash what actually reached the writer
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> IOResult<()> {
+        self.inner.flush()
+    }
+}
+
+/// A writer adapter counting the bytes written through it.
+#[derive(Debug)]
+pub struct CountingWriter<W> {
+    inner: W,
+    count: u64,
+}
+
+impl<W> CountingWriter<W> where W: Write {
+    /// constructs a [CountingWriter] writing to `inner`.
+    pub fn new(inner: W) -> CountingWriter<W> {
+        CountingWriter { inner, count: 0 }
+    }
+
+    /// returns the amount of bytes written so far.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// returns a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// consumes the adapter and returns the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W> Write for CountingWriter<W> where W: Write {
+    fn write(&mut self, buf: &[u8]) -> IOResult<usize> {
+        let written = self.inner.write(buf)?;
+        self.count += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> IOResult<()> {
+        self.inner.flush()
+    }
+}
+
+/// A reader adapter retrying transient errors of the underlying reader.
+///
+/// Network mounts and FUSE filesystems may fail reads with [ErrorKind::Interrupted] or
+/// [ErrorKind::WouldBlock] although the data arrives shortly after. Interrupted reads are retried
+/// right away, would-block ones after a backoff doubling with every attempt. A read fails with the
+/// last error once it was retried [RetryingReader::with_max_retries] times.
+///
+/// # Examples
+///
+/// ```
+/// use std::time::Duration;
+/// use rom_patcher::io_util::RetryingReader;
+/// use rom_patcher::ips::IPSPatch;
+///
+/// let data = b"PATCHEOF";
+/// let mut reader = RetryingReader::new(data.as_slice())
+///     .with_max_retries(8)
+///     .with_backoff(Duration::from_millis(5), Duration::from_millis(200));
+/// assert_eq!(IPSPatch::read_from(&mut reader).unwrap(), IPSPatch::new());
+/// ```
+#[derive(Debug)]
+pub struct RetryingReader<R> {
+    inner: R,
+    max_retries: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    retries: u64,
+}
+
+impl<R> RetryingReader<R> {
+    /// Retries of a single read before giving up by default.
+    pub const DEFAULT_MAX_RETRIES: u32 = 10;
+
+    /// constructs a [RetryingReader] reading from `inner` with the default limits.
+    pub fn new(inner: R) -> RetryingReader<R> {
+        RetryingReader {
+            inner,
+            max_retries: Self::DEFAULT_MAX_RETRIES,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(500),
+            retries: 0,
+        }
+    }
+
+    /// modifies the adapter to retry a single read at most `max_retries` times.
+    pub fn with_max_retries(mut self, max_retries: u32) -> RetryingReader<R> {
+        self.max_retries = max_retries;
+        return self;
+    }
+
+    /// modifies the adapter to wait `initial` before the first retry of a would-block read,
+    /// doubling the wait with every further retry up to `max`.
+    pub fn with_backoff(mut self, initial: Duration, max: Duration) -> RetryingReader<R> {
+        self.initial_backoff = initial;
+        self.max_backoff = max;
+        return self;
+    }
+
+    /// returns the amount of retries done so far over all reads.
+    pub fn retries(&self) -> u64 {
+        self.retries
+    }
+
+    /// returns a reference to the underlying reader.
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// consumes the adapter and returns the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R> Read for RetryingReader<R> where R: Read {
+    fn read(&mut self, buf: &mut [u8]) -> IOResult<usize> {
+        let mut attempt = 0;
+        let mut backoff = self.initial_backoff;
+        loop {
+            match self.inner.read(buf) {
+                Err(e) if attempt < self.max_retries && e.kind() == ErrorKind::Interrupted => {}
+                Err(e) if attempt < self.max_retries && e.kind() == ErrorKind::WouldBlock => {
+                    sleep(backoff);
+                    backoff = (backoff * 2).min(self.max_backoff);
+                }
+                result => return result,
+            }
+            attempt += 1;
+            self.retries += 1;
+        }
+    }
+}
+
+impl<R> Seek for RetryingReader<R> where R: Seek {
+    fn seek(&mut self, pos: SeekFrom) -> IOResult<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::sink;
+
+    use spectral::prelude::*;
+
+    use crate::hash::Sha1;
+    use crate::ips::apply_multi;
+    use crate::testkit::Fixture;
+
+    use super::*;
+
+    #[test]
+    fn u24_is_big_endian() {
+        assert_that!(0x123456u32.to_u24_be_bytes()).is_equal_to([0x12, 0x34, 0x56]);
+        assert_that!(u32::from_u24_be_bytes(&[0x12, 0x34, 0x56])).is_equal_to(0x123456);
+    }
+
+    #[test]
+    fn offsets_convert_to_indices() {
+        assert_that!(0x1234u64.to_index()).is_equal_to(0x1234);
+        assert_that!(0x1234u64.saturating_usize()).is_equal_to(0x1234);
+        assert_that!(u64::MAX.saturating_usize()).is_equal_to(usize::MAX);
+    }
+
+    #[test]
+    fn varints_round_trip() {
+        for value in [0, 1, 0x7F, 0x80, 0x407F, 0x4080, 0x1234_5678, u64::MAX] {
+            let mut data = Vec::new();
+            write_varint(&mut data, value).unwrap();
+            assert_that!((&data[..]).read_varint(String::new()).unwrap()).is_equal_to(value);
+        }
+        let mut data = Vec::new();
+        write_varint(&mut data, 0x80).unwrap();
+        assert_that!(data).is_equal_to(vec![0x00, 0x80]);
+    }
+
+    #[test]
+    fn overlong_varints_are_refused() {
+        let err = (&[0u8; 12][..]).read_varint("Unable to read size.".to_string()).unwrap_err();
+        assert_that!(err.to_string()).is_equal_to("ParsingError: Unable to read size.".to_string());
+    }
+
+    #[test]
+    fn hashing_writer_hashes_written_bytes() {
+        let mut writer = HashingWriter::new(Vec::new(), Sha1::new());
+        writer.write_all(b"ab").unwrap();
+        writer.write_all(b"c").unwrap();
+        let (inner, hasher) = writer.into_parts();
+        assert_that!(inner).is_equal_to(b"abc".to_vec());
+        assert_that!(hasher.digest()).is_equal_to(Sha1::hash(b"abc"));
+    }
+
+    #[test]
+    fn counting_writer_counts_written_bytes() {
+        let mut writer = CountingWriter::new(sink());
+        writer.write_all(&[0; 10]).unwrap();
+        writer.write_all(&[0; 5]).unwrap();
+        assert_that!(writer.count()).is_equal_to(15);
+    }
+
+    /// a reader failing with `kind` before each of its reads, `failures` times in total.
+    struct FlakyReader<'a> {
+        data: &'a [u8],
+        kind: ErrorKind,
+        failures: u32,
+    }
+
+    impl Read for FlakyReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> IOResult<usize> {
+            if self.failures > 0 {
+                self.failures -= 1;
+                return Err(std::io::Error::from(self.kind));
+            }
+            self.data.read(buf)
+        }
+    }
+
+    fn no_backoff<R>(reader: RetryingReader<R>) -> RetryingReader<R> {
+        reader.with_backoff(Duration::ZERO, Duration::ZERO)
+    }
+
+    #[test]
+    fn retrying_reader_retries_transient_errors() {
+        for kind in [ErrorKind::Interrupted, ErrorKind::WouldBlock] {
+            let flaky = FlakyReader { data: b"PATCHEOF", kind, failures: 3 };
+            let mut reader = no_backoff(RetryingReader::new(flaky));
+            assert_that!(crate::ips::IPSPatch::read_from(&mut reader)).is_ok();
+            assert_that!(reader.retries()).is_equal_to(3);
+        }
+    }
+
+    #[test]
+    fn retrying_reader_gives_up_after_max_retries() {
+        let flaky = FlakyReader { data: b"abc", kind: ErrorKind::WouldBlock, failures: 3 };
+        let mut reader = no_backoff(RetryingReader::new(flaky).with_max_retries(2));
+        let err = reader.read(&mut [0; 3]).unwrap_err();
+        assert_that!(err.kind()).is_equal_to(ErrorKind::WouldBlock);
+    }
+
+    #[test]
+    fn retrying_reader_passes_other_errors_through() {
+        let flaky = FlakyReader { data: b"abc", kind: ErrorKind::PermissionDenied, failures: 1 };
+        let mut reader = no_backoff(RetryingReader::new(flaky));
+        assert_that!(reader.read(&mut [0; 3]).unwrap_err().kind()).is_equal_to(ErrorKind::PermissionDenied);
+        assert_that!(reader.retries()).is_equal_to(0);
+    }
+
+    #[test]
+    fn adapters_compute_hash_and_length_while_applying() {
+        let fixture = Fixture::generate(11);
+        let mut output = Vec::new();
+        let mut crc = HashingWriter::new(sink(), Crc32::new());
+        let mut count = CountingWriter::new(sink());
+        apply_multi(&fixture.ips, &mut fixture.source.as_slice(), &mut [&mut output, &mut crc, &mut count]).unwrap();
+        assert_that!(crc.hasher().value()).is_equal_to(Crc32::checksum(&fixture.target));
+        assert_that!(count.count()).is_equal_to(fixture.target.len() as u64);
+        assert_that!(output).is_equal_to(fixture.target);
+    }
+}
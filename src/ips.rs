@@ -1,48 +1,97 @@
+use std::cmp::Ordering;
+use std::fmt::{Display, Formatter};
 use std::io::{ErrorKind, Read, Result as IOResult, Seek, SeekFrom};
 use std::io::Write;
+use std::ops::Range;
 
+use crate::apply::{check_max_offset, ApplyOptions, DuplicateWrites, HeaderHandling, TruncateCheck};
+use crate::create::{Diff, DiffTarget};
+use crate::diagnostics::{Diagnostic, DiagnosticCode};
 use crate::Error;
-use crate::ErrorKind::{ParsingError, PatchingError};
-use crate::io_util::{AssertRead, ReaderExtensions, Truncate, U32Extensions};
+use crate::header::{copier_header_len, Offset};
+use crate::ErrorKind::{CreatingError, ParsingError, PatchingError, ValidationError};
+use crate::hunk::{duplicate_writes, overlapping_part, overlay_slice};
+use crate::index::IntervalIndex;
+use crate::io_util::{AssertRead, ReaderExtensions, Truncate, U32Extensions, U64Extensions};
+use crate::messages::Message;
 
 /// Represents a regular hunk.
 ///
 /// Regular hunks consist of a three-byte offset followed by a two-byte length of the payload and
 /// the payload itself. Applying the hunk is done by writing the payload at the specified offset.
-#[derive(Debug, PartialEq)]
+///
+/// The length is that of the payload. Payloads longer than [u16::MAX] bytes are written as several
+/// consecutive hunks.
+///
+/// Like the hunks of every format, the offset is 64-bit in memory and only narrowed to the three
+/// bytes IPS stores when the hunk is written.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct IPSRegularHunkData {
     /// The offset to apply the payload.
-    pub offset: u32,
-    /// The length of the payload.
-    pub length: u16,
+    pub offset: u64,
     /// The payload to apply.
     pub payload: Box<[u8]>,
 }
 
 
 impl IPSRegularHunkData {
-    /// writes `self` to `writer`.
+    /// constructs a hunk writing `payload` at `offset`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rom_patcher::ips::{IPSHunk, IPSRegularHunkData};
+    /// let hunk = IPSHunk::Regular(IPSRegularHunkData::new(4, vec![0xEA, 0xEA]));
+    /// assert_eq!(hunk.length(), 2);
+    /// ```
+    pub fn new(offset: u64, payload: impl Into<Box<[u8]>>) -> IPSRegularHunkData {
+        IPSRegularHunkData { offset, payload: payload.into() }
+    }
+
+    /// writes `self` to `writer`, split into hunks of at most [u16::MAX] bytes.
+    ///
+    /// An empty payload would be read back as an RLE hunk, so it isn't written at all.
     fn write(&self, writer: &mut impl Write) -> IOResult<()> {
-        writer.write_all(&self.offset.to_u24_be_bytes())?;
-        writer.write_all(&self.length.to_be_bytes())?;
-        writer.write_all(&*self.payload)?;
+        for (offset, chunk) in self.chunks() {
+            writer.write_all(&checked_hunk_offset(offset)?.to_u24_be_bytes())?;
+            writer.write_all(&(chunk.len() as u16).to_be_bytes())?;
+            writer.write_all(chunk)?;
+        }
         Ok(())
     }
 
+    /// returns the offsets and payloads of the hunks [IPSRegularHunkData::write] splits `self` into.
+    fn chunks(&self) -> impl Iterator<Item = (u64, &[u8])> {
+        let eof = u32::from_u24_be_bytes(IPSPatch::EOF) as u64;
+        let mut start = 0;
+        std::iter::from_fn(move || {
+            if start >= self.payload.len() {
+                return None;
+            }
+            let mut end = self.payload.len().min(start + u16::MAX as usize);
+            // a hunk at "EOF" would end the patch, so the one before ends a byte early instead
+            if end < self.payload.len() && self.offset + end as u64 == eof {
+                end -= 1;
+            }
+            let chunk = (self.offset + start as u64, &self.payload[start..end]);
+            start = end;
+            Some(chunk)
+        })
+    }
+
     /// reads an [IPSHunk::Regular] from `reader` and adds it to `result`. Already parsed information must be passed to `offset`, `length`.
     fn read(reader: &mut impl Read, offset: u32, length: u16) -> Result<IPSHunk, Error> {
         let mut payload = vec![0; length as usize];
         reader.read_exact(&mut payload).map_err(|_| Error::new(ParsingError).with_description("Unable to read payload.".to_string()))?;
         Ok(IPSHunk::Regular(IPSRegularHunkData {
-            offset,
-            length,
+            offset: offset as u64,
             payload: payload.into_boxed_slice(),
         }))
     }
 
     /// Applies patch to `target`.
     fn apply<T>(&self, target: &mut T) -> Result<(), Error> where T: Seek + Write {
-        target.seek(SeekFrom::Start(self.offset as u64))
+        target.seek(SeekFrom::Start(self.offset))
             .map_err(|_| Error::new(PatchingError).with_description("Unable to apply ips regular hunk.".to_string()))?;
         target.write_all(self.payload.as_ref())
             .map_err(|_| Error::new(PatchingError).with_description("Unable to apply ips regular hunk.".to_string()))?;
@@ -56,10 +105,10 @@ impl IPSRegularHunkData {
 /// RLE hunks have their length field set to zero; in place of a payload there is a two-byte length
 /// of the run followed by a single byte indicating the value to be written. Applying the RLE hunk
 /// is done by writing this byte the specified number of times at the specified offset.
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct IPSRLEHunkData {
     /// the offset to write payload
-    pub offset: u32,
+    pub offset: u64,
     /// amount of times to write payload.
     pub run_length: u16,
     /// byte to repeat run_length times.
@@ -69,7 +118,7 @@ pub struct IPSRLEHunkData {
 impl IPSRLEHunkData {
     /// writes `self` to `writer`.
     fn write(&self, writer: &mut impl Write) -> IOResult<()> {
-        writer.write_all(&self.offset.to_u24_be_bytes())?;
+        writer.write_all(&checked_hunk_offset(self.offset)?.to_u24_be_bytes())?;
         writer.write_all(&[0x0, 0x0])?; // rle hunks have length set to 0
         writer.write_all(&self.run_length.to_be_bytes())?;
         writer.write_all(&[self.payload])?;
@@ -80,7 +129,7 @@ impl IPSRLEHunkData {
         let run_length = reader.read_u16_be("Unable to read RLE run length.".to_string())?;
         let payload = reader.read_u8("Unable to read RLE payload.".to_string())?;
         return Ok(IPSHunk::RLE(IPSRLEHunkData {
-            offset,
+            offset: offset as u64,
             run_length,
             payload,
         }));
@@ -89,7 +138,7 @@ impl IPSRLEHunkData {
     /// Applies patch to `target`.
     fn apply<T>(&self, target: &mut T) -> Result<(), Error> where T: Seek + Write {
         // go to the offset
-        target.seek(SeekFrom::Start(self.offset as u64))
+        target.seek(SeekFrom::Start(self.offset))
             .map_err(|_| Error::new(PatchingError).with_description("Unable to apply ips RLE hunk.".to_string()))?;
 
         // write the payload
@@ -100,7 +149,10 @@ impl IPSRLEHunkData {
 }
 
 /// represents an IPS Hunk.
-#[derive(Debug, PartialEq)]
+///
+/// Hunks are ordered by offset, then regular before RLE hunks, then by their data, so they can be
+/// kept in [BTreeSet](std::collections::BTreeSet)s. This is not the order they are applied in.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum IPSHunk {
     /// A [regular IPS hunk.](IPSRegularHunkData).
     Regular(IPSRegularHunkData),
@@ -108,6 +160,23 @@ pub enum IPSHunk {
     RLE(IPSRLEHunkData),
 }
 
+impl Ord for IPSHunk {
+    fn cmp(&self, other: &IPSHunk) -> Ordering {
+        self.offset().cmp(&other.offset()).then_with(|| match (self, other) {
+            (IPSHunk::Regular(x), IPSHunk::Regular(y)) => x.cmp(y),
+            (IPSHunk::RLE(x), IPSHunk::RLE(y)) => x.cmp(y),
+            (IPSHunk::Regular(_), IPSHunk::RLE(_)) => Ordering::Less,
+            (IPSHunk::RLE(_), IPSHunk::Regular(_)) => Ordering::Greater,
+        })
+    }
+}
+
+impl PartialOrd for IPSHunk {
+    fn partial_cmp(&self, other: &IPSHunk) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
 enum ReadHunkResult {
     Hunk(IPSHunk),
     EOF(Option<u32>),
@@ -147,7 +216,7 @@ impl IPSHunk {
         let offset = reader.read_u24_be("Unable to parse offset.".to_string())?;
         // try to read eof first
         if let Some(result) = Self::try_read_eof(reader, offset) {
-            return Ok(result?);
+            return result;
         }
         let length = reader.read_u16_be("Unable to read length.".to_string())?;
         // rle hunks have their length field set to zero
@@ -165,15 +234,98 @@ impl IPSHunk {
             IPSHunk::RLE(x) => x.apply(target)
         }
     }
+
+    /// returns the offset the hunk is written to.
+    pub fn offset(&self) -> u64 {
+        match self {
+            IPSHunk::Regular(x) => x.offset,
+            IPSHunk::RLE(x) => x.offset,
+        }
+    }
+
+    /// returns the amount of bytes the hunk writes.
+    pub fn length(&self) -> u64 {
+        match self {
+            IPSHunk::Regular(x) => x.payload.len() as u64,
+            IPSHunk::RLE(x) => x.run_length as u64,
+        }
+    }
+
+    /// returns the offset directly after the last byte written by the hunk.
+    ///
+    /// Offsets of hunks built in memory aren't limited to what IPS can store, so the end saturates
+    /// at [u64::MAX] instead of wrapping around.
+    pub fn end(&self) -> u64 {
+        self.offset().saturating_add(self.length())
+    }
+
+    /// returns `true` if `self` and `other` write to at least one common offset.
+    pub fn overlaps(&self, other: &IPSHunk) -> bool {
+        self.offset() < other.end() && other.offset() < self.end()
+    }
+
+    /// writes the part of the hunk that falls into `buf` to it, where `buf` holds the bytes
+    /// starting at `buf_offset`.
+    pub(crate) fn overlay(&self, buf_offset: u64, buf: &mut [u8]) {
+        match self {
+            IPSHunk::Regular(x) => overlay_slice(x.offset, &x.payload, buf_offset, buf),
+            IPSHunk::RLE(x) => {
+                if let Some(target) = overlapping_part(self.offset()..self.end(), buf_offset, buf) {
+                    target.fill(x.payload);
+                }
+            }
+        }
+    }
+}
+
+/// Bytes of a payload shown by the alternate [Display] form of a hunk.
+const DISPLAY_PAYLOAD_LEN: usize = 16;
+
+/// Formats the hunk as a one-line summary, e.g. `RLE hunk at 0x000102 writing 0xCC 4 times`.
+///
+/// The alternate form `{:#}` adds the first bytes of regular payloads on a second line.
+impl Display for IPSHunk {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IPSHunk::Regular(x) => {
+                write!(f, "regular hunk at {:#08X} writing {} bytes", x.offset, x.payload.len())?;
+                if f.alternate() {
+                    let shown = &x.payload[..x.payload.len().min(DISPLAY_PAYLOAD_LEN)];
+                    let bytes: Vec<String> = shown.iter().map(|x| format!("{:02X}", x)).collect();
+                    write!(f, "\n  {}", bytes.join(" "))?;
+                    if shown.len() < x.payload.len() {
+                        write!(f, " ...")?;
+                    }
+                }
+                Ok(())
+            }
+            IPSHunk::RLE(x) => write!(f, "RLE hunk at {:#08X} writing {:#04X} {} times", x.offset, x.payload, x.run_length),
+        }
+    }
+}
+
+/// How hard creating an [IPSPatch] works to make its hunks small.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum IPSOptimization {
+    /// writes each change as a single hunk, an RLE hunk if it repeats one byte and that is smaller.
+    #[default]
+    Basic,
+    /// splits changes into regular and RLE hunks wherever that saves bytes, e.g. a run of one byte
+    /// followed by varied data becomes an RLE hunk and a regular hunk.
+    Size,
 }
 
 /// Represents an IPS patch file.
-#[derive(Debug, PartialEq)]
+///
+/// Hunks are kept in the order they are applied in. Later hunks overwrite earlier ones where they
+/// overlap, so the order is only changed through the mutators below.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
 pub struct IPSPatch {
     /// List of [hunks](IPSHunk) to apply.
-    pub hunks: Vec<IPSHunk>,
+    hunks: Vec<IPSHunk>,
     /// optional value to truncate patched files to.
-    pub truncate: Option<u32>,
+    truncate: Option<u64>,
 }
 
 impl IPSPatch {
@@ -202,11 +354,11 @@ impl IPSPatch {
     ///
     /// # Examples
     ///
-    /// ```
+    /// ```no_run
     /// // writes a patch to a file
     /// use std::fs::File;
     /// use rom_patcher::ips::IPSPatch;
-    /// let mut patch_file = File::create("test.ips");
+    /// let mut patch_file = File::create("test.ips").expect("Unable to create file.");
     /// let patch = IPSPatch::new();
     /// patch.write(&mut patch_file).expect("Write failed.");
     /// ```
@@ -220,10 +372,124 @@ impl IPSPatch {
         };
         writer.write_all(IPSPatch::EOF)?;
         if let Some(truncate) = self.truncate {
-            writer.write_all(&truncate.to_u24_be_bytes())?;
+            writer.write_all(&checked_u24(truncate)?.to_u24_be_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// returns the amount of bytes [IPSPatch::write] writes.
+    pub fn serialized_len(&self) -> usize {
+        let hunks: usize = self.hunks.iter()
+            .map(|x| match x {
+                IPSHunk::Regular(data) => data.payload.len() + 5 * data.chunks().count(),
+                IPSHunk::RLE(_) => 8,
+            })
+            .sum();
+        Self::HEADER.len() + hunks + Self::EOF.len() + self.truncate.map_or(0, |_| 3)
+    }
+
+    /// appends `self` to `buf`, growing it at most once.
+    pub fn write_to_vec(&self, buf: &mut Vec<u8>) -> IOResult<()> {
+        buf.reserve_exact(self.serialized_len());
+        self.write(buf)
+    }
+
+    /// returns `self` as written by [IPSPatch::write].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rom_patcher::ips::IPSPatch;
+    /// let patch = IPSPatch::new().with_truncate(32);
+    /// let data = patch.to_bytes().unwrap();
+    /// assert_eq!(data, b"PATCHEOF\0\0\x20");
+    /// assert_eq!(IPSPatch::from_bytes(&data).unwrap(), patch);
+    /// ```
+    pub fn to_bytes(&self) -> IOResult<Vec<u8>> {
+        let mut result = Vec::new();
+        self.write_to_vec(&mut result)?;
+        Ok(result)
+    }
+
+    /// reads an [IPSPatch] from `data`, see [IPSPatch::read_from].
+    pub fn from_bytes(data: &[u8]) -> Result<IPSPatch, Error> {
+        Self::read_from(&mut &data[..])
+    }
+
+    /// returns the [hunks](IPSHunk) of the patch in the order they are applied.
+    pub fn hunks(&self) -> &[IPSHunk] {
+        &self.hunks
+    }
+
+    /// returns the value patched files are truncated to, if any.
+    pub fn truncate(&self) -> Option<u64> {
+        self.truncate
+    }
+
+    /// sets the value patched files are truncated to. `None` disables truncation.
+    pub fn set_truncate(&mut self, truncate: Option<u64>) {
+        self.truncate = truncate;
+    }
+
+    /// returns `true` if the hunks are ordered by offset.
+    pub fn is_sorted(&self) -> bool {
+        self.hunks.windows(2).all(|pair| pair[0].offset() <= pair[1].offset())
+    }
+
+    /// inserts `hunk` after all hunks with an offset lower or equal to its own.
+    ///
+    /// If the patch [is sorted](IPSPatch::is_sorted) it stays sorted. Since `hunk` is placed after
+    /// any hunk it may overlap with at the same offset, it still wins over those when applied.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rom_patcher::ips::{IPSHunk, IPSPatch, IPSRLEHunkData};
+    /// let mut patch = IPSPatch::new();
+    /// patch.insert_sorted(IPSHunk::RLE(IPSRLEHunkData { offset: 8, run_length: 2, payload: 0xFF }));
+    /// patch.insert_sorted(IPSHunk::RLE(IPSRLEHunkData { offset: 0, run_length: 2, payload: 0xFF }));
+    /// assert_eq!(patch.hunks()[0].offset(), 0);
+    /// ```
+    pub fn insert_sorted(&mut self, hunk: IPSHunk) {
+        let index = self.hunks.partition_point(|x| x.offset() <= hunk.offset());
+        self.hunks.insert(index, hunk);
+    }
+
+    /// inserts `hunk` like [IPSPatch::insert_sorted] and returns a [ValidationError] instead if it
+    /// overlaps with any existing hunk.
+    pub fn insert_sorted_checked(&mut self, hunk: IPSHunk) -> Result<(), Error> {
+        if let Some(existing) = self.hunks.iter().position(|x| x.overlaps(&hunk)) {
+            return Err(Error::new(ValidationError)
+                .with_message(Message::OverlappingHunk { offset: hunk.offset(), existing }));
         }
+        self.insert_sorted(hunk);
         Ok(())
     }
+
+    /// removes and returns the hunk at `index`, keeping the order of the remaining hunks.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds.
+    pub fn remove_hunk(&mut self, index: usize) -> IPSHunk {
+        self.hunks.remove(index)
+    }
+
+    /// keeps only the hunks for which `predicate` returns `true`, keeping their order.
+    pub fn retain_hunks(&mut self, predicate: impl FnMut(&IPSHunk) -> bool) {
+        self.hunks.retain(predicate);
+    }
+
+    /// removes all hunks from the patch.
+    pub fn clear_hunks(&mut self) {
+        self.hunks.clear();
+    }
+
+    /// consumes the patch and returns its hunks in the order they are applied.
+    pub fn into_hunks(self) -> Vec<IPSHunk> {
+        self.hunks
+    }
+
     /// adds `hunk` to patch.
     ///
     /// # Examples
@@ -235,7 +501,6 @@ impl IPSPatch {
     ///     IPSHunk::Regular(
     ///         IPSRegularHunkData {
     ///             offset: 0,
-    ///             length: 1,
     ///             payload: vec![1].into_boxed_slice()
     ///         }
     ///     )
@@ -255,7 +520,6 @@ impl IPSPatch {
     ///         IPSHunk::Regular(
     ///             IPSRegularHunkData {
     ///                 offset: 0,
-    ///                 length: 1,
     ///                 payload: vec![1].into_boxed_slice(),
     ///             }
     ///         )
@@ -276,11 +540,258 @@ impl IPSPatch {
     /// let patch = IPSPatch::new()
     ///     .with_truncate(32);
     /// ```
-    pub fn with_truncate(mut self, truncate: u32) -> Self {
+    pub fn with_truncate(mut self, truncate: u64) -> Self {
         self.truncate = Some(truncate);
         return self;
     }
 
+    /// returns the length a file of `source_len` bytes has after applying the patch.
+    pub fn patched_len(&self, source_len: u64) -> u64 {
+        let end = self.hunks.iter()
+            .map(|x| x.end())
+            .fold(source_len, u64::max);
+        match self.truncate {
+            Some(truncate) => end.min(truncate),
+            None => end,
+        }
+    }
+
+    /// returns a copy of the patch with every payload zeroed, keeping hunk kinds, offsets, lengths,
+    /// order and truncation.
+    ///
+    /// The redacted patch parses like the original, so it can be shared when debugging the
+    /// structure of a patch without sharing the copyrighted data it contains.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rom_patcher::ips::{IPSHunk, IPSPatch, IPSRegularHunkData};
+    /// let patch = IPSPatch::new()
+    ///     .with_hunk(IPSHunk::Regular(IPSRegularHunkData { offset: 4, payload: Box::new([7, 9]) }));
+    /// let redacted = patch.redact_payloads();
+    /// assert_eq!(redacted.hunks()[0], IPSHunk::Regular(IPSRegularHunkData { offset: 4, payload: Box::new([0, 0]) }));
+    /// ```
+    pub fn redact_payloads(&self) -> IPSPatch {
+        let hunks = self.hunks.iter()
+            .map(|x| match x {
+                IPSHunk::Regular(data) => IPSHunk::Regular(IPSRegularHunkData {
+                    offset: data.offset,
+                    payload: vec![0; data.payload.len()].into_boxed_slice(),
+                }),
+                IPSHunk::RLE(data) => IPSHunk::RLE(IPSRLEHunkData {
+                    offset: data.offset,
+                    run_length: data.run_length,
+                    payload: 0,
+                }),
+            })
+            .collect();
+        return IPSPatch { hunks, truncate: self.truncate };
+    }
+
+    /// builds an [IndexedIPSPatch] answering offset queries over the hunks of the patch.
+    ///
+    /// Building the index is `O(n log n)`, so it should be kept around for repeated queries.
+    pub fn indexed(&self) -> IndexedIPSPatch<'_> {
+        IndexedIPSPatch::new(self)
+    }
+
+    /// returns the bytes in `range` of the file that results from applying the patch to `source`,
+    /// without modifying `source`.
+    ///
+    /// The result is shorter than `range` if the patched file ends before it.
+    pub fn apply_range<T>(&self, source: &mut T, range: Range<u64>) -> Result<Vec<u8>, Error> where T: Read + Seek {
+        self.indexed().apply_range(source, range)
+    }
+
+    /// Largest offset a hunk can start at.
+    pub const MAX_OFFSET: u64 = 0xFFFFFF;
+
+    /// Unchanged bytes between two changes up to which both are written as a single hunk, because a
+    /// hunk header costs more.
+    const MERGE_GAP: u64 = 5;
+
+    /// creates a patch turning `source` into `target`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rom_patcher::ips::IPSPatch;
+    /// let patch = IPSPatch::create(&[0, 1, 2, 3], &[0, 9, 2]).unwrap();
+    /// assert_eq!(patch.hunks().len(), 1);
+    /// assert_eq!(patch.truncate(), Some(3));
+    /// ```
+    pub fn create(source: &[u8], target: &[u8]) -> Result<IPSPatch, Error> {
+        Self::from_diff(&Diff::new(source, target), target)
+    }
+
+    /// creates a patch from `diff`, the differences between some source and `target`.
+    ///
+    /// Returns a [CreatingError] if the differences lie beyond what IPS can address.
+    pub fn from_diff(diff: &Diff, target: &[u8]) -> Result<IPSPatch, Error> {
+        Self::from_diff_with_optimization(diff, target, IPSOptimization::Basic)
+    }
+
+    /// creates a patch from `diff` like [IPSPatch::from_diff], choosing hunks according to
+    /// `optimization`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rom_patcher::create::Diff;
+    /// use rom_patcher::ips::{IPSOptimization, IPSPatch};
+    ///
+    /// let target = [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 2];
+    /// let diff = Diff::new(&[0xFF; 12], &target);
+    /// let basic = IPSPatch::from_diff(&diff, &target).unwrap();
+    /// let size = IPSPatch::from_diff_with_optimization(&diff, &target, IPSOptimization::Size).unwrap();
+    /// assert_eq!(basic.hunks().len(), 1);
+    /// assert_eq!(size.hunks().len(), 2);
+    /// assert!(size.serialized_len() < basic.serialized_len());
+    /// ```
+    pub fn from_diff_with_optimization(diff: &Diff, target: &[u8], optimization: IPSOptimization) -> Result<IPSPatch, Error> {
+        Self::from_diff_target(diff, &mut &target[..], optimization)
+    }
+
+    /// creates a patch from `diff`, reading the changed bytes from `target`.
+    pub(crate) fn from_diff_target(diff: &Diff, target: &mut dyn DiffTarget, optimization: IPSOptimization) -> Result<IPSPatch, Error> {
+        let mut result = IPSPatch::new();
+        if diff.target_len < diff.source_len {
+            if diff.target_len > Self::MAX_OFFSET {
+                return Err(Error::new(CreatingError)
+                    .with_message(Message::BeyondFormatLimit { format: "IPS".to_string(), offset: diff.target_len }));
+            }
+            result.truncate = Some(diff.target_len);
+        }
+
+        let mut merged: Vec<Range<u64>> = Vec::new();
+        for region in &diff.regions {
+            match merged.last_mut() {
+                Some(last) if region.start - last.end <= Self::MERGE_GAP => last.end = region.end,
+                _ => merged.push(region.clone()),
+            }
+        }
+
+        for region in merged {
+            let previous = match region.start.checked_sub(1) {
+                Some(x) => Some(target.read_range(x..region.start)?[0]),
+                None => None,
+            };
+            result.push_changes_with_optimization(region.start, &target.read_range(region.clone())?, previous, optimization)?;
+        }
+        Ok(result)
+    }
+
+    /// appends hunks writing `payload` at `offset`, split into as many hunks as needed.
+    ///
+    /// `previous` is the byte in front of `offset` in the target, used to start a hunk early if it
+    /// would otherwise begin at the offset spelling "EOF".
+    pub(crate) fn push_changes(&mut self, offset: u64, payload: &[u8], previous: Option<u8>) -> Result<(), Error> {
+        self.push_changes_with_optimization(offset, payload, previous, IPSOptimization::Basic)
+    }
+
+    /// appends hunks writing `payload` at `offset` like [IPSPatch::push_changes], choosing hunks
+    /// according to `optimization`.
+    pub(crate) fn push_changes_with_optimization(&mut self, offset: u64, payload: &[u8], previous: Option<u8>, optimization: IPSOptimization) -> Result<(), Error> {
+        let eof = u32::from_u24_be_bytes(IPSPatch::EOF) as u64;
+        let end = offset + payload.len() as u64;
+        let mut start = offset;
+        while start < end {
+            let mut prefix = None;
+            // a hunk at "EOF" would end the patch, so it starts a byte early instead
+            if start == eof {
+                prefix = match start.checked_sub(offset + 1) {
+                    Some(x) => Some(payload[x.to_index()]),
+                    None => previous,
+                };
+                if prefix.is_none() {
+                    return Err(Error::new(CreatingError)
+                        .with_description(format!("IPS can't start a hunk at offset {}.", eof)));
+                }
+                start -= 1;
+            }
+            if start > Self::MAX_OFFSET {
+                return Err(Error::new(CreatingError)
+                    .with_message(Message::BeyondFormatLimit { format: "IPS".to_string(), offset: start }));
+            }
+            let chunk_end = end.min(start + u16::MAX as u64);
+            let mut chunk: Vec<u8> = prefix.into_iter().collect();
+            chunk.extend_from_slice(&payload[(start + chunk.len() as u64 - offset).to_index()..(chunk_end - offset).to_index()]);
+            match optimization {
+                IPSOptimization::Basic => self.hunks.push(Self::create_hunk(start, &chunk)),
+                IPSOptimization::Size => self.hunks.extend(Self::create_smallest_hunks(start, &chunk, eof)),
+            }
+            start = chunk_end;
+        }
+        Ok(())
+    }
+
+    /// creates the hunk writing `payload` at `offset`, preferring RLE where it is smaller.
+    fn create_hunk(offset: u64, payload: &[u8]) -> IPSHunk {
+        // an RLE hunk is 8 bytes, a regular one 5 bytes plus its payload
+        if payload.len() > 3 && payload.iter().all(|x| *x == payload[0]) {
+            return IPSHunk::RLE(IPSRLEHunkData {
+                offset,
+                run_length: payload.len() as u16,
+                payload: payload[0],
+            });
+        }
+        IPSHunk::Regular(IPSRegularHunkData {
+            offset,
+            payload: payload.into(),
+        })
+    }
+
+    /// creates the hunks writing `payload` at `offset` that take the fewest bytes, none of them
+    /// starting at `eof`.
+    ///
+    /// Finds the cheapest split into regular hunks, costing 5 bytes plus their payload, and RLE
+    /// hunks, costing 8 bytes, in a single pass. `payload` must fit a single hunk.
+    fn create_smallest_hunks(offset: u64, payload: &[u8], eof: u64) -> Vec<IPSHunk> {
+        // cost[i] is the size of the cheapest hunks writing payload[..i], the last of them
+        // starting at from[i]
+        let mut cost = vec![0i64; payload.len() + 1];
+        let mut from = vec![(0, false); payload.len() + 1];
+        // cheapest start of a regular hunk by cost[j] - j, and of an RLE hunk inside the run
+        let mut regular: Option<(i64, usize)> = None;
+        let mut rle: Option<(i64, usize)> = None;
+        for i in 1..=payload.len() {
+            let j = i - 1;
+            if j > 0 && payload[j] != payload[j - 1] {
+                rle = None;
+            }
+            if j == 0 || offset + j as u64 != eof {
+                if regular.is_none_or(|(x, _)| cost[j] - (j as i64) < x) {
+                    regular = Some((cost[j] - j as i64, j));
+                }
+                if rle.is_none_or(|(x, _)| cost[j] < x) {
+                    rle = Some((cost[j], j));
+                }
+            }
+            let as_regular = regular.map(|(x, j)| (x + i as i64 + 5, j));
+            let as_rle = rle.map(|(x, j)| (x + 8, j));
+            (cost[i], from[i]) = match (as_regular, as_rle) {
+                (Some(a), Some(b)) if b.0 < a.0 => (b.0, (b.1, true)),
+                (Some(a), _) => (a.0, (a.1, false)),
+                (None, Some(b)) => (b.0, (b.1, true)),
+                (None, None) => unreachable!("a hunk can always start at the offset of the payload"),
+            };
+        }
+
+        let mut result = Vec::new();
+        let mut end = payload.len();
+        while end > 0 {
+            let (start, is_rle) = from[end];
+            let hunk_offset = offset + start as u64;
+            result.push(match is_rle {
+                true => IPSHunk::RLE(IPSRLEHunkData { offset: hunk_offset, run_length: (end - start) as u16, payload: payload[start] }),
+                false => IPSHunk::Regular(IPSRegularHunkData { offset: hunk_offset, payload: payload[start..end].into() }),
+            });
+            end = start;
+        }
+        result.reverse();
+        return result;
+    }
+
     /// Reads data from `reader` and returns [PatchParsingError] if [IPSPatch::HEADER] was not read.
     fn read_header(reader: &mut impl Read) -> Result<(), Error> {
         reader.assert_read(
@@ -295,12 +806,12 @@ impl IPSPatch {
     ///
     /// # Examples
     ///
-    /// ```
+    /// ```no_run
     /// use std::fs::File;
     /// use rom_patcher::ips::IPSPatch;
     ///
     /// // reads a patch file
-    /// let mut file = File::open("patch.ips");
+    /// let mut file = File::open("patch.ips").expect("Unable to open file.");
     /// let patch = IPSPatch::read_from(&mut file);
     /// ```
     pub fn read_from(reader: &mut impl Read) -> Result<IPSPatch, Error> {
@@ -313,69 +824,716 @@ impl IPSPatch {
                     result.hunks.push(hunk);
                 }
                 ReadHunkResult::EOF(value) => {
-                    result.truncate = value;
+                    result.truncate = value.map(u64::from);
                     return Ok(result);
                 }
             }
         }
     }
 
-
-    /// Applies the patch to `target`.
-    pub fn apply<T>(&self, target: &mut T) -> Result<(), Error> where T: Write + Seek + Truncate {
-        for hunk in &self.hunks {
-            hunk.apply(target)?;
+    /// Reads every [IPSPatch] from `reader`, for files some tools make by concatenating patches.
+    ///
+    /// Parsing continues after each [IPSPatch::EOF] while data remains. Three bytes following an
+    /// EOF are read as its truncate unless they start the [IPSPatch::HEADER] of the next patch.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rom_patcher::ips::IPSPatch;
+    ///
+    /// let patches = IPSPatch::read_all_from(&mut &b"PATCH\0\0\x01\0\x01\xAAEOFPATCHEOF\0\0\x02"[..]).unwrap();
+    /// assert_eq!(patches.len(), 2);
+    /// assert_eq!(patches[0].truncate(), None);
+    /// assert_eq!(patches[1].truncate(), Some(2));
+    /// ```
+    pub fn read_all_from(reader: &mut impl Read) -> Result<Vec<IPSPatch>, Error> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)
+            .map_err(|e| Error::new(ParsingError)
+                .with_description("Unable to read patches.".to_string())
+                .with_source(Box::new(e)))?;
+        let mut rest = &data[..];
+        let mut result = Vec::new();
+        loop {
+            let mut patch = IPSPatch::new();
+            Self::read_header(&mut rest)?;
+            // the EOF is handled here, since its truncate may be the header of the next patch
+            while !rest.starts_with(IPSPatch::EOF) {
+                match IPSHunk::try_read(&mut rest)? {
+                    ReadHunkResult::Hunk(hunk) => patch.hunks.push(hunk),
+                    ReadHunkResult::EOF(_) => unreachable!("the offset isn't EOF"),
+                }
+            }
+            rest = &rest[IPSPatch::EOF.len()..];
+            if rest.len() >= 3 && !rest.starts_with(IPSPatch::HEADER) {
+                patch.truncate = Some(u32::from_u24_be_bytes(&rest[..3]) as u64);
+                rest = &rest[3..];
+            }
+            result.push(patch);
+            if rest.is_empty() {
+                return Ok(result);
+            }
         }
-        if let Some(value) = self.truncate {
-            target.truncate(value).map_err(|_|Error::new(PatchingError).with_description("Unable to truncate target.".to_string()))?;
+    }
+
+    /// Applies `patches` to `target` in order, e.g. those read by [IPSPatch::read_all_from].
+    pub fn apply_concatenated<T>(patches: &[IPSPatch], target: &mut T) -> Result<(), Error> where T: Write + Seek + Truncate {
+        for patch in patches {
+            patch.apply(target)?;
         }
         Ok(())
     }
-}
 
-/// applies `patch` to `target`.
-///
-/// This method differs from read and apply from [IPSPatch] because there are no intermediate patch
-/// structs and hunks are applied as they are read.
-///
-/// # Examples
-/// ```
-/// use std::fs::File;
-/// use rom_patcher::ips::apply_ips_patch;
-/// use std::error::Error;
-///
-/// fn main() -> Result<(), dyn Error> {
-///     let mut patch_file = File::open("my_patch.ips")?;
-///     let mut target_file = File::options().write(true).open("target.bin")?;
-///     apply_ips_patch(&mut patch_file, &mut target_file)?;
-///     Ok(())
-/// }
-/// ```
-pub fn apply_ips_patch<TPatch, TTarget>(patch: &mut TPatch, target: &mut TTarget) -> Result<(), Error> where TPatch: Read, TTarget: Write + Seek + Truncate {
-    IPSPatch::read_header(patch)?;
-    loop {
-        let hunk_result = IPSHunk::try_read(patch)?;
-        match hunk_result {
-            ReadHunkResult::Hunk(hunk) => {
-                hunk.apply(target)?;
-            }
-            ReadHunkResult::EOF(trunc) => {
-                if let Some(value) = trunc {
-                    target.truncate(value).map_err(|_|Error::new(PatchingError).with_description("Unable to truncate target.".to_string()))?;
-                }
-                return Ok(());
-            }
+
+    /// returns the indices of all hunks writing at or past [IPSPatch::truncate], whose data is
+    /// therefore partly or entirely cut off again.
+    pub fn truncated_hunks(&self) -> Vec<usize> {
+        match self.truncate {
+            Some(truncate) => self.hunks.iter()
+                .enumerate()
+                .filter(|(_, hunk)| hunk.end() > truncate)
+                .map(|(i, _)| i)
+                .collect(),
+            None => Vec::new(),
         }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use spectral::prelude::*;
+    /// returns everything found wrong with the patch.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rom_patcher::ips::{IPSHunk, IPSPatch, IPSRLEHunkData};
+    /// let patch = IPSPatch::new()
+    ///     .with_hunk(IPSHunk::RLE(IPSRLEHunkData { offset: 8, run_length: 2, payload: 0xFF }))
+    ///     .with_truncate(4);
+    /// assert_eq!(patch.validate()[0].hunk, Some(0));
+    /// ```
+    pub fn validate(&self) -> Vec<Diagnostic> {
+        let mut result = Vec::new();
+        if let Some(truncate) = self.truncate {
+            for i in self.truncated_hunks() {
+                let hunk = &self.hunks[i];
+                let message = if hunk.offset() >= truncate {
+                    format!("Hunk at offset {} lies past the truncation to {} bytes.", hunk.offset(), truncate)
+                } else {
+                    format!("Hunk at offset {} is cut off by the truncation to {} bytes.", hunk.offset(), truncate)
+                };
+                result.push(Diagnostic::error(Some(i), message).with_code(DiagnosticCode::TruncatedHunk));
+            }
+        }
+        return result;
+    }
 
-    use test_data::*;
+    /// returns a [ValidationError] if a hunk writes past the [maximum
+    /// offset](ApplyOptions::max_offset) of `options`.
+    fn check_max_offset(&self, options: &ApplyOptions) -> Result<(), Error> {
+        for hunk in &self.hunks {
+            check_max_offset(hunk.offset(), hunk.length(), options)?;
+        }
+        Ok(())
+    }
 
-    use crate::test_util::*;
+    /// returns the diagnostics [TruncateCheck] of `options` asks for, or a [ValidationError] if it
+    /// denies truncated hunks and there are some.
+    fn check_truncation(&self, options: &ApplyOptions) -> Result<Vec<Diagnostic>, Error> {
+        let diagnostics = match options.truncate_check() {
+            TruncateCheck::Ignore => Vec::new(),
+            TruncateCheck::Warn | TruncateCheck::Deny => self.validate(),
+        };
+        if options.truncate_check() == TruncateCheck::Deny {
+            if let Some(diagnostic) = diagnostics.first() {
+                return Err(Error::new(ValidationError).with_description(diagnostic.message.clone()));
+            }
+        }
+        Ok(diagnostics)
+    }
+
+    /// returns the diagnostics of [IPSPatch::check_max_offset], [IPSPatch::check_truncation] and
+    /// [IPSPatch::check_duplicate_writes] without failing on them, together with the indices of the
+    /// hunks that have to be skipped. Hunks that `options` refuse are reported as failed hunks.
+    fn collect_failures(&self, options: &ApplyOptions) -> (Vec<Diagnostic>, Vec<usize>) {
+        let mut diagnostics = Vec::new();
+        let mut skipped = Vec::new();
+        let truncated = match options.truncate_check() {
+            TruncateCheck::Ignore => Vec::new(),
+            TruncateCheck::Warn | TruncateCheck::Deny => self.validate(),
+        };
+        for diagnostic in truncated {
+            let Some(i) = diagnostic.hunk else { continue };
+            if options.truncate_check() == TruncateCheck::Deny {
+                diagnostics.push(Diagnostic::failed_hunk(i, &Error::new(ValidationError).with_description(diagnostic.message)));
+                skipped.push(i);
+            } else {
+                diagnostics.push(diagnostic);
+            }
+        }
+        for (i, hunk) in self.hunks.iter().enumerate() {
+            if let Err(e) = check_max_offset(hunk.offset(), hunk.length(), options) {
+                diagnostics.push(Diagnostic::failed_hunk(i, &e));
+                skipped.push(i);
+            }
+        }
+        for diagnostic in self.duplicate_writes() {
+            let Some(i) = diagnostic.hunk else { continue };
+            if options.duplicate_writes() == DuplicateWrites::Error {
+                diagnostics.push(Diagnostic::failed_hunk(i, &Error::new(ValidationError).with_description(diagnostic.message)));
+                skipped.push(i);
+            } else {
+                diagnostics.push(diagnostic);
+            }
+        }
+        return (diagnostics, skipped);
+    }
+
+    /// returns a [DiagnosticCode::DuplicateWrite] warning for every hunk writing different data to
+    /// an offset than an earlier hunk.
+    fn duplicate_writes(&self) -> Vec<Diagnostic> {
+        duplicate_writes(&self.hunks).into_iter()
+            .map(|(earlier, later, offset)| Diagnostic::warning(Some(later), format!(
+                "Hunk at offset {} writes different data than hunk {} to offset {}.", self.hunks[later].offset(), earlier, offset,
+            )).with_code(DiagnosticCode::DuplicateWrite))
+            .collect()
+    }
+
+    /// returns the diagnostics of [IPSPatch::duplicate_writes], or a [ValidationError] if
+    /// `options` refuse duplicate writes and there are some.
+    fn check_duplicate_writes(&self, options: &ApplyOptions) -> Result<Vec<Diagnostic>, Error> {
+        let diagnostics = self.duplicate_writes();
+        if options.duplicate_writes() == DuplicateWrites::Error {
+            if let Some(diagnostic) = diagnostics.first() {
+                return Err(Error::new(ValidationError).with_description(diagnostic.message.clone()));
+            }
+        }
+        Ok(diagnostics)
+    }
+
+    /// returns the diagnostics of applying the patch according to `options` and the indices of the
+    /// hunks to skip, which are only the failing ones with [ApplyOptions::keep_going]. Without it
+    /// the first failure is returned instead, see [IPSPatch::apply_with_options].
+    fn check_options(&self, options: &ApplyOptions) -> Result<(Vec<Diagnostic>, Vec<usize>), Error> {
+        if options.keep_going() {
+            return Ok(self.collect_failures(options));
+        }
+        self.check_max_offset(options)?;
+        let mut diagnostics = self.check_truncation(options)?;
+        diagnostics.extend(self.check_duplicate_writes(options)?);
+        Ok((diagnostics, Vec::new()))
+    }
+
+    /// Applies the patch to `target` according to `options`, returning the diagnostics found.
+    ///
+    /// Returns a [ValidationError] without touching `target` if a hunk would be truncated away and
+    /// `options` deny that, if a hunk writes past [ApplyOptions::max_offset], or if hunks write
+    /// different data to the same offset and [ApplyOptions::duplicate_writes] refuses that. With
+    /// [ApplyOptions::keep_going] those and hunks failing to be written are reported instead, and
+    /// the other hunks are still applied.
+    pub fn apply_with_options<T>(&self, target: &mut T, options: &ApplyOptions) -> Result<Vec<Diagnostic>, Error>
+        where T: Write + Seek + Truncate {
+        let (mut diagnostics, skipped) = self.check_options(options)?;
+        // applying the hunks back to front leaves the data of the earliest hunk at every offset
+        let reverse = options.duplicate_writes() == DuplicateWrites::FirstWins;
+        let selected = |i: usize, _: &IPSHunk| !skipped.contains(&i);
+        let mut failures = Vec::new();
+        let mut failed = |i: usize, e: Error| {
+            if !options.keep_going() {
+                return Err(e);
+            }
+            failures.push(Diagnostic::failed_hunk(i, &e));
+            Ok(())
+        };
+        let mut header_len = 0;
+        if options.header() == HeaderHandling::SkipCopierHeader {
+            let len = target.seek(SeekFrom::End(0))
+                .map_err(|e| Error::new(PatchingError)
+                    .with_description("Unable to read target length.".to_string())
+                    .with_source(Box::new(e)))?;
+            header_len = copier_header_len(len);
+        }
+        if header_len > 0 {
+            self.apply_hunks(&mut Offset::new(target, header_len), options.gap_fill(), reverse, &selected, &mut failed)?;
+        } else {
+            self.apply_hunks(target, options.gap_fill(), reverse, &selected, &mut failed)?;
+        }
+        diagnostics.extend(failures);
+        Ok(diagnostics)
+    }
+
+    /// Applies the patch to `target`.
+    pub fn apply<T>(&self, target: &mut T) -> Result<(), Error> where T: Write + Seek + Truncate {
+        self.apply_filled(target, 0)
+    }
+
+    /// applies the patch to `buf` in place and returns the length of the patched file, which is
+    /// shorter than `buf` if the patch truncates it.
+    ///
+    /// `buf` is never grown. Returns a [PatchingError] without touching `buf` if a hunk writes past
+    /// its end.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rom_patcher::ips::{IPSHunk, IPSPatch, IPSRLEHunkData};
+    /// let patch = IPSPatch::new()
+    ///     .with_hunk(IPSHunk::RLE(IPSRLEHunkData { offset: 1, run_length: 2, payload: 0xFF }))
+    ///     .with_truncate(3);
+    /// let mut rom = [0; 4];
+    /// assert_eq!(patch.apply_in_place(&mut rom).unwrap(), 3);
+    /// assert_eq!(rom, [0, 0xFF, 0xFF, 0]);
+    /// assert!(patch.apply_in_place(&mut [0; 2]).is_err());
+    /// ```
+    pub fn apply_in_place(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        let len = buf.len() as u64;
+        if let Some(hunk) = self.hunks.iter().find(|x| x.end() > len) {
+            return Err(Error::new(PatchingError)
+                .with_message(Message::BeyondBuffer { offset: hunk.offset().max(len), len }));
+        }
+        for hunk in &self.hunks {
+            hunk.overlay(0, buf);
+        }
+        Ok(self.truncate.map_or(buf.len(), |x| (buf.len() as u64).min(x) as usize))
+    }
+
+    /// applies only the hunks at `indices` to `target`, in the order of the patch.
+    ///
+    /// Truncation belongs to the patch as a whole and is still applied. Returns a
+    /// [ValidationError] without touching `target` if an index is out of bounds.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::io::Cursor;
+    /// use rom_patcher::ips::{IPSHunk, IPSPatch, IPSRLEHunkData};
+    /// let patch = IPSPatch::new()
+    ///     .with_hunk(IPSHunk::RLE(IPSRLEHunkData { offset: 0, run_length: 1, payload: 0xAA }))
+    ///     .with_hunk(IPSHunk::RLE(IPSRLEHunkData { offset: 1, run_length: 1, payload: 0xBB }));
+    /// let mut target = Cursor::new(vec![0; 2]);
+    /// patch.apply_selected(&mut target, &[1]).unwrap();
+    /// assert_eq!(target.into_inner(), vec![0, 0xBB]);
+    /// ```
+    pub fn apply_selected<T>(&self, target: &mut T, indices: &[usize]) -> Result<(), Error> where T: Write + Seek + Truncate {
+        if let Some(index) = indices.iter().find(|x| **x >= self.hunks.len()) {
+            return Err(Error::new(ValidationError)
+                .with_message(Message::MissingHunk { index: *index, count: self.hunks.len() }));
+        }
+        self.apply_where(target, |i, _| indices.contains(&i))
+    }
+
+    /// applies only the hunks for which `predicate`, called with the index of each hunk, returns
+    /// `true` to `target`, like [IPSPatch::apply_selected].
+    pub fn apply_where<T>(&self, target: &mut T, predicate: impl Fn(usize, &IPSHunk) -> bool) -> Result<(), Error>
+        where T: Write + Seek + Truncate {
+        self.apply_hunks(target, 0, false, &predicate, &mut |_, e| Err(e))
+    }
+
+    /// applies the patch to `target`, writing `fill` to gaps between the end of `target` and hunks
+    /// past it.
+    fn apply_filled<T>(&self, target: &mut T, fill: u8) -> Result<(), Error> where T: Write + Seek + Truncate {
+        self.apply_hunks(target, fill, false, &|_, _| true, &mut |_, e| Err(e))
+    }
+
+    /// applies the hunks selected by `selected` to `target` like [IPSPatch::apply_filled], from the
+    /// last to the first if `reverse` is `true`.
+    ///
+    /// Errors writing a hunk are passed to `failed` with the index of the hunk, which decides
+    /// whether to stop with an error or continue with the next hunk.
+    fn apply_hunks<T>(&self, target: &mut T, fill: u8, reverse: bool, selected: &dyn Fn(usize, &IPSHunk) -> bool,
+                      failed: &mut dyn FnMut(usize, Error) -> Result<(), Error>) -> Result<(), Error>
+        where T: Write + Seek + Truncate {
+        // seeking past the end already fills with zeros, so only other bytes are written explicitly
+        let mut len = if fill == 0 {
+            u64::MAX
+        } else {
+            target.seek(SeekFrom::End(0))
+                .map_err(|e| Error::new(PatchingError)
+                    .with_description("Unable to read target length.".to_string())
+                    .with_source(Box::new(e)))?
+        };
+        let order: Box<dyn Iterator<Item=usize>> = match reverse {
+            true => Box::new((0..self.hunks.len()).rev()),
+            false => Box::new(0..self.hunks.len()),
+        };
+        for i in order {
+            let hunk = &self.hunks[i];
+            if !selected(i, hunk) {
+                continue;
+            }
+            let offset = hunk.offset();
+            if offset > len {
+                let filled = target.seek(SeekFrom::Start(len))
+                    .and_then(|_| std::io::copy(&mut std::io::repeat(fill).take(offset - len), target))
+                    .map_err(|e| Error::new(PatchingError)
+                        .with_description("Unable to fill gap before hunk.".to_string())
+                        .with_source(Box::new(e)));
+                if let Err(e) = filled {
+                    failed(i, e)?;
+                    continue;
+                }
+            }
+            match hunk.apply(target) {
+                Ok(()) => len = len.max(hunk.end()),
+                Err(e) => failed(i, e)?,
+            }
+        }
+        if let Some(value) = self.truncate {
+            target.truncate(value).map_err(|_|Error::new(PatchingError).with_description("Unable to truncate target.".to_string()))?;
+        }
+        Ok(())
+    }
+}
+
+/// Collects hunks into a patch without truncation, keeping their order.
+///
+/// # Examples
+///
+/// ```
+/// use rom_patcher::ips::{IPSHunk, IPSPatch, IPSRLEHunkData};
+/// let patch: IPSPatch = (0..4)
+///     .map(|x| IPSHunk::RLE(IPSRLEHunkData { offset: x * 8, run_length: 4, payload: 0xFF }))
+///     .collect();
+/// assert_eq!(patch.hunks().len(), 4);
+/// ```
+impl FromIterator<IPSHunk> for IPSPatch {
+    fn from_iter<I: IntoIterator<Item=IPSHunk>>(iter: I) -> IPSPatch {
+        IPSPatch { hunks: iter.into_iter().collect(), truncate: None }
+    }
+}
+
+/// Appends hunks to the patch like [IPSPatch::add_hunk].
+impl Extend<IPSHunk> for IPSPatch {
+    fn extend<I: IntoIterator<Item=IPSHunk>>(&mut self, iter: I) {
+        self.hunks.extend(iter);
+    }
+}
+
+/// Iterates the hunks of the patch in the order they are applied, dropping its truncation.
+impl IntoIterator for IPSPatch {
+    type Item = IPSHunk;
+    type IntoIter = std::vec::IntoIter<IPSHunk>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.hunks.into_iter()
+    }
+}
+
+/// Iterates the hunks of the patch in the order they are applied.
+impl<'a> IntoIterator for &'a IPSPatch {
+    type Item = &'a IPSHunk;
+    type IntoIter = std::slice::Iter<'a, IPSHunk>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.hunks.iter()
+    }
+}
+
+/// Formats the patch as a one-line summary, e.g. `IPS patch with 2 hunks writing 6 bytes`.
+///
+/// The alternate form `{:#}` lists every hunk on a line of its own below the summary.
+///
+/// # Examples
+///
+/// ```
+/// use rom_patcher::ips::{IPSHunk, IPSPatch, IPSRLEHunkData};
+/// let patch = IPSPatch::new()
+///     .with_hunk(IPSHunk::RLE(IPSRLEHunkData { offset: 2, run_length: 4, payload: 0xFF }))
+///     .with_truncate(8);
+/// assert_eq!(patch.to_string(), "IPS patch with 1 hunk writing 4 bytes, truncating to 8 bytes");
+/// assert_eq!(format!("{:#}", patch), "IPS patch with 1 hunk writing 4 bytes, truncating to 8 bytes\n  0: RLE hunk at 0x000002 writing 0xFF 4 times");
+/// ```
+impl Display for IPSPatch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let written: u64 = self.hunks.iter().map(|x| x.length()).sum();
+        let plural = if self.hunks.len() == 1 { "" } else { "s" };
+        write!(f, "IPS patch with {} hunk{} writing {} bytes", self.hunks.len(), plural, written)?;
+        if let Some(truncate) = self.truncate {
+            write!(f, ", truncating to {} bytes", truncate)?;
+        }
+        if f.alternate() {
+            for (i, hunk) in self.hunks.iter().enumerate() {
+                // indent the payload line of the hunk below the hunk
+                write!(f, "\n  {}: {}", i, format!("{:#}", hunk).replace('\n', "\n  "))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// An [IPSPatch] together with an interval index over its hunks.
+///
+/// Queries are `O(log n + k)`, which keeps random access into patched files cheap even for patches
+/// with hundreds of thousands of hunks.
+#[derive(Debug)]
+pub struct IndexedIPSPatch<'a> {
+    patch: &'a IPSPatch,
+    index: IntervalIndex<usize>,
+}
+
+impl<'a> IndexedIPSPatch<'a> {
+    fn new(patch: &'a IPSPatch) -> IndexedIPSPatch<'a> {
+        let index = IntervalIndex::new(patch.hunks.iter()
+            .enumerate()
+            .map(|(i, hunk)| (hunk.offset()..hunk.end(), i)));
+        IndexedIPSPatch { patch, index }
+    }
+
+    /// returns the indexed patch.
+    pub fn patch(&self) -> &'a IPSPatch {
+        self.patch
+    }
+
+    /// returns the indices of all hunks writing into `range`, in the order they are applied.
+    pub fn hunks_overlapping(&self, range: Range<u64>) -> Vec<usize> {
+        let mut result: Vec<usize> = self.index.overlapping(range)
+            .into_iter()
+            .map(|(_, i)| *i)
+            .collect();
+        result.sort_unstable();
+        return result;
+    }
+
+    /// returns all hunks writing to `offset`, in the order they are applied.
+    pub fn hunks_covering(&self, offset: u64) -> Vec<&'a IPSHunk> {
+        let mut indices: Vec<usize> = self.index.covering(offset)
+            .into_iter()
+            .map(|(_, i)| *i)
+            .collect();
+        indices.sort_unstable();
+        indices.into_iter()
+            .map(|i| &self.patch.hunks[i])
+            .collect()
+    }
+
+    /// returns every pair of hunk indices `(earlier, later)` whose hunks write to a common offset.
+    pub fn conflicts(&self) -> Vec<(usize, usize)> {
+        let mut result = Vec::new();
+        for (i, hunk) in self.patch.hunks.iter().enumerate() {
+            for j in self.hunks_overlapping(hunk.offset()..hunk.end()) {
+                if j > i {
+                    result.push((i, j));
+                }
+            }
+        }
+        result.sort_unstable();
+        return result;
+    }
+
+    /// see [IPSPatch::apply_range].
+    pub fn apply_range<T>(&self, source: &mut T, range: Range<u64>) -> Result<Vec<u8>, Error> where T: Read + Seek {
+        self.apply_range_filled(source, range, 0)
+    }
+
+    /// like [IndexedIPSPatch::apply_range], with the bytes past the end of `source` that no hunk
+    /// writes set to `fill`.
+    pub(crate) fn apply_range_filled<T>(&self, source: &mut T, range: Range<u64>, fill: u8) -> Result<Vec<u8>, Error> where T: Read + Seek {
+        let source_len = source.seek(SeekFrom::End(0))
+            .map_err(|_| Error::new(PatchingError).with_description("Unable to read source length.".to_string()))?;
+        let end = range.end.min(self.patch.patched_len(source_len));
+        if range.start >= end {
+            return Ok(Vec::new());
+        }
+        let len = usize::try_from(end - range.start)
+            .map_err(|_| Error::new(PatchingError).with_description(format!("Range of {} bytes doesn't fit into memory.", end - range.start)))?;
+        let mut result = vec![fill; len];
+        if range.start < source_len {
+            let available = (source_len.min(end) - range.start) as usize;
+            source.seek(SeekFrom::Start(range.start))
+                .and_then(|_| source.read_exact(&mut result[..available]))
+                .map_err(|_| Error::new(PatchingError).with_description("Unable to read source.".to_string()))?;
+        }
+        self.overlay(range.start, &mut result);
+        Ok(result)
+    }
+
+    /// writes every hunk falling into `buf` to it in apply order, where `buf` holds the bytes
+    /// starting at `buf_offset`.
+    pub(crate) fn overlay(&self, buf_offset: u64, buf: &mut [u8]) {
+        for i in self.hunks_overlapping(buf_offset..buf_offset.saturating_add(buf.len() as u64)) {
+            self.patch.hunks[i].overlay(buf_offset, buf);
+        }
+    }
+}
+
+/// Size of the chunks the streaming apply functions read and write at once.
+const STREAM_CHUNK_SIZE: usize = 0x10000;
+
+/// applies `patch` to `source` and streams the patched file to every writer in `outputs`.
+///
+/// `source` is read exactly once from start to end, so a large rom can be written to a file, hashed
+/// and uploaded at the same time without reading it again. Returns the length of the patched file.
+///
+/// # Examples
+///
+/// ```
+/// use std::io::Write;
+/// use rom_patcher::ips::{apply_multi, IPSHunk, IPSPatch, IPSRLEHunkData};
+///
+/// let patch = IPSPatch::new()
+///     .with_hunk(IPSHunk::RLE(IPSRLEHunkData { offset: 2, run_length: 2, payload: 0xFF }));
+/// let source = vec![0; 4];
+/// let mut first = Vec::new();
+/// let mut second = Vec::new();
+/// apply_multi(&patch, &mut source.as_slice(), &mut [&mut first, &mut second]).unwrap();
+/// assert_eq!(first, vec![0, 0, 0xFF, 0xFF]);
+/// assert_eq!(first, second);
+/// ```
+pub fn apply_multi(patch: &IPSPatch, source: &mut impl Read, outputs: &mut [&mut dyn Write]) -> Result<u64, Error> {
+    stream(patch, source, outputs, 0)
+}
+
+/// applies `patch` to `source` according to `options` and streams the patched file to every writer
+/// in `outputs` like [apply_multi], returning the length of the patched file and the diagnostics
+/// found.
+///
+/// Hunks are checked and [duplicate writes](ApplyOptions::duplicate_writes) resolved like
+/// [IPSPatch::apply_with_options]. With [ApplyOptions::keep_going] the hunks failing those checks
+/// are skipped and reported, but failing to read `source` or to write an output still fails the
+/// whole stream. Since the length of `source` isn't known up front,
+/// [HeaderHandling::SkipCopierHeader] is refused with a [PatchingError].
+pub fn apply_multi_with_options(patch: &IPSPatch, source: &mut impl Read, outputs: &mut [&mut dyn Write], options: &ApplyOptions)
+    -> Result<(u64, Vec<Diagnostic>), Error> {
+    if options.header() == HeaderHandling::SkipCopierHeader {
+        return Err(Error::new(PatchingError)
+            .with_description("Copier headers can't be skipped when streaming.".to_string()));
+    }
+    let (diagnostics, skipped) = patch.check_options(options)?;
+    let reverse = options.duplicate_writes() == DuplicateWrites::FirstWins;
+    if skipped.is_empty() && !reverse {
+        let len = stream(patch, source, outputs, options.gap_fill())?;
+        return Ok((len, diagnostics));
+    }
+    // later hunks overlay earlier ones, so reversing them leaves the data of the earliest hunk
+    let mut hunks: Vec<IPSHunk> = patch.hunks.iter()
+        .enumerate()
+        .filter(|(i, _)| !skipped.contains(i))
+        .map(|(_, hunk)| hunk.clone())
+        .collect();
+    if reverse {
+        hunks.reverse();
+    }
+    let selected = IPSPatch { hunks, truncate: patch.truncate };
+    let len = stream(&selected, source, outputs, options.gap_fill())?;
+    Ok((len, diagnostics))
+}
+
+/// returns `offset` if it fits the three bytes IPS stores offsets in.
+fn checked_u24(offset: u64) -> IOResult<u32> {
+    if offset > IPSPatch::MAX_OFFSET {
+        return Err(std::io::Error::new(ErrorKind::InvalidInput, format!("IPS can't address offset {}.", offset)));
+    }
+    Ok(offset as u32)
+}
+
+/// returns `offset` if a hunk can start there: it must fit three bytes and not read as "EOF".
+fn checked_hunk_offset(offset: u64) -> IOResult<u32> {
+    if offset == u32::from_u24_be_bytes(IPSPatch::EOF) as u64 {
+        return Err(std::io::Error::new(ErrorKind::InvalidInput, format!("IPS can't start a hunk at offset {}.", offset)));
+    }
+    checked_u24(offset)
+}
+
+/// streams `source` patched with `patch` to `outputs`, filling gaps past the source with `fill`.
+fn stream(patch: &IPSPatch, source: &mut impl Read, outputs: &mut [&mut dyn Write], fill: u8) -> Result<u64, Error> {
+    let indexed = patch.indexed();
+    let limit = patch.truncate.unwrap_or(u64::MAX);
+    let hunks_end = patch.hunks.iter().map(|x| x.end()).max().unwrap_or(0);
+    let mut buf = vec![0; STREAM_CHUNK_SIZE];
+    let mut position: u64 = 0;
+
+    // patch the source while there is data left
+    loop {
+        let read = read_chunk(source, &mut buf)?;
+        if read == 0 {
+            break;
+        }
+        let chunk = &mut buf[..read];
+        indexed.overlay(position, chunk);
+        let emitted = limit.saturating_sub(position).saturating_usize().min(read);
+        write_to_all(outputs, &chunk[..emitted])?;
+        position += read as u64;
+    }
+
+    // emit hunks extending the source
+    let end = hunks_end.max(position).min(limit);
+    let mut emitted = position.min(end);
+    while emitted < end {
+        let chunk = &mut buf[..(end - emitted).min(STREAM_CHUNK_SIZE as u64) as usize];
+        chunk.fill(fill);
+        indexed.overlay(emitted, chunk);
+        write_to_all(outputs, chunk)?;
+        emitted += chunk.len() as u64;
+    }
+    Ok(end)
+}
+
+/// fills `buf` from `source` as far as possible and returns the amount of bytes read.
+fn read_chunk(source: &mut impl Read, buf: &mut [u8]) -> Result<usize, Error> {
+    let mut read = 0;
+    while read < buf.len() {
+        match source.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(Error::new(PatchingError)
+                .with_description("Unable to read source.".to_string())
+                .with_source(Box::new(e))),
+        }
+    }
+    Ok(read)
+}
+
+/// writes `data` to every writer in `outputs`.
+fn write_to_all(outputs: &mut [&mut dyn Write], data: &[u8]) -> Result<(), Error> {
+    for (i, output) in outputs.iter_mut().enumerate() {
+        output.write_all(data).map_err(|e| Error::new(PatchingError)
+            .with_description(format!("Unable to write output {}.", i))
+            .with_source(Box::new(e)))?;
+    }
+    Ok(())
+}
+
+/// applies `patch` to `target`.
+///
+/// This method differs from read and apply from [IPSPatch] because there are no intermediate patch
+/// structs and hunks are applied as they are read.
+///
+/// # Examples
+/// ```no_run
+/// use std::fs::File;
+/// use rom_patcher::ips::apply_ips_patch;
+/// use std::error::Error;
+///
+/// fn main() -> Result<(), Box<dyn Error>> {
+///     let mut patch_file = File::open("my_patch.ips")?;
+///     let mut target_file = File::options().write(true).open("target.bin")?;
+///     apply_ips_patch(&mut patch_file, &mut target_file)?;
+///     Ok(())
+/// }
+/// ```
+pub fn apply_ips_patch<TPatch, TTarget>(patch: &mut TPatch, target: &mut TTarget) -> Result<(), Error> where TPatch: Read, TTarget: Write + Seek + Truncate {
+    IPSPatch::read_header(patch)?;
+    loop {
+        let hunk_result = IPSHunk::try_read(patch)?;
+        match hunk_result {
+            ReadHunkResult::Hunk(hunk) => {
+                hunk.apply(target)?;
+            }
+            ReadHunkResult::EOF(trunc) => {
+                if let Some(value) = trunc {
+                    target.truncate(value as u64).map_err(|_|Error::new(PatchingError).with_description("Unable to truncate target.".to_string()))?;
+                }
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use spectral::prelude::*;
+
+    use test_data::*;
+
+    use crate::test_util::*;
 
     use super::*;
 
@@ -394,7 +1552,6 @@ mod tests {
             IPSPatch::new()
                 .with_hunk(IPSHunk::Regular(IPSRegularHunkData {
                     offset: 258,
-                    length: 2,
                     payload: Box::new([0xAA, 0xBB]),
                 }))
         }
@@ -431,7 +1588,6 @@ mod tests {
             IPSPatch::new()
                 .with_hunk(IPSHunk::Regular(IPSRegularHunkData {
                     offset: 258,
-                    length: 2,
                     payload: Box::new([0xAA, 0xBB]),
                 }))
                 .with_hunk(IPSHunk::RLE(IPSRLEHunkData {
@@ -508,6 +1664,141 @@ mod tests {
             patch_with_multiple_hunks().write(&mut actual).unwrap();
             assert_that!(actual).is_equal_to(patch_with_multiple_hunks_data());
         }
+
+        #[test]
+        fn oversized_payloads_are_split() {
+            let payload: Vec<u8> = (0..70000u32).map(|x| x as u8).collect();
+            let patch = IPSPatch::new()
+                .with_hunk(IPSHunk::Regular(IPSRegularHunkData { offset: 16, payload: payload.clone().into_boxed_slice() }));
+            let mut data = Vec::new();
+            patch.write(&mut data).unwrap();
+
+            let read = IPSPatch::read_from(&mut data.as_slice()).unwrap();
+            let hunks: Vec<(u64, u64)> = read.hunks().iter().map(|x| (x.offset(), x.length())).collect();
+            assert_that!(hunks).is_equal_to(vec![(16, 65535), (16 + 65535, 70000 - 65535)]);
+            assert_that!(crate::patch::Patch::apply_to_vec(&read, &[]).unwrap()[16..].to_vec()).is_equal_to(payload);
+        }
+
+        #[test]
+        fn split_payloads_never_start_at_eof_marker() {
+            let eof = u32::from_u24_be_bytes(IPSPatch::EOF) as u64;
+            let payload: Vec<u8> = (0..70000u32).map(|x| x as u8).collect();
+            let patch = IPSPatch::new()
+                .with_hunk(IPSHunk::Regular(IPSRegularHunkData::new(eof - 65535, payload.clone())));
+            let data = patch.to_bytes().unwrap();
+
+            let read = IPSPatch::from_bytes(&data).unwrap();
+            let hunks: Vec<(u64, u64)> = read.hunks().iter().map(|x| (x.offset(), x.length())).collect();
+            assert_that!(hunks).is_equal_to(vec![(eof - 65535, 65534), (eof - 1, 70000 - 65534)]);
+            let patched = crate::patch::Patch::apply_to_vec(&read, &[]).unwrap();
+            assert_that!(patched[(eof - 65535) as usize..].to_vec()).is_equal_to(payload);
+        }
+
+        #[test]
+        fn hunks_at_eof_marker_are_rejected() {
+            let eof = u32::from_u24_be_bytes(IPSPatch::EOF) as u64;
+            let patch = IPSPatch::new().with_hunk(IPSHunk::RLE(IPSRLEHunkData { offset: eof, run_length: 1, payload: 0 }));
+            assert_that!(patch.write(&mut Vec::new())).is_err();
+        }
+
+        #[test]
+        fn unaddressable_offsets_are_rejected() {
+            let patch = IPSPatch::new()
+                .with_hunk(IPSHunk::RLE(IPSRLEHunkData { offset: IPSPatch::MAX_OFFSET + 1, run_length: 1, payload: 0 }));
+            assert_that!(patch.write(&mut Vec::new())).is_err();
+        }
+
+        #[test]
+        fn unaddressable_truncate_is_rejected() {
+            let patch = IPSPatch::new().with_truncate(IPSPatch::MAX_OFFSET + 1);
+            assert_that!(patch.write(&mut Vec::new())).is_err();
+        }
+
+        #[test]
+        fn payloads_running_past_the_last_offset_are_rejected() {
+            let patch = IPSPatch::new()
+                .with_hunk(IPSHunk::Regular(IPSRegularHunkData::new(IPSPatch::MAX_OFFSET, vec![0; 0x10000])));
+            assert_that!(patch.write(&mut Vec::new())).is_err();
+        }
+    }
+
+    mod ord_tests {
+        use std::collections::{BTreeSet, HashSet};
+
+        use super::*;
+
+        #[test]
+        fn hunks_are_ordered_by_offset_then_kind() {
+            let rle = IPSHunk::RLE(IPSRLEHunkData { offset: 2, run_length: 1, payload: 0 });
+            let regular = IPSHunk::Regular(IPSRegularHunkData::new(2, vec![0xFF]));
+            let first = IPSHunk::RLE(IPSRLEHunkData { offset: 1, run_length: 9, payload: 9 });
+            let set: BTreeSet<IPSHunk> = [rle.clone(), regular.clone(), first.clone(), rle.clone()].into_iter().collect();
+            assert_that!(set.into_iter().collect::<Vec<_>>()).is_equal_to(vec![first, regular, rle]);
+        }
+
+        #[test]
+        fn equal_patches_hash_equally() {
+            let patch = patch_with_multiple_hunks();
+            let set: HashSet<IPSPatch> = [patch.clone(), patch, EMPTY_PATCH].into_iter().collect();
+            assert_that!(set.len()).is_equal_to(2);
+        }
+    }
+
+    mod display_tests {
+        use super::*;
+
+        #[test]
+        fn hunks_are_summarized() {
+            let patch = patch_with_multiple_hunks();
+            assert_that!(patch.hunks()[0].to_string()).is_equal_to("regular hunk at 0x000102 writing 2 bytes".to_string());
+            assert_that!(format!("{:#}", patch.hunks()[0])).is_equal_to("regular hunk at 0x000102 writing 2 bytes\n  AA BB".to_string());
+            assert_that!(patch.hunks()[1].to_string()).is_equal_to("RLE hunk at 0x000102 writing 0xCC 43707 times".to_string());
+        }
+
+        #[test]
+        fn long_payloads_are_shortened() {
+            let hunk = IPSHunk::Regular(IPSRegularHunkData::new(0, vec![0xEA; 20]));
+            let expected = format!("regular hunk at 0x000000 writing 20 bytes\n  {} ...", vec!["EA"; 16].join(" "));
+            assert_that!(format!("{:#}", hunk)).is_equal_to(expected);
+        }
+
+        #[test]
+        fn patches_list_their_hunks() {
+            assert_that!(EMPTY_PATCH.to_string()).is_equal_to("IPS patch with 0 hunks writing 0 bytes".to_string());
+            let expected = "IPS patch with 2 hunks writing 43709 bytes, truncating to 32 bytes\n  \
+                0: regular hunk at 0x000102 writing 2 bytes\n    AA BB\n  \
+                1: RLE hunk at 0x000102 writing 0xCC 43707 times";
+            assert_that!(format!("{:#}", patch_with_multiple_hunks())).is_equal_to(expected.to_string());
+        }
+    }
+
+    mod bytes_tests {
+        use super::*;
+
+        #[test]
+        fn serialized_len_matches_written_len() {
+            let patches = [
+                EMPTY_PATCH,
+                patch_with_multiple_hunks(),
+                IPSPatch::new().with_hunk(IPSHunk::Regular(IPSRegularHunkData::new(0, vec![1; 70000]))),
+                IPSPatch::new().with_hunk(IPSHunk::Regular(IPSRegularHunkData::new(0x454F46 - 65535, vec![1; 2 * 65535]))),
+                IPSPatch::new().with_hunk(IPSHunk::Regular(IPSRegularHunkData::new(0, vec![]))),
+            ];
+            for patch in patches {
+                let mut data = Vec::new();
+                patch.write(&mut data).unwrap();
+                assert_that!(patch.serialized_len()).is_equal_to(data.len());
+                assert_that!(patch.to_bytes().unwrap()).is_equal_to(data);
+            }
+        }
+
+        #[test]
+        fn write_to_vec_appends_and_from_bytes_reads_back() {
+            let mut data = vec![0xAA];
+            patch_with_multiple_hunks().write_to_vec(&mut data).unwrap();
+            assert_that!(data[1..].to_vec()).is_equal_to(patch_with_multiple_hunks_data());
+            assert_that!(IPSPatch::from_bytes(&data[1..]).unwrap()).is_equal_to(patch_with_multiple_hunks());
+        }
     }
 
     mod read_tests {
@@ -568,6 +1859,471 @@ mod tests {
             let actual = IPSPatch::read_from(&mut patch_with_multiple_hunks_data().as_slice()).unwrap();
             assert_that!(actual).is_equal_to(patch_with_multiple_hunks());
         }
+
+        #[test]
+        fn read_concatenated_patches() {
+            let data = Vec::new()
+                .build_with_slice(&patch_with_multiple_hunks_data())
+                .build_with_slice(&patch_with_regular_hunk_data())
+                .build_with_slice(&patch_with_rle_hunk_data());
+            let actual = IPSPatch::read_all_from(&mut data.as_slice()).unwrap();
+            assert_that!(actual).is_equal_to(vec![patch_with_multiple_hunks(), patch_with_regular_hunk(), patch_with_rle_hunk()]);
+        }
+
+        #[test]
+        fn read_concatenated_patches_with_trailing_garbage() {
+            let data = Vec::new()
+                .build_with_slice(&patch_with_regular_hunk_data())
+                .build_with_slice(&[0, 0, 1, 2]);
+            let err = IPSPatch::read_all_from(&mut data.as_slice()).unwrap_err();
+            assert_that!(err.to_string()).is_equal_to("ParsingError: Unable to parse header.".to_string());
+        }
+
+        #[test]
+        fn apply_concatenated_patches_in_order() {
+            let patches = IPSPatch::read_all_from(&mut Vec::new()
+                .build_with_slice(&patch_with_regular_hunk_data())
+                .build_with_slice(&patch_with_truncate_data())
+                .as_slice()).unwrap();
+            let mut target = std::io::Cursor::new(vec![0; 512]);
+            IPSPatch::apply_concatenated(&patches, &mut target).unwrap();
+            let mut expected = std::io::Cursor::new(vec![0; 512]);
+            patch_with_regular_hunk().apply(&mut expected).unwrap();
+            patch_with_truncate().apply(&mut expected).unwrap();
+            assert_that!(target.into_inner()).is_equal_to(expected.into_inner());
+        }
+    }
+
+    mod create_tests {
+        use crate::create::DiffStrategy;
+        use crate::patch::Patch;
+
+        use super::*;
+
+        #[test]
+        fn creating_from_equal_roms_creates_an_empty_patch() {
+            assert_that!(IPSPatch::create(&[1, 2, 3], &[1, 2, 3]).unwrap()).is_equal_to(EMPTY_PATCH);
+        }
+
+        #[test]
+        fn nearby_changes_are_merged() {
+            let patch = IPSPatch::create(&[0; 16], &[1, 0, 0, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 3, 0, 0]).unwrap();
+            let ranges: Vec<(u64, u64)> = patch.hunks().iter().map(|x| (x.offset(), x.length())).collect();
+            assert_that!(ranges).is_equal_to(vec![(0, 7), (13, 1)]);
+        }
+
+        #[test]
+        fn runs_of_one_byte_become_rle_hunks() {
+            let patch = IPSPatch::create(&[0; 8], &[0, 7, 7, 7, 7, 0, 0, 0]).unwrap();
+            assert_that!(patch.hunks()).is_equal_to(&[IPSHunk::RLE(IPSRLEHunkData {
+                offset: 1,
+                run_length: 4,
+                payload: 7,
+            })][..]);
+        }
+
+        #[test]
+        fn long_changes_are_split() {
+            let target = vec![1; 0x20000];
+            let patch = IPSPatch::create(&[], &target).unwrap();
+            let lengths: Vec<u64> = patch.hunks().iter().map(|x| x.length()).collect();
+            assert_that!(lengths).is_equal_to(vec![0xFFFF, 0xFFFF, 2]);
+        }
+
+        #[test]
+        fn hunks_never_start_at_eof_marker() {
+            let eof = u32::from_u24_be_bytes(IPSPatch::EOF) as usize;
+            let source = vec![0; eof + 4];
+            let mut target = source.clone();
+            target[eof] = 1;
+            let patch = IPSPatch::create(&source, &target).unwrap();
+            assert_that!(patch.hunks()[0].offset()).is_equal_to(eof as u64 - 1);
+
+            let mut data = Vec::new();
+            patch.write(&mut data).unwrap();
+            let mut patched = std::io::Cursor::new(source);
+            apply_ips_patch(&mut data.as_slice(), &mut patched).unwrap();
+            assert_that!(patched.into_inner()).is_equal_to(target);
+        }
+
+        #[test]
+        fn size_optimization_splits_mixed_payloads() {
+            let target = [5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 1, 2, 3, 9, 9, 9, 9, 9, 9, 9, 9, 9];
+            let diff = Diff::new(&[0; 22], &target);
+            let patch = IPSPatch::from_diff_with_optimization(&diff, &target, IPSOptimization::Size).unwrap();
+            let hunks: Vec<(u64, u64, bool)> = patch.hunks().iter()
+                .map(|x| (x.offset(), x.length(), matches!(x, IPSHunk::RLE(_))))
+                .collect();
+            assert_that!(hunks).is_equal_to(vec![(0, 10, true), (10, 3, false), (13, 9, true)]);
+            assert_that!(patch.apply_to_vec(&[0; 22]).unwrap()).is_equal_to(target.to_vec());
+        }
+
+        #[test]
+        fn size_optimization_is_never_larger() {
+            for fixture in crate::testkit::corpus(0..8, &crate::testkit::FixtureOptions::default()) {
+                let diff = Diff::new(&fixture.source, &fixture.target);
+                let basic = IPSPatch::from_diff(&diff, &fixture.target).unwrap();
+                let size = IPSPatch::from_diff_with_optimization(&diff, &fixture.target, IPSOptimization::Size).unwrap();
+                assert_that!(size.serialized_len()).is_less_than_or_equal_to(basic.serialized_len());
+                assert_that!(size.apply_to_vec(&fixture.source).unwrap()).is_equal_to(fixture.target.clone());
+            }
+        }
+
+        #[test]
+        fn size_optimization_never_splits_at_eof_marker() {
+            let eof = u32::from_u24_be_bytes(IPSPatch::EOF) as u64;
+            let payload = [1, 2, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7];
+            let hunks = IPSPatch::create_smallest_hunks(eof - 2, &payload, eof);
+            assert_that!(hunks.iter().all(|x| x.offset() != eof)).is_true();
+            let mut target = vec![0; payload.len()];
+            hunks.iter().for_each(|x| x.overlay(eof - 2, &mut target));
+            assert_that!(target).is_equal_to(payload.to_vec());
+        }
+
+        #[test]
+        fn changes_beyond_16_mib_fail() {
+            let diff = Diff {
+                source_len: 0x2000000,
+                target_len: 0x2000000,
+                regions: vec![0x1000000..0x1000001, 0x1000002..0x1000003],
+                strategy: DiffStrategy::Exact,
+            };
+            let err = IPSPatch::from_diff(&diff, &vec![0; 0x2000000]).unwrap_err();
+            assert_that!(err.to_string()).is_equal_to("CreatingError: IPS can't address offset 16777216.".to_string());
+        }
+    }
+
+    mod validate_tests {
+        use std::io::Cursor;
+
+        use crate::diagnostics::Severity;
+
+        use super::*;
+
+        fn rle(offset: u64, run_length: u16) -> IPSHunk {
+            IPSHunk::RLE(IPSRLEHunkData { offset, run_length, payload: 0xFF })
+        }
+
+        #[test]
+        fn patch_without_truncate_is_valid() {
+            assert_that!(IPSPatch::new().with_hunk(rle(8, 8)).validate()).is_empty();
+        }
+
+        #[test]
+        fn hunks_past_truncate_are_reported() {
+            let patch = IPSPatch::new()
+                .with_hunk(rle(0, 4))
+                .with_hunk(rle(6, 4))
+                .with_hunk(rle(12, 2))
+                .with_truncate(8);
+            assert_that!(patch.truncated_hunks()).is_equal_to(vec![1, 2]);
+            let diagnostics = patch.validate();
+            assert_that!(diagnostics.iter().map(|x| x.hunk).collect::<Vec<_>>()).is_equal_to(vec![Some(1), Some(2)]);
+            assert_that!(diagnostics[0].severity).is_equal_to(Severity::Error);
+        }
+
+        #[test]
+        fn hunks_ending_at_truncate_are_valid() {
+            assert_that!(IPSPatch::new().with_hunk(rle(4, 4)).with_truncate(8).validate()).is_empty();
+        }
+
+        #[test]
+        fn deny_refuses_to_apply_truncated_hunks() {
+            let patch = IPSPatch::new().with_hunk(rle(6, 4)).with_truncate(8);
+            let mut target = Cursor::new(vec![0; 16]);
+            let result = patch.apply_with_options(&mut target, &ApplyOptions::new());
+            assert_that!(result).is_err();
+            assert_that!(target.into_inner()).is_equal_to(vec![0; 16]);
+        }
+
+        #[test]
+        fn warn_applies_and_reports_truncated_hunks() {
+            let patch = IPSPatch::new().with_hunk(rle(6, 4)).with_truncate(8);
+            let mut target = Cursor::new(vec![0; 16]);
+            let options = ApplyOptions::new().with_truncate_check(TruncateCheck::Warn);
+            let diagnostics = patch.apply_with_options(&mut target, &options).unwrap();
+            assert_that!(diagnostics.len()).is_equal_to(1);
+            assert_that!(target.into_inner()).is_equal_to(vec![0, 0, 0, 0, 0, 0, 0xFF, 0xFF]);
+        }
+
+        #[test]
+        fn skipping_copier_header_shifts_the_patch() {
+            let patch = IPSPatch::new().with_hunk(rle(0, 2));
+            let options = ApplyOptions::new().with_header(HeaderHandling::SkipCopierHeader);
+
+            let mut headered = Cursor::new(vec![0; 1024 + 512]);
+            patch.apply_with_options(&mut headered, &options).unwrap();
+            assert_that!(headered.into_inner()[510..514].to_vec()).is_equal_to(vec![0, 0, 0xFF, 0xFF]);
+
+            let mut headerless = Cursor::new(vec![0; 1024]);
+            patch.apply_with_options(&mut headerless, &options).unwrap();
+            assert_that!(headerless.into_inner()[0..3].to_vec()).is_equal_to(vec![0xFF, 0xFF, 0]);
+        }
+
+        #[test]
+        fn ignore_applies_silently() {
+            let patch = IPSPatch::new().with_hunk(rle(6, 4)).with_truncate(8);
+            let mut target = Cursor::new(vec![0; 16]);
+            let options = ApplyOptions::new().with_truncate_check(TruncateCheck::Ignore);
+            assert_that!(patch.apply_with_options(&mut target, &options).unwrap()).is_empty();
+        }
+
+        #[test]
+        fn hunks_past_max_offset_are_refused() {
+            let patch = IPSPatch::new().with_hunk(rle(4, 4));
+            let mut target = Cursor::new(vec![0; 16]);
+            let err = patch.apply_with_options(&mut target, &ApplyOptions::new().with_max_offset(Some(6))).unwrap_err();
+            assert_that!(err.to_string())
+                .is_equal_to("ValidationError: The patch writes to offset 7, past the maximum offset 6.".to_string());
+            assert_that!(target.get_ref()).is_equal_to(&vec![0; 16]);
+
+            patch.apply_with_options(&mut target, &ApplyOptions::new().with_max_offset(Some(7))).unwrap();
+            assert_that!(target.into_inner()[4..8].to_vec()).is_equal_to(vec![0xFF; 4]);
+            let options = ApplyOptions::new().with_max_offset(Some(0));
+            assert_that!(apply_multi_with_options(&patch, &mut [0u8; 8].as_slice(), &mut [], &options)).is_err();
+        }
+
+        #[test]
+        fn keep_going_reports_every_failed_hunk() {
+            let patch = IPSPatch::new().with_hunk(rle(12, 2)).with_hunk(rle(0, 2)).with_hunk(rle(6, 4)).with_truncate(8);
+            let mut target = Cursor::new(vec![0; 16]);
+            let options = ApplyOptions::new().with_max_offset(Some(10)).with_keep_going(true);
+            let diagnostics = patch.apply_with_options(&mut target, &options).unwrap();
+            let failed: Vec<(Option<usize>, Option<DiagnosticCode>)> = diagnostics.iter().map(|x| (x.hunk, x.code)).collect();
+            assert_that!(failed).is_equal_to(vec![
+                (Some(0), Some(DiagnosticCode::FailedHunk)),
+                (Some(2), Some(DiagnosticCode::FailedHunk)),
+                (Some(0), Some(DiagnosticCode::FailedHunk)),
+            ]);
+            assert_that!(diagnostics[1].message.as_str()).contains("cut off by the truncation");
+            assert_that!(target.into_inner()).is_equal_to(vec![0xFF, 0xFF, 0, 0, 0, 0, 0, 0]);
+
+            let mut target = Cursor::new(vec![0; 16]);
+            let options = options.with_truncate_check(TruncateCheck::Warn);
+            let diagnostics = patch.apply_with_options(&mut target, &options).unwrap();
+            assert_that!(diagnostics[1].code).is_equal_to(Some(DiagnosticCode::TruncatedHunk));
+            assert_that!(target.into_inner()).is_equal_to(vec![0xFF, 0xFF, 0, 0, 0, 0, 0xFF, 0xFF]);
+        }
+
+        #[test]
+        fn duplicate_writes_follow_the_policy() {
+            let patch = IPSPatch::new()
+                .with_hunk(IPSHunk::Regular(IPSRegularHunkData::new(1, vec![1, 2, 3])))
+                .with_hunk(rle(3, 3))
+                .with_hunk(IPSHunk::Regular(IPSRegularHunkData::new(0, vec![0, 1])));
+            let apply = |policy: DuplicateWrites| {
+                let mut target = Cursor::new(vec![0; 4]);
+                let options = ApplyOptions::new().with_duplicate_writes(policy).with_gap_fill(0xEE);
+                patch.apply_with_options(&mut target, &options).map(|x| (target.into_inner(), x))
+            };
+            let (patched, diagnostics) = apply(DuplicateWrites::LastWins).unwrap();
+            assert_that!(patched).is_equal_to(vec![0, 1, 2, 0xFF, 0xFF, 0xFF]);
+            assert_that!(diagnostics.iter().map(|x| (x.hunk, x.code)).collect::<Vec<_>>())
+                .is_equal_to(vec![(Some(1), Some(DiagnosticCode::DuplicateWrite))]);
+            assert_that!(diagnostics[0].message).is_equal_to("Hunk at offset 3 writes different data than hunk 0 to offset 3.".to_string());
+
+            let (patched, _) = apply(DuplicateWrites::FirstWins).unwrap();
+            assert_that!(patched).is_equal_to(vec![0, 1, 2, 3, 0xFF, 0xFF]);
+            assert_that!(apply(DuplicateWrites::Error).unwrap_err().kind()).matches(|x| matches!(x, ValidationError));
+
+            let mut target = Cursor::new(vec![0; 4]);
+            let options = ApplyOptions::new().with_duplicate_writes(DuplicateWrites::Error).with_keep_going(true);
+            let diagnostics = patch.apply_with_options(&mut target, &options).unwrap();
+            assert_that!(diagnostics[0].code).is_equal_to(Some(DiagnosticCode::FailedHunk));
+            assert_that!(target.into_inner()).is_equal_to(vec![0, 1, 2, 3]);
+        }
+
+        #[test]
+        fn gaps_are_filled_with_gap_fill() {
+            let patch = IPSPatch::new().with_hunk(rle(4, 2)).with_hunk(rle(1, 1));
+            let mut target = Cursor::new(vec![1; 2]);
+            let options = ApplyOptions::new().with_gap_fill(0xEE);
+            patch.apply_with_options(&mut target, &options).unwrap();
+            assert_that!(target.into_inner()).is_equal_to(vec![1, 0xFF, 0xEE, 0xEE, 0xFF, 0xFF]);
+        }
+
+        #[test]
+        fn presets_fix_checksums() {
+            let mut rom = vec![0; 0xC0];
+            rom[0xB2] = 0x96;
+            let patch = IPSPatch::new().with_hunk(rle(0xA0, 1));
+            let options = ApplyOptions::preset(crate::console::Console::GameBoyAdvance);
+            let (patched, _) = crate::patch::Patch::apply_to_vec_with_options(&patch, &rom, &options).unwrap();
+            assert_that!(patched[0xBD]).is_equal_to(0x52);
+            let (unfixed, _) = crate::patch::Patch::apply_to_vec_with_options(&patch, &rom, &ApplyOptions::new()).unwrap();
+            assert_that!(unfixed[0xBD]).is_equal_to(0);
+        }
+    }
+
+    mod mutation_tests {
+        use super::*;
+
+        fn rle(offset: u64, run_length: u16) -> IPSHunk {
+            IPSHunk::RLE(IPSRLEHunkData {
+                offset,
+                run_length,
+                payload: 0xFF,
+            })
+        }
+
+        #[test]
+        fn accessors_expose_hunks_and_truncate() {
+            let patch = patch_with_multiple_hunks();
+            assert_that!(patch.hunks().len()).is_equal_to(2);
+            assert_that!(patch.truncate()).is_equal_to(Some(32));
+        }
+
+        #[test]
+        fn set_truncate_can_clear_truncate() {
+            let mut patch = patch_with_truncate();
+            patch.set_truncate(None);
+            assert_that!(patch).is_equal_to(EMPTY_PATCH);
+        }
+
+        #[test]
+        fn insert_sorted_keeps_hunks_ordered_by_offset() {
+            let mut patch = IPSPatch::new();
+            patch.insert_sorted(rle(8, 1));
+            patch.insert_sorted(rle(0, 1));
+            patch.insert_sorted(rle(4, 1));
+            let offsets: Vec<u64> = patch.hunks().iter().map(|x| x.offset()).collect();
+            assert_that!(offsets).is_equal_to(vec![0, 4, 8]);
+            assert_that!(patch.is_sorted()).is_true();
+        }
+
+        #[test]
+        fn insert_sorted_places_hunk_after_hunks_with_same_offset() {
+            let mut patch = IPSPatch::new();
+            patch.insert_sorted(rle(4, 1));
+            patch.insert_sorted(rle(4, 2));
+            assert_that!(patch.hunks()[1].length()).is_equal_to(2);
+        }
+
+        #[test]
+        fn insert_sorted_checked_rejects_overlapping_hunks() {
+            let mut patch = IPSPatch::new();
+            patch.insert_sorted_checked(rle(4, 4)).unwrap();
+            patch.insert_sorted_checked(rle(8, 4)).unwrap();
+            let result = patch.insert_sorted_checked(rle(6, 4));
+            let err = assert_that!(result)
+                .is_err()
+                .subject;
+            assert_that!(err.to_string())
+                .is_equal_to("ValidationError: Hunk at offset 6 overlaps hunk 0.".to_string());
+            assert_that!(patch.hunks().len()).is_equal_to(2);
+        }
+
+        #[test]
+        fn remove_and_retain_hunks_keep_order() {
+            let mut patch = IPSPatch::new()
+                .with_hunk(rle(8, 1))
+                .with_hunk(rle(0, 1))
+                .with_hunk(rle(4, 1));
+            assert_that!(patch.remove_hunk(0)).is_equal_to(rle(8, 1));
+            patch.retain_hunks(|x| x.offset() != 0);
+            assert_that!(patch.into_hunks()).is_equal_to(vec![rle(4, 1)]);
+        }
+
+        #[test]
+        fn patches_collect_extend_and_iterate_hunks() {
+            let mut patch: IPSPatch = [rle(8, 1), rle(0, 1)].into_iter().collect();
+            patch.extend(vec![rle(4, 1)]);
+            let offsets: Vec<u64> = (&patch).into_iter().map(|x| x.offset()).collect();
+            assert_that!(offsets).is_equal_to(vec![8, 0, 4]);
+
+            let shifted: IPSPatch = patch.into_iter()
+                .filter(|x| x.offset() > 0)
+                .map(|x| IPSHunk::RLE(IPSRLEHunkData { offset: x.offset() + 1, run_length: 1, payload: 0xFF }))
+                .collect();
+            assert_that!(shifted.into_hunks()).is_equal_to(vec![rle(9, 1), rle(5, 1)]);
+        }
+
+        #[test]
+        fn redact_payloads_keeps_structure() {
+            let redacted = patch_with_multiple_hunks().redact_payloads();
+            let mut data = Vec::new();
+            redacted.write(&mut data).unwrap();
+            let mut expected = patch_with_multiple_hunks_data();
+            expected[10..12].copy_from_slice(&[0, 0]); // regular payload
+            expected[19] = 0; // rle payload
+            assert_that!(data).is_equal_to(expected);
+        }
+    }
+
+    mod index_tests {
+        use std::io::Cursor;
+
+        use super::*;
+
+        fn regular(offset: u64, payload: &[u8]) -> IPSHunk {
+            IPSHunk::Regular(IPSRegularHunkData {
+                offset,
+                payload: payload.into(),
+            })
+        }
+
+        #[test]
+        fn hunks_covering_returns_hunks_in_apply_order() {
+            let patch = IPSPatch::new()
+                .with_hunk(regular(4, &[1, 2, 3, 4]))
+                .with_hunk(regular(0, &[5, 6, 7, 8, 9, 10]))
+                .with_hunk(regular(12, &[11]));
+            let indexed = patch.indexed();
+            let covering = indexed.hunks_covering(5);
+            assert_that!(covering).is_equal_to(vec![&patch.hunks()[0], &patch.hunks()[1]]);
+            assert_that!(indexed.hunks_covering(10)).is_empty();
+        }
+
+        #[test]
+        fn conflicts_lists_overlapping_pairs() {
+            let patch = IPSPatch::new()
+                .with_hunk(regular(4, &[1, 2, 3, 4]))
+                .with_hunk(regular(0, &[5, 6, 7, 8, 9]))
+                .with_hunk(regular(8, &[11]))
+                .with_hunk(regular(7, &[12, 13]));
+            assert_that!(patch.indexed().conflicts()).is_equal_to(vec![(0, 1), (0, 3), (2, 3)]);
+        }
+
+        #[test]
+        fn hunks_at_the_end_of_the_address_space_do_not_wrap() {
+            let last = IPSHunk::RLE(IPSRLEHunkData { offset: u64::MAX - 1, run_length: 0xFFFF, payload: 0 });
+            let first = regular(0, &[1]);
+            assert_that!(last.end()).is_equal_to(u64::MAX);
+            assert_that!(last.overlaps(&first)).is_false();
+            assert_that!(IPSPatch::new().with_hunk(last).patched_len(0)).is_equal_to(u64::MAX);
+        }
+
+        #[test]
+        fn patched_len_accounts_for_extension_and_truncate() {
+            let patch = IPSPatch::new().with_hunk(regular(14, &[1, 2, 3, 4]));
+            assert_that!(patch.patched_len(16)).is_equal_to(18);
+            assert_that!(patch.with_truncate(8).patched_len(16)).is_equal_to(8);
+        }
+
+        #[test]
+        fn apply_range_matches_apply() {
+            let base: Vec<u8> = (0..32).collect();
+            let patch = IPSPatch::new()
+                .with_hunk(regular(4, &[0xA1, 0xA2, 0xA3, 0xA4]))
+                .with_hunk(IPSHunk::RLE(IPSRLEHunkData {
+                    offset: 6,
+                    run_length: 4,
+                    payload: 0xCC,
+                }))
+                .with_hunk(regular(30, &[0xB1, 0xB2, 0xB3, 0xB4]));
+            let mut expected = Cursor::new(base.clone());
+            patch.apply(&mut expected).unwrap();
+            let expected = expected.into_inner();
+
+            let mut source = Cursor::new(base.clone());
+            for start in 0..36u64 {
+                let actual = patch.apply_range(&mut source, start..start + 5).unwrap();
+                let from = (start as usize).min(expected.len());
+                let to = (start as usize + 5).min(expected.len());
+                assert_that!(actual.as_slice()).is_equal_to(&expected[from..to]);
+            }
+            assert_that!(source.get_ref()).is_equal_to(&base);
+        }
     }
 
     mod apply_tests {
@@ -575,6 +2331,46 @@ mod tests {
 
         use super::*;
 
+        #[test]
+        fn apply_selected_skips_other_hunks() {
+            let mut target = Cursor::new(vec![0; 8]);
+            let patch = IPSPatch::new()
+                .with_hunk(IPSHunk::RLE(IPSRLEHunkData { offset: 0, run_length: 2, payload: 1 }))
+                .with_hunk(IPSHunk::RLE(IPSRLEHunkData { offset: 2, run_length: 2, payload: 2 }))
+                .with_hunk(IPSHunk::RLE(IPSRLEHunkData { offset: 4, run_length: 2, payload: 3 }));
+            patch.apply_where(&mut target, |i, _| i != 1).unwrap();
+            assert_that!(target.into_inner()).is_equal_to(vec![1, 1, 0, 0, 3, 3, 0, 0]);
+        }
+
+        #[test]
+        fn apply_selected_rejects_unknown_hunks() {
+            let mut target = Cursor::new(vec![0; 8]);
+            let result = patch_with_rle_hunk().apply_selected(&mut target, &[0, 1]);
+            assert_that!(result).is_err();
+            assert_that!(target.into_inner()).is_equal_to(vec![0; 8]);
+        }
+
+        #[test]
+        fn apply_in_place_matches_apply() {
+            let fixture = crate::testkit::Fixture::generate(3);
+            let mut buf = fixture.source.clone();
+            buf.resize(fixture.source.len().max(fixture.target.len()), 0);
+            let len = fixture.ips.apply_in_place(&mut buf).unwrap();
+            assert_that!(buf[..len].to_vec()).is_equal_to(fixture.target);
+        }
+
+        #[test]
+        fn apply_in_place_refuses_to_grow_the_buffer() {
+            let mut buf = [1; 4];
+            let patch = IPSPatch::new()
+                .with_hunk(IPSHunk::RLE(IPSRLEHunkData { offset: 0, run_length: 1, payload: 0 }))
+                .with_hunk(IPSHunk::RLE(IPSRLEHunkData { offset: 3, run_length: 2, payload: 0 }));
+            let err = patch.apply_in_place(&mut buf).unwrap_err();
+            assert_that!(err.to_string())
+                .is_equal_to("PatchingError: The patch writes to offset 4, past the end of the 4 byte buffer.".to_string());
+            assert_that!(buf).is_equal_to([1; 4]);
+        }
+
         #[test]
         fn apply_empty_patch_does_nothing_to_input() {
             let base: Vec<u8> = (0..16).collect();
@@ -591,7 +2387,6 @@ mod tests {
             let patch = IPSPatch::new()
                 .with_hunk(IPSHunk::Regular(IPSRegularHunkData {
                     offset: 1,
-                    length: 3,
                     payload: Box::new([0xa, 0xb, 0xc]),
                 }));
             let expected = vec![0x0, 0xa, 0xb, 0xc, 0x4, 0x5, 0x6, 0x7, 0x8, 0x9, 0xA, 0xB, 0xC, 0xD, 0xE, 0xF];
@@ -632,6 +2427,114 @@ mod tests {
         }
     }
 
+    mod apply_multi_tests {
+        use std::io::Cursor;
+
+        use crate::testkit::{corpus, FixtureOptions};
+
+        use super::*;
+
+        #[test]
+        fn every_output_receives_the_patched_file() {
+            for fixture in corpus(0..4, &FixtureOptions::default()) {
+                let mut first = Vec::new();
+                let mut second = Cursor::new(Vec::new());
+                let len = apply_multi(&fixture.ips, &mut fixture.source.as_slice(), &mut [&mut first, &mut second]).unwrap();
+                assert_that!(len).is_equal_to(fixture.target.len() as u64);
+                assert_that!(first).is_equal_to(&fixture.target);
+                assert_that!(second.into_inner()).is_equal_to(&fixture.target);
+            }
+        }
+
+        #[test]
+        fn sources_spanning_multiple_chunks_are_patched() {
+            let options = FixtureOptions {
+                source_size: STREAM_CHUNK_SIZE * 3 + 17,
+                truncate: true,
+                ..FixtureOptions::default()
+            };
+            for fixture in corpus(0..4, &options) {
+                let mut output = Vec::new();
+                apply_multi(&fixture.ips, &mut fixture.source.as_slice(), &mut [&mut output]).unwrap();
+                assert_that!(output).is_equal_to(&fixture.target);
+            }
+        }
+
+        #[test]
+        fn hunks_past_the_end_extend_output() {
+            let patch = IPSPatch::new()
+                .with_hunk(IPSHunk::RLE(IPSRLEHunkData {
+                    offset: 6,
+                    run_length: 2,
+                    payload: 0xFF,
+                }));
+            let mut output = Vec::new();
+            apply_multi(&patch, &mut [1u8, 2].as_slice(), &mut [&mut output]).unwrap();
+            assert_that!(output).is_equal_to(vec![1, 2, 0, 0, 0, 0, 0xFF, 0xFF]);
+        }
+
+        #[test]
+        fn streamed_gaps_are_filled_with_gap_fill() {
+            let patch = IPSPatch::new()
+                .with_hunk(IPSHunk::RLE(IPSRLEHunkData {
+                    offset: 4,
+                    run_length: 1,
+                    payload: 0,
+                }));
+            let mut output = Vec::new();
+            let options = ApplyOptions::new().with_gap_fill(0xFF);
+            let (len, _) = apply_multi_with_options(&patch, &mut [1u8].as_slice(), &mut [&mut output], &options).unwrap();
+            assert_that!(len).is_equal_to(5);
+            assert_that!(output).is_equal_to(vec![1, 0xFF, 0xFF, 0xFF, 0]);
+        }
+
+        #[test]
+        fn streamed_duplicate_writes_follow_the_policy() {
+            let patch = IPSPatch::new()
+                .with_hunk(IPSHunk::Regular(IPSRegularHunkData::new(1, vec![1, 2, 3])))
+                .with_hunk(IPSHunk::RLE(IPSRLEHunkData { offset: 3, run_length: 3, payload: 0xFF }));
+            let stream = |policy: DuplicateWrites| {
+                let mut output = Vec::new();
+                let options = ApplyOptions::new().with_duplicate_writes(policy);
+                apply_multi_with_options(&patch, &mut [0u8; 4].as_slice(), &mut [&mut output], &options)
+                    .map(|(_, diagnostics)| (output, diagnostics))
+            };
+            let (output, diagnostics) = stream(DuplicateWrites::LastWins).unwrap();
+            assert_that!(output).is_equal_to(vec![0, 1, 2, 0xFF, 0xFF, 0xFF]);
+            assert_that!(diagnostics[0].code).is_equal_to(Some(DiagnosticCode::DuplicateWrite));
+            let (output, _) = stream(DuplicateWrites::FirstWins).unwrap();
+            assert_that!(output).is_equal_to(vec![0, 1, 2, 3, 0xFF, 0xFF]);
+            assert_that!(stream(DuplicateWrites::Error).unwrap_err().kind()).matches(|x| matches!(x, ValidationError));
+        }
+
+        #[test]
+        fn keep_going_streams_the_hunks_that_pass() {
+            let patch = IPSPatch::new()
+                .with_hunk(IPSHunk::RLE(IPSRLEHunkData { offset: 6, run_length: 2, payload: 0xFF }))
+                .with_hunk(IPSHunk::RLE(IPSRLEHunkData { offset: 1, run_length: 1, payload: 0xFF }));
+            let options = ApplyOptions::new().with_max_offset(Some(4));
+            let mut output = Vec::new();
+            assert_that!(apply_multi_with_options(&patch, &mut [0u8; 4].as_slice(), &mut [&mut output], &options)).is_err();
+
+            let mut output = Vec::new();
+            let options = options.with_keep_going(true);
+            let (len, diagnostics) = apply_multi_with_options(&patch, &mut [0u8; 4].as_slice(), &mut [&mut output], &options).unwrap();
+            assert_that!(len).is_equal_to(4);
+            assert_that!(output).is_equal_to(vec![0, 0xFF, 0, 0]);
+            assert_that!(diagnostics.iter().map(|x| (x.hunk, x.code)).collect::<Vec<_>>())
+                .is_equal_to(vec![(Some(0), Some(DiagnosticCode::FailedHunk))]);
+        }
+
+        #[test]
+        fn truncate_limits_output() {
+            let patch = IPSPatch::new().with_truncate(3);
+            let mut output = Vec::new();
+            let len = apply_multi(&patch, &mut [1u8, 2, 3, 4, 5].as_slice(), &mut [&mut output]).unwrap();
+            assert_that!(len).is_equal_to(3);
+            assert_that!(output).is_equal_to(vec![1, 2, 3]);
+        }
+    }
+
     mod stream_apply_ips_patch_tests {
         use std::io::Cursor;
 
@@ -682,7 +2585,6 @@ mod tests {
             let patch = IPSPatch::new()
                 .with_hunk(IPSHunk::Regular(IPSRegularHunkData {
                     offset: 1,
-                    length: 3,
                     payload: Box::new([0xa, 0xb, 0xc]),
                 }));
             let mut patch_data = Vec::new();
@@ -736,7 +2638,6 @@ mod tests {
                 }))
                 .with_hunk(IPSHunk::Regular(IPSRegularHunkData {
                     offset: 4,
-                    length: 3,
                     payload: Box::new([0xb, 0xc, 0xd])
                 }));
             let mut patch_data = Vec::new();
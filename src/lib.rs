@@ -1,7 +1,81 @@
+#![allow(clippy::needless_return, clippy::upper_case_acronyms)]
+
 pub mod ips;
+pub mod pmsr;
+pub mod ups;
+pub mod bps;
+pub mod ppf;
+pub mod vcdiff;
+pub mod dldi;
+pub mod cheat;
+pub mod console;
+pub mod detect;
+pub mod diagnostics;
+#[cfg(feature = "disasm")]
+pub mod disasm;
+#[cfg(feature = "eventlog")]
+pub mod eventlog;
+pub mod explain;
+pub mod extract;
+pub mod facade;
+pub mod feed;
+#[cfg(feature = "http")]
+pub mod fetch;
+pub mod fingerprint;
+pub mod groups;
+pub mod header;
+pub mod hunk;
+pub mod compression;
+pub mod apply;
+pub mod audit;
+pub mod badblock;
+pub mod batch;
+pub mod cache;
+pub mod capture;
+pub mod catalog;
+pub mod chain;
+pub mod codec;
+#[cfg(feature = "config")]
+pub mod config;
+#[cfg(any(test, feature = "testkit"))]
+pub mod conformance;
+#[cfg(feature = "json")]
+pub mod commands;
+pub mod create;
+pub mod messages;
+pub mod metrics;
+pub mod naming;
+pub mod outcome;
+pub mod overlay;
+pub mod record;
+pub mod patch;
+pub mod progress;
+pub mod report;
+pub mod sanitize;
+pub mod scheduler;
+pub mod selftest;
+pub mod symbols;
+#[cfg(any(test, feature = "dev-tools"))]
+pub mod synthetic;
+pub mod temp;
+#[cfg(feature = "server")]
+pub mod server;
+pub mod registry;
+pub mod verbatim;
+pub mod verify;
+pub mod versions;
+pub mod vfs;
+pub mod view;
+pub mod watch;
+pub mod watermark;
+pub mod hash;
 mod err;
+mod index;
 #[cfg(test)]
 mod test_util;
-mod io_util;
+pub mod io_util;
+#[cfg(any(test, feature = "testkit"))]
+pub mod testkit;
 
-pub use err::*;
\ No newline at end of file
+pub use err::*;
+pub use selftest::self_test;
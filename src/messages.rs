@@ -0,0 +1,256 @@
+//! The catalog of user-facing messages, so frontends can show them translated.
+//!
+//! Errors constructed with [Error::with_message] keep their [Message] next to the English
+//! description. A [Localizer] renders errors and diagnostics in another language from their
+//! structured data instead of matching on their [Display](std::fmt::Display) output; messages it
+//! doesn't know about fall back to the English description.
+//!
+//! # Examples
+//!
+//! ```
+//! use rom_patcher::{Error, ErrorKind};
+//! use rom_patcher::messages::{English, Localizer, Message};
+//!
+//! struct German;
+//!
+//! impl Localizer for German {
+//!     fn message(&self, message: &Message) -> String {
+//!         match message {
+//!             Message::Cancelled => "Der Vorgang wurde abgebrochen.".to_string(),
+//!             _ => English.message(message),
+//!         }
+//!     }
+//! }
+//!
+//! let error = Error::new(ErrorKind::Cancelled).with_message(Message::Cancelled);
+//! assert_eq!(error.to_string(), "Cancelled: Operation was cancelled.");
+//! assert_eq!(error.localized(&German), "Cancelled: Der Vorgang wurde abgebrochen.");
+//! ```
+
+use std::path::PathBuf;
+
+use crate::detect::Confidence;
+use crate::diagnostics::{Diagnostic, Severity};
+use crate::fingerprint::Fingerprint;
+use crate::{Error, ErrorKind};
+
+/// A user-facing message with the data it is about.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Message {
+    /// an operation was cancelled, see [crate::progress::CancellationToken].
+    Cancelled,
+    /// patch data is not of any known format.
+    UnknownFormat,
+    /// the rom already has a patch of `format` applied, detected with `confidence`.
+    AlreadyApplied { format: String, confidence: Confidence },
+    /// the source rom has SHA-1 `actual` instead of `expected`, both as hex. `copier_header` is
+    /// whether the rom without its copier header has the expected SHA-1, `fingerprint` what its
+    /// headers say it is.
+    SourceChecksumMismatch { actual: String, expected: String, copier_header: bool, fingerprint: Option<Fingerprint> },
+    /// the patched output has SHA-1 `actual` instead of `expected`, both as hex.
+    OutputChecksumMismatch { actual: String, expected: String },
+    /// a hunk at `offset` overlaps the hunk at index `existing`.
+    OverlappingHunk { offset: u64, existing: usize },
+    /// hunk `index` was selected of a patch with only `count` hunks.
+    MissingHunk { index: usize, count: usize },
+    /// `offset` lies beyond what patches of `format` can address.
+    BeyondFormatLimit { format: String, offset: u64 },
+    /// a patch writes to `offset` although it may only write up to `max_offset`, see
+    /// [ApplyOptions::with_max_offset](crate::apply::ApplyOptions::with_max_offset).
+    BeyondMaxOffset { offset: u64, max_offset: u64 },
+    /// a patch writes to `offset` past the end of a fixed-size buffer of `len` bytes.
+    BeyondBuffer { offset: u64, len: u64 },
+    /// the file at `path` couldn't be read.
+    UnableToRead { path: PathBuf },
+    /// the file at `path` couldn't be written.
+    UnableToWrite { path: PathBuf },
+}
+
+/// Advice for users on how to resolve an error, see [Error::suggestion].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Suggestion {
+    /// the rom has a copier header the patch wasn't made for.
+    StripCopierHeader,
+    /// the rom already has the patch applied.
+    AlreadyPatched,
+    /// the patch was made for the rom with SHA-1 `expected`, as hex.
+    UseMatchingRom { expected: String },
+    /// the patch or its published checksum is likely corrupt.
+    DownloadAgain,
+    /// the file is not a patch of any of the `formats`.
+    UseSupportedFormat { formats: Vec<String> },
+    /// the file at `path` doesn't exist or isn't accessible.
+    CheckPath { path: PathBuf },
+}
+
+impl Suggestion {
+    /// returns the suggestion for an error with `message`, if there is one.
+    pub fn for_message(message: &Message) -> Option<Suggestion> {
+        let suggestion = match message {
+            Message::SourceChecksumMismatch { copier_header: true, .. } => Suggestion::StripCopierHeader,
+            Message::SourceChecksumMismatch { expected, .. } => Suggestion::UseMatchingRom { expected: expected.clone() },
+            Message::AlreadyApplied { .. } => Suggestion::AlreadyPatched,
+            Message::OutputChecksumMismatch { .. } => Suggestion::DownloadAgain,
+            Message::UnknownFormat => Suggestion::UseSupportedFormat {
+                formats: crate::registry::formats().iter().map(|x| x.name.to_string()).collect(),
+            },
+            Message::UnableToRead { path } | Message::UnableToWrite { path } => Suggestion::CheckPath { path: path.clone() },
+            Message::Cancelled | Message::OverlappingHunk { .. } | Message::MissingHunk { .. }
+            | Message::BeyondFormatLimit { .. } | Message::BeyondMaxOffset { .. }
+            | Message::BeyondBuffer { .. } => return None,
+        };
+        return Some(suggestion);
+    }
+}
+
+impl std::fmt::Display for Suggestion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&English.suggestion(self))
+    }
+}
+
+/// Renders errors and diagnostics for users.
+///
+/// Only [Localizer::message] is required, the other methods default to English names of error
+/// kinds and severities.
+pub trait Localizer: Send + Sync {
+    /// returns the text of `message`.
+    fn message(&self, message: &Message) -> String;
+
+    /// returns the text of `suggestion`.
+    fn suggestion(&self, suggestion: &Suggestion) -> String {
+        English.suggestion(suggestion)
+    }
+
+    /// returns the name of `kind`.
+    fn error_kind(&self, kind: &ErrorKind) -> String {
+        format!("{:?}", kind)
+    }
+
+    /// returns the name of `severity`.
+    fn severity(&self, severity: Severity) -> String {
+        format!("{:?}", severity)
+    }
+
+    /// returns `error` as shown to users, its English description if it has no [Message].
+    fn error(&self, error: &Error) -> String {
+        let kind = self.error_kind(error.kind());
+        match (error.message(), error.description()) {
+            (Some(message), _) => format!("{}: {}", kind, self.message(message)),
+            (None, Some(description)) => format!("{}: {}", kind, description),
+            (None, None) => kind,
+        }
+    }
+
+    /// returns `diagnostic` as shown to users.
+    fn diagnostic(&self, diagnostic: &Diagnostic) -> String {
+        let severity = self.severity(diagnostic.severity);
+        match diagnostic.hunk {
+            Some(hunk) => format!("{} in hunk {}: {}", severity, hunk, diagnostic.message),
+            None => format!("{}: {}", severity, diagnostic.message),
+        }
+    }
+}
+
+/// The English messages, which are also the descriptions of errors.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct English;
+
+impl Localizer for English {
+    fn message(&self, message: &Message) -> String {
+        match message {
+            Message::Cancelled => "Operation was cancelled.".to_string(),
+            Message::UnknownFormat => "Unknown patch format.".to_string(),
+            Message::AlreadyApplied { format, confidence } =>
+                format!("The rom already has the {} patch applied ({:?}), set force to apply it again.", format, confidence),
+            Message::SourceChecksumMismatch { actual, expected, fingerprint: Some(fingerprint), .. } =>
+                format!("SHA-1 of the source is {}, expected {}. The source is {}.", actual, expected, fingerprint),
+            Message::SourceChecksumMismatch { actual, expected, .. } =>
+                format!("SHA-1 of the source is {}, expected {}.", actual, expected),
+            Message::OutputChecksumMismatch { actual, expected } =>
+                format!("SHA-1 of the output is {}, expected {}.", actual, expected),
+            Message::OverlappingHunk { offset, existing } =>
+                format!("Hunk at offset {} overlaps hunk {}.", offset, existing),
+            Message::MissingHunk { index, count } =>
+                format!("Hunk {} doesn't exist, the patch has {} hunks.", index, count),
+            Message::BeyondFormatLimit { format, offset } => format!("{} can't address offset {}.", format, offset),
+            Message::BeyondMaxOffset { offset, max_offset } =>
+                format!("The patch writes to offset {}, past the maximum offset {}.", offset, max_offset),
+            Message::BeyondBuffer { offset, len } =>
+                format!("The patch writes to offset {}, past the end of the {} byte buffer.", offset, len),
+            Message::UnableToRead { path } => format!("Unable to read {}.", path.display()),
+            Message::UnableToWrite { path } => format!("Unable to write {}.", path.display()),
+        }
+    }
+
+    fn suggestion(&self, suggestion: &Suggestion) -> String {
+        match suggestion {
+            Suggestion::StripCopierHeader =>
+                "Your rom appears to have a 512-byte copier header, retry with header stripping.".to_string(),
+            Suggestion::AlreadyPatched =>
+                "Your rom already appears to be patched, use it as is or retry with force to patch it again.".to_string(),
+            Suggestion::UseMatchingRom { expected } =>
+                format!("The patch was made for another rom, use the rom with SHA-1 {} its documentation names.", expected),
+            Suggestion::DownloadAgain =>
+                "The patch or its checksum is likely corrupt, download it again.".to_string(),
+            Suggestion::UseSupportedFormat { formats } =>
+                format!("The file is not a supported patch ({}), make sure it was extracted from its archive.", formats.join(", ")),
+            Suggestion::CheckPath { path } =>
+                format!("Check that {} exists and is accessible.", path.display()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use spectral::prelude::*;
+
+    use crate::ErrorKind::{PatchingError, ValidationError};
+
+    use super::*;
+
+    struct Shouting;
+
+    impl Localizer for Shouting {
+        fn message(&self, message: &Message) -> String {
+            English.message(message).to_uppercase()
+        }
+
+        fn error_kind(&self, _: &ErrorKind) -> String {
+            "ERROR".to_string()
+        }
+    }
+
+    #[test]
+    fn errors_are_localized_from_their_message() {
+        let error = Error::new(PatchingError).with_message(Message::UnableToRead { path: PathBuf::from("a.ips") });
+        assert_that!(error.to_string()).is_equal_to("PatchingError: Unable to read a.ips.".to_string());
+        assert_that!(error.localized(&Shouting)).is_equal_to("ERROR: UNABLE TO READ A.IPS.".to_string());
+    }
+
+    #[test]
+    fn errors_without_message_fall_back_to_their_description() {
+        let error = Error::new(ValidationError).with_description("Invalid.".to_string());
+        assert_that!(error.localized(&Shouting)).is_equal_to("ERROR: Invalid.".to_string());
+        assert_that!(English.error(&error)).is_equal_to(error.to_string());
+    }
+
+    #[test]
+    fn headered_roms_suggest_stripping_the_header() {
+        use crate::apply::apply_verified;
+        use crate::hash::Sha1;
+        use crate::testkit::Fixture;
+
+        let fixture = Fixture::generate(7);
+        let expected = Sha1::hash(&fixture.target);
+        let mut headered = vec![0; 512];
+        headered.extend_from_slice(&fixture.source);
+        let error = apply_verified(&fixture.ips, &headered, &Sha1::hash(&fixture.source), &expected).unwrap_err();
+        assert_that!(error.suggestion()).is_equal_to(Some(Suggestion::StripCopierHeader));
+
+        let error = apply_verified(&fixture.ips, &[1; 1024], &Sha1::hash(&fixture.source), &expected).unwrap_err();
+        assert_that!(error.suggestion()).matches(|x| matches!(x, Some(Suggestion::UseMatchingRom { .. })));
+    }
+}
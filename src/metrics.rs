@@ -0,0 +1,155 @@
+//! Hooks for counting and timing operations, so services can export throughput and failure rates
+//! to the metrics library of their choice.
+//!
+//! Parsing patches with [read_any_from_slice](crate::registry::read_any_from_slice) (`parse`),
+//! applying them with [PatchJob](crate::facade::PatchJob) (`apply`) and creating them from roms in
+//! memory with [create_all](crate::create::create_all) and its variants (`create`) report to the
+//! [Metrics] set with [set_metrics]. By default nothing is reported.
+//!
+//! Each operation increments [OPERATIONS] by one, labeled with the `operation` and its `result`,
+//! which is `ok` or the [ErrorKind](crate::ErrorKind) of the failure. Successful operations also
+//! increment [BYTES] by the size of the parsed patch, the applied output or the target of the
+//! created patches. Every operation observes its duration in seconds as [DURATION], labeled with
+//! the `operation`.
+//!
+//! # Examples
+//!
+//! ```
+//! use std::sync::Arc;
+//! use std::sync::atomic::{AtomicU64, Ordering};
+//! use rom_patcher::metrics::{self, Metrics, OPERATIONS};
+//!
+//! #[derive(Default)]
+//! struct Counter(AtomicU64);
+//!
+//! impl Metrics for Counter {
+//!     fn increment(&self, name: &str, _: &[(&str, &str)], value: u64) {
+//!         if name == OPERATIONS {
+//!             self.0.fetch_add(value, Ordering::Relaxed);
+//!         }
+//!     }
+//! }
+//!
+//! let counter = Arc::new(Counter::default());
+//! metrics::set_metrics(counter.clone());
+//! let _ = rom_patcher::registry::read_any_from_slice(b"PATCHEOF");
+//! assert!(counter.0.load(Ordering::Relaxed) >= 1);
+//! ```
+
+use std::sync::{Arc, RwLock};
+use std::time::Instant;
+
+use crate::Error;
+
+/// Name of the counter of finished operations.
+pub const OPERATIONS: &str = "rom_patcher_operations_total";
+/// Name of the counter of bytes processed by successful operations.
+pub const BYTES: &str = "rom_patcher_bytes_total";
+/// Name of the timer of operations, observed in seconds.
+pub const DURATION: &str = "rom_patcher_operation_duration_seconds";
+
+/// Receives counters and timings of operations. Both methods do nothing by default.
+pub trait Metrics: Send + Sync {
+    /// adds `value` to the counter `name` with `labels`, pairs of label name and value.
+    fn increment(&self, name: &str, labels: &[(&str, &str)], value: u64) {
+        let _ = (name, labels, value);
+    }
+
+    /// records `value` as an observation of the timer or histogram `name` with `labels`.
+    fn observe(&self, name: &str, labels: &[(&str, &str)], value: f64) {
+        let _ = (name, labels, value);
+    }
+}
+
+/// [Metrics] discarding everything, the default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NoMetrics;
+
+impl Metrics for NoMetrics {}
+
+/// The metrics set with [set_metrics].
+static METRICS: RwLock<Option<Arc<dyn Metrics>>> = RwLock::new(None);
+
+/// makes every following operation report to `metrics`, replacing the previous ones.
+pub fn set_metrics(metrics: Arc<dyn Metrics>) {
+    *METRICS.write().unwrap_or_else(|e| e.into_inner()) = Some(metrics);
+}
+
+/// returns the metrics set with [set_metrics], [NoMetrics] if none were.
+pub fn metrics() -> Arc<dyn Metrics> {
+    let metrics = METRICS.read().unwrap_or_else(|e| e.into_inner());
+    metrics.clone().unwrap_or_else(|| Arc::new(NoMetrics))
+}
+
+/// runs `run`, the `operation`, and reports it to the [metrics], with `bytes` returning the amount
+/// of bytes it processed if it succeeds.
+pub(crate) fn measure<T>(operation: &str, run: impl FnOnce() -> Result<T, Error>, bytes: impl FnOnce(&T) -> u64) -> Result<T, Error> {
+    let started = Instant::now();
+    let result = run();
+    let metrics = metrics();
+    let kind = result.as_ref().err().map(|e| format!("{:?}", e.kind()));
+    let outcome = kind.as_deref().unwrap_or("ok");
+    metrics.increment(OPERATIONS, &[("operation", operation), ("result", outcome)], 1);
+    if let Ok(value) = &result {
+        metrics.increment(BYTES, &[("operation", operation)], bytes(value));
+    }
+    metrics.observe(DURATION, &[("operation", operation)], started.elapsed().as_secs_f64());
+    return result;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use spectral::prelude::*;
+
+    use crate::ErrorKind::ParsingError;
+
+    use super::*;
+
+    /// A counter or timer with its labels and value.
+    type Record = (String, Vec<(String, String)>, f64);
+
+    /// Records every counter and timer it receives.
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<Record>>);
+
+    impl Recorder {
+        fn record(&self, name: &str, labels: &[(&str, &str)], value: f64) {
+            let labels = labels.iter().map(|(a, b)| (a.to_string(), b.to_string())).collect();
+            self.0.lock().unwrap().push((name.to_string(), labels, value));
+        }
+
+        fn of(&self, operation: &str) -> Vec<Record> {
+            let operation = ("operation".to_string(), operation.to_string());
+            self.0.lock().unwrap().iter().filter(|x| x.1.contains(&operation)).cloned().collect()
+        }
+    }
+
+    impl Metrics for Recorder {
+        fn increment(&self, name: &str, labels: &[(&str, &str)], value: u64) {
+            self.record(name, labels, value as f64);
+        }
+
+        fn observe(&self, name: &str, labels: &[(&str, &str)], value: f64) {
+            self.record(name, labels, value);
+        }
+    }
+
+    #[test]
+    fn operations_are_counted_and_timed() {
+        let recorder = Arc::new(Recorder::default());
+        set_metrics(recorder.clone());
+        let ok = measure("metrics-test-ok", || Ok(()), |_| 42);
+        let failed: Result<(), Error> = measure("metrics-test-failed", || Err(Error::new(ParsingError)), |_| 42);
+        set_metrics(Arc::new(NoMetrics));
+        assert_that!(ok).is_ok();
+        assert_that!(failed).is_err();
+
+        let names: Vec<String> = recorder.of("metrics-test-ok").into_iter().map(|x| x.0).collect();
+        assert_that!(names).is_equal_to(vec![OPERATIONS.to_string(), BYTES.to_string(), DURATION.to_string()]);
+        let failed = recorder.of("metrics-test-failed");
+        assert_that!(failed.len()).is_equal_to(2);
+        assert_that!(failed[0].1.contains(&("result".to_string(), "ParsingError".to_string()))).is_true();
+    }
+}
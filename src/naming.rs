@@ -0,0 +1,283 @@
+//! Naming patched roms.
+//!
+//! Patches and their sidecars can suggest how the patched rom should be named, see
+//! [Patch::suggested_output_name](crate::patch::Patch::suggested_output_name). Those names come
+//! from whoever made the patch, so [sanitize_file_name] strips anything that would leave the
+//! output directory or isn't a valid file name on common file systems. Frontends naming outputs
+//! themselves use a [NamingTemplate].
+
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+
+use crate::console::Console;
+use crate::Error;
+use crate::ErrorKind::ParsingError;
+
+/// characters that aren't allowed in file names on Windows.
+const RESERVED_CHARS: &[char] = &['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+
+/// file names Windows reserves for devices, regardless of their extension.
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL",
+    "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+    "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// longest file name in bytes most file systems accept.
+const MAX_LEN: usize = 255;
+
+/// returns `name` as a file name that stays in the directory it is written to, or [None] if
+/// nothing usable is left of it.
+///
+/// Only the last component of a path is kept, control and reserved characters are removed,
+/// leading and trailing dots and spaces are trimmed, device names like `CON` are prefixed with
+/// `_` and the name is cut to 255 bytes, keeping its extension.
+///
+/// # Examples
+///
+/// ```
+/// use rom_patcher::naming::sanitize_file_name;
+/// assert_eq!(sanitize_file_name("../../Hack: Deluxe?.sfc"), Some("Hack Deluxe.sfc".to_string()));
+/// assert_eq!(sanitize_file_name("aux.gba"), Some("_aux.gba".to_string()));
+/// assert_eq!(sanitize_file_name(".."), None);
+/// ```
+pub fn sanitize_file_name(name: &str) -> Option<String> {
+    let last = name.rsplit(['/', '\\']).next().unwrap_or(name);
+    let cleaned: String = last.chars().filter(|x| !x.is_control() && !RESERVED_CHARS.contains(x)).collect();
+    let trimmed = cleaned.trim_matches(|x| x == '.' || x == ' ');
+    if trimmed.is_empty() {
+        return None;
+    }
+    let stem = trimmed.split('.').next().unwrap_or(trimmed).trim_end();
+    let mut result = if RESERVED_NAMES.iter().any(|x| x.eq_ignore_ascii_case(stem)) {
+        format!("_{}", trimmed)
+    } else {
+        trimmed.to_string()
+    };
+    if result.len() > MAX_LEN {
+        result = truncate(&result);
+    }
+    return Some(result);
+}
+
+/// returns `name` cut to [MAX_LEN] bytes on a character boundary, keeping a short extension.
+fn truncate(name: &str) -> String {
+    let extension = match name.rsplit_once('.') {
+        Some((_, extension)) if extension.len() < 16 => &name[name.len() - extension.len() - 1..],
+        _ => "",
+    };
+    let mut end = MAX_LEN - extension.len();
+    while !name.is_char_boundary(end) {
+        end -= 1;
+    }
+    return format!("{}{}", name[..end].trim_end_matches(['.', ' ']), extension);
+}
+
+/// What a [NamingTemplate] names an output after.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NamingContext<'a> {
+    /// path of the rom the patch is applied to.
+    pub rom: &'a Path,
+    /// path of the applied patch.
+    pub patch: &'a Path,
+    /// title of the patch, e.g. from its manifest, [None] to use the file name of the patch.
+    pub title: Option<&'a str>,
+    /// the console the rom was dumped from, [None] to keep the extension of the rom.
+    pub console: Option<Console>,
+}
+
+/// A placeholder of a [NamingTemplate].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    RomStem,
+    PatchStem,
+    PatchTitle,
+    Ext,
+}
+
+impl Field {
+    const NAMES: [(&'static str, Field); 4] = [
+        ("rom_stem", Field::RomStem),
+        ("patch_stem", Field::PatchStem),
+        ("patch_title", Field::PatchTitle),
+        ("ext", Field::Ext),
+    ];
+
+    fn value(&self, context: &NamingContext) -> String {
+        let stem = |x: &Path| x.file_stem().map(|x| x.to_string_lossy().into_owned()).unwrap_or_default();
+        match self {
+            Field::RomStem => stem(context.rom),
+            Field::PatchStem => stem(context.patch),
+            Field::PatchTitle => context.title.map(str::to_string).unwrap_or_else(|| stem(context.patch)),
+            Field::Ext => context.console.and_then(|x| x.extension())
+                .map(str::to_string)
+                .or_else(|| context.rom.extension().map(|x| x.to_string_lossy().into_owned()))
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// A pattern output file names are built from, like `{rom_stem} ({patch_title}).{ext}`.
+///
+/// The placeholders are
+/// - `{rom_stem}`, the file name of the rom without its extension,
+/// - `{patch_stem}`, the file name of the patch without its extension,
+/// - `{patch_title}`, the [title](NamingContext::title) of the patch, or its stem if it has none,
+/// - `{ext}`, the canonical extension of the [console](Console::extension) of the rom, or the
+///   extension of the rom if its console isn't known.
+///
+/// # Examples
+///
+/// ```
+/// use std::path::Path;
+/// use rom_patcher::console::Console;
+/// use rom_patcher::naming::{NamingContext, NamingTemplate};
+///
+/// let context = NamingContext {
+///     rom: Path::new("roms/Super Metroid.smc"),
+///     patch: Path::new("hacks/redesign.bps"),
+///     title: None,
+///     console: Some(Console::SnesLoRom),
+/// };
+/// let name = NamingTemplate::default().render(&context);
+/// assert_eq!(name, Some("Super Metroid (redesign).sfc".to_string()));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize), serde(try_from = "String", into = "String"))]
+pub struct NamingTemplate {
+    template: String,
+    segments: Vec<(String, Option<Field>)>,
+}
+
+impl NamingTemplate {
+    /// The template used unless another one is configured.
+    pub const DEFAULT: &'static str = "{rom_stem} ({patch_title}).{ext}";
+
+    /// parses `template`.
+    ///
+    /// Returns a [ParsingError] if a brace isn't closed or a placeholder is unknown.
+    pub fn parse(template: &str) -> Result<NamingTemplate, Error> {
+        let mut segments = Vec::new();
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            let text = &rest[..start];
+            if text.contains('}') {
+                break;
+            }
+            let Some(end) = rest[start..].find('}') else {
+                return Err(Error::new(ParsingError)
+                    .with_description(format!("Unclosed placeholder in naming template \"{}\".", template)));
+            };
+            let name = &rest[start + 1..start + end];
+            let field = Field::NAMES.iter().find(|x| x.0 == name).map(|x| x.1).ok_or_else(|| Error::new(ParsingError)
+                .with_description(format!("Unknown placeholder {{{}}} in naming template \"{}\".", name, template)))?;
+            segments.push((text.to_string(), Some(field)));
+            rest = &rest[start + end + 1..];
+        }
+        if rest.contains('}') {
+            return Err(Error::new(ParsingError)
+                .with_description(format!("Unopened placeholder in naming template \"{}\".", template)));
+        }
+        segments.push((rest.to_string(), None));
+        return Ok(NamingTemplate { template: template.to_string(), segments });
+    }
+
+    /// returns the file name for `context`, [sanitized](sanitize_file_name), or [None] if nothing
+    /// usable is left of it.
+    ///
+    /// A trailing dot left by an empty `{ext}` is dropped.
+    pub fn render(&self, context: &NamingContext) -> Option<String> {
+        let mut result = String::new();
+        for (text, field) in &self.segments {
+            result.push_str(text);
+            if let Some(field) = field {
+                // values can't cut the name short with a separator
+                result.extend(field.value(context).chars().filter(|x| !RESERVED_CHARS.contains(x)));
+            }
+        }
+        sanitize_file_name(&result)
+    }
+}
+
+impl Default for NamingTemplate {
+    fn default() -> Self {
+        // the default template is valid
+        NamingTemplate::parse(Self::DEFAULT).unwrap()
+    }
+}
+
+/// Formats the template as it was parsed.
+impl Display for NamingTemplate {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.template)
+    }
+}
+
+impl TryFrom<String> for NamingTemplate {
+    type Error = Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        NamingTemplate::parse(&value)
+    }
+}
+
+impl From<NamingTemplate> for String {
+    fn from(value: NamingTemplate) -> Self {
+        value.template
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use spectral::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn paths_are_reduced_to_file_names() {
+        assert_that!(sanitize_file_name("Hack.sfc")).is_equal_to(Some("Hack.sfc".to_string()));
+        assert_that!(sanitize_file_name("/etc/passwd")).is_equal_to(Some("passwd".to_string()));
+        assert_that!(sanitize_file_name("..\\..\\boot.ini")).is_equal_to(Some("boot.ini".to_string()));
+        assert_that!(sanitize_file_name(" .hidden. ")).is_equal_to(Some("hidden".to_string()));
+        assert_that!(sanitize_file_name("roms/")).is_none();
+        assert_that!(sanitize_file_name("\u{0}\u{7}")).is_none();
+    }
+
+    #[test]
+    fn device_names_are_escaped() {
+        assert_that!(sanitize_file_name("CON")).is_equal_to(Some("_CON".to_string()));
+        assert_that!(sanitize_file_name("com1.sfc")).is_equal_to(Some("_com1.sfc".to_string()));
+        assert_that!(sanitize_file_name("console.sfc")).is_equal_to(Some("console.sfc".to_string()));
+    }
+
+    #[test]
+    fn long_names_keep_their_extension() {
+        let name = format!("{}.sfc", "ä".repeat(200));
+        let result = sanitize_file_name(&name).unwrap();
+        assert_that!(result.len()).is_less_than_or_equal_to(MAX_LEN);
+        assert_that!(result.ends_with("ä.sfc")).is_true();
+    }
+
+    #[test]
+    fn templates_fill_in_placeholders() {
+        let context = NamingContext {
+            rom: Path::new("Zelda.z64"),
+            patch: Path::new("patches/randomizer.bps"),
+            title: Some("Randomizer: Seed 4/2"),
+            console: None,
+        };
+        let template = NamingTemplate::parse("{patch_stem}/{rom_stem} - {patch_title}.{ext}").unwrap();
+        assert_that!(template.render(&context)).is_equal_to(Some("Zelda - Randomizer Seed 42.z64".to_string()));
+        let no_extension = NamingContext { rom: Path::new("dump"), ..context };
+        assert_that!(NamingTemplate::default().render(&no_extension)).is_equal_to(Some("dump (Randomizer Seed 42)".to_string()));
+    }
+
+    #[test]
+    fn invalid_templates_are_refused() {
+        assert_that!(NamingTemplate::parse("{rom_stem").unwrap_err().to_string()).contains("Unclosed");
+        assert_that!(NamingTemplate::parse("{title}").unwrap_err().to_string()).contains("Unknown placeholder {title}");
+        assert_that!(NamingTemplate::parse("rom}").unwrap_err().to_string()).contains("Unopened");
+        assert_that!(NamingTemplate::parse("}{ext}").unwrap_err().to_string()).contains("Unopened");
+        assert_that!(NamingTemplate::default().to_string()).is_equal_to(NamingTemplate::DEFAULT.to_string());
+    }
+}
@@ -0,0 +1,134 @@
+//! A machine readable outcome of the high level operations, for scripts deciding what to do next.
+//!
+//! Every [OperationResult] maps to a distinct [exit code](OperationResult::exit_code), so command
+//! line frontends can report it without inventing their own mapping.
+
+use crate::diagnostics::Diagnostic;
+use crate::{Error, ErrorKind};
+use crate::facade::JobReport;
+
+/// The outcome of an operation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum OperationResult {
+    /// the operation succeeded without findings.
+    Success,
+    /// the operation succeeded, but found something worth a look, e.g. a patch without checksums.
+    AppliedWithWarnings,
+    /// the output doesn't have its expected checksum.
+    ChecksumMismatch,
+    /// the source rom isn't the one the patch was made for.
+    WrongSource,
+    /// the source rom already has the patch applied.
+    AlreadyApplied,
+    /// the patch is not of any supported format.
+    FormatUnsupported,
+    /// the patch is malformed or violates one of its invariants.
+    InvalidPatch,
+    /// the operation was cancelled before it finished.
+    Cancelled,
+    /// the operation failed for another reason, e.g. a file couldn't be read.
+    Failed,
+}
+
+impl OperationResult {
+    /// returns the result of an operation that failed with `error`.
+    pub fn from_error(error: &Error) -> OperationResult {
+        match error.kind() {
+            ErrorKind::ParsingError | ErrorKind::ValidationError => OperationResult::InvalidPatch,
+            ErrorKind::UnsupportedFormat => OperationResult::FormatUnsupported,
+            ErrorKind::Cancelled => OperationResult::Cancelled,
+            ErrorKind::WrongSource => OperationResult::WrongSource,
+            ErrorKind::ChecksumMismatch => OperationResult::ChecksumMismatch,
+            ErrorKind::AlreadyApplied => OperationResult::AlreadyApplied,
+            ErrorKind::PatchingError | ErrorKind::CreatingError => OperationResult::Failed,
+        }
+    }
+
+    /// returns the result of an operation that succeeded with `diagnostics`.
+    pub fn from_diagnostics(diagnostics: &[Diagnostic]) -> OperationResult {
+        if !diagnostics.is_empty() {
+            return OperationResult::AppliedWithWarnings;
+        }
+        return OperationResult::Success;
+    }
+
+    /// returns `true` if the operation produced its output.
+    pub fn is_success(&self) -> bool {
+        matches!(self, OperationResult::Success | OperationResult::AppliedWithWarnings)
+    }
+
+    /// returns the exit code a command line frontend reports the result with.
+    ///
+    /// | result                | code |
+    /// |-----------------------|------|
+    /// | `Success`             | 0    |
+    /// | `AppliedWithWarnings` | 1    |
+    /// | `Failed`              | 2    |
+    /// | `InvalidPatch`        | 3    |
+    /// | `FormatUnsupported`   | 4    |
+    /// | `WrongSource`         | 5    |
+    /// | `ChecksumMismatch`    | 6    |
+    /// | `AlreadyApplied`      | 7    |
+    /// | `Cancelled`           | 130  |
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            OperationResult::Success => 0,
+            OperationResult::AppliedWithWarnings => 1,
+            OperationResult::Failed => 2,
+            OperationResult::InvalidPatch => 3,
+            OperationResult::FormatUnsupported => 4,
+            OperationResult::WrongSource => 5,
+            OperationResult::ChecksumMismatch => 6,
+            OperationResult::AlreadyApplied => 7,
+            // the code shells report for an interrupt
+            OperationResult::Cancelled => 130,
+        }
+    }
+}
+
+impl From<&Result<JobReport, Error>> for OperationResult {
+    fn from(value: &Result<JobReport, Error>) -> Self {
+        match value {
+            Ok(report) => OperationResult::from_diagnostics(&report.diagnostics),
+            Err(e) => OperationResult::from_error(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use spectral::prelude::*;
+
+    use crate::apply::apply_verified;
+    use crate::hash::Sha1;
+    use crate::ips::{IPSHunk, IPSPatch, IPSRLEHunkData};
+
+    use super::*;
+
+    #[test]
+    fn verification_failures_are_told_apart() {
+        let patch = IPSPatch::new()
+            .with_hunk(IPSHunk::RLE(IPSRLEHunkData { offset: 0, run_length: 2, payload: 0xFF }));
+        let rom = [0; 4];
+        let wrong_source = apply_verified(&patch, &[1; 4], &Sha1::hash(&rom), &Sha1::hash(&rom));
+        assert_that!(OperationResult::from_error(&wrong_source.unwrap_err())).is_equal_to(OperationResult::WrongSource);
+        let mismatch = apply_verified(&patch, &rom, &Sha1::hash(&rom), &Sha1::hash(&rom));
+        assert_that!(OperationResult::from_error(&mismatch.unwrap_err())).is_equal_to(OperationResult::ChecksumMismatch);
+    }
+
+    #[test]
+    fn results_have_distinct_exit_codes() {
+        let results = [
+            OperationResult::Success, OperationResult::AppliedWithWarnings, OperationResult::ChecksumMismatch,
+            OperationResult::WrongSource, OperationResult::AlreadyApplied, OperationResult::FormatUnsupported, OperationResult::InvalidPatch,
+            OperationResult::Cancelled, OperationResult::Failed,
+        ];
+        let mut codes: Vec<i32> = results.iter().map(|x| x.exit_code()).collect();
+        codes.sort();
+        codes.dedup();
+        assert_that!(codes).has_length(results.len());
+        assert_that!(OperationResult::from_diagnostics(&[Diagnostic::unverified("ips")]))
+            .is_equal_to(OperationResult::AppliedWithWarnings);
+    }
+}
@@ -0,0 +1,635 @@
+//! Applying patches to read-only sources, see [OverlayTarget].
+
+use std::collections::BTreeMap;
+use std::io::{Error as IOError, ErrorKind, Read, Result as IOResult, Seek, SeekFrom, Write};
+
+use crate::bps::BPSPatch;
+use crate::Error;
+use crate::ErrorKind::{CreatingError, PatchingError};
+use crate::io_util::{Truncate, U64Extensions};
+use crate::ips::IPSPatch;
+
+/// Size of the chunks [OverlayTarget::flush_to] reads and writes at once.
+const FLUSH_CHUNK_SIZE: usize = 0x10000;
+
+/// A patch target recording writes in memory instead of passing them on to its source.
+///
+/// The source is only ever read, so patches can be applied to roms on read-only media or shared
+/// between several overlays. Writes are kept as extents keyed by their offset. Reading the overlay
+/// returns the source with the extents laid over it, like [PatchedView](crate::view::PatchedView),
+/// and [OverlayTarget::flush_to] writes the patched file out.
+///
+/// Writes go to the topmost of a stack of [OverlayLayer]s. [OverlayTarget::push_layer] starts a
+/// new one, e.g. for each patch of a mod list, so single patches can be taken back off with
+/// [OverlayTarget::pop_layer] while reads always see all of them combined.
+///
+/// # Examples
+///
+/// ```
+/// use std::io::{Cursor, Read};
+/// use rom_patcher::ips::{IPSHunk, IPSPatch, IPSRLEHunkData};
+/// use rom_patcher::overlay::OverlayTarget;
+///
+/// let patch = IPSPatch::new()
+///     .with_hunk(IPSHunk::RLE(IPSRLEHunkData { offset: 1, run_length: 2, payload: 0xFF }));
+/// let mut overlay = OverlayTarget::new(Cursor::new(vec![0; 4])).unwrap();
+/// patch.apply(&mut overlay).unwrap();
+///
+/// let mut patched = Vec::new();
+/// overlay.flush_to(&mut patched).unwrap();
+/// assert_eq!(patched, vec![0, 0xFF, 0xFF, 0]);
+/// assert_eq!(overlay.into_inner().into_inner(), vec![0; 4]);
+/// ```
+#[derive(Debug)]
+pub struct OverlayTarget<R> {
+    source: R,
+    source_len: u64,
+    /// never empty, the last layer is written to.
+    layers: Vec<OverlayLayer>,
+    position: u64,
+}
+
+/// The writes and truncations recorded by one layer of an [OverlayTarget].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct OverlayLayer {
+    /// length of the file below the layer.
+    base_len: u64,
+    /// amount of bytes of the file below that are still part of the file, less than `base_len`
+    /// once the layer truncated it.
+    below_visible: u64,
+    /// written extents by their offset, never overlapping or adjacent.
+    extents: BTreeMap<u64, Vec<u8>>,
+    len: u64,
+}
+
+impl OverlayLayer {
+    /// constructs an empty layer over a file of `len` bytes.
+    fn new(len: u64) -> OverlayLayer {
+        OverlayLayer {
+            base_len: len,
+            below_visible: len,
+            extents: BTreeMap::new(),
+            len,
+        }
+    }
+
+    /// returns the length of the file with the layer applied.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// returns `true` if the file with the layer applied is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// returns `true` if the layer wrote to or truncated the file.
+    pub fn is_modified(&self) -> bool {
+        !self.extents.is_empty() || self.len != self.base_len || self.below_visible != self.base_len
+    }
+
+    /// returns the written extents as their offset and data, ordered by offset.
+    ///
+    /// Overlapping and adjacent writes are merged into a single extent, bytes cut off by
+    /// truncation are left out.
+    pub fn extents(&self) -> impl Iterator<Item=(u64, &[u8])> {
+        self.extents.iter().map(|(offset, data)| (*offset, data.as_slice()))
+    }
+
+    /// returns the amount of bytes of the file below the layer that are still part of the file.
+    pub fn below_visible(&self) -> u64 {
+        self.below_visible
+    }
+
+    /// records `data` written at `offset`, merging it with the extents it overlaps or touches.
+    fn record(&mut self, offset: u64, data: &[u8]) {
+        let end = offset + data.len() as u64;
+        let touching: Vec<u64> = self.extents.range(..=end)
+            .rev()
+            .take_while(|(start, extent)| *start + extent.len() as u64 >= offset)
+            .map(|(start, _)| *start)
+            .collect();
+        let start = touching.last().map_or(offset, |x| (*x).min(offset));
+        let mut merged = Vec::new();
+        for key in touching.into_iter().rev() {
+            let extent = self.extents.remove(&key).unwrap_or_default();
+            let at = (key - start).to_index();
+            if merged.len() < at + extent.len() {
+                merged.resize(at + extent.len(), 0);
+            }
+            merged[at..at + extent.len()].copy_from_slice(&extent);
+        }
+        let at = (offset - start).to_index();
+        if merged.len() < at + data.len() {
+            merged.resize(at + data.len(), 0);
+        }
+        merged[at..at + data.len()].copy_from_slice(data);
+        self.extents.insert(start, merged);
+        self.len = self.len.max(end);
+    }
+
+    /// shortens the file to `amount` bytes if it is longer.
+    fn truncate(&mut self, amount: u64) {
+        if amount >= self.len {
+            return;
+        }
+        self.len = amount;
+        self.below_visible = self.below_visible.min(amount);
+        let cut: Vec<u64> = self.extents.iter().rev()
+            .take_while(|(start, extent)| *start + extent.len() as u64 > amount)
+            .map(|(start, _)| *start)
+            .collect();
+        for start in cut {
+            if start >= amount {
+                self.extents.remove(&start);
+            } else if let Some(extent) = self.extents.get_mut(&start) {
+                extent.truncate((amount - start) as usize);
+            }
+        }
+    }
+
+    /// writes the extents that fall into `buf` to it, where `buf` holds the bytes starting at
+    /// `offset`.
+    fn overlay(&self, offset: u64, buf: &mut [u8]) {
+        let end = offset + buf.len() as u64;
+        let first = self.extents.range(..=offset).next_back().map_or(offset, |(x, _)| *x);
+        for (start, data) in self.extents.range(first..end) {
+            crate::hunk::overlay_slice(*start, data, offset, buf);
+        }
+    }
+}
+
+impl<R> OverlayTarget<R> where R: Read + Seek {
+    /// constructs an overlay over `source`, leaving it untouched.
+    pub fn new(mut source: R) -> Result<OverlayTarget<R>, Error> {
+        let len = source.seek(SeekFrom::End(0))
+            .map_err(|e| Error::new(PatchingError)
+                .with_description("Unable to read source length.".to_string())
+                .with_source(Box::new(e)))?;
+        Ok(OverlayTarget {
+            source,
+            source_len: len,
+            layers: vec![OverlayLayer::new(len)],
+            position: 0,
+        })
+    }
+
+    /// returns the length of the patched file.
+    pub fn len(&self) -> u64 {
+        self.top().len
+    }
+
+    /// returns `true` if the patched file is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// returns `true` if anything was written to or truncated off the file by any layer.
+    pub fn is_modified(&self) -> bool {
+        self.layers.iter().any(|x| x.is_modified())
+    }
+
+    /// returns the length of the source.
+    pub fn source_len(&self) -> u64 {
+        self.source_len
+    }
+
+    /// returns the written extents of the topmost layer as their offset and data, ordered by
+    /// offset.
+    ///
+    /// Overlapping and adjacent writes are merged into a single extent, bytes cut off by
+    /// truncation are left out.
+    pub fn extents(&self) -> impl Iterator<Item=(u64, &[u8])> {
+        self.top().extents()
+    }
+
+    /// returns the amount of bytes of the source that are still part of the file.
+    pub fn source_visible(&self) -> u64 {
+        self.layers.iter().map(|x| x.below_visible).min().unwrap_or(self.source_len)
+    }
+
+    /// returns the layers from the bottom to the topmost one, which is written to.
+    pub fn layers(&self) -> &[OverlayLayer] {
+        &self.layers
+    }
+
+    /// starts a new topmost layer that further writes go to.
+    pub fn push_layer(&mut self) {
+        let len = self.len();
+        self.layers.push(OverlayLayer::new(len));
+    }
+
+    /// removes the topmost layer, discarding its changes, and returns it.
+    ///
+    /// Returns [None] without removing anything if it is the only layer.
+    pub fn pop_layer(&mut self) -> Option<OverlayLayer> {
+        if self.layers.len() == 1 {
+            return None;
+        }
+        self.layers.pop()
+    }
+
+    /// merges all layers into a single one with the same combined contents.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::io::{Cursor, Write};
+    /// use rom_patcher::overlay::OverlayTarget;
+    ///
+    /// let mut overlay = OverlayTarget::new(Cursor::new(vec![0; 4])).unwrap();
+    /// overlay.write_all(&[1, 1]).unwrap();
+    /// overlay.push_layer();
+    /// overlay.write_all(&[2]).unwrap();
+    /// overlay.flatten();
+    /// assert_eq!(overlay.layers().len(), 1);
+    /// assert_eq!(overlay.to_vec().unwrap(), vec![1, 1, 2, 0]);
+    /// ```
+    pub fn flatten(&mut self) {
+        let mut result = OverlayLayer::new(self.source_len);
+        for (i, layer) in self.layers.iter().enumerate() {
+            // bytes past what a later layer truncated to are gone, even if rewritten later
+            let visible = self.layers[i + 1..].iter().map(|x| x.below_visible).min().unwrap_or(u64::MAX);
+            for (start, data) in layer.extents() {
+                let len = visible.saturating_sub(start).min(data.len() as u64) as usize;
+                if len > 0 {
+                    result.record(start, &data[..len]);
+                }
+            }
+        }
+        result.below_visible = self.source_visible();
+        result.len = self.len();
+        self.layers = vec![result];
+    }
+
+    /// discards every layer, returning to the unmodified source.
+    pub fn reset(&mut self) {
+        self.layers = vec![OverlayLayer::new(self.source_len)];
+        self.position = 0;
+    }
+
+    /// writes the whole patched file to `writer` and returns its length.
+    pub fn flush_to(&mut self, writer: &mut impl Write) -> Result<u64, Error> {
+        let len = self.len();
+        let mut buf = vec![0; FLUSH_CHUNK_SIZE];
+        let mut offset = 0;
+        while offset < len {
+            let chunk = &mut buf[..(len - offset).min(FLUSH_CHUNK_SIZE as u64) as usize];
+            self.read_at(offset, chunk)
+                .map_err(|e| Error::new(PatchingError)
+                    .with_description("Unable to read source.".to_string())
+                    .with_source(Box::new(e)))?;
+            writer.write_all(chunk)
+                .map_err(|e| Error::new(PatchingError)
+                    .with_description("Unable to write output.".to_string())
+                    .with_source(Box::new(e)))?;
+            offset += chunk.len() as u64;
+        }
+        Ok(len)
+    }
+
+    /// returns the whole patched file.
+    pub fn to_vec(&mut self) -> Result<Vec<u8>, Error> {
+        let mut result = Vec::with_capacity(self.len() as usize);
+        self.flush_to(&mut result)?;
+        Ok(result)
+    }
+
+    /// creates an IPS patch turning the source into the patched file.
+    ///
+    /// The patch is created by diffing against the source, so it leaves out bytes that were
+    /// written with the value they already had, and it truncates the file if it got shorter.
+    /// Returns a [CreatingError] if the changes lie beyond what IPS can address.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::io::{Cursor, Seek, SeekFrom, Write};
+    /// use rom_patcher::overlay::OverlayTarget;
+    ///
+    /// let mut overlay = OverlayTarget::new(Cursor::new(vec![0, 1, 2, 3])).unwrap();
+    /// overlay.seek(SeekFrom::Start(1)).unwrap();
+    /// overlay.write_all(&[1, 9]).unwrap();
+    /// let patch = overlay.to_ips().unwrap();
+    /// assert_eq!(patch.hunks().len(), 1);
+    /// assert_eq!(patch.hunks()[0].offset(), 2);
+    /// ```
+    pub fn to_ips(&mut self) -> Result<IPSPatch, Error> {
+        let source = self.read_source()?;
+        let target = self.to_vec()?;
+        IPSPatch::create(&source, &target)
+    }
+
+    /// creates a BPS patch turning the source into the patched file, like [OverlayTarget::to_ips].
+    ///
+    /// Unlike IPS, BPS addresses files of any size and records the checksums of both files.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::io::{Cursor, Seek, SeekFrom, Write};
+    /// use rom_patcher::overlay::OverlayTarget;
+    ///
+    /// let mut overlay = OverlayTarget::new(Cursor::new(vec![0, 1, 2, 3])).unwrap();
+    /// overlay.seek(SeekFrom::Start(1)).unwrap();
+    /// overlay.write_all(&[1, 9]).unwrap();
+    /// let patch = overlay.to_bps().unwrap();
+    /// assert_eq!(patch.apply_to_vec(&[0, 1, 2, 3]).unwrap(), vec![0, 1, 9, 3]);
+    /// ```
+    pub fn to_bps(&mut self) -> Result<BPSPatch, Error> {
+        let source = self.read_source()?;
+        let target = self.to_vec()?;
+        Ok(BPSPatch::create(&source, &target))
+    }
+
+    /// returns the whole untouched source.
+    fn read_source(&mut self) -> Result<Vec<u8>, Error> {
+        let mut source = Vec::with_capacity(self.source_len as usize);
+        self.source.seek(SeekFrom::Start(0))
+            .and_then(|_| self.source.read_to_end(&mut source))
+            .map_err(|e| Error::new(CreatingError)
+                .with_description("Unable to read source.".to_string())
+                .with_source(Box::new(e)))?;
+        Ok(source)
+    }
+
+    /// consumes the overlay and returns the untouched source.
+    pub fn into_inner(self) -> R {
+        self.source
+    }
+
+
+    /// fills `buf` with the patched bytes starting at `offset`, which must lie inside the file.
+    pub(crate) fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> IOResult<()> {
+        self.read_below(self.layers.len(), offset, buf)
+    }
+
+    /// fills `buf` with the bytes starting at `offset` of the file with the lowest `depth` layers
+    /// applied.
+    fn read_below(&mut self, depth: usize, offset: u64, buf: &mut [u8]) -> IOResult<()> {
+        let end = offset + buf.len() as u64;
+        let visible = match depth {
+            0 => self.source_len,
+            _ => self.layers[depth - 1].below_visible,
+        };
+        // the file below as far as it is still part of the file, zeros after it
+        let from_below = visible.min(end).saturating_sub(offset) as usize;
+        if from_below > 0 {
+            match depth {
+                0 => {
+                    self.source.seek(SeekFrom::Start(offset))?;
+                    self.source.read_exact(&mut buf[..from_below])?;
+                }
+                _ => self.read_below(depth - 1, offset, &mut buf[..from_below])?,
+            }
+        }
+        buf[from_below..].fill(0);
+        if depth > 0 {
+            self.layers[depth - 1].overlay(offset, buf);
+        }
+        Ok(())
+    }
+
+    fn top(&self) -> &OverlayLayer {
+        &self.layers[self.layers.len() - 1]
+    }
+
+    fn top_mut(&mut self) -> &mut OverlayLayer {
+        let last = self.layers.len() - 1;
+        &mut self.layers[last]
+    }
+}
+
+impl<R> Read for OverlayTarget<R> where R: Read + Seek {
+    fn read(&mut self, buf: &mut [u8]) -> IOResult<usize> {
+        let len = (self.len().saturating_sub(self.position)).min(buf.len() as u64) as usize;
+        self.read_at(self.position, &mut buf[..len])?;
+        self.position += len as u64;
+        Ok(len)
+    }
+}
+
+impl<R> Write for OverlayTarget<R> where R: Read + Seek {
+    fn write(&mut self, buf: &[u8]) -> IOResult<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        self.position.checked_add(buf.len() as u64)
+            .ok_or_else(|| IOError::new(ErrorKind::InvalidInput, "write past the end of the address space"))?;
+        let position = self.position;
+        self.top_mut().record(position, buf);
+        self.position += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> IOResult<()> {
+        Ok(())
+    }
+}
+
+impl<R> Seek for OverlayTarget<R> where R: Read + Seek {
+    fn seek(&mut self, pos: SeekFrom) -> IOResult<u64> {
+        let position = match pos {
+            SeekFrom::Start(x) => Some(x),
+            SeekFrom::End(x) => self.len().checked_add_signed(x),
+            SeekFrom::Current(x) => self.position.checked_add_signed(x),
+        };
+        match position {
+            Some(x) => {
+                self.position = x;
+                Ok(x)
+            }
+            None => Err(IOError::new(ErrorKind::InvalidInput, "invalid seek to a negative position")),
+        }
+    }
+}
+
+impl<R> Truncate for OverlayTarget<R> where R: Read + Seek {
+    fn truncate(&mut self, amount: u64) -> IOResult<()> {
+        self.top_mut().truncate(amount);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use spectral::prelude::*;
+
+    use crate::ips::{IPSHunk, IPSRegularHunkData, IPSRLEHunkData};
+    use crate::patch::Patch;
+
+    use super::*;
+
+    fn overlay() -> OverlayTarget<Cursor<Vec<u8>>> {
+        OverlayTarget::new(Cursor::new((0..8).collect())).unwrap()
+    }
+
+    fn contents(overlay: &mut OverlayTarget<Cursor<Vec<u8>>>) -> Vec<u8> {
+        let mut actual = Vec::new();
+        overlay.flush_to(&mut actual).unwrap();
+        return actual;
+    }
+
+    #[test]
+    fn applying_matches_applying_to_vec() {
+        let patch = IPSPatch::new()
+            .with_hunk(IPSHunk::Regular(IPSRegularHunkData::new(6, vec![0xA, 0xB, 0xC, 0xD])))
+            .with_hunk(IPSHunk::RLE(IPSRLEHunkData { offset: 1, run_length: 2, payload: 0xFF }));
+        let mut overlay = overlay();
+        patch.apply(&mut overlay).unwrap();
+        assert_that!(contents(&mut overlay)).is_equal_to(patch.apply_to_vec(&(0..8).collect::<Vec<u8>>()).unwrap());
+        assert_that!(overlay.into_inner().into_inner()).is_equal_to((0..8).collect::<Vec<u8>>());
+    }
+
+    #[test]
+    fn overlapping_and_adjacent_writes_are_merged() {
+        let mut overlay = overlay();
+        for (offset, data) in [(2, &[1, 1][..]), (5, &[2]), (3, &[3, 3])] {
+            overlay.seek(SeekFrom::Start(offset)).unwrap();
+            overlay.write_all(data).unwrap();
+        }
+        let extents: Vec<(u64, Vec<u8>)> = overlay.extents().map(|(x, data)| (x, data.to_vec())).collect();
+        assert_that!(extents).is_equal_to(vec![(2, vec![1, 3, 3, 2])]);
+        assert_that!(contents(&mut overlay)).is_equal_to(vec![0, 1, 1, 3, 3, 2, 6, 7]);
+    }
+
+    #[test]
+    fn writes_past_the_end_leave_zeros() {
+        let mut overlay = overlay();
+        overlay.seek(SeekFrom::Start(10)).unwrap();
+        overlay.write_all(&[9]).unwrap();
+        assert_that!(overlay.len()).is_equal_to(11);
+        assert_that!(contents(&mut overlay)).is_equal_to(vec![0, 1, 2, 3, 4, 5, 6, 7, 0, 0, 9]);
+    }
+
+    #[test]
+    fn truncated_source_is_not_read_again() {
+        let mut overlay = overlay();
+        overlay.seek(SeekFrom::Start(3)).unwrap();
+        overlay.write_all(&[9, 9]).unwrap();
+        overlay.truncate(4).unwrap();
+        overlay.seek(SeekFrom::Start(6)).unwrap();
+        overlay.write_all(&[8]).unwrap();
+        assert_that!(overlay.source_visible()).is_equal_to(4);
+        assert_that!(contents(&mut overlay)).is_equal_to(vec![0, 1, 2, 9, 0, 0, 8]);
+    }
+
+    #[test]
+    fn reading_returns_patched_bytes() {
+        let mut overlay = overlay();
+        overlay.seek(SeekFrom::Start(6)).unwrap();
+        overlay.write_all(&[0xA, 0xB, 0xC]).unwrap();
+        let mut actual = [0; 4];
+        overlay.seek(SeekFrom::Start(5)).unwrap();
+        overlay.read_exact(&mut actual).unwrap();
+        assert_that!(actual).is_equal_to([5, 0xA, 0xB, 0xC]);
+        assert_that!(overlay.read(&mut actual).unwrap()).is_equal_to(0);
+    }
+
+    #[test]
+    fn reset_discards_changes() {
+        let mut overlay = overlay();
+        overlay.write_all(&[9]).unwrap();
+        overlay.truncate(1).unwrap();
+        assert_that!(overlay.is_modified()).is_true();
+        overlay.reset();
+        assert_that!(overlay.is_modified()).is_false();
+        assert_that!(contents(&mut overlay)).is_equal_to((0..8).collect::<Vec<u8>>());
+    }
+
+    #[test]
+    fn exported_patch_recreates_edits() {
+        let source: Vec<u8> = (0..8).collect();
+        let mut overlay = overlay();
+        overlay.seek(SeekFrom::Start(2)).unwrap();
+        overlay.write_all(&[2, 3, 0xA]).unwrap();
+        overlay.truncate(6).unwrap();
+        overlay.seek(SeekFrom::Start(7)).unwrap();
+        overlay.write_all(&[0xB]).unwrap();
+        let patch = overlay.to_ips().unwrap();
+        assert_that!(patch.apply_to_vec(&source).unwrap()).is_equal_to(overlay.to_vec().unwrap());
+        assert_that!(patch.apply_to_vec(&source).unwrap()).is_equal_to(vec![0, 1, 2, 3, 0xA, 5, 0, 0xB]);
+    }
+
+    #[test]
+    fn exported_bps_patch_recreates_edits() {
+        let source: Vec<u8> = (0..8).collect();
+        let mut overlay = overlay();
+        overlay.seek(SeekFrom::Start(2)).unwrap();
+        overlay.write_all(&[2, 3, 0xA]).unwrap();
+        overlay.truncate(6).unwrap();
+        overlay.seek(SeekFrom::Start(7)).unwrap();
+        overlay.write_all(&[0xB]).unwrap();
+        let patch = BPSPatch::from_bytes(&overlay.to_bps().unwrap().to_bytes().unwrap()).unwrap();
+        assert_that!(patch.apply_to_vec(&source).unwrap()).is_equal_to(overlay.to_vec().unwrap());
+        assert_that!(patch.apply_to_vec(&source).unwrap()).is_equal_to(vec![0, 1, 2, 3, 0xA, 5, 0, 0xB]);
+    }
+
+    #[test]
+    fn unmodified_overlay_exports_empty_patch() {
+        let mut overlay = overlay();
+        overlay.write_all(&[0, 1, 2]).unwrap();
+        assert_that!(overlay.to_ips().unwrap().hunks().len()).is_equal_to(0);
+    }
+
+    #[test]
+    fn unaddressable_edits_are_rejected() {
+        let mut overlay = OverlayTarget::new(Cursor::new(vec![0; IPSPatch::MAX_OFFSET as usize + 2])).unwrap();
+        overlay.seek(SeekFrom::End(-1)).unwrap();
+        overlay.write_all(&[1]).unwrap();
+        assert_that!(overlay.to_ips()).is_err();
+    }
+
+    #[test]
+    fn reads_combine_all_layers() {
+        let mut overlay = overlay();
+        overlay.seek(SeekFrom::Start(1)).unwrap();
+        overlay.write_all(&[0xA, 0xA, 0xA]).unwrap();
+        overlay.push_layer();
+        overlay.seek(SeekFrom::Start(3)).unwrap();
+        overlay.write_all(&[0xB, 0xB]).unwrap();
+        assert_that!(overlay.layers().len()).is_equal_to(2);
+        assert_that!(contents(&mut overlay)).is_equal_to(vec![0, 0xA, 0xA, 0xB, 0xB, 5, 6, 7]);
+
+        let popped = overlay.pop_layer().unwrap();
+        assert_that!(popped.extents().count()).is_equal_to(1);
+        assert_that!(contents(&mut overlay)).is_equal_to(vec![0, 0xA, 0xA, 0xA, 4, 5, 6, 7]);
+        assert_that!(overlay.pop_layer()).is_none();
+    }
+
+    #[test]
+    fn truncating_a_layer_hides_the_layers_below() {
+        let mut overlay = overlay();
+        overlay.seek(SeekFrom::Start(5)).unwrap();
+        overlay.write_all(&[0xA]).unwrap();
+        overlay.push_layer();
+        overlay.truncate(3).unwrap();
+        overlay.seek(SeekFrom::Start(7)).unwrap();
+        overlay.write_all(&[0xB]).unwrap();
+        assert_that!(overlay.source_visible()).is_equal_to(3);
+        assert_that!(contents(&mut overlay)).is_equal_to(vec![0, 1, 2, 0, 0, 0, 0, 0xB]);
+    }
+
+    #[test]
+    fn flattening_keeps_contents() {
+        let mut overlay = overlay();
+        overlay.seek(SeekFrom::Start(1)).unwrap();
+        overlay.write_all(&[0xA; 5]).unwrap();
+        overlay.truncate(7).unwrap();
+        overlay.push_layer();
+        overlay.truncate(4).unwrap();
+        overlay.seek(SeekFrom::Start(6)).unwrap();
+        overlay.write_all(&[0xB]).unwrap();
+        overlay.push_layer();
+        overlay.seek(SeekFrom::Start(2)).unwrap();
+        overlay.write_all(&[0xC]).unwrap();
+        let expected = contents(&mut overlay);
+
+        overlay.flatten();
+        assert_that!(overlay.layers().len()).is_equal_to(1);
+        assert_that!(contents(&mut overlay)).is_equal_to(expected.clone());
+        assert_that!(expected).is_equal_to(vec![0, 0xA, 0xC, 0xA, 0, 0, 0xB]);
+    }
+}
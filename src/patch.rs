@@ -0,0 +1,365 @@
+use std::any::Any;
+use std::fmt::Debug;
+use std::io::{Cursor, Result as IOResult, Write};
+
+use crate::apply::{check_max_offset_written, check_not_applied, fix_checksum, ApplyOptions, HeaderHandling};
+use crate::bps::BPSPatch;
+use crate::chain::PatchMetadata;
+use crate::diagnostics::Diagnostic;
+use crate::Error;
+use crate::ErrorKind::PatchingError;
+use crate::header::split_header;
+use crate::ips::IPSPatch;
+use crate::messages::Message;
+use crate::pmsr::PMSRPatch;
+use crate::ppf::PPFPatch;
+use crate::ups::UPSPatch;
+use crate::vcdiff::VCDIFFPatch;
+
+/// What a patch tells about the files it is made for.
+///
+/// Every field is optional since most formats only record some of them, and IPS none at all.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize), serde(default))]
+pub struct PatchInfo {
+    /// CRC-32 of the rom the patch expects.
+    pub source_crc32: Option<u32>,
+    /// length of the rom the patch expects.
+    pub source_len: Option<u64>,
+    /// CRC-32 of the patched rom.
+    pub target_crc32: Option<u32>,
+    /// length of the patched rom.
+    pub target_len: Option<u64>,
+}
+
+/// A parsed patch of any format.
+///
+/// This is the common interface [registered formats](crate::registry) are read into, so code that
+/// does not care about the format of a patch can work with `Box<dyn Patch>`.
+pub trait Patch: Debug + Send + Sync {
+    /// returns the name of the format of the patch, e.g. `"ips"`.
+    fn format(&self) -> &'static str;
+
+    /// applies the patch to `source` and returns the patched file.
+    fn apply_to_vec(&self, source: &[u8]) -> Result<Vec<u8>, Error>;
+
+    /// applies the patch to `source` according to `options` and returns the patched file together
+    /// with the diagnostics found.
+    ///
+    /// The default implementation honors [ApplyOptions::header] and reports [Patch::validate], and
+    /// [Diagnostic::unverified] for [unverified](Patch::is_verified) patches. Unless
+    /// [forced](ApplyOptions::with_force), it refuses to apply the patch again to a rom that
+    /// already has it applied with an [AlreadyApplied](crate::ErrorKind::AlreadyApplied) error.
+    /// Patches changing the rom past [ApplyOptions::max_offset] are refused with a
+    /// [ValidationError](crate::ErrorKind::ValidationError), and the checksum of the output is
+    /// fixed if [ApplyOptions::fix_checksum] is set.
+    fn apply_to_vec_with_options(&self, source: &[u8], options: &ApplyOptions) -> Result<(Vec<u8>, Vec<Diagnostic>), Error> {
+        check_not_applied(self, source, options)?;
+        let mut patched = match options.header() {
+            HeaderHandling::AsIs => {
+                let patched = self.apply_to_vec(source)?;
+                check_max_offset_written(source, &patched, options)?;
+                patched
+            }
+            HeaderHandling::SkipCopierHeader => {
+                let (header, body) = split_header(source);
+                let patched = self.apply_to_vec(body)?;
+                check_max_offset_written(body, &patched, options)?;
+                let mut result = header.to_vec();
+                result.extend(patched);
+                result
+            }
+        };
+        fix_checksum(&mut patched, options);
+        let mut diagnostics = self.validate();
+        if !self.is_verified() {
+            diagnostics.push(Diagnostic::unverified(self.format()));
+        }
+        Ok((patched, diagnostics))
+    }
+
+    /// applies the patch to `buf` in place and returns the length of the patched file, which is
+    /// shorter than `buf` if the patch truncates it.
+    ///
+    /// `buf` is never grown, so patches extending the file past its end are refused with a
+    /// [PatchingError](crate::ErrorKind::PatchingError) before anything is written. This suits
+    /// emulators that preallocate the rom buffer. The default implementation patches a copy of
+    /// `buf` and copies it back.
+    fn apply_in_place(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        let patched = self.apply_to_vec(buf)?;
+        if patched.len() > buf.len() {
+            return Err(Error::new(PatchingError)
+                .with_message(Message::BeyondBuffer { offset: buf.len() as u64, len: buf.len() as u64 }));
+        }
+        buf[..patched.len()].copy_from_slice(&patched);
+        Ok(patched.len())
+    }
+
+    /// returns `true` if the patch records a checksum of its source or target, so applying it to
+    /// the wrong rom can be noticed.
+    fn is_verified(&self) -> bool {
+        let info = self.info();
+        info.source_crc32.is_some() || info.target_crc32.is_some()
+    }
+
+    /// returns everything found wrong with the patch.
+    fn validate(&self) -> Vec<Diagnostic> {
+        Vec::new()
+    }
+
+    /// returns what the patch records about its source and target.
+    fn info(&self) -> PatchInfo {
+        PatchInfo::default()
+    }
+
+    /// returns the metadata the patch carries itself, e.g. its [manifest](PatchMetadata::manifest).
+    ///
+    /// Formats without a metadata field, like IPS, return empty metadata. Dependencies are only
+    /// declared in sidecars, see [crate::chain].
+    fn metadata(&self) -> PatchMetadata {
+        PatchMetadata::default()
+    }
+
+    /// returns the file name the author intends for the patched rom, if the patch carries one.
+    ///
+    /// The name comes straight from the patch, so it has to go through
+    /// [sanitize_file_name](crate::naming::sanitize_file_name) before it is used as a path.
+    fn suggested_output_name(&self) -> Option<String> {
+        self.metadata().output_name
+    }
+
+    /// writes the patch in its format to `writer`.
+    fn write_to(&self, writer: &mut dyn Write) -> IOResult<()>;
+
+    /// returns the patch written in its format.
+    ///
+    /// Formats knowing their size up front override this to allocate only once.
+    fn to_bytes(&self) -> IOResult<Vec<u8>> {
+        let mut result = Vec::new();
+        self.write_to(&mut result)?;
+        Ok(result)
+    }
+
+    /// returns `self` as [Any] so callers can downcast to the concrete patch type.
+    fn as_any(&self) -> &dyn Any;
+}
+
+impl Patch for IPSPatch {
+    fn format(&self) -> &'static str {
+        "ips"
+    }
+
+    fn apply_to_vec(&self, source: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut target = Cursor::new(source.to_vec());
+        self.apply(&mut target)?;
+        Ok(target.into_inner())
+    }
+
+    fn apply_to_vec_with_options(&self, source: &[u8], options: &ApplyOptions) -> Result<(Vec<u8>, Vec<Diagnostic>), Error> {
+        check_not_applied(self, source, options)?;
+        let mut target = Cursor::new(source.to_vec());
+        let mut diagnostics = self.apply_with_options(&mut target, options)?;
+        diagnostics.push(Diagnostic::unverified(Patch::format(self)));
+        let mut patched = target.into_inner();
+        fix_checksum(&mut patched, options);
+        Ok((patched, diagnostics))
+    }
+
+    fn apply_in_place(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        IPSPatch::apply_in_place(self, buf)
+    }
+
+    fn validate(&self) -> Vec<Diagnostic> {
+        IPSPatch::validate(self)
+    }
+
+    fn write_to(&self, mut writer: &mut dyn Write) -> IOResult<()> {
+        self.write(&mut writer)
+    }
+
+    fn to_bytes(&self) -> IOResult<Vec<u8>> {
+        IPSPatch::to_bytes(self)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl Patch for PMSRPatch {
+    fn format(&self) -> &'static str {
+        "pmsr"
+    }
+
+    fn apply_to_vec(&self, source: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut target = Cursor::new(source.to_vec());
+        self.apply(&mut target)?;
+        Ok(target.into_inner())
+    }
+
+    fn info(&self) -> PatchInfo {
+        PatchInfo {
+            source_crc32: Some(PMSRPatch::SOURCE_CRC32),
+            source_len: Some(PMSRPatch::SOURCE_SIZE as u64),
+            ..PatchInfo::default()
+        }
+    }
+
+    fn write_to(&self, mut writer: &mut dyn Write) -> IOResult<()> {
+        self.write(&mut writer)
+    }
+
+    fn to_bytes(&self) -> IOResult<Vec<u8>> {
+        PMSRPatch::to_bytes(self)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl Patch for UPSPatch {
+    fn format(&self) -> &'static str {
+        "ups"
+    }
+
+    fn apply_to_vec(&self, source: &[u8]) -> Result<Vec<u8>, Error> {
+        UPSPatch::apply_to_vec(self, source)
+    }
+
+    fn info(&self) -> PatchInfo {
+        PatchInfo {
+            source_crc32: Some(self.source_crc32()),
+            source_len: Some(self.source_len()),
+            target_crc32: Some(self.target_crc32()),
+            target_len: Some(self.target_len()),
+        }
+    }
+
+    fn write_to(&self, mut writer: &mut dyn Write) -> IOResult<()> {
+        self.write(&mut writer)
+    }
+
+    fn to_bytes(&self) -> IOResult<Vec<u8>> {
+        UPSPatch::to_bytes(self)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl Patch for BPSPatch {
+    fn format(&self) -> &'static str {
+        "bps"
+    }
+
+    fn apply_to_vec(&self, source: &[u8]) -> Result<Vec<u8>, Error> {
+        BPSPatch::apply_to_vec(self, source)
+    }
+
+    fn info(&self) -> PatchInfo {
+        PatchInfo {
+            source_crc32: Some(self.source_crc32()),
+            source_len: Some(self.source_len()),
+            target_crc32: Some(self.target_crc32()),
+            target_len: Some(self.target_len()),
+        }
+    }
+
+    fn metadata(&self) -> PatchMetadata {
+        let mut result = PatchMetadata::new();
+        if !self.manifest().is_empty() {
+            result = result.with_manifest(self.manifest());
+        }
+        return result;
+    }
+
+    fn write_to(&self, mut writer: &mut dyn Write) -> IOResult<()> {
+        self.write(&mut writer)
+    }
+
+    fn to_bytes(&self) -> IOResult<Vec<u8>> {
+        BPSPatch::to_bytes(self)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl Patch for PPFPatch {
+    fn format(&self) -> &'static str {
+        "ppf"
+    }
+
+    fn apply_to_vec(&self, source: &[u8]) -> Result<Vec<u8>, Error> {
+        PPFPatch::apply_to_vec(self, source)
+    }
+
+    /// PPF patches record no checksums, but a patch checking a block of the image refuses others.
+    fn is_verified(&self) -> bool {
+        self.block().is_some()
+    }
+
+    fn info(&self) -> PatchInfo {
+        PatchInfo {
+            source_len: self.source_len(),
+            ..PatchInfo::default()
+        }
+    }
+
+    fn metadata(&self) -> PatchMetadata {
+        let mut result = PatchMetadata::new();
+        if let Some(file_id) = self.file_id() {
+            result = result.with_manifest(file_id);
+        }
+        return result;
+    }
+
+    fn write_to(&self, mut writer: &mut dyn Write) -> IOResult<()> {
+        self.write(&mut writer)
+    }
+
+    fn to_bytes(&self) -> IOResult<Vec<u8>> {
+        PPFPatch::to_bytes(self)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl Patch for VCDIFFPatch {
+    fn format(&self) -> &'static str {
+        "vcdiff"
+    }
+
+    fn apply_to_vec(&self, source: &[u8]) -> Result<Vec<u8>, Error> {
+        VCDIFFPatch::apply_to_vec(self, source)
+    }
+
+    /// Only delta files whose windows all carry the Adler-32 checksum of xdelta3 are verified.
+    fn is_verified(&self) -> bool {
+        self.has_checksums()
+    }
+
+    fn info(&self) -> PatchInfo {
+        PatchInfo {
+            target_len: Some(self.target_len()),
+            ..PatchInfo::default()
+        }
+    }
+
+    fn write_to(&self, mut writer: &mut dyn Write) -> IOResult<()> {
+        self.write(&mut writer)
+    }
+
+    fn to_bytes(&self) -> IOResult<Vec<u8>> {
+        VCDIFFPatch::to_bytes(self)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
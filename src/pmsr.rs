@@ -0,0 +1,393 @@
+//! Paper Mario Star Rod mods (`.mod`).
+//!
+//! Star Rod mods consist of the magic `PMSR`, a big-endian record count and the records. Each record
+//! is a big-endian offset and length followed by the data written at that offset. Mods are always
+//! made against Paper Mario (USA) 1.0, which [PMSRPatch::validate_source] checks for.
+
+use std::fmt::{Display, Formatter};
+use std::io::{ErrorKind, Read, Result as IOResult, Seek, SeekFrom, Write};
+
+use crate::compression::yay0;
+use crate::create::{Diff, DiffTarget};
+use crate::Error;
+use crate::ErrorKind::{CreatingError, ParsingError, PatchingError};
+use crate::io_util::{AssertRead, ReaderExtensions};
+use crate::messages::Message;
+
+/// A record of a Star Rod mod.
+///
+/// Applying the record writes `data` verbatim at `offset`. Data of assets that are compressed in the
+/// rom is stored compressed as well, see [PMSRRecord::is_compressed].
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PMSRRecord {
+    /// the offset to write data at, narrowed to the four bytes Star Rod mods store when written.
+    pub offset: u64,
+    /// the data to write.
+    pub data: Box<[u8]>,
+}
+
+impl PMSRRecord {
+    /// returns `true` if the data of the record is a Yay0 compressed section.
+    pub fn is_compressed(&self) -> bool {
+        yay0::is_yay0(&self.data)
+    }
+
+    /// returns the data of the record, decompressed if it [is compressed](PMSRRecord::is_compressed).
+    pub fn decompressed(&self) -> Result<Vec<u8>, Error> {
+        if self.is_compressed() {
+            yay0::decompress(&self.data)
+        } else {
+            Ok(self.data.to_vec())
+        }
+    }
+
+    /// writes `self` to `writer`.
+    fn write(&self, writer: &mut impl Write) -> IOResult<()> {
+        writer.write_all(&checked_u32(self.offset, "record offset")?.to_be_bytes())?;
+        writer.write_all(&checked_u32(self.data.len() as u64, "record length")?.to_be_bytes())?;
+        writer.write_all(&self.data)?;
+        Ok(())
+    }
+
+    /// reads a [PMSRRecord] from `reader`.
+    fn read(reader: &mut impl Read) -> Result<PMSRRecord, Error> {
+        let offset = reader.read_u32_be("Unable to read record offset.".to_string())?;
+        let length = reader.read_u32_be("Unable to read record length.".to_string())?;
+        // don't trust the length for allocating, the data may end early
+        let mut data = Vec::new();
+        reader.take(length as u64).read_to_end(&mut data)
+            .map_err(|_| Error::new(ParsingError).with_description("Unable to read record data.".to_string()))?;
+        if data.len() != length as usize {
+            return Err(Error::new(ParsingError).with_description("Unable to read record data.".to_string()));
+        }
+        Ok(PMSRRecord {
+            offset: offset as u64,
+            data: data.into_boxed_slice(),
+        })
+    }
+
+    /// Applies the record to `target`.
+    fn apply<T>(&self, target: &mut T) -> Result<(), Error> where T: Seek + Write + ?Sized {
+        target.seek(SeekFrom::Start(self.offset))
+            .and_then(|_| target.write_all(&self.data))
+            .map_err(|_| Error::new(PatchingError).with_description("Unable to apply Star Rod record.".to_string()))
+    }
+}
+
+/// Formats the record as a one-line summary, e.g. `record at 0x00000002 writing 2 bytes`.
+impl Display for PMSRRecord {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "record at {:#010X} writing {} bytes", self.offset, self.data.len())?;
+        if self.is_compressed() {
+            write!(f, " (Yay0 compressed)")?;
+        }
+        Ok(())
+    }
+}
+
+/// Represents a Paper Mario Star Rod mod.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct PMSRPatch {
+    /// records in the order they are applied.
+    records: Vec<PMSRRecord>,
+}
+
+impl PMSRPatch {
+    /// Patch header for Star Rod mods.
+    pub const HEADER: &'static [u8] = "PMSR".as_bytes();
+
+    /// CRC-32 of Paper Mario (USA) 1.0, the only rom Star Rod mods apply to.
+    pub const SOURCE_CRC32: u32 = 0xA7F5CD7E;
+
+    /// size of Paper Mario (USA) 1.0.
+    pub const SOURCE_SIZE: usize = 0x2800000;
+
+    /// constructs an empty [PMSRPatch].
+    pub const fn new() -> PMSRPatch {
+        PMSRPatch { records: Vec::new() }
+    }
+
+    /// returns the records of the patch in the order they are applied.
+    pub fn records(&self) -> &[PMSRRecord] {
+        &self.records
+    }
+
+    /// adds `record` to patch.
+    pub fn add_record(&mut self, record: PMSRRecord) {
+        self.records.push(record);
+    }
+
+    /// returns a new patch with a given `record`.
+    pub fn with_record(mut self, record: PMSRRecord) -> Self {
+        self.add_record(record);
+        return self;
+    }
+
+    /// creates a patch from `diff`, the differences between some source and `target`.
+    ///
+    /// Returns a [CreatingError] if `target` is shorter than the source, since Star Rod mods can't
+    /// shrink the rom.
+    pub fn from_diff(diff: &Diff, target: &[u8]) -> Result<PMSRPatch, Error> {
+        Self::from_diff_target(diff, &mut &target[..])
+    }
+
+    /// creates a patch from `diff`, reading the changed bytes from `target`.
+    pub(crate) fn from_diff_target(diff: &Diff, target: &mut dyn DiffTarget) -> Result<PMSRPatch, Error> {
+        if diff.target_len < diff.source_len {
+            return Err(Error::new(CreatingError)
+                .with_description("Star Rod mods can't truncate the rom.".to_string()));
+        }
+        let mut result = PMSRPatch::new();
+        for region in &diff.regions {
+            if region.start > u32::MAX as u64 || region.end - region.start > u32::MAX as u64 {
+                return Err(Error::new(CreatingError)
+                    .with_message(Message::BeyondFormatLimit { format: "Star Rod".to_string(), offset: region.start }));
+            }
+            result.add_record(PMSRRecord {
+                offset: region.start,
+                data: target.read_range(region.clone())?.into(),
+            });
+        }
+        Ok(result)
+    }
+
+    /// returns `true` if `source` is Paper Mario (USA) 1.0.
+    pub fn validate_source(source: &[u8]) -> bool {
+        source.len() == Self::SOURCE_SIZE && crate::hash::Crc32::checksum(source) == Self::SOURCE_CRC32
+    }
+
+    /// returns the length a file of `source_len` bytes has after applying the patch.
+    pub fn patched_len(&self, source_len: u64) -> u64 {
+        self.records.iter()
+            .map(|x| x.offset.saturating_add(x.data.len() as u64))
+            .fold(source_len, u64::max)
+    }
+
+    /// writes `self` to `writer`.
+    pub fn write(&self, writer: &mut impl Write) -> IOResult<()> {
+        writer.write_all(Self::HEADER)?;
+        writer.write_all(&checked_u32(self.records.len() as u64, "record count")?.to_be_bytes())?;
+        for record in &self.records {
+            record.write(writer)?;
+        }
+        Ok(())
+    }
+
+    /// returns the amount of bytes [PMSRPatch::write] writes.
+    pub fn serialized_len(&self) -> usize {
+        Self::HEADER.len() + 4 + self.records.iter().map(|x| 8 + x.data.len()).sum::<usize>()
+    }
+
+    /// appends `self` to `buf`, growing it at most once.
+    pub fn write_to_vec(&self, buf: &mut Vec<u8>) -> IOResult<()> {
+        buf.reserve_exact(self.serialized_len());
+        self.write(buf)
+    }
+
+    /// returns `self` as written by [PMSRPatch::write].
+    pub fn to_bytes(&self) -> IOResult<Vec<u8>> {
+        let mut result = Vec::new();
+        self.write_to_vec(&mut result)?;
+        Ok(result)
+    }
+
+    /// reads a [PMSRPatch] from `data`, see [PMSRPatch::read_from].
+    pub fn from_bytes(data: &[u8]) -> Result<PMSRPatch, Error> {
+        Self::read_from(&mut &data[..])
+    }
+
+    /// Reads a [PMSRPatch] from `reader`.
+    pub fn read_from(reader: &mut impl Read) -> Result<PMSRPatch, Error> {
+        reader.assert_read(
+            Self::HEADER,
+            "Unable to parse header.".to_string(),
+            "Invalid header.".to_string(),
+        )?;
+        let count = reader.read_u32_be("Unable to read record count.".to_string())?;
+        let mut result = PMSRPatch::new();
+        for _ in 0..count {
+            result.add_record(PMSRRecord::read(reader)?);
+        }
+        Ok(result)
+    }
+
+    /// Applies the patch to `target`. Records past the end of `target` extend it.
+    pub fn apply<T>(&self, target: &mut T) -> Result<(), Error> where T: Write + Seek + ?Sized {
+        for record in &self.records {
+            record.apply(target)?;
+        }
+        Ok(())
+    }
+}
+
+/// Formats the mod as a one-line summary, e.g. `Star Rod mod with 2 records writing 3 bytes`.
+///
+/// The alternate form `{:#}` lists every record on a line of its own below the summary.
+impl Display for PMSRPatch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let written: usize = self.records.iter().map(|x| x.data.len()).sum();
+        let plural = if self.records.len() == 1 { "" } else { "s" };
+        write!(f, "Star Rod mod with {} record{} writing {} bytes", self.records.len(), plural, written)?;
+        if f.alternate() {
+            for (i, record) in self.records.iter().enumerate() {
+                write!(f, "\n  {}: {}", i, record)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// returns `value` if it fits the four bytes Star Rod mods store `field` in.
+fn checked_u32(value: u64, field: &str) -> IOResult<u32> {
+    u32::try_from(value)
+        .map_err(|_| std::io::Error::new(ErrorKind::InvalidInput, format!("Star Rod mods can't store a {} of {}.", field, value)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use spectral::prelude::*;
+
+    use crate::test_util::BuildVec;
+
+    use super::*;
+
+    fn patch() -> PMSRPatch {
+        PMSRPatch::new()
+            .with_record(PMSRRecord {
+                offset: 2,
+                data: Box::new([0xAA, 0xBB]),
+            })
+            .with_record(PMSRRecord {
+                offset: 0x10203,
+                data: Box::new([0xCC]),
+            })
+    }
+
+    fn patch_data() -> Vec<u8> {
+        Vec::new()
+            .build_with_slice(PMSRPatch::HEADER)
+            .build_with_slice(&[0, 0, 0, 2]) // record count
+            .build_with_slice(&[0, 0, 0, 2]) // offset
+            .build_with_slice(&[0, 0, 0, 2]) // length
+            .build_with_slice(&[0xAA, 0xBB]) // data
+            .build_with_slice(&[0, 1, 2, 3]) // offset
+            .build_with_slice(&[0, 0, 0, 1]) // length
+            .build_with_slice(&[0xCC]) // data
+    }
+
+    #[test]
+    fn write_records() {
+        let mut actual = Vec::new();
+        patch().write(&mut actual).unwrap();
+        assert_that!(actual).is_equal_to(patch_data());
+    }
+
+    #[test]
+    fn to_bytes_preallocates_exactly() {
+        let data = patch().to_bytes().unwrap();
+        assert_that!(patch().serialized_len()).is_equal_to(data.len());
+        assert_that!(data.capacity()).is_equal_to(data.len());
+        assert_that!(PMSRPatch::from_bytes(&data).unwrap()).is_equal_to(patch());
+    }
+
+    #[test]
+    fn offsets_are_narrowed_when_written() {
+        let patch = PMSRPatch::new().with_record(PMSRRecord { offset: u32::MAX as u64 + 1, data: Box::new([1]) });
+        assert_that!(patch.write(&mut Vec::new())).is_err();
+        assert_that!(patch.patched_len(0)).is_equal_to(u32::MAX as u64 + 2);
+    }
+
+    #[test]
+    fn read_records() {
+        let actual = PMSRPatch::read_from(&mut patch_data().as_slice()).unwrap();
+        assert_that!(actual).is_equal_to(patch());
+    }
+
+    #[test]
+    fn display_summarizes_records() {
+        assert_that!(patch().to_string()).is_equal_to("Star Rod mod with 2 records writing 3 bytes".to_string());
+        let expected = "Star Rod mod with 2 records writing 3 bytes\n  \
+            0: record at 0x00000002 writing 2 bytes\n  \
+            1: record at 0x00010203 writing 1 bytes";
+        assert_that!(format!("{:#}", patch())).is_equal_to(expected.to_string());
+    }
+
+    #[test]
+    fn invalid_header() {
+        let data = Vec::new().build_with_slice(b"IPSR\0\0\0\0");
+        let err = PMSRPatch::read_from(&mut data.as_slice()).unwrap_err();
+        assert_that!(err.to_string()).is_equal_to("ParsingError: Invalid header.".to_string());
+    }
+
+    #[test]
+    fn truncated_record_data() {
+        let mut data = patch_data();
+        data.pop();
+        let err = PMSRPatch::read_from(&mut data.as_slice()).unwrap_err();
+        assert_that!(err.to_string()).is_equal_to("ParsingError: Unable to read record data.".to_string());
+    }
+
+    #[test]
+    fn apply_writes_records_and_extends_target() {
+        let patch = PMSRPatch::new()
+            .with_record(PMSRRecord {
+                offset: 1,
+                data: Box::new([0xA, 0xB]),
+            })
+            .with_record(PMSRRecord {
+                offset: 5,
+                data: Box::new([0xC]),
+            });
+        let mut target = Cursor::new(vec![0, 1, 2, 3]);
+        patch.apply(&mut target).unwrap();
+        assert_that!(target.into_inner()).is_equal_to(vec![0, 0xA, 0xB, 3, 0, 0xC]);
+        assert_that!(patch.patched_len(4)).is_equal_to(6);
+    }
+
+    #[test]
+    fn records_past_max_offset_are_refused() {
+        use crate::apply::ApplyOptions;
+        use crate::patch::Patch;
+
+        let patch = PMSRPatch::new().with_record(PMSRRecord { offset: 3, data: Box::new([1, 2]) });
+        let options = ApplyOptions::new().with_max_offset(Some(3));
+        let err = patch.apply_to_vec_with_options(&[0; 8], &options).unwrap_err();
+        assert_that!(err.to_string())
+            .is_equal_to("ValidationError: The patch writes to offset 4, past the maximum offset 3.".to_string());
+        assert_that!(patch.apply_to_vec_with_options(&[0; 3], &options)).is_err();
+        assert_that!(patch.apply_to_vec_with_options(&[0, 0, 0, 1, 2], &options.with_force(true))).is_ok();
+    }
+
+    #[test]
+    fn apply_in_place_refuses_records_past_the_buffer() {
+        use crate::patch::Patch;
+
+        let patch = PMSRPatch::new().with_record(PMSRRecord { offset: 3, data: Box::new([1, 2]) });
+        let mut buf = [0; 5];
+        assert_that!(patch.apply_in_place(&mut buf).unwrap()).is_equal_to(5);
+        assert_that!(buf).is_equal_to([0, 0, 0, 1, 2]);
+        assert_that!(patch.apply_in_place(&mut [0; 4])).is_err();
+    }
+
+    #[test]
+    fn compressed_records_can_be_decompressed() {
+        let record = PMSRRecord {
+            offset: 0,
+            data: Vec::new()
+                .build_with_slice(yay0::MAGIC)
+                .build_with_slice(&[0, 0, 0, 9, 0, 0, 0, 0x14, 0, 0, 0, 0x16])
+                .build_with_slice(&[0xE0, 0, 0, 0, 0x40, 0x02])
+                .build_with_slice(b"ABC")
+                .into_boxed_slice(),
+        };
+        assert_that!(record.is_compressed()).is_true();
+        assert_that!(record.decompressed().unwrap()).is_equal_to(b"ABCABCABC".to_vec());
+    }
+
+    #[test]
+    fn validate_source_rejects_other_roms() {
+        assert_that!(PMSRPatch::validate_source(&[0; 16])).is_false();
+    }
+}
@@ -0,0 +1,727 @@
+//! PPF 2.0 and 3.0 patches (`.ppf`), the format PlayStation patches are distributed in.
+//!
+//! PPF 3.0 patches consist of the magic `PPF30`, the encoding method `0x02`, a description of 50
+//! bytes, the type of the image, whether the patch checks a block of the image and whether it
+//! carries undo data, each a byte, and an unused byte. The block of 1024 bytes the image has to
+//! match follows if the patch checks one. Every record is its offset as a little-endian 64 bit
+//! integer, its length as a byte, its data and, with undo data, the bytes it overwrites.
+//!
+//! PPF 2.0 patches start with the magic `PPF20`, the encoding method `0x01` and the description,
+//! followed by the size of the image as a little-endian 32 bit integer and the block, which they
+//! always check. Their records have 32 bit offsets and never carry undo data.
+//!
+//! A patch may end with a `FILE_ID.DIZ` text between `@BEGIN_FILE_ID.DIZ` and `@END_FILE_ID.DIZ`,
+//! followed by its length as a little-endian integer of 16 bits for PPF 3.0 and 32 bits for
+//! PPF 2.0.
+
+use std::fmt::{Display, Formatter};
+use std::io::{Cursor, Read, Result as IOResult, Seek, SeekFrom, Write};
+
+use crate::create::Diff;
+use crate::Error;
+use crate::ErrorKind::{CreatingError, ParsingError, PatchingError, ValidationError, WrongSource};
+use crate::io_util::U64Extensions;
+
+/// The version of the PPF format a [PPFPatch] is written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum PPFVersion {
+    /// PPF 2.0, which always checks a block of a BIN image and records its size, but can't carry
+    /// undo data.
+    V2,
+    /// PPF 3.0.
+    #[default]
+    V3,
+}
+
+impl PPFVersion {
+    /// returns the magic patches of this version start with.
+    pub fn header(&self) -> &'static [u8] {
+        match self {
+            PPFVersion::V2 => PPFPatch::HEADER_V2,
+            PPFVersion::V3 => PPFPatch::HEADER,
+        }
+    }
+
+    /// returns the encoding method byte following the magic.
+    fn encoding(&self) -> u8 {
+        match self {
+            PPFVersion::V2 => 0x01,
+            PPFVersion::V3 => 0x02,
+        }
+    }
+}
+
+/// The kind of disc image a [PPFPatch] is made for, which decides where its block check lies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum PPFImageType {
+    /// a raw BIN or ISO image.
+    #[default]
+    Bin,
+    /// a PrimoDVD GI image.
+    Gi,
+}
+
+impl PPFImageType {
+    /// returns the offset of the block a patch for this type of image checks.
+    pub fn block_offset(&self) -> u64 {
+        match self {
+            PPFImageType::Bin => 0x9320,
+            PPFImageType::Gi => 0x80A0,
+        }
+    }
+}
+
+/// A record of a PPF patch.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PPFRecord {
+    /// the offset of the first byte to write.
+    pub offset: u64,
+    /// the bytes to write. Records of more than 255 bytes are split when written.
+    pub data: Box<[u8]>,
+    /// the bytes of the image `data` overwrites, as long as `data`, if the patch can be undone.
+    pub undo: Option<Box<[u8]>>,
+}
+
+impl PPFRecord {
+    /// returns the offset past the last byte the record writes.
+    pub fn end(&self) -> u64 {
+        self.offset.saturating_add(self.data.len() as u64)
+    }
+}
+
+/// Formats the record as a one-line summary, e.g. `record at 0x00009320 writing 4 bytes`.
+impl Display for PPFRecord {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "record at {:#010X} writing {} bytes", self.offset, self.data.len())
+    }
+}
+
+/// Represents a PPF 2.0 or 3.0 patch.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct PPFPatch {
+    version: PPFVersion,
+    description: String,
+    /// the size of the image, only recorded by PPF 2.0.
+    source_len: Option<u64>,
+    image_type: PPFImageType,
+    block: Option<Box<[u8]>>,
+    /// records in the order they are applied.
+    records: Vec<PPFRecord>,
+    file_id: Option<String>,
+}
+
+impl PPFPatch {
+    /// Patch header for PPF 3.0 patches.
+    pub const HEADER: &'static [u8] = "PPF30".as_bytes();
+
+    /// Patch header for PPF 2.0 patches.
+    pub const HEADER_V2: &'static [u8] = "PPF20".as_bytes();
+
+    /// Length of the block a patch checks.
+    pub const BLOCK_LEN: usize = 1024;
+
+    /// Length of the description.
+    const DESCRIPTION_LEN: usize = 50;
+
+    /// Length of everything in front of the block.
+    const HEADER_LEN: usize = 60;
+
+    /// Longest record the format can store.
+    const MAX_RECORD_LEN: usize = 0xFF;
+
+    const BEGIN_FILE_ID: &'static [u8] = b"@BEGIN_FILE_ID.DIZ";
+    const END_FILE_ID: &'static [u8] = b"@END_FILE_ID.DIZ";
+
+    /// constructs an empty PPF 3.0 patch for a BIN image.
+    pub fn new() -> PPFPatch {
+        PPFPatch::default()
+    }
+
+    /// creates a PPF 3.0 patch turning `source` into `target`, carrying undo data and checking the
+    /// block of `source` if it is long enough to have one.
+    ///
+    /// Returns a [CreatingError] if `target` is shorter than `source`, since PPF patches can't
+    /// truncate the image.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rom_patcher::ppf::PPFPatch;
+    /// let patch = PPFPatch::create(&[0, 1, 2, 3], &[0, 9, 2, 3, 4]).unwrap();
+    /// assert_eq!(patch.records().len(), 2);
+    /// assert_eq!(patch.apply_to_vec(&[0, 1, 2, 3]).unwrap(), vec![0, 9, 2, 3, 4]);
+    /// assert_eq!(patch.revert_to_vec(&[0, 9, 2, 3, 4]).unwrap(), vec![0, 1, 2, 3, 0]);
+    /// ```
+    pub fn create(source: &[u8], target: &[u8]) -> Result<PPFPatch, Error> {
+        if target.len() < source.len() {
+            return Err(Error::new(CreatingError)
+                .with_description("PPF patches can't truncate the image.".to_string()));
+        }
+        let mut result = PPFPatch::new().with_block_check(source);
+        for region in Diff::new(source, target).regions {
+            let (start, end) = (region.start.to_index(), region.end.to_index());
+            let undo: Vec<u8> = (start..end).map(|i| *source.get(i).unwrap_or(&0)).collect();
+            result.records.push(PPFRecord { offset: region.start, data: target[start..end].into(), undo: Some(undo.into()) });
+        }
+        return Ok(result);
+    }
+
+    /// returns the version of the format the patch is written in.
+    pub fn version(&self) -> PPFVersion {
+        self.version
+    }
+
+    /// returns a new patch written in `version` of the format.
+    ///
+    /// PPF 2.0 patches only apply to BIN images, have to check a block and record the size of the
+    /// image, see [PPFPatch::write].
+    pub fn with_version(mut self, version: PPFVersion) -> Self {
+        self.version = version;
+        return self;
+    }
+
+    /// returns the size of the image the patch applies to, if it records one.
+    pub fn source_len(&self) -> Option<u64> {
+        self.source_len
+    }
+
+    /// returns a new patch only applying to images of `source_len` bytes. Only PPF 2.0 patches
+    /// write it.
+    pub fn with_source_len(mut self, source_len: u64) -> Self {
+        self.source_len = Some(source_len);
+        return self;
+    }
+
+    /// returns the description of the patch.
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+
+    /// returns a new patch described by `description`, which is stored in 50 bytes.
+    pub fn with_description(mut self, description: impl Into<String>) -> Self {
+        self.description = description.into();
+        return self;
+    }
+
+    /// returns the kind of image the patch is made for.
+    pub fn image_type(&self) -> PPFImageType {
+        self.image_type
+    }
+
+    /// returns a new patch made for images of `image_type`.
+    pub fn with_image_type(mut self, image_type: PPFImageType) -> Self {
+        self.image_type = image_type;
+        return self;
+    }
+
+    /// returns the block the image has to match at the [block offset](PPFImageType::block_offset)
+    /// of its type, if the patch checks one.
+    pub fn block(&self) -> Option<&[u8]> {
+        self.block.as_deref()
+    }
+
+    /// returns a new patch only applying to images matching the block of `image` at the
+    /// [block offset](PPFImageType::block_offset) of the image type, or checking no block if
+    /// `image` is too short to have one.
+    pub fn with_block_check(mut self, image: &[u8]) -> Self {
+        let start = self.image_type.block_offset().to_index();
+        self.block = image.get(start..start + Self::BLOCK_LEN).map(Box::from);
+        return self;
+    }
+
+    /// returns the `FILE_ID.DIZ` text of the patch, if it has one.
+    pub fn file_id(&self) -> Option<&str> {
+        self.file_id.as_deref()
+    }
+
+    /// returns a new patch carrying the `FILE_ID.DIZ` text `file_id`.
+    pub fn with_file_id(mut self, file_id: impl Into<String>) -> Self {
+        self.file_id = Some(file_id.into());
+        return self;
+    }
+
+    /// returns the records of the patch in the order they are applied.
+    pub fn records(&self) -> &[PPFRecord] {
+        &self.records
+    }
+
+    /// adds `record` to the patch, applied after the records added before.
+    pub fn add_record(&mut self, record: PPFRecord) {
+        self.records.push(record);
+    }
+
+    /// returns a new patch with a given `record`.
+    pub fn with_record(mut self, record: PPFRecord) -> Self {
+        self.add_record(record);
+        return self;
+    }
+
+    /// returns `true` if every record carries undo data, so the patch can be
+    /// [reverted](PPFPatch::revert). PPF 2.0 patches never do.
+    pub fn has_undo(&self) -> bool {
+        !self.records.is_empty() && self.records.iter().all(|x| x.undo.is_some())
+    }
+
+    /// writes `self` to `writer`.
+    ///
+    /// Returns an error of kind [InvalidInput](std::io::ErrorKind::InvalidInput) if the description
+    /// is longer than 50 bytes, if only some records carry undo data or if undo data isn't as long
+    /// as the data of its record. PPF 2.0 patches also have to check a block of a BIN image, record
+    /// its size, carry no undo data and only write below 4 GiB.
+    pub fn write(&self, writer: &mut impl Write) -> IOResult<()> {
+        writer.write_all(&self.to_bytes()?)
+    }
+
+    /// returns `self` as written by [PPFPatch::write].
+    pub fn to_bytes(&self) -> IOResult<Vec<u8>> {
+        let invalid = |message: String| std::io::Error::new(std::io::ErrorKind::InvalidInput, message);
+        if self.description.len() > Self::DESCRIPTION_LEN {
+            return Err(invalid(format!("PPF descriptions can't be longer than {} bytes.", Self::DESCRIPTION_LEN)));
+        }
+        let undo = self.has_undo();
+        if !undo && self.records.iter().any(|x| x.undo.is_some()) {
+            return Err(invalid("Either all or none of the records of a PPF patch carry undo data.".to_string()));
+        }
+        if let Some(record) = self.records.iter().find(|x| x.undo.as_ref().is_some_and(|u| u.len() != x.data.len())) {
+            return Err(invalid(format!("The undo data of the record at {} isn't as long as its data.", record.offset)));
+        }
+        let file_id_max = match self.version {
+            PPFVersion::V2 => u32::MAX as usize,
+            PPFVersion::V3 => u16::MAX as usize,
+        };
+        if self.file_id.as_ref().is_some_and(|x| x.len() > file_id_max) {
+            return Err(invalid(format!("FILE_ID.DIZ texts can't be longer than {} bytes.", file_id_max)));
+        }
+
+        let mut result = Vec::new();
+        result.extend_from_slice(self.version.header());
+        result.push(self.version.encoding());
+        result.extend_from_slice(self.description.as_bytes());
+        result.resize(6 + Self::DESCRIPTION_LEN, b' ');
+        match self.version {
+            PPFVersion::V2 => {
+                if self.image_type != PPFImageType::Bin {
+                    return Err(invalid("PPF 2.0 patches only apply to BIN images.".to_string()));
+                }
+                if undo {
+                    return Err(invalid("PPF 2.0 patches can't carry undo data.".to_string()));
+                }
+                if self.records.iter().any(|x| x.end() > u32::MAX as u64) {
+                    return Err(invalid("PPF 2.0 patches can't write past 4 GiB.".to_string()));
+                }
+                let source_len = self.source_len.and_then(|x| u32::try_from(x).ok())
+                    .ok_or_else(|| invalid("PPF 2.0 patches have to record a size of the image below 4 GiB.".to_string()))?;
+                let block = self.block.as_ref()
+                    .ok_or_else(|| invalid("PPF 2.0 patches always check a block of the image.".to_string()))?;
+                result.extend_from_slice(&source_len.to_le_bytes());
+                result.extend_from_slice(block);
+            }
+            PPFVersion::V3 => {
+                result.push(match self.image_type {
+                    PPFImageType::Bin => 0,
+                    PPFImageType::Gi => 1,
+                });
+                result.push(self.block.is_some() as u8);
+                result.push(undo as u8);
+                result.push(0);
+                if let Some(block) = &self.block {
+                    result.extend_from_slice(block);
+                }
+            }
+        }
+        for record in &self.records {
+            for (i, data) in record.data.chunks(Self::MAX_RECORD_LEN).enumerate() {
+                let start = i * Self::MAX_RECORD_LEN;
+                let offset = record.offset + start as u64;
+                match self.version {
+                    PPFVersion::V2 => result.extend_from_slice(&(offset as u32).to_le_bytes()),
+                    PPFVersion::V3 => result.extend_from_slice(&offset.to_le_bytes()),
+                }
+                result.push(data.len() as u8);
+                result.extend_from_slice(data);
+                if let Some(undo) = &record.undo {
+                    result.extend_from_slice(&undo[start..start + data.len()]);
+                }
+            }
+        }
+        if let Some(file_id) = &self.file_id {
+            result.extend_from_slice(Self::BEGIN_FILE_ID);
+            result.extend_from_slice(file_id.as_bytes());
+            result.extend_from_slice(Self::END_FILE_ID);
+            match self.version {
+                PPFVersion::V2 => result.extend_from_slice(&(file_id.len() as u32).to_le_bytes()),
+                PPFVersion::V3 => result.extend_from_slice(&(file_id.len() as u16).to_le_bytes()),
+            }
+        }
+        Ok(result)
+    }
+
+    /// reads a [PPFPatch] from `data`, see [PPFPatch::read_from].
+    pub fn from_bytes(data: &[u8]) -> Result<PPFPatch, Error> {
+        Self::read_from(&mut &data[..])
+    }
+
+    /// Reads a [PPFPatch] from `reader`.
+    ///
+    /// Both PPF 2.0 and 3.0 patches are read. The description and `FILE_ID.DIZ` text are read with
+    /// invalid UTF-8 replaced, and the padding of the description is trimmed.
+    pub fn read_from(reader: &mut impl Read) -> Result<PPFPatch, Error> {
+        let parsing_error = |message: &str| Error::new(ParsingError).with_description(message.to_string());
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)
+            .map_err(|e| Error::new(ParsingError)
+                .with_description("Unable to read patch.".to_string())
+                .with_source(Box::new(e)))?;
+        if data.len() < Self::HEADER_LEN {
+            return Err(parsing_error("Unable to parse header."));
+        }
+        let version = [PPFVersion::V2, PPFVersion::V3].into_iter()
+            .find(|x| &data[..5] == x.header() && data[5] == x.encoding())
+            .ok_or_else(|| parsing_error("Invalid header."))?;
+        let description = String::from_utf8_lossy(&data[6..6 + Self::DESCRIPTION_LEN])
+            .trim_end_matches([' ', '\0'])
+            .to_string();
+        let mut result = PPFPatch { version, description, ..PPFPatch::default() };
+        let (has_block, undo) = match version {
+            PPFVersion::V2 => {
+                result.source_len = Some(u32::from_le_bytes(data[56..60].try_into().unwrap()) as u64);
+                (true, false)
+            }
+            PPFVersion::V3 => {
+                result.image_type = match data[56] {
+                    0 => PPFImageType::Bin,
+                    1 => PPFImageType::Gi,
+                    _ => return Err(parsing_error("Invalid image type.")),
+                };
+                (data[57] != 0, data[58] != 0)
+            }
+        };
+
+        let (body, file_id) = Self::split_file_id(&data, version)?;
+        result.file_id = file_id;
+        let mut position = Self::HEADER_LEN;
+        if has_block {
+            let block = body.get(position..position + Self::BLOCK_LEN).ok_or_else(|| parsing_error("Unable to read block check."))?;
+            result.block = Some(block.into());
+            position += Self::BLOCK_LEN;
+        }
+        let offset_len = match version {
+            PPFVersion::V2 => 4,
+            PPFVersion::V3 => 8,
+        };
+        while position < body.len() {
+            let header = body.get(position..position + offset_len + 1).ok_or_else(|| parsing_error("Unable to read record header."))?;
+            let mut offset = [0; 8];
+            offset[..offset_len].copy_from_slice(&header[..offset_len]);
+            let offset = u64::from_le_bytes(offset);
+            let len = header[offset_len] as usize;
+            position += offset_len + 1;
+            let data = body.get(position..position + len).ok_or_else(|| parsing_error("Unable to read record data."))?;
+            position += len;
+            let undo = if undo {
+                let undo = body.get(position..position + len).ok_or_else(|| parsing_error("Unable to read undo data."))?;
+                position += len;
+                Some(undo.into())
+            } else {
+                None
+            };
+            result.records.push(PPFRecord { offset, data: data.into(), undo });
+        }
+        Ok(result)
+    }
+
+    /// returns `data` without the `FILE_ID.DIZ` block it ends with, and its text.
+    fn split_file_id(data: &[u8], version: PPFVersion) -> Result<(&[u8], Option<String>), Error> {
+        let len_len = match version {
+            PPFVersion::V2 => 4,
+            PPFVersion::V3 => 2,
+        };
+        let suffix_len = Self::END_FILE_ID.len() + len_len;
+        if data.len() < Self::HEADER_LEN + suffix_len || !data[..data.len() - len_len].ends_with(Self::END_FILE_ID) {
+            return Ok((data, None));
+        }
+        let mut len = [0; 4];
+        len[..len_len].copy_from_slice(&data[data.len() - len_len..]);
+        let len = u32::from_le_bytes(len) as usize;
+        let start = (data.len() - suffix_len).checked_sub(len + Self::BEGIN_FILE_ID.len())
+            .filter(|x| *x >= Self::HEADER_LEN && data[*x..].starts_with(Self::BEGIN_FILE_ID))
+            .ok_or_else(|| Error::new(ParsingError).with_description("Unable to read FILE_ID.DIZ.".to_string()))?;
+        let text = &data[start + Self::BEGIN_FILE_ID.len()..data.len() - suffix_len];
+        Ok((&data[..start], Some(String::from_utf8_lossy(text).into_owned())))
+    }
+
+    /// returns a [WrongSource] error if `target` doesn't match the block the patch checks.
+    fn check_block<T: Read + Seek>(&self, target: &mut T) -> Result<(), Error> {
+        let Some(block) = &self.block else {
+            return Ok(());
+        };
+        let offset = self.image_type.block_offset();
+        let mut actual = vec![0; Self::BLOCK_LEN];
+        let matches = target.seek(SeekFrom::Start(offset))
+            .and_then(|_| target.read_exact(&mut actual))
+            .is_ok_and(|_| actual[..] == block[..]);
+        if !matches {
+            return Err(Error::new(WrongSource)
+                .with_description(format!("The image doesn't match the block the patch checks at {:#X}.", offset)));
+        }
+        Ok(())
+    }
+
+    /// returns a [WrongSource] error if `target` isn't as long as the patch records.
+    fn check_len<T: Seek>(&self, target: &mut T) -> Result<(), Error> {
+        let Some(expected) = self.source_len else {
+            return Ok(());
+        };
+        let len = target.seek(SeekFrom::End(0))
+            .map_err(|e| Error::new(PatchingError)
+                .with_description("Unable to determine the size of the image.".to_string())
+                .with_source(Box::new(e)))?;
+        if len != expected {
+            return Err(Error::new(WrongSource)
+                .with_description(format!("The image is {} bytes long, but the patch expects {} bytes.", len, expected)));
+        }
+        Ok(())
+    }
+
+    /// Applies the patch to `target` in place, extending it if records write past its end.
+    ///
+    /// Returns a [WrongSource] error without touching `target` if it doesn't match the block the
+    /// patch checks or, for PPF 2.0 patches, the size it records. Disc images are large, so they
+    /// are patched where they are instead of being read into memory.
+    pub fn apply<T>(&self, target: &mut T) -> Result<(), Error> where T: Read + Write + Seek {
+        self.check_len(target)?;
+        self.check_block(target)?;
+        for record in &self.records {
+            Self::write_at(target, record.offset, &record.data)?;
+        }
+        Ok(())
+    }
+
+    /// Reverts the patch on `target` in place by writing back the undo data of every record, in
+    /// reverse order.
+    ///
+    /// Returns a [ValidationError] if the patch carries no undo data, like every PPF 2.0 patch, and
+    /// a [WrongSource] error if `target` doesn't match the block the patch checks.
+    pub fn revert<T>(&self, target: &mut T) -> Result<(), Error> where T: Read + Write + Seek {
+        if !self.has_undo() {
+            return Err(Error::new(ValidationError).with_description("The patch carries no undo data.".to_string()));
+        }
+        self.check_block(target)?;
+        for record in self.records.iter().rev() {
+            // has_undo checked every record
+            Self::write_at(target, record.offset, record.undo.as_ref().unwrap())?;
+        }
+        Ok(())
+    }
+
+    /// returns `source` patched, see [PPFPatch::apply].
+    ///
+    /// Returns a [PatchingError] if the records write further past the end of `source` than fits
+    /// into memory.
+    pub fn apply_to_vec(&self, source: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut target = Cursor::new(self.copy_for_records(source)?);
+        self.apply(&mut target)?;
+        Ok(target.into_inner())
+    }
+
+    /// returns `target` with the patch reverted, see [PPFPatch::revert].
+    pub fn revert_to_vec(&self, target: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut source = Cursor::new(self.copy_for_records(target)?);
+        self.revert(&mut source)?;
+        Ok(source.into_inner())
+    }
+
+    /// returns a copy of `image` with room for everything the records write.
+    fn copy_for_records(&self, image: &[u8]) -> Result<Vec<u8>, Error> {
+        let len = self.records.iter().map(PPFRecord::end).fold(image.len() as u64, u64::max);
+        let mut result = Vec::new();
+        result.try_reserve_exact(len.saturating_usize())
+            .map_err(|_| Error::new(PatchingError)
+                .with_description(format!("The patched image of {} bytes doesn't fit into memory.", len)))?;
+        result.extend_from_slice(image);
+        Ok(result)
+    }
+
+    fn write_at<T: Write + Seek>(target: &mut T, offset: u64, data: &[u8]) -> Result<(), Error> {
+        target.seek(SeekFrom::Start(offset))
+            .and_then(|_| target.write_all(data))
+            .map_err(|e| Error::new(PatchingError)
+                .with_description(format!("Unable to write record at {:#X}.", offset))
+                .with_source(Box::new(e)))
+    }
+}
+
+/// Formats the patch as a one-line summary, e.g. `PPF 3.0 patch "Translation" with 2 records`.
+///
+/// The alternate form `{:#}` lists every record on a line of its own below the summary.
+impl Display for PPFPatch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let plural = if self.records.len() == 1 { "" } else { "s" };
+        let version = match self.version {
+            PPFVersion::V2 => "2.0",
+            PPFVersion::V3 => "3.0",
+        };
+        write!(f, "PPF {} patch {:?} with {} record{}", version, self.description, self.records.len(), plural)?;
+        if f.alternate() {
+            for (i, record) in self.records.iter().enumerate() {
+                write!(f, "\n  {}: {}", i, record)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use spectral::prelude::*;
+
+    use crate::test_util::BuildVec;
+
+    use super::*;
+
+    fn image() -> Vec<u8> {
+        (0..0x9800u32).map(|x| (x % 251) as u8).collect()
+    }
+
+    fn patch_data() -> Vec<u8> {
+        let mut description = b"Test".to_vec();
+        description.resize(50, b' ');
+        Vec::new()
+            .build_with_slice(PPFPatch::HEADER)
+            .build_with_slice(&[0x02])
+            .build_with_slice(&description)
+            .build_with_slice(&[0, 0, 1, 0]) // BIN, no block check, undo data
+            .build_with_slice(&[0x10, 0, 0, 0, 0, 0, 0, 0, 2, 0xAA, 0xBB, 0x10, 0x11])
+            .build_with_slice(b"@BEGIN_FILE_ID.DIZ")
+            .build_with_slice(b"hi")
+            .build_with_slice(b"@END_FILE_ID.DIZ")
+            .build_with_slice(&[2, 0])
+    }
+
+    #[test]
+    fn read_and_write_records() {
+        let patch = PPFPatch::from_bytes(&patch_data()).unwrap();
+        assert_that!(patch.description()).is_equal_to("Test");
+        assert_that!(patch.file_id()).is_equal_to(Some("hi"));
+        assert_that!(patch.records().to_vec()).is_equal_to(vec![
+            PPFRecord { offset: 0x10, data: Box::new([0xAA, 0xBB]), undo: Some(Box::new([0x10, 0x11])) },
+        ]);
+        assert_that!(patch.to_bytes().unwrap()).is_equal_to(patch_data());
+    }
+
+    #[test]
+    fn apply_and_revert() {
+        let source = image();
+        let mut target = source.clone();
+        target[0x20..0x220].fill(0xEE);
+        target.extend_from_slice(&[1, 2, 3]);
+        let patch = PPFPatch::create(&source, &target).unwrap().with_file_id("readme");
+        assert_that!(patch.block()).is_some();
+        let patch = PPFPatch::from_bytes(&patch.to_bytes().unwrap()).unwrap();
+        assert_that!(patch.records().len()).is_equal_to(4);
+        assert_that!(patch.apply_to_vec(&source).unwrap()).is_equal_to(&target);
+        let reverted = patch.revert_to_vec(&target).unwrap();
+        assert_that!(reverted[..source.len()].to_vec()).is_equal_to(source);
+    }
+
+    #[test]
+    fn block_checks_refuse_other_images() {
+        let patch = PPFPatch::new().with_block_check(&image()).with_record(PPFRecord { offset: 0, data: Box::new([1]), undo: None });
+        let mut other = image();
+        other[0x9400] ^= 1;
+        let err = patch.apply_to_vec(&other).unwrap_err();
+        assert_that!(matches!(err.kind(), WrongSource)).is_true();
+        assert_that!(patch.apply_to_vec(&[0; 16])).is_err();
+        assert_that!(patch.revert_to_vec(&image()).unwrap_err().to_string()).contains("no undo data");
+    }
+
+    #[test]
+    fn records_past_the_memory_are_refused() {
+        let patch = PPFPatch::new().with_record(PPFRecord { offset: 1 << 62, data: Box::new([1]), undo: Some(Box::new([0])) });
+        let err = patch.apply_to_vec(&image()).unwrap_err();
+        assert_that!(matches!(err.kind(), PatchingError)).is_true();
+        assert_that!(err.to_string()).contains("doesn't fit into memory");
+        assert_that!(patch.revert_to_vec(&image())).is_err();
+    }
+
+    #[test]
+    fn gi_images_check_their_own_block() {
+        let patch = PPFPatch::new().with_image_type(PPFImageType::Gi).with_block_check(&image());
+        assert_that!(patch.block().unwrap().to_vec()).is_equal_to(image()[0x80A0..0x84A0].to_vec());
+        let read = PPFPatch::from_bytes(&patch.to_bytes().unwrap()).unwrap();
+        assert_that!(read).is_equal_to(patch);
+    }
+
+    #[test]
+    fn invalid_patches_are_refused() {
+        assert_that!(PPFPatch::from_bytes(b"PPF30").unwrap_err().to_string()).contains("Unable to parse header");
+        let mut data = patch_data();
+        data.truncate(data.len() - 39);
+        assert_that!(PPFPatch::from_bytes(&data).unwrap_err().to_string()).contains("Unable to read undo data");
+        let mixed = PPFPatch::new()
+            .with_record(PPFRecord { offset: 0, data: Box::new([1]), undo: Some(Box::new([0])) })
+            .with_record(PPFRecord { offset: 1, data: Box::new([1]), undo: None });
+        assert_that!(mixed.to_bytes()).is_err();
+    }
+
+    #[test]
+    fn shrinking_images_are_not_created() {
+        assert_that!(PPFPatch::create(&[0; 4], &[0; 2])).is_err();
+    }
+
+    fn v2_patch_data() -> Vec<u8> {
+        let mut description = b"Old".to_vec();
+        description.resize(50, b' ');
+        Vec::new()
+            .build_with_slice(PPFPatch::HEADER_V2)
+            .build_with_slice(&[0x01])
+            .build_with_slice(&description)
+            .build_with_slice(&0x9800u32.to_le_bytes())
+            .build_with_slice(&image()[0x9320..0x9720])
+            .build_with_slice(&[0x10, 0, 0, 0, 2, 0xAA, 0xBB])
+            .build_with_slice(&[0x00, 0x98, 0, 0, 1, 0xCC])
+            .build_with_slice(b"@BEGIN_FILE_ID.DIZ")
+            .build_with_slice(b"v2")
+            .build_with_slice(b"@END_FILE_ID.DIZ")
+            .build_with_slice(&[2, 0, 0, 0])
+    }
+
+    #[test]
+    fn read_and_write_ppf2() {
+        let patch = PPFPatch::from_bytes(&v2_patch_data()).unwrap();
+        assert_that!(patch.version()).is_equal_to(PPFVersion::V2);
+        assert_that!(patch.description()).is_equal_to("Old");
+        assert_that!(patch.source_len()).is_equal_to(Some(0x9800));
+        assert_that!(patch.file_id()).is_equal_to(Some("v2"));
+        assert_that!(patch.records().len()).is_equal_to(2);
+        assert_that!(patch.to_bytes().unwrap()).is_equal_to(v2_patch_data());
+
+        let patched = patch.apply_to_vec(&image()).unwrap();
+        assert_that!(patched[0x10..0x12].to_vec()).is_equal_to(vec![0xAA, 0xBB]);
+        assert_that!(patched.len()).is_equal_to(0x9801);
+        assert_that!(patch.revert_to_vec(&patched)).is_err();
+    }
+
+    #[test]
+    fn ppf2_checks_the_image_size() {
+        let patch = PPFPatch::from_bytes(&v2_patch_data()).unwrap();
+        let mut longer = image();
+        longer.push(0);
+        let err = patch.apply_to_vec(&longer).unwrap_err();
+        assert_that!(matches!(err.kind(), WrongSource)).is_true();
+        assert_that!(err.to_string()).contains("38913 bytes long");
+    }
+
+    #[test]
+    fn ppf2_refuses_what_it_cant_store() {
+        let source = image();
+        let mut target = source.clone();
+        target[0] = 0xFF;
+        let patch = PPFPatch::create(&source, &target).unwrap().with_version(PPFVersion::V2);
+        assert_that!(patch.to_bytes().unwrap_err().to_string()).contains("undo data");
+        let without_undo = PPFPatch::new().with_version(PPFVersion::V2).with_block_check(&source);
+        assert_that!(without_undo.to_bytes().unwrap_err().to_string()).contains("size of the image");
+        let without_block = PPFPatch::new().with_version(PPFVersion::V2).with_source_len(16);
+        assert_that!(without_block.to_bytes().unwrap_err().to_string()).contains("always check a block");
+    }
+}
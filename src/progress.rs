@@ -0,0 +1,86 @@
+//! Reporting progress of long running operations and cancelling them.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::Error;
+use crate::ErrorKind::Cancelled;
+use crate::messages::Message;
+
+/// A step of a long running operation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum Stage {
+    /// reading the inputs.
+    Reading,
+    /// detecting the format of the patch.
+    Detecting,
+    /// validating the patch.
+    Validating,
+    /// applying the patch.
+    Applying,
+    /// hashing the roms, e.g. indexing the source to find data a created patch copies from it.
+    Hashing,
+    /// finding the differences between source and target of a created patch.
+    Matching,
+    /// encoding the differences in the requested patch formats.
+    Encoding,
+    /// writing the output.
+    Writing,
+    /// the operation finished.
+    Done,
+}
+
+/// A progress update of a long running operation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize))]
+pub struct Progress {
+    /// the step the operation is at.
+    pub stage: Stage,
+    /// amount of steps finished so far.
+    pub done: u64,
+    /// amount of steps in total. Applying counts stages, while creating counts compared bytes in
+    /// [Stage::Matching] and encoded formats in [Stage::Encoding].
+    pub total: u64,
+}
+
+/// A cloneable flag to ask a running operation to stop.
+///
+/// # Examples
+///
+/// ```
+/// use rom_patcher::progress::CancellationToken;
+/// let token = CancellationToken::new();
+/// let handle = token.clone();
+/// handle.cancel();
+/// assert!(token.is_cancelled());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    /// constructs a token that is not cancelled.
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    /// asks every operation observing this token to stop.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    /// returns `true` if [CancellationToken::cancel] was called on this token or a clone of it.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// returns a [Cancelled] error if the token is cancelled.
+    pub fn check(&self) -> Result<(), Error> {
+        if self.is_cancelled() {
+            return Err(Error::new(Cancelled).with_message(Message::Cancelled));
+        }
+        Ok(())
+    }
+}
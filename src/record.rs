@@ -0,0 +1,239 @@
+//! Recording writes made to a file so they can be replayed or distributed as a patch.
+
+use std::collections::BTreeMap;
+use std::io::{Read, Result as IOResult, Seek, SeekFrom, Write};
+
+use crate::Error;
+use crate::io_util::U64Extensions;
+use crate::ips::IPSPatch;
+
+/// A single write recorded by a [Recorder].
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordedWrite {
+    /// offset the data was written at.
+    pub offset: u64,
+    /// the written data.
+    pub data: Box<[u8]>,
+}
+
+/// A `Write + Seek` adapter logging every write made through it.
+///
+/// Any tool writing a file through a [Recorder], e.g. a hex editor, leaves behind a log that can be
+/// replayed onto another copy of the file or exported as a distributable [IPSPatch].
+///
+/// # Examples
+///
+/// ```
+/// use std::io::{Cursor, Seek, SeekFrom, Write};
+/// use rom_patcher::record::Recorder;
+///
+/// let mut recorder = Recorder::new(Cursor::new(vec![0; 8])).unwrap();
+/// recorder.seek(SeekFrom::Start(2)).unwrap();
+/// recorder.write_all(&[1, 2]).unwrap();
+/// let patch = recorder.to_patch().unwrap();
+/// assert_eq!(patch.hunks()[0].offset(), 2);
+/// ```
+#[derive(Debug)]
+pub struct Recorder<W> {
+    inner: W,
+    position: u64,
+    writes: Vec<RecordedWrite>,
+}
+
+impl<W> Recorder<W> where W: Write + Seek {
+    /// constructs a [Recorder] writing to `inner`, starting at its current position.
+    pub fn new(mut inner: W) -> IOResult<Recorder<W>> {
+        let position = inner.stream_position()?;
+        Ok(Recorder {
+            inner,
+            position,
+            writes: Vec::new(),
+        })
+    }
+
+    /// returns the writes made so far, in the order they were made.
+    pub fn writes(&self) -> &[RecordedWrite] {
+        &self.writes
+    }
+
+    /// makes the recorded writes again on `target`.
+    pub fn replay(&self, target: &mut (impl Write + Seek)) -> IOResult<()> {
+        for write in &self.writes {
+            target.seek(SeekFrom::Start(write.offset))?;
+            target.write_all(&write.data)?;
+        }
+        Ok(())
+    }
+
+    /// exports the recorded writes as an [IPSPatch], later writes taking precedence over earlier ones.
+    ///
+    /// Returns a [crate::ErrorKind::CreatingError] if a write lies beyond what IPS can address, or if
+    /// a write starts at the offset spelling "EOF" with the byte in front of it never written.
+    pub fn to_patch(&self) -> Result<IPSPatch, Error> {
+        let mut result = IPSPatch::new();
+        for (offset, data) in self.merged() {
+            result.push_changes(offset, &data, None)?;
+        }
+        return Ok(result);
+    }
+
+    /// returns the written bytes as disjoint, non-adjacent segments keyed by their offset.
+    fn merged(&self) -> BTreeMap<u64, Vec<u8>> {
+        let mut segments: BTreeMap<u64, Vec<u8>> = BTreeMap::new();
+        for write in &self.writes {
+            let end = write.offset + write.data.len() as u64;
+            let touched: Vec<u64> = segments.range(..=end)
+                .rev()
+                .take_while(|(start, data)| *start + data.len() as u64 >= write.offset)
+                .map(|(start, _)| *start)
+                .collect();
+            let start = touched.last().map_or(write.offset, |x| write.offset.min(*x));
+            let mut buffer = Vec::new();
+            for old in touched.iter().rev() {
+                let data = segments.remove(old).unwrap();
+                let at = (old - start).to_index();
+                if buffer.len() < at + data.len() {
+                    buffer.resize(at + data.len(), 0);
+                }
+                buffer[at..at + data.len()].copy_from_slice(&data);
+            }
+            let at = (write.offset - start).to_index();
+            if buffer.len() < at + write.data.len() {
+                buffer.resize(at + write.data.len(), 0);
+            }
+            buffer[at..at + write.data.len()].copy_from_slice(&write.data);
+            segments.insert(start, buffer);
+        }
+        return segments;
+    }
+
+    /// returns a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// consumes the recorder and returns the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W> Write for Recorder<W> where W: Write + Seek {
+    fn write(&mut self, buf: &[u8]) -> IOResult<usize> {
+        let written = self.inner.write(buf)?;
+        // only record what actually reached the writer
+        if written > 0 {
+            self.writes.push(RecordedWrite {
+                offset: self.position,
+                data: buf[..written].into(),
+            });
+        }
+        self.position += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> IOResult<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W> Seek for Recorder<W> where W: Write + Seek {
+    fn seek(&mut self, pos: SeekFrom) -> IOResult<u64> {
+        self.position = self.inner.seek(pos)?;
+        Ok(self.position)
+    }
+}
+
+impl<W> Read for Recorder<W> where W: Read + Write + Seek {
+    fn read(&mut self, buf: &mut [u8]) -> IOResult<usize> {
+        let read = self.inner.read(buf)?;
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use spectral::prelude::*;
+
+    use crate::ips::IPSHunk;
+
+    use super::*;
+
+    fn recorder() -> Recorder<Cursor<Vec<u8>>> {
+        Recorder::new(Cursor::new(vec![0; 16])).unwrap()
+    }
+
+    #[test]
+    fn writes_reach_the_underlying_writer() {
+        let mut recorder = recorder();
+        recorder.seek(SeekFrom::Start(4)).unwrap();
+        recorder.write_all(&[1, 2]).unwrap();
+        assert_that!(recorder.into_inner().into_inner()[4..6].to_vec()).is_equal_to(vec![1, 2]);
+    }
+
+    #[test]
+    fn writes_are_logged_at_their_offset() {
+        let mut recorder = recorder();
+        recorder.write_all(&[1]).unwrap();
+        recorder.seek(SeekFrom::Current(2)).unwrap();
+        let mut buf = [0; 2];
+        recorder.read_exact(&mut buf).unwrap();
+        recorder.write_all(&[2]).unwrap();
+        assert_that!(recorder.writes()).is_equal_to(&[
+            RecordedWrite { offset: 0, data: Box::new([1]) },
+            RecordedWrite { offset: 5, data: Box::new([2]) },
+        ][..]);
+    }
+
+    #[test]
+    fn overlapping_and_adjacent_writes_merge_into_one_hunk() {
+        let mut recorder = recorder();
+        recorder.seek(SeekFrom::Start(2)).unwrap();
+        recorder.write_all(&[1, 1, 1]).unwrap();
+        recorder.seek(SeekFrom::Start(4)).unwrap();
+        recorder.write_all(&[2, 2]).unwrap();
+        recorder.seek(SeekFrom::Start(0)).unwrap();
+        recorder.write_all(&[3, 3]).unwrap();
+        recorder.seek(SeekFrom::Start(10)).unwrap();
+        recorder.write_all(&[4]).unwrap();
+
+        let patch = recorder.to_patch().unwrap();
+        let hunks: Vec<(u64, u64)> = patch.hunks().iter().map(|x| (x.offset(), x.length())).collect();
+        assert_that!(hunks).is_equal_to(vec![(0, 6), (10, 1)]);
+        match &patch.hunks()[0] {
+            IPSHunk::Regular(x) => assert_that!(x.payload.to_vec()).is_equal_to(vec![3, 3, 1, 1, 2, 2]),
+            IPSHunk::RLE(_) => panic!("expected a regular hunk"),
+        }
+    }
+
+    #[test]
+    fn exported_patch_reproduces_the_edits() {
+        let mut recorder = recorder();
+        recorder.seek(SeekFrom::Start(3)).unwrap();
+        recorder.write_all(&[7, 8, 9]).unwrap();
+        recorder.seek(SeekFrom::End(0)).unwrap();
+        recorder.write_all(&[5; 4]).unwrap();
+        let patch = recorder.to_patch().unwrap();
+        let edited = recorder.into_inner().into_inner();
+
+        let mut copy = Cursor::new(vec![0; 16]);
+        patch.apply(&mut copy).unwrap();
+        assert_that!(copy.into_inner()).is_equal_to(edited);
+    }
+
+    #[test]
+    fn replaying_makes_the_same_writes() {
+        let mut recorder = recorder();
+        recorder.seek(SeekFrom::Start(1)).unwrap();
+        recorder.write_all(&[1, 2, 3]).unwrap();
+        recorder.seek(SeekFrom::Start(2)).unwrap();
+        recorder.write_all(&[4]).unwrap();
+
+        let mut copy = Cursor::new(vec![0; 16]);
+        recorder.replay(&mut copy).unwrap();
+        assert_that!(copy.into_inner()).is_equal_to(recorder.into_inner().into_inner());
+    }
+}
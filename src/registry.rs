@@ -0,0 +1,267 @@
+//! Registry of the patch formats known to [detect_format] and [read_any].
+//!
+//! Built-in formats are always known. Other crates can add formats with [register] so niche or
+//! proprietary formats can be detected and read like the built-in ones without forking this crate.
+//!
+//! # Examples
+//!
+//! ```
+//! use rom_patcher::registry::{self, Format};
+//!
+//! fn read_dummy(_: &[u8]) -> Result<Box<dyn rom_patcher::patch::Patch>, rom_patcher::Error> {
+//!     Ok(Box::new(rom_patcher::ips::IPSPatch::new()))
+//! }
+//!
+//! registry::register(Format {
+//!     name: "dummy",
+//!     extensions: &["dmy"],
+//!     matches: |data| data.starts_with(b"DUMMY"),
+//!     read: read_dummy,
+//! }).unwrap();
+//! assert_eq!(registry::detect_format(b"DUMMY...").unwrap().name, "dummy");
+//! ```
+
+use std::io::Read;
+use std::sync::RwLock;
+
+use crate::bps::BPSPatch;
+use crate::Error;
+use crate::ErrorKind::{ParsingError, UnsupportedFormat, ValidationError};
+use crate::ips::IPSPatch;
+use crate::messages::Message;
+use crate::metrics::measure;
+use crate::patch::Patch;
+use crate::pmsr::PMSRPatch;
+use crate::ppf::PPFPatch;
+use crate::ups::UPSPatch;
+use crate::vcdiff::VCDIFFPatch;
+
+/// Parses complete patch data of a single format.
+pub type ReadFn = fn(&[u8]) -> Result<Box<dyn Patch>, Error>;
+
+/// Describes how to recognize and read a patch format.
+#[derive(Debug, Clone, Copy)]
+pub struct Format {
+    /// unique name of the format, e.g. `"ips"`.
+    pub name: &'static str,
+    /// file extensions patches of this format usually have, without leading dot.
+    pub extensions: &'static [&'static str],
+    /// returns `true` if the given patch data, starting at its first byte, is of this format.
+    pub matches: fn(&[u8]) -> bool,
+    /// parses complete patch data of this format.
+    pub read: ReadFn,
+}
+
+/// Formats implemented by this crate, checked before registered formats.
+const BUILTIN: &[Format] = &[
+    Format {
+        name: "ips",
+        extensions: &["ips"],
+        matches: |data| data.starts_with(IPSPatch::HEADER),
+        read: |mut data| Ok(Box::new(IPSPatch::read_from(&mut data)?)),
+    },
+    Format {
+        name: "pmsr",
+        extensions: &["mod"],
+        matches: |data| data.starts_with(PMSRPatch::HEADER),
+        read: |mut data| Ok(Box::new(PMSRPatch::read_from(&mut data)?)),
+    },
+    Format {
+        name: "ups",
+        extensions: &["ups"],
+        matches: |data| data.starts_with(UPSPatch::HEADER),
+        read: |mut data| Ok(Box::new(UPSPatch::read_from(&mut data)?)),
+    },
+    Format {
+        name: "bps",
+        extensions: &["bps"],
+        matches: |data| data.starts_with(BPSPatch::HEADER),
+        read: |mut data| Ok(Box::new(BPSPatch::read_from(&mut data)?)),
+    },
+    Format {
+        name: "ppf",
+        extensions: &["ppf"],
+        matches: |data| data.starts_with(PPFPatch::HEADER) || data.starts_with(PPFPatch::HEADER_V2),
+        read: |mut data| Ok(Box::new(PPFPatch::read_from(&mut data)?)),
+    },
+    Format {
+        name: "vcdiff",
+        extensions: &["vcdiff", "xdelta", "vcd"],
+        matches: |data| data.starts_with(&VCDIFFPatch::HEADER[..3]),
+        read: |data| Ok(Box::new(VCDIFFPatch::from_bytes(data)?)),
+    },
+];
+
+/// Formats added through [register].
+static REGISTERED: RwLock<Vec<Format>> = RwLock::new(Vec::new());
+
+/// adds `format` to the registry.
+///
+/// Returns a [ValidationError] if a format with the same name is already known.
+pub fn register(format: Format) -> Result<(), Error> {
+    let mut registered = REGISTERED.write().unwrap_or_else(|e| e.into_inner());
+    if BUILTIN.iter().chain(registered.iter()).any(|x| x.name == format.name) {
+        return Err(Error::new(ValidationError)
+            .with_description(format!("Patch format {} is already registered.", format.name)));
+    }
+    registered.push(format);
+    Ok(())
+}
+
+/// returns all known formats, built-in formats first.
+pub fn formats() -> Vec<Format> {
+    let registered = REGISTERED.read().unwrap_or_else(|e| e.into_inner());
+    BUILTIN.iter()
+        .chain(registered.iter())
+        .copied()
+        .collect()
+}
+
+/// returns the first known format matching `data`.
+pub fn detect_format(data: &[u8]) -> Option<Format> {
+    formats().into_iter().find(|x| (x.matches)(data))
+}
+
+/// returns the first known format using `extension`, ignoring case and a leading dot.
+pub fn format_for_extension(extension: &str) -> Option<Format> {
+    let extension = extension.trim_start_matches('.');
+    formats().into_iter()
+        .find(|x| x.extensions.iter().any(|e| e.eq_ignore_ascii_case(extension)))
+}
+
+/// reads a patch of any known format from `data`.
+pub fn read_any_from_slice(data: &[u8]) -> Result<Box<dyn Patch>, Error> {
+    let read = || match detect_format(data) {
+        Some(format) => (format.read)(data),
+        None => Err(Error::new(UnsupportedFormat).with_message(Message::UnknownFormat)),
+    };
+    measure("parse", read, |_| data.len() as u64)
+}
+
+/// reads a patch of any known format from `reader`.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::fs::File;
+/// use rom_patcher::registry::read_any;
+///
+/// let mut file = File::open("patch.ips").expect("Unable to open file.");
+/// let patch = read_any(&mut file).expect("Unable to read patch.");
+/// println!("read a {} patch", patch.format());
+/// ```
+pub fn read_any(reader: &mut impl Read) -> Result<Box<dyn Patch>, Error> {
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)
+        .map_err(|e| Error::new(ParsingError)
+            .with_description("Unable to read patch.".to_string())
+            .with_source(Box::new(e)))?;
+    read_any_from_slice(&data)
+}
+
+#[cfg(test)]
+mod tests {
+    use spectral::prelude::*;
+
+    use crate::testkit::Fixture;
+
+    use super::*;
+
+    fn read_test_format(_: &[u8]) -> Result<Box<dyn Patch>, Error> {
+        Ok(Box::new(IPSPatch::new().with_truncate(1)))
+    }
+
+    #[test]
+    fn detects_builtin_formats() {
+        let (_, data) = &Fixture::generate(1).encoded_patches()[0];
+        assert_that!(detect_format(data).map(|x| x.name)).is_equal_to(Some("ips"));
+    }
+
+    #[test]
+    fn read_any_reads_builtin_formats() {
+        let fixture = Fixture::generate(1);
+        let (_, data) = &fixture.encoded_patches()[0];
+        let patch = read_any(&mut data.as_slice()).unwrap();
+        assert_that!(patch.format()).is_equal_to("ips");
+        assert_that!(patch.apply_to_vec(&fixture.source).unwrap()).is_equal_to(&fixture.target);
+        assert_that!(patch.as_any().downcast_ref::<IPSPatch>()).is_equal_to(Some(&fixture.ips));
+    }
+
+    #[test]
+    fn detects_star_rod_mods() {
+        let mut data = Vec::new();
+        PMSRPatch::new().write(&mut data).unwrap();
+        assert_that!(detect_format(&data).map(|x| x.name)).is_equal_to(Some("pmsr"));
+        assert_that!(format_for_extension("mod").map(|x| x.name)).is_equal_to(Some("pmsr"));
+    }
+
+    #[test]
+    fn detects_ups_patches() {
+        let fixture = Fixture::generate(2);
+        let (_, data) = fixture.encoded_patches().into_iter().find(|x| x.0 == "ups").unwrap();
+        let patch = read_any_from_slice(&data).unwrap();
+        assert_that!(patch.format()).is_equal_to("ups");
+        assert_that!(patch.apply_to_vec(&fixture.source).unwrap()).is_equal_to(&fixture.target);
+        assert_that!(format_for_extension("UPS").map(|x| x.name)).is_equal_to(Some("ups"));
+    }
+
+    #[test]
+    fn detects_bps_patches() {
+        let fixture = Fixture::generate(3);
+        let (_, data) = fixture.encoded_patches().into_iter().find(|x| x.0 == "bps").unwrap();
+        let patch = read_any_from_slice(&data).unwrap();
+        assert_that!(patch.format()).is_equal_to("bps");
+        assert_that!(patch.apply_to_vec(&fixture.source).unwrap()).is_equal_to(&fixture.target);
+        assert_that!(format_for_extension(".bps").map(|x| x.name)).is_equal_to(Some("bps"));
+    }
+
+    #[test]
+    fn detects_ppf_patches() {
+        let fixture = Fixture::generate(4);
+        let (_, data) = fixture.encoded_patches().into_iter().find(|x| x.0 == "ppf").unwrap();
+        let patch = read_any_from_slice(&data).unwrap();
+        assert_that!(patch.format()).is_equal_to("ppf");
+        assert_that!(patch.apply_to_vec(&fixture.source).unwrap()).is_equal_to(&fixture.target);
+        assert_that!(format_for_extension("ppf").map(|x| x.name)).is_equal_to(Some("ppf"));
+    }
+
+    #[test]
+    fn detects_vcdiff_patches() {
+        let data = [0xD6, 0xC3, 0xC4, 0x00, 0x00, 0x01, 0x04, 0x00, 0x08, 0x05, 0x00, 0x01, 0x01, 0x01, 0xFF, 0xF7, 0x00];
+        let patch = read_any_from_slice(&data).unwrap();
+        assert_that!(patch.format()).is_equal_to("vcdiff");
+        assert_that!(patch.apply_to_vec(&[1, 2, 3, 4]).unwrap()).is_equal_to(vec![1, 2, 3, 4, 0xFF]);
+        assert_that!(format_for_extension("xdelta").map(|x| x.name)).is_equal_to(Some("vcdiff"));
+    }
+
+    #[test]
+    fn registered_formats_are_detected_and_read() {
+        register(Format {
+            name: "registry-test",
+            extensions: &["rtst"],
+            matches: |data| data.starts_with(b"RTST"),
+            read: read_test_format,
+        }).unwrap();
+        let patch = read_any_from_slice(b"RTST").unwrap();
+        let ips = patch.as_any().downcast_ref::<IPSPatch>().unwrap();
+        assert_that!(ips.truncate()).is_equal_to(Some(1));
+        assert_that!(format_for_extension(".RTST").map(|x| x.name)).is_equal_to(Some("registry-test"));
+    }
+
+    #[test]
+    fn registering_a_known_name_fails() {
+        let result = register(Format {
+            name: "ips",
+            extensions: &[],
+            matches: |_| false,
+            read: read_test_format,
+        });
+        assert_that!(result).is_err();
+    }
+
+    #[test]
+    fn unknown_data_is_unsupported() {
+        let err = read_any_from_slice(b"nothing").unwrap_err();
+        assert_that!(err.to_string()).is_equal_to("UnsupportedFormat: Unknown patch format.".to_string());
+    }
+}
@@ -0,0 +1,154 @@
+//! Helpers keeping reports and manifests identical across operating systems.
+
+use std::path::{Component, Path, PathBuf};
+
+/// Formats paths relative to a root directory the same way on every operating system.
+///
+/// Paths are resolved lexically, without touching the file system, and always use `/` as
+/// separator, so reports written on Windows and Linux can be diffed against each other.
+///
+/// # Examples
+///
+/// ```
+/// use rom_patcher::report::Paths;
+/// let paths = Paths::new("patches");
+/// assert_eq!(paths.relative("patches/./hacks/../hack.ips"), "hack.ips");
+/// assert_eq!(paths.relative("other/hack.ips"), "../other/hack.ips");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Paths {
+    root: Vec<String>,
+    absolute: bool,
+}
+
+impl Paths {
+    /// constructs [Paths] relative to `root`.
+    pub fn new(root: impl AsRef<Path>) -> Paths {
+        let (absolute, root) = components(root.as_ref());
+        Paths { root, absolute }
+    }
+
+    /// returns `path` relative to the root, separated by `/`.
+    ///
+    /// Paths that can't be made relative, e.g. absolute paths below a relative root, are returned
+    /// normalized as a whole.
+    pub fn relative(&self, path: impl AsRef<Path>) -> String {
+        let (absolute, path) = components(path.as_ref());
+        if absolute != self.absolute {
+            return normalize(path, absolute);
+        }
+        let common = self.root.iter().zip(&path).take_while(|(a, b)| a == b).count();
+        // a root escaping upwards can't be climbed out of by name
+        if self.root[common..].iter().any(|x| x == "..") {
+            return normalize(path, absolute);
+        }
+        let mut result: Vec<String> = vec!["..".to_string(); self.root.len() - common];
+        result.extend(path[common..].iter().cloned());
+        if result.is_empty() {
+            return ".".to_string();
+        }
+        return result.join("/");
+    }
+
+    /// returns `paths` formatted with [Paths::relative], sorted by their formatted value.
+    pub fn sorted<P>(&self, paths: impl IntoIterator<Item=P>) -> Vec<String> where P: AsRef<Path> {
+        let mut result: Vec<String> = paths.into_iter().map(|x| self.relative(x)).collect();
+        result.sort();
+        return result;
+    }
+}
+
+/// returns `path` resolved lexically and separated by `/`.
+pub fn normalized(path: impl AsRef<Path>) -> String {
+    let (absolute, path) = components(path.as_ref());
+    normalize(path, absolute)
+}
+
+fn normalize(components: Vec<String>, absolute: bool) -> String {
+    let joined = components.join("/");
+    match (absolute, joined.is_empty()) {
+        (true, _) => format!("/{}", joined),
+        (false, true) => ".".to_string(),
+        (false, false) => joined,
+    }
+}
+
+/// splits `path` into whether it is absolute and its normal components, with `.` and `..`
+/// resolved where possible.
+fn components(path: &Path) -> (bool, Vec<String>) {
+    let mut absolute = false;
+    let mut result: Vec<String> = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::Prefix(_) | Component::RootDir => {
+                absolute = true;
+                result.clear();
+            }
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if result.last().is_some_and(|x| x != "..") {
+                    result.pop();
+                } else if !absolute {
+                    result.push("..".to_string());
+                }
+            }
+            Component::Normal(x) => result.push(x.to_string_lossy().into_owned()),
+        }
+    }
+    return (absolute, result);
+}
+
+/// joins `root` and a path formatted by [Paths::relative].
+pub fn resolve(root: impl AsRef<Path>, relative: &str) -> PathBuf {
+    let mut result = root.as_ref().to_path_buf();
+    result.extend(relative.split('/').filter(|x| !x.is_empty() && *x != "."));
+    return result;
+}
+
+#[cfg(test)]
+mod tests {
+    use spectral::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn relative_paths_below_root() {
+        let paths = Paths::new("/data/patches/");
+        assert_that!(paths.relative("/data/patches/a/b.ips")).is_equal_to("a/b.ips".to_string());
+        assert_that!(paths.relative("/data/patches")).is_equal_to(".".to_string());
+    }
+
+    #[test]
+    fn relative_paths_outside_root() {
+        let paths = Paths::new("/data/patches");
+        assert_that!(paths.relative("/data/roms/game.sfc")).is_equal_to("../roms/game.sfc".to_string());
+    }
+
+    #[test]
+    fn mismatched_roots_are_normalized_whole() {
+        let paths = Paths::new("patches");
+        assert_that!(paths.relative("/tmp/./x/../hack.ips")).is_equal_to("/tmp/hack.ips".to_string());
+        assert_that!(Paths::new("../up").relative("other")).is_equal_to("other".to_string());
+    }
+
+    #[test]
+    fn sorted_is_independent_of_input_order() {
+        let paths = Paths::new("root");
+        let expected = vec!["a/z.ips".to_string(), "b.ips".to_string(), "c/a.ips".to_string()];
+        assert_that!(paths.sorted(["root/c/a.ips", "root/b.ips", "root/a/z.ips"])).is_equal_to(&expected);
+        assert_that!(paths.sorted(["root/a/z.ips", "root/c/a.ips", "root/b.ips"])).is_equal_to(&expected);
+    }
+
+    #[test]
+    fn normalized_resolves_dots() {
+        assert_that!(normalized("./a/../../b/./c")).is_equal_to("../b/c".to_string());
+        assert_that!(normalized("/../a")).is_equal_to("/a".to_string());
+    }
+
+    #[test]
+    fn resolve_inverts_relative() {
+        let paths = Paths::new("/data");
+        let relative = paths.relative("/data/a/b.ips");
+        assert_that!(resolve("/data", &relative)).is_equal_to(PathBuf::from("/data/a/b.ips"));
+    }
+}
@@ -0,0 +1,207 @@
+//! Reducing patches to the minimal canonical artifact worth publishing, see [strip_nonessential].
+
+use std::ops::Range;
+
+use crate::hunk::{coverage, Hunk};
+use crate::index::IntervalIndex;
+use crate::io_util::U64Extensions;
+use crate::ips::{IPSHunk, IPSOptimization, IPSPatch, IPSRegularHunkData};
+use crate::patch::Patch;
+use crate::pmsr::{PMSRPatch, PMSRRecord};
+use crate::Error;
+use crate::ErrorKind::UnsupportedFormat;
+
+/// returns a copy of `patch` with everything that doesn't affect its output removed.
+///
+/// Metadata, comments and undo data are dropped, and the writes are normalized: they are ordered
+/// by offset, bytes later writes overwrite or a truncation discards are left out, and adjacent
+/// writes are merged. Patches producing the same output from every source therefore sanitize to
+/// the same bytes, whichever tool made them. IPS patches are re-encoded into the smallest hunks,
+/// see [IPSOptimization::Size]. Star Rod records of compressed assets that aren't overwritten stay
+/// records of their own, so they can still be recognized as compressed.
+///
+/// Returns an [UnsupportedFormat] error for formats the crate can't rewrite.
+///
+/// # Examples
+///
+/// ```
+/// use rom_patcher::ips::{IPSHunk, IPSPatch, IPSRegularHunkData};
+/// use rom_patcher::patch::Patch;
+/// use rom_patcher::sanitize::strip_nonessential;
+///
+/// let patch = IPSPatch::new()
+///     .with_hunk(IPSHunk::Regular(IPSRegularHunkData::new(4, vec![3, 4])))
+///     .with_hunk(IPSHunk::Regular(IPSRegularHunkData::new(2, vec![1, 2])))
+///     .with_hunk(IPSHunk::Regular(IPSRegularHunkData::new(5, vec![9])));
+/// let sanitized = strip_nonessential(&patch).unwrap();
+/// let source = [0; 8];
+/// assert_eq!(sanitized.apply_to_vec(&source).unwrap(), patch.apply_to_vec(&source).unwrap());
+/// assert_eq!(sanitized.to_bytes().unwrap(), b"PATCH\x00\x00\x02\x00\x04\x01\x02\x03\x09EOF");
+/// ```
+pub fn strip_nonessential(patch: &dyn Patch) -> Result<Box<dyn Patch>, Error> {
+    if let Some(ips) = patch.as_any().downcast_ref::<IPSPatch>() {
+        return Ok(Box::new(sanitize_ips(ips)?));
+    }
+    if let Some(pmsr) = patch.as_any().downcast_ref::<PMSRPatch>() {
+        return Ok(Box::new(sanitize_pmsr(pmsr)));
+    }
+    Err(Error::new(UnsupportedFormat)
+        .with_description(format!("{} patches can't be sanitized.", patch.format())))
+}
+
+/// returns the canonical form of `patch`.
+fn sanitize_ips(patch: &IPSPatch) -> Result<IPSPatch, Error> {
+    let limit = patch.truncate().unwrap_or(u64::MAX);
+    let mut result = IPSPatch::new();
+    for (offset, payload) in written(patch.hunks(), limit) {
+        // bytes in front of a write are never written, so a hunk can't start early at "EOF"
+        result.push_changes_with_optimization(offset, &payload, None, IPSOptimization::Size)?;
+    }
+    if let Some(truncate) = patch.truncate() {
+        // writes past the truncation still extend a shorter source up to it, so one of them is
+        // kept, down to a single byte, if nothing else reaches the truncation
+        let reaches = |hunks: &[IPSHunk]| hunks.iter().any(|x| x.end() >= truncate);
+        if reaches(patch.hunks()) && !reaches(result.hunks()) {
+            let offset = patch.hunks().iter().map(|x| x.target_range().start).filter(|x| *x >= truncate).min();
+            if let Some(offset) = offset {
+                result.add_hunk(IPSHunk::Regular(IPSRegularHunkData::new(offset, vec![0])));
+            }
+        }
+        result.set_truncate(Some(truncate));
+    }
+    return Ok(result);
+}
+
+/// returns the canonical form of `patch`.
+fn sanitize_pmsr(patch: &PMSRPatch) -> PMSRPatch {
+    let records = patch.records();
+    let index = IntervalIndex::new(records.iter().enumerate().map(|(i, x)| (x.target_range(), i)));
+    // compressed records no later record overwrites any part of
+    let mut intact: Vec<Range<u64>> = records.iter().enumerate()
+        .filter(|(i, x)| x.is_compressed() && index.overlapping(x.target_range()).iter().all(|(_, j)| **j <= *i))
+        .map(|(_, x)| x.target_range())
+        .collect();
+    intact.sort_unstable_by_key(|x| x.start);
+    intact.dedup_by(|a, b| a.start < b.end);
+
+    let mut result = PMSRPatch::new();
+    for (offset, data) in written(records, u64::MAX) {
+        let end = offset + data.len() as u64;
+        let mut boundaries = vec![offset, end];
+        boundaries.extend(intact.iter().filter(|x| x.start >= offset && x.end <= end).flat_map(|x| [x.start, x.end]));
+        boundaries.sort_unstable();
+        boundaries.dedup();
+        for part in boundaries.windows(2) {
+            result.add_record(PMSRRecord {
+                offset: part[0],
+                data: data[(part[0] - offset).to_index()..(part[1] - offset).to_index()].into(),
+            });
+        }
+    }
+    return result;
+}
+
+/// returns what `hunks` write in front of `limit`, later writes taking precedence, as the offsets
+/// and bytes of maximal contiguous writes ordered by offset.
+fn written<H: Hunk>(hunks: &[H], limit: u64) -> Vec<(u64, Vec<u8>)> {
+    let index = IntervalIndex::new(hunks.iter().enumerate().map(|(i, x)| (x.target_range(), i)));
+    let mut result = Vec::new();
+    for range in coverage(hunks) {
+        let range = range.start..range.end.min(limit);
+        if range.is_empty() {
+            continue;
+        }
+        let mut order: Vec<usize> = index.overlapping(range.clone()).into_iter().map(|(_, i)| *i).collect();
+        order.sort_unstable();
+        let mut bytes = vec![0; (range.end - range.start).saturating_usize()];
+        for i in order {
+            hunks[i].overlay(range.start, &mut bytes);
+        }
+        result.push((range.start, bytes));
+    }
+    return result;
+}
+
+#[cfg(test)]
+mod tests {
+    use spectral::prelude::*;
+
+    use crate::ips::IPSRLEHunkData;
+    use crate::testkit::Fixture;
+
+    use super::*;
+
+    fn regular(offset: u64, payload: &[u8]) -> IPSHunk {
+        IPSHunk::Regular(IPSRegularHunkData::new(offset, payload.to_vec()))
+    }
+
+    #[test]
+    fn sanitized_patches_produce_the_same_output() {
+        for seed in 0..8 {
+            let fixture = Fixture::generate(seed);
+            let sanitized = strip_nonessential(&fixture.ips).unwrap();
+            assert_that!(sanitized.apply_to_vec(&fixture.source).unwrap()).is_equal_to(fixture.target.clone());
+            assert_that!(sanitized.to_bytes().unwrap().len()).is_less_than_or_equal_to(fixture.ips.to_bytes().unwrap().len());
+        }
+    }
+
+    #[test]
+    fn equivalent_patches_sanitize_to_the_same_bytes() {
+        let a = IPSPatch::new()
+            .with_hunk(IPSHunk::RLE(IPSRLEHunkData { offset: 0, run_length: 6, payload: 1 }))
+            .with_hunk(regular(2, &[7, 8]));
+        let b = IPSPatch::new()
+            .with_hunk(regular(2, &[0, 0]))
+            .with_hunk(regular(4, &[1, 1]))
+            .with_hunk(regular(0, &[1, 1, 7, 8]));
+        let a = strip_nonessential(&a).unwrap().to_bytes().unwrap();
+        let b = strip_nonessential(&b).unwrap().to_bytes().unwrap();
+        assert_that!(a).is_equal_to(b);
+    }
+
+    #[test]
+    fn truncated_writes_are_dropped() {
+        let patch = IPSPatch::new()
+            .with_hunk(regular(2, &[1, 2, 3, 4]))
+            .with_hunk(regular(10, &[5, 6]))
+            .with_truncate(4);
+        let sanitized = strip_nonessential(&patch).unwrap();
+        let ips = sanitized.as_any().downcast_ref::<IPSPatch>().unwrap();
+        assert_that!(ips.hunks().to_vec()).is_equal_to(vec![regular(2, &[1, 2])]);
+        assert_that!(ips.truncate()).is_equal_to(Some(4));
+    }
+
+    #[test]
+    fn truncated_writes_extending_the_source_are_kept() {
+        let patch = IPSPatch::new()
+            .with_hunk(regular(0, &[1]))
+            .with_hunk(regular(10, &[5, 6]))
+            .with_truncate(6);
+        let sanitized = strip_nonessential(&patch).unwrap();
+        for source in [vec![0; 2], vec![9; 8]] {
+            assert_that!(sanitized.apply_to_vec(&source).unwrap()).is_equal_to(patch.apply_to_vec(&source).unwrap());
+        }
+        let ips = sanitized.as_any().downcast_ref::<IPSPatch>().unwrap();
+        assert_that!(ips.hunks().to_vec()).is_equal_to(vec![regular(0, &[1]), regular(10, &[0])]);
+    }
+
+    #[test]
+    fn star_rod_records_are_merged_around_compressed_ones() {
+        let compressed: Box<[u8]> = crate::compression::yay0::compress(&[1; 32]).unwrap().into();
+        let end = 4 + compressed.len() as u64;
+        let patch = PMSRPatch::new()
+            .with_record(PMSRRecord { offset: end, data: Box::new([2, 3]) })
+            .with_record(PMSRRecord { offset: 4, data: compressed.clone() })
+            .with_record(PMSRRecord { offset: 0, data: Box::new([1, 1, 1, 1]) })
+            .with_record(PMSRRecord { offset: end + 1, data: Box::new([4]) });
+        let sanitized = strip_nonessential(&patch).unwrap();
+        let pmsr = sanitized.as_any().downcast_ref::<PMSRPatch>().unwrap();
+        assert_that!(pmsr.records().to_vec()).is_equal_to(vec![
+            PMSRRecord { offset: 0, data: Box::new([1, 1, 1, 1]) },
+            PMSRRecord { offset: 4, data: compressed },
+            PMSRRecord { offset: end, data: Box::new([2, 4]) },
+        ]);
+        let source = [0; 4];
+        assert_that!(sanitized.apply_to_vec(&source).unwrap()).is_equal_to(patch.apply_to_vec(&source).unwrap());
+    }
+}
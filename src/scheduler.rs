@@ -0,0 +1,169 @@
+//! Limiting how many large jobs run at once by their estimated memory cost.
+
+use std::sync::{Condvar, Mutex};
+
+use crate::patch::PatchInfo;
+
+/// returns the estimated amount of memory needed to apply a patch of `patch_len` bytes to a
+/// source of `source_len` bytes.
+///
+/// Applying holds the source, the patch and the output in memory at once. The output length is
+/// taken from `info` where the patch records it and assumed to be the source length otherwise.
+///
+/// # Examples
+///
+/// ```
+/// use rom_patcher::patch::PatchInfo;
+/// use rom_patcher::scheduler::estimate_cost;
+/// assert_eq!(estimate_cost(&PatchInfo::default(), 100, 10), 210);
+/// ```
+pub fn estimate_cost(info: &PatchInfo, source_len: u64, patch_len: u64) -> u64 {
+    let target_len = info.target_len.unwrap_or(source_len);
+    source_len.saturating_add(patch_len).saturating_add(target_len)
+}
+
+/// A counting semaphore over a memory budget.
+///
+/// Jobs [acquire](Scheduler::acquire) their estimated cost before running and release it when the
+/// returned [Permit] is dropped. A job costing more than the whole budget still runs, but only
+/// once it has the budget to itself.
+#[derive(Debug)]
+pub struct Scheduler {
+    budget: u64,
+    used: Mutex<u64>,
+    released: Condvar,
+}
+
+/// A share of a [Scheduler]'s budget, given back when dropped.
+#[derive(Debug)]
+pub struct Permit<'a> {
+    scheduler: &'a Scheduler,
+    cost: u64,
+}
+
+impl Scheduler {
+    /// constructs a scheduler allowing jobs costing `budget` in total to run at once.
+    pub fn new(budget: u64) -> Scheduler {
+        Scheduler {
+            budget,
+            used: Mutex::new(0),
+            released: Condvar::new(),
+        }
+    }
+
+    /// constructs a scheduler that never makes jobs wait.
+    pub fn unlimited() -> Scheduler {
+        Scheduler::new(u64::MAX)
+    }
+
+    /// returns the total budget.
+    pub fn budget(&self) -> u64 {
+        self.budget
+    }
+
+    /// returns the cost of all jobs currently holding a [Permit].
+    pub fn used(&self) -> u64 {
+        *self.used.lock().unwrap()
+    }
+
+    /// waits until `cost` fits into the budget and reserves it.
+    pub fn acquire(&self, cost: u64) -> Permit<'_> {
+        let cost = cost.min(self.budget);
+        let mut used = self.used.lock().unwrap();
+        // used never exceeds the budget, so this can't overflow like adding the cost could
+        while cost > self.budget - *used {
+            used = self.released.wait(used).unwrap();
+        }
+        *used += cost;
+        Permit { scheduler: self, cost }
+    }
+
+    /// reserves `cost` if it fits into the budget right now.
+    pub fn try_acquire(&self, cost: u64) -> Option<Permit<'_>> {
+        let cost = cost.min(self.budget);
+        let mut used = self.used.lock().unwrap();
+        if cost > self.budget - *used {
+            return None;
+        }
+        *used += cost;
+        Some(Permit { scheduler: self, cost })
+    }
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Scheduler::unlimited()
+    }
+}
+
+impl<'a> Drop for Permit<'a> {
+    fn drop(&mut self) {
+        *self.scheduler.used.lock().unwrap() -= self.cost;
+        self.scheduler.released.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    use spectral::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn permits_are_released_on_drop() {
+        let scheduler = Scheduler::new(10);
+        let permit = scheduler.acquire(6);
+        assert_that!(scheduler.try_acquire(6).is_none()).is_true();
+        drop(permit);
+        assert_that!(scheduler.try_acquire(6).is_some()).is_true();
+        assert_that!(scheduler.used()).is_equal_to(0);
+    }
+
+    #[test]
+    fn oversized_jobs_run_alone() {
+        let scheduler = Scheduler::new(10);
+        let permit = scheduler.try_acquire(100);
+        assert_that!(permit.is_some()).is_true();
+        assert_that!(scheduler.try_acquire(1).is_none()).is_true();
+    }
+
+    #[test]
+    fn costs_up_to_the_largest_budget_fit() {
+        let scheduler = Scheduler::unlimited();
+        let permit = scheduler.acquire(u64::MAX);
+        assert_that!(scheduler.try_acquire(1).is_none()).is_true();
+        drop(permit);
+        let _permit = scheduler.acquire(u64::MAX);
+        assert_that!(scheduler.used()).is_equal_to(u64::MAX);
+    }
+
+    #[test]
+    fn concurrent_cost_never_exceeds_budget() {
+        let scheduler = Arc::new(Scheduler::new(10));
+        let peak = Arc::new(AtomicU64::new(0));
+        let handles: Vec<_> = (0..8).map(|_| {
+            let scheduler = scheduler.clone();
+            let peak = peak.clone();
+            thread::spawn(move || {
+                let _permit = scheduler.acquire(4);
+                peak.fetch_max(scheduler.used(), Ordering::SeqCst);
+                thread::sleep(Duration::from_millis(5));
+            })
+        }).collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_that!(peak.load(Ordering::SeqCst)).is_less_than_or_equal_to(10);
+    }
+
+    #[test]
+    fn estimate_uses_recorded_target_length() {
+        let info = PatchInfo { target_len: Some(1000), ..PatchInfo::default() };
+        assert_that!(estimate_cost(&info, 100, 10)).is_equal_to(1110);
+    }
+}
@@ -0,0 +1,213 @@
+//! Checks of the compiled crate against embedded golden vectors, see [self_test].
+
+use crate::codec::{Codec, Huffman, Lz77, Yay0, Yaz0};
+use crate::hash::{to_hex, Crc32, Sha1, Sha256};
+use crate::registry::read_any_from_slice;
+
+/// The rom every embedded patch is applied to.
+const SOURCE: &[u8] = &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9];
+
+/// An IPS patch with a regular hunk, an RLE hunk and truncation.
+const IPS_PATCH: &[u8] = &[
+    b'P', b'A', b'T', b'C', b'H',
+    0x00, 0x00, 0x02, 0x00, 0x02, 0xAA, 0xBB,
+    0x00, 0x00, 0x05, 0x00, 0x00, 0x00, 0x03, 0xCC,
+    b'E', b'O', b'F',
+    0x00, 0x00, 0x09,
+];
+const IPS_TARGET: &[u8] = &[0, 1, 0xAA, 0xBB, 4, 0xCC, 0xCC, 0xCC, 8];
+
+/// A Star Rod mod with a record inside and one past the end of the rom.
+const PMSR_PATCH: &[u8] = &[
+    b'P', b'M', b'S', b'R',
+    0x00, 0x00, 0x00, 0x02,
+    0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x02, 0xAA, 0xBB,
+    0x00, 0x00, 0x00, 0x0B, 0x00, 0x00, 0x00, 0x01, 0xCC,
+];
+const PMSR_TARGET: &[u8] = &[0, 1, 0xAA, 0xBB, 4, 5, 6, 7, 8, 9, 0, 0xCC];
+
+/// A UPS patch turning the rom into [IPS_TARGET], with a record past the end of the target.
+const UPS_PATCH: &[u8] = &[
+    b'U', b'P', b'S', b'1',
+    0x8A, 0x89,
+    0x82, 0xA8, 0xB8, 0x00,
+    0x80, 0xC9, 0xCA, 0xCB, 0x00,
+    0x80, 0x09, 0x00,
+    0x46, 0xD7, 0x6C, 0x45, 0x8C, 0xF3, 0x4F, 0x81, 0x87, 0xBE, 0x1F, 0xA4,
+];
+
+/// A BPS patch turning the rom into [IPS_TARGET] with every kind of action.
+const BPS_PATCH: &[u8] = &[
+    b'B', b'P', b'S', b'1',
+    0x8A, 0x89, 0x80,
+    0x84,
+    0x85, 0xAA, 0xBB,
+    0x82, 0x88,
+    0x81, 0xCC,
+    0x87, 0x8A,
+    0x80,
+    0x46, 0xD7, 0x6C, 0x45, 0x8C, 0xF3, 0x4F, 0x81, 0xFD, 0xC6, 0x7B, 0x73,
+];
+
+/// A PPF 3.0 patch turning the rom into [PMSR_TARGET], described as `Selftest`.
+const PPF_PATCH: &[u8] = &[
+    b'P', b'P', b'F', b'3', b'0', 0x02,
+    b'S', b'e', b'l', b'f', b't', b'e', b's', b't', b' ', b' ',
+    b' ', b' ', b' ', b' ', b' ', b' ', b' ', b' ', b' ', b' ',
+    b' ', b' ', b' ', b' ', b' ', b' ', b' ', b' ', b' ', b' ',
+    b' ', b' ', b' ', b' ', b' ', b' ', b' ', b' ', b' ', b' ',
+    b' ', b' ', b' ', b' ', b' ', b' ', b' ', b' ', b' ', b' ',
+    0x00, 0x00, 0x00, 0x00,
+    0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0xAA, 0xBB,
+    0x0B, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0xCC,
+];
+
+/// The data every embedded compressed asset decompresses to.
+const PLAIN: &[u8] = b"ABRACADABRA ABRACADABRA";
+const LZ77: &[u8] = &[
+    0x10, 0x17, 0x00, 0x00, 0x01, 0x41, 0x42, 0x52, 0x41, 0x43, 0x41, 0x44, 0x10, 0x06, 0x40, 0x20,
+    0x80, 0x0B, 0x00, 0x00,
+];
+const HUFFMAN: &[u8] = &[
+    0x28, 0x17, 0x00, 0x00, 0x05, 0x80, 0x41, 0x00, 0x81, 0xC0, 0x42, 0x52, 0x44, 0xC0, 0x20, 0x43,
+    0xA6, 0x6E, 0xB4, 0x6E, 0x00, 0xE0, 0x46, 0xEB,
+];
+const YAY0: &[u8] = &[
+    0x59, 0x61, 0x79, 0x30, 0x00, 0x00, 0x00, 0x17, 0x00, 0x00, 0x00, 0x14, 0x00, 0x00, 0x00, 0x18,
+    0xFE, 0x80, 0x00, 0x00, 0x20, 0x06, 0x90, 0x0B, 0x41, 0x42, 0x52, 0x41, 0x43, 0x41, 0x44, 0x20,
+];
+const YAZ0: &[u8] = &[
+    0x59, 0x61, 0x7A, 0x30, 0x00, 0x00, 0x00, 0x17, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0xFE, 0x41, 0x42, 0x52, 0x41, 0x43, 0x41, 0x44, 0x20, 0x06, 0x80, 0x20, 0x90, 0x0B,
+];
+
+/// A named check of [self_test], returning why it failed.
+type Check = (&'static str, fn() -> Result<(), String>);
+
+/// The failure of one check of [self_test].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SelfTestFailure {
+    /// name of the failed check, e.g. `"ips"`.
+    pub check: &'static str,
+    /// what went wrong.
+    pub reason: String,
+}
+
+/// The outcomes of [self_test].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SelfTestReport {
+    /// names of the checks that passed.
+    pub passed: Vec<&'static str>,
+    /// the checks that failed.
+    pub failures: Vec<SelfTestFailure>,
+}
+
+impl SelfTestReport {
+    /// returns `true` if every check passed.
+    pub fn is_success(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+/// checks every compiled-in patch format, codec and hash against embedded golden vectors.
+///
+/// Patches are decoded, applied and encoded again, compressed assets are decompressed and
+/// compressed again, each compared byte for byte against the expected output. This lets packagers
+/// and FFI consumers verify builds for targets the crate isn't tested on, such as big-endian or
+/// 32-bit platforms.
+///
+/// # Examples
+///
+/// ```
+/// let report = rom_patcher::self_test();
+/// assert!(report.is_success(), "{:?}", report.failures);
+/// ```
+pub fn self_test() -> SelfTestReport {
+    let checks: [Check; 12] = [
+        ("ips", || check_patch("ips", IPS_PATCH, IPS_TARGET)),
+        ("pmsr", || check_patch("pmsr", PMSR_PATCH, PMSR_TARGET)),
+        ("ups", || check_patch("ups", UPS_PATCH, IPS_TARGET)),
+        ("bps", || check_patch("bps", BPS_PATCH, IPS_TARGET)),
+        ("ppf", || check_patch("ppf", PPF_PATCH, PMSR_TARGET)),
+        ("lz77", || check_codec(&Lz77, LZ77)),
+        ("huffman", || check_codec(&Huffman { symbol_bits: 8 }, HUFFMAN)),
+        ("yay0", || check_codec(&Yay0, YAY0)),
+        ("yaz0", || check_codec(&Yaz0, YAZ0)),
+        ("crc32", || compare_hash(format!("{:08x}", Crc32::checksum(b"123456789")), "cbf43926")),
+        ("sha1", || compare_hash(to_hex(&Sha1::hash(b"abc")), "a9993e364706816aba3e25717850c26c9cd0d89d")),
+        ("sha256", || compare_hash(
+            to_hex(&Sha256::hash(b"abc")),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+        )),
+    ];
+    let mut report = SelfTestReport::default();
+    for (check, run) in checks {
+        match run() {
+            Ok(()) => report.passed.push(check),
+            Err(reason) => report.failures.push(SelfTestFailure { check, reason }),
+        }
+    }
+    return report;
+}
+
+fn check_patch(format: &str, data: &[u8], target: &[u8]) -> Result<(), String> {
+    let patch = read_any_from_slice(data).map_err(|e| format!("Unable to decode patch: {}", e))?;
+    if patch.format() != format {
+        return Err(format!("Patch was decoded as {}.", patch.format()));
+    }
+    let output = patch.apply_to_vec(SOURCE).map_err(|e| format!("Unable to apply patch: {}", e))?;
+    compare("Output", &output, target)?;
+    let encoded = patch.to_bytes().map_err(|e| format!("Unable to encode patch: {}", e))?;
+    return compare("Encoded patch", &encoded, data);
+}
+
+fn check_codec(codec: &dyn Codec, compressed: &[u8]) -> Result<(), String> {
+    let (decompressed, len) = codec.decompress(compressed).map_err(|e| format!("Unable to decompress: {}", e))?;
+    compare("Decompressed data", &decompressed, PLAIN)?;
+    // compressed assets may be padded
+    if len > compressed.len() {
+        return Err(format!("Compressed data spans {} bytes, past its end at {}.", len, compressed.len()));
+    }
+    let recompressed = codec.compress(PLAIN).map_err(|e| format!("Unable to compress: {}", e))?;
+    return compare("Compressed data", &recompressed, compressed);
+}
+
+fn compare_hash(actual: String, expected: &str) -> Result<(), String> {
+    if actual != expected {
+        return Err(format!("Hash is {}, expected {}.", actual, expected));
+    }
+    Ok(())
+}
+
+fn compare(name: &str, actual: &[u8], expected: &[u8]) -> Result<(), String> {
+    if actual.len() != expected.len() {
+        return Err(format!("{} is {} bytes long, expected {}.", name, actual.len(), expected.len()));
+    }
+    match actual.iter().zip(expected).position(|(a, b)| a != b) {
+        Some(offset) => Err(format!("{} differs first at offset {:#X}.", name, offset)),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use spectral::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn every_check_passes() {
+        let report = self_test();
+        assert_that!(report.failures).is_equal_to(Vec::new());
+        assert_that!(report.passed.len()).is_equal_to(12);
+    }
+
+    #[test]
+    fn mismatches_are_reported() {
+        let mut target = IPS_TARGET.to_vec();
+        target[2] = 0;
+        let err = check_patch("ips", IPS_PATCH, &target).unwrap_err();
+        assert_that!(err).is_equal_to("Output differs first at offset 0x2.".to_string());
+        assert_that!(check_patch("pmsr", IPS_PATCH, IPS_TARGET)).is_err();
+    }
+}
@@ -0,0 +1,504 @@
+//! A small HTTP service running [commands](crate::commands) as concurrent jobs.
+//!
+//! | Method   | Path          | Description                                                    |
+//! |----------|---------------|----------------------------------------------------------------|
+//! | `POST`   | `/jobs`       | starts a job for the [Request] in the body, returns its `id`   |
+//! | `GET`    | `/jobs/<id>`  | returns the state, latest progress and response of a job       |
+//! | `DELETE` | `/jobs/<id>`  | cancels a job                                                  |
+//! | `POST`   | `/run`        | runs a job for the [Request] in the body, returns its response |
+//!
+//! Jobs, including those of `/run`, wait in state `queued` until one of the server's workers picks
+//! them up and their estimated memory cost fits into the budget of its [Scheduler], see
+//! [Server::with_workers] and [Server::with_memory_budget]. Done jobs are forgotten once their
+//! response is fetched, or after [Server::with_job_ttl] if it never is.
+//!
+//! The service speaks just enough HTTP/1.1 for curl and HTTP client libraries and has no
+//! authentication, so it should only listen on trusted interfaces.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Result as IOResult, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::mpsc::{channel, sync_channel, Receiver, Sender, SyncSender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+
+use crate::commands::{handle_with_progress, Request, Response};
+#[cfg(feature = "eventlog")]
+use crate::eventlog::EventLog;
+use crate::progress::{CancellationToken, Progress};
+use crate::registry::read_any_from_slice;
+use crate::scheduler::{estimate_cost, Scheduler};
+
+/// the largest request body accepted.
+const MAX_BODY_LEN: usize = 1 << 20;
+
+/// the most jobs waiting for a worker at once, more are refused.
+const MAX_QUEUED_JOBS: usize = 1024;
+
+#[derive(Debug, Default)]
+struct JobState {
+    running: bool,
+    progress: Option<Progress>,
+    response: Option<Response>,
+    finished: Option<Instant>,
+}
+
+#[derive(Debug)]
+struct Job {
+    state: Mutex<JobState>,
+    /// notified once the response is set.
+    done: Condvar,
+    cancellation: CancellationToken,
+}
+
+impl Job {
+    fn new() -> Job {
+        Job { state: Mutex::new(JobState::default()), done: Condvar::new(), cancellation: CancellationToken::new() }
+    }
+}
+
+#[derive(Debug)]
+struct Jobs {
+    next_id: u64,
+    jobs: HashMap<u64, Arc<Job>>,
+    ttl: Duration,
+}
+
+impl Jobs {
+    fn new(ttl: Duration) -> Jobs {
+        Jobs { next_id: 0, jobs: HashMap::new(), ttl }
+    }
+
+    /// forgets the jobs that have been done for longer than the ttl.
+    fn evict_expired(&mut self) {
+        let ttl = self.ttl;
+        self.jobs.retain(|_, job| job.state.lock().unwrap().finished.is_none_or(|x| x.elapsed() < ttl));
+    }
+}
+
+/// A job waiting for a worker.
+type QueuedJob = (Request, Arc<Job>);
+
+/// Executes the requests of a server, logging them if it has an event log.
+#[derive(Debug, Clone, Default)]
+struct Runner {
+    #[cfg(feature = "eventlog")]
+    event_log: Option<Arc<EventLog>>,
+}
+
+impl Runner {
+    fn run(&self, request: &Request, progress: Option<Sender<Progress>>, cancellation: &CancellationToken) -> Response {
+        #[cfg(feature = "eventlog")]
+        if let Some(event_log) = &self.event_log {
+            return event_log.handle(request, progress, cancellation);
+        }
+        handle_with_progress(request, progress, cancellation)
+    }
+}
+
+/// An HTTP server running patch jobs.
+///
+/// # Examples
+///
+/// ```no_run
+/// use rom_patcher::server::Server;
+/// let server = Server::bind("127.0.0.1:8080").expect("Unable to bind.");
+/// server.serve().expect("Server failed.");
+/// ```
+#[derive(Debug)]
+pub struct Server {
+    listener: TcpListener,
+    jobs: Arc<Mutex<Jobs>>,
+    scheduler: Arc<Scheduler>,
+    runner: Runner,
+    workers: usize,
+}
+
+impl Server {
+    /// How long done jobs are kept for by default if their response is never fetched.
+    pub const DEFAULT_JOB_TTL: Duration = Duration::from_secs(600);
+
+    /// constructs a server listening on `address`, with a worker per available CPU.
+    pub fn bind(address: impl ToSocketAddrs) -> IOResult<Server> {
+        Ok(Server {
+            listener: TcpListener::bind(address)?,
+            jobs: Arc::new(Mutex::new(Jobs::new(Self::DEFAULT_JOB_TTL))),
+            scheduler: Arc::new(Scheduler::unlimited()),
+            runner: Runner::default(),
+            workers: thread::available_parallelism().map_or(1, |x| x.get()),
+        })
+    }
+
+    /// modifies the server to run at most `workers` jobs at once, at least one.
+    pub fn with_workers(mut self, workers: usize) -> Server {
+        self.workers = workers.max(1);
+        return self;
+    }
+
+    /// modifies the server to forget done jobs whose response isn't fetched within `ttl`.
+    pub fn with_job_ttl(mut self, ttl: Duration) -> Server {
+        self.jobs = Arc::new(Mutex::new(Jobs::new(ttl)));
+        return self;
+    }
+
+    /// modifies the server to only run jobs estimated to need `budget` bytes of memory in total at
+    /// once, queueing the rest.
+    pub fn with_memory_budget(mut self, budget: u64) -> Server {
+        self.scheduler = Arc::new(Scheduler::new(budget));
+        return self;
+    }
+
+    /// modifies the server to log an [Event](crate::eventlog::Event) for every request it runs to
+    /// `event_log`.
+    #[cfg(feature = "eventlog")]
+    pub fn with_event_log(mut self, event_log: EventLog) -> Server {
+        self.runner.event_log = Some(Arc::new(event_log));
+        return self;
+    }
+
+    /// returns the address the server listens on.
+    pub fn local_addr(&self) -> IOResult<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// serves connections until accepting one fails, each on its own thread.
+    ///
+    /// Jobs are run by the workers, which stop once the server does.
+    pub fn serve(&self) -> IOResult<()> {
+        let (queue, receiver) = sync_channel(MAX_QUEUED_JOBS);
+        let receiver = Arc::new(Mutex::new(receiver));
+        for _ in 0..self.workers {
+            let receiver = receiver.clone();
+            let scheduler = self.scheduler.clone();
+            let runner = self.runner.clone();
+            thread::spawn(move || work(&receiver, &scheduler, &runner));
+        }
+        for stream in self.listener.incoming() {
+            let stream = stream?;
+            let jobs = self.jobs.clone();
+            let queue = queue.clone();
+            thread::spawn(move || {
+                // a client going away mid request only affects its own connection
+                let _ = handle_connection(stream, &jobs, &queue);
+            });
+        }
+        Ok(())
+    }
+}
+
+fn handle_connection(stream: TcpStream, jobs: &Arc<Mutex<Jobs>>, queue: &SyncSender<QueuedJob>) -> IOResult<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let path = parts.next().unwrap_or_default().to_string();
+
+    let mut content_length = 0;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().unwrap_or(0);
+            }
+        }
+    }
+    if content_length > MAX_BODY_LEN {
+        return respond(stream, 413, &json!({ "error": "Request body too large." }));
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+
+    let (status, response) = route(&method, &path, &body, jobs, queue);
+    respond(stream, status, &response)
+}
+
+fn route(method: &str, path: &str, body: &[u8], jobs: &Arc<Mutex<Jobs>>, queue: &SyncSender<QueuedJob>) -> (u16, Value) {
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    match (method, segments.as_slice()) {
+        ("POST", ["run"]) => match parse_request(body) {
+            Ok(request) => match run_job(request, queue) {
+                Some(response) => (200, to_value(&response)),
+                None => (503, json!({ "error": "Too many queued jobs." })),
+            },
+            Err(e) => (400, e),
+        },
+        ("POST", ["jobs"]) => match parse_request(body) {
+            Ok(request) => match start_job(request, jobs, queue) {
+                Some(id) => (202, json!({ "id": id })),
+                None => (503, json!({ "error": "Too many queued jobs." })),
+            },
+            Err(e) => (400, e),
+        },
+        ("GET", ["jobs", id]) => match find_job(id, jobs) {
+            Some(job) => {
+                let state = job.state.lock().unwrap();
+                let status = match (&state.response, job.cancellation.is_cancelled(), state.running) {
+                    (Some(_), _, _) => "done",
+                    (None, true, _) => "cancelling",
+                    (None, false, true) => "running",
+                    (None, false, false) => "queued",
+                };
+                let body = json!({ "state": status, "progress": state.progress, "response": state.response });
+                drop(state);
+                if let (Ok(id), "done") = (id.parse::<u64>(), status) {
+                    // the response is handed out once, so done jobs don't pile up
+                    jobs.lock().unwrap().jobs.remove(&id);
+                }
+                (200, body)
+            }
+            None => (404, json!({ "error": "Unknown job." })),
+        },
+        ("DELETE", ["jobs", id]) => match find_job(id, jobs) {
+            Some(job) => {
+                job.cancellation.cancel();
+                (202, json!({ "id": id }))
+            }
+            None => (404, json!({ "error": "Unknown job." })),
+        },
+        _ => (404, json!({ "error": "Unknown endpoint." })),
+    }
+}
+
+fn parse_request(body: &[u8]) -> Result<Request, Value> {
+    serde_json::from_slice(body).map_err(|e| json!({ "error": format!("Invalid request: {}.", e) }))
+}
+
+/// returns the estimated memory cost of running `request`, see [estimate_cost].
+fn job_cost(request: &Request) -> u64 {
+    let len = |path: &std::path::Path| fs::metadata(path).map_or(0, |x| x.len());
+    match request {
+        Request::Apply { source, patch, .. } => {
+            let info = fs::read(patch).ok()
+                .and_then(|x| read_any_from_slice(&x).ok())
+                .map(|x| x.info())
+                .unwrap_or_default();
+            estimate_cost(&info, len(source), len(patch))
+        }
+        // the created patch is at most as large as the target
+        Request::Create { source, target, .. } => len(source) + len(target) * 2,
+        _ => 0,
+    }
+}
+
+/// queues `request` for the workers and returns the id of its job, or [None] if the queue is full.
+fn start_job(request: Request, jobs: &Arc<Mutex<Jobs>>, queue: &SyncSender<QueuedJob>) -> Option<u64> {
+    let job = Arc::new(Job::new());
+    let mut jobs = jobs.lock().unwrap();
+    jobs.evict_expired();
+    if queue.try_send((request, job.clone())).is_err() {
+        return None;
+    }
+    jobs.next_id += 1;
+    let id = jobs.next_id;
+    jobs.jobs.insert(id, job);
+    return Some(id);
+}
+
+/// queues `request` for the workers like [start_job] and waits for its response, or returns [None]
+/// if the queue is full. The job isn't listed, as nobody else can poll it.
+fn run_job(request: Request, queue: &SyncSender<QueuedJob>) -> Option<Response> {
+    let job = Arc::new(Job::new());
+    queue.try_send((request, job.clone())).ok()?;
+    let mut state = job.state.lock().unwrap();
+    while state.response.is_none() {
+        state = job.done.wait(state).unwrap();
+    }
+    state.response.take()
+}
+
+/// runs queued jobs one after another until the server is gone.
+fn work(queue: &Mutex<Receiver<QueuedJob>>, scheduler: &Scheduler, runner: &Runner) {
+    loop {
+        // the lock is only held while waiting for the next job, not while running it
+        let next = queue.lock().unwrap().recv();
+        let Ok((request, job)) = next else {
+            return;
+        };
+        let _permit = scheduler.acquire(job_cost(&request));
+        job.state.lock().unwrap().running = true;
+        let (sender, receiver) = channel();
+        let progress_job = job.clone();
+        let progress = thread::spawn(move || {
+            for update in receiver {
+                progress_job.state.lock().unwrap().progress = Some(update);
+            }
+        });
+        let response = runner.run(&request, Some(sender), &job.cancellation);
+        // the sender is gone with the request, so this waits for the last progress update only
+        let _ = progress.join();
+        let mut state = job.state.lock().unwrap();
+        state.response = Some(response);
+        state.finished = Some(Instant::now());
+        job.done.notify_all();
+    }
+}
+
+fn find_job(id: &str, jobs: &Arc<Mutex<Jobs>>) -> Option<Arc<Job>> {
+    let id: u64 = id.parse().ok()?;
+    let mut jobs = jobs.lock().unwrap();
+    jobs.evict_expired();
+    jobs.jobs.get(&id).cloned()
+}
+
+fn to_value(response: &Response) -> Value {
+    // responses only contain plain data
+    serde_json::to_value(response).unwrap()
+}
+
+fn respond(mut stream: TcpStream, status: u16, body: &Value) -> IOResult<()> {
+    let reason = match status {
+        200 => "OK",
+        202 => "Accepted",
+        400 => "Bad Request",
+        404 => "Not Found",
+        413 => "Payload Too Large",
+        503 => "Service Unavailable",
+        _ => "Internal Server Error",
+    };
+    let body = body.to_string();
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status, reason, body.len(), body
+    )?;
+    stream.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::time::Duration;
+
+    use spectral::prelude::*;
+
+    use crate::testkit::Fixture;
+
+    use super::*;
+
+    fn spawn_server() -> SocketAddr {
+        let server = Server::bind("127.0.0.1:0").unwrap().with_memory_budget(1 << 30);
+        let address = server.local_addr().unwrap();
+        thread::spawn(move || server.serve());
+        return address;
+    }
+
+    fn send(address: SocketAddr, method: &str, path: &str, body: &str) -> (u16, Value) {
+        let mut stream = TcpStream::connect(address).unwrap();
+        write!(stream, "{} {} HTTP/1.1\r\nHost: test\r\nContent-Length: {}\r\n\r\n{}", method, path, body.len(), body).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let status = response[9..12].parse().unwrap();
+        let (_, body) = response.split_once("\r\n\r\n").unwrap();
+        return (status, serde_json::from_str(body).unwrap());
+    }
+
+    #[test]
+    fn run_answers_synchronously() {
+        let address = spawn_server();
+        let (status, body) = send(address, "POST", "/run", r#"{"command": "formats"}"#);
+        assert_that!(status).is_equal_to(200);
+        assert_that!(body["status"].as_str()).is_equal_to(Some("ok"));
+    }
+
+    #[cfg(feature = "eventlog")]
+    #[test]
+    fn requests_are_logged() {
+        let path = std::env::temp_dir().join(format!("rom-patcher-server-log-{}.jsonl", std::process::id()));
+        let server = Server::bind("127.0.0.1:0").unwrap().with_event_log(EventLog::new(fs::File::create(&path).unwrap()));
+        let address = server.local_addr().unwrap();
+        thread::spawn(move || server.serve());
+        send(address, "POST", "/run", r#"{"command": "formats"}"#);
+        let log = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let event: crate::eventlog::Event = serde_json::from_str(log.lines().next().unwrap()).unwrap();
+        assert_that!(event.operation).is_equal_to("formats".to_string());
+    }
+
+    #[test]
+    fn jobs_can_be_polled_until_done() {
+        let dir = env::temp_dir().join(format!("rom-patcher-server-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let fixture = Fixture::generate(4);
+        fs::write(dir.join("source.bin"), &fixture.source).unwrap();
+        let mut patch = Vec::new();
+        fixture.ips.write(&mut patch).unwrap();
+        fs::write(dir.join("patch.ips"), patch).unwrap();
+
+        let address = spawn_server();
+        let request = json!({
+            "command": "apply",
+            "source": dir.join("source.bin"),
+            "patch": dir.join("patch.ips"),
+            "output": dir.join("out.bin"),
+        });
+        let (status, body) = send(address, "POST", "/jobs", &request.to_string());
+        assert_that!(status).is_equal_to(202);
+        let path = format!("/jobs/{}", body["id"]);
+
+        let mut polled = send(address, "GET", &path, "").1;
+        for _ in 0..500 {
+            if polled["state"] == "done" {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+            polled = send(address, "GET", &path, "").1;
+        }
+        assert_that!(polled["state"].as_str()).is_equal_to(Some("done"));
+        assert_that!(polled["response"]["status"].as_str()).is_equal_to(Some("ok"));
+        assert_that!(polled["progress"]["stage"].as_str()).is_equal_to(Some("done"));
+        assert_that!(fs::read(dir.join("out.bin")).unwrap()).is_equal_to(&fixture.target);
+        assert_that!(send(address, "GET", &path, "").0).is_equal_to(404);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn jobs_queue_for_the_workers() {
+        let server = Server::bind("127.0.0.1:0").unwrap().with_workers(1);
+        let address = server.local_addr().unwrap();
+        thread::spawn(move || server.serve());
+        let paths: Vec<String> = (0..4)
+            .map(|_| format!("/jobs/{}", send(address, "POST", "/jobs", r#"{"command": "formats"}"#).1["id"]))
+            .collect();
+        for path in paths {
+            let mut polled = send(address, "GET", &path, "").1;
+            for _ in 0..500 {
+                if polled["state"] == "done" {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(10));
+                polled = send(address, "GET", &path, "").1;
+            }
+            assert_that!(polled["response"]["status"].as_str()).is_equal_to(Some("ok"));
+        }
+    }
+
+    #[test]
+    fn expired_jobs_are_evicted() {
+        let mut jobs = Jobs::new(Duration::ZERO);
+        let job = |finished| Arc::new(Job {
+            state: Mutex::new(JobState { finished, ..JobState::default() }),
+            ..Job::new()
+        });
+        jobs.jobs.insert(1, job(Some(Instant::now())));
+        jobs.jobs.insert(2, job(None));
+        jobs.evict_expired();
+        assert_that!(jobs.jobs.keys().copied().collect::<Vec<_>>()).is_equal_to(vec![2]);
+    }
+
+    #[test]
+    fn unknown_jobs_are_not_found() {
+        let address = spawn_server();
+        assert_that!(send(address, "GET", "/jobs/99", "").0).is_equal_to(404);
+        assert_that!(send(address, "DELETE", "/jobs/99", "").0).is_equal_to(404);
+    }
+}
@@ -0,0 +1,179 @@
+//! Symbol maps of assemblers, used to annotate patches with the routines they alter.
+//!
+//! The format read is the `.sym` format written by WLA-DX, asar and bass: one `address name` pair
+//! per line, addresses in hex optionally split into `bank:address`. Lines in sections other than
+//! `[labels]` and comments starting with `;` are ignored.
+
+use std::fmt::{Display, Formatter};
+
+use crate::console::Console;
+use crate::Error;
+use crate::ErrorKind::ParsingError;
+use crate::ips::IPSPatch;
+
+/// A named address of a symbol map.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Symbol {
+    /// name of the symbol.
+    pub name: String,
+    /// the CPU address the symbol was defined at.
+    pub address: u32,
+    /// the rom offset the address maps to.
+    pub offset: u64,
+}
+
+/// The symbols of a symbol map that lie in rom, ordered by offset.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SymbolMap {
+    symbols: Vec<Symbol>,
+}
+
+/// A written range of a patch together with the symbol it belongs to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Annotation {
+    /// index of the annotated hunk.
+    pub hunk: usize,
+    /// rom offset of the first written byte.
+    pub offset: u64,
+    /// amount of bytes written.
+    pub length: u64,
+    /// name of the nearest symbol at or before [Annotation::offset], [None] if there is none.
+    pub symbol: Option<String>,
+    /// distance of [Annotation::offset] from the symbol.
+    pub displacement: u64,
+}
+
+impl SymbolMap {
+    /// parses the symbol map `text`, mapping its addresses to rom offsets through `console`.
+    ///
+    /// Symbols that don't map to rom, like RAM variables, are left out.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rom_patcher::console::Console;
+    /// use rom_patcher::symbols::SymbolMap;
+    /// let map = SymbolMap::parse("[labels]\n00:8000 reset\n00:8040 main\n", Console::SnesLoRom).unwrap();
+    /// assert_eq!(map.nearest(0x48).unwrap().name, "main");
+    /// ```
+    pub fn parse(text: &str, console: Console) -> Result<SymbolMap, Error> {
+        let mut symbols = Vec::new();
+        let mut in_labels = true;
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split(';').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            if line.starts_with('[') {
+                in_labels = line.eq_ignore_ascii_case("[labels]");
+                continue;
+            }
+            if !in_labels {
+                continue;
+            }
+            let invalid = || Error::new(ParsingError)
+                .with_description(format!("Invalid symbol on line {}.", number + 1));
+            let (address, name) = line.split_once(char::is_whitespace).ok_or_else(invalid)?;
+            let address = parse_address(address).ok_or_else(invalid)?;
+            if let Some(offset) = console.rom_offset(address) {
+                symbols.push(Symbol { name: name.trim().to_string(), address, offset: offset as u64 });
+            }
+        }
+        symbols.sort_by_key(|x| x.offset);
+        return Ok(SymbolMap { symbols });
+    }
+
+    /// returns the symbols ordered by offset.
+    pub fn symbols(&self) -> &[Symbol] {
+        &self.symbols
+    }
+
+    /// returns the last symbol at or before `offset`.
+    pub fn nearest(&self, offset: u64) -> Option<&Symbol> {
+        let index = self.symbols.partition_point(|x| x.offset <= offset);
+        return index.checked_sub(1).map(|i| &self.symbols[i]);
+    }
+
+    /// annotates every hunk of `patch` with its nearest symbol, in the order the hunks are applied.
+    pub fn annotate(&self, patch: &IPSPatch) -> Vec<Annotation> {
+        patch.hunks()
+            .iter()
+            .enumerate()
+            .map(|(hunk, x)| {
+                let offset = x.offset();
+                let symbol = self.nearest(offset);
+                Annotation {
+                    hunk,
+                    offset,
+                    length: x.length(),
+                    symbol: symbol.map(|x| x.name.clone()),
+                    displacement: symbol.map_or(0, |x| offset - x.offset),
+                }
+            })
+            .collect()
+    }
+}
+
+/// parses `bank:address` or a plain address in hex.
+fn parse_address(text: &str) -> Option<u32> {
+    match text.split_once(':') {
+        Some((bank, low)) => {
+            let bank = u32::from_str_radix(bank, 16).ok()?;
+            let low = u32::from_str_radix(low, 16).ok()?;
+            if low > 0xFFFF {
+                return None;
+            }
+            bank.checked_mul(0x10000).map(|x| x | low)
+        }
+        None => u32::from_str_radix(text.trim_start_matches('$'), 16).ok(),
+    }
+}
+
+impl Display for Annotation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "hunk {} at 0x{:06X} ({} bytes)", self.hunk, self.offset, self.length)?;
+        match (&self.symbol, self.displacement) {
+            (Some(symbol), 0) => write!(f, " in {}", symbol),
+            (Some(symbol), displacement) => write!(f, " in {}+0x{:X}", symbol, displacement),
+            (None, _) => write!(f, " before any symbol"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use spectral::prelude::*;
+
+    use crate::ips::{IPSHunk, IPSRLEHunkData};
+
+    use super::*;
+
+    const MAP: &str = "; wla-dx symbols\n[labels]\n00:8000 reset\n01:8010 draw_hud\n7E:0100 player_hp\n\n[definitions]\n00000010 HP_MAX\n";
+
+    #[test]
+    fn parses_labels_in_rom() {
+        let map = SymbolMap::parse(MAP, Console::SnesLoRom).unwrap();
+        let names: Vec<&str> = map.symbols().iter().map(|x| x.name.as_str()).collect();
+        assert_that!(names).is_equal_to(vec!["reset", "draw_hud"]);
+        assert_that!(map.symbols()[1].offset).is_equal_to(0x8010);
+    }
+
+    #[test]
+    fn invalid_lines_fail() {
+        let result = SymbolMap::parse("00:8000 reset\nnonsense\n", Console::SnesLoRom);
+        assert_that!(result).is_err();
+    }
+
+    #[test]
+    fn annotates_hunks_with_nearest_symbol() {
+        let map = SymbolMap::parse(MAP, Console::SnesLoRom).unwrap();
+        let patch = IPSPatch::new()
+            .with_hunk(IPSHunk::RLE(IPSRLEHunkData { offset: 0x8014, run_length: 2, payload: 0xEA }))
+            .with_hunk(IPSHunk::RLE(IPSRLEHunkData { offset: 0, run_length: 1, payload: 0xEA }));
+        let lines: Vec<String> = map.annotate(&patch).iter().map(|x| x.to_string()).collect();
+        assert_that!(lines).is_equal_to(vec![
+            "hunk 0 at 0x008014 (2 bytes) in draw_hud+0x4".to_string(),
+            "hunk 1 at 0x000000 (1 bytes) in reset".to_string(),
+        ]);
+    }
+}
@@ -0,0 +1,311 @@
+//! Synthetic roms for integration tests and demos.
+//!
+//! The generated roms carry the headers, vectors and checksums of their console, so
+//! [Console::detect] recognizes them and console specific code like
+//! [Console::fix_checksum] has something real to work on, but their content is pseudo-random
+//! filler derived from a seed. Nothing copyrighted is reproduced: the Nintendo logo of GBA headers
+//! and the boot code of Nintendo 64 cartridges are left as filler, so real hardware and
+//! emulators checking them won't boot the roms.
+//!
+//! This module is only compiled for tests or with the `dev-tools` feature enabled.
+
+use std::fs;
+use std::io::Result as IOResult;
+use std::path::{Path, PathBuf};
+
+use crate::console::Console;
+use crate::testkit::Rng;
+
+/// Options controlling the shape of generated roms.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RomOptions {
+    /// the console the rom is made for.
+    pub console: Console,
+    /// size of the rom without a copier header, [None] for the default of the console.
+    ///
+    /// Sizes are raised to the smallest rom the console's header fits in, and NES roms are
+    /// rounded to whole PRG banks.
+    pub size: Option<usize>,
+    /// the title written to the header, cut to the length the header has room for.
+    pub title: String,
+    /// whether SNES roms get a 512 byte copier header.
+    pub copier_header: bool,
+}
+
+impl RomOptions {
+    /// returns the default options for roms of `console`.
+    pub fn new(console: Console) -> RomOptions {
+        RomOptions {
+            console,
+            size: None,
+            title: "SYNTHETIC ROM".to_string(),
+            copier_header: false,
+        }
+    }
+
+    /// returns the size of the generated rom without a copier header.
+    fn rom_size(&self) -> usize {
+        let (default, min) = match self.console {
+            Console::Flat => (0x10000, 0),
+            Console::SnesLoRom => (0x40000, 0x8000),
+            Console::SnesHiRom => (0x40000, 0x10000),
+            Console::GameBoyAdvance => (0x40000, 0xC0),
+            // the checksum covers the first megabyte past the boot code
+            Console::Nintendo64 => (N64_CHECKSUM_END, N64_CHECKSUM_END),
+            Console::Genesis => (0x20000, 0x200),
+            Console::Nes => (0x8000 + 0x2000 + 16, 0x4000 + 0x2000 + 16),
+        };
+        let size = self.size.unwrap_or(default).max(min);
+        if self.console == Console::Nes {
+            return 16 + nes_prg_banks(size) * 0x4000 + 0x2000;
+        }
+        return size;
+    }
+}
+
+/// Nintendo 64 checksums cover the rom from `0x1000` up to here.
+const N64_CHECKSUM_END: usize = 0x101000;
+
+/// returns the amount of 16 KiB PRG banks of an NES rom of `size` bytes with one CHR bank.
+fn nes_prg_banks(size: usize) -> usize {
+    ((size - 16 - 0x2000) / 0x4000).clamp(1, 0xFF)
+}
+
+/// generates a rom for `console` from `seed` using the [default options](RomOptions::new).
+///
+/// # Examples
+///
+/// ```
+/// use rom_patcher::console::Console;
+/// use rom_patcher::synthetic::generate_rom;
+/// let rom = generate_rom(Console::Genesis, 1);
+/// assert_eq!(Console::detect(&rom), Some(Console::Genesis));
+/// ```
+pub fn generate_rom(console: Console, seed: u64) -> Vec<u8> {
+    generate_rom_with(seed, &RomOptions::new(console))
+}
+
+/// generates a rom from `seed` shaped by `options`.
+pub fn generate_rom_with(seed: u64, options: &RomOptions) -> Vec<u8> {
+    let mut rng = Rng::new(seed);
+    let size = options.rom_size();
+    let mut rom = vec![0; size];
+    // unused space at the end is padded like on real cartridges
+    let padding = size - size / 4;
+    // leave the start empty, random bytes there could pass as another console's header
+    rng.fill(&mut rom[padding.min(0x200)..padding]);
+    rom[padding..].fill(0xFF);
+
+    let title = options.title.as_bytes();
+    match options.console {
+        Console::Flat => {
+            clear_snes_header(&mut rom, 0x7FC0);
+            clear_snes_header(&mut rom, 0xFFC0);
+        }
+        Console::SnesLoRom => write_snes_header(&mut rom, 0x7FC0, 0x20, title),
+        Console::SnesHiRom => {
+            clear_snes_header(&mut rom, 0x7FC0);
+            write_snes_header(&mut rom, 0xFFC0, 0x21, title);
+        }
+        Console::GameBoyAdvance => {
+            // branch over the header to 0xC0
+            rom[..4].copy_from_slice(&0xEA00002Eu32.to_le_bytes());
+            write_padded(&mut rom[0xA0..0xAC], title, 0);
+            rom[0xAC..0xB2].copy_from_slice(b"ASYE01");
+            rom[0xB2] = 0x96;
+            Console::GameBoyAdvance.fix_checksum(&mut rom);
+        }
+        Console::Nintendo64 => {
+            rom[..4].copy_from_slice(&[0x80, 0x37, 0x12, 0x40]);
+            rom[4..8].copy_from_slice(&0x0Fu32.to_be_bytes());
+            rom[8..12].copy_from_slice(&0x80000400u32.to_be_bytes());
+            write_padded(&mut rom[0x20..0x34], title, b' ');
+            rom[0x3B..0x3F].copy_from_slice(b"NSYE");
+            let (crc1, crc2) = n64_checksum(&rom);
+            rom[0x10..0x14].copy_from_slice(&crc1.to_be_bytes());
+            rom[0x14..0x18].copy_from_slice(&crc2.to_be_bytes());
+        }
+        Console::Genesis => {
+            rom[..4].copy_from_slice(&0x00FF0000u32.to_be_bytes());
+            rom[4..8].copy_from_slice(&0x200u32.to_be_bytes());
+            rom[0x100..0x200].fill(b' ');
+            rom[0x100..0x110].copy_from_slice(b"SEGA MEGA DRIVE ");
+            rom[0x110..0x120].copy_from_slice(b"(C)SYNT 2024.JAN");
+            write_padded(&mut rom[0x120..0x150], title, b' ');
+            write_padded(&mut rom[0x150..0x180], title, b' ');
+            rom[0x180..0x18E].copy_from_slice(b"GM 00000000-00");
+            rom[0x190] = b'J';
+            rom[0x1A0..0x1A4].copy_from_slice(&0u32.to_be_bytes());
+            rom[0x1A4..0x1A8].copy_from_slice(&(size as u32 - 1).to_be_bytes());
+            rom[0x1A8..0x1AC].copy_from_slice(&0x00FF0000u32.to_be_bytes());
+            rom[0x1AC..0x1B0].copy_from_slice(&0x00FFFFFFu32.to_be_bytes());
+            rom[0x1F0..0x1F3].copy_from_slice(b"JUE");
+            Console::Genesis.fix_checksum(&mut rom);
+        }
+        Console::Nes => {
+            let prg_banks = nes_prg_banks(size);
+            // mapper 0 with vertical mirroring
+            rom[..16].copy_from_slice(&[b'N', b'E', b'S', 0x1A, prg_banks as u8, 1, 0x01, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+            let vectors = 16 + prg_banks * 0x4000 - 6;
+            for (i, vector) in [0x8000u16, 0x8000, 0x8000].iter().enumerate() {
+                rom[vectors + i * 2..vectors + i * 2 + 2].copy_from_slice(&vector.to_le_bytes());
+            }
+        }
+    }
+
+    if options.copier_header && matches!(options.console, Console::SnesLoRom | Console::SnesHiRom) {
+        let mut result = vec![0; 512];
+        result.extend(rom);
+        return result;
+    }
+    return rom;
+}
+
+/// writes a SNES internal header at `offset` with `map_mode` and a matching reset vector, and
+/// fixes its checksum.
+fn write_snes_header(rom: &mut [u8], offset: usize, map_mode: u8, title: &[u8]) {
+    let size_kib = rom.len().div_ceil(1024).next_power_of_two();
+    let header = &mut rom[offset..offset + 0x40];
+    write_padded(&mut header[..0x15], title, b' ');
+    header[0x15] = map_mode;
+    header[0x16] = 0x00; // rom only
+    header[0x17] = size_kib.ilog2() as u8;
+    header[0x18] = 0x00; // no save RAM
+    header[0x19] = 0x01; // North America
+    header[0x1A] = 0x00;
+    header[0x1B] = 0x00; // version 1.0
+    // every vector points at the start of bank 0, where a real rom has its code
+    for vector in header[0x20..0x40].chunks_mut(2) {
+        vector.copy_from_slice(&0x8000u16.to_le_bytes());
+    }
+    let console = if map_mode == 0x20 { Console::SnesLoRom } else { Console::SnesHiRom };
+    console.fix_checksum(rom);
+}
+
+/// clears the checksum of a SNES internal header at `offset` so filler there can't pass as one.
+fn clear_snes_header(rom: &mut [u8], offset: usize) {
+    if let Some(checksum) = rom.get_mut(offset + 0x1C..offset + 0x20) {
+        checksum.fill(0);
+    }
+}
+
+/// copies as much of `value` as fits into `field` and fills the rest with `padding`.
+fn write_padded(field: &mut [u8], value: &[u8], padding: u8) {
+    let len = value.len().min(field.len());
+    field[..len].copy_from_slice(&value[..len]);
+    field[len..].fill(padding);
+}
+
+/// returns the two checksums of a Nintendo 64 rom as the CIC-NUS-6102 boot code computes them.
+fn n64_checksum(rom: &[u8]) -> (u32, u32) {
+    let seed = 0xF8CA4DDCu32;
+    let (mut t1, mut t2, mut t3, mut t4, mut t5, mut t6) = (seed, seed, seed, seed, seed, seed);
+    for word in rom[0x1000..N64_CHECKSUM_END].chunks(4) {
+        let d = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        let (sum, carry) = t6.overflowing_add(d);
+        if carry {
+            t4 = t4.wrapping_add(1);
+        }
+        t6 = sum;
+        t3 ^= d;
+        let r = d.rotate_left(d & 0x1F);
+        t5 = t5.wrapping_add(r);
+        if t2 > d {
+            t2 ^= r;
+        } else {
+            t2 ^= t6 ^ d;
+        }
+        t1 = t1.wrapping_add(t5 ^ d);
+    }
+    return (t6 ^ t4 ^ t3, t5 ^ t2 ^ t1);
+}
+
+/// writes a rom generated from `seed` for every console but [Console::Flat] to `dir`, named after
+/// the console with its canonical extension, and returns their paths.
+///
+/// `dir` is created if it does not exist.
+pub fn write_corpus(dir: &Path, seed: u64) -> IOResult<Vec<PathBuf>> {
+    fs::create_dir_all(dir)?;
+    let mut result = Vec::new();
+    for console in Console::ALL {
+        let (Some(name), Some(extension)) = (file_stem(console), console.extension()) else {
+            continue;
+        };
+        let path = dir.join(format!("{}.{}", name, extension));
+        fs::write(&path, generate_rom(console, seed))?;
+        result.push(path);
+    }
+    Ok(result)
+}
+
+fn file_stem(console: Console) -> Option<&'static str> {
+    match console {
+        Console::Flat => None,
+        Console::SnesLoRom => Some("snes-lorom"),
+        Console::SnesHiRom => Some("snes-hirom"),
+        Console::GameBoyAdvance => Some("gba"),
+        Console::Nintendo64 => Some("n64"),
+        Console::Genesis => Some("genesis"),
+        Console::Nes => Some("nes"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use spectral::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn roms_are_detected_as_their_console() {
+        for console in Console::ALL {
+            let rom = generate_rom(console, 3);
+            let expected = if console == Console::Flat { None } else { Some(console) };
+            assert_that!(Console::detect(&rom)).is_equal_to(expected);
+            assert_that!(rom).is_equal_to(generate_rom(console, 3));
+        }
+    }
+
+    #[test]
+    fn checksums_are_correct() {
+        for console in Console::ALL {
+            for copier_header in [false, true] {
+                let options = RomOptions { copier_header, ..RomOptions::new(console) };
+                let rom = generate_rom_with(5, &options);
+                let mut fixed = rom.clone();
+                console.fix_checksum(&mut fixed);
+                assert_that!(fixed).is_equal_to(&rom);
+            }
+        }
+    }
+
+    #[test]
+    fn n64_checksums_match_the_boot_code() {
+        // zeros leave every running sum at the seed but the last, which adds it once per word
+        let seed = 0xF8CA4DDCu32;
+        assert_that!(n64_checksum(&vec![0; N64_CHECKSUM_END])).is_equal_to((seed, seed.wrapping_mul(0x40001)));
+        let generated = generate_rom(Console::Nintendo64, 1);
+        let expected = n64_checksum(&generated);
+        assert_that!(u32::from_be_bytes(generated[0x10..0x14].try_into().unwrap())).is_equal_to(expected.0);
+    }
+
+    #[test]
+    fn sizes_are_adjusted_to_the_console() {
+        let options = |console, size| RomOptions { size: Some(size), ..RomOptions::new(console) };
+        assert_that!(generate_rom_with(1, &options(Console::SnesHiRom, 0x100)).len()).is_equal_to(0x10000);
+        assert_that!(generate_rom_with(1, &options(Console::Nes, 0x10000)).len()).is_equal_to(16 + 3 * 0x4000 + 0x2000);
+        let headered = RomOptions { copier_header: true, ..options(Console::SnesLoRom, 0x8000) };
+        assert_that!(generate_rom_with(1, &headered).len()).is_equal_to(0x8200);
+    }
+
+    #[test]
+    fn corpus_is_written_with_canonical_extensions() {
+        let dir = std::env::temp_dir().join(format!("rom-patcher-synthetic-{}", std::process::id()));
+        let paths = write_corpus(&dir, 1).unwrap();
+        let names: Vec<String> = paths.iter().map(|x| x.file_name().unwrap().to_string_lossy().into_owned()).collect();
+        assert_that!(names).contains("snes-hirom.sfc".to_string());
+        assert_that!(names).contains("n64.z64".to_string());
+        assert_that!(names.len()).is_equal_to(6);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
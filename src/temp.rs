@@ -0,0 +1,183 @@
+//! Temporary files that never outlive the operation writing them.
+//!
+//! Outputs of multi-gigabyte images are written to a [TempTarget] next to their destination and
+//! only moved into place once complete, so neither a failed, cancelled or panicking operation
+//! leaves a partial output or an orphaned temporary file behind.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Result as IOResult, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::apply::ApplyOptions;
+use crate::diagnostics::Diagnostic;
+use crate::Error;
+use crate::ErrorKind::PatchingError;
+use crate::ips::IPSPatch;
+use crate::progress::CancellationToken;
+
+/// distinguishes the temporary files of one process.
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// A temporary file that becomes the file at its destination once [persisted](TempTarget::persist)
+/// and is removed when dropped otherwise, including while unwinding from a panic.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::io::Write;
+/// use rom_patcher::temp::TempTarget;
+///
+/// let mut target = TempTarget::new("hacked.sfc").unwrap();
+/// target.file().write_all(&[0; 16]).unwrap();
+/// target.persist().unwrap();
+/// ```
+#[derive(Debug)]
+pub struct TempTarget {
+    path: PathBuf,
+    destination: PathBuf,
+    file: Option<File>,
+}
+
+impl TempTarget {
+    /// creates an empty temporary file next to `destination`, so persisting it is a rename on the
+    /// same file system.
+    pub fn new(destination: impl Into<PathBuf>) -> IOResult<TempTarget> {
+        let destination = destination.into();
+        let dir = match destination.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        return Self::in_dir(destination, &dir);
+    }
+
+    /// creates an empty temporary file in `dir` that becomes `destination` once persisted.
+    ///
+    /// Temporary files are named `.<destination name>.<process id>.<counter>.tmp`, so leftovers of
+    /// a killed process can be told apart and cleaned up.
+    pub fn in_dir(destination: impl Into<PathBuf>, dir: &Path) -> IOResult<TempTarget> {
+        let destination = destination.into();
+        let name = destination.file_name().map_or("output".into(), |x| x.to_string_lossy());
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let path = dir.join(format!(".{}.{}.{}.tmp", name, std::process::id(), id));
+        let file = OpenOptions::new().read(true).write(true).create_new(true).open(&path)?;
+        return Ok(TempTarget { path, destination, file: Some(file) });
+    }
+
+    /// returns the path of the temporary file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// returns the path the file is moved to once persisted.
+    pub fn destination(&self) -> &Path {
+        &self.destination
+    }
+
+    /// returns the temporary file.
+    pub fn file(&mut self) -> &mut File {
+        // the file is only taken by persist, which consumes self
+        self.file.as_mut().unwrap()
+    }
+
+    /// flushes the temporary file to disk and moves it to its destination, replacing it.
+    ///
+    /// Falls back to copying if the temporary file is on another file system than the destination.
+    pub fn persist(mut self) -> IOResult<()> {
+        let file = self.file.take().unwrap();
+        file.sync_all()?;
+        drop(file);
+        if fs::rename(&self.path, &self.destination).is_err() {
+            fs::copy(&self.path, &self.destination)?;
+            // the drop below removes the temporary file
+        }
+        Ok(())
+    }
+}
+
+impl Drop for TempTarget {
+    fn drop(&mut self) {
+        self.file = None;
+        // nothing is left to clean up if the file was renamed
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// applies `patch` to the file at `source`, writing the result to `output` only once it is
+/// complete.
+///
+/// The source is copied to a [TempTarget] in `temp_dir`, or next to `output` if `None`, and
+/// patched there without loading it into memory. Returns a [crate::ErrorKind::Cancelled] error
+/// and removes the temporary file if `cancellation` is cancelled before the output is in place.
+pub fn apply_atomic(patch: &IPSPatch, source: &Path, output: &Path, options: &ApplyOptions, temp_dir: Option<&Path>, cancellation: &CancellationToken)
+    -> Result<Vec<Diagnostic>, Error> {
+    let io_error = |action: &str, path: &Path, e: io::Error| Error::new(PatchingError)
+        .with_description(format!("Unable to {} {}.", action, path.display()))
+        .with_source(Box::new(e));
+    let mut target = match temp_dir {
+        Some(dir) => TempTarget::in_dir(output, dir),
+        None => TempTarget::new(output),
+    }.map_err(|e| io_error("create a temporary file for", output, e))?;
+    let path = target.path().to_path_buf();
+
+    let mut source_file = File::open(source).map_err(|e| io_error("read", source, e))?;
+    io::copy(&mut source_file, target.file())
+        .and_then(|_| target.file().seek(SeekFrom::Start(0)))
+        .map_err(|e| io_error("write", &path, e))?;
+    cancellation.check()?;
+    let diagnostics = patch.apply_with_options(target.file(), options)?;
+    target.file().flush().map_err(|e| io_error("write", &path, e))?;
+    cancellation.check()?;
+    target.persist().map_err(|e| io_error("write", output, e))?;
+    return Ok(diagnostics);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use spectral::prelude::*;
+
+    use crate::testkit::Fixture;
+
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("rom-patcher-temp-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        return dir;
+    }
+
+    #[test]
+    fn dropped_targets_are_removed() {
+        let dir = temp_dir("drop");
+        let mut target = TempTarget::new(dir.join("out.bin")).unwrap();
+        target.file().write_all(&[1, 2, 3]).unwrap();
+        let path = target.path().to_path_buf();
+        assert_that!(path.exists()).is_true();
+        drop(target);
+        assert_that!(path.exists()).is_false();
+        assert_that!(dir.join("out.bin").exists()).is_false();
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn applies_atomically() {
+        let dir = temp_dir("apply");
+        let fixture = Fixture::generate(31);
+        fs::write(dir.join("source.bin"), &fixture.source).unwrap();
+
+        let token = CancellationToken::new();
+        token.cancel();
+        let cancelled = apply_atomic(&fixture.ips, &dir.join("source.bin"), &dir.join("out.bin"), &ApplyOptions::new(), None, &token);
+        assert_that!(cancelled).is_err();
+        assert_that!(fs::read_dir(&dir).unwrap().count()).is_equal_to(1);
+
+        let scratch = temp_dir("apply-scratch");
+        apply_atomic(&fixture.ips, &dir.join("source.bin"), &dir.join("out.bin"), &ApplyOptions::new(), Some(&scratch), &CancellationToken::new()).unwrap();
+        assert_that!(fs::read(dir.join("out.bin")).unwrap()).is_equal_to(&fixture.target);
+        assert_that!(fs::read_dir(&scratch).unwrap().count()).is_equal_to(0);
+        fs::remove_dir_all(dir).unwrap();
+        fs::remove_dir_all(scratch).unwrap();
+    }
+}
@@ -1,6 +1,7 @@
 /// Builder pattern utility for vectors
 pub trait BuildVec<T> {
 
+    #[allow(dead_code)]
     fn build_with(self, elements: &Self) -> Self;
 
     fn build_with_slice(self, elements: &[T]) -> Self;
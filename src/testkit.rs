@@ -0,0 +1,290 @@
+//! Deterministic fixtures for testing patch implementations.
+//!
+//! Every fixture is derived from a seed, so the same seed always produces the same source rom,
+//! target rom and patches. This makes fixtures usable as golden files for integration tests and for
+//! CI of downstream tools without shipping any rom data.
+//!
+//! This module is only compiled for tests or with the `testkit` feature enabled.
+
+use std::fs;
+use std::io::{Cursor, Result as IOResult};
+use std::path::Path;
+
+use crate::bps::BPSPatch;
+use crate::ips::{IPSHunk, IPSPatch, IPSRLEHunkData, IPSRegularHunkData};
+use crate::io_util::U32Extensions;
+use crate::pmsr::{PMSRPatch, PMSRRecord};
+use crate::ppf::PPFPatch;
+use crate::ups::UPSPatch;
+
+/// A small deterministic pseudo-random number generator (splitmix64).
+///
+/// This is not suitable for anything but generating test data.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    /// constructs a new [Rng] from `seed`.
+    pub fn new(seed: u64) -> Rng {
+        Rng { state: seed }
+    }
+
+    /// returns the next pseudo-random `u64`.
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        return z ^ (z >> 31);
+    }
+
+    /// returns a pseudo-random value in `0..bound`. `bound` must not be zero.
+    pub fn below(&mut self, bound: u64) -> u64 {
+        self.next_u64() % bound
+    }
+
+    /// fills `buf` with pseudo-random bytes.
+    pub fn fill(&mut self, buf: &mut [u8]) {
+        for chunk in buf.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+}
+
+/// Options controlling the shape of generated fixtures.
+#[derive(Debug, Clone)]
+pub struct FixtureOptions {
+    /// size of the generated source rom.
+    pub source_size: usize,
+    /// amount of hunks in the generated patch.
+    pub hunk_count: usize,
+    /// maximum length of a single hunk.
+    pub max_hunk_length: u16,
+    /// whether hunks may write past the end of the source rom.
+    pub allow_extension: bool,
+    /// whether the generated patch should truncate the target.
+    pub truncate: bool,
+}
+
+impl Default for FixtureOptions {
+    fn default() -> Self {
+        FixtureOptions {
+            source_size: 0x10000,
+            hunk_count: 16,
+            max_hunk_length: 256,
+            allow_extension: true,
+            truncate: false,
+        }
+    }
+}
+
+/// A generated source/target rom pair together with matching patches.
+#[derive(Debug)]
+pub struct Fixture {
+    /// seed the fixture was generated from.
+    pub seed: u64,
+    /// the unpatched rom.
+    pub source: Vec<u8>,
+    /// the result of applying any of the patches to [Fixture::source].
+    pub target: Vec<u8>,
+    /// an [IPSPatch] turning [Fixture::source] into [Fixture::target].
+    pub ips: IPSPatch,
+}
+
+impl Fixture {
+    /// generates a fixture from `seed` using the [default options](FixtureOptions::default).
+    pub fn generate(seed: u64) -> Fixture {
+        Self::generate_with(seed, &FixtureOptions::default())
+    }
+
+    /// generates a fixture from `seed` shaped by `options`.
+    pub fn generate_with(seed: u64, options: &FixtureOptions) -> Fixture {
+        let mut rng = Rng::new(seed);
+        let mut source = vec![0; options.source_size];
+        rng.fill(&mut source);
+
+        let max_length = options.max_hunk_length.max(1) as u64;
+        let extension = if options.allow_extension { max_length } else { 0 };
+        let offset_bound = (options.source_size as u64 + extension).clamp(1, 0xFFFFFF);
+        let eof = u32::from_u24_be_bytes(IPSPatch::EOF) as u64;
+
+        let mut ips = IPSPatch::new();
+        while ips.hunks().len() < options.hunk_count {
+            let offset = rng.below(offset_bound);
+            // a hunk starting at "EOF" would be read back as the end of the patch
+            if offset == eof {
+                continue;
+            }
+            let mut length = 1 + rng.below(max_length) as u16;
+            if !options.allow_extension {
+                length = length.min((options.source_size as u64 - offset).max(1) as u16);
+            }
+            if rng.below(4) == 0 {
+                ips.add_hunk(IPSHunk::RLE(IPSRLEHunkData {
+                    offset,
+                    run_length: length,
+                    payload: rng.next_u64() as u8,
+                }));
+            } else {
+                let mut payload = vec![0; length as usize];
+                rng.fill(&mut payload);
+                ips.add_hunk(IPSHunk::Regular(IPSRegularHunkData {
+                    offset,
+                    payload: payload.into_boxed_slice(),
+                }));
+            }
+        }
+        if options.truncate {
+            let half = options.source_size as u64 / 2;
+            ips.set_truncate(Some(half + rng.below(half.max(1))));
+        }
+
+        let mut target = Cursor::new(source.clone());
+        ips.apply(&mut target).expect("Unable to apply generated patch.");
+
+        Fixture {
+            seed,
+            source,
+            target: target.into_inner(),
+            ips,
+        }
+    }
+
+    /// returns every patch of the fixture encoded in its format, paired with its file extension.
+    ///
+    /// Formats that can't represent the fixture, e.g. Star Rod mods and PPF patches for fixtures
+    /// that truncate, are left out.
+    pub fn encoded_patches(&self) -> Vec<(&'static str, Vec<u8>)> {
+        let mut ips = Vec::new();
+        self.ips.write(&mut ips).expect("Unable to write generated patch.");
+        let mut result = vec![("ips", ips)];
+
+        if self.ips.truncate().is_none() {
+            let mut pmsr = Vec::new();
+            self.pmsr().write(&mut pmsr).expect("Unable to write generated patch.");
+            result.push(("mod", pmsr));
+            let ppf = PPFPatch::create(&self.source, &self.target)
+                .expect("Unable to generate patch.")
+                .to_bytes()
+                .expect("Unable to write generated patch.");
+            result.push(("ppf", ppf));
+        }
+        let ups = UPSPatch::create(&self.source, &self.target).to_bytes().expect("Unable to write generated patch.");
+        result.push(("ups", ups));
+        let bps = BPSPatch::create(&self.source, &self.target).to_bytes().expect("Unable to write generated patch.");
+        result.push(("bps", bps));
+        return result;
+    }
+
+    /// returns the hunks of [Fixture::ips] as a Star Rod mod, ignoring truncation.
+    fn pmsr(&self) -> PMSRPatch {
+        let mut result = PMSRPatch::new();
+        for hunk in self.ips.hunks() {
+            let data = match hunk {
+                IPSHunk::Regular(x) => x.payload.clone(),
+                IPSHunk::RLE(x) => vec![x.payload; x.run_length as usize].into_boxed_slice(),
+            };
+            result.add_record(PMSRRecord {
+                offset: hunk.offset(),
+                data,
+            });
+        }
+        return result;
+    }
+
+    /// writes `source.bin`, `target.bin` and `patch.<extension>` for every encoded patch to `dir`.
+    ///
+    /// `dir` is created if it does not exist.
+    pub fn write_to_dir(&self, dir: &Path) -> IOResult<()> {
+        fs::create_dir_all(dir)?;
+        fs::write(dir.join("source.bin"), &self.source)?;
+        fs::write(dir.join("target.bin"), &self.target)?;
+        for (extension, data) in self.encoded_patches() {
+            fs::write(dir.join(format!("patch.{}", extension)), data)?;
+        }
+        Ok(())
+    }
+}
+
+/// generates one fixture per seed in `seeds` using `options`.
+pub fn corpus(seeds: impl IntoIterator<Item=u64>, options: &FixtureOptions) -> Vec<Fixture> {
+    seeds.into_iter()
+        .map(|seed| Fixture::generate_with(seed, options))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use spectral::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn same_seed_generates_same_fixture() {
+        let a = Fixture::generate(7);
+        let b = Fixture::generate(7);
+        assert_that!(a.source).is_equal_to(b.source);
+        assert_that!(a.target).is_equal_to(b.target);
+        assert_that!(a.ips).is_equal_to(b.ips);
+    }
+
+    #[test]
+    fn different_seeds_generate_different_fixtures() {
+        assert_that!(Fixture::generate(1).target).is_not_equal_to(Fixture::generate(2).target);
+    }
+
+    #[test]
+    fn encoded_patches_turn_source_into_target() {
+        for truncate in [false, true] {
+            let options = FixtureOptions {
+                truncate,
+                ..FixtureOptions::default()
+            };
+            for fixture in corpus(0..8, &options) {
+                for (_, data) in fixture.encoded_patches() {
+                    let patch = crate::registry::read_any_from_slice(&data).unwrap();
+                    assert_that!(patch.apply_to_vec(&fixture.source).unwrap()).is_equal_to(&fixture.target);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn truncating_fixtures_are_only_encoded_in_formats_supporting_it() {
+        let options = FixtureOptions {
+            truncate: true,
+            ..FixtureOptions::default()
+        };
+        let extensions: Vec<&str> = Fixture::generate_with(1, &options)
+            .encoded_patches()
+            .into_iter()
+            .map(|(x, _)| x)
+            .collect();
+        assert_that!(extensions).is_equal_to(vec!["ips", "ups", "bps"]);
+    }
+
+    #[test]
+    fn write_to_dir_writes_golden_files() {
+        let dir = std::env::temp_dir().join("rom-patcher-testkit-golden");
+        let fixture = Fixture::generate(5);
+        fixture.write_to_dir(&dir).unwrap();
+        assert_that!(fs::read(dir.join("source.bin")).unwrap()).is_equal_to(&fixture.source);
+        assert_that!(fs::read(dir.join("target.bin")).unwrap()).is_equal_to(&fixture.target);
+        assert_that!(dir.join("patch.ips").exists()).is_true();
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn hunks_stay_in_source_without_extension() {
+        let options = FixtureOptions {
+            source_size: 64,
+            allow_extension: false,
+            ..FixtureOptions::default()
+        };
+        let fixture = Fixture::generate_with(3, &options);
+        assert_that!(fixture.target.len()).is_equal_to(64);
+    }
+}
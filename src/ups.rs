@@ -0,0 +1,425 @@
+//! UPS patches (`.ups`).
+//!
+//! UPS patches consist of the magic `UPS1`, the sizes of the source and target as variable-length
+//! integers and the records, followed by the CRC-32 of the source, the target and the patch itself,
+//! each little-endian. A record is the amount of unchanged bytes to skip, again a variable-length
+//! integer, and the bytes to XOR the rom with from there, ended by a zero byte. The zero byte also
+//! covers an unchanged byte.
+//!
+//! Since records are XORed, the same patch turns the target back into the source. Applying checks
+//! the checksum of the rom to tell the directions apart, and refuses roms matching neither.
+
+use std::fmt::{Display, Formatter};
+use std::io::{Read, Result as IOResult, Seek, SeekFrom, Write};
+
+use crate::create::Diff;
+use crate::Error;
+use crate::ErrorKind::{ChecksumMismatch, ParsingError, PatchingError, WrongSource};
+use crate::hash::Crc32;
+use crate::io_util::{write_varint, AssertRead, ReaderExtensions, Truncate, U64Extensions};
+
+/// A record of a UPS patch.
+///
+/// Applying the record XORs the rom with `xor` starting at `offset`. Zero bytes leave the rom as it
+/// is, so a record may contain them, they are split off into records of their own when written.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct UPSRecord {
+    /// the offset of the first byte to XOR. Records store it relative to the end of the previous
+    /// record when written.
+    pub offset: u64,
+    /// the bytes to XOR the rom with.
+    pub xor: Box<[u8]>,
+}
+
+impl UPSRecord {
+    /// returns the offset past the last byte the record XORs.
+    pub fn end(&self) -> u64 {
+        self.offset.saturating_add(self.xor.len() as u64)
+    }
+}
+
+/// Formats the record as a one-line summary, e.g. `record at 0x00000002 changing 2 bytes`.
+impl Display for UPSRecord {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let changed = self.xor.iter().filter(|x| **x != 0).count();
+        write!(f, "record at {:#010X} changing {} bytes", self.offset, changed)
+    }
+}
+
+/// Represents a UPS patch.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct UPSPatch {
+    source_len: u64,
+    target_len: u64,
+    /// records ordered by offset.
+    records: Vec<UPSRecord>,
+    source_crc32: u32,
+    target_crc32: u32,
+}
+
+impl UPSPatch {
+    /// Patch header for UPS patches.
+    pub const HEADER: &'static [u8] = "UPS1".as_bytes();
+
+    /// Length of the footer holding the checksums.
+    const FOOTER_LEN: usize = 12;
+
+    /// constructs a patch without records turning a rom of `source_len` bytes with the CRC-32
+    /// `source_crc32` into one of `target_len` bytes with the CRC-32 `target_crc32`.
+    pub const fn new(source_len: u64, source_crc32: u32, target_len: u64, target_crc32: u32) -> UPSPatch {
+        UPSPatch { source_len, target_len, records: Vec::new(), source_crc32, target_crc32 }
+    }
+
+    /// creates a patch turning `source` into `target`, and `target` back into `source`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rom_patcher::ups::UPSPatch;
+    /// let patch = UPSPatch::create(&[0, 1, 2, 3], &[0, 9, 2]);
+    /// assert_eq!(patch.records().len(), 2);
+    /// assert_eq!(patch.apply_to_vec(&[0, 1, 2, 3]).unwrap(), vec![0, 9, 2]);
+    /// assert_eq!(patch.apply_to_vec(&[0, 9, 2]).unwrap(), vec![0, 1, 2, 3]);
+    /// ```
+    pub fn create(source: &[u8], target: &[u8]) -> UPSPatch {
+        Self::from_diff(&Diff::new(source, target), source, target)
+    }
+
+    /// encodes `diff` of `source` and `target` like [UPSPatch::create].
+    pub(crate) fn from_diff(diff: &Diff, source: &[u8], target: &[u8]) -> UPSPatch {
+        let mut result = UPSPatch::new(source.len() as u64, Crc32::checksum(source), target.len() as u64, Crc32::checksum(target));
+        // bytes past the end of either rom XOR with zeros, so both directions can be restored
+        let xor = |i: usize| source.get(i).unwrap_or(&0) ^ target.get(i).unwrap_or(&0);
+        let past_target = diff.target_len..diff.source_len.max(diff.target_len);
+        for region in diff.regions.iter().chain([&past_target]) {
+            // regions may hold unchanged bytes, which end a record
+            let (mut i, end) = (region.start.to_index(), region.end.to_index());
+            while i < end {
+                if xor(i) == 0 {
+                    i += 1;
+                    continue;
+                }
+                let start = i;
+                while i < end && xor(i) != 0 {
+                    i += 1;
+                }
+                result.records.push(UPSRecord {
+                    offset: start as u64,
+                    xor: (start..i).map(xor).collect(),
+                });
+            }
+        }
+        return result;
+    }
+
+    /// returns the records of the patch ordered by offset.
+    pub fn records(&self) -> &[UPSRecord] {
+        &self.records
+    }
+
+    /// adds `record` to the patch, keeping the records ordered by offset.
+    pub fn add_record(&mut self, record: UPSRecord) {
+        let index = self.records.partition_point(|x| x.offset <= record.offset);
+        self.records.insert(index, record);
+    }
+
+    /// returns a new patch with a given `record`.
+    pub fn with_record(mut self, record: UPSRecord) -> Self {
+        self.add_record(record);
+        return self;
+    }
+
+    /// returns the length of the rom the patch expects.
+    pub fn source_len(&self) -> u64 {
+        self.source_len
+    }
+
+    /// returns the length of the patched rom.
+    pub fn target_len(&self) -> u64 {
+        self.target_len
+    }
+
+    /// returns the CRC-32 of the rom the patch expects.
+    pub fn source_crc32(&self) -> u32 {
+        self.source_crc32
+    }
+
+    /// returns the CRC-32 of the patched rom.
+    pub fn target_crc32(&self) -> u32 {
+        self.target_crc32
+    }
+
+    /// writes `self` to `writer`, ending with the CRC-32 of everything written before it.
+    ///
+    /// Returns an error of kind [InvalidInput](std::io::ErrorKind::InvalidInput) if records
+    /// overlap.
+    pub fn write(&self, writer: &mut impl Write) -> IOResult<()> {
+        writer.write_all(&self.to_bytes()?)
+    }
+
+    /// returns `self` as written by [UPSPatch::write].
+    pub fn to_bytes(&self) -> IOResult<Vec<u8>> {
+        let mut result = Vec::new();
+        result.extend_from_slice(Self::HEADER);
+        write_varint(&mut result, self.source_len)?;
+        write_varint(&mut result, self.target_len)?;
+        let mut position = 0;
+        for (offset, xor) in self.runs()? {
+            write_varint(&mut result, offset - position)?;
+            result.extend_from_slice(&xor);
+            result.push(0);
+            position = offset + xor.len() as u64 + 1;
+        }
+        result.extend_from_slice(&self.source_crc32.to_le_bytes());
+        result.extend_from_slice(&self.target_crc32.to_le_bytes());
+        result.extend_from_slice(&Crc32::checksum(&result).to_le_bytes());
+        Ok(result)
+    }
+
+    /// returns the offsets and bytes of the runs of non-zero bytes the records XOR with, the
+    /// records as they are written.
+    fn runs(&self) -> IOResult<Vec<(u64, Vec<u8>)>> {
+        let mut result: Vec<(u64, Vec<u8>)> = Vec::new();
+        let mut end = 0;
+        for record in &self.records {
+            if record.offset < end {
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput,
+                    format!("UPS records can't overlap, the record at {} does.", record.offset)));
+            }
+            end = record.end();
+            let mut offset = record.offset;
+            for run in record.xor.split(|x| *x == 0) {
+                match result.last_mut() {
+                    _ if run.is_empty() => {}
+                    // runs of adjacent records are written as one
+                    Some((start, last)) if *start + last.len() as u64 == offset => last.extend_from_slice(run),
+                    _ => result.push((offset, run.to_vec())),
+                }
+                offset += run.len() as u64 + 1;
+            }
+        }
+        Ok(result)
+    }
+
+    /// reads a [UPSPatch] from `data`, see [UPSPatch::read_from].
+    pub fn from_bytes(data: &[u8]) -> Result<UPSPatch, Error> {
+        Self::read_from(&mut &data[..])
+    }
+
+    /// Reads a [UPSPatch] from `reader`.
+    ///
+    /// Returns a [ChecksumMismatch] error if the patch doesn't have the CRC-32 it ends with.
+    pub fn read_from(reader: &mut impl Read) -> Result<UPSPatch, Error> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)
+            .map_err(|e| Error::new(ParsingError)
+                .with_description("Unable to read patch.".to_string())
+                .with_source(Box::new(e)))?;
+        let mut rest = &data[..];
+        rest.assert_read(
+            Self::HEADER,
+            "Unable to parse header.".to_string(),
+            "Invalid header.".to_string(),
+        )?;
+        if rest.len() < Self::FOOTER_LEN {
+            return Err(Error::new(ParsingError).with_description("Unable to read checksums.".to_string()));
+        }
+        let (body, footer) = rest.split_at(rest.len() - Self::FOOTER_LEN);
+        let crc = |i: usize| u32::from_le_bytes([footer[i], footer[i + 1], footer[i + 2], footer[i + 3]]);
+        let actual = Crc32::checksum(&data[..data.len() - 4]);
+        if actual != crc(8) {
+            return Err(Error::new(ChecksumMismatch)
+                .with_description(format!("CRC-32 of the patch is {:08x}, expected {:08x}.", actual, crc(8))));
+        }
+
+        let mut body = body;
+        let source_len = body.read_varint("Unable to read source size.".to_string())?;
+        let target_len = body.read_varint("Unable to read target size.".to_string())?;
+        let mut result = UPSPatch::new(source_len, crc(0), target_len, crc(4));
+        let mut position: u64 = 0;
+        while !body.is_empty() {
+            let skip = body.read_varint("Unable to read record offset.".to_string())?;
+            let Some(len) = body.iter().position(|x| *x == 0) else {
+                return Err(Error::new(ParsingError).with_description("Unable to read record data.".to_string()));
+            };
+            let offset = position.checked_add(skip)
+                .ok_or_else(|| Error::new(ParsingError).with_description("Unable to read record offset.".to_string()))?;
+            result.records.push(UPSRecord { offset, xor: body[..len].into() });
+            position = offset.saturating_add(len as u64 + 1);
+            body = &body[len + 1..];
+        }
+        Ok(result)
+    }
+
+    /// returns `rom` patched, the target if `rom` is the source and the source if it is the target.
+    ///
+    /// Returns a [WrongSource] error if `rom` is neither, and a [ChecksumMismatch] error if the
+    /// output isn't what the patch records.
+    pub fn apply_to_vec(&self, rom: &[u8]) -> Result<Vec<u8>, Error> {
+        let crc = Crc32::checksum(rom);
+        let (len, expected) = if rom.len() as u64 == self.source_len && crc == self.source_crc32 {
+            (self.target_len, self.target_crc32)
+        } else if rom.len() as u64 == self.target_len && crc == self.target_crc32 {
+            (self.source_len, self.source_crc32)
+        } else {
+            return Err(Error::new(WrongSource)
+                .with_description(format!("CRC-32 of the source is {:08x}, expected {:08x}.", crc, self.source_crc32)));
+        };
+        let mut result = rom.to_vec();
+        result.try_reserve(len.saturating_usize().saturating_sub(rom.len()))
+            .map_err(|_| Error::new(PatchingError)
+                .with_description(format!("The patched rom of {} bytes doesn't fit into memory.", len)))?;
+        result.resize(len.to_index(), 0);
+        for record in &self.records {
+            let start = record.offset.min(len).to_index();
+            let end = record.end().min(len).to_index();
+            for (byte, x) in result[start..end].iter_mut().zip(record.xor.iter()) {
+                *byte ^= x;
+            }
+        }
+        let actual = Crc32::checksum(&result);
+        if actual != expected {
+            return Err(Error::new(ChecksumMismatch)
+                .with_description(format!("CRC-32 of the output is {:08x}, expected {:08x}.", actual, expected)));
+        }
+        Ok(result)
+    }
+
+    /// Applies the patch to `target` like [UPSPatch::apply_to_vec], leaving it untouched if that
+    /// fails.
+    pub fn apply<T>(&self, target: &mut T) -> Result<(), Error> where T: Read + Write + Seek + Truncate {
+        let io_error = |e: std::io::Error| Error::new(PatchingError)
+            .with_description("Unable to apply UPS patch.".to_string())
+            .with_source(Box::new(e));
+        let mut rom = Vec::new();
+        target.seek(SeekFrom::Start(0)).and_then(|_| target.read_to_end(&mut rom)).map_err(io_error)?;
+        let patched = self.apply_to_vec(&rom)?;
+        target.seek(SeekFrom::Start(0))
+            .and_then(|_| target.write_all(&patched))
+            .and_then(|_| target.truncate(patched.len() as u64))
+            .map_err(io_error)
+    }
+}
+
+/// Formats the patch as a one-line summary, e.g. `UPS patch with 2 records turning 16 bytes into
+/// 32 bytes`.
+///
+/// The alternate form `{:#}` lists every record on a line of its own below the summary.
+impl Display for UPSPatch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let plural = if self.records.len() == 1 { "" } else { "s" };
+        write!(f, "UPS patch with {} record{} turning {} bytes into {} bytes", self.records.len(), plural, self.source_len, self.target_len)?;
+        if f.alternate() {
+            for (i, record) in self.records.iter().enumerate() {
+                write!(f, "\n  {}: {}", i, record)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use spectral::prelude::*;
+
+    use crate::test_util::BuildVec;
+
+    use super::*;
+
+    const SOURCE: &[u8] = &[0, 1, 2, 3, 4, 5];
+    const TARGET: &[u8] = &[0, 9, 9, 3, 4, 5, 6];
+
+    fn patch_data() -> Vec<u8> {
+        let mut data = Vec::new()
+            .build_with_slice(UPSPatch::HEADER)
+            .build_with_slice(&[0x86, 0x87]) // source and target size
+            .build_with_slice(&[0x81, 0x08, 0x0B, 0x00]) // skip 1, XOR 2 bytes
+            .build_with_slice(&[0x82, 0x06, 0x00]) // skip 2, XOR 1 byte
+            .build_with_slice(&Crc32::checksum(SOURCE).to_le_bytes())
+            .build_with_slice(&Crc32::checksum(TARGET).to_le_bytes());
+        let crc = Crc32::checksum(&data);
+        data.extend_from_slice(&crc.to_le_bytes());
+        return data;
+    }
+
+    #[test]
+    fn create_and_write_records() {
+        let patch = UPSPatch::create(SOURCE, TARGET);
+        assert_that!(patch.to_bytes().unwrap()).is_equal_to(patch_data());
+        assert_that!(UPSPatch::from_bytes(&patch_data()).unwrap()).is_equal_to(patch);
+    }
+
+    #[test]
+    fn apply_works_in_both_directions() {
+        let patch = UPSPatch::from_bytes(&patch_data()).unwrap();
+        assert_that!(patch.apply_to_vec(SOURCE).unwrap()).is_equal_to(TARGET.to_vec());
+        assert_that!(patch.apply_to_vec(TARGET).unwrap()).is_equal_to(SOURCE.to_vec());
+        let mut target = Cursor::new(TARGET.to_vec());
+        patch.apply(&mut target).unwrap();
+        assert_that!(target.into_inner()).is_equal_to(SOURCE.to_vec());
+    }
+
+    #[test]
+    fn wrong_roms_are_refused() {
+        let patch = UPSPatch::create(SOURCE, TARGET);
+        let err = patch.apply_to_vec(&[0; 6]).unwrap_err();
+        assert_that!(matches!(err.kind(), WrongSource)).is_true();
+        let mut target = Cursor::new(vec![0; 6]);
+        assert_that!(patch.apply(&mut target)).is_err();
+        assert_that!(target.into_inner()).is_equal_to(vec![0; 6]);
+    }
+
+    #[test]
+    fn corrupt_patches_are_refused() {
+        let mut data = patch_data();
+        data[7] ^= 1;
+        let err = UPSPatch::from_bytes(&data).unwrap_err();
+        assert_that!(matches!(err.kind(), ChecksumMismatch)).is_true();
+        let err = UPSPatch::from_bytes(b"UPS1\x80").unwrap_err();
+        assert_that!(err.to_string()).is_equal_to("ParsingError: Unable to read checksums.".to_string());
+    }
+
+    #[test]
+    fn zeros_and_adjacent_records_are_written_as_the_format_requires() {
+        let patch = UPSPatch::new(8, 0, 8, 0)
+            .with_record(UPSRecord { offset: 3, xor: Box::new([1]) })
+            .with_record(UPSRecord { offset: 0, xor: Box::new([1, 0, 0]) });
+        let read = UPSPatch::from_bytes(&patch.to_bytes().unwrap()).unwrap();
+        let records: Vec<(u64, Vec<u8>)> = read.records().iter().map(|x| (x.offset, x.xor.to_vec())).collect();
+        assert_that!(records).is_equal_to(vec![(0, vec![1]), (3, vec![1])]);
+
+        let adjacent = UPSPatch::new(8, 0, 8, 0)
+            .with_record(UPSRecord { offset: 0, xor: Box::new([1]) })
+            .with_record(UPSRecord { offset: 1, xor: Box::new([2]) });
+        let read = UPSPatch::from_bytes(&adjacent.to_bytes().unwrap()).unwrap();
+        assert_that!(read.records().len()).is_equal_to(1);
+        assert_that!(read.records()[0].xor.to_vec()).is_equal_to(vec![1, 2]);
+    }
+
+    #[test]
+    fn overlapping_records_are_not_written() {
+        let patch = UPSPatch::new(8, 0, 8, 0)
+            .with_record(UPSRecord { offset: 0, xor: Box::new([1, 1]) })
+            .with_record(UPSRecord { offset: 1, xor: Box::new([2]) });
+        assert_that!(patch.to_bytes()).is_err();
+    }
+
+    #[test]
+    fn shrinking_roms_are_restored() {
+        let source: Vec<u8> = (0..32).collect();
+        let target = vec![1; 4];
+        let patch = UPSPatch::create(&source, &target);
+        let patch = UPSPatch::from_bytes(&patch.to_bytes().unwrap()).unwrap();
+        assert_that!(patch.apply_to_vec(&source).unwrap()).is_equal_to(target.clone());
+        assert_that!(patch.apply_to_vec(&target).unwrap()).is_equal_to(source);
+    }
+
+    #[test]
+    fn display_summarizes_records() {
+        let patch = UPSPatch::create(SOURCE, TARGET);
+        assert_that!(patch.to_string()).is_equal_to("UPS patch with 2 records turning 6 bytes into 7 bytes".to_string());
+        assert_that!(format!("{:#}", patch)).contains("\n  0: record at 0x00000001 changing 2 bytes");
+    }
+}
@@ -0,0 +1,618 @@
+//! VCDIFF delta files (RFC 3284), the format xdelta3 writes `.xdelta` patches in.
+//!
+//! A delta file consists of the magic `0xD6 0xC3 0xC4`, a version byte and a header indicator,
+//! followed by windows. Each window optionally names a segment of the source or of the target
+//! decoded so far that its COPY instructions read from, and holds three sections: the data ADD
+//! and RUN instructions write, the instructions and the addresses of COPY instructions. Integers
+//! are big-endian with 7 bits per byte, the high bit set on every byte but the last.
+//!
+//! Instructions are decoded through the default code table and addresses through the default
+//! address cache of the RFC. Secondary compression, custom code tables and the interleaved format
+//! of open-vcdiff aren't supported. The Adler-32 checksums xdelta3 adds to windows are checked.
+
+use std::fmt::{Display, Formatter};
+use std::io::{Read, Result as IOResult, Write};
+
+use crate::Error;
+use crate::ErrorKind::{ChecksumMismatch, ParsingError, PatchingError, WrongSource};
+use crate::io_util::U64Extensions;
+
+/// Where the segment a [VCDIFFWindow] copies from lies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum VCDIFFSegmentKind {
+    /// the segment lies in the source.
+    Source,
+    /// the segment lies in the target decoded by earlier windows.
+    Target,
+}
+
+/// The segment a [VCDIFFWindow] copies from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct VCDIFFSegment {
+    /// whether the segment lies in the source or target.
+    pub kind: VCDIFFSegmentKind,
+    /// the offset of the segment.
+    pub position: u64,
+    /// the length of the segment.
+    pub len: u64,
+}
+
+/// A window of a VCDIFF delta file, decoding to a part of the target.
+///
+/// The sections are kept encoded and only decoded when the window is applied.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct VCDIFFWindow {
+    /// the segment COPY instructions read from, if any.
+    pub segment: Option<VCDIFFSegment>,
+    /// the length of the part of the target the window decodes to.
+    pub target_len: u64,
+    /// the Adler-32 checksum xdelta3 records for the decoded window, if any.
+    pub adler32: Option<u32>,
+    /// the bytes ADD and RUN instructions write.
+    pub data: Box<[u8]>,
+    /// the encoded instructions.
+    pub instructions: Box<[u8]>,
+    /// the encoded addresses of COPY instructions.
+    pub addresses: Box<[u8]>,
+}
+
+/// Represents a VCDIFF delta file.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct VCDIFFPatch {
+    app_header: Option<Box<[u8]>>,
+    windows: Vec<VCDIFFWindow>,
+}
+
+/// Header indicator flag for secondary compression.
+const VCD_DECOMPRESS: u8 = 0x01;
+/// Header indicator flag for a custom code table.
+const VCD_CODETABLE: u8 = 0x02;
+/// Header indicator flag for the application header of xdelta3.
+const VCD_APPHEADER: u8 = 0x04;
+/// Window indicator flag for a source segment.
+const VCD_SOURCE: u8 = 0x01;
+/// Window indicator flag for a target segment.
+const VCD_TARGET: u8 = 0x02;
+/// Window indicator flag for the Adler-32 checksum of xdelta3.
+const VCD_ADLER32: u8 = 0x04;
+
+impl VCDIFFPatch {
+    /// Patch header for VCDIFF delta files, the magic followed by the version.
+    pub const HEADER: &'static [u8] = &[0xD6, 0xC3, 0xC4, 0x00];
+
+    /// constructs an empty delta file.
+    pub fn new() -> VCDIFFPatch {
+        VCDIFFPatch::default()
+    }
+
+    /// returns the application header, which xdelta3 stores the file names in.
+    pub fn app_header(&self) -> Option<&[u8]> {
+        self.app_header.as_deref()
+    }
+
+    /// returns a new delta file with the application header `app_header`.
+    pub fn with_app_header(mut self, app_header: &[u8]) -> Self {
+        self.app_header = Some(app_header.into());
+        return self;
+    }
+
+    /// returns the windows in the order they are decoded.
+    pub fn windows(&self) -> &[VCDIFFWindow] {
+        &self.windows
+    }
+
+    /// adds `window` to the delta file, decoded after the windows added before.
+    pub fn add_window(&mut self, window: VCDIFFWindow) {
+        self.windows.push(window);
+    }
+
+    /// returns a new delta file with a given `window`.
+    pub fn with_window(mut self, window: VCDIFFWindow) -> Self {
+        self.add_window(window);
+        return self;
+    }
+
+    /// returns the length of the target the delta file decodes to.
+    pub fn target_len(&self) -> u64 {
+        self.windows.iter().fold(0u64, |a, x| a.saturating_add(x.target_len))
+    }
+
+    /// returns `true` if every window carries an Adler-32 checksum.
+    pub fn has_checksums(&self) -> bool {
+        !self.windows.is_empty() && self.windows.iter().all(|x| x.adler32.is_some())
+    }
+
+    /// writes `self` to `writer`, with every integer in its shortest encoding.
+    pub fn write(&self, writer: &mut impl Write) -> IOResult<()> {
+        writer.write_all(&self.to_bytes()?)
+    }
+
+    /// returns `self` as written by [VCDIFFPatch::write].
+    pub fn to_bytes(&self) -> IOResult<Vec<u8>> {
+        let mut result = Self::HEADER.to_vec();
+        match &self.app_header {
+            Some(app_header) => {
+                result.push(VCD_APPHEADER);
+                write_int(&mut result, app_header.len() as u64);
+                result.extend_from_slice(app_header);
+            }
+            None => result.push(0),
+        }
+        for window in &self.windows {
+            let mut indicator = 0;
+            if let Some(segment) = &window.segment {
+                indicator |= match segment.kind {
+                    VCDIFFSegmentKind::Source => VCD_SOURCE,
+                    VCDIFFSegmentKind::Target => VCD_TARGET,
+                };
+            }
+            if window.adler32.is_some() {
+                indicator |= VCD_ADLER32;
+            }
+            result.push(indicator);
+            if let Some(segment) = &window.segment {
+                write_int(&mut result, segment.len);
+                write_int(&mut result, segment.position);
+            }
+            let mut delta = Vec::new();
+            write_int(&mut delta, window.target_len);
+            delta.push(0);
+            write_int(&mut delta, window.data.len() as u64);
+            write_int(&mut delta, window.instructions.len() as u64);
+            write_int(&mut delta, window.addresses.len() as u64);
+            if let Some(adler32) = window.adler32 {
+                delta.extend_from_slice(&adler32.to_be_bytes());
+            }
+            delta.extend_from_slice(&window.data);
+            delta.extend_from_slice(&window.instructions);
+            delta.extend_from_slice(&window.addresses);
+            write_int(&mut result, delta.len() as u64);
+            result.extend(delta);
+        }
+        Ok(result)
+    }
+
+    /// reads a [VCDIFFPatch] from `data`, see [VCDIFFPatch::read_from].
+    pub fn from_bytes(data: &[u8]) -> Result<VCDIFFPatch, Error> {
+        Self::read_from(&mut &data[..])
+    }
+
+    /// Reads a [VCDIFFPatch] from `reader`.
+    ///
+    /// Returns a [ParsingError] for malformed delta files and for those using secondary
+    /// compression or a custom code table.
+    pub fn read_from(reader: &mut impl Read) -> Result<VCDIFFPatch, Error> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)
+            .map_err(|e| Error::new(ParsingError)
+                .with_description("Unable to read patch.".to_string())
+                .with_source(Box::new(e)))?;
+        let mut input = Section::new(&data, "header");
+        if input.bytes(3)? != &Self::HEADER[..3] {
+            return Err(Error::new(ParsingError).with_description("Invalid header.".to_string()));
+        }
+        if input.byte()? != 0 {
+            return Err(Error::new(ParsingError)
+                .with_description("Only version 0 of VCDIFF is supported, not the interleaved format of open-vcdiff.".to_string()));
+        }
+        let indicator = input.byte()?;
+        if indicator & (VCD_DECOMPRESS | VCD_CODETABLE) != 0 {
+            return Err(Error::new(ParsingError)
+                .with_description("Secondary compression and custom code tables aren't supported.".to_string()));
+        }
+        let mut result = VCDIFFPatch::new();
+        if indicator & VCD_APPHEADER != 0 {
+            let len = input.int()?;
+            result.app_header = Some(input.bytes(len)?.into());
+        }
+        input.name = "window";
+        while !input.is_empty() {
+            result.windows.push(read_window(&mut input)?);
+        }
+        Ok(result)
+    }
+
+    /// returns `source` patched.
+    ///
+    /// Returns a [WrongSource] error if a window copies from past the end of `source`, a
+    /// [PatchingError] if a window is malformed and a [ChecksumMismatch] if a decoded window
+    /// doesn't have its checksum.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rom_patcher::vcdiff::VCDIFFPatch;
+    /// let patch = VCDIFFPatch::from_bytes(&[
+    ///     0xD6, 0xC3, 0xC4, 0x00, 0x00,
+    ///     // a window copying from the 4 byte source at 0
+    ///     0x01, 0x04, 0x00,
+    ///     0x08, 0x05, 0x00, 0x01, 0x01, 0x01,
+    ///     0xFF,
+    ///     // COPY 4 bytes from address 0, ADD 1 byte
+    ///     0xF7,
+    ///     0x00,
+    /// ]).unwrap();
+    /// assert_eq!(patch.apply_to_vec(&[1, 2, 3, 4]).unwrap(), vec![1, 2, 3, 4, 0xFF]);
+    /// ```
+    pub fn apply_to_vec(&self, source: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut target = Vec::new();
+        for (i, window) in self.windows.iter().enumerate() {
+            let decoded = decode_window(window, source, &target)
+                .map_err(|e| Error::new(e.kind().clone())
+                    .with_description(format!("Unable to decode window {}.", i))
+                    .with_source(Box::new(e)))?;
+            target.extend(decoded);
+        }
+        Ok(target)
+    }
+}
+
+/// Formats the delta file as a one-line summary, e.g. `VCDIFF patch with 2 windows`.
+impl Display for VCDIFFPatch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let plural = if self.windows.len() == 1 { "" } else { "s" };
+        write!(f, "VCDIFF patch with {} window{} decoding to {} bytes", self.windows.len(), plural, self.target_len())
+    }
+}
+
+/// A section of a delta file that is read from front to back.
+struct Section<'a> {
+    data: &'a [u8],
+    name: &'static str,
+}
+
+impl<'a> Section<'a> {
+    fn new(data: &'a [u8], name: &'static str) -> Section<'a> {
+        Section { data, name }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    fn end_error(&self) -> Error {
+        Error::new(ParsingError).with_description(format!("Unexpected end of {} section.", self.name))
+    }
+
+    fn byte(&mut self) -> Result<u8, Error> {
+        let (first, rest) = self.data.split_first().ok_or_else(|| self.end_error())?;
+        self.data = rest;
+        Ok(*first)
+    }
+
+    fn bytes(&mut self, len: u64) -> Result<&'a [u8], Error> {
+        let len = len.to_index();
+        if len > self.data.len() {
+            return Err(self.end_error());
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    /// reads a VCDIFF integer.
+    fn int(&mut self) -> Result<u64, Error> {
+        let mut result: u64 = 0;
+        loop {
+            let byte = self.byte()?;
+            if result > u64::MAX >> 7 {
+                return Err(Error::new(ParsingError)
+                    .with_description(format!("Integer in {} section is too large.", self.name)));
+            }
+            result = (result << 7) | (byte & 0x7F) as u64;
+            if byte & 0x80 == 0 {
+                return Ok(result);
+            }
+        }
+    }
+}
+
+/// appends `value` to `out` as a VCDIFF integer.
+fn write_int(out: &mut Vec<u8>, value: u64) {
+    let mut bytes = vec![(value & 0x7F) as u8];
+    let mut rest = value >> 7;
+    while rest > 0 {
+        bytes.push((rest & 0x7F) as u8 | 0x80);
+        rest >>= 7;
+    }
+    out.extend(bytes.iter().rev());
+}
+
+fn read_window(input: &mut Section) -> Result<VCDIFFWindow, Error> {
+    let indicator = input.byte()?;
+    let kind = match indicator & (VCD_SOURCE | VCD_TARGET) {
+        0 => None,
+        VCD_SOURCE => Some(VCDIFFSegmentKind::Source),
+        VCD_TARGET => Some(VCDIFFSegmentKind::Target),
+        _ => return Err(Error::new(ParsingError)
+            .with_description("Windows can't copy from both the source and the target.".to_string())),
+    };
+    let segment = match kind {
+        Some(kind) => {
+            let len = input.int()?;
+            let position = input.int()?;
+            Some(VCDIFFSegment { kind, position, len })
+        }
+        None => None,
+    };
+    let delta_len = input.int()?;
+    let mut delta = Section::new(input.bytes(delta_len)?, "delta encoding");
+    let target_len = delta.int()?;
+    if delta.byte()? != 0 {
+        return Err(Error::new(ParsingError)
+            .with_description("Secondary compression isn't supported.".to_string()));
+    }
+    let data_len = delta.int()?;
+    let instructions_len = delta.int()?;
+    let addresses_len = delta.int()?;
+    let adler32 = match indicator & VCD_ADLER32 {
+        0 => None,
+        _ => Some(u32::from_be_bytes(delta.bytes(4)?.try_into().unwrap())),
+    };
+    let data = delta.bytes(data_len)?.into();
+    let instructions = delta.bytes(instructions_len)?.into();
+    let addresses = delta.bytes(addresses_len)?.into();
+    if !delta.is_empty() {
+        return Err(Error::new(ParsingError)
+            .with_description("Delta encoding is longer than its sections.".to_string()));
+    }
+    Ok(VCDIFFWindow { segment, target_len, adler32, data, instructions, addresses })
+}
+
+/// The kind of an instruction of the code table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Noop,
+    Add,
+    Run,
+    Copy(u8),
+}
+
+/// An instruction of the code table, with a size of 0 read from the instructions section.
+#[derive(Debug, Clone, Copy)]
+struct Instruction {
+    kind: Kind,
+    size: u8,
+}
+
+const NOOP: Instruction = Instruction { kind: Kind::Noop, size: 0 };
+
+/// returns the default code table of RFC 3284, section 5.6.
+fn code_table() -> Vec<[Instruction; 2]> {
+    let add = |size| Instruction { kind: Kind::Add, size };
+    let copy = |mode, size| Instruction { kind: Kind::Copy(mode), size };
+    let mut result = vec![[Instruction { kind: Kind::Run, size: 0 }, NOOP]];
+    result.extend((0..=17).map(|size| [add(size), NOOP]));
+    for mode in 0..=8 {
+        result.push([copy(mode, 0), NOOP]);
+        result.extend((4..=18).map(|size| [copy(mode, size), NOOP]));
+    }
+    for mode in 0..=8 {
+        let copy_sizes = if mode <= 5 { 4..=6 } else { 4..=4 };
+        for add_size in 1..=4 {
+            result.extend(copy_sizes.clone().map(|size| [add(add_size), copy(mode, size)]));
+        }
+    }
+    result.extend((0..=8).map(|mode| [copy(mode, 4), add(1)]));
+    return result;
+}
+
+/// The address cache of RFC 3284, section 5.1, with its default sizes.
+struct AddressCache {
+    near: [u64; Self::NEAR],
+    next_slot: usize,
+    same: Vec<u64>,
+}
+
+impl AddressCache {
+    const NEAR: usize = 4;
+    const SAME: usize = 3;
+
+    fn new() -> AddressCache {
+        AddressCache { near: [0; Self::NEAR], next_slot: 0, same: vec![0; Self::SAME * 256] }
+    }
+
+    /// reads the address of a COPY instruction at `here` in `mode` from `addresses`.
+    fn decode(&mut self, here: u64, mode: u8, addresses: &mut Section) -> Result<u64, Error> {
+        let mode = mode as usize;
+        let invalid = || Error::new(PatchingError).with_description("Invalid COPY address.".to_string());
+        let address = match mode {
+            0 => addresses.int()?,
+            1 => here.checked_sub(addresses.int()?).ok_or_else(invalid)?,
+            _ if mode < 2 + Self::NEAR => self.near[mode - 2].checked_add(addresses.int()?).ok_or_else(invalid)?,
+            _ => self.same[(mode - 2 - Self::NEAR) * 256 + addresses.byte()? as usize],
+        };
+        self.near[self.next_slot] = address;
+        self.next_slot = (self.next_slot + 1) % Self::NEAR;
+        self.same[(address % (Self::SAME * 256) as u64) as usize] = address;
+        Ok(address)
+    }
+}
+
+/// returns `window` decoded against `source` and the `target` decoded by earlier windows.
+fn decode_window(window: &VCDIFFWindow, source: &[u8], target: &[u8]) -> Result<Vec<u8>, Error> {
+    let segment: &[u8] = match &window.segment {
+        None => &[],
+        Some(segment) => {
+            let base = match segment.kind {
+                VCDIFFSegmentKind::Source => source,
+                VCDIFFSegmentKind::Target => target,
+            };
+            segment.position.checked_add(segment.len)
+                .and_then(|end| base.get(segment.position.to_index()..end.to_index()))
+                .ok_or_else(|| Error::new(WrongSource)
+                    .with_description(format!("The window copies from {:#X} to {:#X}, past the end at {:#X}.",
+                                              segment.position, segment.position.saturating_add(segment.len), base.len())))?
+        }
+    };
+    let table = code_table();
+    let target_len = window.target_len.to_index();
+    let mut result = Vec::with_capacity(target_len.min(1 << 24));
+    let mut data = Section::new(&window.data, "data");
+    let mut instructions = Section::new(&window.instructions, "instructions");
+    let mut addresses = Section::new(&window.addresses, "addresses");
+    let mut cache = AddressCache::new();
+    let too_long = || Error::new(PatchingError).with_description("Window decodes past its length.".to_string());
+
+    while !instructions.is_empty() {
+        for instruction in table[instructions.byte()? as usize] {
+            if instruction.kind == Kind::Noop {
+                continue;
+            }
+            let size = match instruction.size {
+                0 => instructions.int()?,
+                size => size as u64,
+            };
+            if size > (target_len - result.len()) as u64 {
+                return Err(too_long());
+            }
+            let size = size as usize;
+            match instruction.kind {
+                Kind::Noop => {}
+                Kind::Add => result.extend_from_slice(data.bytes(size as u64)?),
+                Kind::Run => {
+                    let byte = data.byte()?;
+                    result.resize(result.len() + size, byte);
+                }
+                Kind::Copy(mode) => {
+                    let here = (segment.len() + result.len()) as u64;
+                    let address = cache.decode(here, mode, &mut addresses)?;
+                    if address >= here {
+                        return Err(Error::new(PatchingError)
+                            .with_description(format!("COPY from {:#X} reads past the data decoded so far.", address)));
+                    }
+                    // copies from the target may overlap what they write, so they go byte by byte
+                    for i in address.to_index()..address.to_index() + size {
+                        let byte = match i.checked_sub(segment.len()) {
+                            None => segment[i],
+                            Some(i) => result[i],
+                        };
+                        result.push(byte);
+                    }
+                }
+            }
+        }
+    }
+    if result.len() != target_len {
+        return Err(Error::new(PatchingError)
+            .with_description(format!("Window decodes to {} bytes instead of {}.", result.len(), target_len)));
+    }
+    if let Some(expected) = window.adler32 {
+        let actual = adler32(&result);
+        if actual != expected {
+            return Err(Error::new(ChecksumMismatch)
+                .with_description(format!("Window has Adler-32 {:08x} instead of {:08x}.", actual, expected)));
+        }
+    }
+    Ok(result)
+}
+
+/// returns the Adler-32 checksum of `data`.
+fn adler32(data: &[u8]) -> u32 {
+    const MOD: u32 = 65521;
+    let (mut a, mut b) = (1u32, 0u32);
+    // 5552 bytes is the most that can be summed before b overflows
+    for chunk in data.chunks(5552) {
+        for byte in chunk {
+            a += *byte as u32;
+            b += a;
+        }
+        a %= MOD;
+        b %= MOD;
+    }
+    return (b << 16) | a;
+}
+
+#[cfg(test)]
+mod tests {
+    use spectral::prelude::*;
+
+    use super::*;
+
+    /// a window turning `[0, 1, ..., 9]` into `[0, 1, 0xAA, 0xBB, 4, 0xCC, 0xCC, 0xCC, 8]`.
+    fn window() -> VCDIFFWindow {
+        VCDIFFWindow {
+            segment: Some(VCDIFFSegment { kind: VCDIFFSegmentKind::Source, position: 0, len: 10 }),
+            target_len: 9,
+            adler32: Some(adler32(&[0, 1, 0xAA, 0xBB, 4, 0xCC, 0xCC, 0xCC, 8])),
+            data: Box::new([0xAA, 0xBB, 0xCC]),
+            // COPY 2 from 0, ADD 2, COPY 1 from HERE - 10, RUN 3, COPY 1 from near[0] + 8
+            instructions: Box::new([19, 2, 3, 35, 1, 0, 3, 51, 1]),
+            addresses: Box::new([0, 10, 8]),
+        }
+    }
+
+    fn source() -> Vec<u8> {
+        (0..10).collect()
+    }
+
+    #[test]
+    fn integers_round_trip() {
+        for value in [0, 1, 0x7F, 0x80, 0x3FFF, 0x4000, u32::MAX as u64, u64::MAX] {
+            let mut encoded = Vec::new();
+            write_int(&mut encoded, value);
+            assert_that!(Section::new(&encoded, "test").int().unwrap()).is_equal_to(value);
+        }
+        let mut encoded = Vec::new();
+        write_int(&mut encoded, 0x4000);
+        assert_that!(encoded).is_equal_to(vec![0x81, 0x80, 0x00]);
+    }
+
+    #[test]
+    fn code_table_matches_the_rfc() {
+        let table = code_table();
+        assert_that!(table.len()).is_equal_to(256);
+        assert_that!(table[18][0].size).is_equal_to(17);
+        assert_that!(table[163][0].kind).is_equal_to(Kind::Add);
+        assert_that!(table[163][1].kind).is_equal_to(Kind::Copy(0));
+        assert_that!(table[235][1].kind).is_equal_to(Kind::Copy(6));
+        assert_that!(table[255][0].kind).is_equal_to(Kind::Copy(8));
+        assert_that!(table[255][1].kind).is_equal_to(Kind::Add);
+    }
+
+    #[test]
+    fn windows_decode_every_instruction() {
+        let patch = VCDIFFPatch::new().with_app_header(b"rom.sfc//hack.sfc/").with_window(window());
+        let read = VCDIFFPatch::from_bytes(&patch.to_bytes().unwrap()).unwrap();
+        assert_that!(read).is_equal_to(&patch);
+        assert_that!(read.apply_to_vec(&source()).unwrap()).is_equal_to(vec![0, 1, 0xAA, 0xBB, 4, 0xCC, 0xCC, 0xCC, 8]);
+        assert_that!(read.has_checksums()).is_true();
+    }
+
+    #[test]
+    fn target_segments_and_overlapping_copies() {
+        let second = VCDIFFWindow {
+            segment: Some(VCDIFFSegment { kind: VCDIFFSegmentKind::Target, position: 2, len: 2 }),
+            target_len: 7,
+            adler32: None,
+            data: Box::new([]),
+            // COPY 2 from the segment, COPY 5 from 2, overlapping what it writes
+            instructions: Box::new([19, 2, 19, 5]),
+            addresses: Box::new([0, 2]),
+        };
+        let patch = VCDIFFPatch::new().with_window(window()).with_window(second);
+        let patched = patch.apply_to_vec(&source()).unwrap();
+        assert_that!(patched[9..].to_vec()).is_equal_to(vec![0xAA, 0xBB, 0xAA, 0xBB, 0xAA, 0xBB, 0xAA]);
+    }
+
+    #[test]
+    fn malformed_windows_are_refused() {
+        let err = VCDIFFPatch::new().with_window(window()).apply_to_vec(&[0; 4]).unwrap_err();
+        assert_that!(matches!(err.kind(), WrongSource)).is_true();
+        let mut corrupt = window();
+        corrupt.data[0] = 0;
+        let err = VCDIFFPatch::new().with_window(corrupt).apply_to_vec(&source()).unwrap_err();
+        assert_that!(matches!(err.kind(), ChecksumMismatch)).is_true();
+        let long = VCDIFFWindow { target_len: 8, adler32: None, ..window() };
+        assert_that!(VCDIFFPatch::new().with_window(long).apply_to_vec(&source())).is_err();
+    }
+
+    #[test]
+    fn unsupported_features_are_refused() {
+        assert_that!(VCDIFFPatch::from_bytes(&[0xD6, 0xC3, 0xC4, 0x00, VCD_DECOMPRESS, 0x01]).unwrap_err().to_string())
+            .contains("Secondary compression");
+        assert_that!(VCDIFFPatch::from_bytes(&[0xD6, 0xC3, 0xC4, b'S', 0x00]).unwrap_err().to_string()).contains("version 0");
+        assert_that!(VCDIFFPatch::from_bytes(&[0xD6, 0xC3, 0xC4, 0x00, 0x00, 0x01, 0x04]).unwrap_err().to_string())
+            .contains("Unexpected end of window section");
+    }
+}
@@ -0,0 +1,239 @@
+//! Finding data a patch copies verbatim from the rom it is made for.
+//!
+//! Patches are meant to carry only what their authors changed. Long runs of a payload that also
+//! appear somewhere in the source rom, e.g. relocated graphics or a copied bank of an expanded
+//! rom, distribute the original data along with the patch and are worth reviewing before the
+//! patch is published.
+//!
+//! Runs of a single repeated byte, like padding, are not reported.
+
+use std::collections::HashMap;
+use std::ops::Range;
+
+use crate::diagnostics::{Diagnostic, DiagnosticCode};
+use crate::ips::{IPSHunk, IPSPatch};
+
+/// Length of verbatim runs reported by default.
+pub const DEFAULT_MIN_LEN: usize = 64;
+
+/// A run of written bytes that is also found in the source.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct VerbatimRun {
+    /// index of the write the run is part of, the hunk index for [check_ips].
+    pub write: usize,
+    /// range of offsets in the patched file the run is written to.
+    pub range: Range<u64>,
+    /// offset of the copy in the source.
+    pub source_offset: u64,
+}
+
+impl VerbatimRun {
+    /// returns the length of the run.
+    pub fn len(&self) -> u64 {
+        self.range.end - self.range.start
+    }
+
+    /// returns `true` if the run is empty, which found runs never are.
+    pub fn is_empty(&self) -> bool {
+        self.range.is_empty()
+    }
+
+    /// returns the [DiagnosticCode::VerbatimSource] warning about the run, attributed to hunk
+    /// [VerbatimRun::write].
+    pub fn to_diagnostic(&self) -> Diagnostic {
+        Diagnostic::warning(Some(self.write), format!(
+            "{} bytes written at {:#X} are copied verbatim from the source at {:#X}, distributing them may infringe on the copyright of the rom.",
+            self.len(), self.range.start, self.source_offset,
+        )).with_code(DiagnosticCode::VerbatimSource)
+    }
+}
+
+/// finds runs of at least `min_len` bytes of `writes`, pairs of offset and written bytes, that are
+/// also found in `source`, ordered by write and offset.
+///
+/// Runs are found by looking up blocks of half of `min_len` bytes, so a run is found no matter
+/// where it lies in the source.
+///
+/// # Examples
+///
+/// ```
+/// use rom_patcher::verbatim::find_verbatim;
+/// let source: Vec<u8> = (0..=255).collect();
+/// let runs = find_verbatim(&source, [(0x1000, &source[16..116])], 64);
+/// assert_eq!(runs[0].range, 0x1000..0x1064);
+/// assert_eq!(runs[0].source_offset, 16);
+/// ```
+pub fn find_verbatim<'a>(source: &[u8], writes: impl IntoIterator<Item=(u64, &'a [u8])>, min_len: usize) -> Vec<VerbatimRun> {
+    let min_len = min_len.max(1);
+    let block = min_len.div_ceil(2);
+    let index = BlockIndex::new(source, block);
+    let mut result = Vec::new();
+    for (write, (offset, data)) in writes.into_iter().enumerate() {
+        if data.len() < min_len {
+            continue;
+        }
+        let mut hash = RollingHash::new(&data[..block]);
+        let mut position = 0;
+        // bytes before this are already part of a reported run
+        let mut covered = 0;
+        while position + block <= data.len() {
+            if let Some(source_position) = index.find(hash.value, &data[position..position + block], source) {
+                let back = data[covered..position].iter().rev()
+                    .zip(source[..source_position].iter().rev())
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                let forward = data[position..].iter()
+                    .zip(&source[source_position..])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                let (start, end) = (position - back, position + forward);
+                if end - start >= min_len {
+                    result.push(VerbatimRun {
+                        write,
+                        range: offset + start as u64..offset + end as u64,
+                        source_offset: (source_position - back) as u64,
+                    });
+                    covered = end;
+                    if end + block > data.len() {
+                        break;
+                    }
+                    position = end;
+                    hash = RollingHash::new(&data[position..position + block]);
+                    continue;
+                }
+            }
+            if position + block == data.len() {
+                break;
+            }
+            hash.roll(data[position], data[position + block]);
+            position += 1;
+        }
+    }
+    return result;
+}
+
+/// finds the runs of the payloads of `patch` of at least `min_len` bytes that are also found in
+/// `source`, see [find_verbatim].
+pub fn check_ips(patch: &IPSPatch, source: &[u8], min_len: usize) -> Vec<VerbatimRun> {
+    // RLE hunks repeat a single byte, which is never reported
+    let writes = patch.hunks().iter().map(|x| match x {
+        IPSHunk::Regular(data) => (data.offset, &data.payload[..]),
+        IPSHunk::RLE(data) => (data.offset, &[][..]),
+    });
+    find_verbatim(source, writes, min_len)
+}
+
+/// The first offset of every distinct block of the source starting at a multiple of the block
+/// length, by hash.
+struct BlockIndex {
+    block: usize,
+    offsets: HashMap<u64, usize>,
+}
+
+impl BlockIndex {
+    fn new(source: &[u8], block: usize) -> BlockIndex {
+        let mut offsets = HashMap::new();
+        for (i, chunk) in source.chunks_exact(block).enumerate() {
+            if chunk.iter().all(|x| *x == chunk[0]) {
+                continue;
+            }
+            offsets.entry(RollingHash::new(chunk).value).or_insert(i * block);
+        }
+        BlockIndex { block, offsets }
+    }
+
+    /// returns the offset of `data`, a block with hash `hash`, in `source`.
+    fn find(&self, hash: u64, data: &[u8], source: &[u8]) -> Option<usize> {
+        let offset = *self.offsets.get(&hash)?;
+        return (source[offset..offset + self.block] == *data).then_some(offset);
+    }
+}
+
+/// A polynomial hash of a window of bytes that can be moved a byte at a time.
+struct RollingHash {
+    value: u64,
+    /// the weight of the first byte of the window.
+    top: u64,
+}
+
+impl RollingHash {
+    const BASE: u64 = 0x100000001B3;
+
+    fn new(window: &[u8]) -> RollingHash {
+        let mut value = 0u64;
+        let mut top = 1u64;
+        for (i, byte) in window.iter().enumerate() {
+            value = value.wrapping_mul(Self::BASE).wrapping_add(*byte as u64 + 1);
+            if i > 0 {
+                top = top.wrapping_mul(Self::BASE);
+            }
+        }
+        RollingHash { value, top }
+    }
+
+    /// moves the window past `leaving` onto `entering`.
+    fn roll(&mut self, leaving: u8, entering: u8) {
+        self.value = self.value
+            .wrapping_sub((leaving as u64 + 1).wrapping_mul(self.top))
+            .wrapping_mul(Self::BASE)
+            .wrapping_add(entering as u64 + 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use spectral::prelude::*;
+
+    use crate::ips::{IPSRegularHunkData, IPSRLEHunkData};
+    use crate::testkit::Rng;
+
+    use super::*;
+
+    fn random(seed: u64, len: usize) -> Vec<u8> {
+        let mut data = vec![0; len];
+        Rng::new(seed).fill(&mut data);
+        return data;
+    }
+
+    #[test]
+    fn copied_runs_are_found_at_any_alignment() {
+        let source = random(1, 4096);
+        for start in [0, 1, 31, 977] {
+            let mut payload = random(2, 20);
+            payload.extend_from_slice(&source[start..start + 70]);
+            payload.extend(random(3, 20));
+            let runs = find_verbatim(&source, [(0x8000, &payload[..])], 64);
+            assert_that!(runs).is_equal_to(vec![VerbatimRun {
+                write: 0,
+                range: 0x8000 + 20..0x8000 + 90,
+                source_offset: start as u64,
+            }]);
+        }
+    }
+
+    #[test]
+    fn short_and_uniform_runs_are_ignored() {
+        let mut source = random(4, 1024);
+        source[256..512].fill(0xFF);
+        let mut payload = source[100..150].to_vec();
+        payload.extend(vec![0xFF; 200]);
+        assert_that!(find_verbatim(&source, [(0, &payload[..])], 64)).is_empty();
+    }
+
+    #[test]
+    fn hunks_are_checked_and_reported() {
+        let source = random(5, 2048);
+        let patch = IPSPatch::new()
+            .with_hunk(IPSHunk::RLE(IPSRLEHunkData { offset: 0, run_length: 500, payload: 0 }))
+            .with_hunk(IPSHunk::Regular(IPSRegularHunkData {
+                offset: 0x10000,
+                payload: source[512..640].to_vec().into_boxed_slice(),
+            }));
+        let diagnostics: Vec<Diagnostic> = check_ips(&patch, &source, DEFAULT_MIN_LEN).iter()
+            .map(|x| x.to_diagnostic())
+            .collect();
+        assert_that!(diagnostics.len()).is_equal_to(1);
+        assert_that!(diagnostics[0].hunk).is_equal_to(Some(1));
+        assert_that!(diagnostics[0].code).is_equal_to(Some(DiagnosticCode::VerbatimSource));
+    }
+}
@@ -0,0 +1,246 @@
+//! Verifying pre-patched roms against the patch they were made with, see [verify_applied].
+
+use std::ops::Range;
+
+use crate::hash::Crc32;
+use crate::hunk::{coverage, Hunk};
+use crate::index::IntervalIndex;
+use crate::io_util::U64Extensions;
+use crate::ips::IPSPatch;
+use crate::patch::Patch;
+use crate::pmsr::PMSRPatch;
+
+/// The findings of [verify_applied].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize))]
+pub struct VerifyResult {
+    /// whether the patched rom has the checksum the patch records for its output, [None] if it
+    /// records none.
+    pub checksum_matches: Option<bool>,
+    /// whether the patched rom has the length the patch records or implies, [None] if it is
+    /// unknown.
+    pub length_matches: Option<bool>,
+    /// whether the source has the checksum the patch records for it, [None] without a source or if
+    /// the patch records none.
+    pub source_matches: Option<bool>,
+    /// ranges the patch writes to that don't hold what it writes, ordered by offset.
+    pub missing_writes: Vec<Range<u64>>,
+    /// ranges the patch leaves alone that differ from the source, ordered by offset. Only checked
+    /// with a source.
+    pub unexpected_changes: Vec<Range<u64>>,
+    /// amount of bytes written by the patch that were compared.
+    pub checked_bytes: u64,
+}
+
+impl VerifyResult {
+    /// returns `true` if nothing contradicts the patched rom being made with the patch.
+    pub fn is_consistent(&self) -> bool {
+        self.checksum_matches != Some(false)
+            && self.length_matches != Some(false)
+            && self.source_matches != Some(false)
+            && self.missing_writes.is_empty()
+            && self.unexpected_changes.is_empty()
+    }
+
+    /// returns `true` if anything was checked at all, since formats unknown to the crate can
+    /// neither be compared byte by byte nor may record a checksum.
+    pub fn is_verified(&self) -> bool {
+        self.checksum_matches.is_some() || self.checked_bytes > 0 || !self.unexpected_changes.is_empty()
+    }
+}
+
+/// checks whether `patched` is consistent with being `source` patched with `patch`, without
+/// applying the patch again.
+///
+/// The bytes the patch writes are compared against `patched`, taking later writes over earlier
+/// ones. With `source`, the bytes the patch leaves alone are compared against it as well. Patches
+/// recording checksums are checked against them. This suits checking distributed pre-patched
+/// images where the source may not be at hand.
+///
+/// # Examples
+///
+/// ```
+/// use rom_patcher::ips::{IPSHunk, IPSPatch, IPSRLEHunkData};
+/// use rom_patcher::verify::verify_applied;
+///
+/// let patch = IPSPatch::new()
+///     .with_hunk(IPSHunk::RLE(IPSRLEHunkData { offset: 1, run_length: 2, payload: 0xFF }));
+/// assert!(verify_applied(&patch, None, &[0, 0xFF, 0xFF, 0]).is_consistent());
+/// let result = verify_applied(&patch, Some(&[0, 0, 0, 0]), &[0, 0xFF, 0, 1]);
+/// assert_eq!(result.missing_writes, vec![2..3]);
+/// assert_eq!(result.unexpected_changes, vec![3..4]);
+/// ```
+pub fn verify_applied(patch: &dyn Patch, source: Option<&[u8]>, patched: &[u8]) -> VerifyResult {
+    let info = patch.info();
+    let mut result = VerifyResult {
+        checksum_matches: info.target_crc32.map(|x| x == Crc32::checksum(patched)),
+        length_matches: info.target_len.map(|x| x == patched.len() as u64),
+        source_matches: source.and_then(|s| info.source_crc32.map(|x| x == Crc32::checksum(s))),
+        ..VerifyResult::default()
+    };
+
+    if let Some(ips) = patch.as_any().downcast_ref::<IPSPatch>() {
+        // without a source, the length is only known if the patch truncates inside what it writes
+        let expected_len = source.map(|x| ips.patched_len(x.len() as u64))
+            .or_else(|| ips.truncate().filter(|x| *x <= end(ips.hunks())));
+        let limit = ips.truncate().unwrap_or(u64::MAX);
+        check_hunks(ips.hunks(), expected_len, limit, source, patched, &mut result);
+    } else if let Some(pmsr) = patch.as_any().downcast_ref::<PMSRPatch>() {
+        let expected_len = source.map(|x| pmsr.patched_len(x.len() as u64));
+        check_hunks(pmsr.records(), expected_len, u64::MAX, source, patched, &mut result);
+    }
+    return result;
+}
+
+/// returns the end of the last write of `hunks`.
+fn end<H: Hunk>(hunks: &[H]) -> u64 {
+    hunks.iter().map(|x| x.target_range().end).max().unwrap_or(0)
+}
+
+/// compares what `hunks` write before `limit`, and with `source` what they leave alone, against
+/// `patched`, which should be `expected_len` bytes long if that is known.
+fn check_hunks<H: Hunk>(
+    hunks: &[H],
+    expected_len: Option<u64>,
+    limit: u64,
+    source: Option<&[u8]>,
+    patched: &[u8],
+    result: &mut VerifyResult,
+) {
+    if let Some(len) = expected_len {
+        result.length_matches = Some(result.length_matches.unwrap_or(true) && len == patched.len() as u64);
+    }
+    let index = IntervalIndex::new(hunks.iter().enumerate().map(|(i, x)| (x.target_range(), i)));
+    let written = coverage(hunks);
+    for range in &written {
+        let range = range.start..range.end.min(limit);
+        if range.is_empty() {
+            continue;
+        }
+        let mut order: Vec<usize> = index.overlapping(range.clone()).into_iter().map(|(_, i)| *i).collect();
+        order.sort_unstable();
+        let mut expected = vec![0; (range.end - range.start).saturating_usize()];
+        for i in order {
+            hunks[i].overlay(range.start, &mut expected);
+        }
+        let actual = patched.get(range.start.saturating_usize()..).unwrap_or_default();
+        let differing = differing_ranges(&expected, actual).map(|x| x.start + range.start..x.end + range.start);
+        push_ranges(&mut result.missing_writes, differing);
+        result.checked_bytes += range.end - range.start;
+    }
+
+    if let Some(source) = source {
+        let common = (source.len().min(patched.len()) as u64).min(limit);
+        let mut position = 0;
+        for range in written.iter().map(|x| x.start.min(common)..x.end.min(common)).chain(std::iter::once(common..common)) {
+            let unwritten = position.to_index()..range.start.to_index();
+            let differing = differing_ranges(&source[unwritten.clone()], &patched[unwritten.clone()])
+                .map(|x| x.start + position..x.end + position);
+            push_ranges(&mut result.unexpected_changes, differing);
+            position = position.max(range.end);
+        }
+    }
+}
+
+/// returns the ranges where `expected` and `actual` differ, counting bytes missing from `actual`
+/// as differing.
+fn differing_ranges<'a>(expected: &'a [u8], actual: &'a [u8]) -> impl Iterator<Item=Range<u64>> + 'a {
+    let mut position = 0;
+    std::iter::from_fn(move || {
+        while position < expected.len() && actual.get(position) == Some(&expected[position]) {
+            position += 1;
+        }
+        if position == expected.len() {
+            return None;
+        }
+        let start = position;
+        while position < expected.len() && actual.get(position) != Some(&expected[position]) {
+            position += 1;
+        }
+        Some(start as u64..position as u64)
+    })
+}
+
+/// appends `ranges` to `target`, merging adjacent ones.
+fn push_ranges(target: &mut Vec<Range<u64>>, ranges: impl Iterator<Item=Range<u64>>) {
+    for range in ranges {
+        match target.last_mut() {
+            Some(last) if last.end == range.start => last.end = range.end,
+            _ => target.push(range),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use spectral::prelude::*;
+
+    use crate::ips::{IPSHunk, IPSRegularHunkData, IPSRLEHunkData};
+    use crate::pmsr::PMSRRecord;
+    use crate::testkit::Fixture;
+
+    use super::*;
+
+    #[test]
+    fn patched_roms_are_consistent() {
+        for seed in 0..8 {
+            let fixture = Fixture::generate(seed);
+            let result = verify_applied(&fixture.ips, Some(&fixture.source), &fixture.target);
+            assert_that!(result.is_consistent()).is_true();
+            assert_that!(result.is_verified()).is_true();
+            assert_that!(verify_applied(&fixture.ips, None, &fixture.target).is_consistent()).is_true();
+            if fixture.source != fixture.target {
+                let result = verify_applied(&fixture.ips, Some(&fixture.source), &fixture.source);
+                assert_that!(result.is_consistent()).is_false();
+            }
+        }
+    }
+
+    #[test]
+    fn star_rod_mods_are_checked() {
+        let source = [1; 8];
+        let patch = PMSRPatch::new()
+            .with_record(PMSRRecord { offset: 2, data: Box::new([5, 6]) })
+            .with_record(PMSRRecord { offset: 9, data: Box::new([7]) });
+        let target = patch.apply_to_vec(&source).unwrap();
+        let result = verify_applied(&patch, Some(&source), &target);
+        // the source isn't the Paper Mario rom the mod expects
+        assert_that!(result.source_matches).is_equal_to(Some(false));
+        assert_that!(result.length_matches).is_equal_to(Some(true));
+        assert_that!(result.missing_writes.len() + result.unexpected_changes.len()).is_equal_to(0);
+        let mut tampered = target.clone();
+        tampered[0] = 0;
+        let result = verify_applied(&patch, Some(&source), &tampered);
+        assert_that!(result.unexpected_changes).is_equal_to(std::iter::once(0..1).collect::<Vec<_>>());
+        assert_that!(result.missing_writes.len()).is_equal_to(0);
+    }
+
+    #[test]
+    fn later_writes_take_precedence() {
+        let patch = IPSPatch::new()
+            .with_hunk(IPSHunk::RLE(IPSRLEHunkData { offset: 0, run_length: 4, payload: 1 }))
+            .with_hunk(IPSHunk::Regular(IPSRegularHunkData::new(1, vec![2, 2])));
+        let result = verify_applied(&patch, None, &[1, 2, 2, 1]);
+        assert_that!(result.is_consistent()).is_true();
+        assert_that!(result.checked_bytes).is_equal_to(4);
+    }
+
+    #[test]
+    fn truncation_is_checked() {
+        let patch = IPSPatch::new()
+            .with_hunk(IPSHunk::RLE(IPSRLEHunkData { offset: 2, run_length: 4, payload: 1 }))
+            .with_truncate(4);
+        let result = verify_applied(&patch, Some(&[0; 8]), &[0, 0, 1, 1]);
+        assert_that!(result.is_consistent()).is_true();
+        let result = verify_applied(&patch, Some(&[0; 8]), &[0, 0, 1, 1, 1, 1]);
+        assert_that!(result.length_matches).is_equal_to(Some(false));
+    }
+
+    #[test]
+    fn short_roms_miss_writes() {
+        let patch = PMSRPatch::new().with_record(PMSRRecord { offset: 2, data: Box::new([5, 6, 7]) });
+        let result = verify_applied(&patch, None, &[0, 0, 5]);
+        assert_that!(result.missing_writes).is_equal_to(std::iter::once(3..5).collect::<Vec<_>>());
+        assert_that!(result.length_matches).is_none();
+    }
+}
@@ -0,0 +1,121 @@
+//! Upgrading a patched rom from one version of a patch to another without the original rom.
+//!
+//! Hacks are usually distributed as patches against the original rom, so moving from v1 to v2
+//! would need the original. Everything the v1 patch didn't overwrite is still the original in the
+//! v1 rom though, so as long as v2 overwrites at least the same bytes, v2 can be built from the
+//! v1 rom directly.
+
+use crate::detect::{already_applied, Confidence};
+use crate::Error;
+use crate::ErrorKind::{CreatingError, WrongSource};
+use crate::io_util::U64Extensions;
+use crate::ips::{IPSHunk, IPSPatch};
+use crate::patch::Patch;
+
+/// returns a patch turning `rom`, which has `from` applied to some base rom, into that base rom
+/// with `to` applied.
+///
+/// Assumes `from` didn't change the length of the base rom. Returns a [WrongSource] error if
+/// `rom` doesn't have `from` applied, and a [CreatingError] naming the offset if `to` needs a byte
+/// of the base rom that `from` overwrote.
+///
+/// # Examples
+///
+/// ```
+/// use rom_patcher::ips::{IPSHunk, IPSPatch, IPSRLEHunkData};
+/// use rom_patcher::versions::upgrade;
+///
+/// let v1 = IPSPatch::new().with_hunk(IPSHunk::RLE(IPSRLEHunkData { offset: 1, run_length: 1, payload: 0xAA }));
+/// let v2 = IPSPatch::new()
+///     .with_hunk(IPSHunk::RLE(IPSRLEHunkData { offset: 1, run_length: 1, payload: 0xBB }))
+///     .with_hunk(IPSHunk::RLE(IPSRLEHunkData { offset: 3, run_length: 1, payload: 0xCC }));
+/// assert_eq!(upgrade(&v1, &v2, &[1, 0xAA, 3, 4]).unwrap(), vec![1, 0xBB, 3, 0xCC]);
+/// ```
+pub fn upgrade_patch(from: &IPSPatch, to: &IPSPatch, rom: &[u8]) -> Result<IPSPatch, Error> {
+    if already_applied(from, rom) < Confidence::Likely {
+        return Err(Error::new(WrongSource).with_description("The rom doesn't have the patch to upgrade from applied.".to_string()));
+    }
+    if from.patched_len(rom.len() as u64) != rom.len() as u64 {
+        return Err(Error::new(CreatingError)
+            .with_description("The patch to upgrade from changes the length of the rom.".to_string()));
+    }
+
+    // the base rom as far as it is known, `None` where `from` overwrote it
+    let mut target: Vec<Option<u8>> = rom.iter().map(|x| Some(*x)).collect();
+    // hunks past the end of the rom were truncated away by `from`
+    let len = target.len() as u64;
+    for hunk in from.hunks() {
+        target[hunk.offset().min(len).to_index()..hunk.end().min(len).to_index()].fill(None);
+    }
+    for hunk in to.hunks() {
+        let range = hunk.offset().saturating_usize()..hunk.end().saturating_usize();
+        if target.len() < range.end {
+            target.resize(range.end, Some(0));
+        }
+        match hunk {
+            IPSHunk::Regular(data) => target[range].iter_mut().zip(data.payload.iter()).for_each(|(x, y)| *x = Some(*y)),
+            IPSHunk::RLE(data) => target[range].fill(Some(data.payload)),
+        }
+    }
+    if let Some(truncate) = to.truncate() {
+        target.truncate(truncate.saturating_usize());
+    }
+
+    let target: Vec<u8> = match target.iter().position(|x| x.is_none()) {
+        Some(offset) => return Err(Error::new(CreatingError).with_description(format!(
+            "The new version keeps the byte at {:#x} of the original rom, which the old version overwrote.", offset,
+        ))),
+        None => target.into_iter().flatten().collect(),
+    };
+    return IPSPatch::create(rom, &target);
+}
+
+/// applies [upgrade_patch] to `rom` and returns the upgraded rom.
+pub fn upgrade(from: &IPSPatch, to: &IPSPatch, rom: &[u8]) -> Result<Vec<u8>, Error> {
+    let patch = upgrade_patch(from, to, rom)?;
+    return patch.apply_to_vec(rom);
+}
+
+#[cfg(test)]
+mod tests {
+    use spectral::prelude::*;
+
+    use crate::ips::{IPSRegularHunkData, IPSRLEHunkData};
+
+    use super::*;
+
+    fn regular(offset: u64, payload: &[u8]) -> IPSHunk {
+        IPSHunk::Regular(IPSRegularHunkData { offset, payload: payload.into() })
+    }
+
+    #[test]
+    fn upgrades_match_patching_the_original() {
+        let base: Vec<u8> = (0..32).collect();
+        let v1 = IPSPatch::new().with_hunk(regular(4, &[0xAA; 4])).with_hunk(regular(20, &[0xAB; 2]));
+        let v2 = IPSPatch::new()
+            .with_hunk(regular(2, &[0xBB; 8]))
+            .with_hunk(IPSHunk::RLE(IPSRLEHunkData { offset: 20, run_length: 2, payload: 0xBC }))
+            .with_hunk(regular(32, &[0xBD; 4]));
+        let rom = v1.apply_to_vec(&base).unwrap();
+        assert_that!(upgrade(&v1, &v2, &rom).unwrap()).is_equal_to(v2.apply_to_vec(&base).unwrap());
+    }
+
+    #[test]
+    fn upgrades_needing_the_original_fail() {
+        let v1 = IPSPatch::new().with_hunk(regular(4, &[0xAA; 4]));
+        let v2 = IPSPatch::new().with_hunk(regular(4, &[0xBB; 2]));
+        let rom = v1.apply_to_vec(&[0; 16]).unwrap();
+        assert_that!(upgrade(&v1, &v2, &rom).unwrap_err().to_string()).contains("0x6");
+        assert_that!(upgrade(&v1, &v2, &[0; 16])).is_err();
+    }
+
+    #[test]
+    fn hunks_truncated_away_are_ignored() {
+        let v1 = IPSPatch::new()
+            .with_hunk(IPSHunk::RLE(IPSRLEHunkData { offset: 0, run_length: 1, payload: 0xAA }))
+            .with_hunk(IPSHunk::RLE(IPSRLEHunkData { offset: 4, run_length: 2, payload: 0xAB }))
+            .with_truncate(4);
+        let v2 = IPSPatch::new().with_hunk(regular(0, &[0xBB]));
+        assert_that!(upgrade(&v1, &v2, &[0xAA, 1, 2, 3]).unwrap()).is_equal_to(vec![0xBB, 1, 2, 3]);
+    }
+}
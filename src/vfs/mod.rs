@@ -0,0 +1,220 @@
+//! Where the path based APIs read sources and patches from and write outputs to.
+//!
+//! [PatchJob](crate::facade::PatchJob) goes through a [Vfs] instead of [std::fs], so it can be run
+//! against [memory](MemoryFs) in tests and sandboxes, or read roms straight out of zip archives
+//! with [ZipFs] and the `zip` feature enabled. Object storage is supported by [s3] with the `s3`
+//! feature enabled.
+
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::fs;
+use std::io::{Cursor, Error as IOError, ErrorKind, Read, Result as IOResult};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+#[cfg(feature = "s3")]
+pub mod s3;
+
+/// A file system the path based APIs work on.
+pub trait Vfs: Debug + Send + Sync {
+    /// opens the file at `path` for reading.
+    fn open(&self, path: &Path) -> IOResult<Box<dyn Read + Send + '_>>;
+
+    /// reads the whole file at `path`.
+    fn read(&self, path: &Path) -> IOResult<Vec<u8>> {
+        let mut result = Vec::new();
+        self.open(path)?.read_to_end(&mut result)?;
+        return Ok(result);
+    }
+
+    /// writes `data` to the file at `path`, replacing it if it exists.
+    fn write(&self, path: &Path, data: &[u8]) -> IOResult<()>;
+
+    /// moves the file at `from` to `to`, replacing `to` if it exists.
+    fn rename(&self, from: &Path, to: &Path) -> IOResult<()>;
+}
+
+/// The real file system, through [std::fs].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StdFs;
+
+impl Vfs for StdFs {
+    fn open(&self, path: &Path) -> IOResult<Box<dyn Read + Send + '_>> {
+        Ok(Box::new(fs::File::open(path)?))
+    }
+
+    fn read(&self, path: &Path) -> IOResult<Vec<u8>> {
+        fs::read(path)
+    }
+
+    fn write(&self, path: &Path, data: &[u8]) -> IOResult<()> {
+        fs::write(path, data)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> IOResult<()> {
+        fs::rename(from, to)
+    }
+}
+
+/// A file system held in memory, for tests and environments without disk access.
+///
+/// # Examples
+///
+/// ```
+/// use std::path::Path;
+/// use rom_patcher::vfs::{MemoryFs, Vfs};
+///
+/// let vfs = MemoryFs::new().with_file("game.sfc", vec![1, 2, 3]);
+/// vfs.rename(Path::new("game.sfc"), Path::new("renamed.sfc")).unwrap();
+/// assert_eq!(vfs.file("renamed.sfc"), Some(vec![1, 2, 3]));
+/// assert!(vfs.read(Path::new("game.sfc")).is_err());
+/// ```
+#[derive(Debug, Default)]
+pub struct MemoryFs {
+    files: Mutex<BTreeMap<PathBuf, Vec<u8>>>,
+}
+
+impl MemoryFs {
+    /// constructs an empty file system.
+    pub fn new() -> MemoryFs {
+        MemoryFs::default()
+    }
+
+    /// modifies the file system to contain `data` at `path`.
+    pub fn with_file(self, path: impl Into<PathBuf>, data: Vec<u8>) -> MemoryFs {
+        self.files.lock().unwrap().insert(path.into(), data);
+        return self;
+    }
+
+    /// returns a copy of the file at `path`.
+    pub fn file(&self, path: impl AsRef<Path>) -> Option<Vec<u8>> {
+        self.files.lock().unwrap().get(path.as_ref()).cloned()
+    }
+
+    /// returns the paths of every file, sorted.
+    pub fn paths(&self) -> Vec<PathBuf> {
+        self.files.lock().unwrap().keys().cloned().collect()
+    }
+}
+
+impl Vfs for MemoryFs {
+    fn open(&self, path: &Path) -> IOResult<Box<dyn Read + Send + '_>> {
+        Ok(Box::new(Cursor::new(self.read(path)?)))
+    }
+
+    fn read(&self, path: &Path) -> IOResult<Vec<u8>> {
+        self.file(path).ok_or_else(|| not_found(path))
+    }
+
+    fn write(&self, path: &Path, data: &[u8]) -> IOResult<()> {
+        self.files.lock().unwrap().insert(path.to_path_buf(), data.to_vec());
+        Ok(())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> IOResult<()> {
+        let mut files = self.files.lock().unwrap();
+        let data = files.remove(from).ok_or_else(|| not_found(from))?;
+        files.insert(to.to_path_buf(), data);
+        Ok(())
+    }
+}
+
+/// A read only file system over the entries of a zip archive, addressed by their path within the
+/// archive.
+#[cfg(feature = "zip")]
+#[derive(Debug)]
+pub struct ZipFs<R> {
+    archive: Mutex<zip::ZipArchive<R>>,
+}
+
+#[cfg(feature = "zip")]
+impl<R> ZipFs<R> where R: Read + std::io::Seek {
+    /// opens the zip archive read from `reader`.
+    pub fn new(reader: R) -> IOResult<ZipFs<R>> {
+        let archive = zip::ZipArchive::new(reader).map_err(|e| IOError::new(ErrorKind::InvalidData, e))?;
+        return Ok(ZipFs { archive: Mutex::new(archive) });
+    }
+
+    /// returns the paths of every file in the archive.
+    pub fn paths(&self) -> Vec<PathBuf> {
+        let archive = self.archive.lock().unwrap();
+        archive.file_names()
+            .filter_map(|x| x.ok())
+            .filter(|x| !x.ends_with('/'))
+            .map(|x| PathBuf::from(x.into_owned()))
+            .collect()
+    }
+}
+
+#[cfg(feature = "zip")]
+impl<R> Vfs for ZipFs<R> where R: Read + std::io::Seek + Debug + Send {
+    fn open(&self, path: &Path) -> IOResult<Box<dyn Read + Send + '_>> {
+        // entries borrow the archive, so they are read up front
+        Ok(Box::new(Cursor::new(self.read(path)?)))
+    }
+
+    fn read(&self, path: &Path) -> IOResult<Vec<u8>> {
+        let name = crate::report::normalized(path);
+        let mut archive = self.archive.lock().unwrap();
+        let mut entry = match archive.by_name(&name) {
+            Ok(entry) => entry,
+            Err(zip::result::ZipError::FileNotFound) => return Err(not_found(path)),
+            Err(e) => return Err(IOError::new(ErrorKind::InvalidData, e)),
+        };
+        let mut result = Vec::new();
+        entry.read_to_end(&mut result)?;
+        return Ok(result);
+    }
+
+    fn write(&self, path: &Path, _data: &[u8]) -> IOResult<()> {
+        Err(read_only(path))
+    }
+
+    fn rename(&self, from: &Path, _to: &Path) -> IOResult<()> {
+        Err(read_only(from))
+    }
+}
+
+fn not_found(path: &Path) -> IOError {
+    IOError::new(ErrorKind::NotFound, format!("{} doesn't exist", path.display()))
+}
+
+#[cfg(feature = "zip")]
+fn read_only(path: &Path) -> IOError {
+    IOError::new(ErrorKind::Unsupported, format!("{} is in a read only zip archive", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use spectral::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn memory_files_are_written_and_renamed() {
+        let vfs = MemoryFs::new();
+        vfs.write(Path::new("out.tmp"), &[1, 2]).unwrap();
+        vfs.rename(Path::new("out.tmp"), Path::new("out.bin")).unwrap();
+        assert_that!(vfs.paths()).is_equal_to(vec![PathBuf::from("out.bin")]);
+        assert_that!(vfs.read(Path::new("out.bin")).unwrap()).is_equal_to(vec![1, 2]);
+        assert_that!(vfs.read(Path::new("out.tmp")).unwrap_err().kind()).is_equal_to(ErrorKind::NotFound);
+    }
+
+    #[cfg(feature = "zip")]
+    #[test]
+    fn zip_entries_are_read() {
+        use std::io::Write;
+
+        let mut data = Vec::new();
+        let mut writer = zip::ZipWriter::new(Cursor::new(&mut data));
+        writer.start_file("roms/game.sfc", zip::write::SimpleFileOptions::default()).unwrap();
+        writer.write_all(&[7; 16]).unwrap();
+        writer.finish().unwrap();
+
+        let vfs = ZipFs::new(Cursor::new(data)).unwrap();
+        assert_that!(vfs.paths()).is_equal_to(vec![PathBuf::from("roms/game.sfc")]);
+        assert_that!(vfs.read(Path::new("roms/game.sfc")).unwrap()).is_equal_to(vec![7; 16]);
+        assert_that!(vfs.read(Path::new("roms/other.sfc"))).is_err();
+        assert_that!(vfs.write(Path::new("roms/game.sfc"), &[])).is_err();
+    }
+}
@@ -0,0 +1,164 @@
+use std::io::{Error as IOError, ErrorKind, Read, Result as IOResult, Seek, SeekFrom};
+
+use crate::Error;
+use crate::ErrorKind::PatchingError;
+use crate::ips::{IndexedIPSPatch, IPSPatch};
+
+/// A read-only view of a patched file.
+///
+/// Reads are served from `source` with the hunks of the patch laid over them, so the patched file
+/// can be inspected without writing it and without modifying `source`.
+///
+/// # Examples
+///
+/// ```
+/// use std::io::{Cursor, Read};
+/// use rom_patcher::ips::{IPSHunk, IPSPatch, IPSRLEHunkData};
+/// use rom_patcher::view::PatchedView;
+///
+/// let patch = IPSPatch::new()
+///     .with_hunk(IPSHunk::RLE(IPSRLEHunkData { offset: 1, run_length: 2, payload: 0xFF }));
+/// let mut view = PatchedView::new(&patch, Cursor::new(vec![0; 4])).unwrap();
+/// let mut patched = Vec::new();
+/// view.read_to_end(&mut patched).unwrap();
+/// assert_eq!(patched, vec![0, 0xFF, 0xFF, 0]);
+/// ```
+#[derive(Debug)]
+pub struct PatchedView<'a, R> {
+    patch: IndexedIPSPatch<'a>,
+    source: R,
+    position: u64,
+    len: u64,
+    gap_fill: u8,
+}
+
+impl<'a, R> PatchedView<'a, R> where R: Read + Seek {
+    /// constructs a view of `source` patched with `patch`.
+    pub fn new(patch: &'a IPSPatch, mut source: R) -> Result<PatchedView<'a, R>, Error> {
+        let source_len = source.seek(SeekFrom::End(0))
+            .map_err(|e| Error::new(PatchingError)
+                .with_description("Unable to read source length.".to_string())
+                .with_source(Box::new(e)))?;
+        Ok(PatchedView {
+            len: patch.patched_len(source_len),
+            patch: patch.indexed(),
+            source,
+            position: 0,
+            gap_fill: 0,
+        })
+    }
+
+    /// modifies the view to read `gap_fill` from gaps between the end of the source and hunks past
+    /// it, see [crate::apply::ApplyOptions::with_gap_fill].
+    pub fn with_gap_fill(mut self, gap_fill: u8) -> PatchedView<'a, R> {
+        self.gap_fill = gap_fill;
+        return self;
+    }
+
+    /// returns the length of the patched file.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// returns `true` if the patched file is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// consumes the view and returns the underlying source.
+    pub fn into_inner(self) -> R {
+        self.source
+    }
+}
+
+impl<'a, R> Read for PatchedView<'a, R> where R: Read + Seek {
+    fn read(&mut self, buf: &mut [u8]) -> IOResult<usize> {
+        let end = self.position.saturating_add(buf.len() as u64);
+        let data = self.patch.apply_range_filled(&mut self.source, self.position..end, self.gap_fill)
+            .map_err(|e| IOError::other(e.to_string()))?;
+        buf[..data.len()].copy_from_slice(&data);
+        self.position += data.len() as u64;
+        Ok(data.len())
+    }
+}
+
+impl<'a, R> Seek for PatchedView<'a, R> where R: Read + Seek {
+    fn seek(&mut self, pos: SeekFrom) -> IOResult<u64> {
+        let position = match pos {
+            SeekFrom::Start(x) => Some(x),
+            SeekFrom::End(x) => self.len.checked_add_signed(x),
+            SeekFrom::Current(x) => self.position.checked_add_signed(x),
+        };
+        match position {
+            Some(x) => {
+                self.position = x;
+                Ok(x)
+            }
+            None => Err(IOError::new(ErrorKind::InvalidInput, "invalid seek to a negative position")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use spectral::prelude::*;
+
+    use crate::ips::{IPSHunk, IPSRegularHunkData};
+
+    use super::*;
+
+    fn patch() -> IPSPatch {
+        IPSPatch::new()
+            .with_hunk(IPSHunk::Regular(IPSRegularHunkData {
+                offset: 6,
+                payload: Box::new([0xA, 0xB, 0xC, 0xD]),
+            }))
+    }
+
+    #[test]
+    fn reading_the_view_returns_patched_file() {
+        let patch = patch();
+        let mut view = PatchedView::new(&patch, Cursor::new((0..8).collect::<Vec<u8>>())).unwrap();
+        let mut actual = Vec::new();
+        view.read_to_end(&mut actual).unwrap();
+        assert_that!(actual).is_equal_to(vec![0, 1, 2, 3, 4, 5, 0xA, 0xB, 0xC, 0xD]);
+        assert_that!(view.len()).is_equal_to(10);
+    }
+
+    #[test]
+    fn seeking_reads_from_new_position() {
+        let patch = patch();
+        let mut view = PatchedView::new(&patch, Cursor::new((0..8).collect::<Vec<u8>>())).unwrap();
+        let mut actual = [0; 3];
+        view.seek(SeekFrom::End(-4)).unwrap();
+        view.read_exact(&mut actual).unwrap();
+        assert_that!(actual).is_equal_to([0xA, 0xB, 0xC]);
+    }
+
+    #[test]
+    fn source_is_left_untouched() {
+        let patch = patch();
+        let base: Vec<u8> = (0..8).collect();
+        let mut view = PatchedView::new(&patch, Cursor::new(base.clone())).unwrap();
+        view.read_to_end(&mut Vec::new()).unwrap();
+        assert_that!(view.into_inner().into_inner()).is_equal_to(base);
+    }
+
+    #[test]
+    fn gaps_read_as_gap_fill() {
+        let patch = patch();
+        let mut view = PatchedView::new(&patch, Cursor::new(vec![1; 4])).unwrap().with_gap_fill(0xFF);
+        let mut actual = Vec::new();
+        view.read_to_end(&mut actual).unwrap();
+        assert_that!(actual).is_equal_to(vec![1, 1, 1, 1, 0xFF, 0xFF, 0xA, 0xB, 0xC, 0xD]);
+    }
+
+    #[test]
+    fn seeking_before_start_fails() {
+        let patch = patch();
+        let mut view = PatchedView::new(&patch, Cursor::new(Vec::new())).unwrap();
+        assert_that!(view.seek(SeekFrom::Current(-1))).is_err();
+    }
+}
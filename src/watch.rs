@@ -0,0 +1,174 @@
+//! Keeping indexes of patch and rom directories up to date while the directories change.
+//!
+//! A [Watched] index re-runs its indexing function, e.g. [Catalog::index](crate::catalog::Catalog::index)
+//! or [audit::scan](crate::audit::scan), on a background thread whenever a file in its directory is
+//! added, removed or modified, so long-running frontends never show a stale catalog.
+//!
+//! Directories are polled for changes of the length or modification time of their files, which
+//! works the same on every platform and file system, including network shares.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime};
+
+use crate::Error;
+
+/// the length and modification time of every file below a directory.
+type Snapshot = BTreeMap<PathBuf, (u64, Option<SystemTime>)>;
+
+/// An index of a directory that is rebuilt whenever the directory changes.
+///
+/// The background thread polling the directory stops when the [Watched] is dropped.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use rom_patcher::catalog::Catalog;
+/// use rom_patcher::watch::Watched;
+///
+/// let catalog = Watched::start("patches", Duration::from_secs(2), Catalog::index).unwrap();
+/// // later, e.g. whenever the GUI redraws
+/// println!("{} patches", catalog.current().entries.len());
+/// ```
+#[derive(Debug)]
+pub struct Watched<T> {
+    state: Arc<State<T>>,
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+#[derive(Debug)]
+struct State<T> {
+    current: RwLock<(Arc<T>, u64)>,
+    last_error: Mutex<Option<String>>,
+}
+
+impl<T> Watched<T> where T: Send + Sync + 'static {
+    /// indexes `dir` with `index` and starts checking it for changes every `interval`.
+    ///
+    /// Returns the error of the first indexing. Errors of later indexings keep the previous index,
+    /// see [Watched::last_error].
+    pub fn start<F>(dir: impl Into<PathBuf>, interval: Duration, index: F) -> Result<Watched<T>, Error>
+        where F: Fn(&Path) -> Result<T, Error> + Send + 'static {
+        let dir = dir.into();
+        let mut previous = snapshot(&dir);
+        let state = Arc::new(State {
+            current: RwLock::new((Arc::new(index(&dir)?), 0)),
+            last_error: Mutex::new(None),
+        });
+        let (stop, stopped) = channel::<()>();
+        let watched = Arc::clone(&state);
+        let thread = thread::spawn(move || {
+            // the sender is dropped to stop the thread
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                let next = snapshot(&dir);
+                if next != previous {
+                    previous = next;
+                    watched.update(index(&dir));
+                }
+            }
+        });
+        return Ok(Watched { state, stop: Some(stop), thread: Some(thread) });
+    }
+
+    /// returns the latest index.
+    pub fn current(&self) -> Arc<T> {
+        Arc::clone(&self.state.current.read().unwrap_or_else(|e| e.into_inner()).0)
+    }
+
+    /// returns how often the index was rebuilt, so frontends can tell whether it changed.
+    pub fn generation(&self) -> u64 {
+        self.state.current.read().unwrap_or_else(|e| e.into_inner()).1
+    }
+
+    /// returns the error of the latest rebuild, or [None] if it succeeded.
+    pub fn last_error(&self) -> Option<String> {
+        self.state.last_error.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+}
+
+impl<T> State<T> {
+    fn update(&self, result: Result<T, Error>) {
+        let mut last_error = self.last_error.lock().unwrap_or_else(|e| e.into_inner());
+        match result {
+            Ok(index) => {
+                let mut current = self.current.write().unwrap_or_else(|e| e.into_inner());
+                *current = (Arc::new(index), current.1 + 1);
+                *last_error = None;
+            }
+            Err(e) => *last_error = Some(e.to_string()),
+        }
+    }
+}
+
+impl<T> Drop for Watched<T> {
+    fn drop(&mut self) {
+        self.stop = None;
+        if let Some(thread) = self.thread.take() {
+            // a panicking index function already reported its panic
+            let _ = thread.join();
+        }
+    }
+}
+
+/// returns the snapshot of `dir`, leaving out whatever can't be read.
+fn snapshot(dir: &Path) -> Snapshot {
+    let mut result = Snapshot::new();
+    collect(dir, &mut result);
+    return result;
+}
+
+fn collect(dir: &Path, result: &mut Snapshot) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        match entry.metadata() {
+            Ok(metadata) if metadata.is_dir() => collect(&path, result),
+            Ok(metadata) => {
+                result.insert(path, (metadata.len(), metadata.modified().ok()));
+            }
+            Err(_) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::time::Instant;
+
+    use spectral::prelude::*;
+
+    use crate::catalog::Catalog;
+    use crate::testkit::Fixture;
+
+    use super::*;
+
+    #[test]
+    fn catalogs_follow_their_directory() {
+        let dir = env::temp_dir().join(format!("rom-patcher-watch-catalog-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let watched = Watched::start(&dir, Duration::from_millis(10), Catalog::index).unwrap();
+        assert_that!(watched.current().entries).is_empty();
+
+        let mut ips = Vec::new();
+        Fixture::generate(3).ips.write(&mut ips).unwrap();
+        fs::write(dir.join("hack.ips"), ips).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while watched.current().entries.is_empty() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_that!(watched.current().entries.len()).is_equal_to(1);
+        assert_that!(watched.generation()).is_greater_than(0);
+        assert_that!(watched.last_error()).is_none();
+        drop(watched);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,229 @@
+//! Watermarks tracing which distributor a patch was handed to, see [embed_watermark].
+//!
+//! The watermark is carried by the order of hunks whose order doesn't matter: hunks writing
+//! distinct ranges are sorted by offset and paired up, and each pair stores one bit by being
+//! swapped or not. Marked patches produce exactly the output of the unmarked ones and keep their
+//! size, and [remove_watermark] or [strip_nonessential](crate::sanitize::strip_nonessential)
+//! restores the order. The watermark holds the length of the ID, the ID and 16 bits of its CRC-32,
+//! so a patch needs `16 * (3 + id.len())` hunks to carry it, see [watermark_capacity].
+//!
+//! Anyone aware of the scheme can remove or forge it, it only traces patches leaked as they were
+//! distributed.
+//!
+//! # Examples
+//!
+//! ```
+//! use rom_patcher::ips::{IPSHunk, IPSPatch, IPSRegularHunkData};
+//! use rom_patcher::watermark::{detect_watermark, embed_watermark};
+//!
+//! let mut patch = IPSPatch::new();
+//! for i in 0..96 {
+//!     patch.add_hunk(IPSHunk::Regular(IPSRegularHunkData::new(i * 2, vec![i as u8])));
+//! }
+//! let marked = embed_watermark(&patch, b"t1").unwrap();
+//! assert_eq!(detect_watermark(marked.as_ref()), Some(b"t1".to_vec()));
+//! assert_eq!(detect_watermark(&patch), None);
+//! ```
+
+use crate::hash::Crc32;
+use crate::hunk::Hunk;
+use crate::ips::{IPSHunk, IPSPatch};
+use crate::patch::Patch;
+use crate::pmsr::{PMSRPatch, PMSRRecord};
+use crate::Error;
+use crate::ErrorKind::{UnsupportedFormat, ValidationError};
+
+/// returns a copy of `patch` carrying `id`, replacing any watermark it carried before.
+///
+/// Returns a [ValidationError] if `id` is empty or longer than 255 bytes, if hunks of the patch
+/// overlap, since their order then matters, or if the patch has too few hunks to carry `id`.
+/// Overlapping hunks can be resolved with [strip_nonessential](crate::sanitize::strip_nonessential)
+/// first. Returns an [UnsupportedFormat] error for formats without a watermark channel.
+pub fn embed_watermark(patch: &dyn Patch, id: &[u8]) -> Result<Box<dyn Patch>, Error> {
+    if id.is_empty() || id.len() > u8::MAX as usize {
+        return Err(Error::new(ValidationError)
+            .with_description("A watermark must be between 1 and 255 bytes long.".to_string()));
+    }
+    let bits = encode(id);
+    reorder(patch, Some(&bits))
+}
+
+/// returns the ID `patch` carries, or [None] if it isn't watermarked.
+///
+/// A patch whose hunks are out of order by chance is mistaken for a watermarked one about once in
+/// 65536 times.
+pub fn detect_watermark(patch: &dyn Patch) -> Option<Vec<u8>> {
+    if let Some(ips) = patch.as_any().downcast_ref::<IPSPatch>() {
+        return decode(&read_bits(ips.hunks())?);
+    }
+    if let Some(pmsr) = patch.as_any().downcast_ref::<PMSRPatch>() {
+        return decode(&read_bits(pmsr.records())?);
+    }
+    None
+}
+
+/// returns a copy of `patch` without a watermark, its hunks sorted by offset as before marking.
+pub fn remove_watermark(patch: &dyn Patch) -> Result<Box<dyn Patch>, Error> {
+    reorder(patch, None)
+}
+
+/// returns the length of the longest ID `patch` can carry, 0 if it can't carry any.
+pub fn watermark_capacity(patch: &dyn Patch) -> usize {
+    let hunks = if let Some(ips) = patch.as_any().downcast_ref::<IPSPatch>() {
+        ips.hunks().len()
+    } else if let Some(pmsr) = patch.as_any().downcast_ref::<PMSRPatch>() {
+        pmsr.records().len()
+    } else {
+        return 0;
+    };
+    (hunks / 16).saturating_sub(3).min(u8::MAX as usize)
+}
+
+/// returns a copy of `patch` with its hunks sorted, and swapped in pairs according to `bits`.
+fn reorder(patch: &dyn Patch, bits: Option<&[bool]>) -> Result<Box<dyn Patch>, Error> {
+    if let Some(ips) = patch.as_any().downcast_ref::<IPSPatch>() {
+        let mut result = IPSPatch::new();
+        for hunk in permute::<IPSHunk>(ips.hunks(), bits)? {
+            result.add_hunk(hunk);
+        }
+        result.set_truncate(ips.truncate());
+        return Ok(Box::new(result));
+    }
+    if let Some(pmsr) = patch.as_any().downcast_ref::<PMSRPatch>() {
+        let mut result = PMSRPatch::new();
+        for record in permute::<PMSRRecord>(pmsr.records(), bits)? {
+            result.add_record(record);
+        }
+        return Ok(Box::new(result));
+    }
+    Err(Error::new(UnsupportedFormat)
+        .with_description(format!("{} patches can't carry a watermark.", patch.format())))
+}
+
+/// returns the indices of `hunks` ordered by offset, or [None] if any of them overlap.
+fn sorted<H: Hunk>(hunks: &[H]) -> Option<Vec<usize>> {
+    let mut order: Vec<usize> = (0..hunks.len()).collect();
+    order.sort_by_key(|i| (hunks[*i].target_range().start, hunks[*i].target_range().end));
+    let disjoint = order.windows(2).all(|x| {
+        let (a, b) = (hunks[x[0]].target_range(), hunks[x[1]].target_range());
+        a.end <= b.start && a.start < b.start
+    });
+    disjoint.then_some(order)
+}
+
+/// returns `hunks` sorted by offset, with the pairs of neighbours swapped where `bits` are set.
+fn permute<H: Hunk + Clone>(hunks: &[H], bits: Option<&[bool]>) -> Result<Vec<H>, Error> {
+    let mut order = sorted(hunks).ok_or_else(|| Error::new(ValidationError)
+        .with_description("The order of overlapping hunks can't be changed.".to_string()))?;
+    if let Some(bits) = bits {
+        if bits.len() > order.len() / 2 {
+            return Err(Error::new(ValidationError).with_description(format!(
+                "A watermark of {} bits needs {} hunks, but the patch has {}.",
+                bits.len(), bits.len() * 2, order.len())));
+        }
+        for (pair, bit) in order.chunks_mut(2).zip(bits) {
+            if *bit {
+                pair.swap(0, 1);
+            }
+        }
+    }
+    Ok(order.into_iter().map(|i| hunks[i].clone()).collect())
+}
+
+/// returns the bits stored by the pairs of neighbouring `hunks`, or [None] if hunks overlap.
+fn read_bits<H: Hunk>(hunks: &[H]) -> Option<Vec<bool>> {
+    let order = sorted(hunks)?;
+    Some(order.chunks_exact(2).map(|x| x[0] > x[1]).collect())
+}
+
+/// returns the bits of the watermark carrying `id`.
+fn encode(id: &[u8]) -> Vec<bool> {
+    let mut bytes = vec![id.len() as u8];
+    bytes.extend_from_slice(id);
+    let check = Crc32::checksum(&bytes) as u16;
+    bytes.extend_from_slice(&check.to_be_bytes());
+    bytes.iter().flat_map(|x| (0..8).rev().map(move |i| x >> i & 1 == 1)).collect()
+}
+
+/// returns the ID carried by `bits`, or [None] if they don't hold a valid watermark.
+fn decode(bits: &[bool]) -> Option<Vec<u8>> {
+    let bytes: Vec<u8> = bits.chunks_exact(8)
+        .map(|x| x.iter().fold(0, |a, b| a << 1 | *b as u8))
+        .collect();
+    let len = *bytes.first()? as usize;
+    let data = bytes.get(..len + 1)?;
+    let check = bytes.get(len + 1..len + 3)?;
+    if len == 0 || check != (Crc32::checksum(data) as u16).to_be_bytes() {
+        return None;
+    }
+    Some(data[1..].to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use spectral::prelude::*;
+
+    use crate::ips::IPSRegularHunkData;
+    use crate::pmsr::PMSRRecord;
+
+    use super::*;
+
+    fn patch(hunks: u64) -> IPSPatch {
+        let mut result = IPSPatch::new();
+        for i in 0..hunks {
+            result.add_hunk(IPSHunk::Regular(IPSRegularHunkData::new(i * 3, vec![i as u8, 1])));
+        }
+        result.set_truncate(Some(hunks * 3 - 1));
+        return result;
+    }
+
+    #[test]
+    fn watermarks_are_detected_and_removed() {
+        let original = patch(160);
+        let marked = embed_watermark(&original, b"beta-7").unwrap();
+        assert_that!(detect_watermark(marked.as_ref())).is_equal_to(Some(b"beta-7".to_vec()));
+        let source = [0xFF; 400];
+        assert_that!(marked.apply_to_vec(&source).unwrap()).is_equal_to(original.apply_to_vec(&source).unwrap());
+        assert_that!(marked.to_bytes().unwrap().len()).is_equal_to(original.to_bytes().unwrap().len());
+
+        let parsed = IPSPatch::from_bytes(&marked.to_bytes().unwrap()).unwrap();
+        assert_that!(detect_watermark(&parsed)).is_equal_to(Some(b"beta-7".to_vec()));
+        let removed = remove_watermark(&parsed).unwrap();
+        assert_that!(removed.as_any().downcast_ref::<IPSPatch>()).is_equal_to(Some(&original));
+        assert_that!(detect_watermark(removed.as_ref())).is_none();
+    }
+
+    #[test]
+    fn watermarks_are_replaced() {
+        let marked = embed_watermark(&patch(160), b"first").unwrap();
+        let marked = embed_watermark(marked.as_ref(), b"second").unwrap();
+        assert_that!(detect_watermark(marked.as_ref())).is_equal_to(Some(b"second".to_vec()));
+    }
+
+    #[test]
+    fn small_patches_refuse_long_ids() {
+        let patch = patch(64);
+        assert_that!(watermark_capacity(&patch)).is_equal_to(1);
+        assert_that!(embed_watermark(&patch, b"a").is_ok()).is_true();
+        assert_that!(embed_watermark(&patch, b"ab").is_err()).is_true();
+    }
+
+    #[test]
+    fn overlapping_hunks_are_refused() {
+        let patch = patch(64).with_hunk(IPSHunk::Regular(IPSRegularHunkData::new(1, vec![9])));
+        let error = embed_watermark(&patch, b"a").unwrap_err();
+        assert_that!(error.to_string()).contains("overlapping");
+        assert_that!(detect_watermark(&patch)).is_none();
+    }
+
+    #[test]
+    fn star_rod_mods_carry_watermarks() {
+        let mut original = PMSRPatch::new();
+        for i in 0..64 {
+            original.add_record(PMSRRecord { offset: i * 4, data: Box::new([i as u8]) });
+        }
+        let marked = embed_watermark(&original, b"x").unwrap();
+        let parsed = PMSRPatch::from_bytes(&marked.to_bytes().unwrap()).unwrap();
+        assert_that!(detect_watermark(&parsed)).is_equal_to(Some(b"x".to_vec()));
+    }
+}